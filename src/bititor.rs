@@ -1,9 +1,19 @@
-use borrow_iter::{BPeekable, BorrowPeekable};
-
 /// Iterates over the bits of a buffer
+///
+/// Bits come out one at a time as bools, or several at once with
+/// read_bits. Bytes are taken from the underlying iterator as soon as
+/// any of their bits are needed (peek_bits can take a few ahead).
 pub struct BitItor<'a, I: Iterator + 'a> {
-    buf: BPeekable<'a, I>,
-    bit: u8,
+    buf: &'a mut I,
+    // bits taken from buf that have not been read, the next one is
+    // the highest of the low `cached` bits
+    cache: u64,
+    cached: u8,
+}
+
+// the low n bits set
+fn mask(n: u8) -> u64 {
+    (1u64 << n) - 1
 }
 
 // NOTE TO SELF -- this works if i just take mut ref to an already iterator
@@ -12,38 +22,54 @@ impl<'a, 'b, I> BitItor<'a, I>
 
     pub fn new(buf: &'a mut I) -> Self {
         BitItor {
-            buf: buf.borrow_peekable(),
-            bit: 0,
+            buf: buf,
+            cache: 0,
+            cached: 0,
         }
     }
-}
 
-impl<'a, 'b, I> Iterator for BitItor<'a, I>
-    where 'b: 'a, I: Iterator<Item=&'b u8> {
-    type Item = bool;
+    // take whole bytes until at least n bits are cached,
+    // false if the buffer ends first
+    fn fill(&mut self, n: u8) -> bool {
+        while self.cached < n {
+            match self.buf.next() {
+                Some(byte) => {
+                    self.cache = self.cache << 8 | *byte as u64;
+                    self.cached += 8;
+                },
+                None => return false,
+            }
+        }
+        true
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // is this the end of the buffer
-        if self.buf.bpeek().is_none() {
+    /// the next n bits (at most 32) as a number, the first bit being
+    /// the highest, without reading them
+    ///
+    /// None if there are not n bits left.
+    pub fn peek_bits(&mut self, n: u8) -> Option<u32> {
+        assert!(n <= 32, "at most 32 bits at a time");
+        if !self.fill(n) {
             return None;
         }
+        Some((self.cache >> (self.cached - n) & mask(n)) as u32)
+    }
 
-        // get is_set
-        let is_set: bool;
-        {
-            let byte = self.buf.bpeek().unwrap();
-            let mask = 0x80 >> self.bit;
-            is_set = *byte & mask > 0;
-        }
+    /// read the next n bits (at most 32) as with peek_bits
+    pub fn read_bits(&mut self, n: u8) -> Option<u32> {
+        let bits = self.peek_bits(n)?;
+        self.cached -= n;
+        self.cache &= mask(self.cached);
+        Some(bits)
+    }
+}
 
-        // iterate
-        self.bit += 1;
-        if self.bit > 7 {
-            self.bit = 0;
-            self.buf.next();
-        }
+impl<'a, 'b, I> Iterator for BitItor<'a, I>
+    where 'b: 'a, I: Iterator<Item=&'b u8> {
+    type Item = bool;
 
-        Some(is_set)
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_bits(1).map(|bit| bit == 1)
     }
 }
 
@@ -85,4 +111,42 @@ mod bit_iter_tests {
         assert_eq!(buf, tbuf);
 
     }
+
+    #[test]
+    fn read_bits() {
+        let buf = [0xf3, 0x21, 0x75, 0x21];
+        let mut biter = buf.iter();
+        let mut bi = BitItor::new(&mut biter);
+
+        // the same 32 bits put back together from reads that do not
+        // line up with the bytes
+        let mut whole = 0u32;
+        for &n in &[3, 5, 8, 13, 3] {
+            whole = whole << n | bi.read_bits(n).unwrap();
+        }
+        assert_eq!(whole, 0xf3217521);
+        assert_eq!(bi.read_bits(1), None);
+        assert_eq!(bi.read_bits(0), Some(0));
+
+        let mut biter = buf.iter();
+        let mut bi = BitItor::new(&mut biter);
+        assert_eq!(bi.read_bits(32), Some(0xf3217521));
+    }
+
+    #[test]
+    fn peek_bits() {
+        let buf = [0xf3, 0x21, 0x75];
+        let mut biter = buf.iter();
+        let mut bi = BitItor::new(&mut biter);
+
+        assert_eq!(bi.peek_bits(13), Some(0xf321 >> 3));
+        assert_eq!(bi.peek_bits(4), Some(0xf));
+        assert_eq!(bi.read_bits(4), Some(0xf));
+        assert_eq!(bi.peek_bits(8), Some(0x32));
+        // not enough left to peek at, but what is there still is
+        assert_eq!(bi.peek_bits(21), None);
+        assert_eq!(bi.next(), Some(false));
+        assert_eq!(bi.read_bits(19), Some(0x32175 & 0x7ffff));
+        assert_eq!(bi.next(), None);
+    }
 }
@@ -1,20 +1,36 @@
 
+use std::collections::VecDeque;
+
 pub struct BPeekable<'a, I: Iterator + 'a> {
     iter: &'a mut I,
-    peeked: Option<Option<I::Item>>,
+    // items taken from iter to be looked at, next hands them out first
+    peeked: VecDeque<I::Item>,
+    // iter has ended, so it is not asked again
+    done: bool,
 }
 
 impl<'a, I: Iterator> BPeekable<'a, I> {
 
     pub fn bpeek(&mut self) -> Option<&I::Item> {
-        if self.peeked.is_none() {
-            self.peeked = Some(self.iter.next());
-        }
-        match self.peeked {
-            Some(Some(ref value)) => Some(value),
-            Some(None) => None,
-            _ => unreachable!(),
+        self.bpeek_n(1).first()
+    }
+
+    /// look at the next n items without consuming them,
+    /// fewer if the iterator ends before that
+    pub fn bpeek_n(&mut self, n: usize) -> &[I::Item] {
+        while self.peeked.len() < n && !self.done {
+            match self.iter.next() {
+                Some(item) => self.peeked.push_back(item),
+                None => self.done = true,
+            }
         }
+        let n = ::std::cmp::min(n, self.peeked.len());
+        &self.peeked.make_contiguous()[..n]
+    }
+
+    /// how many items have been peeked at and not consumed
+    pub fn peeked_len(&self) -> usize {
+        self.peeked.len()
     }
 }
 
@@ -22,8 +38,9 @@ impl<'a, I: Iterator> Iterator for BPeekable<'a, I> {
     type Item = I::Item;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.peeked.take() {
-            Some(v) => v,
+        match self.peeked.pop_front() {
+            Some(item) => Some(item),
+            None if self.done => None,
             None => self.iter.next(),
         }
     }
@@ -37,6 +54,46 @@ pub trait BorrowPeekable<T: Iterator> {
 impl<T> BorrowPeekable<T> for T where T: Iterator {
 
     fn borrow_peekable<'a>(&'a mut self) -> BPeekable<T> {
-        BPeekable { iter: self, peeked: None }
+        BPeekable { iter: self, peeked: VecDeque::new(), done: false }
+    }
+}
+
+#[cfg(test)]
+mod peek_tests {
+
+    use super::BorrowPeekable;
+
+    #[test]
+    fn peek_ahead() {
+        let buf = [1u8, 2, 3, 4, 5, 6];
+        let mut iter = buf.iter();
+        {
+            let mut peekable = iter.borrow_peekable();
+            assert_eq!(peekable.bpeek_n(4), &[&1, &2, &3, &4]);
+            assert_eq!(peekable.peeked_len(), 4);
+            assert_eq!(peekable.next(), Some(&1));
+            assert_eq!(peekable.next(), Some(&2));
+
+            // overlapping the first peek
+            assert_eq!(peekable.bpeek_n(4), &[&3, &4, &5, &6]);
+            assert_eq!(peekable.bpeek(), Some(&&3));
+            assert_eq!(peekable.peeked_len(), 4);
+            assert_eq!(peekable.next(), Some(&3));
+        }
+        // nothing peeked is left behind in the underlying iterator
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn peek_past_the_end() {
+        let buf = [1u8, 2, 3];
+        let mut iter = buf.iter();
+        let mut peekable = iter.borrow_peekable();
+        assert_eq!(peekable.bpeek_n(5), &[&1, &2, &3]);
+        assert_eq!(peekable.bpeek_n(0), &[] as &[&u8]);
+        assert_eq!(peekable.by_ref().collect::<Vec<_>>(), vec![&1, &2, &3]);
+        assert_eq!(peekable.bpeek_n(2), &[] as &[&u8]);
+        assert_eq!(peekable.bpeek(), None);
+        assert_eq!(peekable.next(), None);
     }
-}
\ No newline at end of file
+}
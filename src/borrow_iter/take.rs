@@ -18,11 +18,23 @@ impl<'a, I: Iterator> Iterator for BTake<'a, I> {
         }
     }
 
+    // what is left of the take, unless the iterator runs out first
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.take, None)
+        let left = self.take - self.count;
+        if left == 0 {
+            return (0, Some(0));
+        }
+        let (lower, upper) = self.iter.size_hint();
+        let upper = match upper {
+            Some(upper) => ::std::cmp::min(upper, left),
+            None => left,
+        };
+        (::std::cmp::min(lower, left), Some(upper))
     }
 }
 
+impl<'a, I: ExactSizeIterator> ExactSizeIterator for BTake<'a, I> {}
+
 pub trait BorrowTake<T: Iterator> {
 
     fn borrow_take<'a>(&'a mut self, take: usize) -> BTake<'a, T>;
@@ -34,3 +46,44 @@ impl<T> BorrowTake<T> for T where T: Iterator {
         BTake { iter: self, take, count: 0 }
     }
 }
+
+#[cfg(test)]
+mod take_tests {
+
+    use super::BorrowTake;
+
+    #[test]
+    fn size_hint() {
+        let buf = [1u8, 2, 3, 4, 5, 6, 7, 8];
+        let mut iter = buf.iter();
+        {
+            let mut take = iter.borrow_take(5);
+            assert_eq!(take.size_hint(), (5, Some(5)));
+            take.next();
+            take.next();
+            assert_eq!(take.size_hint(), (3, Some(3)));
+            assert_eq!(take.len(), 3);
+            assert_eq!(take.by_ref().count(), 3);
+            assert_eq!(take.size_hint(), (0, Some(0)));
+        }
+
+        // more asked for than is left
+        assert_eq!(iter.borrow_take(10).size_hint(), (3, Some(3)));
+
+        // an iterator that does not know how long it is
+        let mut filtered = buf.iter().filter(|&&b| b % 2 == 0);
+        assert_eq!(filtered.borrow_take(3).size_hint(), (0, Some(3)));
+    }
+
+    #[test]
+    fn collect_exact() {
+        let buf = [0u8; 100];
+        let mut iter = buf.iter();
+        iter.next();
+        let taken: Vec<u8> = iter.borrow_take(40).map(|b| *b).collect();
+        assert_eq!(taken.len(), 40);
+        // sized right up front, so it was allocated just the once
+        assert_eq!(taken.capacity(), 40);
+        assert_eq!(iter.len(), 59);
+    }
+}
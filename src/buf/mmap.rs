@@ -0,0 +1,193 @@
+//! Files mapped into memory, so they can be sent without reading them
+//! through a buffer first
+//!
+//! An Mmap is a read-only, private mapping of part of a file, unmapped
+//! when it is dropped. A MappedSlice is part of one that is shared in an
+//! Arc, which is what the payload of a DATA frame can be, so the mapping
+//! is there for as long as any frame that points into it is queued.
+//!
+//! Mapping only works on unix, elsewhere map always fails and the file
+//! has to be read instead.
+//!
+//! A file that is cut short while it is mapped takes the process down with
+//! SIGBUS when the part that is gone is touched, so only map files that
+//! are replaced (renamed over) rather than rewritten in place.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A read-only mapping of part of a file
+pub struct Mmap {
+    // the start of the mapping, which is at a page boundary
+    base: *const u8,
+    mapped: usize,
+    // where the part asked for starts in it, and how long it is
+    skip: usize,
+    len: usize,
+}
+
+// the mapping is read-only and only let go of in drop
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+
+    /// map len bytes of file from offset, which have to be in the file
+    #[cfg(unix)]
+    pub fn map(file: &File, offset: u64, len: usize) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+        use libc;
+
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "nothing to map"));
+        }
+        if file.metadata()?.len() < offset + len as u64 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the file is shorter than the mapping"));
+        }
+        // the offset of a mapping has to be a multiple of the page size
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        let page = if page > 0 { page as u64 } else { 4096 };
+        let aligned = offset - offset % page;
+        let skip = (offset - aligned) as usize;
+
+        let base = unsafe {
+            libc::mmap(ptr::null_mut(), skip + len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), aligned as libc::off_t)
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { base: base as *const u8, mapped: skip + len, skip: skip, len: len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File, _offset: u64, _len: usize) -> io::Result<Mmap> {
+        Err(io::Error::new(io::ErrorKind::Other, "files can only be mapped on unix"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { ::std::slice::from_raw_parts(self.base.offset(self.skip as isize), self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    #[cfg(unix)]
+    fn drop(&mut self) {
+        unsafe { ::libc::munmap(self.base as *mut ::libc::c_void, self.mapped); }
+    }
+
+    #[cfg(not(unix))]
+    fn drop(&mut self) {}
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mmap({} bytes)", self.len)
+    }
+}
+
+/// Part of a shared mapping
+#[derive(Clone)]
+pub struct MappedSlice {
+    map: Arc<Mmap>,
+    start: usize,
+    end: usize,
+}
+
+impl MappedSlice {
+
+    /// all of map
+    pub fn new(map: Arc<Mmap>) -> Self {
+        let end = map.len();
+        MappedSlice { map: map, start: 0, end: end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// take the first n bytes (or all of them, if there are fewer) off
+    /// the front, as a slice of the same mapping
+    pub fn split_to(&mut self, n: usize) -> MappedSlice {
+        let mid = self.start + ::std::cmp::min(n, self.len());
+        let front = MappedSlice { map: self.map.clone(), start: self.start, end: mid };
+        self.start = mid;
+        front
+    }
+}
+
+impl Deref for MappedSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[self.start..self.end]
+    }
+}
+
+impl AsRef<[u8]> for MappedSlice {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for MappedSlice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedSlice({}..{})", self.start, self.end)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod mmap_tests {
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+    use std::sync::Arc;
+
+    use super::{MappedSlice, Mmap};
+
+    #[test]
+    fn map_part_of_a_file() {
+        let path = env::temp_dir().join(format!("kurisu-mmap-{}", process::id()));
+        let contents: Vec<u8> = (0..20000u32).map(|i| (i * 7 % 251) as u8).collect();
+        File::create(&path).unwrap().write_all(&contents).unwrap();
+        let file = File::open(&path).unwrap();
+
+        // an offset that is not on a page boundary
+        let map = Mmap::map(&file, 5000, 10000).unwrap();
+        assert_eq!(map.len(), 10000);
+        assert!(&map[..] == &contents[5000..15000]);
+
+        let mut slice = MappedSlice::new(Arc::new(map));
+        let front = slice.split_to(4000);
+        assert!(&front[..] == &contents[5000..9000]);
+        assert!(&slice[..] == &contents[9000..15000]);
+        // the mapping is still there once the slice it was made with is gone
+        drop(slice);
+        assert!(&front[..] == &contents[5000..9000]);
+
+        assert!(Mmap::map(&file, 15000, 10000).is_err());
+        assert!(Mmap::map(&file, 0, 0).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Trait for type that holds a unique mutable reference
+//! to some underlying buffer. Used as the base for more
+//! more complex types that point to and map out the buffer
+//!
+//! Also the buffers that own their memory, like ReadBuffer and
+//! RingBuffer which collect what is read from a stream, and the Pool
+//! they (and owned frames) get their memory from, and files mapped into
+//! memory
+
+use std::ops::Range;
+
+mod mmap;
+mod pool;
+mod read_buffer;
+mod ring_buffer;
+
+pub use self::mmap::{MappedSlice, Mmap};
+pub use self::pool::{Pool, PoolStats, PooledBuf};
+pub use self::read_buffer::ReadBuffer;
+pub use self::ring_buffer::RingBuffer;
+
+/// The Buf Trait that that says a type contains an borrowed
+/// buffer in its underlying memory and can be safely
+/// exposed for exterior usage
+pub trait Buf<'obj, 'buf, T> {
+    fn buf(&'obj self) -> &'obj [T];
+    fn mut_buf(&'obj mut self) -> &'obj mut [T];
+    fn point_to(&'buf mut [T]) -> Self;
+    /// give up the type for the whole buffer it pointed to
+    fn into_buf(self) -> &'buf mut [T] where Self: Sized;
+
+    /// part of the buffer, None if the range is not all in it
+    fn view(&'obj self, range: Range<usize>) -> Option<&'obj [T]> {
+        self.buf().get(range)
+    }
+
+    fn mut_view(&'obj mut self, range: Range<usize>) -> Option<&'obj mut [T]> {
+        self.mut_buf().get_mut(range)
+    }
+
+    /// the buffer as two mutable parts, split at mid, so each can be
+    /// changed while the other is held (None if mid is past the end)
+    fn split_at_mut(&'obj mut self, mid: usize) -> Option<(&'obj mut [T], &'obj mut [T])> {
+        let buf = self.mut_buf();
+        if mid > buf.len() {
+            return None;
+        }
+        Some(buf.split_at_mut(mid))
+    }
+}
+
+/// macro to automatically implement Buf for all listed types
+/// with buffer type and member name pointer to the buffer given
+///
+/// A type with more fields than the buffer gives their starting
+/// values after its name (one type per invocation):
+///
+/// ```ignore
+/// impl_buf!( u8 : buf => GenericFrame { parsed_len: Cell::new(None) }; );
+/// ```
+macro_rules! impl_buf {
+    ( @impl $buf_type:ty, $mem_name:ident, $type_name:ident, { $($field:ident : $init:expr),* } ) =>
+        {
+            impl<'obj, 'buf> Buf<'obj, 'buf, $buf_type> for $type_name<'buf>
+                where 'buf: 'obj, [$buf_type]: 'buf{
+                fn buf(&'obj self) -> &'obj [$buf_type] {
+                    &self.$mem_name
+                }
+                fn mut_buf(&'obj mut self) -> &'obj mut [$buf_type] {
+                    &mut self.$mem_name
+                }
+                fn point_to(buf: &'buf mut [$buf_type]) -> Self {
+                    $type_name { $mem_name: buf, $($field: $init),* }
+                }
+                fn into_buf(self) -> &'buf mut [$buf_type] {
+                    self.$mem_name
+                }
+            }
+        };
+    ( $buf_type:ty : $mem_name:ident => $type_name:ident { $($field:ident : $init:expr),* $(,)* } ; ) =>
+        {
+            impl_buf!( @impl $buf_type, $mem_name, $type_name, { $($field : $init),* } );
+        };
+    ( $($buf_type:ty : $mem_name:ident => $($type_name:ident),+;)+ ) =>
+        {
+            $(
+                $(
+                    impl_buf!( @impl $buf_type, $mem_name, $type_name, { } );
+                 )*
+             )*
+        }
+}
+
+#[cfg(test)]
+mod buf_tests {
+    use std::cell::Cell;
+    use std::ops::Range;
+
+    use super::Buf;
+
+    struct TstImplBuf<'a> {
+        buf: &'a mut [u8],
+    }
+
+    impl_buf!( u8 : buf => TstImplBuf ; );
+
+    // with more than the buffer
+    struct TstExtraBuf<'a> {
+        buf: &'a mut [u8],
+        name: &'static str,
+        seen: Cell<usize>,
+    }
+
+    impl_buf!( u8 : buf => TstExtraBuf { name: "extra", seen: Cell::new(0) }; );
+
+    struct TstOtherBuf<'a> {
+        buf: &'a mut [u8],
+    }
+
+    // several types at once
+    impl_buf!( u8 : buf => TstOtherBuf, TstImplBuf2 ; );
+
+    struct TstImplBuf2<'a> {
+        buf: &'a mut [u8],
+    }
+
+    #[test]
+    fn test_buf() {
+        let mut buf = vec![1,2,3,4];
+        let tb = TstImplBuf::point_to(&mut buf);
+
+        assert_eq!(&[1,2,3,4], tb.buf());
+    }
+
+    #[test]
+    fn test_mut_buf() {
+        let mut buf = vec![1,2,3,4];
+        let mut tmb = TstImplBuf::point_to(&mut buf);
+
+        {
+            let mr = tmb.mut_buf();
+
+            mr[0] = 0;
+            mr[3] = 9;
+        }
+
+        assert_eq!(&[0,2,3,9], tmb.buf());
+    }
+
+    #[test]
+    fn views() {
+        let mut buf = vec![1,2,3,4];
+        let tb = TstImplBuf::point_to(&mut buf);
+
+        assert_eq!(tb.view(1..3), Some(&[2,3][..]));
+        assert_eq!(tb.view(4..4), Some(&[][..]));
+        assert_eq!(tb.view(2..5), None);
+        assert_eq!(tb.view(Range { start: 3, end: 2 }), None);
+    }
+
+    #[test]
+    fn mut_views() {
+        let mut buf = vec![1,2,3,4];
+        {
+            let mut tmb = TstImplBuf::point_to(&mut buf);
+            assert!(tmb.mut_view(0..9).is_none());
+            tmb.mut_view(3..4).unwrap()[0] = 7;
+
+            // both halves held and changed at once
+            {
+                let (front, back) = tmb.split_at_mut(1).unwrap();
+                front[0] = back[0] + back[1];
+                back[2] = front[0];
+            }
+            assert!(tmb.split_at_mut(5).is_none());
+            assert_eq!(tmb.into_buf(), &mut [5,2,3,5]);
+        }
+    }
+
+    #[test]
+    fn extra_fields() {
+        let mut buf = vec![1,2,3];
+        let mut teb = TstExtraBuf::point_to(&mut buf);
+        assert_eq!((teb.name, teb.seen.get()), ("extra", 0));
+        teb.seen.set(teb.buf().len());
+        teb.mut_buf()[0] = 9;
+        assert_eq!(teb.seen.get(), 3);
+        assert_eq!(teb.into_buf(), &mut [9,2,3]);
+
+        let mut buf = vec![4,5];
+        assert_eq!(TstOtherBuf::point_to(&mut buf).buf(), &[4,5]);
+        assert_eq!(TstImplBuf2::point_to(&mut buf).buf(), &[4,5]);
+    }
+}
@@ -0,0 +1,245 @@
+//! Buffers that go back to be used again when they are dropped
+//!
+//! Buffers are kept by size class (powers of two), a get for some
+//! capacity is served from the smallest class that fits. Each class
+//! keeps at most max_per_class buffers, whatever is given back past that
+//! (or is too big for any class) is just freed.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// the smallest and largest size classes (powers of two)
+const MIN_CLASS_SHIFT : usize = 6;
+const MAX_CLASS_SHIFT : usize = 20;
+const CLASSES : usize = MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1;
+
+/// how many buffers of each size the global pool keeps
+pub const DEFAULT_MAX_PER_CLASS : usize = 64;
+
+lazy_static! {
+    static ref GLOBAL: Pool = Pool::new(DEFAULT_MAX_PER_CLASS);
+}
+
+/// How a pool has been used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// gets served with a buffer from the pool
+    pub hits: usize,
+    /// gets that had to allocate
+    pub misses: usize,
+    /// buffers that were handed out and not dropped yet
+    pub outstanding: usize,
+    /// buffers waiting in the pool
+    pub pooled: usize,
+}
+
+struct Shared {
+    classes: Vec<Mutex<Vec<Vec<u8>>>>,
+    max_per_class: usize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    outstanding: AtomicUsize,
+}
+
+impl Shared {
+    fn lock(&self, class: usize) -> MutexGuard<Vec<Vec<u8>>> {
+        match self.classes[class].lock() {
+            Ok(class) => class,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// A pool of byte buffers, clones share the same buffers
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+// the smallest class that has room for capacity
+fn class_for(capacity: usize) -> Option<usize> {
+    (0..CLASSES).find(|&class| capacity <= 1 << (class + MIN_CLASS_SHIFT))
+}
+
+// the largest class a buffer of capacity can serve
+fn class_of(capacity: usize) -> Option<usize> {
+    (0..CLASSES).rev().find(|&class| capacity >= 1 << (class + MIN_CLASS_SHIFT))
+}
+
+impl Pool {
+
+    pub fn new(max_per_class: usize) -> Self {
+        Pool {
+            shared: Arc::new(Shared {
+                classes: (0..CLASSES).map(|_| Mutex::new(Vec::new())).collect(),
+                max_per_class: max_per_class,
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+                outstanding: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// the pool shared by every connection
+    pub fn global() -> &'static Pool {
+        &GLOBAL
+    }
+
+    /// an empty buffer with at least min_capacity
+    pub fn get(&self, min_capacity: usize) -> PooledBuf {
+        let class = class_for(min_capacity);
+        let pooled = class.and_then(|class| self.shared.lock(class).pop());
+        let buf = match pooled {
+            Some(buf) => {
+                self.shared.hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            },
+            None => {
+                self.shared.misses.fetch_add(1, Ordering::Relaxed);
+                let capacity = class.map_or(min_capacity, |class| 1 << (class + MIN_CLASS_SHIFT));
+                Vec::with_capacity(capacity)
+            },
+        };
+        self.shared.outstanding.fetch_add(1, Ordering::Relaxed);
+        PooledBuf { buf: buf, pool: self.clone() }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.shared.hits.load(Ordering::Relaxed),
+            misses: self.shared.misses.load(Ordering::Relaxed),
+            outstanding: self.shared.outstanding.load(Ordering::Relaxed),
+            pooled: (0..CLASSES).map(|class| self.shared.lock(class).len()).sum(),
+        }
+    }
+
+    fn give_back(&self, mut buf: Vec<u8>) {
+        self.shared.outstanding.fetch_sub(1, Ordering::Relaxed);
+        if buf.capacity() > 1 << MAX_CLASS_SHIFT {
+            return;
+        }
+        if let Some(class) = class_of(buf.capacity()) {
+            let mut pooled = self.shared.lock(class);
+            if pooled.len() < self.shared.max_per_class {
+                buf.clear();
+                pooled.push(buf);
+            }
+        }
+    }
+}
+
+/// A buffer from a Pool, used like the Vec it holds, that goes back
+/// to the pool when it is dropped
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    pool: Pool,
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        let buf = ::std::mem::replace(&mut self.buf, Vec::new());
+        self.pool.give_back(buf);
+    }
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.buf.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod pool_tests {
+
+    use std::thread;
+
+    use super::{Pool, PoolStats};
+
+    #[test]
+    fn reused() {
+        let pool = Pool::new(4);
+        let (ptr, capacity) = {
+            let mut buf = pool.get(100);
+            assert!(buf.capacity() >= 100);
+            buf.extend_from_slice(b"left behind");
+            (buf.as_ptr(), buf.capacity())
+        };
+
+        // same size class, same memory, and nothing left in it
+        let buf = pool.get(120);
+        assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, capacity));
+        assert!(buf.is_empty());
+        assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 1, outstanding: 1, pooled: 0 });
+
+        // a bigger class does not get it
+        let other = pool.get(1000);
+        assert!(other.as_ptr() != ptr);
+        drop(buf);
+        drop(other);
+        assert_eq!(pool.stats(), PoolStats { hits: 1, misses: 2, outstanding: 0, pooled: 2 });
+    }
+
+    #[test]
+    fn bounded() {
+        let pool = Pool::new(3);
+        let bufs: Vec<_> = (0..10).map(|_| pool.get(64)).collect();
+        assert_eq!(pool.stats().outstanding, 10);
+        drop(bufs);
+        assert_eq!(pool.stats().pooled, 3);
+
+        // too big to pool at all
+        drop(pool.get(2 << 20));
+        assert_eq!(pool.stats().pooled, 3);
+    }
+
+    #[test]
+    fn grown_buffers_change_class() {
+        let pool = Pool::new(4);
+        {
+            let mut buf = pool.get(10);
+            buf.extend_from_slice(&[0; 5000]);
+        }
+        // big enough now to serve a bigger get
+        assert!(pool.get(4000).capacity() >= 5000);
+        assert_eq!(pool.stats().hits, 1);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let pool = Pool::new(8);
+        let threads: Vec<_> = (0..8).map(|t| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    let mut buf = pool.get(64 + (i * t) % 4000);
+                    buf.push(t as u8);
+                    assert_eq!(&buf[..], &[t as u8]);
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.hits + stats.misses, 8000);
+        assert_eq!(stats.outstanding, 0);
+        assert!(stats.hits > stats.misses);
+    }
+}
@@ -0,0 +1,160 @@
+//! A buffer for what is read from a stream before it is used
+//!
+//! Reads go into the spare room at the end, which is zeroed before it
+//! is handed to the stream (so there is no unsafe here). Consumed bytes
+//! at the front are only moved out of the way when the room is needed
+//! and they are at least as many as the bytes being kept, so most of
+//! the time a frame split over reads is just read onto the end of what
+//! is already there.
+
+#![forbid(unsafe_code)]
+
+use std::io::{self, Read};
+
+// how much is asked of the stream in a single read
+const DEFAULT_CHUNK : usize = 4096;
+
+pub struct ReadBuffer {
+    buf: Vec<u8>,
+    // where the unconsumed bytes start
+    start: usize,
+    chunk: usize,
+}
+
+impl ReadBuffer {
+
+    pub fn new() -> Self {
+        ReadBuffer::with_capacity(DEFAULT_CHUNK)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        ReadBuffer { buf: Vec::with_capacity(capacity), start: 0, chunk: DEFAULT_CHUNK }
+    }
+
+    /// how much to ask of the stream in each fill_from
+    pub fn set_chunk(&mut self, chunk: usize) {
+        assert!(chunk > 0, "reads need room for a byte");
+        self.chunk = chunk;
+    }
+
+    /// do one read from r onto the end of the buffer, returning how
+    /// many bytes were added (0 at the end of the stream)
+    ///
+    /// The buffer grows as needed, nothing is lost if the read fails.
+    pub fn fill_from<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
+        let kept = self.buf.len() - self.start;
+        if self.buf.capacity() - self.buf.len() < self.chunk && self.start >= kept {
+            self.compact();
+        }
+
+        let end = self.buf.len();
+        self.buf.resize(end + self.chunk, 0);
+        let res = r.read(&mut self.buf[end..]);
+        let n = *res.as_ref().unwrap_or(&0);
+        self.buf.truncate(end + n);
+        res
+    }
+
+    /// mark n of the buffered bytes as used
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.len(), "consumed more than was buffered");
+        self.start += n;
+        if self.start == self.buf.len() {
+            // nothing left, so starting over is free
+            self.buf.clear();
+            self.start = 0;
+        }
+    }
+
+    /// the bytes that have been read but not consumed
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..]
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    // move the unconsumed bytes to the front
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod read_buffer_tests {
+
+    use std::io::{self, Cursor, ErrorKind, Read};
+
+    use super::ReadBuffer;
+
+    #[test]
+    fn grows_past_capacity() {
+        let input: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let mut stream = Cursor::new(input.clone());
+        let mut buf = ReadBuffer::with_capacity(16);
+        buf.set_chunk(1000);
+
+        while buf.fill_from(&mut stream).unwrap() > 0 {}
+        assert_eq!(buf.len(), 10000);
+        assert!(buf.capacity() >= 10000);
+        assert_eq!(buf.as_slice(), &input[..]);
+    }
+
+    #[test]
+    fn compacts_after_consuming() {
+        let input: Vec<u8> = (0..100).collect();
+        let mut stream = Cursor::new(input.clone());
+        let mut buf = ReadBuffer::with_capacity(32);
+        buf.set_chunk(10);
+
+        buf.fill_from(&mut stream).unwrap();
+        buf.fill_from(&mut stream).unwrap();
+        buf.consume(15);
+        assert_eq!(buf.as_slice(), &input[15..20]);
+        // more was consumed than kept, so the room at the front is
+        // used instead of growing
+        buf.fill_from(&mut stream).unwrap();
+        buf.fill_from(&mut stream).unwrap();
+        assert_eq!(buf.as_slice(), &input[15..40]);
+        assert_eq!(buf.capacity(), 32);
+
+        // consuming everything starts over at the front
+        buf.consume(25);
+        assert!(buf.is_empty());
+        buf.fill_from(&mut stream).unwrap();
+        assert_eq!(buf.as_slice(), &input[40..50]);
+    }
+
+    #[test]
+    fn failed_read() {
+        struct Fails;
+        impl Read for Fails {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(ErrorKind::WouldBlock, "later"))
+            }
+        }
+
+        let mut buf = ReadBuffer::new();
+        buf.fill_from(&mut Cursor::new(b"kept".to_vec())).unwrap();
+        assert_eq!(buf.fill_from(&mut Fails).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(buf.as_slice(), b"kept");
+        assert_eq!(buf.fill_from(&mut Cursor::new(Vec::new())).unwrap(), 0);
+        assert_eq!(buf.as_slice(), b"kept");
+    }
+}
@@ -0,0 +1,210 @@
+//! A fixed size buffer for what is read from a stream, that wraps
+//! around instead of moving what is left to the front
+//!
+//! What is buffered is at most two slices, the part up to the end of the
+//! memory and the part that wrapped to the start. Whenever everything is
+//! consumed it starts over at the front, so on a connection that keeps up
+//! with its reads nothing is split at all.
+//!
+//! The memory comes from the global Pool, and goes back to it when the
+//! ring is dropped.
+
+#![forbid(unsafe_code)]
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::{Pool, PooledBuf};
+
+pub struct RingBuffer {
+    buf: PooledBuf,
+    // where the buffered bytes start, and how many there are
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a ring buffer needs room for a byte");
+        let mut buf = Pool::global().get(capacity);
+        buf.resize(capacity, 0);
+        RingBuffer { buf: buf, head: 0, len: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// how many more bytes fit
+    pub fn space(&self) -> usize {
+        self.capacity() - self.len
+    }
+
+    /// do one read from r into the free space, returning how many bytes
+    /// were added (0 at the end of the stream)
+    ///
+    /// The read only goes up to the end of the memory, the next one
+    /// carries on at the front. A full buffer is an error, so 0 always
+    /// means the end of the stream.
+    pub fn write_from<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
+        if self.space() == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "the ring buffer is full"));
+        }
+        let tail = self.head + self.len;
+        let n = if tail < self.capacity() {
+            r.read(&mut self.buf[tail..])?
+        }
+        else {
+            let tail = tail - self.capacity();
+            r.read(&mut self.buf[tail..self.head])?
+        };
+        self.len += n;
+        Ok(n)
+    }
+
+    /// the buffered bytes up to the end of the memory (all of them
+    /// unless they wrapped)
+    pub fn peek_contiguous(&self) -> &[u8] {
+        let end = cmp::min(self.head + self.len, self.capacity());
+        &self.buf[self.head..end]
+    }
+
+    pub fn peek_contiguous_mut(&mut self) -> &mut [u8] {
+        let end = cmp::min(self.head + self.len, self.capacity());
+        &mut self.buf[self.head..end]
+    }
+
+    /// all the buffered bytes, in order, the second slice is
+    /// the part that wrapped (empty if nothing did)
+    pub fn peek_split(&self) -> (&[u8], &[u8]) {
+        let wrapped = (self.head + self.len).saturating_sub(self.capacity());
+        (self.peek_contiguous(), &self.buf[..wrapped])
+    }
+
+    /// copy the first buffered bytes into dst without consuming them,
+    /// returning how many were copied
+    pub fn copy_to(&self, dst: &mut [u8]) -> usize {
+        let (first, second) = self.peek_split();
+        let n = cmp::min(first.len(), dst.len());
+        dst[..n].copy_from_slice(&first[..n]);
+        let m = cmp::min(second.len(), dst.len() - n);
+        dst[n..n + m].copy_from_slice(&second[..m]);
+        n + m
+    }
+
+    /// mark n of the buffered bytes as used
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.len, "consumed more than was buffered");
+        self.len -= n;
+        self.head = if self.len == 0 { 0 } else { (self.head + n) % self.capacity() };
+    }
+}
+
+#[cfg(test)]
+mod ring_buffer_tests {
+
+    use std::cmp;
+    use std::io::{self, Cursor, ErrorKind, Read};
+
+    use super::RingBuffer;
+
+    // xorshift, to pick sizes the same way every run
+    struct Rng(u32);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+    }
+
+    // gives out total bytes of a known pattern, a random amount at a time
+    struct Pattern {
+        pos: usize,
+        total: usize,
+        rng: Rng,
+    }
+
+    fn pattern(i: usize) -> u8 {
+        (i ^ (i >> 8) ^ (i >> 16)) as u8
+    }
+
+    impl Read for Pattern {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = cmp::min(cmp::min(buf.len(), 1 + self.rng.below(700)), self.total - self.pos);
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                *b = pattern(self.pos + i);
+            }
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut ring = RingBuffer::new(8);
+        ring.write_from(&mut Cursor::new(b"abcdef".to_vec())).unwrap();
+        ring.consume(4);
+        // the read goes up to the end, the next one wraps to the front
+        let mut input = Cursor::new(b"ghijkl".to_vec());
+        assert_eq!(ring.write_from(&mut input).unwrap(), 2);
+        assert_eq!(ring.write_from(&mut input).unwrap(), 4);
+        assert_eq!(ring.space(), 0);
+        assert_eq!(ring.peek_split(), (&b"efgh"[..], &b"ijkl"[..]));
+        assert_eq!(ring.peek_contiguous(), b"efgh");
+
+        let mut header = [0; 6];
+        assert_eq!(ring.copy_to(&mut header), 6);
+        assert_eq!(&header, b"efghij");
+        assert_eq!(ring.write_from(&mut input).unwrap_err().kind(), ErrorKind::Other);
+
+        ring.consume(5);
+        assert_eq!(ring.peek_split(), (&b"jkl"[..], &b""[..]));
+        // empty starts over at the front
+        ring.consume(3);
+        ring.write_from(&mut Cursor::new(b"12345678".to_vec())).unwrap();
+        assert_eq!(ring.peek_contiguous(), b"12345678");
+    }
+
+    #[test]
+    fn millions_of_wrapped_bytes() {
+        const TOTAL : usize = 4 << 20;
+        // an odd size so the reads and consumes hardly ever line up with it
+        let mut ring = RingBuffer::new(1021);
+        let mut stream = Pattern { pos: 0, total: TOTAL, rng: Rng(0x9e3779b9) };
+        let mut rng = Rng(12345);
+        let mut checked = 0;
+        let mut wrapped = 0;
+
+        loop {
+            if ring.space() > 0 && ring.write_from(&mut stream).unwrap() == 0 && ring.is_empty() {
+                break;
+            }
+            let n = cmp::min(1 + rng.below(900), ring.len());
+            {
+                let (first, second) = ring.peek_split();
+                if !second.is_empty() {
+                    wrapped += 1;
+                }
+                for (i, &b) in first.iter().chain(second).take(n).enumerate() {
+                    assert_eq!(b, pattern(checked + i), "byte {}", checked + i);
+                }
+            }
+            ring.consume(n);
+            checked += n;
+        }
+        assert_eq!(checked, TOTAL);
+        assert!(wrapped > 1000);
+    }
+}
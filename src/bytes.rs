@@ -1,5 +1,24 @@
 use std::io;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// where a seek from pos in a buffer of len lands, past the end is
+// clamped to the end and before the start is an error
+fn seek_to(pos: usize, len: usize, from: SeekFrom) -> io::Result<usize> {
+    let to = match from {
+        SeekFrom::Start(n) => return Ok(if n > len as u64 { len } else { n as usize }),
+        SeekFrom::Current(n) => pos as i64 + n,
+        SeekFrom::End(n) => len as i64 + n,
+    };
+    if to < 0 {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to before the start"))
+    }
+    else if to > len as i64 {
+        Ok(len)
+    }
+    else {
+        Ok(to as usize)
+    }
+}
 
 pub struct Bytes<'buf> {
     buf: &'buf [u8],
@@ -11,6 +30,34 @@ impl<'buf> Bytes<'buf> {
     pub fn new(buf: &'buf [u8]) -> Self {
         Self { buf, pos: 0 }
     }
+
+    /// how many bytes are left to read
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// the bytes that are left to read
+    pub fn as_slice(&self) -> &'buf [u8] {
+        &self.buf[self.pos..]
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// past the end is the end
+    pub fn set_position(&mut self, pos: usize) {
+        self.pos = ::std::cmp::min(pos, self.buf.len());
+    }
+
+    /// skip n bytes (or to the end), giving how many were skipped
+    ///
+    /// (not called skip, that would be hidden by Iterator::skip)
+    pub fn advance(&mut self, n: usize) -> usize {
+        let n = ::std::cmp::min(n, self.remaining());
+        self.pos += n;
+        n
+    }
 }
 
 pub struct BytesMut<'buf> {
@@ -23,16 +70,58 @@ impl<'buf> BytesMut<'buf> {
     pub fn new(buf: &'buf mut [u8]) -> Self {
         Self { buf, pos: 0 }
     }
+
+    /// how many bytes are left to read or write over
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// past the end is the end
+    pub fn set_position(&mut self, pos: usize) {
+        self.pos = ::std::cmp::min(pos, self.buf.len());
+    }
+
+    /// skip n bytes (or to the end), giving how many were skipped
+    pub fn advance(&mut self, n: usize) -> usize {
+        let n = ::std::cmp::min(n, self.remaining());
+        self.pos += n;
+        n
+    }
 }
 
 impl<'buf> Read for Bytes<'buf> {
 
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         use std::cmp;
-        let max = cmp::min(self.buf.len(), buf.len());
-        buf.copy_from_slice(&self.buf[self.pos..max + self.pos]);
-        self.pos += max;
-        Ok(max)
+        let n = cmp::min(self.remaining(), buf.len());
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<'buf> Seek for Bytes<'buf> {
+
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_to(self.pos, self.buf.len(), from)?;
+        Ok(self.pos as u64)
+    }
+}
+
+// reads and writes share the position, so what was written can be
+// read back by seeking to it first
+impl<'buf> Read for BytesMut<'buf> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::cmp;
+        let n = cmp::min(self.remaining(), buf.len());
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
 
@@ -51,6 +140,14 @@ impl<'buf> Write for BytesMut<'buf> {
     }
 }
 
+impl<'buf> Seek for BytesMut<'buf> {
+
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_to(self.pos, self.buf.len(), from)?;
+        Ok(self.pos as u64)
+    }
+}
+
 impl<'buf> Iterator for Bytes<'buf> {
     type Item = u8;
 
@@ -150,6 +247,56 @@ mod bytes_test {
         assert_eq!(&t2, &read_to2);
     }
 
+    #[test]
+    fn read_past_the_end() {
+        use std::io::Read;
+
+        let buf = [1u8, 2, 3];
+        let mut bts = Bytes::new(&buf);
+
+        // a destination bigger than what is left only gets what is left
+        let mut read_to = [0; 8];
+        assert_eq!(bts.read(&mut read_to).unwrap(), 3);
+        assert_eq!(&read_to[..3], &buf);
+        assert_eq!(bts.remaining(), 0);
+        assert_eq!(bts.read(&mut read_to).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_to_eof() {
+        use std::io::Read;
+
+        let buf = [10u8, 11, 12, 13, 14, 15, 16];
+        let mut bts = Bytes::new(&buf);
+
+        let mut read_to = [0; 3];
+        let mut got = Vec::new();
+        loop {
+            match bts.read(&mut read_to).unwrap() {
+                0 => break,
+                n => got.extend_from_slice(&read_to[..n]),
+            }
+            assert_eq!(bts.as_slice(), &buf[got.len()..]);
+        }
+        assert_eq!(&got[..], &buf);
+
+        let mut bts = Bytes::new(&buf);
+        let mut all = Vec::new();
+        bts.read_to_end(&mut all).unwrap();
+        assert_eq!(&all[..], &buf);
+    }
+
+    #[test]
+    fn read_nothing() {
+        use std::io::Read;
+
+        let buf = [1u8, 2, 3];
+        let mut bts = Bytes::new(&buf);
+        assert_eq!(bts.read(&mut []).unwrap(), 0);
+        assert_eq!(bts.remaining(), 3);
+        assert_eq!(bts.as_slice(), &buf);
+    }
+
     #[test]
     fn write_test() {
         use std::io::Write;
@@ -176,4 +323,89 @@ mod bytes_test {
 
         assert_eq!(&buf, &write_to);
     }
+
+    #[test]
+    fn seek_start() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let buf = [0u8, 1, 2, 3, 4, 5];
+        let mut bts = Bytes::new(&buf);
+        assert_eq!(bts.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(bts.next(), Some(4));
+        // past the end is clamped to the end
+        assert_eq!(bts.seek(SeekFrom::Start(100)).unwrap(), 6);
+        assert_eq!(bts.read(&mut [0; 2]).unwrap(), 0);
+        assert_eq!(bts.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_eq!(bts.as_slice(), &buf);
+    }
+
+    #[test]
+    fn seek_current() {
+        use std::io::{Seek, SeekFrom};
+
+        let buf = [0u8, 1, 2, 3, 4, 5];
+        let mut bts = Bytes::new(&buf);
+        bts.advance(3);
+        assert_eq!(bts.seek(SeekFrom::Current(-2)).unwrap(), 1);
+        assert_eq!(bts.seek(SeekFrom::Current(2)).unwrap(), 3);
+        assert_eq!(bts.seek(SeekFrom::Current(10)).unwrap(), 6);
+        assert!(bts.seek(SeekFrom::Current(-7)).is_err());
+        // a failed seek does not move
+        assert_eq!(bts.position(), 6);
+    }
+
+    #[test]
+    fn seek_end() {
+        use std::io::{Seek, SeekFrom};
+
+        let mut buf = [0u8, 1, 2, 3, 4, 5];
+        {
+            let mut bts = Bytes::new(&buf);
+            assert_eq!(bts.seek(SeekFrom::End(-2)).unwrap(), 4);
+            assert_eq!(bts.as_slice(), &[4, 5]);
+            assert_eq!(bts.seek(SeekFrom::End(3)).unwrap(), 6);
+            assert!(bts.seek(SeekFrom::End(-7)).is_err());
+        }
+
+        let mut bts = BytesMut::new(&mut buf);
+        assert_eq!(bts.seek(SeekFrom::End(-1)).unwrap(), 5);
+        assert_eq!(bts.remaining(), 1);
+    }
+
+    #[test]
+    fn position_and_advance() {
+        let buf = [0u8, 1, 2, 3, 4, 5];
+        let mut bts = Bytes::new(&buf);
+        assert_eq!(bts.advance(4), 4);
+        assert_eq!(bts.position(), 4);
+        assert_eq!(bts.advance(4), 2);
+        bts.set_position(1);
+        assert_eq!(bts.next(), Some(1));
+        bts.set_position(10);
+        assert_eq!(bts.position(), 6);
+    }
+
+    #[test]
+    fn read_and_write_mut() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let mut buf = [0u8; 8];
+        {
+            let mut bts = BytesMut::new(&mut buf);
+            bts.write_all(b"abcd").unwrap();
+
+            // go back and read what was written, then write on from there
+            bts.seek(SeekFrom::Start(1)).unwrap();
+            let mut read_to = [0; 2];
+            assert_eq!(bts.read(&mut read_to).unwrap(), 2);
+            assert_eq!(&read_to, b"bc");
+            assert_eq!(bts.write(b"XYZ").unwrap(), 3);
+            assert_eq!(bts.position(), 6);
+
+            bts.advance(1);
+            assert_eq!(bts.write(b"end").unwrap(), 1);
+            assert_eq!(bts.read(&mut read_to).unwrap(), 0);
+        }
+        assert_eq!(&buf, b"abcXYZ\0e");
+    }
 }
@@ -0,0 +1,154 @@
+//! How much memory a connection holds on to, all together
+//!
+//! Each buffer has its own limit, but a hundred streams each with a full
+//! window of body, a header block and a long outbound queue still add up.
+//! Everything the connection buffers for the peer is charged to one
+//! MemoryBudget and credited when it is let go:
+//!
+//! - the FrameReader's buffers;
+//! - DATA that was received and not released yet (the request bodies);
+//! - a header block being put together from CONTINUATION frames;
+//! - frames queued to be written.
+//!
+//! Past the high-water mark the connection holds back WINDOW_UPDATEs, so
+//! the peer runs out of window and stops sending bodies. Going over the
+//! ceiling anyway resets the stream that holds the most, and a header
+//! block that still does not fit ends the connection, both with
+//! ENHANCE_YOUR_CALM.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// how much a connection can hold unless configured otherwise
+pub const DEFAULT_MEMORY_BUDGET : usize = 4 << 20;
+
+// the share of the ceiling (in percent) past which the peer is slowed down
+const HIGH_WATER_PERCENT : usize = 75;
+
+struct Shared {
+    used: AtomicUsize,
+    ceiling: usize,
+}
+
+/// A count of bytes held against a ceiling, clones share the count
+#[derive(Clone)]
+pub struct MemoryBudget {
+    shared: Arc<Shared>,
+}
+
+impl MemoryBudget {
+
+    pub fn new(ceiling: usize) -> Self {
+        MemoryBudget { shared: Arc::new(Shared { used: AtomicUsize::new(0), ceiling: ceiling }) }
+    }
+
+    pub fn ceiling(&self) -> usize {
+        self.shared.ceiling
+    }
+
+    /// how many bytes are charged
+    pub fn used(&self) -> usize {
+        self.shared.used.load(Ordering::Relaxed)
+    }
+
+    /// how many more bytes fit under the ceiling
+    pub fn available(&self) -> usize {
+        self.ceiling().saturating_sub(self.used())
+    }
+
+    /// charge n bytes if they fit under the ceiling, false if they do not
+    /// (and nothing was charged)
+    pub fn try_charge(&self, n: usize) -> bool {
+        let mut used = self.used();
+        loop {
+            if used.saturating_add(n) > self.shared.ceiling {
+                return false;
+            }
+            match self.shared.used.compare_exchange_weak(used, used + n, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(now) => used = now,
+            }
+        }
+    }
+
+    /// charge n bytes whether they fit or not, for what is already held
+    /// by the time it is counted (like DATA the peer had the window for)
+    pub fn charge(&self, n: usize) {
+        self.shared.used.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// give back n bytes that were charged
+    pub fn credit(&self, n: usize) {
+        let prev = self.shared.used.fetch_sub(n, Ordering::Relaxed);
+        debug_assert!(prev >= n, "credited more than was charged");
+    }
+
+    /// is the count past the high-water mark, where the peer should be
+    /// slowed down
+    pub fn under_pressure(&self) -> bool {
+        self.used() > self.ceiling() / 100 * HIGH_WATER_PERCENT
+    }
+
+    /// is the count past the ceiling
+    pub fn is_over(&self) -> bool {
+        self.used() > self.ceiling()
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget::new(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+
+    use std::thread;
+
+    use super::MemoryBudget;
+
+    #[test]
+    fn charges() {
+        let budget = MemoryBudget::new(1000);
+        assert!(budget.try_charge(700));
+        assert!(!budget.under_pressure());
+        assert!(!budget.try_charge(301));
+        assert_eq!(budget.used(), 700);
+        budget.charge(100);
+        assert!(budget.under_pressure());
+        assert_eq!(budget.available(), 200);
+
+        // what can not be refused still counts
+        budget.charge(500);
+        assert!(budget.is_over());
+        assert_eq!(budget.available(), 0);
+        budget.credit(1300);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let budget = MemoryBudget::new(10000);
+        let threads: Vec<_> = (0..8).map(|_| {
+            let budget = budget.clone();
+            thread::spawn(move || {
+                let mut held = 0;
+                for i in 0..1000 {
+                    if budget.try_charge(i % 50) {
+                        held += i % 50;
+                    }
+                    if i % 3 == 0 {
+                        budget.credit(held);
+                        held = 0;
+                    }
+                }
+                budget.credit(held);
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,510 @@
+//! The connection as a state machine, frames in and actions out
+//!
+//! A Connection does no I/O, it is the core that a driver (run, serve or
+//! a test) feeds the frames it reads and takes what to write from. Every
+//! driver handles what goes wrong with a frame the same way, so that is
+//! done here too: receive_frame resets the stream over a stream error and
+//! only hands back a connection error, which the driver reports and ends
+//! the connection over.
+//!
+//! handle_frame goes one step further for a test (or a driver that does
+//! not mind the copies), whatever the frame led to comes back as a list
+//! of actions. The socket, the reader and writer and TLS are all the
+//! driver's, and a test can go through a whole exchange with none of
+//! them. What is left in the Connection is not I/O: the clock is the one
+//! set with set_clock (a MockClock in a test), the access log a sink the
+//! driver hands it, and the budget and the Date cache only keep count.
+
+use frame::frame_types::GenericFrame;
+use frame::OwnedFrame;
+use log::Context;
+use super::Connection;
+use super::error::H2Error;
+use super::event::Event;
+
+/// What a driver has to do after a frame
+pub enum Action {
+    /// write a frame to the peer
+    Send(OwnedFrame),
+    /// pass something on to the application
+    Event(Event),
+    /// the connection failed, close it once the frames before this
+    /// (which end with the GOAWAY) are written
+    Close(H2Error),
+}
+
+impl Connection {
+
+    /// dispatch_frame, with a stream error logged and the stream reset
+    ///
+    /// A connection error is returned without being reported, for the
+    /// driver to send in a GOAWAY (along with anything it knows about why)
+    /// and close the connection.
+    pub fn receive_frame(&mut self, frame: GenericFrame) -> Result<(), H2Error> {
+        let res = self.dispatch_frame(frame);
+        self.stream_errors(res)
+    }
+
+    /// dispatch_skipped, with errors handled as receive_frame does
+    pub fn receive_skipped(&mut self, header: &[u8]) -> Result<(), H2Error> {
+        let res = self.dispatch_skipped(header);
+        self.stream_errors(res)
+    }
+
+    /// receive a frame and take the actions it led to, what the driver
+    /// has to do about it
+    pub fn handle_frame(&mut self, frame: GenericFrame) -> Vec<Action> {
+        match self.receive_frame(frame) {
+            Ok(()) => self.take_actions(),
+            Err(e) => {
+                self.report_error(&e);
+                let mut actions = self.take_actions();
+                actions.push(Action::Close(e));
+                actions
+            },
+        }
+    }
+
+    /// every frame queued to send, then every event, e.g. what the
+    /// application queued in answer to the last ones
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while let Some(frame) = self.next_outbound() {
+            actions.push(Action::Send(frame));
+        }
+        while let Some(event) = self.poll_event() {
+            actions.push(Action::Event(event));
+        }
+        actions
+    }
+
+    fn stream_errors(&mut self, res: Result<(), H2Error>) -> Result<(), H2Error> {
+        match res {
+            Err(e @ H2Error::Stream(..)) => {
+                klog_debug!(Context::peer(self.peer_addr()) => "{}", e);
+                self.report_error(&e);
+                Ok(())
+            },
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod core_tests {
+
+    use super::Action;
+    use connection::Connection;
+    use connection::error::{ErrorCode, H2Error};
+    use connection::event::Event;
+    use connection::limits::{HeaderBlockLimits, DEFAULT_MAX_CONTINUATIONS};
+    use connection::settings::INITIAL_WINDOW_SIZE;
+    use connection::stream::StreamState;
+    use connection::window::WindowUpdates;
+    use frame::OwnedFrame;
+    use frame::frame_types::{types, flags};
+    use header::HeaderList;
+    use test_util::GET_BLOCK;
+
+    fn handle(core: &mut Connection, mut frame: OwnedFrame) -> Vec<Action> {
+        core.handle_frame(frame.as_frame())
+    }
+
+    // a connection past the exchange of SETTINGS
+    fn settled() -> Connection {
+        let mut core = Connection::new();
+        core.take_actions(); // preface
+        handle(&mut core, OwnedFrame::settings(&[]));
+        handle(&mut core, OwnedFrame::settings_ack());
+        core
+    }
+
+    // (type, stream, flags) of the frames sent, and what else there was
+    fn sent(actions: &[Action]) -> Vec<(u8, u32, u8)> {
+        actions.iter().filter_map(|a| match *a {
+            Action::Send(ref frame) => Some((frame.frame_type(), frame.stream_id(), frame.frame_flags())),
+            _ => None,
+        }).collect()
+    }
+
+    fn described(actions: &[Action]) -> Vec<String> {
+        actions.iter().map(|a| match *a {
+            Action::Send(ref frame) => format!("send {} {}", frame.frame_type(), frame.stream_id()),
+            Action::Event(Event::Headers { stream_id, end_stream, .. }) => format!("headers {} {}", stream_id, end_stream),
+            Action::Event(Event::Data { stream_id, ref data, end_stream }) => format!("data {} {} {}", stream_id, data.len(), end_stream),
+            Action::Event(Event::StreamReset { stream_id, error, .. }) => format!("reset {} {:?}", stream_id, error),
+            Action::Event(_) => "event".to_string(),
+            Action::Close(ref e) => format!("close {}", e),
+        }).collect()
+    }
+
+    // the payload size of the DATA frames sent, and if one ended the stream
+    fn data_sent(actions: &[Action]) -> (usize, bool) {
+        let mut total = 0;
+        let mut end_stream = false;
+        for action in actions {
+            if let Action::Send(ref frame) = *action {
+                if frame.frame_type() == types::DATA {
+                    total += frame.payload().len();
+                    end_stream |= frame.frame_flags() & flags::END_STREAM != 0;
+                }
+            }
+        }
+        (total, end_stream)
+    }
+
+    // the (stream id, increment) of every WINDOW_UPDATE sent
+    fn window_updates(actions: &[Action]) -> Vec<(u32, u32)> {
+        actions.iter().filter_map(|a| match *a {
+            Action::Send(ref frame) if frame.frame_type() == types::WINDOW_UPDATE => {
+                let p = frame.payload();
+                Some((frame.stream_id(), (p[0] as u32) << 24 | (p[1] as u32) << 16 | (p[2] as u32) << 8 | p[3] as u32))
+            },
+            _ => None,
+        }).collect()
+    }
+
+    // the error the connection was closed over, if it was
+    fn closed_with(actions: &[Action]) -> Option<ErrorCode> {
+        match actions.last() {
+            Some(&Action::Close(ref e)) => Some(e.code()),
+            _ => None,
+        }
+    }
+
+    fn status_200() -> HeaderList {
+        let mut headers = HeaderList::with_capacity(1);
+        headers.add_entry((":status", "200").into());
+        headers
+    }
+
+    #[test]
+    fn stream_states() {
+        let mut core = settled();
+
+        let actions = handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS));
+        assert_eq!(described(&actions), vec!["headers 1 false"]);
+        assert_eq!(core.stream(1).unwrap().state(), StreamState::Open);
+
+        let actions = handle(&mut core, OwnedFrame::data(1, b"body", true));
+        assert_eq!(described(&actions), vec!["data 1 4 true"]);
+        assert_eq!(core.stream(1).unwrap().state(), StreamState::HalfClosedRemote);
+
+        // more DATA once the peer ended the stream only resets it
+        let actions = handle(&mut core, OwnedFrame::data(1, b"more", false));
+        assert_eq!(sent(&actions), vec![(types::RST_STREAM, 1, 0)]);
+        assert!(core.stream(1).is_none());
+
+        // the application answering another stream
+        handle(&mut core, OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        core.send_headers(3, &status_200(), false).unwrap();
+        core.send_data(3, b"hello", true).unwrap();
+        assert_eq!(sent(&core.take_actions()), vec![(types::HEADERS, 3, flags::END_HEADERS), (types::DATA, 3, flags::END_STREAM)]);
+        assert!(core.stream(3).is_none());
+    }
+
+    #[test]
+    fn flow_control() {
+        let mut core = settled();
+        let actions = handle(&mut core, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 10)]));
+        assert_eq!(sent(&actions), vec![(types::SETTINGS, 0, flags::ACK)]);
+
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        core.send_data(1, &[0; 25], true).unwrap();
+        let actions = core.take_actions();
+        assert_eq!(described(&actions), vec!["send 0 1"]);
+
+        // each WINDOW_UPDATE lets as much more go, the last of it ends the stream
+        let actions = handle(&mut core, OwnedFrame::window_update(1, 10));
+        assert_eq!(sent(&actions), vec![(types::DATA, 1, 0)]);
+        let actions = handle(&mut core, OwnedFrame::window_update(1, 10));
+        assert_eq!(sent(&actions), vec![(types::DATA, 1, flags::END_STREAM)]);
+        assert!(core.stream(1).is_none());
+
+        // too much for the window of the connection ends it
+        let actions = handle(&mut core, OwnedFrame::window_update(0, 0x7FFFFFFF));
+        assert_eq!(sent(&actions), vec![(types::GOAWAY, 0, 0)]);
+        match actions.last() {
+            Some(&Action::Close(ref e)) => assert_eq!(e.code(), ErrorCode::FlowControlError),
+            _ => panic!("the connection was not closed"),
+        }
+    }
+
+    #[test]
+    fn continuation() {
+        let mut core = settled();
+
+        // nothing comes of a header block until all of it is in
+        let actions = handle(&mut core, OwnedFrame::headers(1, &GET_BLOCK[..2], 0));
+        assert!(actions.is_empty());
+        let actions = handle(&mut core, OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &GET_BLOCK[2..]));
+        assert_eq!(described(&actions), vec!["headers 1 false"]);
+
+        // and any other frame in the middle of one is a connection error
+        handle(&mut core, OwnedFrame::headers(3, &GET_BLOCK[..2], 0));
+        let actions = handle(&mut core, OwnedFrame::ping(false, &[0; 8]));
+        assert_eq!(sent(&actions), vec![(types::GOAWAY, 0, 0)]);
+        match actions.last() {
+            Some(&Action::Close(H2Error::Connection(code, _))) => assert_eq!(code, ErrorCode::ProtocolError),
+            _ => panic!("the connection was not closed"),
+        }
+    }
+
+    #[test]
+    fn stream_ids() {
+        // a client only opens odd streams, each above the last
+        let actions = handle(&mut settled(), OwnedFrame::headers(2, GET_BLOCK, flags::END_HEADERS));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::ProtocolError));
+
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(5, GET_BLOCK, flags::END_HEADERS));
+        let actions = handle(&mut core, OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn frames_on_the_wrong_stream() {
+        let frames = vec![
+            OwnedFrame::data(0, b"data", false),
+            OwnedFrame::headers(0, GET_BLOCK, flags::END_HEADERS),
+            OwnedFrame::rst_stream(0, 0),
+            OwnedFrame::new(types::SETTINGS, 0, 5, &[]),
+            OwnedFrame::new(types::GOAWAY, 0, 1, &[0; 8]),
+        ];
+        for frame in frames {
+            let actions = handle(&mut settled(), frame);
+            assert_eq!(sent(&actions), vec![(types::GOAWAY, 0, 0)]);
+            assert_eq!(closed_with(&actions), Some(ErrorCode::ProtocolError));
+        }
+    }
+
+    #[test]
+    fn frames_on_unknown_streams() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(5, GET_BLOCK, flags::END_HEADERS));
+
+        // stream 3 was skipped over so it is closed without ever existing
+        let actions = handle(&mut core, OwnedFrame::data(3, b"data", false));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::StreamClosed));
+
+        // stream 7 is idle
+        for frame in vec![OwnedFrame::data(7, b"data", false), OwnedFrame::rst_stream(7, 0)] {
+            let mut core = settled();
+            handle(&mut core, OwnedFrame::headers(5, GET_BLOCK, flags::END_HEADERS));
+            assert_eq!(closed_with(&handle(&mut core, frame)), Some(ErrorCode::ProtocolError));
+        }
+    }
+
+    #[test]
+    fn rst_stream_received() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS));
+        core.send_data(1, &vec![0; 70000], false).unwrap();
+        assert_eq!(data_sent(&core.take_actions()), (65535, false));
+
+        let actions = handle(&mut core, OwnedFrame::rst_stream(1, ErrorCode::Cancel as u32));
+        assert_eq!(described(&actions), vec!["reset 1 Cancel"]);
+        assert!(core.stream(1).is_none());
+
+        // frames that were already in flight are ignored
+        let frames = vec![
+            OwnedFrame::data(1, b"late", true),
+            OwnedFrame::window_update(1, 100),
+            OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM),
+        ];
+        for frame in frames {
+            let actions = handle(&mut core, frame);
+            assert!(actions.iter().all(|a| match *a { Action::Event(_) => false, _ => true }));
+            assert!(closed_with(&actions).is_none());
+        }
+
+        // and the data that was still queued is not sent
+        let actions = handle(&mut core, OwnedFrame::window_update(0, 100000));
+        assert_eq!(data_sent(&actions), (0, false));
+    }
+
+    #[test]
+    fn recv_flow_control() {
+        let mut core = settled();
+        core.set_window_updates(WindowUpdates::Immediate);
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS));
+        let chunk = vec![0; 16384];
+        let mut actions = Vec::new();
+        for _ in 0..3 {
+            actions.extend(handle(&mut core, OwnedFrame::data(1, &chunk, false)));
+        }
+        actions.extend(handle(&mut core, OwnedFrame::data(1, &chunk[..16383], false)));
+        assert_eq!((core.recv_window(), core.stream(1).unwrap().recv_window()), (0, 0));
+        assert_eq!(window_updates(&actions), vec![]);
+
+        // released data can be sent again
+        core.release_window(1, 16384);
+        assert_eq!(window_updates(&core.take_actions()), vec![(0, 16384), (1, 16384)]);
+        let actions = handle(&mut core, OwnedFrame::data(1, &chunk, false));
+        assert!(closed_with(&actions).is_none());
+
+        // but not more than that
+        let actions = handle(&mut core, OwnedFrame::data(1, b"x", false));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::FlowControlError));
+    }
+
+    #[test]
+    fn send_data_respects_windows() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 16384)]));
+        handle(&mut core, OwnedFrame::window_update(0, 100 * 1024));
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+
+        let body = vec![0xAB; 100 * 1024];
+        core.send_data(1, &body, true).unwrap();
+        let (sent, end_stream) = data_sent(&core.take_actions());
+        assert_eq!(sent, 16384);
+        assert!(!end_stream);
+
+        // nothing more goes out until the stream window opens up again
+        let mut total = sent;
+        while total < body.len() {
+            assert_eq!(data_sent(&core.take_actions()), (0, false));
+            let (sent, end_stream) = data_sent(&handle(&mut core, OwnedFrame::window_update(1, 16384)));
+            assert!(sent > 0 && sent <= 16384);
+            total += sent;
+            assert_eq!(end_stream, total == body.len());
+        }
+
+        assert_eq!(total, body.len());
+        assert_eq!(core.send_window(), 65535 + 100 * 1024 - body.len() as i32);
+    }
+
+    #[test]
+    fn connection_window_limits_streams() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        core.send_data(1, &vec![0; 70000], true).unwrap();
+
+        // default connection window is 65535
+        assert_eq!(data_sent(&core.take_actions()), (65535, false));
+        assert_eq!(data_sent(&handle(&mut core, OwnedFrame::window_update(1, 10000))), (0, false));
+        assert_eq!(data_sent(&handle(&mut core, OwnedFrame::window_update(0, 10000))), (70000 - 65535, true));
+    }
+
+    #[test]
+    fn initial_window_size_change() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        core.send_data(1, &[0; 200], false).unwrap();
+        assert_eq!(data_sent(&core.take_actions()), (200, false));
+
+        // shrink the window below what has already been sent
+        handle(&mut core, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 100)]));
+        assert_eq!(core.stream(1).unwrap().send_window(), -100);
+
+        core.send_data(1, &[0; 100], true).unwrap();
+        assert_eq!(data_sent(&core.take_actions()), (0, false));
+        assert_eq!(data_sent(&handle(&mut core, OwnedFrame::window_update(1, 150))), (50, false));
+
+        // growing the setting again applies the positive delta
+        let actions = handle(&mut core, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 150)]));
+        assert_eq!(data_sent(&actions), (50, true));
+    }
+
+    #[test]
+    fn window_overflow() {
+        // a stream window past 2^31-1 resets the stream
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        let actions = handle(&mut core, OwnedFrame::window_update(1, 0x7FFFFFFF));
+        assert_eq!(sent(&actions), vec![(types::RST_STREAM, 1, 0)]);
+        assert!(core.stream(1).is_none());
+
+        // the window of the connection ends it
+        let actions = handle(&mut core, OwnedFrame::window_update(0, 0x7FFFFFFF));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::FlowControlError));
+
+        // and so does a SETTINGS that takes a stream window past it
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM));
+        handle(&mut core, OwnedFrame::window_update(1, 0x7FFFFFFF - 65535));
+        let actions = handle(&mut core, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 65536)]));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::FlowControlError));
+    }
+
+    #[test]
+    fn header_block_over_continuation() {
+        let mut core = settled();
+        let frames = vec![
+            OwnedFrame::headers(1, &GET_BLOCK[..1], flags::END_STREAM),
+            OwnedFrame::new(types::CONTINUATION, 0, 1, &GET_BLOCK[1..2]),
+        ];
+        for frame in frames {
+            assert!(handle(&mut core, frame).is_empty());
+        }
+
+        let mut actions = handle(&mut core, OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &GET_BLOCK[2..]));
+        assert_eq!(actions.len(), 1);
+        match actions.pop() {
+            Some(Action::Event(Event::Headers { stream_id: 1, headers, end_stream: true })) => {
+                assert_eq!(headers.get_value_by_name(":method"), Some("GET"));
+                assert_eq!(headers.get_value_by_name(":path"), Some("/"));
+                assert_eq!(headers.get_value_by_name(":scheme"), Some("https"));
+            },
+            _ => panic!("expected the header block"),
+        }
+
+        // the block is done so other frames are fine again
+        let actions = handle(&mut core, OwnedFrame::ping(false, &[0; 8]));
+        assert_eq!(sent(&actions), vec![(types::PING, 0, flags::ACK)]);
+    }
+
+    #[test]
+    fn continuation_sequencing() {
+        // the error for the second frame, after the first began a block
+        fn after(first: OwnedFrame, second: OwnedFrame) -> Option<ErrorCode> {
+            let mut core = settled();
+            handle(&mut core, first);
+            closed_with(&handle(&mut core, second))
+        }
+
+        // another frame type in the middle of the block
+        assert_eq!(after(OwnedFrame::headers(1, GET_BLOCK, 0), OwnedFrame::data(1, b"body", true)),
+                   Some(ErrorCode::ProtocolError));
+
+        // including frames for the connection
+        assert_eq!(after(OwnedFrame::headers(1, GET_BLOCK, 0), OwnedFrame::ping(false, &[0; 8])),
+                   Some(ErrorCode::ProtocolError));
+
+        // interleaved with another stream
+        assert_eq!(after(OwnedFrame::headers(1, &GET_BLOCK[..1], 0), OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 3, &GET_BLOCK[1..])),
+                   Some(ErrorCode::ProtocolError));
+        assert_eq!(after(OwnedFrame::headers(1, &GET_BLOCK[..1], 0), OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS)),
+                   Some(ErrorCode::ProtocolError));
+
+        // with no header block to continue
+        assert_eq!(after(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS), OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &[])),
+                   Some(ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn continuation_flood() {
+        let mut core = settled();
+        handle(&mut core, OwnedFrame::headers(1, &GET_BLOCK[..1], 0));
+        for _ in 0..DEFAULT_MAX_CONTINUATIONS {
+            assert!(handle(&mut core, OwnedFrame::new(types::CONTINUATION, 0, 1, &[])).is_empty());
+        }
+        let actions = handle(&mut core, OwnedFrame::new(types::CONTINUATION, 0, 1, &[]));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::EnhanceYourCalm));
+
+        let mut core = settled();
+        core.set_header_block_limits(HeaderBlockLimits { max_block_size: 100, .. HeaderBlockLimits::default() });
+        handle(&mut core, OwnedFrame::headers(1, &GET_BLOCK[..1], 0));
+        handle(&mut core, OwnedFrame::new(types::CONTINUATION, 0, 1, &[0x84; 90]));
+        let actions = handle(&mut core, OwnedFrame::new(types::CONTINUATION, 0, 1, &[0x84; 10]));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::EnhanceYourCalm));
+        assert!(core.partial_headers.block.is_empty());
+
+        // a single HEADERS frame is held to the same size
+        let mut core = settled();
+        core.set_header_block_limits(HeaderBlockLimits { max_block_size: 2, .. HeaderBlockLimits::default() });
+        let actions = handle(&mut core, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS));
+        assert_eq!(closed_with(&actions), Some(ErrorCode::EnhanceYourCalm));
+    }
+}
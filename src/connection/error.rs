@@ -0,0 +1,225 @@
+//! Error codes defined in section 7 of the spec and the error type
+//! used through the connection to say what scope an error applies to.
+//!
+//! Error codes are 32-bit fields that are used in RST_STREAM and GOAWAY
+//! frames to convey the reasons for the stream or connection error.
+
+use std::fmt;
+use std::error::Error;
+use std::net::SocketAddr;
+
+use krserr::{HasKind, Kind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd,
+}
+
+impl From<u32> for ErrorCode {
+    // Unknown or unsupported error codes MUST NOT trigger any special behavior.
+    // These MAY be treated by an implementation as being equivalent to INTERNAL_ERROR.
+    fn from(code: u32) -> ErrorCode {
+        use self::ErrorCode::*;
+        match code {
+            0x0 => NoError,
+            0x1 => ProtocolError,
+            0x2 => InternalError,
+            0x3 => FlowControlError,
+            0x4 => SettingsTimeout,
+            0x5 => StreamClosed,
+            0x6 => FrameSizeError,
+            0x7 => RefusedStream,
+            0x8 => Cancel,
+            0x9 => CompressionError,
+            0xa => ConnectError,
+            0xb => EnhanceYourCalm,
+            0xc => InadequateSecurity,
+            0xd => Http11Required,
+            _   => InternalError,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ErrorCode::*;
+        let name = match *self {
+            NoError => "NO_ERROR",
+            ProtocolError => "PROTOCOL_ERROR",
+            InternalError => "INTERNAL_ERROR",
+            FlowControlError => "FLOW_CONTROL_ERROR",
+            SettingsTimeout => "SETTINGS_TIMEOUT",
+            StreamClosed => "STREAM_CLOSED",
+            FrameSizeError => "FRAME_SIZE_ERROR",
+            RefusedStream => "REFUSED_STREAM",
+            Cancel => "CANCEL",
+            CompressionError => "COMPRESSION_ERROR",
+            ConnectError => "CONNECT_ERROR",
+            EnhanceYourCalm => "ENHANCE_YOUR_CALM",
+            InadequateSecurity => "INADEQUATE_SECURITY",
+            Http11Required => "HTTP_1_1_REQUIRED",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An error is either fatal to the whole connection (section 5.4.1)
+/// or only to a single stream (section 5.4.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum H2Error {
+    Connection(ErrorCode, String),
+    Stream(u32, ErrorCode),
+}
+
+impl H2Error {
+    // convenience for the common case of a connection error
+    pub fn connection<S: Into<String>>(code: ErrorCode, msg: S) -> H2Error {
+        H2Error::Connection(code, msg.into())
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            H2Error::Connection(code, _) => code,
+            H2Error::Stream(_, code) => code,
+        }
+    }
+}
+
+impl fmt::Display for H2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            H2Error::Connection(code, ref msg) => write!(f, "connection error {}: {}", code, msg),
+            H2Error::Stream(id, code) => write!(f, "stream {} error {}", id, code),
+        }
+    }
+}
+
+impl Error for H2Error {
+    fn description(&self) -> &str {
+        "Error: H2Error"
+    }
+}
+
+/// The outermost link of the chain for an error that ended a
+/// connection, it has the kind of what ended it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionFailed {
+    peer: Option<SocketAddr>,
+    kind: Kind,
+}
+
+impl ConnectionFailed {
+    pub fn new(peer: Option<SocketAddr>, kind: Kind) -> Self {
+        ConnectionFailed { peer: peer, kind: kind }
+    }
+}
+
+impl fmt::Display for ConnectionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.peer {
+            Some(addr) => write!(f, "connection {} failed", addr),
+            None => write!(f, "connection failed"),
+        }
+    }
+}
+
+impl Error for ConnectionFailed {
+    fn description(&self) -> &str {
+        "Error: ConnectionFailed"
+    }
+}
+
+impl HasKind for ConnectionFailed {
+    fn kind(&self) -> Kind {
+        self.kind
+    }
+}
+
+/// Why a server push could not be started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError {
+    /// the peer set SETTINGS_ENABLE_PUSH to 0
+    Disabled,
+    /// only safe and cacheable requests (GET and HEAD) can be pushed
+    NotCacheable,
+    /// the peer's SETTINGS_MAX_CONCURRENT_STREAMS is reached
+    TooManyStreams,
+    /// no new streams can be opened after GOAWAY
+    GoingAway,
+    /// the associated stream is not open, or there are no stream ids left
+    StreamClosed,
+    /// the request is bigger than the peer's SETTINGS_MAX_HEADER_LIST_SIZE
+    HeadersTooLarge,
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PushError::*;
+        let msg = match *self {
+            Disabled => "push is disabled by the peer",
+            NotCacheable => "pushed request must be GET or HEAD",
+            TooManyStreams => "too many concurrent streams",
+            GoingAway => "connection is going away",
+            StreamClosed => "associated stream is closed",
+            HeadersTooLarge => "pushed request headers are too large for the peer",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl Error for PushError {
+    fn description(&self) -> &str {
+        "Error: PushError"
+    }
+}
+
+/// Why an extension frame could not be sent, what was asked for is
+/// wrong and the connection is fine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionError {
+    /// the type is one of the frames the spec defines (0x0 to 0x9)
+    NotAnExtension(u8),
+    /// the payload is bigger than the peer's SETTINGS_MAX_FRAME_SIZE
+    TooLarge(usize),
+}
+
+impl fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtensionError::NotAnExtension(type_id) => write!(f, "frame type 0x{:02X} is not an extension", type_id),
+            ExtensionError::TooLarge(len) => write!(f, "extension frame of {} bytes is too large for the peer", len),
+        }
+    }
+}
+
+impl Error for ExtensionError {
+    fn description(&self) -> &str {
+        "Error: ExtensionError"
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::ErrorCode;
+
+    #[test]
+    fn error_code_from_u32() {
+        assert_eq!(ErrorCode::from(0x3), ErrorCode::FlowControlError);
+        assert_eq!(ErrorCode::from(0xb), ErrorCode::EnhanceYourCalm);
+        assert_eq!(ErrorCode::from(0xff), ErrorCode::InternalError);
+        assert_eq!(ErrorCode::Cancel as u32, 0x8);
+    }
+}
@@ -0,0 +1,35 @@
+//! Things that happen on the connection that the
+//! application using it needs to know about
+
+use std::time::Duration;
+
+use header::HeaderList;
+use super::error::ErrorCode;
+
+/// Identifies a PING sent with Connection::ping so the
+/// acknowledgement can be matched up with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PingToken(pub u64);
+
+pub enum Event {
+    /// a complete header block was received, opening the stream
+    Headers { stream_id: u32, headers: HeaderList, end_stream: bool },
+    /// 8.1 a header block after the request body, which always ends the stream
+    Trailers { stream_id: u32, headers: HeaderList },
+    /// payload of a DATA frame with padding removed, which needs to be
+    /// given back with Connection::release_window once it is consumed
+    Data { stream_id: u32, data: Vec<u8>, end_stream: bool },
+    /// the peer reset the stream (or the connection did, to stay within
+    /// its memory budget), whatever was being done for it should be
+    /// abandoned
+    StreamReset { stream_id: u32, error: ErrorCode, by_peer: bool },
+    /// the peer acknowledged one of our PINGs
+    PongReceived { token: PingToken, rtt: Duration },
+    /// the peer is shutting down the connection, streams above
+    /// last_stream_id were not and will not be processed
+    GoAway { last_stream_id: u32, error: ErrorCode, debug: Vec<u8> },
+    /// 5.5 a frame of a type the connection does not know, only given to
+    /// the application when it asked for them with
+    /// Connection::set_deliver_extension_frames
+    ExtensionFrame { frame_type: u8, flags: u8, stream_id: u32, payload: Vec<u8> },
+}
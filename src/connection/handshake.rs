@@ -0,0 +1,291 @@
+//! 3. Starting HTTP/2
+//!
+//! A connection starts either with the client connection preface (when
+//! the client knows the server speaks HTTP/2, e.g. after TLS ALPN) or with
+//! an HTTP/1.1 request asking to upgrade to h2c (section 3.2). In the
+//! upgrade case the request becomes stream 1, and the client still sends
+//! the preface once it gets the 101 response.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+use frame::OwnedFrame;
+use h1::{self, RequestHead};
+use header::{intern, HeaderList};
+use util::{base64, Clock, SystemClock};
+
+use super::Connection;
+use super::error::H2Error;
+use super::event::Event;
+use super::priority::DEFAULT_WEIGHT;
+use super::reader::FrameReader;
+use super::settings::{Settings, SettingsEffect};
+use super::stream::{Stream, StreamState};
+
+/// 3.5 the client connection preface starts with this sequence
+pub const PREFACE : &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+static SWITCHING_PROTOCOLS : &'static [u8] = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+// header fields that only mean something to the HTTP/1.1 connection
+// and are not carried over to the HTTP/2 request (8.1.2.2)
+static CONNECTION_SPECIFIC : &'static [&'static str] = &[
+    "connection", "upgrade", "http2-settings", "host", "keep-alive", "proxy-connection", "transfer-encoding",
+];
+
+fn invalid_data<E>(error: E) -> io::Error where E: Into<Box<::std::error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl Connection {
+
+    /// read the start of the connection and set up the HTTP/2 connection
+    ///
+    /// The preface is what decides how the connection goes. Without it the
+    /// connection is only accepted if allow_upgrade is set (which it should
+    /// only be for cleartext connections) and it starts with an h2c upgrade.
+    ///
+    /// The returned reader holds whatever was read past the preface,
+    /// frames are read with it from then on.
+    pub fn handshake<S: Read + Write>(stream: &mut S, allow_upgrade: bool) -> io::Result<(Connection, FrameReader)> {
+        Connection::handshake_within(stream, allow_upgrade, Settings::local_default(), None, SystemClock::shared())
+    }
+
+    /// handshake for a connection advertising local_settings, failing
+    /// with TimedOut if the preface is not all in by the deadline
+    ///
+    /// The stream needs a read timeout of its own for this to work,
+    /// a read that never returns can not be given up on.
+    pub fn handshake_within<S: Read + Write>(stream: &mut S, allow_upgrade: bool, local_settings: Settings, deadline: Option<Instant>,
+                                             clock: Arc<Clock>) -> io::Result<(Connection, FrameReader)> {
+
+        let mut reader = FrameReader::new();
+        reader.set_clock(clock.clone());
+        reader.set_deadline(deadline);
+        let res = Connection::read_preface(stream, allow_upgrade, local_settings, &mut reader);
+        reader.set_deadline(None);
+        res.map(|mut conn| {
+            conn.set_clock(clock);
+            (conn, reader)
+        })
+    }
+
+    fn read_preface<S: Read + Write>(stream: &mut S, allow_upgrade: bool, local_settings: Settings, reader: &mut FrameReader)
+        -> io::Result<Connection> {
+
+        // read as much of the preface as it takes to tell it apart from an HTTP/1.1 request
+        loop {
+            let n = ::std::cmp::min(reader.buffered().len(), PREFACE.len());
+            if reader.buffered()[..n] != PREFACE[..n] || n == PREFACE.len() {
+                break;
+            }
+            if reader.fill(stream)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended before the preface"));
+            }
+        }
+
+        if reader.buffered().starts_with(PREFACE) {
+            reader.consume(PREFACE.len());
+            return Ok(Connection::with_settings(local_settings));
+        }
+        if !allow_upgrade {
+            return Err(invalid_data("invalid connection preface"));
+        }
+
+        let request = read_request_head(stream, reader)?;
+        let conn = Connection::upgrade(&request, local_settings).map_err(invalid_data)?;
+        stream.write_all(SWITCHING_PROTOCOLS)?;
+
+        if !reader.fill_to(stream, PREFACE.len())? || !reader.buffered().starts_with(PREFACE) {
+            return Err(invalid_data("expected the preface after upgrading"));
+        }
+        reader.consume(PREFACE.len());
+
+        Ok(conn)
+    }
+
+    /// 3.2 Starting HTTP/2 for "http" URIs
+    ///
+    /// Accept an HTTP/1.1 request that asks to upgrade to h2c. The settings
+    /// in HTTP2-Settings are applied as if they came in a SETTINGS frame
+    /// (without an ACK, the 101 response acknowledges them) and the request
+    /// is stream 1, which is half-closed (remote) as the client is done with it.
+    /// The connection advertises local_settings.
+    pub fn upgrade(request: &RequestHead, local_settings: Settings) -> Result<Connection, H2Error> {
+        use super::error::ErrorCode::ProtocolError;
+
+        if !request.has_token("upgrade", "h2c") || !request.has_token("connection", "upgrade") {
+            return Err(H2Error::connection(ProtocolError, "HTTP/1.1 request is not an h2c upgrade"));
+        }
+        // the server can not read a request body before switching
+        // protocols so requests with one are not upgraded
+        if request.header("content-length").map_or(false, |l| l.trim() != "0") || request.header("transfer-encoding").is_some() {
+            return Err(H2Error::connection(ProtocolError, "h2c upgrade with a request body"));
+        }
+        let settings = match (request.header("http2-settings"), request.has_token("connection", "http2-settings")) {
+            (Some(settings), true) => base64::decode_url(settings).map_err(|e| H2Error::connection(ProtocolError, e.to_string()))?,
+            _ => return Err(H2Error::connection(ProtocolError, "h2c upgrade without HTTP2-Settings")),
+        };
+
+        let mut conn = Connection::with_settings(local_settings);
+
+        let mut frame = OwnedFrame::new(::frame::frame_types::types::SETTINGS, 0, 0, &settings);
+        let effects = conn.remote_settings.apply_remote(&frame.as_frame().into())?;
+        for effect in effects {
+            if let SettingsEffect::InitialWindowSize { old, new } = effect {
+                conn.adjust_stream_windows(old, new)?;
+            }
+        }
+
+        let mut stream = Stream::new(1, conn.remote_settings.initial_window_size);
+        stream.set_state(StreamState::HalfClosedRemote);
+        conn.streams.insert(1, stream);
+        conn.priority.insert(1, 0, DEFAULT_WEIGHT, false)?;
+        conn.highest_seen_client_stream = 1;
+
+        conn.events.push_back(Event::Headers { stream_id: 1, headers: upgraded_headers(request), end_stream: true });
+        Ok(conn)
+    }
+}
+
+// read until the whole request head is buffered, and parse it
+fn read_request_head<S: Read>(stream: &mut S, reader: &mut FrameReader) -> io::Result<RequestHead> {
+    loop {
+        if let Some(len) = h1::head_len(reader.buffered()) {
+            let request = h1::parse_request(&reader.buffered()[..len]).map_err(invalid_data)?;
+            reader.consume(len);
+            return Ok(request);
+        }
+        if reader.buffered().len() > h1::MAX_HEAD_SIZE {
+            return Err(invalid_data("HTTP/1.1 request head is too large"));
+        }
+        if reader.fill(stream)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in the HTTP/1.1 request"));
+        }
+    }
+}
+
+// 8.1.2.3 turn the HTTP/1.1 request into the HTTP/2 pseudo-header
+// fields followed by the other fields with lowercase names
+fn upgraded_headers(request: &RequestHead) -> HeaderList {
+    let mut headers = HeaderList::with_capacity(request.headers.len() + 4);
+    headers.add_entry((":method", request.method.clone()).into());
+    headers.add_entry((":scheme", "http").into());
+    if let Some(host) = request.header("host") {
+        headers.add_entry((":authority", host.to_string()).into());
+    }
+    headers.add_entry((":path", request.target.clone()).into());
+
+    for &(ref name, ref value) in &request.headers {
+        let name = intern(name);
+        if !CONNECTION_SPECIFIC.contains(&&*name) {
+            headers.add_entry((name, value.clone()).into());
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod handshake_tests {
+
+    use std::io::{self, Cursor};
+
+    use super::{PREFACE, SWITCHING_PROTOCOLS};
+    use connection::Connection;
+    use connection::event::Event;
+    use connection::mock::MockStream;
+    use connection::reader::FrameReader;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags};
+    use header::{Decoder, HeaderList};
+
+    static UPGRADE : &'static [u8] = b"GET /index.html HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\
+        Accept: text/html\r\n\
+        \r\n";
+
+    #[test]
+    fn prior_knowledge_preface() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        let mut stream = MockStream::new(input);
+
+        let (_, mut reader) = Connection::handshake(&mut stream, true).unwrap();
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::SETTINGS);
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn h2c_upgrade() {
+        let mut input = UPGRADE.to_vec();
+        input.extend_from_slice(PREFACE);
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        let mut stream = MockStream::new(input);
+
+        let (mut conn, mut reader) = Connection::handshake(&mut stream, true).unwrap();
+        assert_eq!(&stream.output[..], SWITCHING_PROTOCOLS);
+        stream.output.clear();
+
+        // the client's SETTINGS is next after the preface
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::SETTINGS);
+
+        // the HTTP/1.1 request is stream 1
+        match conn.poll_event() {
+            Some(Event::Headers { stream_id: 1, headers, end_stream: true }) => {
+                assert_eq!(headers.get_value_by_name(":method"), Some("GET"));
+                assert_eq!(headers.get_value_by_name(":path"), Some("/index.html"));
+                assert_eq!(headers.get_value_by_name(":authority"), Some("example.com"));
+                assert_eq!(headers.get_value_by_name("accept"), Some("text/html"));
+                assert_eq!(headers.get_value_by_name("upgrade"), None);
+            },
+            _ => panic!("expected the upgraded request"),
+        }
+        assert_eq!(conn.stream(1).unwrap().send_window(), 1 << 30);
+
+        let mut response = HeaderList::with_capacity(1);
+        response.add_entry((":status", "200").into());
+        conn.send_headers(1, &response, true).unwrap();
+        conn.write_outbound(&mut stream).unwrap();
+
+        // server preface, then the response on stream 1
+        let mut out = FrameReader::new();
+        let mut output = Cursor::new(stream.output);
+        assert_eq!(out.read_frame(&mut output).unwrap().unwrap().get_type(), types::SETTINGS);
+        let headers = out.read_frame(&mut output).unwrap().unwrap();
+        assert_eq!(headers.get_type(), types::HEADERS);
+        assert_eq!(headers.get_stream_id(), 1);
+        assert_eq!(headers.get_flags(), flags::END_HEADERS | flags::END_STREAM);
+        let status = Decoder::new(4096, 10).get_header_list(headers.payload()).unwrap();
+        assert_eq!(status.get_value_by_name(":status"), Some("200"));
+    }
+
+    #[test]
+    fn not_an_upgrade() {
+        let mut stream = MockStream::new(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec());
+        let err = Connection::handshake(&mut stream, true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn upgrade_not_allowed() {
+        let mut input = UPGRADE.to_vec();
+        input.extend_from_slice(PREFACE);
+        let mut stream = MockStream::new(input);
+        let err = Connection::handshake(&mut stream, false).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(stream.output.is_empty());
+    }
+
+    #[test]
+    fn upgrade_without_preface() {
+        let mut input = UPGRADE.to_vec();
+        input.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        let mut stream = MockStream::new(input);
+        assert!(Connection::handshake(&mut stream, true).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Limits on what the peer can make the connection hold on to
+//!
+//! These are not part of the protocol and are not advertised, going
+//! over one is treated as abuse and ends the connection with
+//! ENHANCE_YOUR_CALM. The exceptions are the size of a request's headers,
+//! which only gets that request a 431 response, and the number of streams
+//! on one connection, which gets a GOAWAY so the client opens another.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::settings::Settings;
+
+/// how many CONTINUATION frames may follow a HEADERS frame
+pub const DEFAULT_MAX_CONTINUATIONS : usize = 16;
+
+/// how long a header block can take to come in, from its HEADERS
+/// frame to the CONTINUATION with END_HEADERS
+pub const DEFAULT_HEADER_BLOCK_TIMEOUT : u64 = 10;
+
+/// the header list size the block size limit is based on when
+/// no SETTINGS_MAX_HEADER_LIST_SIZE is advertised
+pub const DEFAULT_HEADER_LIST_SIZE : usize = 0x10000;
+
+/// the largest header list (as HeaderList::size counts it) a request can
+/// have before it is answered with 431 Request Header Fields Too Large
+pub const DEFAULT_MAX_REQUEST_HEADERS : usize = 16384;
+
+/// how many streams the peer can reset within DEFAULT_RESET_WINDOW
+pub const DEFAULT_MAX_RESETS : usize = 100;
+
+/// how far back resets are counted, in seconds
+pub const DEFAULT_RESET_WINDOW : u64 = 10;
+
+/// the share of its streams (in percent) the peer can reset
+pub const DEFAULT_MAX_RESET_PERCENT : u64 = 50;
+
+/// how many resets there have to be before their share is looked at,
+/// so a few cancelled requests early on are not held against the peer
+pub const DEFAULT_MIN_RESETS : u64 = 100;
+
+/// how many streams the peer can open on one connection
+pub const DEFAULT_MAX_STREAMS : u64 = 100000;
+
+// how much bigger than the header list size a header block can be,
+// HPACK makes the block smaller than the list in all but odd cases
+const BLOCK_SIZE_FACTOR : usize = 2;
+
+/// Limits on a header block that is put together from CONTINUATION frames
+/// (against the "CONTINUATION flood", where a peer never ends a header
+/// block, and against it taking its time to end one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderBlockLimits {
+    /// the most bytes of a header block that are buffered before decoding it
+    pub max_block_size: usize,
+    /// the most CONTINUATION frames in a single header block
+    pub max_continuations: usize,
+    /// how long until the header block has to be done
+    pub timeout: Duration,
+}
+
+impl HeaderBlockLimits {
+
+    /// the default limits for a connection advertising local_settings
+    pub fn for_settings(local_settings: &Settings) -> Self {
+        let list_size = local_settings.max_header_list_size.map_or(DEFAULT_HEADER_LIST_SIZE, |max| max as usize);
+        HeaderBlockLimits {
+            max_block_size: list_size.saturating_mul(BLOCK_SIZE_FACTOR),
+            max_continuations: DEFAULT_MAX_CONTINUATIONS,
+            timeout: Duration::from_secs(DEFAULT_HEADER_BLOCK_TIMEOUT),
+        }
+    }
+}
+
+impl Default for HeaderBlockLimits {
+    fn default() -> Self {
+        HeaderBlockLimits::for_settings(&Settings::local_default())
+    }
+}
+
+/// Limits on the streams the peer opens, against the "rapid reset"
+/// attack where streams are opened and cancelled right away, which
+/// costs the server a handler each and the peer nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamLimits {
+    /// the most streams the peer can reset within reset_window
+    pub max_resets: usize,
+    pub reset_window: Duration,
+    /// the most of the streams it opened (in percent) the peer can reset,
+    /// once it has reset min_resets
+    pub max_reset_percent: u64,
+    pub min_resets: u64,
+    /// the most streams the peer can open on the connection, after that
+    /// it is sent a GOAWAY (None for as many as there are stream ids)
+    pub max_streams: Option<u64>,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        StreamLimits {
+            max_resets: DEFAULT_MAX_RESETS,
+            reset_window: Duration::from_secs(DEFAULT_RESET_WINDOW),
+            max_reset_percent: DEFAULT_MAX_RESET_PERCENT,
+            min_resets: DEFAULT_MIN_RESETS,
+            max_streams: Some(DEFAULT_MAX_STREAMS),
+        }
+    }
+}
+
+/// The streams the peer has opened and reset, to hold against StreamLimits
+#[derive(Debug, Default)]
+pub struct StreamCount {
+    opened: u64,
+    resets: u64,
+    // when the resets within the window happened, oldest first
+    recent_resets: VecDeque<Instant>,
+}
+
+impl StreamCount {
+
+    pub fn new() -> Self {
+        StreamCount::default()
+    }
+
+    /// count a stream the peer opened, returning how many it has
+    pub fn opened(&mut self) -> u64 {
+        self.opened += 1;
+        self.opened
+    }
+
+    /// count a stream the peer reset at now, false when that takes it
+    /// over the limits
+    pub fn reset(&mut self, now: Instant, limits: &StreamLimits) -> bool {
+        self.resets += 1;
+        while self.recent_resets.front().map_or(false, |&at| now.duration_since(at) >= limits.reset_window) {
+            self.recent_resets.pop_front();
+        }
+        // the window never holds more than is needed to go over
+        if self.recent_resets.len() >= limits.max_resets {
+            return false;
+        }
+        self.recent_resets.push_back(now);
+
+        self.resets < limits.min_resets || self.resets * 100 <= self.opened * limits.max_reset_percent
+    }
+
+    pub fn total_opened(&self) -> u64 {
+        self.opened
+    }
+
+    pub fn total_resets(&self) -> u64 {
+        self.resets
+    }
+}
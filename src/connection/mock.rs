@@ -0,0 +1,127 @@
+//! A stand in for the socket in tests
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use util::MockClock;
+
+/// Reads from a fixed input and collects everything written
+#[derive(Debug)]
+pub struct MockStream {
+    input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+    // a peer that has gone quiet instead of closing the stream,
+    // with the clock that moves on while it is
+    stall: Option<Arc<MockClock>>,
+}
+
+impl MockStream {
+    pub fn new(input: Vec<u8>) -> Self {
+        MockStream { input: Cursor::new(input), output: Vec::new(), stall: None }
+    }
+
+    /// once the input is used up reads move the clock on a moment and
+    /// fail with WouldBlock, like a socket with a short read timeout
+    pub fn stalling(input: Vec<u8>, clock: Arc<MockClock>) -> Self {
+        MockStream { stall: Some(clock), .. MockStream::new(input) }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.input.read(buf)? {
+            0 if self.stall.is_some() && !buf.is_empty() => {
+                self.stall.as_ref().unwrap().advance(Duration::from_millis(1));
+                Err(io::Error::new(ErrorKind::WouldBlock, "read timed out"))
+            },
+            n => Ok(n),
+        }
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A MockStream whose reads go as scripted, Ok(n) reads at most n bytes
+/// and Err(kind) fails with that kind, then reads go as usual
+#[derive(Debug)]
+pub struct FlakyStream {
+    stream: MockStream,
+    script: VecDeque<Result<usize, ErrorKind>>,
+}
+
+impl FlakyStream {
+    pub fn new(input: Vec<u8>, script: Vec<Result<usize, ErrorKind>>) -> Self {
+        FlakyStream { stream: MockStream::new(input), script: script.into_iter().collect() }
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.stream.output
+    }
+}
+
+impl Read for FlakyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.script.pop_front() {
+            Some(Ok(n)) => {
+                let n = ::std::cmp::min(n, buf.len());
+                self.stream.read(&mut buf[..n])
+            },
+            Some(Err(kind)) => Err(io::Error::new(kind, "scripted")),
+            None => self.stream.read(buf),
+        }
+    }
+}
+
+impl Write for FlakyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A MockStream that can be given away (to what needs a 'static
+/// stream) and still have its output looked at through a clone
+#[derive(Debug, Clone)]
+pub struct SharedStream(Rc<RefCell<MockStream>>);
+
+impl SharedStream {
+    pub fn new(input: Vec<u8>) -> Self {
+        SharedStream(Rc::new(RefCell::new(MockStream::new(input))))
+    }
+
+    pub fn stalling(input: Vec<u8>, clock: Arc<MockClock>) -> Self {
+        SharedStream(Rc::new(RefCell::new(MockStream::stalling(input, clock))))
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.0.borrow().output.clone()
+    }
+}
+
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
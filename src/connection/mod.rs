@@ -0,0 +1,3530 @@
+//! The state of a single HTTP2 connection.
+//!
+//! Frames received from the peer are passed to dispatch_frame which
+//! updates the connection and stream state. Frames that need to be sent
+//! back are queued up and taken with next_outbound, and anything the
+//! application needs to act on is taken with poll_event.
+//!
+//! The connection does no I/O itself so that it can be driven by whatever
+//! owns the socket (and tested without one).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use buf::{Buf, MappedSlice, Pool, PooledBuf};
+use frame::{FrameHeader, Http2Frame};
+use frame::{OwnedFrame, Padder, PaddingCalc, PaddingPolicy};
+use frame::frame_types::*;
+use header::{Decoder, Encoder, HeaderList};
+use krserr::ErrLink;
+use log::Context;
+use server::{AccessLog, LogRecord};
+use util::{Clock, DateCache, HexDump, SystemClock};
+
+pub mod budget;
+pub mod core;
+pub mod error;
+pub mod event;
+pub mod handshake;
+pub mod limits;
+pub mod observer;
+pub mod priority;
+pub mod reader;
+pub mod run;
+pub mod settings;
+pub mod stream;
+pub mod trace;
+pub mod transfer;
+pub mod window;
+pub mod writer;
+
+#[cfg(test)]
+pub mod mock;
+
+use self::budget::MemoryBudget;
+use self::error::{ErrorCode, ExtensionError, H2Error, PushError};
+use self::event::{Event, PingToken};
+use self::limits::{HeaderBlockLimits, StreamCount, StreamLimits, DEFAULT_MAX_REQUEST_HEADERS};
+use self::observer::{CloseInfo, ConnectionObserver, FrameSummary, Observed};
+use self::priority::{PriorityTree, DEFAULT_WEIGHT};
+use self::settings::{Settings, SettingsEffect, DEFAULT_MAX_CONCURRENT_STREAMS, DEFAULT_SETTINGS_TIMEOUT, MAX_WINDOW_SIZE};
+use self::stream::{content_length, RequestInfo, Stream, StreamState};
+use self::trace::{Direction, Tracer, TracingFrameWriter};
+use self::transfer::TransferCount;
+use self::window::{WindowInfo, WindowUpdates, DEFAULT_STALL_WARNING};
+use self::writer::{FrameWriter, WriteFrames};
+
+// a frame waiting to be written, DATA keeps its payload apart from
+// the header so it can be written without copying them together
+enum Outbound {
+    Frame(OwnedFrame),
+    Data([u8; 9], BodyChunk),
+}
+
+// the payload of a DATA frame, copied into a pooled buffer or a slice of
+// a mapped file (which keeps the mapping until the frame is written)
+enum BodyChunk {
+    Pooled(PooledBuf),
+    Mapped(MappedSlice),
+}
+
+impl Deref for BodyChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            BodyChunk::Pooled(ref buf) => buf,
+            BodyChunk::Mapped(ref slice) => slice,
+        }
+    }
+}
+
+impl Outbound {
+    fn len(&self) -> usize {
+        match *self {
+            Outbound::Frame(ref frame) => frame.as_bytes().len(),
+            Outbound::Data(ref header, ref payload) => header.len() + payload.len(),
+        }
+    }
+
+    fn stream_id(&self) -> u32 {
+        match *self {
+            Outbound::Frame(ref frame) => frame.stream_id(),
+            Outbound::Data(ref header, _) => FrameHeader::stream_id(header),
+        }
+    }
+}
+
+// the frames waiting to be written, charged to the connection's budget
+// while they wait
+//
+// Frames for the connection as a whole (stream 0) go in the control lane,
+// which is written before anything else, so a GOAWAY or a SETTINGS ACK is
+// not held up behind a queue full of DATA. The frames of streams keep the
+// order they were queued in, which HPACK and the stream states depend on.
+struct OutboundQueue {
+    control: VecDeque<Outbound>,
+    data: VecDeque<Outbound>,
+    budget: MemoryBudget,
+}
+
+impl OutboundQueue {
+    fn new(budget: MemoryBudget) -> Self {
+        OutboundQueue { control: VecDeque::new(), data: VecDeque::new(), budget: budget }
+    }
+
+    fn push_back(&mut self, outbound: Outbound) {
+        self.budget.charge(outbound.len());
+        if outbound.stream_id() == 0 {
+            self.control.push_back(outbound);
+        }
+        else {
+            self.data.push_back(outbound);
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<Outbound> {
+        let outbound = match self.control.pop_front() {
+            Some(outbound) => Some(outbound),
+            None => self.data.pop_front(),
+        };
+        if let Some(ref outbound) = outbound {
+            self.budget.credit(outbound.len());
+        }
+        outbound
+    }
+
+    // drop the frames of the streams above last_stream_id that drop
+    // picks, returning those streams (lowest first)
+    fn drop_streams_above<F>(&mut self, last_stream_id: u32, drop: F) -> Vec<u32> where F: Fn(u32) -> bool {
+        let mut dropped = Vec::new();
+        let mut freed = 0;
+        self.data.retain(|outbound| {
+            let id = outbound.stream_id();
+            if id <= last_stream_id || !drop(id) {
+                return true;
+            }
+            freed += outbound.len();
+            if !dropped.contains(&id) {
+                dropped.push(id);
+            }
+            false
+        });
+        self.budget.credit(freed);
+        dropped.sort();
+        dropped
+    }
+
+    // how many bytes are queued
+    fn bytes(&self) -> usize {
+        self.control.iter().chain(self.data.iter()).map(|o| o.len()).sum()
+    }
+}
+
+impl Drop for OutboundQueue {
+    fn drop(&mut self) {
+        self.budget.credit(self.bytes());
+    }
+}
+
+pub struct Connection {
+    streams: HashMap<u32, Stream>,
+    // decides which stream sends next when several have data
+    priority: PriorityTree,
+    // settings we advertised to the peer (and it acknowledged)
+    local_settings: Settings,
+    // 6.5.3 SETTINGS we sent that have not been acknowledged yet, in the
+    // order they were sent, with when they were written (None until then)
+    pending_settings: VecDeque<(Settings, Option<Instant>)>,
+    settings_timeout: Duration,
+    // settings the peer advertised to us
+    remote_settings: Settings,
+    // connection level flow control window for sending
+    send_window: i32,
+    // connection level flow control window for receiving, data
+    // the peer sent takes from it until it is released
+    recv_window: i32,
+    // what was released of it and not advertised yet
+    unadvertised: u32,
+    // when released window is advertised, and the streams that have
+    // some that is not (all of it is held back while the budget is
+    // under pressure)
+    window_updates: WindowUpdates,
+    window_pending: HashSet<u32>,
+    // how long a stream can be held up by flow control before it is
+    // warned about, and when the windows were last traced
+    stall_warning: Option<Duration>,
+    last_snapshot: Option<Instant>,
+    decoder: Decoder,
+    encoder: Encoder,
+    outbound: OutboundQueue,
+    events: VecDeque<Event>,
+    // what the connection holds is charged to, and
+    budget: MemoryBudget,
+    // and the DATA of each stream that has not been released yet
+    buffered: HashMap<u32, usize>,
+    // PINGs we sent that have not been acknowledged yet
+    pings: HashMap<u64, Instant>,
+    next_ping: u64,
+    // where the current time comes from
+    clock: Arc<Clock>,
+    // highest stream id opened by the peer
+    // (new streams must always have a higher id)
+    highest_seen_client_stream: u32,
+    // id for the next stream we reserve for a push
+    next_push_id: u32,
+    // last stream id in the GOAWAY we sent
+    sent_go_away: Option<u32>,
+    // last stream id in the GOAWAY the peer sent
+    recv_go_away: Option<u32>,
+    // streams that closed recently (oldest first), frames that
+    // were already in flight for them are ignored
+    closed_streams: VecDeque<(u32, Option<ErrorCode>)>,
+    // stream of the header block being received, every frame until
+    // its END_HEADERS must be a CONTINUATION on that stream
+    expecting_continuation: Option<u32>,
+    // the start of that header block
+    partial_headers: PartialHeaders,
+    header_block_limits: HeaderBlockLimits,
+    max_request_headers: usize,
+    stream_limits: StreamLimits,
+    // the streams the peer opened and reset, for stream_limits
+    stream_count: StreamCount,
+    // the date header of responses
+    date: DateCache,
+    // the server header of responses, if any
+    server: Option<String>,
+    // where a record of each request goes when its stream is done
+    access_log: Option<Arc<AccessLog>>,
+    peer_addr: Option<SocketAddr>,
+    // what traces the frames write_outbound writes, if they are traced
+    tracer: Option<Tracer>,
+    // what is told about streams, GOAWAYs and (maybe) frames
+    observer: Observed,
+    // unknown frame types become events instead of being ignored
+    deliver_extension_frames: bool,
+    // how much the header blocks sent are padded
+    padder: Padder,
+}
+
+/// The server header responses get unless it is changed with set_server
+pub const SERVER : &'static str = "kurisu";
+
+// what is kept from a HEADERS frame without END_HEADERS
+// until the rest of the header block comes in
+#[derive(Default)]
+struct PartialHeaders {
+    end_stream: bool,
+    priority_data: Option<(bool, u32, u8)>,
+    block: Vec<u8>,
+    continuations: usize,
+    // when the HEADERS frame came in
+    started: Option<Instant>,
+}
+
+/// the largest stream identifier (2^31-1)
+pub const MAX_STREAM_ID : u32 = 0x7FFFFFFF;
+
+// how many closed stream ids are remembered
+const CLOSED_STREAMS_KEPT : usize = 64;
+
+// how many streams that are not open the priority tree keeps, for each
+// stream the peer can have open at once
+const IDLE_PRIORITIES_PER_STREAM : usize = 4;
+
+// how much of a frame that failed the connection is logged
+const MAX_ERROR_DUMP : usize = 128;
+
+impl Connection {
+
+    /// create the connection with the default local settings
+    pub fn new() -> Self {
+        Connection::with_settings(Settings::local_default())
+    }
+
+    /// create the connection and queue the server connection preface
+    /// (a SETTINGS frame advertising local_settings)
+    pub fn with_settings(local_settings: Settings) -> Self {
+        let budget = MemoryBudget::default();
+        let mut outbound = OutboundQueue::new(budget.clone());
+        outbound.push_back(Outbound::Frame(OwnedFrame::settings(&local_settings.params())));
+        let mut pending_settings = VecDeque::new();
+        pending_settings.push_back((local_settings.clone(), None));
+        let header_block_limits = HeaderBlockLimits::for_settings(&local_settings);
+        let max_request_headers = local_settings.max_header_list_size.map_or(DEFAULT_MAX_REQUEST_HEADERS, |max| max as usize);
+        let mut encoder = Encoder::new(Settings::default().header_table_size as usize, 20);
+        encoder.prime(&[("server", SERVER)]);
+
+        Connection {
+            streams: HashMap::new(),
+            priority: PriorityTree::new(),
+            decoder: Decoder::new(local_settings.header_table_size as usize, 20),
+            encoder: encoder,
+            local_settings: local_settings,
+            pending_settings: pending_settings,
+            settings_timeout: Duration::from_secs(DEFAULT_SETTINGS_TIMEOUT),
+            remote_settings: Settings::default(),
+            send_window: Settings::default().initial_window_size as i32,
+            recv_window: Settings::default().initial_window_size as i32,
+            unadvertised: 0,
+            window_updates: WindowUpdates::default(),
+            window_pending: HashSet::new(),
+            stall_warning: Some(Duration::from_secs(DEFAULT_STALL_WARNING)),
+            last_snapshot: None,
+            outbound: outbound,
+            events: VecDeque::new(),
+            budget: budget,
+            buffered: HashMap::new(),
+            pings: HashMap::new(),
+            next_ping: 0,
+            clock: SystemClock::shared(),
+            highest_seen_client_stream: 0,
+            next_push_id: 2,
+            sent_go_away: None,
+            recv_go_away: None,
+            closed_streams: VecDeque::new(),
+            expecting_continuation: None,
+            partial_headers: PartialHeaders::default(),
+            header_block_limits: header_block_limits,
+            max_request_headers: max_request_headers,
+            stream_limits: StreamLimits::default(),
+            stream_count: StreamCount::new(),
+            date: DateCache::new(),
+            server: Some(SERVER.to_string()),
+            access_log: None,
+            peer_addr: None,
+            tracer: None,
+            observer: Observed::new(None),
+            deliver_extension_frames: false,
+            padder: Padder::default(),
+        }
+    }
+
+    /// replace where the connection gets the current time from
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &Arc<Clock> {
+        &self.clock
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// the date header for a response sent now
+    pub fn date(&mut self) -> &str {
+        self.date.at(self.clock.system_now())
+    }
+
+    /// the server header for responses, None to not send one
+    pub fn set_server(&mut self, server: Option<String>) {
+        // it goes with every response, the old one no longer does
+        self.encoder.unprime("server");
+        if let Some(ref server) = server {
+            self.encoder.prime(&[("server", server)]);
+        }
+        self.server = server;
+    }
+
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_ref().map(|s| s.as_str())
+    }
+
+    /// the largest request header list (as HeaderList::size counts it)
+    /// that goes to the application, bigger ones are answered with 431
+    ///
+    /// This is the SETTINGS_MAX_HEADER_LIST_SIZE advertised to the peer
+    /// unless it is set to something else.
+    pub fn set_max_request_headers(&mut self, max: usize) {
+        self.max_request_headers = max;
+    }
+
+    /// log every request once its stream is closed (or the connection is)
+    pub fn set_access_log(&mut self, access_log: Option<Arc<AccessLog>>) {
+        self.access_log = access_log;
+    }
+
+    /// trace every frame written by write_outbound
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// tell observer about the streams and GOAWAYs of the connection (and
+    /// its frames, if it wants them)
+    pub fn set_observer(&mut self, observer: Option<Arc<ConnectionObserver>>) {
+        self.observer = Observed::new(observer);
+    }
+
+    /// give frames of unknown types to the application as
+    /// Event::ExtensionFrame rather than ignoring them
+    pub fn set_deliver_extension_frames(&mut self, deliver: bool) {
+        self.deliver_extension_frames = deliver;
+    }
+
+    /// pad the HEADERS and PUSH_PROMISE frames that are sent as policy
+    /// says, which hides how long the header blocks are
+    ///
+    /// DATA is not padded, the length of a body is out in the open
+    /// (in its content-length) more often than not anyway.
+    pub fn set_padding(&mut self, policy: PaddingPolicy) {
+        self.padder = Padder::new(policy);
+    }
+
+    /// the address of the peer, for the access log
+    pub fn set_peer_addr(&mut self, peer_addr: Option<SocketAddr>) {
+        self.peer_addr = peer_addr;
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// replace the limits on header blocks received from the peer
+    pub fn set_header_block_limits(&mut self, limits: HeaderBlockLimits) {
+        self.header_block_limits = limits;
+    }
+
+    pub fn header_block_limits(&self) -> &HeaderBlockLimits {
+        &self.header_block_limits
+    }
+
+    /// charge what the connection holds to budget instead, which can be
+    /// shared with its FrameReader so the two are held to one ceiling
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        let held = self.held();
+        self.budget.credit(held);
+        budget.charge(held);
+        self.outbound.budget = budget.clone();
+        self.budget = budget;
+    }
+
+    pub fn memory_budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    /// replace the limits on the streams the peer opens and resets
+    pub fn set_stream_limits(&mut self, limits: StreamLimits) {
+        self.stream_limits = limits;
+    }
+
+    pub fn stream_limits(&self) -> &StreamLimits {
+        &self.stream_limits
+    }
+
+    /// the streams the peer has opened and reset so far
+    pub fn stream_count(&self) -> &StreamCount {
+        &self.stream_count
+    }
+
+    /// when the header block being received has to be done by,
+    /// None when there is none
+    pub fn header_block_deadline(&self) -> Option<Instant> {
+        self.expecting_continuation.and(self.partial_headers.started)
+            .map(|started| started + self.header_block_limits.timeout)
+    }
+
+    /// warn about a stream that has data to send and no window to send
+    /// it in for longer than after, None to never warn
+    pub fn set_stall_warning(&mut self, after: Option<Duration>) {
+        self.stall_warning = after;
+    }
+
+    /// the flow control windows of a stream that is open
+    pub fn windows(&self, stream_id: u32) -> Option<WindowInfo> {
+        self.streams.get(&stream_id).map(|s| s.windows(self.send_window, self.recv_window))
+    }
+
+    /// when the first stream that is held up by flow control (and not
+    /// warned about yet) is due a warning
+    pub fn stall_deadline(&self) -> Option<Instant> {
+        let after = self.stall_warning?;
+        self.streams.values()
+            .filter(|s| !s.stall_warned())
+            .filter_map(|s| s.send_blocked_since())
+            .min()
+            .map(|since| since + after)
+    }
+
+    // note which streams are held up by flow control and warn about those
+    // that have been for too long, naming the window that holds them up
+    fn check_stalls(&mut self) {
+        // the clock is only read when there is something to note
+        if !self.streams.values().any(|s| s.wants_window() || s.send_blocked_since().is_some()) {
+            return;
+        }
+        let now = self.now();
+        let (send_conn, recv_conn) = (self.send_window, self.recv_window);
+        for stream in self.streams.values_mut() {
+            let windows = stream.windows(send_conn, recv_conn);
+            let limit = windows.send_limit().filter(|_| stream.wants_window());
+            stream.set_send_blocked(limit.map(|_| now));
+
+            let since = match (stream.send_blocked_since(), self.stall_warning) {
+                (Some(since), Some(after)) if now >= since + after => since,
+                _ => continue,
+            };
+            if let Some(limit) = limit {
+                if stream.take_stall_warning() {
+                    klog_warn!(Context::peer(self.peer_addr).stream(stream.id()) =>
+                               "nothing sent for {:?} with data queued, held up by the {} ({})",
+                               now - since, limit, windows);
+                }
+            }
+        }
+    }
+
+    // trace the windows of every open stream, if the tracer wants
+    // them and it has been long enough since the last time
+    fn snapshot_windows(&mut self, tracer: &Tracer) {
+        let every = match tracer.snapshot_every() {
+            Some(every) => every,
+            None => return,
+        };
+        let now = self.now();
+        if self.last_snapshot.map_or(false, |last| now < last + every) {
+            return;
+        }
+        self.last_snapshot = Some(now);
+        let mut ids: Vec<u32> = self.streams.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            tracer.windows(id, &self.streams[&id].windows(self.send_window, self.recv_window));
+        }
+    }
+
+    /// advertise new settings, they are used once the peer acknowledges
+    /// them (which it has settings_timeout to do)
+    pub fn send_settings(&mut self, settings: Settings) {
+        self.outbound.push_back(Outbound::Frame(OwnedFrame::settings(&settings.params())));
+        self.pending_settings.push_back((settings, None));
+    }
+
+    /// how long the peer has to acknowledge SETTINGS once they are written
+    pub fn set_settings_timeout(&mut self, timeout: Duration) {
+        self.settings_timeout = timeout;
+    }
+
+    /// when the oldest SETTINGS that were written have to be acknowledged
+    /// by, None when there are none waiting
+    pub fn settings_ack_deadline(&self) -> Option<Instant> {
+        self.pending_settings.front()
+            .and_then(|&(_, written)| written)
+            .map(|written| written + self.settings_timeout)
+    }
+
+    /// an error for the connection if the peer is taking too long with
+    /// something it started (or to acknowledge our SETTINGS)
+    ///
+    /// This is checked as frames come in, and should also be called
+    /// from time to time while the peer is not sending anything.
+    pub fn check_timeouts(&mut self) -> Result<(), H2Error> {
+        if let Some(deadline) = self.settings_ack_deadline() {
+            if self.now() >= deadline {
+                return Err(H2Error::connection(ErrorCode::SettingsTimeout, "SETTINGS were not acknowledged"));
+            }
+        }
+        match self.header_block_deadline() {
+            Some(deadline) if self.now() >= deadline => {
+                self.clear_header_block();
+                Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "header block took too long"))
+            },
+            _ => Ok(()),
+        }?;
+        self.check_stalls();
+        Ok(())
+    }
+
+    /// take the next frame that should be written to the peer
+    pub fn next_outbound(&mut self) -> Option<OwnedFrame> {
+        self.send_window_updates();
+        let frame = self.outbound.pop_front().map(|outbound| match outbound {
+            Outbound::Frame(frame) => {
+                self.written(&frame);
+                frame
+            },
+            Outbound::Data(header, payload) => OwnedFrame::from_parts(&header, &payload),
+        });
+        if let Some(ref frame) = frame {
+            self.observe_frame(Direction::Sent, &frame.as_bytes()[..9]);
+        }
+        frame
+    }
+
+    /// write every queued frame to the peer
+    pub fn write_outbound<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        match self.tracer.clone() {
+            Some(tracer) => {
+                self.snapshot_windows(&tracer);
+                self.write_all_outbound(&mut TracingFrameWriter::new(FrameWriter::new(out), tracer))
+            },
+            None => self.write_all_outbound(&mut FrameWriter::new(out)),
+        }
+    }
+
+    fn write_all_outbound<F: WriteFrames>(&mut self, writer: &mut F) -> io::Result<()> {
+        // what else shares the budget (the reader) may have let go of some
+        self.send_window_updates();
+        while let Some(outbound) = self.outbound.pop_front() {
+            match outbound {
+                Outbound::Frame(frame) => {
+                    writer.write_frame(&frame)?;
+                    self.written(&frame);
+                    self.observe_frame(Direction::Sent, &frame.as_bytes()[..9]);
+                },
+                Outbound::Data(header, payload) => {
+                    writer.write_frame_parts(&header, &payload)?;
+                    self.observe_frame(Direction::Sent, &header);
+                },
+            }
+        }
+        writer.flush()
+    }
+
+    // tell an observer that wants frames about the one with header
+    fn observe_frame(&mut self, dir: Direction, header: &[u8]) {
+        if self.observer.frames() {
+            let summary = FrameSummary::from_header(header);
+            self.observer.call(self.peer_addr, |o| o.on_frame(dir, summary));
+        }
+    }
+
+    // the peer's time to acknowledge SETTINGS starts when they are written
+    fn written(&mut self, frame: &OwnedFrame) {
+        if frame.frame_type() == types::SETTINGS && frame.frame_flags() & flags::ACK == 0 {
+            let now = self.now();
+            if let Some(pending) = self.pending_settings.iter_mut().find(|&&mut (_, written)| written.is_none()) {
+                pending.1 = Some(now);
+            }
+        }
+    }
+
+    /// take the next thing the application needs to deal with
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    pub fn stream(&self, id: u32) -> Option<&Stream> {
+        self.streams.get(&id)
+    }
+
+    /// the count of what the response on a stream sends, which can be
+    /// kept to read after the stream is closed
+    pub fn transfer(&self, stream_id: u32) -> Option<TransferCount> {
+        self.streams.get(&stream_id).map(|s| s.transfer().clone())
+    }
+
+    pub fn send_window(&self) -> i32 {
+        self.send_window
+    }
+
+    /// the settings the peer advertised
+    pub fn remote_settings(&self) -> &Settings {
+        &self.remote_settings
+    }
+
+    /// the settings advertised to the peer
+    pub fn local_settings(&self) -> &Settings {
+        &self.local_settings
+    }
+
+    pub fn recv_window(&self) -> i32 {
+        self.recv_window
+    }
+
+    /// process a single frame received from the peer
+    pub fn dispatch_frame(&mut self, frame: GenericFrame) -> Result<(), H2Error> {
+        let buf = frame.into_buf();
+        self.observe_frame(Direction::Received, &buf[..9]);
+        // a peer that keeps sending but never acknowledges is caught here,
+        // one that sends nothing by the read loop calling check_timeouts
+        let res = self.dispatch(GenericFrame::point_to(&mut *buf)).and_then(|()| self.check_timeouts());
+        if let Err(ref e @ H2Error::Connection(..)) = res {
+            // what the peer sent that ended the connection, for working out why
+            klog_debug!(Context::peer(self.peer_addr) => "{}, the frame was:\n{:.*}", e, MAX_ERROR_DUMP, HexDump(buf));
+        }
+        res
+    }
+
+    /// whether the payload of the frame with this header can go unread
+    ///
+    /// A frame that is too big is an error whatever it holds, and one of
+    /// an extension type is dropped unless it is delivered, so the reader
+    /// can throw their payload away and hand over the header to
+    /// dispatch_skipped instead.
+    pub fn skips_payload(&self, header: &[u8]) -> bool {
+        let f_type = FrameHeader::frame_type(header);
+        FrameHeader::length(header) > self.local_settings.max_frame_size ||
+            (f_type > types::CONTINUATION && !self.deliver_extension_frames)
+    }
+
+    /// process a frame received from the peer whose payload was skipped,
+    /// as dispatch_frame would (skips_payload has to have said it can be)
+    pub fn dispatch_skipped(&mut self, header: &[u8]) -> Result<(), H2Error> {
+        debug_assert!(self.skips_payload(header));
+        let mut buf = [0u8; 9];
+        buf.copy_from_slice(&header[..9]);
+        self.observe_frame(Direction::Received, &buf);
+        // everything on the way to the error (or to dropping it) only
+        // looks at the header
+        let res = self.dispatch(GenericFrame::point_to(&mut buf)).and_then(|()| self.check_timeouts());
+        if let Err(ref e @ H2Error::Connection(..)) = res {
+            klog_debug!(Context::peer(self.peer_addr) => "{}, the frame header was:\n{}", e, HexDump(&buf));
+        }
+        res
+    }
+
+    fn dispatch(&mut self, frame: GenericFrame) -> Result<(), H2Error> {
+        self.validate_continuation(&frame)?;
+        self.validate_stream_id(&frame)?;
+        self.validate_frame_size(&frame)?;
+
+        // 8.2 A client cannot push. Thus, servers MUST treat the receipt of a
+        // PUSH_PROMISE frame as a connection error of type PROTOCOL_ERROR.
+        if frame.get_type() == types::PUSH_PROMISE {
+            return Err(H2Error::connection(ErrorCode::ProtocolError, "client sent PUSH_PROMISE"));
+        }
+
+        // 6.9 DATA counts against the connection window whatever stream it is
+        // on, what is dropped instead of being given to the application is
+        // released right away
+        let f_type = frame.get_type();
+        let data_len = match f_type {
+            types::DATA => frame.get_length() as usize,
+            _ => 0,
+        };
+        if data_len as i64 > self.recv_window as i64 {
+            return Err(H2Error::connection(ErrorCode::FlowControlError, "DATA over the connection window"));
+        }
+        self.recv_window -= data_len as i32;
+
+        // after sending GOAWAY frames on streams that would be new are ignored
+        // (header blocks still need to be decoded to keep the compression state in sync)
+        if self.is_ignored_stream(frame.get_stream_id()) && f_type != types::HEADERS && f_type != types::CONTINUATION {
+            self.release_connection_window(data_len);
+            return Ok(());
+        }
+
+        let stream_id = frame.get_stream_id();
+        match frame.get_type() {
+            f_type @ types::DATA | f_type @ types::RST_STREAM | f_type @ types::WINDOW_UPDATE
+                if stream_id != 0 && !self.streams.contains_key(&stream_id) => {
+                self.release_connection_window(data_len);
+                return self.recv_unknown_stream(f_type, stream_id);
+            },
+            _ => {},
+        }
+
+        let res = match frame.get_type() {
+            types::DATA => self.recv_data(frame.into()),
+            types::HEADERS => self.recv_headers(frame.into()),
+            types::PRIORITY => self.recv_priority(frame.into()),
+            types::RST_STREAM => self.recv_rst_stream(frame.into()),
+            types::SETTINGS => self.recv_settings(frame.into()),
+            types::PING => self.recv_ping(frame.into()),
+            types::GOAWAY => self.recv_go_away(frame.into()),
+            types::WINDOW_UPDATE => self.recv_window_update(frame.into()),
+            types::CONTINUATION => self.recv_continuation(frame.into()),
+            // unknown frame types MUST be ignored, unless the application
+            // wants to see them
+            f_type if self.deliver_extension_frames => {
+                self.events.push_back(Event::ExtensionFrame {
+                    frame_type: f_type,
+                    flags: frame.get_flags(),
+                    stream_id: stream_id,
+                    payload: frame.payload().to_vec(),
+                });
+                Ok(())
+            },
+            _ => Ok(()),
+        };
+        if res.is_err() {
+            self.release_connection_window(data_len);
+        }
+        self.reap_closed();
+        res
+    }
+
+    /// send a PING to the peer, the round trip time is reported with
+    /// Event::PongReceived when the acknowledgement comes back
+    pub fn ping(&mut self) -> PingToken {
+        let token = self.next_ping;
+        self.next_ping += 1;
+
+        let mut data = [0u8; 8];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = (token >> (56 - i * 8)) as u8;
+        }
+
+        self.pings.insert(token, self.now());
+        self.outbound.push_back(Outbound::Frame(OwnedFrame::ping(false, &data)));
+        PingToken(token)
+    }
+
+    /// 6.8 tell the peer the connection is shutting down
+    ///
+    /// The GOAWAY carries the highest stream id we have processed. Streams
+    /// up to it are allowed to finish and anything above it is ignored.
+    pub fn go_away(&mut self, error: ErrorCode) {
+        self.queue_go_away(error, &[]);
+    }
+
+    /// true when no stream is open (or half closed) and no header block
+    /// is partly received, so nothing is waiting on either side
+    pub fn is_idle(&self) -> bool {
+        self.expecting_continuation.is_none() && self.streams.values().all(|s| !s.is_active())
+    }
+
+    /// first step of a graceful shutdown
+    ///
+    /// A GOAWAY with the last stream id set to 2^31-1 tells the peer to
+    /// stop opening streams while still processing the ones that may
+    /// already be on their way. A call to go_away should follow once at
+    /// least a round trip has passed to set the actual last stream id.
+    pub fn go_away_graceful(&mut self) {
+        self.sent_go_away = Some(MAX_STREAM_ID);
+        self.outbound.push_back(Outbound::Frame(OwnedFrame::go_away(MAX_STREAM_ID, ErrorCode::NoError as u32, &[])));
+        self.observer.call(self.peer_addr, |o| o.on_goaway(Direction::Sent, MAX_STREAM_ID, ErrorCode::NoError));
+    }
+
+    /// 6.5.2 has the peer left SETTINGS_ENABLE_PUSH on, a PUSH_PROMISE
+    /// sent after it turned it off is a connection error on its side
+    pub fn push_enabled(&self) -> bool {
+        self.remote_settings.enable_push
+    }
+
+    /// can another stream be opened by us (for a push) without going
+    /// over the peer's SETTINGS_MAX_CONCURRENT_STREAMS
+    pub fn can_push(&self) -> bool {
+        match self.remote_settings.max_concurrent_streams {
+            Some(max) => (self.active_streams(0) as u64) < max as u64,
+            None => true,
+        }
+    }
+
+    /// has either side sent GOAWAY
+    pub fn is_going_away(&self) -> bool {
+        self.sent_go_away.is_some() || self.recv_go_away.is_some()
+    }
+
+    /// 6.4 abandon a stream (e.g. the handler for it failed), RST_STREAM
+    /// is sent with the error code and anything queued for it is dropped
+    pub fn reset_stream(&mut self, stream_id: u32, error: ErrorCode) {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            if stream.state() != StreamState::Closed {
+                self.outbound.push_back(Outbound::Frame(stream.reset(error)));
+            }
+        }
+        self.reap_closed();
+    }
+
+    /// 5.4 Error Handling
+    ///
+    /// Report an error that came out of dispatch_frame to the peer. A stream
+    /// error resets just that stream and the connection carries on. A
+    /// connection error sends GOAWAY (with the message as debug data), after
+    /// which nothing else should be read and the connection should be closed
+    /// once the GOAWAY is written.
+    pub fn report_error(&mut self, error: &H2Error) {
+        match *error {
+            H2Error::Connection(code, ref msg) => self.queue_go_away(code, msg.as_bytes()),
+            H2Error::Stream(stream_id, code) => {
+                // the stream may not exist (yet), e.g. a bad PRIORITY for an idle stream
+                if self.streams.contains_key(&stream_id) {
+                    self.reset_stream(stream_id, code);
+                }
+                else {
+                    self.remember_closed(stream_id, None);
+                    self.outbound.push_back(Outbound::Frame(OwnedFrame::rst_stream(stream_id, code as u32)));
+                }
+            },
+        }
+    }
+
+    /// report an error chain to the peer
+    ///
+    /// The H2Error in the chain is reported as it is (so a stream error
+    /// still only resets its stream). Otherwise the outermost Protocol kind
+    /// picks the GOAWAY code, and a chain without one is an INTERNAL_ERROR.
+    pub fn report_chain(&mut self, error: &ErrLink) {
+        if let Some(h2) = error.find::<H2Error>() {
+            return self.report_error(h2);
+        }
+        let code = error.protocol_code().unwrap_or(ErrorCode::InternalError);
+        self.queue_go_away(code, error.error().to_string().as_bytes());
+    }
+
+    /// send a header block on a stream, the first HEADERS on a stream
+    /// reserved for a push is what starts the pushed response
+    ///
+    /// 8.1 any number of informational (1xx) responses can come before the
+    /// final response, they can not end the stream and do not count as the
+    /// start of the response.
+    ///
+    /// A header list over the peer's SETTINGS_MAX_HEADER_LIST_SIZE is not
+    /// sent (the peer would end the connection over it), the stream is
+    /// left as it was so something smaller can be sent instead.
+    pub fn send_headers(&mut self, stream_id: u32, headers: &HeaderList, end_stream: bool) -> Result<(), H2Error> {
+        self.check_header_list(stream_id, headers)?;
+        let length = content_length(headers).map_err(|_| H2Error::Stream(stream_id, ErrorCode::InternalError))?;
+        let informational = headers.get_value_by_name(":status").map_or(false, |s| s.starts_with('1'));
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::ReservedLocal || stream.can_send() => {
+                if informational {
+                    if end_stream || stream.headers_sent() {
+                        return Err(H2Error::Stream(stream_id, ErrorCode::InternalError));
+                    }
+                }
+                else {
+                    stream.set_status(headers.get_value_by_name(":status").and_then(|s| s.parse().ok()));
+                    stream.set_send_length(length);
+                    stream.count_sent(0, end_stream)?;
+                    stream.set_headers_sent();
+                }
+                if stream.state() == StreamState::ReservedLocal {
+                    stream.set_state(StreamState::HalfClosedRemote);
+                }
+            },
+            _ => return Err(self.closed_error(stream_id)),
+        }
+
+        let block = self.encoder.encode_header_list(headers);
+        let f_flags = if end_stream { flags::END_STREAM } else { 0 };
+        self.queue_header_block(types::HEADERS, stream_id, f_flags, &[], &block);
+
+        if end_stream {
+            self.streams.get_mut(&stream_id).unwrap().send_end_stream();
+            self.reap_closed();
+        }
+        Ok(())
+    }
+
+    /// end a stream with trailers, sent in a HEADERS frame after
+    /// all of the data that was queued on the stream
+    ///
+    /// Trailers can not carry pseudo-header fields.
+    pub fn send_trailers(&mut self, stream_id: u32, trailers: HeaderList) -> Result<(), H2Error> {
+        if trailers.iter().any(|h| h.name().starts_with(':')) {
+            return Err(H2Error::Stream(stream_id, ErrorCode::ProtocolError));
+        }
+        self.check_header_list(stream_id, &trailers)?;
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.can_send() && !stream.is_end_queued() => {
+                stream.count_sent(0, true)?;
+                stream.queue_trailers(trailers);
+            },
+            _ => return Err(self.closed_error(stream_id)),
+        }
+        self.flush_all();
+        self.reap_closed();
+        Ok(())
+    }
+
+    /// 5.5 send a frame of a type the spec does not define, which a peer
+    /// that does not know it ignores
+    ///
+    /// The frame is queued with the DATA frames and nothing else is done
+    /// with it: the reserved bit of the stream id is cleared, and it has to
+    /// fit in the peer's SETTINGS_MAX_FRAME_SIZE. The defined frame types
+    /// are refused, sending them this way would go around the connection's
+    /// state. Nothing is sent when an error is returned, and the connection
+    /// carries on as it was.
+    pub fn send_extension_frame(&mut self, type_id: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Result<(), ExtensionError> {
+        if type_id <= types::CONTINUATION {
+            return Err(ExtensionError::NotAnExtension(type_id));
+        }
+        if payload.len() > self.remote_settings.max_frame_size as usize {
+            return Err(ExtensionError::TooLarge(payload.len()));
+        }
+        let header = OwnedFrame::header(type_id, flags, stream_id & MAX_STREAM_ID, payload.len());
+        let mut buf = Pool::global().get(payload.len());
+        buf.extend_from_slice(payload);
+        self.outbound.push_back(Outbound::Data(header, BodyChunk::Pooled(buf)));
+        Ok(())
+    }
+
+    /// 8.2 Server Push
+    ///
+    /// Reserve a new stream for a response the peer did not ask for yet,
+    /// and send the request it answers in a PUSH_PROMISE on the stream the
+    /// original request came in on. The response is then sent on the
+    /// returned stream id as for any other stream.
+    ///
+    /// Promised requests MUST be cacheable and MUST be safe, which here
+    /// means the method is GET or HEAD.
+    pub fn push_promise(&mut self, stream_id: u32, request_headers: &HeaderList) -> Result<u32, PushError> {
+        if !self.push_enabled() {
+            return Err(PushError::Disabled);
+        }
+        if self.is_going_away() {
+            return Err(PushError::GoingAway);
+        }
+        match request_headers.get_value_by_name(":method") {
+            Some("GET") | Some("HEAD") => {},
+            _ => return Err(PushError::NotCacheable),
+        }
+        if !self.encoder.fits(request_headers) {
+            return Err(PushError::HeadersTooLarge);
+        }
+        match self.streams.get(&stream_id) {
+            Some(stream) if stream_id % 2 == 1 && stream.can_send() => {},
+            _ => return Err(PushError::StreamClosed),
+        }
+        if !self.can_push() {
+            return Err(PushError::TooManyStreams);
+        }
+        if self.next_push_id > MAX_STREAM_ID {
+            return Err(PushError::StreamClosed);
+        }
+
+        let promised_id = self.next_push_id;
+        self.next_push_id += 2;
+
+        let mut stream = Stream::new(promised_id, self.remote_settings.initial_window_size);
+        stream.set_state(StreamState::ReservedLocal);
+        self.streams.insert(promised_id, stream);
+        // 5.3.5 pushed streams initially depend on their associated stream
+        self.priority.insert(promised_id, stream_id, DEFAULT_WEIGHT, false).unwrap();
+
+        let block = self.encoder.encode_header_list(request_headers);
+        let promised = [(promised_id >> 24) as u8, (promised_id >> 16) as u8, (promised_id >> 8) as u8, promised_id as u8];
+        self.queue_header_block(types::PUSH_PROMISE, stream_id, 0, &promised, &block);
+
+        Ok(promised_id)
+    }
+
+    // 6.5.2 refuse to send a header list the peer said it will not take
+    fn check_header_list(&self, stream_id: u32, headers: &HeaderList) -> Result<(), H2Error> {
+        if self.encoder.fits(headers) {
+            return Ok(());
+        }
+        klog_debug!(Context::peer(self.peer_addr).stream(stream_id) => "header list of {} is over the peer's SETTINGS_MAX_HEADER_LIST_SIZE", headers.size());
+        Err(H2Error::Stream(stream_id, ErrorCode::InternalError))
+    }
+
+    /// send data on a stream, as much as the flow control windows allow
+    ///
+    /// what does not fit is queued on the stream and sent as
+    /// WINDOW_UPDATE frames from the peer open up the windows
+    pub fn send_data(&mut self, stream_id: u32, data: &[u8], end_stream: bool) -> Result<(), H2Error> {
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.can_send() => {
+                stream.count_sent(data.len(), end_stream)?;
+                stream.queue_data(data, end_stream);
+            },
+            _ => return Err(self.closed_error(stream_id)),
+        }
+        self.flush_all();
+        self.reap_closed();
+        Ok(())
+    }
+
+    /// give back receive window for n bytes of DATA that were consumed,
+    /// letting the peer send that much more on the stream
+    ///
+    /// The data of every Event::Data needs to be released once it is
+    /// dealt with (when it is not, the peer runs out of window and stops
+    /// sending). This is what makes a slow reader slow down the peer.
+    /// What is released is advertised as set_window_updates says.
+    ///
+    /// Only what was received (and not already released) is given back, the
+    /// DATA of a stream reset to stay within the memory budget was given
+    /// back when it was reset.
+    pub fn release_window(&mut self, stream_id: u32, n: usize) {
+        let n = match self.buffered.get_mut(&stream_id) {
+            Some(buffered) => {
+                let n = ::std::cmp::min(n, *buffered);
+                *buffered -= n;
+                n
+            },
+            None => 0,
+        };
+        if self.buffered.get(&stream_id) == Some(&0) {
+            self.buffered.remove(&stream_id);
+        }
+        self.budget.credit(n);
+        self.give_back_window(stream_id, n);
+    }
+
+    /// grow (or shrink) the window the peer gets for a stream, for a
+    /// stream that is expected to have a large body
+    ///
+    /// The window it grows by is advertised right away, without waiting
+    /// for data to be released.
+    pub fn set_recv_window(&mut self, stream_id: u32, size: u32) -> Result<(), H2Error> {
+        let under_pressure = self.budget.under_pressure();
+        let n = match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::Open
+                || stream.state() == StreamState::HalfClosedLocal => {
+                stream.set_recv_window(size);
+                if under_pressure || stream.is_recv_paused() { 0 } else { stream.advertise() }
+            },
+            _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+        };
+        if n > 0 {
+            self.outbound.push_back(Outbound::Frame(OwnedFrame::window_update(stream_id, n)));
+        }
+        else {
+            self.window_pending.insert(stream_id);
+        }
+        Ok(())
+    }
+
+    /// stop giving the peer window for a stream, or start again
+    ///
+    /// While a stream is paused what is released for it is kept back
+    /// instead of advertised, so the peer stalls once it has sent the
+    /// window it already has, and the stream can not hold more than that
+    /// against the memory budget. The connection's window is still given
+    /// back, so the other streams carry on. Resuming advertises what was
+    /// kept back (unless the budget is under pressure, like always).
+    pub fn pause_recv_window(&mut self, stream_id: u32, paused: bool) -> Result<(), H2Error> {
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::Open
+                || stream.state() == StreamState::HalfClosedLocal => stream.pause_recv(paused),
+            _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+        }
+        if !paused {
+            self.window_pending.insert(stream_id);
+            self.send_window_updates();
+        }
+        Ok(())
+    }
+
+    /// with auto_release off the data released for a stream no longer
+    /// gives the peer window for it, only grant_recv_window does
+    ///
+    /// It is still given back for the connection, and credited to the
+    /// memory budget. Turning it back on tops the peer's window back up
+    /// to what it would have been, less the data still buffered.
+    pub fn set_auto_release(&mut self, stream_id: u32, auto_release: bool) -> Result<(), H2Error> {
+        let buffered = self.buffered.get(&stream_id).cloned().unwrap_or(0) as i64;
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::Open
+                || stream.state() == StreamState::HalfClosedLocal => {
+                if auto_release && !stream.auto_release() {
+                    let owed = stream.recv_window_size() as i64 - stream.recv_window() as i64 - stream.unadvertised() as i64 - buffered;
+                    if owed > 0 {
+                        stream.release_recv_window(owed as usize);
+                        self.window_pending.insert(stream_id);
+                    }
+                }
+                stream.set_auto_release(auto_release);
+            },
+            _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+        }
+        Ok(())
+    }
+
+    /// let the peer send n more bytes on a stream, advertised right away
+    /// (unless the stream is paused or the budget under pressure)
+    pub fn grant_recv_window(&mut self, stream_id: u32, n: u32) -> Result<(), H2Error> {
+        let under_pressure = self.budget.under_pressure();
+        let n = match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::Open
+                || stream.state() == StreamState::HalfClosedLocal => {
+                stream.grant_recv_window(n);
+                if under_pressure || stream.is_recv_paused() { 0 } else { stream.advertise() }
+            },
+            _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+        };
+        if n > 0 {
+            self.outbound.push_back(Outbound::Frame(OwnedFrame::window_update(stream_id, n)));
+        }
+        else {
+            self.window_pending.insert(stream_id);
+        }
+        Ok(())
+    }
+
+    /// choose when released window is advertised
+    pub fn set_window_updates(&mut self, window_updates: WindowUpdates) {
+        self.window_updates = window_updates;
+    }
+
+    pub fn window_updates(&self) -> WindowUpdates {
+        self.window_updates
+    }
+
+    // let the peer send n more bytes of DATA on the stream (and the
+    // connection), once it is worth a WINDOW_UPDATE
+    fn give_back_window(&mut self, stream_id: u32, n: usize) {
+        if n == 0 {
+            return;
+        }
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            // nothing more comes once the peer ended the stream
+            if (stream.state() == StreamState::Open || stream.state() == StreamState::HalfClosedLocal) && stream.auto_release() {
+                stream.release_recv_window(n);
+                self.window_pending.insert(stream_id);
+            }
+        }
+        self.release_connection_window(n);
+    }
+
+    // queue the WINDOW_UPDATEs that are due, the connection's goes with
+    // any stream's so they are written together
+    //
+    // While the budget is under pressure none are, so the peer runs out
+    // of window and stops sending.
+    fn send_window_updates(&mut self) {
+        if self.budget.under_pressure() || (self.unadvertised == 0 && self.window_pending.is_empty()) {
+            return;
+        }
+        let mut pending: Vec<u32> = self.window_pending.iter().cloned().collect();
+        pending.sort();
+        let mut updates = Vec::new();
+        for stream_id in pending {
+            match self.streams.get_mut(&stream_id) {
+                Some(ref mut stream) if stream.state() == StreamState::Open
+                    || stream.state() == StreamState::HalfClosedLocal => {
+                    if stream.is_recv_paused() || !self.window_updates.due(stream.unadvertised(), stream.recv_window_size()) {
+                        continue;
+                    }
+                    let n = stream.advertise();
+                    if n > 0 {
+                        updates.push(OwnedFrame::window_update(stream_id, n));
+                    }
+                },
+                _ => {},
+            }
+            self.window_pending.remove(&stream_id);
+        }
+
+        let window_size = Settings::default().initial_window_size;
+        if self.unadvertised > 0 && (!updates.is_empty() || self.window_updates.due(self.unadvertised, window_size)) {
+            self.recv_window += self.unadvertised as i32;
+            self.outbound.push_back(Outbound::Frame(OwnedFrame::window_update(0, self.unadvertised)));
+            self.unadvertised = 0;
+        }
+        for update in updates {
+            self.outbound.push_back(Outbound::Frame(update));
+        }
+    }
+
+    /// send everything body reads as the data of a stream, ending the
+    /// stream when it runs out (or when the content-length is reached)
+    ///
+    /// The body is read one frame at a time as the flow control windows
+    /// open up, so it is never held in memory all at once.
+    pub fn send_body(&mut self, stream_id: u32, body: Box<Read>) -> Result<(), H2Error> {
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.can_send() && !stream.is_end_queued() => stream.queue_body(body),
+            _ => return Err(self.closed_error(stream_id)),
+        }
+        self.flush_all();
+        self.reap_closed();
+        Ok(())
+    }
+
+    /// send the mapped file body as the rest of a stream, ending the
+    /// stream with the last of it
+    ///
+    /// Like send_body it goes out as the flow control windows open up,
+    /// but each DATA frame is a slice of the mapping instead of a copy,
+    /// written straight from it.
+    pub fn send_mapped(&mut self, stream_id: u32, body: MappedSlice) -> Result<(), H2Error> {
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.can_send() && !stream.is_end_queued() => stream.queue_mapped(body),
+            _ => return Err(self.closed_error(stream_id)),
+        }
+        self.flush_all();
+        self.reap_closed();
+        Ok(())
+    }
+
+    //=========================================
+    // receiving frames
+    //=========================================
+
+    fn recv_data(&mut self, frame: DataFrame) -> Result<(), H2Error> {
+        let stream_id = frame.get_stream_id();
+        let end_stream = frame.get_flags() & flags::END_STREAM != 0;
+        // 6.1 padding that is not shorter than the payload is a PROTOCOL_ERROR
+        let data = match frame.get_data() {
+            Some(data) => data,
+            None => return Err(H2Error::connection(ErrorCode::ProtocolError, "DATA padding is longer than the frame")),
+        };
+
+        match self.streams.get_mut(&stream_id) {
+            Some(ref mut stream) if stream.state() == StreamState::Open
+                || stream.state() == StreamState::HalfClosedLocal => {
+                stream.consume_recv_window(frame.get_length() as usize)?;
+                stream.count_received(data.len(), end_stream)?;
+                if end_stream {
+                    stream.recv_end_stream();
+                }
+            },
+            _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+        }
+
+        // the padding is never given to the application to release (data
+        // is part of the payload, so this can not go below 0)
+        debug_assert!(data.len() <= frame.get_length() as usize);
+        let padding = frame.get_length() as usize - data.len();
+        self.give_back_window(stream_id, padding);
+
+        // held until the application releases it, the peer had the window
+        // so it can only be made up for after the fact
+        let len = data.len();
+        self.budget.charge(len);
+        *self.buffered.entry(stream_id).or_insert(0) += len;
+        self.events.push_back(Event::Data { stream_id: stream_id, data: data.to_vec(), end_stream: end_stream });
+        self.evict_over_budget();
+        Ok(())
+    }
+
+    fn recv_headers(&mut self, frame: HeadersFrame) -> Result<(), H2Error> {
+        let stream_id = frame.get_stream_id();
+        let end_stream = frame.get_flags() & flags::END_STREAM != 0;
+        // 6.2 the same goes for HEADERS, which also has to fit the priority
+        let header_data = match frame.get_header_data() {
+            Some(header_data) => header_data,
+            None => return Err(H2Error::connection(ErrorCode::ProtocolError, "HEADERS padding is longer than the frame")),
+        };
+
+        if header_data.header_block_fragment.len() > self.header_block_limits.max_block_size {
+            return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "header block is too large"));
+        }
+        if frame.get_flags() & flags::END_HEADERS != 0 {
+            return self.recv_header_block(stream_id, end_stream, header_data.priority_data, header_data.header_block_fragment);
+        }
+
+        // the rest of the block follows in CONTINUATION frames
+        self.expecting_continuation = Some(stream_id);
+        self.partial_headers.end_stream = end_stream;
+        self.partial_headers.priority_data = header_data.priority_data;
+        self.clear_header_block();
+        self.buffer_header_fragment(header_data.header_block_fragment)?;
+        self.partial_headers.continuations = 0;
+        self.partial_headers.started = Some(self.now());
+        Ok(())
+    }
+
+    // validate_continuation makes sure this is for the header block being received
+    fn recv_continuation(&mut self, frame: ContinuationFrame) -> Result<(), H2Error> {
+        let fragment = frame.get_contuniation();
+        self.check_timeouts()?;
+
+        // checked before buffering anything so a flood can not grow the block
+        self.partial_headers.continuations += 1;
+        if self.partial_headers.continuations > self.header_block_limits.max_continuations {
+            self.clear_header_block();
+            return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "too many CONTINUATION frames"));
+        }
+        if self.partial_headers.block.len() + fragment.len() > self.header_block_limits.max_block_size {
+            self.clear_header_block();
+            return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "header block is too large"));
+        }
+
+        self.buffer_header_fragment(fragment)?;
+        if frame.get_flags() & flags::END_HEADERS == 0 {
+            return Ok(());
+        }
+
+        self.expecting_continuation = None;
+        let block = ::std::mem::replace(&mut self.partial_headers.block, Vec::new());
+        let (end_stream, priority_data) = (self.partial_headers.end_stream, self.partial_headers.priority_data);
+        let res = self.recv_header_block(frame.get_stream_id(), end_stream, priority_data, &block);
+
+        // keep the allocation for the next block
+        self.budget.credit(block.len());
+        self.partial_headers.block = block;
+        self.partial_headers.block.clear();
+        res
+    }
+
+    // a complete header block, from a single HEADERS frame or put together from CONTINUATION frames
+    fn recv_header_block(&mut self, stream_id: u32, end_stream: bool, priority_data: Option<(bool, u32, u8)>, block: &[u8]) -> Result<(), H2Error> {
+        let headers = match self.decoder.get_header_list(block) {
+            Ok(headers) => headers,
+            Err(e) => return Err(H2Error::connection(ErrorCode::CompressionError, e.to_string())),
+        };
+
+        // trailers that crossed paths with a RST_STREAM
+        if self.is_ignored_stream(stream_id) || self.is_recently_closed(stream_id) {
+            return Ok(());
+        }
+        if stream_id > self.highest_seen_client_stream {
+            self.highest_seen_client_stream = stream_id;
+        }
+
+        let initial_window = self.remote_settings.initial_window_size;
+
+        // once the peer has opened as many streams as it gets, the ones
+        // it has are finished and it is told to open a new connection
+        if !self.streams.contains_key(&stream_id) {
+            let opened = self.stream_count.opened();
+            if Some(opened) == self.stream_limits.max_streams {
+                self.go_away(ErrorCode::NoError);
+            }
+        }
+
+        // 5.1.2 refusing the stream lets the client retry it later
+        if !self.streams.contains_key(&stream_id) && !self.can_accept_stream() {
+            self.remember_closed(stream_id, None);
+            self.outbound.push_back(Outbound::Frame(OwnedFrame::rst_stream(stream_id, ErrorCode::RefusedStream as u32)));
+            return Ok(());
+        }
+
+        let (exclusive, dependency, weight) = priority_data.unwrap_or((false, 0, DEFAULT_WEIGHT));
+        if priority_data.is_some() || !self.priority.contains(stream_id) {
+            self.priority.insert(stream_id, dependency, weight, exclusive)?;
+        }
+
+        // 8.1 a second header block on a stream is the trailers
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            match stream.state() {
+                StreamState::Open | StreamState::HalfClosedLocal => {},
+                _ => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed)),
+            }
+            // trailers must end the stream and can not have pseudo-header fields (8.1.2.1)
+            if !end_stream || headers.iter().any(|h| h.name().starts_with(':')) {
+                return Err(H2Error::Stream(stream_id, ErrorCode::ProtocolError));
+            }
+            stream.count_received(0, true)?;
+            stream.recv_end_stream();
+            self.events.push_back(Event::Trailers { stream_id: stream_id, headers: headers });
+            return Ok(());
+        }
+
+        let mut stream = Stream::new(stream_id, initial_window);
+        stream.init_recv_window(self.local_settings.initial_window_size);
+        stream.set_state(StreamState::Open);
+        self.observer.call(self.peer_addr, |o| o.on_stream_open(stream_id, &headers));
+
+        // the block was decoded so the compression state is fine, only
+        // this request is refused (RFC 6585 5), and the client is told to
+        // stop sending the rest of it
+        if headers.size() > self.max_request_headers {
+            if end_stream {
+                stream.recv_end_stream();
+            }
+            self.streams.insert(stream_id, stream);
+            let mut response = HeaderList::with_capacity(1);
+            response.add_entry((":status", "431").into());
+            self.send_headers(stream_id, &response, true)?;
+            self.reset_stream(stream_id, ErrorCode::NoError);
+            return Ok(());
+        }
+
+        stream.set_recv_length(content_length(&headers).map_err(|_| H2Error::Stream(stream_id, ErrorCode::ProtocolError))?);
+        stream.set_head(headers.get_value_by_name(":method") == Some("HEAD"));
+        if self.access_log.is_some() {
+            stream.set_request(RequestInfo {
+                method: headers.get_value_by_name(":method").unwrap_or("").to_string(),
+                path: headers.get_value_by_name(":path").unwrap_or("").to_string(),
+                received: self.clock.system_now(),
+                started: self.now(),
+            });
+        }
+        stream.count_received(0, end_stream)?;
+        if end_stream {
+            stream.recv_end_stream();
+        }
+        self.streams.insert(stream_id, stream);
+
+        self.events.push_back(Event::Headers { stream_id: stream_id, headers: headers, end_stream: end_stream });
+        Ok(())
+    }
+
+    // PRIORITY can be sent for a stream in any state, including
+    // idle streams to set up the tree before they are opened
+    fn recv_priority(&mut self, frame: PriorityFrame) -> Result<(), H2Error> {
+        let stream_id = frame.get_stream_id();
+        if frame.get_length() != 5 {
+            return Err(H2Error::Stream(stream_id, ErrorCode::FrameSizeError));
+        }
+
+        let (exclusive, dependency, weight) = frame.get_priority_info();
+        if self.streams.contains_key(&stream_id) {
+            return self.priority.insert(stream_id, dependency, weight, exclusive);
+        }
+        // a closed stream is out of the tree for good, there is no point
+        // keeping its priority around
+        let closed = match stream_id % 2 {
+            1 => stream_id <= self.highest_seen_client_stream,
+            _ => stream_id < self.next_push_id,
+        };
+        if closed {
+            return Ok(());
+        }
+        let max_streams = self.local_settings.max_concurrent_streams.unwrap_or(DEFAULT_MAX_CONCURRENT_STREAMS);
+        let max_idle = IDLE_PRIORITIES_PER_STREAM * max_streams as usize;
+        self.priority.insert_idle(stream_id, dependency, weight, exclusive, max_idle)
+    }
+
+    fn recv_rst_stream(&mut self, frame: RstStreamFrame) -> Result<(), H2Error> {
+        if frame.get_length() != 4 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "RST_STREAM length is not 4"));
+        }
+
+        let stream_id = frame.get_stream_id();
+        let mut cancelled = false;
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            // reset before the response was done, the handler's work is wasted
+            cancelled = stream.state() != StreamState::Closed;
+            stream.recv_reset(frame.get_error_code().into());
+        }
+        self.events.push_back(Event::StreamReset { stream_id: stream_id, error: frame.get_error_code().into(), by_peer: true });
+        if cancelled && !self.stream_count.reset(self.now(), &self.stream_limits) {
+            return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "too many streams reset"));
+        }
+        Ok(())
+    }
+
+    /// 5.1 DATA, RST_STREAM or WINDOW_UPDATE for a stream that is not open
+    ///
+    /// Frames for a stream that closed recently may have been sent before
+    /// the peer knew about it so they are ignored. Those frames are never
+    /// allowed on an idle stream, and DATA on a stream that closed long ago
+    /// (or never existed) means the peer has lost track of its streams.
+    fn recv_unknown_stream(&mut self, f_type: u8, stream_id: u32) -> Result<(), H2Error> {
+        if self.is_recently_closed(stream_id) {
+            return Ok(());
+        }
+        if stream_id % 2 == 1 && stream_id > self.highest_seen_client_stream {
+            return Err(H2Error::connection(ErrorCode::ProtocolError, format!("frame type 0x{:02X} on idle stream {}", f_type, stream_id)));
+        }
+        match f_type {
+            types::DATA => Err(H2Error::connection(ErrorCode::StreamClosed, format!("DATA on closed stream {}", stream_id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn recv_settings(&mut self, frame: SettingsFrame) -> Result<(), H2Error> {
+        // 6.5 an ACK has no payload, and parameters are 6 bytes each
+        if frame.get_flags() & flags::ACK != 0 && frame.get_length() != 0 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "SETTINGS ACK with a payload"));
+        }
+        if frame.get_length() % 6 != 0 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "SETTINGS length is not a multiple of 6"));
+        }
+
+        // 6.5.3 each ACK is for the oldest SETTINGS still waiting, one we
+        // did not ask for is ignored
+        if frame.get_flags() & flags::ACK != 0 {
+            if let Some((settings, _)) = self.pending_settings.pop_front() {
+                self.local_settings = settings;
+            }
+            return Ok(());
+        }
+
+        // 6.5.3 the new values apply from the ACK on, with nothing queued
+        // in between. The frames queued before it were built with the old
+        // ones and go out first, which the peer still accepts as they come
+        // before the ACK. Everything after it is built with the new ones,
+        // the DATA and header blocks read the max frame size frame by frame.
+        let effects = self.remote_settings.apply_remote(&frame)?;
+        for effect in effects {
+            match effect {
+                SettingsEffect::InitialWindowSize { old, new } => self.adjust_stream_windows(old, new)?,
+                // the encoder can use up to that much (but no more than
+                // its own table, the default size)
+                SettingsEffect::HeaderTableSize(size) => {
+                    let own = Settings::default().header_table_size;
+                    self.encoder.set_max_table_size(::std::cmp::min(size, own) as usize);
+                },
+                // read as each frame is built, see above
+                SettingsEffect::MaxFrameSize(_) => {},
+                // what is sent from now on has to fit
+                SettingsEffect::MaxHeaderListSize(max) => self.encoder.set_max_header_list_size(Some(max)),
+            }
+        }
+
+        self.outbound.push_back(Outbound::Frame(OwnedFrame::settings_ack()));
+        // windows may have opened up
+        self.flush_all();
+        Ok(())
+    }
+
+    // apply the change in SETTINGS_INITIAL_WINDOW_SIZE to every open
+    // and half closed stream (new streams just start with the new size)
+    fn adjust_stream_windows(&mut self, old: u32, new: u32) -> Result<(), H2Error> {
+        let delta = new as i64 - old as i64;
+        for stream in self.streams.values_mut().filter(|s| s.is_active()) {
+            stream.adjust_send_window(delta)?;
+        }
+        Ok(())
+    }
+
+    // every PING that is not an ACK gets an ACK with the same data,
+    // and ACKs are matched up with the PINGs we sent
+    fn recv_ping(&mut self, frame: PingFrame) -> Result<(), H2Error> {
+        if frame.get_length() != 8 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "PING length is not 8"));
+        }
+
+        let mut data = [0u8; 8];
+        data.copy_from_slice(frame.get_ping_data());
+
+        if frame.get_flags() & flags::ACK == 0 {
+            self.outbound.push_back(Outbound::Frame(OwnedFrame::ping(true, &data)));
+            return Ok(());
+        }
+
+        let token = data.iter().fold(0u64, |token, b| token << 8 | *b as u64);
+        // an ACK we did not ask for is just ignored
+        if let Some(sent) = self.pings.remove(&token) {
+            let rtt = self.now().duration_since(sent);
+            self.events.push_back(Event::PongReceived { token: PingToken(token), rtt: rtt });
+        }
+        Ok(())
+    }
+
+    fn recv_go_away(&mut self, frame: GoAwayFrame) -> Result<(), H2Error> {
+        if frame.get_length() < 8 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "GOAWAY length is less than 8"));
+        }
+
+        let (last_stream_id, error, debug) = frame.get_go_away_info();
+        self.recv_go_away = Some(last_stream_id);
+
+        // streams we opened above the last id were never processed by the peer
+        for (id, stream) in self.streams.iter_mut() {
+            if id % 2 == 0 && *id > last_stream_id {
+                stream.set_state(StreamState::Closed);
+            }
+        }
+
+        self.observer.call(self.peer_addr, |o| o.on_goaway(Direction::Received, last_stream_id, error.into()));
+        self.events.push_back(Event::GoAway { last_stream_id: last_stream_id, error: error.into(), debug: debug.to_vec() });
+        Ok(())
+    }
+
+    fn recv_window_update(&mut self, frame: WindowUpdateFrame) -> Result<(), H2Error> {
+        if frame.get_length() != 4 {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "WINDOW_UPDATE length is not 4"));
+        }
+
+        let stream_id = frame.get_stream_id();
+        let increment = frame.get_window_update() & 0x7FFFFFFF;
+
+        if increment == 0 {
+            return match stream_id {
+                0 => Err(H2Error::connection(ErrorCode::ProtocolError, "WINDOW_UPDATE with 0 increment")),
+                id => Err(H2Error::Stream(id, ErrorCode::ProtocolError)),
+            };
+        }
+
+        if stream_id == 0 {
+            let window = self.send_window as i64 + increment as i64;
+            if window > MAX_WINDOW_SIZE as i64 {
+                return Err(H2Error::connection(ErrorCode::FlowControlError, "connection window above 2^31-1"));
+            }
+            self.send_window = window as i32;
+        }
+        else if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.increase_send_window(increment)?;
+        }
+        self.flush_all();
+
+        Ok(())
+    }
+
+    /// 5.1.1 Stream Identifiers
+    ///
+    /// Streams initiated by a client MUST use odd-numbered stream identifiers.
+    /// The identifier of a newly established stream MUST be numerically greater
+    /// than all streams that the initiating endpoint has opened or reserved.
+    ///
+    /// Also makes sure frames that belong to a stream are not sent on stream 0,
+    /// and frames that belong to the connection are not sent on a stream.
+    /// All of these are connection errors of type PROTOCOL_ERROR.
+    fn validate_stream_id(&self, frame: &GenericFrame) -> Result<(), H2Error> {
+        let stream_id = frame.get_stream_id();
+        let f_type = frame.get_type();
+
+        match f_type {
+            types::DATA | types::HEADERS | types::PRIORITY | types::RST_STREAM
+                | types::PUSH_PROMISE | types::CONTINUATION if stream_id == 0 => {
+                return Err(H2Error::connection(ErrorCode::ProtocolError, format!("frame type 0x{:02X} on stream 0", f_type)));
+            },
+            types::SETTINGS | types::PING | types::GOAWAY if stream_id != 0 => {
+                return Err(H2Error::connection(ErrorCode::ProtocolError, format!("frame type 0x{:02X} on stream {}", f_type, stream_id)));
+            },
+            _ => {},
+        }
+
+        // a HEADERS frame for a stream we do not know of opens a new stream
+        if f_type == types::HEADERS && !self.streams.contains_key(&stream_id) && !self.is_recently_closed(stream_id) {
+            if stream_id % 2 == 0 {
+                return Err(H2Error::connection(ErrorCode::ProtocolError, format!("client opened even stream {}", stream_id)));
+            }
+            if stream_id <= self.highest_seen_client_stream {
+                return Err(H2Error::connection(ErrorCode::ProtocolError, format!("stream {} is lower than stream {}", stream_id, self.highest_seen_client_stream)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 6.10 CONTINUATION
+    ///
+    /// A header block is sent as one contiguous sequence of frames. After a
+    /// HEADERS frame without END_HEADERS the only frames allowed are
+    /// CONTINUATION frames on the same stream, and a CONTINUATION frame must
+    /// always follow one of those. Anything else is a connection error of
+    /// type PROTOCOL_ERROR.
+    fn validate_continuation(&self, frame: &GenericFrame) -> Result<(), H2Error> {
+        let stream_id = frame.get_stream_id();
+        let f_type = frame.get_type();
+
+        match (self.expecting_continuation, f_type) {
+            (Some(id), types::CONTINUATION) if stream_id == id => Ok(()),
+            (Some(id), _) =>
+                Err(H2Error::connection(ErrorCode::ProtocolError, format!("frame type 0x{:02X} on stream {} in the header block of stream {}", f_type, stream_id, id))),
+            (None, types::CONTINUATION) =>
+                Err(H2Error::connection(ErrorCode::ProtocolError, format!("CONTINUATION on stream {} without a header block", stream_id))),
+            _ => Ok(()),
+        }
+    }
+
+    /// 4.2 Frame Size
+    ///
+    /// A frame larger than our SETTINGS_MAX_FRAME_SIZE is a FRAME_SIZE_ERROR.
+    /// It is a connection error for frames that can change the state of the
+    /// whole connection (including every header block since the decoder
+    /// would miss it) and a stream error otherwise.
+    fn validate_frame_size(&self, frame: &GenericFrame) -> Result<(), H2Error> {
+        if frame.get_length() <= self.local_settings.max_frame_size {
+            return Ok(());
+        }
+
+        match (frame.get_type(), frame.get_stream_id()) {
+            (types::SETTINGS, _) | (types::HEADERS, _) | (types::PUSH_PROMISE, _) | (types::CONTINUATION, _) | (_, 0) =>
+                Err(H2Error::connection(ErrorCode::FrameSizeError, format!("frame of {} bytes is too large", frame.get_length()))),
+            (_, stream_id) => Err(H2Error::Stream(stream_id, ErrorCode::FrameSizeError)),
+        }
+    }
+
+    /// 5.1.2 Stream Concurrency
+    ///
+    /// Streams that are in the "open" state or in either of the "half-closed"
+    /// states count toward the maximum number of streams that an endpoint is
+    /// permitted to open. Parity 1 counts the peer's streams and 0 our own.
+    fn active_streams(&self, parity: u32) -> usize {
+        self.streams.values().filter(|s| s.id() % 2 == parity && s.is_active()).count()
+    }
+
+    // would a new stream from the peer stay within our SETTINGS_MAX_CONCURRENT_STREAMS
+    fn can_accept_stream(&self) -> bool {
+        match self.local_settings.max_concurrent_streams {
+            Some(max) => (self.active_streams(1) as u64) < max as u64,
+            None => true,
+        }
+    }
+
+    // is this a stream the peer opened after we sent GOAWAY
+    fn is_ignored_stream(&self, stream_id: u32) -> bool {
+        match self.sent_go_away {
+            Some(last) => stream_id > last && !self.streams.contains_key(&stream_id),
+            None => false,
+        }
+    }
+
+    fn is_recently_closed(&self, stream_id: u32) -> bool {
+        self.closed_streams.iter().any(|&(id, _)| id == stream_id)
+    }
+
+    // with the code the peer reset it with, if it did
+    fn remember_closed(&mut self, stream_id: u32, reset_by_peer: Option<ErrorCode>) {
+        if self.closed_streams.len() == CLOSED_STREAMS_KEPT {
+            self.closed_streams.pop_front();
+        }
+        self.closed_streams.push_back((stream_id, reset_by_peer));
+    }
+
+    // the error for sending on a stream that can not be sent on, which
+    // has the peer's code when it was the peer that reset the stream (the
+    // application can tell REFUSED_STREAM, safe to retry, from CANCEL)
+    fn closed_error(&self, stream_id: u32) -> H2Error {
+        let reset_by_peer = match self.streams.get(&stream_id) {
+            Some(stream) => match stream.reset_by() {
+                Some((Direction::Received, code)) => Some(code),
+                _ => None,
+            },
+            None => self.closed_streams.iter().find(|&&(id, _)| id == stream_id).and_then(|&(_, code)| code),
+        };
+        H2Error::Stream(stream_id, reset_by_peer.unwrap_or(ErrorCode::StreamClosed))
+    }
+
+    fn release_connection_window(&mut self, n: usize) {
+        self.unadvertised += n as u32;
+        self.send_window_updates();
+    }
+
+    // what the connection itself has charged to the budget
+    fn held(&self) -> usize {
+        self.outbound.bytes() + self.buffered.values().sum::<usize>() + self.partial_headers.block.len()
+    }
+
+    // the stream holding the most DATA, the newest of those holding as much
+    fn most_buffered_stream(&self) -> Option<u32> {
+        self.buffered.iter().max_by_key(|&(&id, &n)| (n, id)).map(|(&id, _)| id)
+    }
+
+    // the last resort when the budget is over its ceiling even with the
+    // peer slowed down, the stream is reset and what it held let go
+    fn evict(&mut self, stream_id: u32) {
+        klog_debug!(Context::peer(self.peer_addr).stream(stream_id) => "reset to stay within the memory budget");
+        let n = self.buffered.remove(&stream_id).unwrap_or(0);
+        self.budget.credit(n);
+        self.events.push_back(Event::StreamReset { stream_id: stream_id, error: ErrorCode::EnhanceYourCalm, by_peer: false });
+        self.reset_stream(stream_id, ErrorCode::EnhanceYourCalm);
+        // the application is not going to release it now
+        self.release_connection_window(n);
+    }
+
+    fn evict_over_budget(&mut self) {
+        while self.budget.is_over() {
+            match self.most_buffered_stream() {
+                Some(stream_id) => self.evict(stream_id),
+                None => return,
+            }
+        }
+    }
+
+    // add to the header block being put together, making room for it
+    // in the budget if there is any to make
+    fn buffer_header_fragment(&mut self, fragment: &[u8]) -> Result<(), H2Error> {
+        while !self.budget.try_charge(fragment.len()) {
+            match self.most_buffered_stream() {
+                Some(stream_id) => self.evict(stream_id),
+                None => {
+                    self.clear_header_block();
+                    return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "header block is over the memory budget"));
+                },
+            }
+        }
+        self.partial_headers.block.extend_from_slice(fragment);
+        Ok(())
+    }
+
+    fn clear_header_block(&mut self) {
+        self.budget.credit(self.partial_headers.block.len());
+        self.partial_headers.block.clear();
+    }
+
+    // the GOAWAY goes out ahead of whatever is queued, the streams up to
+    // its last stream id keep sending until they are done
+    fn queue_go_away(&mut self, error: ErrorCode, debug_data: &[u8]) {
+        let last_stream_id = self.highest_seen_client_stream;
+        self.sent_go_away = Some(last_stream_id);
+        self.outbound.push_back(Outbound::Frame(OwnedFrame::go_away(last_stream_id, error as u32, debug_data)));
+        self.observer.call(self.peer_addr, |o| o.on_goaway(Direction::Sent, last_stream_id, error));
+        self.drop_streams_above(last_stream_id);
+    }
+
+    // 6.8 the peer takes the streams it opened above the last stream id
+    // of our GOAWAY as never processed, so what is queued for them is not
+    // sent and they are reset here without telling it (pushed streams are
+    // ours, the last stream id says nothing about them)
+    fn drop_streams_above(&mut self, last_stream_id: u32) {
+        let dropped = self.outbound.drop_streams_above(last_stream_id, |id| id % 2 == 1);
+        for stream_id in dropped {
+            klog_debug!(Context::peer(self.peer_addr).stream(stream_id) => "dropped, it is above the last stream id of the GOAWAY");
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                stream.reset(ErrorCode::RefusedStream);
+            }
+            self.events.push_back(Event::StreamReset { stream_id: stream_id, error: ErrorCode::RefusedStream, by_peer: false });
+        }
+        self.reap_closed();
+    }
+
+    // closed streams are dropped, keeping only their id for a while
+    fn reap_closed(&mut self) {
+        let mut closed: Vec<u32> = self.streams.values()
+            .filter(|s| s.state() == StreamState::Closed)
+            .map(|s| s.id())
+            .collect();
+        closed.sort();
+        for id in closed {
+            let mut stream = self.streams.remove(&id).unwrap();
+            let aborted = stream.was_reset();
+            self.log_stream(&mut stream, aborted);
+            let info = close_info(&stream, stream.reset_by());
+            self.observer.call(self.peer_addr, |o| o.on_stream_close(id, info));
+            self.priority.remove(id);
+            let reset_by_peer = match stream.reset_by() {
+                Some((Direction::Received, code)) => Some(code),
+                _ => None,
+            };
+            self.remember_closed(id, reset_by_peer);
+        }
+    }
+
+    // tell the access log how the request on the stream went
+    fn log_stream(&self, stream: &mut Stream, aborted: bool) {
+        let (log, request) = match (self.access_log.as_ref(), stream.take_request()) {
+            (Some(log), Some(request)) => (log, request),
+            _ => return,
+        };
+        let transfer = stream.transfer().get();
+        log.log(&LogRecord {
+            peer: self.peer_addr,
+            stream_id: stream.id(),
+            method: request.method,
+            path: request.path,
+            status: stream.status(),
+            request_bytes: stream.data_received(),
+            response_bytes: stream.data_sent(),
+            response_header_bytes: transfer.header_bytes,
+            response_body_bytes: transfer.body_bytes,
+            time: request.received,
+            duration: self.now().duration_since(request.started),
+            aborted: aborted,
+        });
+    }
+
+    //=========================================
+    // sending queued data
+    //=========================================
+
+    // queue a header block as a HEADERS or PUSH_PROMISE frame, followed by as
+    // many CONTINUATION frames as it takes to stay within the peer's max frame size
+    //
+    // only the first frame can be padded, the padding is picked before the
+    // block is split so a long block still gets it
+    fn queue_header_block(&mut self, f_type: u8, stream_id: u32, f_flags: u8, prefix: &[u8], block: &[u8]) {
+        let max_frame_size = self.remote_settings.max_frame_size as usize;
+        let calc = PaddingCalc::new(self.remote_settings.max_frame_size);
+        let pad = self.padder.pad(prefix.len(), &calc);
+
+        let first_len = ::std::cmp::min(block.len(), calc.max_content(pad) - prefix.len());
+        let (first, mut rest) = block.split_at(first_len);
+
+        let end_headers = if rest.is_empty() { flags::END_HEADERS } else { 0 };
+        let frame = OwnedFrame::padded(f_type, f_flags | end_headers, stream_id, prefix, first, pad, &calc)
+            .expect("the first fragment is cut to fit");
+        let mut queued = frame.as_bytes().len();
+        self.outbound.push_back(Outbound::Frame(frame));
+
+        while !rest.is_empty() {
+            let (fragment, remaining) = rest.split_at(::std::cmp::min(rest.len(), max_frame_size));
+            rest = remaining;
+            let end_headers = if rest.is_empty() { flags::END_HEADERS } else { 0 };
+            let frame = OwnedFrame::new(types::CONTINUATION, end_headers, stream_id, fragment);
+            queued += frame.as_bytes().len();
+            self.outbound.push_back(Outbound::Frame(frame));
+        }
+
+        // a PUSH_PROMISE is the request of another stream, not part of the response
+        if f_type == types::HEADERS {
+            if let Some(stream) = self.streams.get(&stream_id) {
+                stream.transfer().add_headers(queued);
+            }
+        }
+    }
+
+    // send the queued data of every stream that has some, one frame at
+    // a time with the priority tree picking which stream goes next
+    fn flush_all(&mut self) {
+        let mut ready: HashSet<u32> = self.streams.values()
+            .filter(|s| s.has_pending_data())
+            .map(|s| s.id())
+            .collect();
+
+        while let Some(stream_id) = self.priority.next_ready(&ready) {
+            match self.send_pending_frame(stream_id) {
+                Some(sent) => self.priority.record_sent(stream_id, sent),
+                None => { ready.remove(&stream_id); },
+            }
+            if !self.streams[&stream_id].has_pending_data() {
+                ready.remove(&stream_id);
+            }
+        }
+        self.check_stalls();
+    }
+
+    // send one DATA frame of the data queued on a stream, as much as the
+    // windows allow, returning its size or None if it is blocked
+    //
+    // once the data is all sent the trailers go out, if there are any, and
+    // a body is read from once the data queued before it is sent
+    fn send_pending_frame(&mut self, stream_id: u32) -> Option<usize> {
+        let max_frame_size = self.remote_settings.max_frame_size as i64;
+        let stream = self.streams.get_mut(&stream_id).unwrap();
+
+        if let Some(trailers) = stream.take_trailers() {
+            stream.send_end_stream();
+            let block = self.encoder.encode_header_list(&trailers);
+            self.queue_header_block(types::HEADERS, stream_id, flags::END_STREAM, &[], &block);
+            return Some(0);
+        }
+
+        let window = ::std::cmp::min(stream.send_window() as i64, self.send_window as i64);
+        let available = ::std::cmp::min(window, max_frame_size);
+
+        // an empty DATA frame carrying END_STREAM is not flow controlled
+        if available <= 0 && (stream.pending_len() > 0 || stream.has_body()) {
+            return None;
+        }
+
+        if stream.pending_len() == 0 && stream.has_body() {
+            if let Err(e) = stream.read_body(available as usize) {
+                klog_warn!(Context::peer(self.peer_addr).stream(stream.id()) => "could not read the body: {}", e);
+                let rst = stream.reset(ErrorCode::InternalError);
+                self.outbound.push_back(Outbound::Frame(rst));
+                return None;
+            }
+        }
+
+        let max = ::std::cmp::max(available, 0) as usize;
+        let taken = match stream.take_mapped(max) {
+            Ok(Some((data, end_stream))) => (BodyChunk::Mapped(data), end_stream),
+            Ok(None) => {
+                let (data, end_stream) = stream.take_pending(max);
+                (BodyChunk::Pooled(data), end_stream)
+            },
+            Err(_) => {
+                klog_warn!(Context::peer(self.peer_addr).stream(stream.id()) => "the mapped body is not as long as its content-length");
+                let rst = stream.reset(ErrorCode::InternalError);
+                self.outbound.push_back(Outbound::Frame(rst));
+                return None;
+            },
+        };
+        let (data, end_stream) = taken;
+        stream.consume_send_window(data.len());
+        self.send_window -= data.len() as i32;
+
+        if end_stream {
+            stream.send_end_stream();
+        }
+        let f_flags = if end_stream { flags::END_STREAM } else { 0 };
+        let sent = data.len();
+        let header = OwnedFrame::header(types::DATA, f_flags, stream_id, sent);
+        self.outbound.push_back(Outbound::Data(header, data));
+        Some(sent)
+    }
+}
+
+// the requests still going when the connection ends never finished
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // a budget shared with the reader outlives the connection
+        let held = self.buffered.values().sum::<usize>() + self.partial_headers.block.len();
+        self.budget.credit(held);
+        if self.access_log.is_none() && !self.observer.is_some() {
+            return;
+        }
+        let mut open: Vec<Stream> = self.streams.drain().map(|(_, s)| s).collect();
+        open.sort_by_key(|s| s.id());
+        for mut stream in open {
+            self.log_stream(&mut stream, true);
+            let info = close_info(&stream, Some(stream.reset_by().unwrap_or((Direction::Sent, ErrorCode::Cancel))));
+            self.observer.call(self.peer_addr, |o| o.on_stream_close(stream.id(), info));
+        }
+    }
+}
+
+// what an observer is told about a stream that closed
+fn close_info(stream: &Stream, reset: Option<(Direction, ErrorCode)>) -> CloseInfo {
+    let transfer = stream.transfer().get();
+    CloseInfo {
+        reset: reset,
+        status: stream.status(),
+        request_bytes: stream.data_received(),
+        response_bytes: transfer.data_bytes,
+        response_header_bytes: transfer.header_bytes,
+        response_body_bytes: transfer.body_bytes,
+    }
+}
+
+#[cfg(test)]
+mod connection_tests {
+
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use std::io::{self, Read};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Connection, SERVER};
+    use super::budget::MemoryBudget;
+    use super::error::{ErrorCode, ExtensionError, H2Error, PushError};
+    use super::event::Event;
+    use super::limits::{StreamLimits, DEFAULT_MAX_REQUEST_HEADERS};
+    use super::settings::{Settings, HEADER_TABLE_SIZE, INITIAL_WINDOW_SIZE, MAX_CONCURRENT_STREAMS, MAX_HEADER_LIST_SIZE, MIN_FRAME_SIZE_LIMIT};
+    use super::stream::StreamState;
+    use super::trace::{TraceSink, Tracer};
+    use super::window::{WindowLimit, WindowUpdates};
+    use frame::{Http2Frame, OwnedFrame, PaddingPolicy};
+    use header::{Decoder, Encoder, HeaderList};
+    use frame::frame_types::{types, flags, GoAwayFrame, HeadersFrame, RstStreamFrame, SettingsFrame};
+    use krserr::{ErrLink, ErrorChain, Kind};
+    use log::capture::capture;
+    use test_util::{open_stream, GET_BLOCK};
+    use util::{Clock, MockClock};
+
+    fn dispatch(conn: &mut Connection, mut frame: OwnedFrame) -> Result<(), H2Error> {
+        conn.dispatch_frame(frame.as_frame())
+    }
+
+    // take all the DATA frames that are queued returning
+    // the total payload size and if END_STREAM was seen
+    fn drain_data(conn: &mut Connection) -> (usize, bool) {
+        let mut total = 0;
+        let mut end_stream = false;
+        while let Some(frame) = conn.next_outbound() {
+            if frame.frame_type() == types::DATA {
+                total += frame.payload().len();
+                end_stream |= frame.frame_flags() & flags::END_STREAM != 0;
+            }
+        }
+        (total, end_stream)
+    }
+
+    // the (stream id, increment) of every WINDOW_UPDATE queued
+    fn drain_window_updates(conn: &mut Connection) -> Vec<(u32, u32)> {
+        let mut updates = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            if frame.frame_type() == types::WINDOW_UPDATE {
+                let p = frame.payload();
+                updates.push((frame.stream_id(), (p[0] as u32) << 24 | (p[1] as u32) << 16 | (p[2] as u32) << 8 | p[3] as u32));
+            }
+        }
+        updates
+    }
+
+    #[test]
+    fn recv_padding_released() {
+        let mut conn = Connection::new();
+        conn.set_window_updates(WindowUpdates::Immediate);
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+
+        // 1 byte of pad length, 3 of data, 10 of padding
+        let mut payload = vec![10, b'a', b'b', b'c'];
+        payload.extend_from_slice(&[0; 10]);
+        dispatch(&mut conn, OwnedFrame::new(types::DATA, flags::PADDED, 1, &payload)).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![(0, 11), (1, 11)]);
+        assert_eq!(conn.recv_window(), 65535 - 3);
+
+        // DATA that is dropped is released for the connection
+        conn.reset_stream(1, ErrorCode::Cancel);
+        dispatch(&mut conn, OwnedFrame::data(1, b"late", false)).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![(0, 4)]);
+        assert_eq!(conn.recv_window(), 65535 - 3);
+    }
+
+    // a body that keeps track of how much of it has been read
+    struct TrackedBody {
+        left: usize,
+        read: Rc<Cell<usize>>,
+    }
+
+    impl Read for TrackedBody {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = ::std::cmp::min(buf.len(), self.left);
+            for b in &mut buf[..n] {
+                *b = 0xCD;
+            }
+            self.left -= n;
+            self.read.set(self.read.get() + n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn send_body_waits_for_window() {
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+        conn.next_outbound(); // preface
+
+        let read = Rc::new(Cell::new(0));
+        let len = 200 * 1024;
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        headers.add_entry(("content-length", len.to_string()).into());
+        conn.send_headers(1, &headers, false).unwrap();
+        conn.send_body(1, Box::new(TrackedBody { left: len, read: read.clone() })).unwrap();
+
+        // only what the windows allow is read
+        let (sent, end_stream) = drain_data(&mut conn);
+        assert_eq!((sent, end_stream), (65535, false));
+        assert_eq!(read.get(), 65535);
+
+        let mut total = sent;
+        while total < len {
+            dispatch(&mut conn, OwnedFrame::window_update(0, 50000)).unwrap();
+            dispatch(&mut conn, OwnedFrame::window_update(1, 50000)).unwrap();
+            let (sent, end_stream) = drain_data(&mut conn);
+            total += sent;
+            assert_eq!(read.get(), total);
+            assert_eq!(end_stream, total == len);
+        }
+        assert_eq!(total, len);
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn ping_is_reflected() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        dispatch(&mut conn, OwnedFrame::ping(false, &data)).unwrap();
+
+        let ack = conn.next_outbound().unwrap();
+        assert_eq!(ack.frame_type(), types::PING);
+        assert_eq!(ack.frame_flags(), flags::ACK);
+        assert_eq!(ack.payload(), &data);
+        assert!(conn.next_outbound().is_none());
+    }
+
+    #[test]
+    fn ping_rtt() {
+        let clock = Arc::new(MockClock::new());
+        let mut conn = Connection::new();
+        conn.set_clock(clock.clone());
+        conn.next_outbound(); // preface
+
+        let token = conn.ping();
+        let ping = conn.next_outbound().unwrap();
+        assert_eq!(ping.frame_flags(), 0);
+
+        clock.advance(Duration::from_millis(40));
+
+        // echo it back as the peer would
+        let mut data = [0u8; 8];
+        data.copy_from_slice(ping.payload());
+        dispatch(&mut conn, OwnedFrame::ping(true, &data)).unwrap();
+
+        match conn.poll_event() {
+            Some(Event::PongReceived { token: t, rtt }) => {
+                assert_eq!(t, token);
+                assert_eq!(rtt, Duration::from_millis(40));
+            },
+            _ => panic!("expected PongReceived"),
+        }
+
+        // an unsolicited ACK is ignored
+        dispatch(&mut conn, OwnedFrame::ping(true, &data)).unwrap();
+        assert!(conn.poll_event().is_none());
+    }
+
+    #[test]
+    fn ping_errors() {
+        let mut conn = Connection::new();
+
+        let err = dispatch(&mut conn, OwnedFrame::new(types::PING, 0, 1, &[0; 8])).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::ProtocolError);
+
+        let err = dispatch(&mut conn, OwnedFrame::new(types::PING, 0, 0, &[0; 7])).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::FrameSizeError);
+    }
+
+    #[test]
+    fn frame_lengths_from_the_peer() {
+        // each of these used to read past the payload or underflow
+        let frames = vec![
+            (OwnedFrame::new(types::WINDOW_UPDATE, 0, 0, &[0, 0, 1]), ErrorCode::FrameSizeError),
+            (OwnedFrame::new(types::WINDOW_UPDATE, 0, 0, &[0, 0, 0, 1, 0]), ErrorCode::FrameSizeError),
+            (OwnedFrame::new(types::GOAWAY, 0, 0, &[0, 0, 0, 1]), ErrorCode::FrameSizeError),
+            (OwnedFrame::new(types::SETTINGS, 0, 0, &[0, 1, 0, 0]), ErrorCode::FrameSizeError),
+            (OwnedFrame::new(types::SETTINGS, flags::ACK, 0, &[0; 6]), ErrorCode::FrameSizeError),
+            (OwnedFrame::new(types::HEADERS, flags::END_HEADERS | flags::PRIORITY, 1, &[0, 0]), ErrorCode::ProtocolError),
+            (OwnedFrame::new(types::HEADERS, flags::END_HEADERS | flags::PADDED, 1, &[9, 0x82]), ErrorCode::ProtocolError),
+        ];
+        for (frame, code) in frames {
+            let mut conn = Connection::new();
+            let err = dispatch(&mut conn, frame).unwrap_err();
+            assert_eq!(err.code(), code, "{:?}", err);
+        }
+
+        // DATA with a pad length as long as the payload
+        for payload in &[&[3u8, 1, 2][..], &[4, 1, 2][..], &[255, 1, 2][..], &[][..]] {
+            let mut conn = Connection::new();
+            dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+            let err = dispatch(&mut conn, OwnedFrame::new(types::DATA, flags::PADDED, 1, payload)).unwrap_err();
+            assert_eq!(err.code(), ErrorCode::ProtocolError);
+        }
+    }
+
+    #[test]
+    fn go_away_sent() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        open_stream(&mut conn, 1);
+        assert!(conn.poll_event().is_some());
+
+        conn.go_away(ErrorCode::NoError);
+        let mut go_away = conn.next_outbound().unwrap();
+        assert_eq!(go_away.frame_type(), types::GOAWAY);
+        let info: GoAwayFrame = go_away.as_frame().into();
+        assert_eq!(info.get_go_away_info(), (1, 0, &[][..]));
+
+        // new streams are ignored
+        dispatch(&mut conn, OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(3, b"ignored", true)).unwrap();
+        assert!(conn.poll_event().is_none());
+        assert!(conn.stream(3).is_none());
+
+        // while the stream in flight completes
+        conn.send_data(1, b"done", true).unwrap();
+        assert_eq!(drain_data(&mut conn), (4, true));
+    }
+
+    #[test]
+    fn go_away_ahead_of_data() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        open_stream(&mut conn, 1);
+        assert!(conn.poll_event().is_some());
+        let mut headers = HeaderList::with_capacity(1);
+        headers.add_entry((":status", "200").into());
+        conn.send_headers(1, &headers, false).unwrap();
+        conn.send_data(1, &[0xAB; 40000], false).unwrap();
+
+        // the GOAWAY comes first, then everything stream 1 had queued
+        conn.go_away(ErrorCode::NoError);
+        let mut go_away = conn.next_outbound().unwrap();
+        assert_eq!(go_away.frame_type(), types::GOAWAY);
+        let info: GoAwayFrame = go_away.as_frame().into();
+        assert_eq!(info.get_go_away_info(), (1, 0, &[][..]));
+        assert_eq!(conn.next_outbound().unwrap().frame_type(), types::HEADERS);
+        assert_eq!(drain_data(&mut conn), (40000, false));
+
+        // and it keeps going as the window opens up
+        conn.send_data(1, &[0xAB; 40000], true).unwrap();
+        assert_eq!(drain_data(&mut conn), (65535 - 40000, false));
+        dispatch(&mut conn, OwnedFrame::window_update(0, 40000)).unwrap();
+        dispatch(&mut conn, OwnedFrame::window_update(1, 40000)).unwrap();
+        assert_eq!(drain_data(&mut conn), (40000 - (65535 - 40000), true));
+    }
+
+    #[test]
+    fn go_away_drops_streams_above() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        let mut headers = HeaderList::with_capacity(1);
+        headers.add_entry((":status", "200").into());
+        for &id in &[1, 3, 5] {
+            open_stream(&mut conn, id);
+            assert!(conn.poll_event().is_some());
+            conn.send_headers(id, &headers, false).unwrap();
+            conn.send_data(id, b"body", true).unwrap();
+        }
+        conn.ping();
+
+        // as if the GOAWAY only went up to stream 1
+        conn.drop_streams_above(1);
+        let mut frames = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            frames.push((frame.frame_type(), frame.stream_id()));
+        }
+        assert_eq!(frames, vec![(types::PING, 0), (types::HEADERS, 1), (types::DATA, 1)]);
+
+        // they are reset without an RST_STREAM
+        for &id in &[3, 5] {
+            assert!(conn.stream(id).is_none());
+            match conn.poll_event() {
+                Some(Event::StreamReset { stream_id, error: ErrorCode::RefusedStream, by_peer: false }) => assert_eq!(stream_id, id),
+                _ => panic!("expected StreamReset"),
+            }
+        }
+        assert_eq!(conn.memory_budget().used(), 0);
+    }
+
+    #[test]
+    fn report_chain() {
+        // the last GOAWAY code queued
+        fn go_away_code(conn: &mut Connection) -> Option<u32> {
+            let mut code = None;
+            while let Some(mut frame) = conn.next_outbound() {
+                if frame.frame_type() == types::GOAWAY {
+                    let go_away: GoAwayFrame = frame.as_frame().into();
+                    code = Some(go_away.get_go_away_info().1);
+                }
+            }
+            code
+        }
+
+        // a stream error under other context still only resets the stream
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        let chain = Err::<(), _>(H2Error::Stream(1, ErrorCode::Cancel))
+            .chain_err(|| io::Error::new(io::ErrorKind::Other, "handler failed")).unwrap_err();
+        conn.report_chain(&chain);
+        let mut rst = conn.next_outbound().unwrap();
+        assert_eq!(rst.frame_type(), types::RST_STREAM);
+        assert!(!conn.is_going_away());
+
+        // a Protocol kind without an H2Error picks the code
+        let mut conn = Connection::new();
+        conn.next_outbound();
+        conn.report_chain(&ErrLink::with_kind(io::Error::new(io::ErrorKind::Other, "bad block"),
+                                              Kind::Protocol(ErrorCode::CompressionError)));
+        assert_eq!(go_away_code(&mut conn), Some(ErrorCode::CompressionError as u32));
+
+        // anything else is our fault
+        let mut conn = Connection::new();
+        conn.next_outbound();
+        conn.report_chain(&io::Error::new(io::ErrorKind::Other, "oops").into());
+        assert_eq!(go_away_code(&mut conn), Some(ErrorCode::InternalError as u32));
+    }
+
+    #[test]
+    fn go_away_graceful() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        open_stream(&mut conn, 1);
+        conn.go_away_graceful();
+
+        // streams that were in flight still get processed
+        open_stream(&mut conn, 3);
+        assert!(conn.stream(3).is_some());
+
+        conn.go_away(ErrorCode::NoError);
+        open_stream(&mut conn, 5);
+        assert!(conn.stream(5).is_none());
+
+        let mut last_ids = Vec::new();
+        while let Some(mut frame) = conn.next_outbound() {
+            if frame.frame_type() == types::GOAWAY {
+                let go_away: GoAwayFrame = frame.as_frame().into();
+                last_ids.push(go_away.get_go_away_info().0);
+            }
+        }
+        assert_eq!(last_ids, vec![0x7FFFFFFF, 3]);
+    }
+
+    #[test]
+    fn go_away_received() {
+        let mut conn = Connection::new();
+
+        dispatch(&mut conn, OwnedFrame::go_away(1, ErrorCode::EnhanceYourCalm as u32, b"calm")).unwrap();
+
+        match conn.poll_event() {
+            Some(Event::GoAway { last_stream_id, error, debug }) => {
+                assert_eq!(last_stream_id, 1);
+                assert_eq!(error, ErrorCode::EnhanceYourCalm);
+                assert_eq!(debug, b"calm");
+            },
+            _ => panic!("expected GoAway"),
+        }
+        assert!(conn.is_going_away());
+    }
+
+    // the error must be a connection error of type PROTOCOL_ERROR
+    fn assert_protocol_error(res: Result<(), H2Error>) {
+        match res {
+            Err(H2Error::Connection(ErrorCode::ProtocolError, _)) => {},
+            _ => panic!("expected a connection PROTOCOL_ERROR"),
+        }
+    }
+
+    #[test]
+    fn client_push_promise() {
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        let payload = [0, 0, 0, 2, 0x82, 0x84, 0x87];
+        assert_protocol_error(dispatch(&mut conn, OwnedFrame::new(types::PUSH_PROMISE, flags::END_HEADERS, 1, &payload)));
+    }
+
+    #[test]
+    fn extension_frames() {
+        let mut sender = Connection::new();
+        let mut receiver = Connection::new();
+        receiver.set_deliver_extension_frames(true);
+        sender.next_outbound(); // preface
+        receiver.next_outbound();
+
+        // the reserved bit is masked off, and the frame goes out as it is
+        sender.send_extension_frame(0xfa, 0x1, 0x80000003, b"experimental").unwrap();
+        let frame = sender.next_outbound().unwrap();
+        assert_eq!(frame.as_bytes(), OwnedFrame::new(0xfa, 0x1, 3, b"experimental").as_bytes());
+
+        dispatch(&mut receiver, frame).unwrap();
+        match receiver.poll_event() {
+            Some(Event::ExtensionFrame { frame_type, flags, stream_id, payload }) =>
+                assert_eq!((frame_type, flags, stream_id, payload), (0xfa, 0x1, 3, b"experimental".to_vec())),
+            _ => panic!("expected the extension frame"),
+        }
+        assert!(receiver.next_outbound().is_none());
+
+        // unless asked for they are ignored, as the spec says
+        dispatch(&mut sender, OwnedFrame::new(0xfa, 0, 0, b"ping?")).unwrap();
+        assert!(sender.poll_event().is_none());
+
+        // what does not fit or is not an extension is not sent
+        let too_large = vec![0; MIN_FRAME_SIZE_LIMIT as usize + 1];
+        assert_eq!(sender.send_extension_frame(0xfa, 0, 0, &too_large), Err(ExtensionError::TooLarge(too_large.len())));
+        assert_eq!(sender.send_extension_frame(types::SETTINGS, 0, 0, &[]), Err(ExtensionError::NotAnExtension(types::SETTINGS)));
+        assert!(sender.next_outbound().is_none());
+    }
+
+    #[test]
+    fn max_concurrent_streams() {
+        let mut settings = Settings::local_default();
+        settings.max_concurrent_streams = Some(3);
+        let mut conn = Connection::with_settings(settings);
+
+        // the limit is advertised in the preface
+        let mut preface = conn.next_outbound().unwrap();
+        let params: Vec<_> = {
+            let sf: SettingsFrame = preface.as_frame().into();
+            sf.get_settings_paramaters().collect()
+        };
+        assert_eq!(params, vec![(MAX_CONCURRENT_STREAMS, 3), (MAX_HEADER_LIST_SIZE, DEFAULT_MAX_REQUEST_HEADERS as u32)]);
+
+        for id in &[1, 3, 5] {
+            open_stream(&mut conn, *id);
+        }
+        assert!(conn.next_outbound().is_none());
+
+        // the 4th is refused
+        open_stream(&mut conn, 7);
+        let mut rst = conn.next_outbound().unwrap();
+        assert_eq!(rst.frame_type(), types::RST_STREAM);
+        let rst: RstStreamFrame = rst.as_frame().into();
+        assert_eq!(rst.get_stream_id(), 7);
+        assert_eq!(rst.get_error_code(), ErrorCode::RefusedStream as u32);
+
+        // one completes so the next is accepted
+        conn.send_data(1, b"done", true).unwrap();
+        assert!(conn.stream(1).is_none());
+        drain_data(&mut conn);
+
+        open_stream(&mut conn, 9);
+        assert!(conn.next_outbound().is_none());
+        assert_eq!(conn.stream(9).unwrap().state(), StreamState::HalfClosedRemote);
+    }
+
+    #[test]
+    fn peer_max_concurrent_streams() {
+        let mut conn = Connection::new();
+        assert!(conn.can_push());
+
+        dispatch(&mut conn, OwnedFrame::settings(&[(MAX_CONCURRENT_STREAMS, 0)])).unwrap();
+        assert!(!conn.can_push());
+    }
+
+    #[test]
+    fn priority_orders_data() {
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 0)])).unwrap();
+        open_stream(&mut conn, 1);
+        open_stream(&mut conn, 3);
+        // stream 1 depends on stream 3
+        dispatch(&mut conn, OwnedFrame::new(types::PRIORITY, 0, 1, &[0, 0, 0, 3, 15])).unwrap();
+
+        conn.send_data(1, &[1; 100], true).unwrap();
+        conn.send_data(3, &[3; 100], true).unwrap();
+        while conn.next_outbound().is_some() {}
+
+        // both windows open at once
+        dispatch(&mut conn, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 100)])).unwrap();
+
+        let mut order = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            if frame.frame_type() == types::DATA {
+                order.push(frame.stream_id());
+            }
+        }
+        assert_eq!(order, vec![3, 1]);
+    }
+
+    #[test]
+    fn stall_warnings() {
+        let clock = Arc::new(MockClock::new());
+        let mut conn = Connection::new();
+        conn.set_clock(clock.clone());
+        conn.set_stall_warning(Some(Duration::from_secs(5)));
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::settings_ack()).unwrap();
+        open_stream(&mut conn, 1);
+        open_stream(&mut conn, 3);
+
+        // stream 1 takes the whole connection window, which leaves stream 3
+        // with all of its own window and nothing to use it with
+        conn.send_data(1, &vec![1; 65535], true).unwrap();
+        assert_eq!(drain_data(&mut conn), (65535, true));
+        conn.send_data(3, b"waiting", true).unwrap();
+        let windows = conn.windows(3).unwrap();
+        assert_eq!(windows.send_limit(), Some(WindowLimit::Connection));
+        assert_eq!((windows.send_stream, windows.send_conn_share), (65535, 0));
+        assert_eq!(conn.stall_deadline(), Some(clock.now() + Duration::from_secs(5)));
+
+        clock.advance(Duration::from_secs(4));
+        assert!(capture(|| conn.check_timeouts().unwrap()).is_empty());
+        clock.advance(Duration::from_secs(1));
+        let messages = capture(|| conn.check_timeouts().unwrap());
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("nothing sent for 5s with data queued, held up by the connection window (send_stream=65535 send_conn=0 "));
+        // once for each time it is held up
+        clock.advance(Duration::from_secs(10));
+        assert!(capture(|| conn.check_timeouts().unwrap()).is_empty());
+        assert_eq!(conn.stall_deadline(), None);
+
+        // a window for the connection lets it go
+        dispatch(&mut conn, OwnedFrame::window_update(0, 100)).unwrap();
+        assert_eq!(drain_data(&mut conn), (7, true));
+        assert!(conn.windows(3).is_none());
+
+        // this time it is the window of the stream
+        dispatch(&mut conn, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 0)])).unwrap();
+        open_stream(&mut conn, 5);
+        conn.send_data(5, b"waiting", true).unwrap();
+        assert_eq!(conn.windows(5).unwrap().send_limit(), Some(WindowLimit::Stream));
+        clock.advance(Duration::from_secs(5));
+        let messages = capture(|| conn.check_timeouts().unwrap());
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("held up by the stream window (send_stream=0 send_conn=93 "));
+
+        // and the windows are in the trace of the connection
+        conn.set_tracer(Some(Tracer::new(TraceSink::Log).window_snapshots(Duration::from_secs(1))));
+        let messages = capture(|| conn.write_outbound(&mut Vec::new()).unwrap());
+        assert!(messages.iter().any(|m| m.ends_with("window snapshot stream=5 send_stream=0 send_conn=93 recv_stream=65535 recv_conn=65535")));
+    }
+
+    #[test]
+    fn server_is_primed() {
+        let mut conn = Connection::new();
+        assert!(conn.encoder.is_primed("server", SERVER));
+
+        // a new server replaces the old one, none keeps neither
+        conn.set_server(Some("other".to_string()));
+        assert!(conn.encoder.is_primed("server", "other"));
+        assert!(!conn.encoder.is_primed("server", SERVER));
+        conn.set_server(None);
+        assert!(!conn.encoder.is_primed("server", "other"));
+    }
+
+    #[test]
+    fn peer_header_table_size() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let mut decoder = Decoder::new(4096, 20);
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        headers.add_entry(("x-custom", "value").into());
+
+        let mut block = |conn: &mut Connection, id: u32| {
+            open_stream(conn, id);
+            conn.send_headers(id, &headers, true).unwrap();
+            let mut frame = conn.next_outbound().unwrap();
+            let frame: HeadersFrame = frame.as_frame().into();
+            let block = frame.get_header_data().unwrap().header_block_fragment.to_vec();
+            decoder.get_header_list(&block).unwrap();
+            block
+        };
+        assert_eq!(block(&mut conn, 1)[..2], [0x88, 0x40]);
+
+        // no dynamic table, the next block says so and then only has literals
+        dispatch(&mut conn, OwnedFrame::settings(&[(HEADER_TABLE_SIZE, 0)])).unwrap();
+        assert_eq!(conn.next_outbound().unwrap().frame_flags(), flags::ACK);
+        assert_eq!(block(&mut conn, 3)[..3], [0x20, 0x88, 0x00]);
+        assert_eq!(block(&mut conn, 5)[..2], [0x88, 0x00]);
+
+        // more than the table of the encoder is as much as it has
+        dispatch(&mut conn, OwnedFrame::settings(&[(HEADER_TABLE_SIZE, 65536)])).unwrap();
+        conn.next_outbound();
+        assert_eq!(block(&mut conn, 7)[..5], [0x3F, 0xE1, 0x1F, 0x88, 0x40]);
+    }
+
+    #[test]
+    fn large_header_block_uses_continuation() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        // X is as long Huffman coded, so the value is sent as is
+        headers.add_entry(("x-large", ::std::iter::repeat("X").take(20000).collect::<String>()).into());
+        conn.send_headers(1, &headers, true).unwrap();
+
+        let first = conn.next_outbound().unwrap();
+        assert_eq!(first.frame_type(), types::HEADERS);
+        assert_eq!(first.frame_flags(), flags::END_STREAM);
+        assert_eq!(first.payload().len(), 16384);
+
+        let second = conn.next_outbound().unwrap();
+        assert_eq!(second.frame_type(), types::CONTINUATION);
+        assert_eq!(second.frame_flags(), flags::END_HEADERS);
+        assert!(conn.next_outbound().is_none());
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn padded_header_blocks() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        conn.set_padding(PaddingPolicy::Fixed(200));
+        open_stream(&mut conn, 1);
+        open_stream(&mut conn, 3);
+
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        headers.add_entry(("x-large", ::std::iter::repeat("X").take(20000).collect::<String>()).into());
+        conn.send_headers(1, &headers, true).unwrap();
+
+        // the padding is on the HEADERS, which is still no longer than a frame
+        let mut first = conn.next_outbound().unwrap();
+        assert_eq!(first.frame_flags(), flags::END_STREAM | flags::PADDED);
+        assert_eq!(first.payload().len(), 16384);
+        let mut block = {
+            let frame: HeadersFrame = first.as_frame().into();
+            let data = frame.get_header_data().unwrap();
+            assert_eq!(data.padding, Some(200));
+            data.header_block_fragment.to_vec()
+        };
+        let second = conn.next_outbound().unwrap();
+        assert_eq!(second.frame_type(), types::CONTINUATION);
+        assert_eq!(second.frame_flags(), flags::END_HEADERS);
+        block.extend_from_slice(second.payload());
+        let decoded = Decoder::new(4096, 20).get_header_list(&block).unwrap();
+        assert_eq!(decoded.get_value_by_name("x-large").map(|v| v.len()), Some(20000));
+
+        // however much is picked, a frame never goes over
+        conn.set_padding(PaddingPolicy::Random(255));
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        headers.add_entry(("x-large", ::std::iter::repeat("X").take(16370).collect::<String>()).into());
+        conn.send_headers(3, &headers, true).unwrap();
+        let mut block = Vec::new();
+        while let Some(mut frame) = conn.next_outbound() {
+            assert!(frame.payload().len() <= 16384);
+            if frame.frame_type() == types::HEADERS {
+                let frame: HeadersFrame = frame.as_frame().into();
+                block.extend_from_slice(frame.get_header_data().unwrap().header_block_fragment);
+            }
+            else {
+                block.extend_from_slice(frame.payload());
+            }
+        }
+        let decoded = Decoder::new(4096, 20).get_header_list(&block).unwrap();
+        assert_eq!(decoded.get_value_by_name("x-large").map(|v| v.len()), Some(16370));
+    }
+
+    #[test]
+    fn rapid_reset() {
+        fn open(conn: &mut Connection, stream_id: u32) -> Result<(), H2Error> {
+            dispatch(conn, OwnedFrame::headers(stream_id, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM))
+        }
+        fn open_and_reset(conn: &mut Connection, stream_id: u32) -> Result<(), H2Error> {
+            open(conn, stream_id)?;
+            dispatch(conn, OwnedFrame::rst_stream(stream_id, ErrorCode::Cancel as u32))
+        }
+        fn is_calm_error(res: Result<(), H2Error>) -> bool {
+            match res {
+                Err(H2Error::Connection(ErrorCode::EnhanceYourCalm, _)) => true,
+                _ => false,
+            }
+        }
+
+        // how many fit in the window, which slides along
+        let clock = Arc::new(MockClock::new());
+        let mut conn = Connection::new();
+        conn.set_clock(clock.clone());
+        conn.set_stream_limits(StreamLimits { max_resets: 10, max_reset_percent: 100, max_streams: None, .. StreamLimits::default() });
+        let mut ids = (1..).step_by(2);
+        for _ in 0..10 {
+            open_and_reset(&mut conn, ids.next().unwrap()).unwrap();
+        }
+        clock.advance(Duration::from_secs(10));
+        for _ in 0..10 {
+            open_and_reset(&mut conn, ids.next().unwrap()).unwrap();
+        }
+        assert!(is_calm_error(open_and_reset(&mut conn, ids.next().unwrap())));
+        assert_eq!(conn.stream_count().total_resets(), 21);
+
+        // the share of streams that are reset, once there are enough
+        let mut conn = Connection::new();
+        conn.set_stream_limits(StreamLimits { max_resets: 1000, min_resets: 20, .. StreamLimits::default() });
+        let mut ids = (1..).step_by(2);
+        for _ in 0..30 {
+            open(&mut conn, ids.next().unwrap()).unwrap();
+        }
+        for _ in 0..30 {
+            open_and_reset(&mut conn, ids.next().unwrap()).unwrap();
+        }
+        assert!(is_calm_error(open_and_reset(&mut conn, ids.next().unwrap())));
+
+        // a stream that was done before the reset came does not count
+        let mut conn = Connection::new();
+        conn.set_stream_limits(StreamLimits { max_resets: 1, .. StreamLimits::default() });
+        open(&mut conn, 1).unwrap();
+        conn.reset_stream(1, ErrorCode::InternalError);
+        dispatch(&mut conn, OwnedFrame::rst_stream(1, ErrorCode::Cancel as u32)).unwrap();
+        open_and_reset(&mut conn, 3).unwrap();
+        assert_eq!(conn.stream_count().total_resets(), 1);
+    }
+
+    #[test]
+    fn max_streams() {
+        let mut conn = Connection::new();
+        conn.set_stream_limits(StreamLimits { max_streams: Some(3), .. StreamLimits::default() });
+        conn.next_outbound(); // preface
+        for &id in &[1, 3, 5] {
+            open_stream(&mut conn, id);
+        }
+        // the last one it gets is still served
+        let go_away = conn.next_outbound().unwrap();
+        assert_eq!(go_away.frame_type(), types::GOAWAY);
+        assert_eq!(go_away.payload(), &[0, 0, 0, 5, 0, 0, 0, 0]);
+        let mut opened = 0;
+        while let Some(event) = conn.poll_event() {
+            if let Event::Headers { .. } = event {
+                opened += 1;
+            }
+        }
+        assert_eq!(opened, 3);
+
+        // and any more are ignored
+        open_stream(&mut conn, 7);
+        assert!(conn.poll_event().is_none());
+        assert_eq!(conn.stream_count().total_opened(), 3);
+    }
+
+    #[test]
+    fn settings_ack() {
+        fn is_settings_timeout(res: Result<(), H2Error>) -> bool {
+            match res {
+                Err(H2Error::Connection(ErrorCode::SettingsTimeout, _)) => true,
+                _ => false,
+            }
+        }
+
+        // each connection with a clock of its own, the time starts
+        // when the preface is written
+        let new_conn = || {
+            let clock = Arc::new(MockClock::new());
+            let mut conn = Connection::new();
+            conn.set_clock(clock.clone());
+            (conn, clock)
+        };
+
+        let (mut conn, clock) = new_conn();
+        let start = clock.now();
+        assert_eq!(conn.settings_ack_deadline(), None);
+        conn.write_outbound(&mut Vec::new()).unwrap();
+        assert_eq!(conn.settings_ack_deadline(), Some(start + Duration::from_secs(10)));
+        clock.advance(Duration::from_secs(9));
+        dispatch(&mut conn, OwnedFrame::settings_ack()).unwrap();
+        assert_eq!(conn.settings_ack_deadline(), None);
+        clock.advance(Duration::from_secs(100));
+        assert!(conn.check_timeouts().is_ok());
+
+        // never acknowledged, whether the peer goes quiet or not
+        let (mut conn, clock) = new_conn();
+        conn.next_outbound();
+        clock.advance(Duration::from_secs(10));
+        assert!(is_settings_timeout(conn.check_timeouts()));
+        assert!(is_settings_timeout(dispatch(&mut conn, OwnedFrame::ping(false, &[0; 8]))));
+
+        // two, acknowledged in order
+        let (mut conn, clock) = new_conn();
+        let start = clock.now();
+        conn.next_outbound();
+        let mut settings = Settings::local_default();
+        settings.max_concurrent_streams = Some(1);
+        conn.send_settings(settings);
+        clock.advance(Duration::from_secs(5));
+        conn.next_outbound();
+        clock.advance(Duration::from_secs(3));
+        dispatch(&mut conn, OwnedFrame::settings_ack()).unwrap();
+        // the first is in use, the second is only used once it is acknowledged
+        assert_eq!(conn.local_settings.max_concurrent_streams, Some(100));
+        assert_eq!(conn.settings_ack_deadline(), Some(start + Duration::from_secs(15)));
+        clock.advance(Duration::from_secs(6));
+        dispatch(&mut conn, OwnedFrame::settings_ack()).unwrap();
+        assert_eq!(conn.local_settings.max_concurrent_streams, Some(1));
+        clock.advance(Duration::from_secs(86));
+        assert!(conn.check_timeouts().is_ok());
+        // one too many is ignored
+        dispatch(&mut conn, OwnedFrame::settings_ack()).unwrap();
+    }
+
+    #[test]
+    fn slow_header_block() {
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let mut conn = Connection::new();
+        conn.set_clock(clock.clone());
+
+        dispatch(&mut conn, OwnedFrame::headers(1, &GET_BLOCK[..1], 0)).unwrap();
+        assert_eq!(conn.header_block_deadline(), Some(start + Duration::from_secs(10)));
+        assert!(!conn.is_idle());
+        clock.advance(Duration::from_secs(9));
+        dispatch(&mut conn, OwnedFrame::new(types::CONTINUATION, 0, 1, &GET_BLOCK[1..2])).unwrap();
+        assert!(conn.check_timeouts().is_ok());
+
+        // the next CONTINUATION is too late, or the read loop notices first
+        clock.advance(Duration::from_secs(1));
+        match dispatch(&mut conn, OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &GET_BLOCK[2..])) {
+            Err(H2Error::Connection(ErrorCode::EnhanceYourCalm, _)) => {},
+            _ => panic!("expected ENHANCE_YOUR_CALM"),
+        }
+        match conn.check_timeouts() {
+            Err(H2Error::Connection(ErrorCode::EnhanceYourCalm, _)) => {},
+            _ => panic!("expected ENHANCE_YOUR_CALM"),
+        }
+
+        // done in time, nothing left to time out
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, &GET_BLOCK[..1], 0)).unwrap();
+        dispatch(&mut conn, OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &GET_BLOCK[1..])).unwrap();
+        assert_eq!(conn.header_block_deadline(), None);
+        assert!(conn.check_timeouts().is_ok());
+    }
+
+    #[test]
+    fn request_headers_too_large() {
+        let request = |encoder: &mut Encoder, cookie_len: usize| {
+            let mut list = HeaderList::with_capacity(4);
+            list.add_entry((":method", "POST").into());
+            list.add_entry((":scheme", "https").into());
+            list.add_entry((":path", "/").into());
+            list.add_entry(("cookie", "c".repeat(cookie_len)).into());
+            encoder.encode_header_list(&list)
+        };
+        // the pseudo-header fields count for 125 and the cookie for 38 plus its value
+        let fits = 16384 - 125 - 38;
+
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let mut encoder = Encoder::new(4096, 20);
+        dispatch(&mut conn, OwnedFrame::headers(1, &request(&mut encoder, fits + 1), flags::END_HEADERS)).unwrap();
+        assert!(conn.poll_event().is_none());
+
+        let headers = conn.next_outbound().unwrap();
+        assert_eq!((headers.frame_type(), headers.frame_flags()), (types::HEADERS, flags::END_HEADERS | flags::END_STREAM));
+        let headers = Decoder::new(4096, 20).get_header_list(headers.payload()).unwrap();
+        assert_eq!(headers.get_value_by_name(":status"), Some("431"));
+        let mut rst = conn.next_outbound().unwrap();
+        let rst: RstStreamFrame = rst.as_frame().into();
+        assert_eq!((rst.get_stream_id(), rst.get_error_code()), (1, ErrorCode::NoError as u32));
+        // the rest of the body is ignored
+        dispatch(&mut conn, OwnedFrame::data(1, b"late", true)).unwrap();
+
+        // the connection carries on
+        dispatch(&mut conn, OwnedFrame::headers(3, &request(&mut encoder, fits), flags::END_HEADERS | flags::END_STREAM)).unwrap();
+        match conn.poll_event() {
+            Some(Event::Headers { stream_id: 3, .. }) => {},
+            _ => panic!("expected the request on stream 3"),
+        }
+
+        // a block that can not be decoded is still a connection error
+        match dispatch(&mut conn, OwnedFrame::headers(5, &[0xFE], flags::END_HEADERS)) {
+            Err(H2Error::Connection(ErrorCode::CompressionError, _)) => {},
+            other => panic!("expected a compression error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn header_list_size_both_ways() {
+        fn list(entries: &[(&'static str, &'static str)]) -> HeaderList {
+            let mut list = HeaderList::with_capacity(entries.len());
+            for entry in entries {
+                list.add_entry((*entry).into());
+            }
+            list
+        }
+
+        // ours is advertised, and what goes over it gets a 431
+        let mut settings = Settings::local_default();
+        settings.max_header_list_size = Some(200);
+        let mut conn = Connection::with_settings(settings);
+        let mut preface = conn.next_outbound().unwrap();
+        let sf: SettingsFrame = preface.as_frame().into();
+        assert!(sf.get_settings_paramaters().any(|p| p == (MAX_HEADER_LIST_SIZE, 200)));
+        let request = list(&[(":method", "GET"), (":scheme", "https"), (":path", "/"), ("user-agent", "a client with a rather long name, version 1.0")]);
+        assert!(request.size() > 200);
+        dispatch(&mut conn, OwnedFrame::headers(1, &Encoder::new(4096, 20).encode_header_list(&request), flags::END_HEADERS | flags::END_STREAM)).unwrap();
+        let headers = conn.next_outbound().unwrap();
+        assert_eq!(Decoder::new(4096, 20).get_header_list(headers.payload()).unwrap().get_value_by_name(":status"), Some("431"));
+
+        // the peer's is kept to, what goes over it is refused before
+        // anything is sent
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::settings(&[(MAX_HEADER_LIST_SIZE, 100)])).unwrap();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        while conn.next_outbound().is_some() {}
+        let big = list(&[(":status", "200"), ("content-type", "text/html; charset=utf-8")]);
+        assert_eq!(conn.send_headers(1, &big, false), Err(H2Error::Stream(1, ErrorCode::InternalError)));
+        assert!(conn.next_outbound().is_none());
+        let big_trailers = list(&[("grpc-message", "a status message that is far too long to fit in what the peer takes")]);
+        assert!(conn.send_trailers(1, big_trailers).is_err());
+        let pushed = list(&[(":method", "GET"), (":scheme", "https"), (":authority", "localhost"), (":path", "/style.css")]);
+        assert_eq!(conn.push_promise(1, &pushed), Err(PushError::HeadersTooLarge));
+
+        // and something smaller can still be sent on the stream
+        conn.send_headers(1, &list(&[(":status", "500")]), true).unwrap();
+        assert_eq!(conn.next_outbound().unwrap().frame_type(), types::HEADERS);
+    }
+
+    #[test]
+    fn trailers_received() {
+        // grpc-status: 0
+        let trailers = [0x00, 0x0b, b'g', b'r', b'p', b'c', b'-', b's', b't', b'a', b't', b'u', b's', 0x01, b'0'];
+
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(1, b"body", false)).unwrap();
+        dispatch(&mut conn, OwnedFrame::headers(1, &trailers, flags::END_HEADERS | flags::END_STREAM)).unwrap();
+
+        assert!(match conn.poll_event() { Some(Event::Headers { stream_id: 1, .. }) => true, _ => false });
+        assert!(match conn.poll_event() { Some(Event::Data { stream_id: 1, .. }) => true, _ => false });
+        match conn.poll_event() {
+            Some(Event::Trailers { stream_id: 1, headers }) => assert_eq!(headers.get_value_by_name("grpc-status"), Some("0")),
+            _ => panic!("expected trailers"),
+        }
+        assert_eq!(conn.stream(1).unwrap().state(), StreamState::HalfClosedRemote);
+
+        // trailers without END_STREAM
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        let err = dispatch(&mut conn, OwnedFrame::headers(1, &trailers, flags::END_HEADERS)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // or with pseudo-header fields
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        let err = dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn trailers_sent_after_data() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 10)])).unwrap();
+        conn.next_outbound(); // ACK
+        open_stream(&mut conn, 1);
+
+        let mut status = HeaderList::with_capacity(1);
+        status.add_entry((":status", "200").into());
+        conn.send_headers(1, &status, false).unwrap();
+        conn.send_data(1, &[0; 15], false).unwrap();
+
+        let mut pseudo = HeaderList::with_capacity(1);
+        pseudo.add_entry((":status", "200").into());
+        assert_eq!(conn.send_trailers(1, pseudo).unwrap_err(), H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        let mut trailers = HeaderList::with_capacity(1);
+        trailers.add_entry(("grpc-status", "0").into());
+        conn.send_trailers(1, trailers).unwrap();
+
+        let order = |conn: &mut Connection| {
+            let mut frames = Vec::new();
+            while let Some(frame) = conn.next_outbound() {
+                frames.push((frame.frame_type(), frame.frame_flags(), frame.payload().len()));
+            }
+            frames
+        };
+
+        // the trailers wait for the rest of the data
+        assert_eq!(order(&mut conn), vec![(types::HEADERS, flags::END_HEADERS, 1), (types::DATA, 0, 10)]);
+        dispatch(&mut conn, OwnedFrame::window_update(1, 100)).unwrap();
+        let frames = order(&mut conn);
+        assert_eq!(&frames[0], &(types::DATA, 0, 5));
+        assert_eq!((frames[1].0, frames[1].1), (types::HEADERS, flags::END_HEADERS | flags::END_STREAM));
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn request_content_length() {
+        fn request(lengths: &[&'static str]) -> Vec<u8> {
+            let mut list = HeaderList::with_capacity(4);
+            list.add_entry((":method", "POST").into());
+            list.add_entry((":path", "/").into());
+            list.add_entry((":scheme", "https").into());
+            for length in lengths {
+                list.add_entry(("content-length", *length).into());
+            }
+            Encoder::new(4096, 20).encode_header_list(&list)
+        }
+
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, &request(&["4"]), flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(1, b"body", true)).unwrap();
+
+        // under-delivery
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, &request(&["4"]), flags::END_HEADERS)).unwrap();
+        let err = dispatch(&mut conn, OwnedFrame::data(1, b"bod", true)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // over-delivery
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, &request(&["4"]), flags::END_HEADERS)).unwrap();
+        let err = dispatch(&mut conn, OwnedFrame::data(1, b"bodies", false)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // ending the stream with the headers
+        let mut conn = Connection::new();
+        let err = dispatch(&mut conn, OwnedFrame::headers(1, &request(&["4"]), flags::END_HEADERS | flags::END_STREAM)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // conflicting values, the stream is refused without opening it
+        let mut conn = Connection::new();
+        let err = dispatch(&mut conn, OwnedFrame::headers(1, &request(&["4", "5"]), flags::END_HEADERS)).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+        assert!(conn.stream(1).is_none());
+        assert!(conn.poll_event().is_none());
+    }
+
+    #[test]
+    fn response_content_length() {
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+
+        let mut headers = HeaderList::with_capacity(2);
+        headers.add_entry((":status", "200").into());
+        headers.add_entry(("content-length", "5").into());
+        conn.send_headers(1, &headers, false).unwrap();
+
+        assert_eq!(conn.send_data(1, b"hello!", true).unwrap_err(), H2Error::Stream(1, ErrorCode::InternalError));
+        conn.send_data(1, b"hel", false).unwrap();
+        assert_eq!(conn.send_data(1, b"l", true).unwrap_err(), H2Error::Stream(1, ErrorCode::InternalError));
+        conn.send_data(1, b"lo", true).unwrap();
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn informational_headers() {
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+
+        let status = |status: &'static str| {
+            let mut headers = HeaderList::with_capacity(1);
+            headers.add_entry((":status", status).into());
+            headers
+        };
+        // 1xx can not end the stream, and do not start the response
+        assert_eq!(conn.send_headers(1, &status("100"), true).unwrap_err(), H2Error::Stream(1, ErrorCode::InternalError));
+        conn.send_headers(1, &status("100"), false).unwrap();
+        conn.send_headers(1, &status("103"), false).unwrap();
+        assert!(!conn.stream(1).unwrap().headers_sent());
+
+        conn.send_headers(1, &status("200"), false).unwrap();
+        assert_eq!(conn.send_headers(1, &status("100"), false).unwrap_err(), H2Error::Stream(1, ErrorCode::InternalError));
+        conn.send_data(1, b"done", true).unwrap();
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn priority_flood() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let priority = |id: u32| OwnedFrame::new(types::PRIORITY, 0, id, &[0, 0, 0, 0, 15]);
+
+        // streams that never open only take up so much
+        for id in 0..10000 {
+            dispatch(&mut conn, priority(101 + 2 * id)).unwrap();
+        }
+        assert_eq!(conn.priority.node_count(), 400);
+
+        // closed ones (and the ones skipped over) are not put back at all
+        dispatch(&mut conn, OwnedFrame::headers(30001, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::rst_stream(30001, ErrorCode::Cancel as u32)).unwrap();
+        assert!(conn.stream(30001).is_none());
+        let count = conn.priority.node_count();
+        for id in 0..1000 {
+            dispatch(&mut conn, priority(1 + 2 * id)).unwrap();
+        }
+        dispatch(&mut conn, priority(30001)).unwrap();
+        assert_eq!(conn.priority.node_count(), count);
+    }
+
+    #[test]
+    fn priority_self_dependency() {
+        let mut conn = Connection::new();
+        let err = dispatch(&mut conn, OwnedFrame::new(types::PRIORITY, 0, 1, &[0, 0, 0, 1, 15])).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::ProtocolError));
+    }
+
+    #[test]
+    fn reset_stream_sent() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        open_stream(&mut conn, 1);
+        conn.reset_stream(1, ErrorCode::InternalError);
+
+        let mut rst = conn.next_outbound().unwrap();
+        let rst: RstStreamFrame = rst.as_frame().into();
+        assert_eq!(rst.get_stream_id(), 1);
+        assert_eq!(rst.get_error_code(), ErrorCode::InternalError as u32);
+
+        assert!(conn.stream(1).is_none());
+        assert!(conn.send_data(1, b"data", true).is_err());
+    }
+
+    #[test]
+    fn rst_stream_length() {
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+
+        let err = dispatch(&mut conn, OwnedFrame::new(types::RST_STREAM, 0, 1, &[0; 5])).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::FrameSizeError);
+    }
+
+    #[test]
+    fn skipped_payloads() {
+        let mut conn = Connection::new();
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        let max = conn.local_settings().max_frame_size as usize;
+
+        // DATA that fits is read, DATA over the limit only gets an error
+        assert!(!conn.skips_payload(&OwnedFrame::header(types::DATA, 0, 1, max)));
+        let header = OwnedFrame::header(types::DATA, 0, 1, max + 1);
+        assert!(conn.skips_payload(&header));
+        match conn.dispatch_skipped(&header) {
+            Err(H2Error::Stream(1, ErrorCode::FrameSizeError)) => {},
+            res => panic!("expected a stream FRAME_SIZE_ERROR, got {:?}", res),
+        }
+
+        // an extension frame nobody wants is dropped whatever its size,
+        // but one that is too big is still an error
+        while conn.poll_event().is_some() {}
+        let header = OwnedFrame::header(0xBB, 0, 0, 100);
+        assert!(conn.skips_payload(&header));
+        conn.dispatch_skipped(&header).unwrap();
+        assert!(conn.poll_event().is_none());
+        let header = OwnedFrame::header(0xBB, 0, 0, 10 << 20);
+        assert_eq!(conn.dispatch_skipped(&header).unwrap_err().code(), ErrorCode::FrameSizeError);
+
+        // unless it is wanted
+        let mut conn = Connection::new();
+        conn.set_deliver_extension_frames(true);
+        assert!(!conn.skips_payload(&OwnedFrame::header(0xBB, 0, 0, 100)));
+        assert!(conn.skips_payload(&OwnedFrame::header(0xBB, 0, 0, max + 1)));
+    }
+
+    // the streams that were sent RST_STREAM ENHANCE_YOUR_CALM
+    fn drain_calm_resets(conn: &mut Connection) -> Vec<u32> {
+        let mut reset = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            if frame.frame_type() == types::RST_STREAM && frame.payload() == [0, 0, 0, ErrorCode::EnhanceYourCalm as u8] {
+                reset.push(frame.stream_id());
+            }
+        }
+        reset
+    }
+
+    #[test]
+    fn budget_backpressure() {
+        let mut conn = Connection::new();
+        conn.set_window_updates(WindowUpdates::Immediate);
+        conn.next_outbound(); // preface
+        let budget = MemoryBudget::new(1000);
+        conn.set_memory_budget(budget.clone());
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 300], false)).unwrap();
+        assert_eq!(budget.used(), 300);
+
+        // released while the reader holds most of the budget, the
+        // peer does not get the window back yet
+        budget.charge(600);
+        conn.release_window(1, 100);
+        assert_eq!(drain_window_updates(&mut conn), vec![]);
+        assert_eq!(conn.stream(1).unwrap().recv_window(), 65535 - 300);
+
+        // until there is room again, along with what is released then
+        budget.credit(600);
+        conn.release_window(1, 200);
+        assert_eq!(drain_window_updates(&mut conn), vec![(0, 300), (1, 300)]);
+        assert_eq!(conn.stream(1).unwrap().recv_window(), 65535);
+        assert_eq!(budget.used(), 0);
+
+        // releasing more than was received gives nothing extra
+        conn.release_window(1, 100);
+        assert_eq!(drain_window_updates(&mut conn), vec![]);
+    }
+
+    #[test]
+    fn budget_eviction() {
+        let mut conn = Connection::new();
+        conn.set_window_updates(WindowUpdates::Immediate);
+        conn.next_outbound(); // preface
+        let budget = MemoryBudget::new(1000);
+        conn.set_memory_budget(budget.clone());
+        for &(stream_id, len) in &[(1, 200), (3, 400), (5, 300)] {
+            dispatch(&mut conn, OwnedFrame::headers(stream_id, GET_BLOCK, flags::END_HEADERS)).unwrap();
+            dispatch(&mut conn, OwnedFrame::data(stream_id, &vec![0; len], false)).unwrap();
+        }
+        assert_eq!(drain_calm_resets(&mut conn), vec![]);
+
+        // over the ceiling the stream holding the most is reset
+        budget.charge(100);
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 50], false)).unwrap();
+        assert_eq!(drain_calm_resets(&mut conn), vec![3]);
+        assert_eq!(budget.used(), 650);
+        let mut reset = Vec::new();
+        while let Some(event) = conn.poll_event() {
+            if let Event::StreamReset { stream_id, error: ErrorCode::EnhanceYourCalm, by_peer: false } = event {
+                reset.push(stream_id);
+            }
+        }
+        assert_eq!(reset, vec![3]);
+
+        // then the next most, and only as many as it takes
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 400], false)).unwrap();
+        assert_eq!(drain_calm_resets(&mut conn), vec![1]);
+        assert_eq!(conn.stream(5).unwrap().state(), StreamState::Open);
+
+        // what was released of a reset stream was already given back
+        conn.release_window(3, 400);
+        assert_eq!(drain_window_updates(&mut conn), vec![]);
+        budget.credit(100);
+        conn.release_window(5, 300);
+        assert_eq!(drain_window_updates(&mut conn), vec![(0, 300), (5, 300)]);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn budget_header_block() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let budget = MemoryBudget::new(150);
+        conn.set_memory_budget(budget.clone());
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 80], false)).unwrap();
+
+        // a body makes room for a header block
+        dispatch(&mut conn, OwnedFrame::headers(3, &[0; 100], 0)).unwrap();
+        assert_eq!(drain_calm_resets(&mut conn), vec![1]);
+        assert_eq!(budget.used(), 100);
+
+        // but with nothing left to reset it ends the connection
+        match dispatch(&mut conn, OwnedFrame::new(types::CONTINUATION, 0, 3, &[0; 100])) {
+            Err(H2Error::Connection(ErrorCode::EnhanceYourCalm, _)) => {},
+            other => panic!("expected ENHANCE_YOUR_CALM, got {:?}", other),
+        }
+        while conn.next_outbound().is_some() {}
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn window_update_strategies() {
+        // a 1MB body sent as fast as the windows allow and released as
+        // it comes in, giving the number of WINDOW_UPDATEs and their totals
+        fn upload(window_updates: WindowUpdates) -> (usize, u32, u32) {
+            let mut conn = Connection::new();
+            conn.set_window_updates(window_updates);
+            dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+            let (mut conn_window, mut stream_window) = (65535, 65535);
+            let (mut frames, mut conn_total, mut stream_total) = (0, 0, 0);
+            let chunk = vec![0; 16384];
+            let mut left = 1 << 20;
+            while left > 0 {
+                let n = *[16384, conn_window, stream_window, left].iter().min().unwrap();
+                assert!(n > 0, "stalled with {} left", left);
+                dispatch(&mut conn, OwnedFrame::data(1, &chunk[..n as usize], n == left)).unwrap();
+                conn.release_window(1, n as usize);
+                conn_window -= n;
+                stream_window -= n;
+                left -= n;
+                for (stream_id, increment) in drain_window_updates(&mut conn) {
+                    frames += 1;
+                    if stream_id == 0 {
+                        conn_window += increment;
+                        conn_total += increment;
+                    }
+                    else {
+                        stream_window += increment;
+                        stream_total += increment;
+                    }
+                }
+            }
+            // the peer and the connection agree on the window
+            assert_eq!(conn.recv_window(), conn_window as i32);
+            (frames, conn_total, stream_total)
+        }
+
+        // one of each for every DATA frame (but the last, on a closed stream)
+        let (frames, conn_total, stream_total) = upload(WindowUpdates::Immediate);
+        assert_eq!(frames, 64 + 63);
+        assert_eq!((conn_total, stream_total), (1 << 20, (1 << 20) - 16384));
+
+        // a pair for every other one (the same but the last)
+        let (frames, conn_total, stream_total) = upload(WindowUpdates::Threshold(50));
+        assert_eq!(frames, 32 + 31);
+        assert_eq!((conn_total, stream_total), (1 << 20, (1 << 20) - 32768));
+
+        // the windows are nearly used up each time
+        let (frames, conn_total, _) = upload(WindowUpdates::Threshold(90));
+        assert!(frames < 40);
+        assert!((1 << 20) - conn_total < 65535 * 9 / 10);
+    }
+
+    #[test]
+    fn enlarge_recv_window() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        dispatch(&mut conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 1000], false)).unwrap();
+        conn.release_window(1, 1000);
+        assert_eq!(drain_window_updates(&mut conn), vec![]);
+
+        // advertised right away, with what was released
+        conn.set_recv_window(1, 1 << 20).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![(1, (1 << 20) - 65535 + 1000)]);
+        assert_eq!(conn.stream(1).unwrap().recv_window(), 1 << 20);
+        assert!(conn.set_recv_window(3, 1 << 20).is_err());
+    }
+
+    // the peer's side of an upload on stream 1, what it can still send on
+    // the connection and the stream, and how much it sent
+    struct Uploader {
+        conn_window: u32,
+        stream_window: u32,
+        sent: u32,
+    }
+
+    impl Uploader {
+        fn new(conn: &mut Connection) -> Self {
+            dispatch(conn, OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS)).unwrap();
+            Uploader { conn_window: 65535, stream_window: 65535, sent: 0 }
+        }
+
+        // send until the windows run out (or 1MB is sent), with the
+        // handler reading (and releasing) everything as it comes in
+        fn send(&mut self, conn: &mut Connection) {
+            let chunk = [0; 16384];
+            while self.sent < 1 << 20 {
+                let n = *[16384, self.conn_window, self.stream_window, (1 << 20) - self.sent].iter().min().unwrap();
+                if n == 0 {
+                    return;
+                }
+                dispatch(conn, OwnedFrame::data(1, &chunk[..n as usize], false)).unwrap();
+                conn.release_window(1, n as usize);
+                self.conn_window -= n;
+                self.stream_window -= n;
+                self.sent += n;
+                self.window_updates(conn);
+            }
+        }
+
+        fn window_updates(&mut self, conn: &mut Connection) {
+            for (stream_id, increment) in drain_window_updates(conn) {
+                match stream_id {
+                    0 => self.conn_window += increment,
+                    1 => self.stream_window += increment,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paused_recv_window() {
+        let mut conn = Connection::new();
+        conn.set_window_updates(WindowUpdates::Immediate);
+        let mut peer = Uploader::new(&mut conn);
+        dispatch(&mut conn, OwnedFrame::data(1, &[0; 10000], false)).unwrap();
+        conn.release_window(1, 10000);
+        peer.sent = 10000;
+        peer.conn_window -= 10000;
+        peer.stream_window -= 10000;
+        peer.window_updates(&mut conn);
+
+        // the peer gets to send the window it has and no more
+        conn.pause_recv_window(1, true).unwrap();
+        peer.send(&mut conn);
+        assert_eq!(peer.sent, 10000 + 65535);
+        assert_eq!(conn.stream(1).unwrap().recv_window(), 0);
+        // the connection's window still came back, for the other streams
+        assert_eq!(peer.conn_window, 65535);
+        dispatch(&mut conn, OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS)).unwrap();
+        dispatch(&mut conn, OwnedFrame::data(3, &[0; 100], false)).unwrap();
+        conn.release_window(3, 100);
+        assert_eq!(drain_window_updates(&mut conn), vec![(0, 100), (3, 100)]);
+
+        // what was held back goes out at once
+        conn.pause_recv_window(1, false).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![(1, 65535)]);
+        peer.stream_window = 65535;
+        peer.send(&mut conn);
+        assert_eq!(peer.sent, 1 << 20);
+        assert!(conn.pause_recv_window(5, true).is_err());
+    }
+
+    #[test]
+    fn granted_recv_window() {
+        let mut conn = Connection::new();
+        conn.set_window_updates(WindowUpdates::Immediate);
+        let mut peer = Uploader::new(&mut conn);
+        conn.set_auto_release(1, false).unwrap();
+        peer.send(&mut conn);
+        assert_eq!((peer.sent, peer.stream_window), (65535, 0));
+
+        // the peer gets exactly what is granted, a step at a time
+        for step in 1..4 {
+            conn.grant_recv_window(1, 4096).unwrap();
+            peer.window_updates(&mut conn);
+            peer.send(&mut conn);
+            assert_eq!(peer.sent, 65535 + step * 4096);
+        }
+        // a grant while paused waits for the stream to be resumed
+        conn.pause_recv_window(1, true).unwrap();
+        conn.grant_recv_window(1, 1000).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![]);
+        conn.pause_recv_window(1, false).unwrap();
+        assert_eq!(drain_window_updates(&mut conn), vec![(1, 1000)]);
+        peer.stream_window += 1000;
+        peer.send(&mut conn);
+
+        // back to releasing as it is read, with the whole window
+        conn.set_auto_release(1, true).unwrap();
+        peer.window_updates(&mut conn);
+        assert_eq!(peer.stream_window, 65535);
+        peer.send(&mut conn);
+        assert_eq!(peer.sent, 1 << 20);
+    }
+}
@@ -0,0 +1,115 @@
+//! Callbacks for what happens on a connection, for tracing systems and
+//! audit logs that want more than the counters and the access log
+//!
+//! An observer is told when each stream opens and closes and when either
+//! side sends GOAWAY. It can also see a summary of every frame, which is
+//! only worked out for observers that ask for it with wants_frames.
+//!
+//! The callbacks run on the connection's thread in the middle of handling
+//! a frame, so they should be quick. An observer that panics is not
+//! called again for that connection, the connection itself carries on.
+
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use frame::FrameHeader;
+use header::HeaderList;
+use log::Context;
+use super::error::ErrorCode;
+use super::trace::Direction;
+
+/// How a stream ended, for ConnectionObserver::on_stream_close
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseInfo {
+    /// which side reset the stream and why, None if it ended normally
+    pub reset: Option<(Direction, ErrorCode)>,
+    /// the status of the response, if one was sent
+    pub status: Option<u16>,
+    /// bytes of request body received
+    pub request_bytes: u64,
+    /// bytes of response body sent
+    pub response_bytes: u64,
+    /// bytes of the response's header frames, and of its body before
+    /// it was compressed (see transfer::Transfer)
+    pub response_header_bytes: u64,
+    pub response_body_bytes: u64,
+}
+
+/// The header of a frame, for ConnectionObserver::on_frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSummary {
+    pub frame_type: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub length: usize,
+}
+
+impl FrameSummary {
+    // from the 9 bytes of a frame header
+    pub(crate) fn from_header(header: &[u8]) -> Self {
+        FrameSummary {
+            frame_type: FrameHeader::frame_type(header),
+            flags: FrameHeader::flags(header),
+            stream_id: FrameHeader::stream_id(header),
+            length: FrameHeader::length(header) as usize,
+        }
+    }
+}
+
+/// What gets called as things happen on a connection, every method does
+/// nothing unless it is implemented
+pub trait ConnectionObserver: Send + Sync {
+    /// the peer opened a stream with a request
+    fn on_stream_open(&self, _stream_id: u32, _headers: &HeaderList) {}
+
+    /// a stream is done, or the connection ended while it was open (which
+    /// counts as a reset with CANCEL)
+    fn on_stream_close(&self, _stream_id: u32, _info: CloseInfo) {}
+
+    /// a GOAWAY was sent or received
+    fn on_goaway(&self, _dir: Direction, _last_stream_id: u32, _error: ErrorCode) {}
+
+    /// whether on_frame is called, asked once when the observer is set
+    fn wants_frames(&self) -> bool {
+        false
+    }
+
+    /// a frame was received or is being sent
+    fn on_frame(&self, _dir: Direction, _frame: FrameSummary) {}
+}
+
+// the observer of one connection, let go of once it panics
+pub(crate) struct Observed {
+    observer: Option<Arc<ConnectionObserver>>,
+    frames: bool,
+}
+
+impl Observed {
+
+    pub fn new(observer: Option<Arc<ConnectionObserver>>) -> Self {
+        let frames = observer.as_ref().map_or(false, |o| o.wants_frames());
+        Observed { observer: observer, frames: frames }
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.observer.is_some()
+    }
+
+    /// is on_frame wanted, so frames need a summary
+    pub fn frames(&self) -> bool {
+        self.frames
+    }
+
+    pub fn call<F>(&mut self, peer: Option<SocketAddr>, f: F) where F: FnOnce(&ConnectionObserver) {
+        let panicked = match self.observer {
+            Some(ref observer) => panic::catch_unwind(AssertUnwindSafe(|| f(&**observer))).is_err(),
+            None => return,
+        };
+        if panicked {
+            klog_warn!(Context::peer(peer) => "the connection observer panicked, it is not called again");
+            self.observer = None;
+            self.frames = false;
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! 5.3 Stream Priority
+//!
+//! Each stream can be given an explicit dependency on another stream.
+//! Including a dependency expresses a preference to allocate resources
+//! to the identified stream rather than to the dependent stream. Streams
+//! that depend on the same parent are allocated resources in proportion
+//! to their weight.
+//!
+//! The tree here decides which stream gets to send the next DATA frame.
+//! A stream that is ready to send goes before its dependents, and among
+//! siblings the one that has received the least (relative to its weight)
+//! goes next.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::error::{ErrorCode, H2Error};
+
+/// weight given to streams that do not specify one (as sent on the wire, 16 - 1)
+pub const DEFAULT_WEIGHT : u8 = 15;
+
+// the cost of sending one byte for a stream with a weight of 1,
+// streams with higher weights are charged proportionally less
+const STRIDE : u64 = 1 << 16;
+
+#[derive(Debug)]
+struct Node {
+    parent: u32,
+    // actual weight 1-256 (one more than what is sent on the wire)
+    weight: u32,
+    children: Vec<u32>,
+    // how much this stream has been given relative to its siblings
+    pass: u64,
+}
+
+impl Node {
+    fn new(parent: u32, weight: u32, pass: u64) -> Self {
+        Node { parent: parent, weight: weight, children: Vec::new(), pass: pass }
+    }
+}
+
+/// The dependency tree of all streams, with stream 0 as the root
+#[derive(Debug)]
+pub struct PriorityTree {
+    nodes: HashMap<u32, Node>,
+    // the nodes that only a PRIORITY frame put in the tree (oldest first),
+    // for streams that are not open (yet)
+    idle: VecDeque<u32>,
+}
+
+impl PriorityTree {
+
+    pub fn new() -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(0, Node::new(0, DEFAULT_WEIGHT as u32 + 1, 0));
+        PriorityTree { nodes: nodes, idle: VecDeque::new() }
+    }
+
+    /// how many streams are in the tree, open or not
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    pub fn contains(&self, stream_id: u32) -> bool {
+        stream_id != 0 && self.nodes.contains_key(&stream_id)
+    }
+
+    pub fn parent(&self, stream_id: u32) -> Option<u32> {
+        if stream_id == 0 {
+            return None;
+        }
+        self.nodes.get(&stream_id).map(|n| n.parent)
+    }
+
+    /// the weight as it is sent on the wire (actual weight - 1)
+    pub fn weight(&self, stream_id: u32) -> Option<u8> {
+        self.nodes.get(&stream_id).map(|n| (n.weight - 1) as u8)
+    }
+
+    /// add a stream to the tree, or reprioritize it if it is already there
+    ///
+    /// 5.3.1 A stream cannot depend on itself. An endpoint MUST treat this
+    /// as a stream error of type PROTOCOL_ERROR. A dependency on a stream
+    /// that is not in the tree results in that stream being given a
+    /// default priority.
+    pub fn insert(&mut self, stream_id: u32, dependency: u32, weight: u8, exclusive: bool) -> Result<(), H2Error> {
+        // the stream is open now, it stays until it is removed
+        self.idle.retain(|&id| id != stream_id);
+        self.insert_node(stream_id, dependency, weight, exclusive)
+    }
+
+    /// insert for a stream that is not open, from a PRIORITY frame
+    ///
+    /// 5.3.4 a peer can give any number of streams a priority without
+    /// opening them, so only max_idle of them are kept. Past that the
+    /// oldest one is removed from the tree (and its dependents move up).
+    pub fn insert_idle(&mut self, stream_id: u32, dependency: u32, weight: u8, exclusive: bool, max_idle: usize) -> Result<(), H2Error> {
+        let new = !self.nodes.contains_key(&stream_id);
+        self.insert_node(stream_id, dependency, weight, exclusive)?;
+        if new {
+            self.idle.push_back(stream_id);
+        }
+        while self.idle.len() > max_idle {
+            let oldest = self.idle.pop_front().unwrap();
+            self.remove(oldest);
+        }
+        Ok(())
+    }
+
+    fn insert_node(&mut self, stream_id: u32, dependency: u32, weight: u8, exclusive: bool) -> Result<(), H2Error> {
+        if stream_id == dependency {
+            return Err(H2Error::Stream(stream_id, ErrorCode::ProtocolError));
+        }
+
+        let (dependency, weight, exclusive) = match self.nodes.contains_key(&dependency) {
+            true => (dependency, weight as u32 + 1, exclusive),
+            false => (0, DEFAULT_WEIGHT as u32 + 1, false),
+        };
+
+        if self.nodes.contains_key(&stream_id) {
+            // 5.3.3 if a stream is made dependent on one of its own dependencies,
+            // the formerly dependent stream is first moved to be dependent on
+            // the reprioritized stream's previous parent
+            if self.is_ancestor(stream_id, dependency) {
+                let old_parent = self.nodes[&stream_id].parent;
+                self.detach(dependency);
+                self.attach(dependency, old_parent, false);
+            }
+            self.detach(stream_id);
+            self.nodes.get_mut(&stream_id).unwrap().weight = weight;
+        }
+        else {
+            self.nodes.insert(stream_id, Node::new(dependency, weight, 0));
+        }
+
+        self.attach(stream_id, dependency, exclusive);
+        Ok(())
+    }
+
+    /// 5.3.4 When a stream is removed from the dependency tree, its
+    /// dependencies can be moved to become dependent on the parent of the
+    /// closed stream. The weights of new dependencies are recalculated by
+    /// distributing the weight of the dependency of the closed stream
+    /// proportionally based on the weights of its dependencies.
+    pub fn remove(&mut self, stream_id: u32) {
+        if !self.contains(stream_id) {
+            return;
+        }
+        self.idle.retain(|&id| id != stream_id);
+        self.detach(stream_id);
+        let node = self.nodes.remove(&stream_id).unwrap();
+
+        let total: u32 = node.children.iter().map(|c| self.nodes[c].weight).sum();
+        for child in node.children {
+            {
+                let c = self.nodes.get_mut(&child).unwrap();
+                c.weight = ::std::cmp::max(1, node.weight * c.weight / total);
+            }
+            self.attach(child, node.parent, false);
+        }
+    }
+
+    /// pick the stream that should send next out of the ones that are ready
+    pub fn next_ready(&self, ready: &HashSet<u32>) -> Option<u32> {
+        let mut parent = 0;
+        loop {
+            let next = self.nodes[&parent].children.iter()
+                .filter(|c| self.subtree_ready(**c, ready))
+                .min_by_key(|c| (self.nodes[*c].pass, **c))
+                .cloned();
+
+            match next {
+                Some(id) if ready.contains(&id) => return Some(id),
+                Some(id) => parent = id,
+                None => return None,
+            }
+        }
+    }
+
+    /// account for len bytes being sent on a stream
+    ///
+    /// every stream on the path to the root is charged since the
+    /// bytes were given to its part of the tree
+    pub fn record_sent(&mut self, stream_id: u32, len: usize) {
+        let cost = ::std::cmp::max(len, 1) as u64 * STRIDE;
+        let mut id = stream_id;
+        while id != 0 {
+            let node = match self.nodes.get_mut(&id) {
+                Some(node) => node,
+                None => return,
+            };
+            node.pass += cost / node.weight as u64;
+            id = node.parent;
+        }
+    }
+
+    //=========================================
+    // helpers
+    //=========================================
+
+    // is ancestor somewhere above stream_id in the tree
+    fn is_ancestor(&self, ancestor: u32, stream_id: u32) -> bool {
+        let mut id = stream_id;
+        while id != 0 {
+            id = self.nodes[&id].parent;
+            if id == ancestor {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn subtree_ready(&self, stream_id: u32, ready: &HashSet<u32>) -> bool {
+        ready.contains(&stream_id)
+            || self.nodes[&stream_id].children.iter().any(|c| self.subtree_ready(*c, ready))
+    }
+
+    // take a stream out of its parent's children
+    fn detach(&mut self, stream_id: u32) {
+        let parent = self.nodes[&stream_id].parent;
+        self.nodes.get_mut(&parent).unwrap().children.retain(|c| *c != stream_id);
+    }
+
+    // make a stream a child of parent, with exclusive it
+    // becomes the only child and takes the other children
+    fn attach(&mut self, stream_id: u32, parent: u32, exclusive: bool) {
+        // start level with the siblings so the stream neither
+        // starves them nor gets starved
+        let pass = self.nodes[&parent].children.iter().map(|c| self.nodes[c].pass).min().unwrap_or(0);
+
+        let adopted = match exclusive {
+            true => ::std::mem::replace(&mut self.nodes.get_mut(&parent).unwrap().children, Vec::new()),
+            false => Vec::new(),
+        };
+        for child in &adopted {
+            self.nodes.get_mut(child).unwrap().parent = stream_id;
+        }
+
+        {
+            let node = self.nodes.get_mut(&stream_id).unwrap();
+            node.parent = parent;
+            node.pass = pass;
+            node.children.extend(adopted);
+        }
+        self.nodes.get_mut(&parent).unwrap().children.push(stream_id);
+    }
+}
+
+#[cfg(test)]
+mod priority_tests {
+
+    use std::collections::HashSet;
+
+    use super::{PriorityTree, DEFAULT_WEIGHT};
+    use connection::error::{ErrorCode, H2Error};
+
+    #[test]
+    fn weighted_siblings() {
+        let mut tree = PriorityTree::new();
+        tree.insert(1, 0, 254, false).unwrap(); // weight 255
+        tree.insert(3, 0, 0, false).unwrap(); // weight 1
+
+        let ready: HashSet<u32> = [1, 3].iter().cloned().collect();
+        let mut counts = [0, 0];
+        for _ in 0..256 {
+            let id = tree.next_ready(&ready).unwrap();
+            tree.record_sent(id, 1024);
+            counts[(id / 2) as usize] += 1;
+        }
+
+        assert_eq!(counts, [255, 1]);
+    }
+
+    #[test]
+    fn parent_goes_first() {
+        let mut tree = PriorityTree::new();
+        tree.insert(1, 0, DEFAULT_WEIGHT, false).unwrap();
+        tree.insert(3, 1, DEFAULT_WEIGHT, false).unwrap();
+
+        let mut ready: HashSet<u32> = [1, 3].iter().cloned().collect();
+        assert_eq!(tree.next_ready(&ready), Some(1));
+        ready.remove(&1);
+        assert_eq!(tree.next_ready(&ready), Some(3));
+        ready.remove(&3);
+        assert_eq!(tree.next_ready(&ready), None);
+    }
+
+    #[test]
+    fn exclusive_reparenting() {
+        let mut tree = PriorityTree::new();
+        tree.insert(1, 0, DEFAULT_WEIGHT, false).unwrap();
+        tree.insert(3, 1, DEFAULT_WEIGHT, false).unwrap();
+        tree.insert(5, 1, DEFAULT_WEIGHT, false).unwrap();
+
+        // 7 takes over the children of 1
+        tree.insert(7, 1, DEFAULT_WEIGHT, true).unwrap();
+        assert_eq!(tree.parent(7), Some(1));
+        assert_eq!(tree.parent(3), Some(7));
+        assert_eq!(tree.parent(5), Some(7));
+
+        // moving 1 under its own dependent moves the dependent up first
+        tree.insert(1, 3, DEFAULT_WEIGHT, false).unwrap();
+        assert_eq!(tree.parent(3), Some(0));
+        assert_eq!(tree.parent(1), Some(3));
+        assert_eq!(tree.parent(7), Some(1));
+    }
+
+    #[test]
+    fn remove_passes_children_to_parent() {
+        let mut tree = PriorityTree::new();
+        tree.insert(1, 0, 31, false).unwrap(); // weight 32
+        tree.insert(3, 1, 0, false).unwrap(); // weight 1
+        tree.insert(5, 1, 2, false).unwrap(); // weight 3
+
+        tree.remove(1);
+        assert!(!tree.contains(1));
+        assert_eq!(tree.parent(3), Some(0));
+        assert_eq!(tree.parent(5), Some(0));
+        assert_eq!(tree.weight(3), Some(8 - 1));
+        assert_eq!(tree.weight(5), Some(24 - 1));
+    }
+
+    #[test]
+    fn self_dependency() {
+        let mut tree = PriorityTree::new();
+        assert_eq!(tree.insert(1, 1, DEFAULT_WEIGHT, false), Err(H2Error::Stream(1, ErrorCode::ProtocolError)));
+    }
+
+    #[test]
+    fn idle_streams_kept() {
+        let mut tree = PriorityTree::new();
+        tree.insert(1, 0, DEFAULT_WEIGHT, false).unwrap();
+        for id in 0..10 {
+            tree.insert_idle(3 + 2 * id, 0, DEFAULT_WEIGHT, false, 4).unwrap();
+        }
+        // the open stream and the last four
+        assert_eq!(tree.node_count(), 5);
+        assert!(tree.contains(1));
+        assert!(!tree.contains(13));
+        assert!(tree.contains(15) && tree.contains(21));
+
+        // once opened a stream is no longer one of them, and the children
+        // of one that goes move up
+        tree.insert(15, 0, DEFAULT_WEIGHT, false).unwrap();
+        tree.insert(23, 17, DEFAULT_WEIGHT, false).unwrap();
+        tree.insert_idle(25, 0, DEFAULT_WEIGHT, false, 2).unwrap();
+        assert!(tree.contains(15) && tree.contains(23) && tree.contains(25));
+        assert!(!tree.contains(17) && !tree.contains(19));
+        assert_eq!(tree.parent(23), Some(0));
+        assert_eq!(tree.node_count(), 5);
+    }
+
+    #[test]
+    fn unknown_dependency() {
+        let mut tree = PriorityTree::new();
+        tree.insert(3, 1, 100, true).unwrap();
+        assert_eq!(tree.parent(3), Some(0));
+        assert_eq!(tree.weight(3), Some(DEFAULT_WEIGHT));
+    }
+}
@@ -0,0 +1,532 @@
+//! Reading whole frames off of a byte stream
+//!
+//! Reads from a socket do not line up with frames. The reader keeps
+//! whatever was read past the end of the current frame for the next
+//! one, and also serves the bytes before the first frame (the preface,
+//! or an HTTP/1.1 request) to the handshake.
+//!
+//! Interrupted reads are always tried again. What WouldBlock means
+//! depends on the ReadMode, either way a frame that was partly read when
+//! it happened is still there for the next read_frame.
+//!
+//! The bytes are kept in a ring buffer, a frame is handed out right
+//! where it is unless it wrapped around the end of the ring, then (or if
+//! it is too big for the ring at all) it is put together in a scratch
+//! buffer instead.
+//!
+//! A socket with a read timeout says WouldBlock (or TimedOut) when the
+//! timeout is up. In blocking mode the reader can be given a deadline,
+//! reads are tried again until it passes and then fail with TimedOut.
+//!
+//! A frame that is going to be dropped or rejected whatever its payload
+//! says (one that is too big, or of a type nobody wants) does not have to
+//! be read in at all. read_frame_or_skip asks about each frame once its
+//! header is in, and the payload of one that is skipped goes through the
+//! ring and is thrown away, so however long the peer claims it is nothing
+//! more is allocated for it.
+
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::Instant;
+
+use buf::{Buf, RingBuffer};
+use frame::frame_types::GenericFrame;
+use util::{Clock, SystemClock};
+use super::budget::MemoryBudget;
+
+// size of the frame header
+const HEADER_LEN : usize = 9;
+
+// room for a few frames of the default max size
+// (and a size class of the buffer pool)
+const RING_CAPACITY : usize = 1 << 16;
+
+/// What to do when a read from the stream says it would block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// the stream is meant to block, so just try again (a TLS stream
+    /// can say it would block while it renegotiates)
+    Blocking,
+    /// pass the WouldBlock on, the caller reads again when it wants to
+    NonBlocking,
+}
+
+/// What read_frame_or_skip read
+pub enum ReadFrame<'a> {
+    /// a whole frame
+    Frame(GenericFrame<'a>),
+    /// the header of a frame whose payload was thrown away
+    Skipped([u8; HEADER_LEN]),
+}
+
+pub struct FrameReader {
+    // bytes read from the stream that have not been consumed
+    buf: RingBuffer,
+    // a frame that wrapped, or one that is being put together because
+    // it does not fit in the ring
+    scratch: Vec<u8>,
+    // the size of the frame being put together in scratch (0 if none)
+    assembling: usize,
+    // the header of the frame being skipped, and how much of its payload
+    // is still to be thrown away
+    skipping: Option<([u8; HEADER_LEN], usize)>,
+    // size of the frame handed out by the last read_frame, if it was
+    // in the ring (consumed at the start of the next call)
+    last_frame: usize,
+    mode: ReadMode,
+    // when blocking reads stop being tried again, by the clock
+    deadline: Option<Instant>,
+    clock: Arc<Clock>,
+    // what the buffers are charged to, and how much is charged
+    budget: Option<MemoryBudget>,
+    charged: usize,
+}
+
+impl FrameReader {
+
+    pub fn new() -> Self {
+        FrameReader::with_capacity(RING_CAPACITY)
+    }
+
+    /// a reader with a ring of the given size, frames bigger than
+    /// that are still read but always by way of a copy
+    pub fn with_capacity(capacity: usize) -> Self {
+        FrameReader {
+            buf: RingBuffer::new(capacity),
+            scratch: Vec::new(),
+            assembling: 0,
+            skipping: None,
+            last_frame: 0,
+            mode: ReadMode::Blocking,
+            deadline: None,
+            clock: SystemClock::shared(),
+            budget: None,
+            charged: 0,
+        }
+    }
+
+    /// charge the reader's buffers to budget (the ring, and the scratch
+    /// buffer as of the last read_frame)
+    pub fn set_budget(&mut self, budget: Option<MemoryBudget>) {
+        if let Some(ref old) = self.budget {
+            old.credit(self.charged);
+        }
+        self.charged = 0;
+        self.budget = budget;
+        self.charge_budget();
+    }
+
+    // bring the charge up to date with what the buffers hold
+    fn charge_budget(&mut self) {
+        if let Some(ref budget) = self.budget {
+            let size = self.buf.capacity() + self.scratch.capacity();
+            if size > self.charged {
+                budget.charge(size - self.charged);
+            }
+            else {
+                budget.credit(self.charged - size);
+            }
+            self.charged = size;
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: ReadMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> ReadMode {
+        self.mode
+    }
+
+    /// give up on a read in blocking mode once the deadline has passed
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// the clock the deadline is checked against
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = clock;
+    }
+
+    /// the bytes that have been read but not consumed yet
+    /// (only for before the first frame)
+    pub fn buffered(&mut self) -> &[u8] {
+        debug_assert!(self.assembling == 0);
+        let (first, second) = self.buf.peek_split();
+        if second.is_empty() {
+            return first;
+        }
+        self.scratch.clear();
+        self.scratch.extend_from_slice(first);
+        self.scratch.extend_from_slice(second);
+        &self.scratch
+    }
+
+    /// mark n of the buffered bytes as used
+    pub fn consume(&mut self, n: usize) {
+        self.buf.consume(n);
+    }
+
+    /// do one read from the stream, returning how many bytes
+    /// were added to the buffer (0 at the end of the stream)
+    pub fn fill<R: Read>(&mut self, stream: &mut R) -> io::Result<usize> {
+        loop {
+            match self.buf.write_from(stream) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if timed_out(e) && self.mode == ReadMode::Blocking => match self.deadline {
+                    Some(deadline) if self.clock.now() >= deadline => {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+                    },
+                    _ => continue,
+                },
+                res => return res,
+            }
+        }
+    }
+
+    /// read until at least n bytes are buffered,
+    /// false if the stream ended before that
+    pub fn fill_to<R: Read>(&mut self, stream: &mut R, n: usize) -> io::Result<bool> {
+        while self.buf.len() < n {
+            if self.fill(stream)? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// read the next complete frame from the stream
+    ///
+    /// None means the stream ended cleanly between frames, ending
+    /// in the middle of one is an UnexpectedEof error. In non-blocking
+    /// mode a WouldBlock error means the frame is not all there yet.
+    pub fn read_frame<'a, R: Read>(&'a mut self, stream: &mut R) -> io::Result<Option<GenericFrame<'a>>> {
+        match self.read_frame_or_skip(stream, |_| false)? {
+            Some(ReadFrame::Frame(frame)) => Ok(Some(frame)),
+            Some(ReadFrame::Skipped(_)) => unreachable!("nothing is skipped"),
+            None => Ok(None),
+        }
+    }
+
+    /// read the next frame as with read_frame, unless skip says (given its
+    /// header) that its payload is not needed, then the payload is read
+    /// and thrown away a bit at a time and only the header is handed out
+    ///
+    /// skip is only asked once for each frame, a skip that is cut short by
+    /// a WouldBlock carries on where it was left with the next call.
+    pub fn read_frame_or_skip<'a, R, F>(&'a mut self, stream: &mut R, skip: F) -> io::Result<Option<ReadFrame<'a>>>
+        where R: Read, F: FnOnce(&[u8]) -> bool {
+
+        let last_frame = self.last_frame;
+        self.consume(last_frame);
+        self.last_frame = 0;
+        self.charge_budget();
+
+        if self.assembling == 0 && self.skipping.is_none() {
+            if !self.fill_to(stream, HEADER_LEN)? {
+                return match self.buf.len() {
+                    0 => Ok(None),
+                    _ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in a frame header")),
+                };
+            }
+
+            let mut header = [0; HEADER_LEN];
+            self.buf.copy_to(&mut header);
+            let length = (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize;
+            let frame_len = HEADER_LEN + length;
+
+            if skip(&header) {
+                self.buf.consume(HEADER_LEN);
+                self.skipping = Some((header, length));
+            }
+            else if frame_len > self.buf.capacity() {
+                self.assembling = frame_len;
+                self.scratch.clear();
+            }
+            else {
+                if !self.fill_to(stream, frame_len)? {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in a frame payload"));
+                }
+                if self.buf.peek_contiguous().len() >= frame_len {
+                    self.last_frame = frame_len;
+                    return Ok(Some(ReadFrame::Frame(GenericFrame::point_to(&mut self.buf.peek_contiguous_mut()[..frame_len]))));
+                }
+                // wrapped around the end of the ring
+                self.scratch.clear();
+                {
+                    let (first, second) = self.buf.peek_split();
+                    self.scratch.extend_from_slice(first);
+                    self.scratch.extend_from_slice(&second[..frame_len - first.len()]);
+                }
+                self.buf.consume(frame_len);
+                return Ok(Some(ReadFrame::Frame(GenericFrame::point_to(&mut self.scratch[..]))));
+            }
+        }
+
+        if let Some((header, mut left)) = self.skipping {
+            loop {
+                let n = ::std::cmp::min(self.buf.len(), left);
+                self.buf.consume(n);
+                left -= n;
+                self.skipping = Some((header, left));
+                if left == 0 {
+                    break;
+                }
+                if self.fill(stream)? == 0 {
+                    self.skipping = None;
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in a frame payload"));
+                }
+            }
+            self.skipping = None;
+            return Ok(Some(ReadFrame::Skipped(header)));
+        }
+
+        // too big for the ring, moved to scratch as it comes in (and
+        // picked up where it was left after a WouldBlock)
+        loop {
+            let n = ::std::cmp::min(self.buf.len(), self.assembling - self.scratch.len());
+            {
+                let (first, second) = self.buf.peek_split();
+                let m = ::std::cmp::min(first.len(), n);
+                self.scratch.extend_from_slice(&first[..m]);
+                self.scratch.extend_from_slice(&second[..n - m]);
+            }
+            self.buf.consume(n);
+            if self.scratch.len() == self.assembling {
+                break;
+            }
+            if self.fill(stream)? == 0 {
+                self.assembling = 0;
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended in a frame payload"));
+            }
+        }
+        self.assembling = 0;
+        Ok(Some(ReadFrame::Frame(GenericFrame::point_to(&mut self.scratch[..]))))
+    }
+}
+
+impl Drop for FrameReader {
+    fn drop(&mut self) {
+        self.set_budget(None);
+    }
+}
+
+// what a read that ran into the socket's read timeout fails with
+// (WouldBlock on unix, TimedOut on windows)
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod reader_tests {
+
+    use std::io::{self, Cursor, Read};
+
+    use super::{FrameReader, ReadFrame, ReadMode};
+    use connection::budget::MemoryBudget;
+    use connection::mock::FlakyStream;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::types;
+
+    // hands out the input a few bytes at a time
+    struct Trickle {
+        input: Vec<u8>,
+        pos: usize,
+        step: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = ::std::cmp::min(::std::cmp::min(self.step, buf.len()), self.input.len() - self.pos);
+            buf[..n].copy_from_slice(&self.input[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn frames_split_over_reads() {
+        let mut input = Vec::new();
+        input.extend_from_slice(OwnedFrame::ping(false, &[1; 8]).as_bytes());
+        input.extend_from_slice(OwnedFrame::data(1, &[7; 5000], true).as_bytes());
+        input.extend_from_slice(OwnedFrame::settings_ack().as_bytes());
+        let mut stream = Trickle { input: input, pos: 0, step: 3 };
+
+        let mut reader = FrameReader::new();
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::PING);
+        {
+            let frame = reader.read_frame(&mut stream).unwrap().unwrap();
+            assert_eq!(frame.get_type(), types::DATA);
+            assert_eq!(frame.get_length(), 5000);
+        }
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::SETTINGS);
+        assert!(reader.read_frame(&mut stream).unwrap().is_none());
+    }
+
+    fn flaky_input() -> Vec<u8> {
+        let mut input = Vec::new();
+        input.extend_from_slice(OwnedFrame::ping(false, &[1; 8]).as_bytes());
+        input.extend_from_slice(OwnedFrame::data(1, &[7; 100], true).as_bytes());
+        input
+    }
+
+    #[test]
+    fn interrupted_and_would_block() {
+        use std::io::ErrorKind::{Interrupted, WouldBlock};
+
+        // errors before anything, in a frame header and in a payload
+        let script = vec![Err(Interrupted), Err(WouldBlock), Ok(4), Err(Interrupted), Ok(13),
+                          Err(WouldBlock), Ok(10), Err(Interrupted), Err(WouldBlock)];
+        let mut stream = FlakyStream::new(flaky_input(), script.clone());
+        let mut reader = FrameReader::new();
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::PING);
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().payload(), &[7; 100][..]);
+        assert!(reader.read_frame(&mut stream).unwrap().is_none());
+
+        // without blocking each WouldBlock comes out, and the frame is
+        // picked up where it was left
+        let mut stream = FlakyStream::new(flaky_input(), script);
+        let mut reader = FrameReader::new();
+        reader.set_mode(ReadMode::NonBlocking);
+        let mut frames = Vec::new();
+        let mut would_block = 0;
+        loop {
+            match reader.read_frame(&mut stream) {
+                Ok(Some(frame)) => frames.push((frame.get_type(), frame.get_length())),
+                Ok(None) => break,
+                Err(ref e) if e.kind() == WouldBlock => would_block += 1,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(frames, vec![(types::PING, 8), (types::DATA, 100)]);
+        assert_eq!(would_block, 3);
+    }
+
+    #[test]
+    fn frames_around_the_ring() {
+        // frames of every size up to twice the ring, trickled in at odd
+        // steps so they land all over it (some wrapped, some too big)
+        let mut input = Vec::new();
+        let mut sizes = Vec::new();
+        for i in 0..300 {
+            let size = (i * 37) % 200;
+            let payload: Vec<u8> = (0..size).map(|b| (b + i) as u8).collect();
+            input.extend_from_slice(OwnedFrame::data(1, &payload, false).as_bytes());
+            sizes.push(size);
+        }
+        for &step in &[1, 7, 64, 1000] {
+            let mut stream = Trickle { input: input.clone(), pos: 0, step: step };
+            let mut reader = FrameReader::with_capacity(100);
+            for (i, &size) in sizes.iter().enumerate() {
+                let frame = reader.read_frame(&mut stream).unwrap().unwrap();
+                assert_eq!(frame.get_length() as usize, size);
+                let payload: Vec<u8> = (0..size).map(|b| (b + i) as u8).collect();
+                assert_eq!(frame.payload(), &payload[..], "frame {} with step {}", i, step);
+            }
+            assert!(reader.read_frame(&mut stream).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn frame_bigger_than_the_ring() {
+        use std::io::ErrorKind::WouldBlock;
+
+        let mut input = Vec::new();
+        input.extend_from_slice(OwnedFrame::data(1, &[7; 100], false).as_bytes());
+        input.extend_from_slice(OwnedFrame::ping(false, &[1; 8]).as_bytes());
+        let script = vec![Ok(30), Err(WouldBlock), Ok(30), Err(WouldBlock), Ok(200)];
+        let mut stream = FlakyStream::new(input, script);
+        let mut reader = FrameReader::with_capacity(32);
+        reader.set_mode(ReadMode::NonBlocking);
+
+        // put together over the WouldBlocks
+        assert_eq!(reader.read_frame(&mut stream).unwrap_err().kind(), WouldBlock);
+        assert_eq!(reader.read_frame(&mut stream).unwrap_err().kind(), WouldBlock);
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().payload(), &[7; 100][..]);
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::PING);
+        assert!(reader.read_frame(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let frame = OwnedFrame::data(1, &[7; 100], true);
+        let mut stream = Trickle { input: frame.as_bytes()[..50].to_vec(), pos: 0, step: 1000 };
+
+        let mut reader = FrameReader::new();
+        let err = reader.read_frame(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn charged_to_budget() {
+        let budget = MemoryBudget::new(1 << 20);
+        let mut reader = FrameReader::with_capacity(32);
+        reader.set_budget(Some(budget.clone()));
+        assert_eq!(budget.used(), 32);
+
+        // the scratch buffer a big frame was put together in counts too
+        let mut input = Vec::new();
+        input.extend_from_slice(OwnedFrame::data(1, &[7; 100], false).as_bytes());
+        input.extend_from_slice(OwnedFrame::ping(false, &[1; 8]).as_bytes());
+        let mut stream = Trickle { input: input, pos: 0, step: 1000 };
+        reader.read_frame(&mut stream).unwrap();
+        reader.read_frame(&mut stream).unwrap();
+        assert!(budget.used() >= 32 + 109);
+
+        drop(reader);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn skip_payload() {
+        let budget = MemoryBudget::new(1 << 20);
+        let mut reader = FrameReader::with_capacity(1024);
+        reader.set_budget(Some(budget.clone()));
+
+        // an unknown frame that claims to be 10MB long, which is all there
+        let len = 10 << 20;
+        let header = OwnedFrame::header(0xBB, 0, 0, len);
+        let mut stream = Cursor::new(header.to_vec())
+            .chain(io::repeat(7).take(len as u64))
+            .chain(Cursor::new(OwnedFrame::ping(false, &[1; 8]).as_bytes().to_vec()));
+        match reader.read_frame_or_skip(&mut stream, |h| h[3] == 0xBB).unwrap() {
+            Some(ReadFrame::Skipped(skipped)) => assert_eq!(skipped, header),
+            _ => panic!("the frame was not skipped"),
+        }
+        // it went through the ring and nothing else
+        assert_eq!(budget.used(), 1024);
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::PING);
+        assert_eq!(budget.used(), 1024);
+    }
+
+    #[test]
+    fn skip_over_would_block() {
+        use std::io::ErrorKind::WouldBlock;
+
+        let script = vec![Ok(30), Err(WouldBlock), Ok(30), Err(WouldBlock), Ok(200)];
+        let mut stream = FlakyStream::new(flaky_input(), script);
+        let mut reader = FrameReader::new();
+        reader.set_mode(ReadMode::NonBlocking);
+
+        // the ping is read, the DATA is skipped over the WouldBlocks
+        // without being asked about again
+        assert_eq!(reader.read_frame(&mut stream).unwrap().unwrap().get_type(), types::PING);
+        assert_eq!(reader.read_frame_or_skip(&mut stream, |_| true).err().unwrap().kind(), WouldBlock);
+        assert_eq!(reader.read_frame_or_skip(&mut stream, |_| panic!("asked again")).err().unwrap().kind(), WouldBlock);
+        match reader.read_frame_or_skip(&mut stream, |_| panic!("asked again")).unwrap() {
+            Some(ReadFrame::Skipped(header)) => assert_eq!(header, OwnedFrame::header(types::DATA, 1, 1, 100)),
+            _ => panic!("the frame was not skipped"),
+        }
+        assert!(reader.read_frame(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_skip() {
+        let frame = OwnedFrame::data(1, &[7; 100], true);
+        let mut stream = Trickle { input: frame.as_bytes()[..50].to_vec(), pos: 0, step: 1000 };
+
+        let mut reader = FrameReader::new();
+        let err = reader.read_frame_or_skip(&mut stream, |_| true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
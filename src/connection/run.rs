@@ -0,0 +1,322 @@
+//! Driving a connection over a socket
+//!
+//! The same loop is used no matter how the connection was established
+//! (TLS, cleartext with prior knowledge or an h2c upgrade), the stream
+//! just has to be something that can be read from and written to.
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use frame::{FrameError, Http2Frame};
+use krserr::{ErrLink, ErrorChain, Kind, Kresult};
+use log::Context;
+use super::Connection;
+use super::error::ConnectionFailed;
+use super::event::Event;
+use super::reader::ReadMode;
+
+// how long to wait before trying a stream that would block again
+const POLL_INTERVAL_MS : u64 = 1;
+
+impl Connection {
+
+    /// run a connection until the peer closes it
+    ///
+    /// Every event is passed to on_event along with the connection so it
+    /// can be answered, and whatever gets queued is written out before the
+    /// next frame is read.
+    ///
+    /// A stream error resets the stream and the connection keeps going. A
+    /// connection error is sent to the peer in a GOAWAY and returned, which
+    /// drops (and so closes) the stream.
+    ///
+    /// The stream ending between frames is the peer closing the connection,
+    /// ending in the middle of one is an UnexpectedEof error.
+    ///
+    /// The error is a chain that starts with the connection failing, then
+    /// the frame it failed on (if it was a frame) and what was wrong, e.g.
+    ///
+    /// ```text
+    /// connection failed
+    ///   caused by: stream 5 HEADERS
+    ///   caused by: connection error COMPRESSION_ERROR: hpack: index is out of range
+    /// ```
+    pub fn run<S, F>(stream: S, allow_upgrade: bool, on_event: F) -> Kresult<()>
+        where S: Read + Write, F: FnMut(&mut Connection, Event) {
+
+        Connection::run_with(stream, allow_upgrade, ReadMode::Blocking, on_event)
+    }
+
+    /// run with the stream read in the given mode, in non-blocking mode
+    /// a stream that has nothing to read is tried again a little later
+    /// (after whatever was queued is written)
+    pub fn run_with<S, F>(mut stream: S, allow_upgrade: bool, mode: ReadMode, mut on_event: F) -> Kresult<()>
+        where S: Read + Write, F: FnMut(&mut Connection, Event) {
+
+        let (mut conn, mut reader) = Connection::handshake(&mut stream, allow_upgrade).map_err(|e| failed(None, e.into()))?;
+        reader.set_mode(mode);
+        let peer = conn.peer_addr();
+
+        loop {
+            while let Some(event) = conn.poll_event() {
+                on_event(&mut conn, event);
+            }
+            conn.write_outbound(&mut stream).map_err(|e| failed(peer, e.into()))?;
+
+            let (stream_id, frame_type, res) = match reader.read_frame(&mut stream) {
+                Ok(Some(frame)) => (frame.get_stream_id(), frame.get_type(), conn.receive_frame(frame)),
+                Ok(None) => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                    continue;
+                },
+                Err(e) => return Err(failed(peer, e.into())),
+            };
+
+            // a stream error was handled already, this is the connection failing
+            if let Err(e) = res {
+                let kind = Kind::Protocol(e.code());
+                let err = Err::<(), _>(e).chain_err(|| FrameError::new(stream_id, frame_type, kind)).unwrap_err();
+                let err = failed(peer, err);
+                klog_warn!(Context::peer(peer) => "{}", err);
+                conn.report_chain(&err);
+                conn.write_outbound(&mut stream).map_err(|e| failed(peer, e.into()))?;
+                return Err(err);
+            }
+        }
+    }
+}
+
+// put the connection failing on top of what made it fail
+fn failed(peer: Option<SocketAddr>, err: ErrLink) -> ErrLink {
+    let kind = err.kind();
+    Err::<(), _>(err).chain_err(|| ConnectionFailed::new(peer, kind)).unwrap_err()
+}
+
+#[cfg(test)]
+mod run_tests {
+
+    use std::io::{self, Cursor};
+
+    use connection::Connection;
+    use connection::error::ErrorCode;
+    use connection::event::Event;
+    use connection::handshake::PREFACE;
+    use connection::mock::{FlakyStream, MockStream};
+    use connection::reader::{FrameReader, ReadMode};
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags};
+    use header::{HeaderList, HpackError};
+    use krserr::Kind;
+    use log::capture::capture;
+    use test_util::GET_BLOCK;
+
+    #[test]
+    fn prior_knowledge_end_to_end() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        let mut stream = MockStream::new(input);
+
+        let mut requests = Vec::new();
+        Connection::run(&mut stream, false, |conn, event| {
+            if let Event::Headers { stream_id, .. } = event {
+                requests.push(stream_id);
+                let mut headers = HeaderList::with_capacity(1);
+                headers.add_entry((":status", "200").into());
+                conn.send_headers(stream_id, &headers, false).unwrap();
+                conn.send_data(stream_id, b"hello", true).unwrap();
+            }
+        }).unwrap();
+        assert_eq!(requests, vec![1, 3]);
+
+        let mut frames = Vec::new();
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(stream.output);
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            frames.push((frame.get_type(), frame.get_flags(), frame.get_stream_id()));
+        }
+        assert_eq!(frames, vec![
+            (types::SETTINGS, 0, 0),
+            (types::SETTINGS, flags::ACK, 0),
+            (types::HEADERS, flags::END_HEADERS, 1),
+            (types::DATA, flags::END_STREAM, 1),
+            (types::HEADERS, flags::END_HEADERS, 3),
+            (types::DATA, flags::END_STREAM, 3),
+        ]);
+    }
+
+    #[test]
+    fn flaky_stream() {
+        use std::io::ErrorKind::{Interrupted, WouldBlock};
+
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(3, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        // the preface goes in one read, then the frames come in pieces
+        let script = vec![Ok(24), Err(WouldBlock), Ok(5), Err(Interrupted), Ok(5),
+                          Err(WouldBlock), Ok(11), Err(WouldBlock), Err(Interrupted), Ok(3), Err(WouldBlock)];
+
+        for &mode in &[ReadMode::Blocking, ReadMode::NonBlocking] {
+            let mut stream = FlakyStream::new(input.clone(), script.clone());
+            let mut requests = Vec::new();
+            Connection::run_with(&mut stream, false, mode, |_, event| {
+                if let Event::Headers { stream_id, .. } = event {
+                    requests.push(stream_id);
+                }
+            }).unwrap();
+            assert_eq!(requests, vec![1, 3]);
+        }
+
+        // cut off in a frame
+        let mut stream = MockStream::new(input[..input.len() - 1].to_vec());
+        let err = Connection::run(&mut stream, false, |_, _| {}).unwrap_err();
+        assert_eq!(err.kind(), Kind::Io);
+        assert_eq!(err.find::<io::Error>().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    // run a connection over input, returning whether it ended without
+    // an error and the frames it wrote after the server preface
+    fn run_frames(frames: &[OwnedFrame]) -> (bool, Vec<(u8, u32, Option<ErrorCode>)>) {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        for frame in frames {
+            input.extend_from_slice(frame.as_bytes());
+        }
+        let mut stream = MockStream::new(input);
+        let ok = Connection::run(&mut stream, false, |_, _| {}).is_ok();
+
+        let mut out = Vec::new();
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(stream.output);
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            let payload = frame.payload();
+            let code = match frame.get_type() {
+                types::RST_STREAM => Some(&payload[0..4]),
+                types::GOAWAY => Some(&payload[4..8]),
+                _ => None,
+            }.map(|b| ErrorCode::from((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32));
+            out.push((frame.get_type(), frame.get_stream_id(), code));
+        }
+        // server SETTINGS and the ACK for the client's
+        assert_eq!(&out[..2], &[(types::SETTINGS, 0, None), (types::SETTINGS, 0, None)]);
+        (ok, out.split_off(2))
+    }
+
+    #[test]
+    fn stream_error_resets_stream() {
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS),
+            OwnedFrame::window_update(1, 0),
+            OwnedFrame::ping(false, &[0; 8]),
+        ]);
+        assert!(ok);
+        assert_eq!(frames, vec![
+            (types::RST_STREAM, 1, Some(ErrorCode::ProtocolError)),
+            (types::PING, 0, None),
+        ]);
+
+        // a bad PRIORITY for a stream that was never opened
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::new(types::PRIORITY, 0, 5, &[0, 0, 0, 0]),
+            OwnedFrame::ping(false, &[0; 8]),
+        ]);
+        assert!(ok);
+        assert_eq!(frames, vec![
+            (types::RST_STREAM, 5, Some(ErrorCode::FrameSizeError)),
+            (types::PING, 0, None),
+        ]);
+    }
+
+    #[test]
+    fn oversized_data_resets_stream() {
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS),
+            OwnedFrame::data(1, &[0; 0x4001], true),
+            OwnedFrame::ping(false, &[0; 8]),
+        ]);
+        assert!(ok);
+        assert_eq!(frames, vec![
+            (types::RST_STREAM, 1, Some(ErrorCode::FrameSizeError)),
+            (types::PING, 0, None),
+        ]);
+    }
+
+    #[test]
+    fn connection_error_sends_go_away() {
+        // index 0 is not a valid HPACK index
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::headers(1, &[0x80], flags::END_HEADERS),
+            OwnedFrame::ping(false, &[0; 8]),
+        ]);
+        assert!(!ok);
+        assert_eq!(frames, vec![(types::GOAWAY, 0, Some(ErrorCode::CompressionError))]);
+
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::new(types::PING, 0, 0, &[0; 7]),
+            OwnedFrame::ping(false, &[0; 8]),
+        ]);
+        assert!(!ok);
+        assert_eq!(frames, vec![(types::GOAWAY, 0, Some(ErrorCode::FrameSizeError))]);
+
+        let (ok, frames) = run_frames(&[
+            OwnedFrame::headers(2, GET_BLOCK, flags::END_HEADERS),
+        ]);
+        assert!(!ok);
+        assert_eq!(frames, vec![(types::GOAWAY, 0, Some(ErrorCode::ProtocolError))]);
+    }
+
+    #[test]
+    fn connection_error_chain() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        // index 70 is past the end of both tables
+        input.extend_from_slice(OwnedFrame::headers(5, &[0xc6], flags::END_HEADERS).as_bytes());
+        let mut stream = MockStream::new(input);
+
+        let err = Connection::run(&mut stream, false, |_, _| {}).unwrap_err();
+        let links: Vec<String> = err.iter().map(|l| l.error().to_string()).collect();
+        assert_eq!(links, vec![
+            "connection failed",
+            "stream 5 HEADERS",
+            "connection error COMPRESSION_ERROR: hpack: index is out of range",
+        ]);
+        assert_eq!(err.kind(), Kind::Protocol(ErrorCode::CompressionError));
+        assert_eq!(err.protocol_code(), Some(ErrorCode::CompressionError));
+
+        // the decoder's own error is a chain of one
+        let err = ::header::Decoder::new(4096, 10).get_header_list(&[0xc6]).unwrap_err();
+        assert_eq!(err.kind(), Kind::Hpack);
+        assert_eq!(err.find::<HpackError>().unwrap().reason(), "hpack: index is out of range");
+    }
+
+    #[test]
+    fn errors_are_logged() {
+        let messages = capture(|| {
+            run_frames(&[
+                OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS),
+                OwnedFrame::window_update(1, 0),
+                OwnedFrame::headers(3, &[0x80], flags::END_HEADERS),
+            ]);
+        });
+        let messages: Vec<_> = messages.into_iter().filter(|m| !m.starts_with("TRACE")).collect();
+        assert_eq!(messages, vec![
+            "DEBUG stream 1 error PROTOCOL_ERROR",
+            "DEBUG connection error COMPRESSION_ERROR: hpack: index of 0 was found, the frame was:\n\
+             00000000  00 00 01 01 04 00 00 00  03 80                    |..........|",
+            "WARN connection failed\n  caused by: stream 3 HEADERS\n  caused by: connection error COMPRESSION_ERROR: hpack: index of 0 was found",
+        ]);
+    }
+
+    #[test]
+    fn no_preface() {
+        let mut stream = MockStream::new(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+        assert!(Connection::run(&mut stream, false, |_, _| {}).is_err());
+        assert!(stream.output.is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! 6.5.2 Defined SETTINGS Parameters
+//!
+//! Each endpoint keeps track of the settings it advertised (local)
+//! and the settings advertised by the peer (remote). The remote
+//! settings restrict what can be sent, and the local ones
+//! restrict what can be received.
+
+use frame::frame_types::SettingsFrame;
+
+use super::error::{ErrorCode, H2Error};
+use super::limits::DEFAULT_MAX_REQUEST_HEADERS;
+
+pub const HEADER_TABLE_SIZE : u16 = 0x1;
+pub const ENABLE_PUSH : u16 = 0x2;
+pub const MAX_CONCURRENT_STREAMS : u16 = 0x3;
+pub const INITIAL_WINDOW_SIZE : u16 = 0x4;
+pub const MAX_FRAME_SIZE : u16 = 0x5;
+pub const MAX_HEADER_LIST_SIZE : u16 = 0x6;
+/// RFC 8441 3 the peer can open tunnels with extended CONNECT
+pub const ENABLE_CONNECT_PROTOCOL : u16 = 0x8;
+
+/// largest window size allowed by flow control (2^31-1)
+pub const MAX_WINDOW_SIZE : u32 = 0x7FFFFFFF;
+
+/// how many streams the peer may have open at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT_STREAMS : u32 = 100;
+
+/// 6.5.3 how long the peer has to acknowledge our SETTINGS, in seconds
+pub const DEFAULT_SETTINGS_TIMEOUT : u64 = 10;
+
+/// the range SETTINGS_MAX_FRAME_SIZE has to be in
+pub const MIN_FRAME_SIZE_LIMIT : u32 = 0x4000; // 2^14
+pub const MAX_FRAME_SIZE_LIMIT : u32 = 0xFFFFFF; // 2^24-1
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub header_table_size: u32,
+    pub enable_push: bool,
+    pub max_concurrent_streams: Option<u32>, // None is unlimited
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    pub max_header_list_size: Option<u32>, // None is unlimited
+    pub enable_connect_protocol: bool,
+}
+
+/// The changes from applying a SETTINGS frame that need
+/// to be acted on by the connection and not just recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsEffect {
+    InitialWindowSize { old: u32, new: u32 },
+    HeaderTableSize(u32),
+    MaxFrameSize(u32),
+    MaxHeaderListSize(u32),
+}
+
+impl Default for Settings {
+    // initial values as defined in the spec
+    fn default() -> Self {
+        Settings {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: 65535,
+            max_frame_size: MIN_FRAME_SIZE_LIMIT,
+            max_header_list_size: None,
+            enable_connect_protocol: false,
+        }
+    }
+}
+
+impl Settings {
+
+    /// the settings this endpoint advertises by default,
+    /// which only differ from the spec defaults where there
+    /// would otherwise be no limit
+    pub fn local_default() -> Self {
+        Settings {
+            max_concurrent_streams: Some(DEFAULT_MAX_CONCURRENT_STREAMS),
+            // so clients do not send requests that would only get a 431
+            max_header_list_size: Some(DEFAULT_MAX_REQUEST_HEADERS as u32),
+            ..Settings::default()
+        }
+    }
+
+    /// apply the parameters of a SETTINGS frame received from the peer
+    ///
+    /// parameters are processed in the order they appear and unknown
+    /// identifiers are ignored as the spec requires
+    pub fn apply_remote(&mut self, frame: &SettingsFrame) -> Result<Vec<SettingsEffect>, H2Error> {
+        let mut effects = Vec::new();
+
+        for (id, value) in frame.get_settings_paramaters() {
+            match id {
+                HEADER_TABLE_SIZE => {
+                    self.header_table_size = value;
+                    effects.push(SettingsEffect::HeaderTableSize(value));
+                },
+                ENABLE_PUSH => {
+                    self.enable_push = match value {
+                        0 => false,
+                        1 => true,
+                        _ => return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_ENABLE_PUSH must be 0 or 1")),
+                    };
+                },
+                MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                INITIAL_WINDOW_SIZE => {
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(H2Error::connection(ErrorCode::FlowControlError, "SETTINGS_INITIAL_WINDOW_SIZE above 2^31-1"));
+                    }
+                    effects.push(SettingsEffect::InitialWindowSize { old: self.initial_window_size, new: value });
+                    self.initial_window_size = value;
+                },
+                MAX_FRAME_SIZE => {
+                    if value < MIN_FRAME_SIZE_LIMIT || value > MAX_FRAME_SIZE_LIMIT {
+                        return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_MAX_FRAME_SIZE out of range"));
+                    }
+                    self.max_frame_size = value;
+                    effects.push(SettingsEffect::MaxFrameSize(value));
+                },
+                MAX_HEADER_LIST_SIZE => {
+                    self.max_header_list_size = Some(value);
+                    effects.push(SettingsEffect::MaxHeaderListSize(value));
+                },
+                ENABLE_CONNECT_PROTOCOL => {
+                    self.enable_connect_protocol = match (value, self.enable_connect_protocol) {
+                        (0, true) => return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_ENABLE_CONNECT_PROTOCOL turned off once on")),
+                        (0, false) => false,
+                        (1, _) => true,
+                        _ => return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_ENABLE_CONNECT_PROTOCOL must be 0 or 1")),
+                    };
+                },
+                _ => {},
+            }
+        }
+
+        Ok(effects)
+    }
+
+    /// the parameters that differ from the spec defaults,
+    /// which is all that needs to be advertised in a SETTINGS frame
+    pub fn params(&self) -> Vec<(u16, u32)> {
+        let default = Settings::default();
+        let mut params = Vec::new();
+
+        if self.header_table_size != default.header_table_size {
+            params.push((HEADER_TABLE_SIZE, self.header_table_size));
+        }
+        if self.enable_push != default.enable_push {
+            params.push((ENABLE_PUSH, self.enable_push as u32));
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            params.push((MAX_CONCURRENT_STREAMS, max));
+        }
+        if self.initial_window_size != default.initial_window_size {
+            params.push((INITIAL_WINDOW_SIZE, self.initial_window_size));
+        }
+        if self.max_frame_size != default.max_frame_size {
+            params.push((MAX_FRAME_SIZE, self.max_frame_size));
+        }
+        if let Some(max) = self.max_header_list_size {
+            params.push((MAX_HEADER_LIST_SIZE, max));
+        }
+        if self.enable_connect_protocol {
+            params.push((ENABLE_CONNECT_PROTOCOL, 1));
+        }
+
+        params
+    }
+}
+
+#[cfg(test)]
+mod settings_tests {
+
+    use super::*;
+    use frame::OwnedFrame;
+
+    #[test]
+    fn apply_remote_settings() {
+        let mut frame = OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, 100), (MAX_FRAME_SIZE, 0x8000), (0xFF, 1)]);
+        let mut settings = Settings::default();
+
+        let effects = settings.apply_remote(&frame.as_frame().into()).unwrap();
+
+        assert_eq!(effects, vec![SettingsEffect::InitialWindowSize { old: 65535, new: 100 }, SettingsEffect::MaxFrameSize(0x8000)]);
+        assert_eq!(settings.initial_window_size, 100);
+        assert_eq!(settings.max_frame_size, 0x8000);
+    }
+
+    #[test]
+    fn invalid_remote_settings() {
+        let mut settings = Settings::default();
+
+        let mut frame = OwnedFrame::settings(&[(ENABLE_PUSH, 2)]);
+        assert!(settings.apply_remote(&frame.as_frame().into()).is_err());
+
+        let mut frame = OwnedFrame::settings(&[(INITIAL_WINDOW_SIZE, MAX_WINDOW_SIZE + 1)]);
+        assert_eq!(settings.apply_remote(&frame.as_frame().into()).unwrap_err().code(), ErrorCode::FlowControlError);
+
+        let mut frame = OwnedFrame::settings(&[(MAX_FRAME_SIZE, 100)]);
+        assert_eq!(settings.apply_remote(&frame.as_frame().into()).unwrap_err().code(), ErrorCode::ProtocolError);
+    }
+
+    #[test]
+    fn connect_protocol() {
+        let mut settings = Settings::default();
+        assert!(!settings.params().contains(&(ENABLE_CONNECT_PROTOCOL, 0)));
+
+        let mut frame = OwnedFrame::settings(&[(ENABLE_CONNECT_PROTOCOL, 2)]);
+        assert!(settings.apply_remote(&frame.as_frame().into()).is_err());
+        let mut frame = OwnedFrame::settings(&[(ENABLE_CONNECT_PROTOCOL, 1)]);
+        assert_eq!(settings.apply_remote(&frame.as_frame().into()), Ok(vec![]));
+        assert!(settings.enable_connect_protocol);
+        assert_eq!(settings.params(), vec![(ENABLE_CONNECT_PROTOCOL, 1)]);
+
+        // RFC 8441 3 once it is on it stays on
+        let mut frame = OwnedFrame::settings(&[(ENABLE_CONNECT_PROTOCOL, 0)]);
+        assert_eq!(settings.apply_remote(&frame.as_frame().into()).unwrap_err().code(), ErrorCode::ProtocolError);
+    }
+}
@@ -0,0 +1,884 @@
+//! 5.1 Stream States
+//!
+//! ```text
+//!                          +--------+
+//!                  send PP |        | recv PP
+//!                 ,--------|  idle  |--------.
+//!                /         |        |         \
+//!               v          +--------+          v
+//!        +----------+          |           +----------+
+//!        |          |          | send H /  |          |
+//! ,------| reserved |          | recv H    | reserved |------.
+//! |      | (local)  |          |           | (remote) |      |
+//! |      +----------+          v           +----------+      |
+//! |          |             +--------+             |          |
+//! |          |     recv ES |        | send ES     |          |
+//! |   send H |     ,-------|  open  |-------.     | recv H   |
+//! |          |    /        |        |        \    |          |
+//! |          v   v         +--------+         v   v          |
+//! |      +----------+          |           +----------+      |
+//! |      |   half   |          |           |   half   |      |
+//! |      |  closed  |          | send R /  |  closed  |      |
+//! |      | (remote) |          | recv R    | (local)  |      |
+//! |      +----------+          |           +----------+      |
+//! |           |                |                 |           |
+//! |           | send ES /      |       recv ES / |           |
+//! |           | send R /       v        send R / |           |
+//! |           | recv R     +--------+   recv R   |           |
+//! | send R /  `----------->|        |<-----------'  send R / |
+//! | recv R                 | closed |               recv R   |
+//! `----------------------->|        |<----------------------'
+//!                          +--------+
+//!
+//!    send:   endpoint sends this frame
+//!    recv:   endpoint receives this frame
+//!
+//!    H:  HEADERS frame (with implied CONTINUATIONs)
+//!    PP: PUSH_PROMISE frame (with implied CONTINUATIONs)
+//!    ES: END_STREAM flag
+//!    R:  RST_STREAM frame
+//! ```
+//!
+//! Figure 2: Stream States
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{Instant, SystemTime};
+
+use buf::{MappedSlice, Pool, PooledBuf};
+use frame::OwnedFrame;
+use header::HeaderList;
+
+use super::error::{ErrorCode, H2Error};
+use super::settings::{Settings, MAX_WINDOW_SIZE};
+use super::trace::Direction;
+use super::transfer::TransferCount;
+use super::window::WindowInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    Idle,
+    ReservedLocal,
+    ReservedRemote,
+    Open,
+    HalfClosedLocal,
+    HalfClosedRemote,
+    Closed,
+}
+
+/// What the access log needs to know about the request on a stream
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub method: String,
+    pub path: String,
+    // the wall clock time for the log, and the connection's time for the duration
+    pub received: SystemTime,
+    pub started: Instant,
+}
+
+/// The state kept for every stream on a connection
+#[derive(Debug)]
+pub struct Stream {
+    id: u32,
+    state: StreamState,
+    // how much data the peer is willing to receive on this stream
+    // (can go negative when SETTINGS_INITIAL_WINDOW_SIZE shrinks)
+    send_window: i32,
+    // how much data the peer may still send on this stream before
+    // the data it already sent is released
+    recv_window: i32,
+    // the window the peer is meant to have, what recv_window goes
+    // back up to as data is released
+    recv_window_size: u32,
+    // window that was released (or grown) but not advertised yet,
+    // negative while a shrink is taken out of what is released
+    unadvertised: i64,
+    // the application holds back the window (it is still released,
+    // just not advertised), or only gives it with grant_recv_window
+    recv_paused: bool,
+    auto_release: bool,
+    // data that could not be sent yet because of flow control
+    pending_data: Vec<u8>,
+    // END_STREAM should be sent with the last of the pending data
+    pending_end_stream: bool,
+    // trailers to send (with END_STREAM) after the pending data
+    pending_trailers: Option<HeaderList>,
+    // the rest of the data, read a frame at a time as the windows allow
+    body: Option<BodySource>,
+    // a header block was sent (the response has started)
+    headers_sent: bool,
+    // the content-length of the message each way, if it had one,
+    // and how much DATA there has been so far
+    recv_length: Option<u64>,
+    recv_total: u64,
+    send_length: Option<u64>,
+    send_total: u64,
+    // the request was HEAD, so the response has no DATA
+    // whatever its content-length says
+    head: bool,
+    // for the access log, what was asked and how it was answered
+    request: Option<RequestInfo>,
+    status: Option<u16>,
+    // what the response sent
+    transfer: TransferCount,
+    // which side reset the stream and why
+    reset: Option<(Direction, ErrorCode)>,
+    // since when data has been queued without window to send it in,
+    // and whether that was warned about
+    send_blocked: Option<Instant>,
+    stall_warned: bool,
+}
+
+impl Stream {
+
+    pub fn new(id: u32, send_window: u32) -> Self {
+        Stream {
+            id: id,
+            state: StreamState::Idle,
+            send_window: send_window as i32,
+            recv_window: Settings::default().initial_window_size as i32,
+            recv_window_size: Settings::default().initial_window_size,
+            unadvertised: 0,
+            recv_paused: false,
+            auto_release: true,
+            pending_data: Vec::new(),
+            pending_end_stream: false,
+            pending_trailers: None,
+            body: None,
+            headers_sent: false,
+            recv_length: None,
+            recv_total: 0,
+            send_length: None,
+            send_total: 0,
+            head: false,
+            request: None,
+            status: None,
+            transfer: TransferCount::new(),
+            reset: None,
+            send_blocked: None,
+            stall_warned: false,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn state(&self) -> StreamState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: StreamState) {
+        self.state = state;
+    }
+
+    pub fn send_window(&self) -> i32 {
+        self.send_window
+    }
+
+    /// the windows of the stream, with the connection's
+    pub fn windows(&self, send_conn: i32, recv_conn: i32) -> WindowInfo {
+        WindowInfo { send_stream: self.send_window, send_conn_share: send_conn, recv_stream: self.recv_window, recv_conn: recv_conn }
+    }
+
+    /// is there data waiting for window, not just an END_STREAM
+    pub fn wants_window(&self) -> bool {
+        !self.pending_data.is_empty() || self.body.is_some()
+    }
+
+    /// the stream has data to send and no window for it as of now,
+    /// or (with None) it is not held up
+    pub fn set_send_blocked(&mut self, now: Option<Instant>) {
+        match now {
+            Some(now) => { self.send_blocked.get_or_insert(now); },
+            None => {
+                self.send_blocked = None;
+                self.stall_warned = false;
+            },
+        }
+    }
+
+    /// since when the stream has been held up, if it is
+    pub fn send_blocked_since(&self) -> Option<Instant> {
+        self.send_blocked
+    }
+
+    /// true the first time it is asked while the stream is held up
+    pub fn take_stall_warning(&mut self) -> bool {
+        let first = self.send_blocked.is_some() && !self.stall_warned;
+        self.stall_warned |= first;
+        first
+    }
+
+    /// has the stall it is in been warned about
+    pub fn stall_warned(&self) -> bool {
+        self.stall_warned
+    }
+
+    /// 6.9.1 A sender MUST NOT allow a flow-control window to exceed 2^31-1 octets.
+    /// If a sender receives a WINDOW_UPDATE that causes a flow-control window to
+    /// exceed this maximum, it MUST terminate the stream with FLOW_CONTROL_ERROR.
+    pub fn increase_send_window(&mut self, increment: u32) -> Result<(), H2Error> {
+        let window = self.send_window as i64 + increment as i64;
+        if window > MAX_WINDOW_SIZE as i64 {
+            return Err(H2Error::Stream(self.id, ErrorCode::FlowControlError));
+        }
+        self.send_window = window as i32;
+        Ok(())
+    }
+
+    /// 6.9.2 When the value of SETTINGS_INITIAL_WINDOW_SIZE changes, a receiver MUST
+    /// adjust the size of all stream flow-control windows that it maintains by the
+    /// difference between the new value and the old value.
+    ///
+    /// This can leave the window negative, and nothing can be sent until
+    /// WINDOW_UPDATE frames bring it back above zero. Going past 2^31-1 this way
+    /// is a connection error.
+    pub fn adjust_send_window(&mut self, delta: i64) -> Result<(), H2Error> {
+        let window = self.send_window as i64 + delta;
+        if window > MAX_WINDOW_SIZE as i64 {
+            return Err(H2Error::connection(ErrorCode::FlowControlError, "SETTINGS_INITIAL_WINDOW_SIZE change overflows a stream window"));
+        }
+        self.send_window = window as i32;
+        Ok(())
+    }
+
+    pub fn recv_window(&self) -> i32 {
+        self.recv_window
+    }
+
+    /// the window the peer was given for the stream when it was opened
+    /// (the SETTINGS_INITIAL_WINDOW_SIZE it was sent)
+    pub fn init_recv_window(&mut self, size: u32) {
+        self.recv_window = size as i32;
+        self.recv_window_size = size;
+    }
+
+    /// grow or shrink the window the peer gets for the stream
+    ///
+    /// Growing it is advertised with the next WINDOW_UPDATE. A window can
+    /// not be taken back, shrinking it holds back that much of the data
+    /// released after it instead.
+    pub fn set_recv_window(&mut self, size: u32) {
+        let size = ::std::cmp::min(size, MAX_WINDOW_SIZE);
+        self.unadvertised += size as i64 - self.recv_window_size as i64;
+        self.recv_window_size = size;
+    }
+
+    pub fn recv_window_size(&self) -> u32 {
+        self.recv_window_size
+    }
+
+    /// 6.9.1 A receiver MAY respond with a stream error of type FLOW_CONTROL_ERROR
+    /// if it is unable to accept a frame (when the sender went over the window)
+    pub fn consume_recv_window(&mut self, size: usize) -> Result<(), H2Error> {
+        if size as i64 > self.recv_window as i64 {
+            return Err(H2Error::Stream(self.id, ErrorCode::FlowControlError));
+        }
+        self.recv_window -= size as i32;
+        Ok(())
+    }
+
+    /// give back window for data that was consumed, it goes back to the
+    /// peer with the next WINDOW_UPDATE (see advertise)
+    pub fn release_recv_window(&mut self, size: usize) {
+        self.unadvertised += size as i64;
+    }
+
+    /// stop (or start again) advertising window for the stream, what is
+    /// released meanwhile is advertised once it is resumed
+    pub fn pause_recv(&mut self, paused: bool) {
+        self.recv_paused = paused;
+    }
+
+    pub fn is_recv_paused(&self) -> bool {
+        self.recv_paused
+    }
+
+    /// should data that is consumed give its window back, without it the
+    /// peer only gets what grant_recv_window gives it
+    pub fn set_auto_release(&mut self, auto_release: bool) {
+        self.auto_release = auto_release;
+    }
+
+    pub fn auto_release(&self) -> bool {
+        self.auto_release
+    }
+
+    /// give the peer n more bytes of window whatever was consumed, as
+    /// much as fits under 2^31-1
+    pub fn grant_recv_window(&mut self, n: u32) {
+        let room = MAX_WINDOW_SIZE as i64 - self.recv_window as i64 - self.unadvertised;
+        self.unadvertised += ::std::cmp::min(n as i64, ::std::cmp::max(room, 0));
+    }
+
+    /// how much window the next WINDOW_UPDATE would give the peer
+    pub fn unadvertised(&self) -> u32 {
+        ::std::cmp::max(self.unadvertised, 0) as u32
+    }
+
+    /// take the window that is to be advertised, the peer can send
+    /// that much more once it gets the WINDOW_UPDATE
+    pub fn advertise(&mut self) -> u32 {
+        let n = self.unadvertised();
+        self.recv_window += n as i32;
+        self.unadvertised -= n as i64;
+        n
+    }
+
+    // the caller must not consume more than the window allows
+    pub fn consume_send_window(&mut self, size: usize) {
+        debug_assert!(size as i64 <= self.send_window as i64);
+        self.send_window -= size as i32;
+    }
+
+    pub fn has_pending_data(&self) -> bool {
+        !self.pending_data.is_empty() || self.pending_end_stream || self.pending_trailers.is_some() || self.body.is_some()
+    }
+
+    /// is there a body left to read from
+    pub fn has_body(&self) -> bool {
+        self.body.is_some()
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending_data.len()
+    }
+
+    /// has a header block been sent on the stream
+    pub fn headers_sent(&self) -> bool {
+        self.headers_sent
+    }
+
+    pub fn set_headers_sent(&mut self) {
+        self.headers_sent = true;
+    }
+
+    /// the content-length of the message the peer is sending on this stream
+    pub fn set_recv_length(&mut self, length: Option<u64>) {
+        self.recv_length = length;
+    }
+
+    /// the content-length of the message being sent on this stream
+    pub fn set_send_length(&mut self, length: Option<u64>) {
+        self.send_length = length;
+    }
+
+    /// the stream is for a HEAD request (RFC 7231 4.3.2), the content-length
+    /// of the response is the length of the body GET would have
+    pub fn set_head(&mut self, head: bool) {
+        self.head = head;
+    }
+
+    /// 8.1.2.6 count received DATA against the content-length, going
+    /// over it or ending the stream short of it makes the request
+    /// malformed which is a stream error of type PROTOCOL_ERROR
+    pub fn count_received(&mut self, len: usize, end_stream: bool) -> Result<(), H2Error> {
+        self.recv_total += len as u64;
+        if !length_matches(self.recv_length, self.recv_total, end_stream) {
+            return Err(H2Error::Stream(self.id, ErrorCode::ProtocolError));
+        }
+        Ok(())
+    }
+
+    /// count data that is being sent against the content-length that was
+    /// sent, a mismatch is a mistake on our side (INTERNAL_ERROR) and
+    /// nothing is counted so the data can be left unsent
+    pub fn count_sent(&mut self, len: usize, end_stream: bool) -> Result<(), H2Error> {
+        let total = self.send_total + len as u64;
+        let length = if self.head { Some(0) } else { self.send_length };
+        if !length_matches(length, total, end_stream) {
+            return Err(H2Error::Stream(self.id, ErrorCode::InternalError));
+        }
+        self.send_total = total;
+        Ok(())
+    }
+
+    /// queue data that is waiting for the send window to open
+    pub fn queue_data(&mut self, data: &[u8], end_stream: bool) {
+        self.pending_data.extend_from_slice(data);
+        self.pending_end_stream |= end_stream;
+    }
+
+    /// queue trailers which end the stream once the pending data is sent
+    pub fn queue_trailers(&mut self, trailers: HeaderList) {
+        self.pending_trailers = Some(trailers);
+    }
+
+    /// send everything body reads after the pending data, ending
+    /// the stream when it runs out
+    pub fn queue_body(&mut self, body: Box<Read>) {
+        self.body = Some(BodySource::Reader(body));
+    }
+
+    /// send a mapped file after the pending data, ending the stream with
+    /// the last of it, the DATA frames are slices of the mapping
+    pub fn queue_mapped(&mut self, body: MappedSlice) {
+        self.body = Some(BodySource::Mapped(body));
+    }
+
+    /// read up to max bytes of the body into the pending data
+    ///
+    /// The body ends when the reader does or when the content-length is
+    /// reached (so a reader for a file that is being appended to is not
+    /// read too far), either way END_STREAM is queued with what was read.
+    /// A failed read is an INTERNAL_ERROR for the stream.
+    pub fn read_body(&mut self, max: usize) -> Result<(), H2Error> {
+        let mut body = match self.body.take() {
+            Some(BodySource::Reader(body)) => body,
+            mapped => {
+                self.body = mapped;
+                return Ok(());
+            },
+        };
+
+        // never past the content-length, what comes after is not the body
+        let max = self.send_length.map_or(max, |len| cmp::min(max as u64, len.saturating_sub(self.send_total)) as usize);
+        let start = self.pending_data.len();
+        self.pending_data.resize(start + max, 0);
+        let read = loop {
+            match body.read(&mut self.pending_data[start..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read,
+            }
+        };
+        let n = match read {
+            Ok(n) => n,
+            Err(_) => {
+                self.pending_data.truncate(start);
+                return Err(H2Error::Stream(self.id, ErrorCode::InternalError));
+            },
+        };
+        self.pending_data.truncate(start + n);
+
+        let done = n == 0 || self.send_length == Some(self.send_total + n as u64);
+        self.count_sent(n, done)?;
+        match done {
+            true => self.pending_end_stream = true,
+            false => self.body = Some(BodySource::Reader(body)),
+        }
+        Ok(())
+    }
+
+    /// take up to max bytes of a mapped body once the data queued before
+    /// it is all taken, and whether END_STREAM should be set with them
+    pub fn take_mapped(&mut self, max: usize) -> Result<Option<(MappedSlice, bool)>, H2Error> {
+        if !self.pending_data.is_empty() {
+            return Ok(None);
+        }
+        let mut body = match self.body.take() {
+            Some(BodySource::Mapped(body)) => body,
+            other => {
+                self.body = other;
+                return Ok(None);
+            },
+        };
+        let data = body.split_to(max);
+        let done = body.is_empty();
+        self.count_sent(data.len(), done)?;
+        self.transfer.add_data(data.len());
+        if !done {
+            self.body = Some(BodySource::Mapped(body));
+        }
+        Ok(Some((data, done)))
+    }
+
+    /// the end of the stream was already queued (with data, a body or trailers)
+    pub fn is_end_queued(&self) -> bool {
+        self.pending_end_stream || self.pending_trailers.is_some() || self.body.is_some()
+    }
+
+    /// the trailers if all of the data before them has been taken
+    pub fn take_trailers(&mut self) -> Option<HeaderList> {
+        match self.pending_data.is_empty() {
+            true => self.pending_trailers.take(),
+            false => None,
+        }
+    }
+
+    /// take up to max bytes of the queued data (in a buffer from the
+    /// global Pool) and whether END_STREAM should be set with it
+    pub fn take_pending(&mut self, max: usize) -> (PooledBuf, bool) {
+        let n = ::std::cmp::min(max, self.pending_data.len());
+        let mut data = Pool::global().get(n);
+        data.extend(self.pending_data.drain(..n));
+        self.transfer.add_data(n);
+        let end_stream = self.pending_end_stream && self.pending_data.is_empty();
+        if end_stream {
+            self.pending_end_stream = false;
+        }
+        (data, end_stream)
+    }
+
+    /// transition for sending END_STREAM
+    pub fn send_end_stream(&mut self) {
+        use self::StreamState::*;
+        self.state = match self.state {
+            Open => HalfClosedLocal,
+            HalfClosedRemote => Closed,
+            state => state,
+        };
+    }
+
+    /// transition for receiving END_STREAM
+    pub fn recv_end_stream(&mut self) {
+        use self::StreamState::*;
+        self.state = match self.state {
+            Open => HalfClosedRemote,
+            HalfClosedLocal => Closed,
+            state => state,
+        };
+    }
+
+    /// close the stream right away, dropping anything that was
+    /// still queued to be sent (sending or receiving RST_STREAM)
+    pub fn close(&mut self) {
+        self.state = StreamState::Closed;
+        self.pending_data.clear();
+        self.pending_end_stream = false;
+        self.pending_trailers = None;
+        self.body = None;
+    }
+
+    /// 6.4 abandon the stream, the returned RST_STREAM frame
+    /// needs to be sent to let the peer know why
+    pub fn reset(&mut self, error: ErrorCode) -> OwnedFrame {
+        self.close();
+        self.reset = Some((Direction::Sent, error));
+        OwnedFrame::rst_stream(self.id, error as u32)
+    }
+
+    /// the peer reset the stream
+    pub fn recv_reset(&mut self, error: ErrorCode) {
+        self.close();
+        self.reset = Some((Direction::Received, error));
+    }
+
+    pub fn was_reset(&self) -> bool {
+        self.reset.is_some()
+    }
+
+    /// which side reset the stream (the RST_STREAM was sent or received)
+    /// and with what error
+    pub fn reset_by(&self) -> Option<(Direction, ErrorCode)> {
+        self.reset
+    }
+
+    pub fn set_request(&mut self, request: RequestInfo) {
+        self.request = Some(request);
+    }
+
+    pub fn take_request(&mut self) -> Option<RequestInfo> {
+        self.request.take()
+    }
+
+    /// the final status sent on the stream
+    pub fn set_status(&mut self, status: Option<u16>) {
+        self.status = status;
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// how much DATA came in, not counting padding
+    pub fn data_received(&self) -> u64 {
+        self.recv_total
+    }
+
+    /// how much DATA actually went out (not just queued)
+    pub fn data_sent(&self) -> u64 {
+        self.transfer.get().data_bytes
+    }
+
+    /// the count of what the response sent, header blocks included
+    pub fn transfer(&self) -> &TransferCount {
+        &self.transfer
+    }
+
+    /// is the stream open or half closed, which is when
+    /// the stream has flow control windows that matter
+    pub fn is_active(&self) -> bool {
+        use self::StreamState::*;
+        match self.state {
+            Open | HalfClosedLocal | HalfClosedRemote => true,
+            _ => false,
+        }
+    }
+
+    /// can this endpoint still send frames carrying data
+    pub fn can_send(&self) -> bool {
+        use self::StreamState::*;
+        match self.state {
+            Open | HalfClosedRemote => true,
+            _ => false,
+        }
+    }
+}
+
+// the reader of a body that is being sent
+// what the rest of a body comes from
+enum BodySource {
+    Reader(Box<Read>),
+    Mapped(MappedSlice),
+}
+
+impl fmt::Debug for BodySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodySource::Reader(_) => write!(f, "BodySource"),
+            BodySource::Mapped(ref body) => write!(f, "BodySource({:?})", body),
+        }
+    }
+}
+
+/// 8.1.2.6 the value of the content-length header field, a
+/// field that is repeated or is not a number is malformed
+pub fn content_length(headers: &HeaderList) -> Result<Option<u64>, ()> {
+    let mut values = headers.iter().filter(|h| h.name() == "content-length");
+    let length = match values.next() {
+        Some(h) if !h.value().is_empty() && h.value().bytes().all(|b| b.is_ascii_digit()) => h.value().parse().map_err(|_| ())?,
+        Some(_) => return Err(()),
+        None => return Ok(None),
+    };
+    match values.next() {
+        Some(_) => Err(()),
+        None => Ok(Some(length)),
+    }
+}
+
+// the total so far can not be over the length, and has to be all of it at the end
+fn length_matches(length: Option<u64>, total: u64, end_stream: bool) -> bool {
+    match length {
+        Some(length) if end_stream => total == length,
+        Some(length) => total <= length,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+
+    use std::io::Cursor;
+
+    use super::{content_length, Stream, StreamState};
+    use connection::error::{ErrorCode, H2Error};
+    use connection::settings::MAX_WINDOW_SIZE;
+    use header::HeaderList;
+
+    // take_pending with the data as a Vec
+    fn take(stream: &mut Stream, max: usize) -> (Vec<u8>, bool) {
+        let (data, end_stream) = stream.take_pending(max);
+        (data.to_vec(), end_stream)
+    }
+
+    #[test]
+    fn stream_state_transitions() {
+        let mut stream = Stream::new(1, 100);
+        stream.set_state(StreamState::Open);
+
+        stream.recv_end_stream();
+        assert_eq!(stream.state(), StreamState::HalfClosedRemote);
+
+        stream.send_end_stream();
+        assert_eq!(stream.state(), StreamState::Closed);
+    }
+
+    #[test]
+    fn stream_window_overflow() {
+        let mut stream = Stream::new(1, 65535);
+
+        assert!(stream.increase_send_window(100).is_ok());
+        assert_eq!(stream.send_window(), 65635);
+
+        let err = stream.increase_send_window(0x7FFFFFFF).unwrap_err();
+        assert_eq!(err, H2Error::Stream(1, ErrorCode::FlowControlError));
+    }
+
+    #[test]
+    fn stream_window_adjust() {
+        let mut stream = Stream::new(1, 65535);
+        stream.consume_send_window(200);
+
+        stream.adjust_send_window(100 - 65535).unwrap();
+        assert_eq!(stream.send_window(), -100);
+
+        assert!(stream.adjust_send_window((1 << 31) + 100).is_err());
+    }
+
+    #[test]
+    fn stream_recv_window_resize() {
+        let mut stream = Stream::new(1, 65535);
+        stream.consume_recv_window(1000).unwrap();
+        stream.release_recv_window(1000);
+        assert_eq!(stream.recv_window(), 65535 - 1000);
+
+        // growing it is advertised along with what was released
+        stream.set_recv_window(1 << 20);
+        assert_eq!(stream.advertise(), (1 << 20) - 65535 + 1000);
+        assert_eq!(stream.recv_window(), 1 << 20);
+
+        // shrinking it comes out of what is released next
+        stream.consume_recv_window(5000).unwrap();
+        stream.set_recv_window((1 << 20) - 3000);
+        stream.release_recv_window(2000);
+        assert_eq!(stream.advertise(), 0);
+        stream.release_recv_window(3000);
+        assert_eq!(stream.advertise(), 2000);
+        assert_eq!(stream.recv_window(), (1 << 20) - 3000);
+    }
+
+    #[test]
+    fn stream_recv_window_grant() {
+        let mut stream = Stream::new(1, 65535);
+        stream.consume_recv_window(65535).unwrap();
+        stream.grant_recv_window(1000);
+        assert_eq!(stream.advertise(), 1000);
+        assert_eq!(stream.recv_window(), 1000);
+
+        // never past 2^31-1
+        stream.grant_recv_window(MAX_WINDOW_SIZE);
+        stream.grant_recv_window(1);
+        assert_eq!(stream.advertise(), MAX_WINDOW_SIZE - 1000);
+        assert_eq!(stream.recv_window(), MAX_WINDOW_SIZE as i32);
+    }
+
+    #[test]
+    fn stream_pending_data() {
+        let mut stream = Stream::new(1, 100);
+
+        stream.queue_data(&[1, 2, 3, 4], true);
+
+        assert_eq!(take(&mut stream, 3), (vec![1, 2, 3], false));
+        assert!(stream.has_pending_data());
+        assert_eq!(take(&mut stream, 3), (vec![4], true));
+        assert!(!stream.has_pending_data());
+    }
+
+    #[test]
+    fn stream_pending_trailers() {
+        let mut stream = Stream::new(1, 100);
+
+        stream.queue_data(&[1, 2, 3], false);
+        let mut trailers = HeaderList::with_capacity(1);
+        trailers.add_entry(("grpc-status", "0").into());
+        stream.queue_trailers(trailers);
+        assert!(stream.is_end_queued());
+
+        // the data has to go first
+        assert!(stream.take_trailers().is_none());
+        assert_eq!(take(&mut stream, 10), (vec![1, 2, 3], false));
+        assert!(stream.has_pending_data());
+        assert_eq!(stream.take_trailers().unwrap().get_value_by_name("grpc-status"), Some("0"));
+        assert!(!stream.has_pending_data());
+    }
+
+    #[test]
+    fn stream_body() {
+        // without a content-length the body ends when the reader does
+        let mut stream = Stream::new(1, 100);
+        stream.queue_body(Box::new(Cursor::new(vec![1, 2, 3, 4, 5])));
+        assert!(stream.is_end_queued());
+        stream.read_body(3).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![1, 2, 3], false));
+        stream.read_body(3).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![4, 5], false));
+        assert!(stream.has_body());
+        stream.read_body(3).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![], true));
+        assert!(!stream.has_pending_data());
+
+        // with one it ends as soon as that much was read
+        let mut stream = Stream::new(1, 100);
+        stream.set_send_length(Some(4));
+        stream.queue_body(Box::new(Cursor::new(vec![1, 2, 3, 4])));
+        stream.read_body(10).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![1, 2, 3, 4], true));
+        assert!(!stream.has_body());
+
+        // and a reader that comes up short is an error
+        let mut stream = Stream::new(1, 100);
+        stream.set_send_length(Some(10));
+        stream.queue_body(Box::new(Cursor::new(vec![1, 2, 3, 4])));
+        stream.read_body(10).unwrap();
+        assert_eq!(stream.read_body(10), Err(H2Error::Stream(1, ErrorCode::InternalError)));
+
+        // one that goes on (a file still being written) is only read that far
+        let mut stream = Stream::new(1, 100);
+        stream.set_send_length(Some(5));
+        stream.queue_body(Box::new(Cursor::new(vec![1, 2, 3, 4, 5, 6, 7, 8])));
+        stream.read_body(3).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![1, 2, 3], false));
+        stream.read_body(10).unwrap();
+        assert_eq!(take(&mut stream, 10), (vec![4, 5], true));
+        assert!(!stream.has_body());
+    }
+
+    #[test]
+    fn stream_content_length() {
+        // exact
+        let mut stream = Stream::new(1, 100);
+        stream.set_recv_length(Some(10));
+        stream.count_received(4, false).unwrap();
+        stream.count_received(6, true).unwrap();
+
+        // under
+        let mut stream = Stream::new(1, 100);
+        stream.set_recv_length(Some(10));
+        stream.count_received(4, false).unwrap();
+        assert_eq!(stream.count_received(5, true).unwrap_err(), H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // over, caught before the end of the stream
+        let mut stream = Stream::new(1, 100);
+        stream.set_recv_length(Some(10));
+        stream.count_received(8, false).unwrap();
+        assert_eq!(stream.count_received(8, false).unwrap_err(), H2Error::Stream(1, ErrorCode::ProtocolError));
+
+        // sending too much is our own error
+        let mut stream = Stream::new(2, 100);
+        stream.set_send_length(Some(3));
+        assert_eq!(stream.count_sent(4, true).unwrap_err(), H2Error::Stream(2, ErrorCode::InternalError));
+        stream.count_sent(3, true).unwrap();
+
+        // the response to HEAD has the length GET would, and no DATA
+        let mut stream = Stream::new(1, 100);
+        stream.set_head(true);
+        stream.set_send_length(Some(3));
+        assert_eq!(stream.count_sent(3, true).unwrap_err(), H2Error::Stream(1, ErrorCode::InternalError));
+        stream.count_sent(0, true).unwrap();
+    }
+
+    #[test]
+    fn content_length_values() {
+        fn list(values: &[&'static str]) -> HeaderList {
+            let mut list = HeaderList::with_capacity(values.len() + 1);
+            list.add_entry((":status", "200").into());
+            for value in values {
+                list.add_entry(("content-length", *value).into());
+            }
+            list
+        }
+
+        assert_eq!(content_length(&list(&[])), Ok(None));
+        assert_eq!(content_length(&list(&["42"])), Ok(Some(42)));
+        assert!(content_length(&list(&["4 2"])).is_err());
+        assert!(content_length(&list(&["-1"])).is_err());
+        assert!(content_length(&list(&[""])).is_err());
+        assert!(content_length(&list(&["99999999999999999999999"])).is_err());
+        assert!(content_length(&list(&["10", "12"])).is_err());
+    }
+
+    #[test]
+    fn stream_reset() {
+        let mut stream = Stream::new(3, 100);
+        stream.set_state(StreamState::Open);
+        stream.queue_data(&[1, 2, 3], true);
+
+        let rst = stream.reset(ErrorCode::InternalError);
+        assert_eq!(stream.state(), StreamState::Closed);
+        assert!(!stream.has_pending_data());
+        assert_eq!(rst.stream_id(), 3);
+        assert_eq!(rst.payload(), &[0, 0, 0, 2]);
+    }
+}
@@ -0,0 +1,356 @@
+//! A decoded log of every frame sent and received, for debugging
+//! against other implementations
+//!
+//! Each frame is one line saying when, which way, what frame and its
+//! header fields, then a util::hexdump of (at most max_dump bytes of)
+//! the payload. Header blocks can be left out of the dump, they carry the
+//! requests' headers (cookies, authorization) in a form that is easy
+//! enough to decode.
+//!
+//! With window_snapshots the flow control windows of every open stream are
+//! traced too, now and then, as "window snapshot" lines among the frames.
+//! Those show a transfer that stalled, and which side's window it is
+//! waiting on.
+//!
+//! Tracing wraps the reader and writer, so a connection that is not
+//! traced goes through the plain ones:
+//!
+//! ```ignore
+//! let tracer = Tracer::new(TraceSink::writer(File::create("wire.log")?)).redact_headers(true);
+//! let mut reader = TracingFrameReader::new(FrameReader::new(), tracer.clone());
+//! conn.set_tracer(Some(tracer));
+//! ```
+
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use buf::Buf;
+use frame::{Http2Frame, OwnedFrame};
+use frame::frame_types::{types, GenericFrame};
+use log::Context;
+use util::{hexdump_max, Clock, SystemClock};
+use super::reader::FrameReader;
+use super::window::WindowInfo;
+use super::writer::{FrameWriter, WriteFrames};
+
+// how much of a payload is dumped unless the tracer says otherwise
+const DEFAULT_MAX_DUMP : usize = 64;
+
+/// Which way a frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Received => write!(f, "<< recv"),
+            Direction::Sent => write!(f, ">> sent"),
+        }
+    }
+}
+
+/// Where the trace goes
+#[derive(Clone)]
+pub enum TraceSink {
+    /// the log, at the Debug level
+    Log,
+    /// its own stream, shared by every connection traced to it
+    Writer(Arc<Mutex<Box<Write + Send>>>),
+}
+
+impl TraceSink {
+    pub fn writer<W: Write + Send + 'static>(out: W) -> Self {
+        TraceSink::Writer(Arc::new(Mutex::new(Box::new(out))))
+    }
+}
+
+/// Writes the trace lines for a connection
+#[derive(Clone)]
+pub struct Tracer {
+    sink: TraceSink,
+    redact_headers: bool,
+    max_dump: usize,
+    peer: Option<SocketAddr>,
+    clock: Arc<Clock>,
+    snapshot_every: Option<Duration>,
+}
+
+impl Tracer {
+
+    pub fn new(sink: TraceSink) -> Self {
+        Tracer { sink: sink, redact_headers: false, max_dump: DEFAULT_MAX_DUMP, peer: None, clock: SystemClock::shared(), snapshot_every: None }
+    }
+
+    /// leave the payload of HEADERS, PUSH_PROMISE and CONTINUATION
+    /// frames out of the dump (their length is still traced)
+    pub fn redact_headers(mut self, redact: bool) -> Self {
+        self.redact_headers = redact;
+        self
+    }
+
+    /// the most of a payload that is dumped
+    pub fn max_dump(mut self, max: usize) -> Self {
+        self.max_dump = max;
+        self
+    }
+
+    /// where the time of each line comes from
+    pub fn clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// also trace the flow control windows of every open stream, as a
+    /// "window snapshot" line for each, at most once every so often
+    pub fn window_snapshots(mut self, every: Duration) -> Self {
+        self.snapshot_every = Some(every);
+        self
+    }
+
+    /// how often the windows are traced, if they are
+    pub fn snapshot_every(&self) -> Option<Duration> {
+        self.snapshot_every
+    }
+
+    /// the same tracer, for a connection to peer
+    pub fn for_peer(&self, peer: Option<SocketAddr>) -> Self {
+        Tracer { peer: peer, .. self.clone() }
+    }
+
+    /// trace a frame from its header and payload
+    pub fn frame(&self, dir: Direction, header: &[u8], payload: &[u8]) {
+        let line = self.describe(dir, header, payload);
+        self.write_line(line);
+    }
+
+    /// trace the windows of a stream
+    pub fn windows(&self, stream_id: u32, windows: &WindowInfo) {
+        let mut line = self.line_start();
+        let _ = write!(line, "window snapshot stream={} {}", stream_id, windows);
+        self.write_line(line);
+    }
+
+    fn write_line(&self, line: String) {
+        match self.sink {
+            TraceSink::Log => klog_debug!(Context::peer(self.peer) => "{}", line),
+            TraceSink::Writer(ref out) => {
+                let mut out = match out.lock() {
+                    Ok(out) => out,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                // the trace is not worth failing the connection over
+                let _ = writeln!(out, "{}", line);
+            },
+        }
+    }
+
+    // when, and to who
+    fn line_start(&self) -> String {
+        let time = self.clock.system_now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = String::new();
+        let _ = write!(line, "{}.{:06} ", time.as_secs(), time.subsec_micros());
+        if let Some(peer) = self.peer {
+            let _ = write!(line, "{} ", peer);
+        }
+        line
+    }
+
+    fn describe(&self, dir: Direction, header: &[u8], payload: &[u8]) -> String {
+        let frame_type = header[3];
+        let stream_id = ((header[5] as u32) << 24 | (header[6] as u32) << 16 | (header[7] as u32) << 8 | header[8] as u32) & 0x7FFFFFFF;
+
+        let mut line = self.line_start();
+        let _ = write!(line, "{} ", dir);
+        match types::name(frame_type) {
+            Some(name) => line.push_str(name),
+            None => { let _ = write!(line, "UNKNOWN({:#04x})", frame_type); },
+        }
+        let length = (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize;
+        let _ = write!(line, " stream={} flags={:#04x} length={}", stream_id, header[4], length);
+
+        let redacted = self.redact_headers &&
+            (frame_type == types::HEADERS || frame_type == types::PUSH_PROMISE || frame_type == types::CONTINUATION);
+        if redacted {
+            line.push_str(" [header block redacted]");
+        }
+        else if payload.len() < length {
+            line.push_str(" [payload skipped]");
+        }
+        else if !payload.is_empty() && self.max_dump > 0 {
+            line.push('\n');
+            let _ = hexdump_max(payload, self.max_dump, &mut line);
+        }
+        line
+    }
+}
+
+/// A FrameReader that traces every frame it hands out
+pub struct TracingFrameReader {
+    reader: FrameReader,
+    tracer: Tracer,
+}
+
+impl TracingFrameReader {
+
+    pub fn new(reader: FrameReader, tracer: Tracer) -> Self {
+        TracingFrameReader { reader: reader, tracer: tracer }
+    }
+
+    pub fn get_mut(&mut self) -> &mut FrameReader {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> FrameReader {
+        self.reader
+    }
+
+    pub fn read_frame<'a, R: Read>(&'a mut self, stream: &mut R) -> io::Result<Option<GenericFrame<'a>>> {
+        let frame = self.reader.read_frame(stream)?;
+        if let Some(ref frame) = frame {
+            trace_received(&self.tracer, frame);
+        }
+        Ok(frame)
+    }
+}
+
+/// trace a frame that was just read
+pub fn trace_received(tracer: &Tracer, frame: &GenericFrame) {
+    tracer.frame(Direction::Received, &frame.buf()[..9], frame.payload());
+}
+
+/// trace a frame the reader skipped the payload of
+pub fn trace_skipped(tracer: &Tracer, header: &[u8]) {
+    tracer.frame(Direction::Received, &header[..9], &[]);
+}
+
+/// A FrameWriter that traces every frame written with it
+pub struct TracingFrameWriter<W> {
+    writer: FrameWriter<W>,
+    tracer: Tracer,
+}
+
+impl<W: Write> TracingFrameWriter<W> {
+
+    pub fn new(writer: FrameWriter<W>, tracer: Tracer) -> Self {
+        TracingFrameWriter { writer: writer, tracer: tracer }
+    }
+
+    pub fn into_inner(self) -> FrameWriter<W> {
+        self.writer
+    }
+}
+
+impl<W: Write> WriteFrames for TracingFrameWriter<W> {
+
+    fn write_frame(&mut self, frame: &OwnedFrame) -> io::Result<()> {
+        self.writer.write_frame(frame)?;
+        let bytes = frame.as_bytes();
+        self.tracer.frame(Direction::Sent, &bytes[..9], &bytes[9..]);
+        Ok(())
+    }
+
+    fn write_frame_parts(&mut self, header: &[u8; 9], payload: &[u8]) -> io::Result<()> {
+        self.writer.write_frame_parts(header, payload)?;
+        self.tracer.frame(Direction::Sent, header, payload);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+
+    use std::io::{self, Cursor, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::{TraceSink, Tracer, TracingFrameReader, TracingFrameWriter};
+    use super::super::reader::FrameReader;
+    use super::super::writer::{FrameWriter, WriteFrames};
+    use frame::OwnedFrame;
+    use frame::frame_types::flags;
+    use log::capture::capture;
+    use test_util::GET_BLOCK;
+
+    // a Write that can be looked at while the tracer holds it
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the trace without the times
+    fn lines(out: &Shared) -> Vec<String> {
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        out.lines().map(|l| match l.find(' ') {
+            Some(i) if l.contains(" stream=") => l[i + 1..].to_string(),
+            _ => l.to_string(),
+        }).collect()
+    }
+
+    #[test]
+    fn scripted_exchange() {
+        let out = Shared(Arc::new(Mutex::new(Vec::new())));
+        let tracer = Tracer::new(TraceSink::writer(out.clone())).redact_headers(true).max_dump(4);
+
+        let mut input = Vec::new();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS).as_bytes());
+        let mut input = Cursor::new(input);
+        let mut reader = TracingFrameReader::new(FrameReader::new(), tracer.clone());
+        let mut writer = TracingFrameWriter::new(FrameWriter::new(Vec::new()), tracer);
+
+        reader.read_frame(&mut input).unwrap();
+        writer.write_frame(&OwnedFrame::settings_ack()).unwrap();
+        reader.read_frame(&mut input).unwrap();
+        let data = OwnedFrame::data(1, b"hello world", true);
+        let mut header = [0; 9];
+        header.copy_from_slice(&data.as_bytes()[..9]);
+        writer.write_frame_parts(&header, b"hello world").unwrap();
+        writer.write_frame(&OwnedFrame::ping(false, &[1, 2, 3, 4, 5, 6, 7, 8])).unwrap();
+
+        assert_eq!(lines(&out), vec![
+            "<< recv SETTINGS stream=0 flags=0x00 length=0",
+            ">> sent SETTINGS stream=0 flags=0x01 length=0",
+            "<< recv HEADERS stream=1 flags=0x04 length=3 [header block redacted]",
+            ">> sent DATA stream=1 flags=0x01 length=11",
+            "00000000  68 65 6c 6c                                       |hell|",
+            "... 7 more bytes",
+            ">> sent PING stream=0 flags=0x00 length=8",
+            "00000000  01 02 03 04                                       |....|",
+            "... 4 more bytes",
+        ]);
+        // the frames themselves are written as they would be without the trace
+        let mut written = OwnedFrame::settings_ack().as_bytes().to_vec();
+        written.extend_from_slice(data.as_bytes());
+        written.extend_from_slice(OwnedFrame::ping(false, &[1, 2, 3, 4, 5, 6, 7, 8]).as_bytes());
+        assert_eq!(writer.into_inner().into_inner(), written);
+    }
+
+    #[test]
+    fn to_the_log() {
+        let tracer = Tracer::new(TraceSink::Log).for_peer(Some("127.0.0.1:4000".parse().unwrap()));
+        let messages = capture(|| {
+            let mut reader = TracingFrameReader::new(FrameReader::new(), tracer);
+            reader.read_frame(&mut Cursor::new(OwnedFrame::headers(3, &[0x82], flags::END_HEADERS).as_bytes().to_vec())).unwrap();
+        });
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("DEBUG 127.0.0.1:4000: "));
+        assert!(messages[0].ends_with("127.0.0.1:4000 << recv HEADERS stream=3 flags=0x04 length=1\n00000000  82                                                |.|"));
+    }
+}
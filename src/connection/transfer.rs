@@ -0,0 +1,107 @@
+//! What the response on a stream has sent
+//!
+//! The header blocks and DATA of a response are counted as they are
+//! queued, along with the body as the application wrote it. That is the
+//! same as the DATA unless the body is compressed on the way out, then
+//! whatever compresses it counts what goes in with add_body.
+//!
+//! The count is shared, so whoever holds on to it (like the ResponseWriter
+//! of the stream) can still read it once the stream is closed and gone.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// How much a response has sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transfer {
+    /// the HEADERS and CONTINUATION frames, frame headers and all
+    pub header_bytes: u64,
+    /// the body as the application wrote it, before any compression
+    pub body_bytes: u64,
+    /// the payload of the DATA frames, after it
+    pub data_bytes: u64,
+}
+
+impl Transfer {
+    /// the header frames and the DATA payload, what the response took
+    /// up on the connection
+    pub fn wire_bytes(&self) -> u64 {
+        self.header_bytes + self.data_bytes
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    transfer: Cell<Transfer>,
+    compressed: Cell<bool>,
+}
+
+/// A count of the Transfer of one stream, clones count the same one
+#[derive(Debug, Clone, Default)]
+pub struct TransferCount(Rc<Counts>);
+
+impl TransferCount {
+
+    pub fn new() -> Self {
+        TransferCount::default()
+    }
+
+    pub fn get(&self) -> Transfer {
+        self.0.transfer.get()
+    }
+
+    /// the body is compressed before it is sent, from now on the body is
+    /// counted with add_body instead of along with the DATA
+    pub fn set_compressed(&self) {
+        self.0.compressed.set(true);
+    }
+
+    /// n bytes of the body went into compression
+    pub fn add_body(&self, n: usize) {
+        self.update(|t| t.body_bytes += n as u64);
+    }
+
+    /// a header block of n bytes (in however many frames) was queued
+    pub fn add_headers(&self, n: usize) {
+        self.update(|t| t.header_bytes += n as u64);
+    }
+
+    /// n bytes of DATA were queued
+    pub fn add_data(&self, n: usize) {
+        let compressed = self.0.compressed.get();
+        self.update(|t| {
+            t.data_bytes += n as u64;
+            if !compressed {
+                t.body_bytes += n as u64;
+            }
+        });
+    }
+
+    fn update<F: FnOnce(&mut Transfer)>(&self, f: F) {
+        let mut transfer = self.0.transfer.get();
+        f(&mut transfer);
+        self.0.transfer.set(transfer);
+    }
+}
+
+#[cfg(test)]
+mod transfer_tests {
+
+    use super::{Transfer, TransferCount};
+
+    #[test]
+    fn counts() {
+        let count = TransferCount::new();
+        let shared = count.clone();
+        count.add_headers(30);
+        count.add_data(100);
+        assert_eq!(shared.get(), Transfer { header_bytes: 30, body_bytes: 100, data_bytes: 100 });
+
+        // once compressed the body is only what is said to go in
+        count.set_compressed();
+        count.add_body(1000);
+        count.add_data(200);
+        assert_eq!(shared.get(), Transfer { header_bytes: 30, body_bytes: 1100, data_bytes: 300 });
+        assert_eq!(shared.get().wire_bytes(), 330);
+    }
+}
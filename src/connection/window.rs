@@ -0,0 +1,127 @@
+//! When receive window that was released is given back to the peer
+//!
+//! A WINDOW_UPDATE for every chunk of a body that is read makes for a
+//! frame (or two, with the connection's) per DATA frame received. Instead
+//! what is released is added up and only advertised once it is worth a
+//! frame, a share of the window it goes back to. The stream's and the
+//! connection's updates are sent next to each other whenever either is
+//! due, so they go out in the same write.
+//!
+//! The windows of a stream can be looked at as a WindowInfo, which is
+//! what tells a transfer that stalled apart from one that is just slow: a
+//! stream with data queued and no window to send it in is held up by the
+//! peer, and the WindowInfo says whether by the stream's window or the
+//! connection's.
+
+use std::cmp;
+use std::fmt;
+
+/// the share of a window (in percent) released before it is advertised
+pub const DEFAULT_UPDATE_THRESHOLD : u8 = 50;
+
+/// how long (in seconds) a stream can have data queued and no window to
+/// send it in before it is warned about
+pub const DEFAULT_STALL_WARNING : u64 = 10;
+
+/// How released window is advertised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowUpdates {
+    /// as soon as it is released
+    Immediate,
+    /// once at least this percent of the window is released
+    Threshold(u8),
+}
+
+impl WindowUpdates {
+
+    /// is released worth a WINDOW_UPDATE for a window of window_size
+    pub fn due(&self, released: u32, window_size: u32) -> bool {
+        match *self {
+            WindowUpdates::Immediate => released > 0,
+            WindowUpdates::Threshold(percent) =>
+                released > 0 && released as u64 * 100 >= window_size as u64 * percent as u64,
+        }
+    }
+}
+
+impl Default for WindowUpdates {
+    fn default() -> Self {
+        WindowUpdates::Threshold(DEFAULT_UPDATE_THRESHOLD)
+    }
+}
+
+/// The flow control windows of a stream, at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// what the peer lets us send on the stream
+    pub send_stream: i32,
+    /// what the peer lets us send on the connection, which the stream
+    /// shares with every other stream
+    pub send_conn_share: i32,
+    /// what we let the peer send on the stream
+    pub recv_stream: i32,
+    /// what we let the peer send on the connection
+    pub recv_conn: i32,
+}
+
+/// Which window is keeping a stream from sending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowLimit {
+    Stream,
+    Connection,
+    Both,
+}
+
+impl fmt::Display for WindowLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WindowLimit::Stream => write!(f, "stream window"),
+            WindowLimit::Connection => write!(f, "connection window"),
+            WindowLimit::Both => write!(f, "stream and connection windows"),
+        }
+    }
+}
+
+impl WindowInfo {
+
+    /// how much the stream can send right now
+    pub fn sendable(&self) -> u32 {
+        cmp::max(cmp::min(self.send_stream, self.send_conn_share), 0) as u32
+    }
+
+    /// the window that is used up, if nothing can be sent
+    pub fn send_limit(&self) -> Option<WindowLimit> {
+        match (self.send_stream <= 0, self.send_conn_share <= 0) {
+            (true, true) => Some(WindowLimit::Both),
+            (true, false) => Some(WindowLimit::Stream),
+            (false, true) => Some(WindowLimit::Connection),
+            (false, false) => None,
+        }
+    }
+}
+
+impl fmt::Display for WindowInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "send_stream={} send_conn={} recv_stream={} recv_conn={}",
+               self.send_stream, self.send_conn_share, self.recv_stream, self.recv_conn)
+    }
+}
+
+#[cfg(test)]
+mod window_tests {
+
+    use super::{WindowInfo, WindowLimit};
+
+    #[test]
+    fn send_limit() {
+        let info = |send_stream, send_conn_share| WindowInfo { send_stream: send_stream, send_conn_share: send_conn_share, recv_stream: 0, recv_conn: 0 };
+        assert_eq!(info(100, 50).send_limit(), None);
+        assert_eq!(info(100, 50).sendable(), 50);
+        assert_eq!(info(100, 0).send_limit(), Some(WindowLimit::Connection));
+        // a stream window can go below 0 when the peer makes it smaller
+        assert_eq!(info(-10, 50).send_limit(), Some(WindowLimit::Stream));
+        assert_eq!(info(-10, 50).sendable(), 0);
+        assert_eq!(info(0, 0).send_limit(), Some(WindowLimit::Both));
+        assert_eq!(info(1, 2).to_string(), "send_stream=1 send_conn=2 recv_stream=0 recv_conn=0");
+    }
+}
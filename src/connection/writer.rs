@@ -0,0 +1,186 @@
+//! Writing frames to a byte stream
+//!
+//! A frame whose payload is kept apart from its header (like DATA, whose
+//! payload is the response body) is written with write_vectored, so the
+//! two never have to be copied together. Streams that do not do vectored
+//! writes just write the first part each time, either way a short write
+//! carries on from wherever it stopped.
+
+use std::io::{self, IoSlice, Write};
+
+use frame::OwnedFrame;
+
+// size of the frame header
+const HEADER_LEN : usize = 9;
+
+/// Something frames are written to, either straight to the stream
+/// or through something that looks at them on the way (a trace)
+pub trait WriteFrames {
+    fn write_frame(&mut self, frame: &OwnedFrame) -> io::Result<()>;
+
+    /// write a frame header followed by its payload
+    fn write_frame_parts(&mut self, header: &[u8; HEADER_LEN], payload: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+pub struct FrameWriter<W> {
+    out: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+
+    pub fn new(out: W) -> Self {
+        FrameWriter { out: out }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> WriteFrames for FrameWriter<W> {
+
+    fn write_frame(&mut self, frame: &OwnedFrame) -> io::Result<()> {
+        self.out.write_all(frame.as_bytes())
+    }
+
+    fn write_frame_parts(&mut self, header: &[u8; HEADER_LEN], payload: &[u8]) -> io::Result<()> {
+        let total = HEADER_LEN + payload.len();
+        let mut written = 0;
+        while written < total {
+            let res = if written < HEADER_LEN {
+                self.out.write_vectored(&[IoSlice::new(&header[written..]), IoSlice::new(payload)])
+            }
+            else {
+                self.out.write_vectored(&[IoSlice::new(&payload[written - HEADER_LEN..])])
+            };
+            match res {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole frame")),
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod writer_tests {
+
+    use std::io::{self, IoSlice, Write};
+
+    use super::{FrameWriter, WriteFrames};
+    use frame::OwnedFrame;
+    use frame::frame_types::types;
+
+    // takes at most the next step of the script in each write, across
+    // as many of the slices as that covers
+    struct Stingy {
+        out: Vec<u8>,
+        steps: Vec<usize>,
+        vectored_calls: usize,
+    }
+
+    impl Write for Stingy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.vectored_calls += 1;
+            let mut left = if self.steps.is_empty() { usize::max_value() } else { self.steps.remove(0) };
+            if left == 0 {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "try again"));
+            }
+            let mut n = 0;
+            for buf in bufs {
+                let m = ::std::cmp::min(left, buf.len());
+                self.out.extend_from_slice(&buf[..m]);
+                left -= m;
+                n += m;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // only has the default write_vectored, which writes the first slice
+    struct Plain(Vec<u8>);
+
+    impl Write for Plain {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = ::std::cmp::min(buf.len(), 5);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn data_frame() -> (OwnedFrame, [u8; 9]) {
+        let frame = OwnedFrame::data(3, b"some response body", true);
+        let mut header = [0; 9];
+        header.copy_from_slice(&frame.as_bytes()[..9]);
+        (frame, header)
+    }
+
+    #[test]
+    fn whole_vectored_write() {
+        let (frame, header) = data_frame();
+        let mut writer = FrameWriter::new(Stingy { out: Vec::new(), steps: Vec::new(), vectored_calls: 0 });
+        writer.write_frame_parts(&header, frame.payload()).unwrap();
+        let out = writer.into_inner();
+        assert_eq!(out.out, frame.as_bytes());
+        assert_eq!(out.vectored_calls, 1);
+    }
+
+    #[test]
+    fn short_vectored_writes() {
+        let (frame, header) = data_frame();
+        // in the header, right at its end, one past it, interrupted, then the rest
+        for steps in vec![vec![4, 5, 1, 0, 100], vec![9, 18], vec![1; 27], vec![10, 3, 0, 0, 7, 100], vec![26, 1]] {
+            let mut writer = FrameWriter::new(Stingy { out: Vec::new(), steps: steps.clone(), vectored_calls: 0 });
+            writer.write_frame_parts(&header, frame.payload()).unwrap();
+            assert_eq!(&writer.get_mut().out[..], frame.as_bytes(), "steps {:?}", steps);
+        }
+    }
+
+    #[test]
+    fn without_vectored_writes() {
+        let (frame, header) = data_frame();
+        let mut writer = FrameWriter::new(Plain(Vec::new()));
+        writer.write_frame_parts(&header, frame.payload()).unwrap();
+        writer.write_frame(&OwnedFrame::settings_ack()).unwrap();
+        let out = writer.into_inner().0;
+        assert_eq!(&out[..27], frame.as_bytes());
+        assert_eq!(out[27 + 3], types::SETTINGS);
+
+        // an empty payload is just the header
+        let mut writer = FrameWriter::new(Plain(Vec::new()));
+        writer.write_frame_parts(&header, &[]).unwrap();
+        assert_eq!(&writer.into_inner().0[..], &header[..]);
+    }
+
+    #[test]
+    fn write_zero() {
+        let (frame, header) = data_frame();
+        let mut out = [0u8; 12];
+        let err = FrameWriter::new(&mut out[..]).write_frame_parts(&header, frame.payload()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+}
@@ -1,40 +1,122 @@
-//! At the time of creating this, using debug_assertions is experimental
-//! on blocks of code within functions. This allows me to get the debug_assertions
-//! behaviour on any arbitrary expressions.
+//! Extra diagnostics that are off unless asked for
+//!
+//! Code wrapped in drun! only runs when verbose is on, which is either
+//! set with set_verbose or read once from the KURISU_VERBOSE environment
+//! variable ("1" or "true"). It works the same in release builds, so
+//! what it shows can be looked at where the interesting traffic is. When
+//! it is off each drun! is a single relaxed load.
+//!
+//! What drun! blocks print goes through the log module, at the Debug
+//! level, which turning verbose on makes sure is logged:
+//!
+//! ```ignore
+//! drun!(klog_debug!("decoded {} of {}", decoded.len(), decoded.capacity()));
+//! ```
 
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{self, Level};
+
+/// the environment variable verbose is read from
+pub const VERBOSE_VAR : &'static str = "KURISU_VERBOSE";
+
+// the environment has not been read yet, verbose is off, verbose is on
+const UNSET : usize = 0;
+const OFF : usize = 1;
+const ON : usize = 2;
+
+static VERBOSE : AtomicUsize = AtomicUsize::new(UNSET);
+
+/// are drun! blocks run
+#[inline]
+pub fn verbose() -> bool {
+    match VERBOSE.load(Ordering::Relaxed) {
+        OFF => false,
+        ON => true,
+        _ => verbose_from_env(),
+    }
+}
+
+/// turn the drun! blocks on or off, this wins over the environment
+pub fn set_verbose(on: bool) {
+    if on && log::max_level() < Level::Debug {
+        log::set_max_level(Level::Debug);
+    }
+    VERBOSE.store(if on { ON } else { OFF }, Ordering::Relaxed);
+}
+
+// the first time verbose is asked for (unless set_verbose came first)
+#[cold]
+#[inline(never)]
+fn verbose_from_env() -> bool {
+    let on = match env::var(VERBOSE_VAR) {
+        Ok(val) => val == "1" || val.eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    };
+    // a set_verbose that got in first is kept
+    if VERBOSE.compare_exchange(UNSET, OFF, Ordering::Relaxed, Ordering::Relaxed).is_ok() && on {
+        set_verbose(true);
+    }
+    verbose()
+}
 
 /// A macro to wrap around arbitrary expressions
-/// that should only be run in debug builds
-#[cfg(debug_assertions)]
+/// that should only be run when verbose is on
 macro_rules! drun {
     ( $run:expr ) => {
         {
-            println!("=================");
-            $run
-            println!("=================");
+            if $crate::debug::verbose() {
+                $run;
+            }
         }
     };
 }
 
-/// No-op version of the macro for release builds
-#[cfg(not(debug_assertions))]
-macro_rules! drun {
-    ( $run:expr ) => { };
+/// Running code with verbose set, without other tests changing it
+/// part way through
+#[cfg(test)]
+pub mod testing {
+
+    use std::sync::Mutex;
+
+    use super::{set_verbose, verbose};
+
+    lazy_static! {
+        static ref LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    pub fn with_verbose<F: FnOnce()>(on: bool, f: F) {
+        let _lock = match LOCK.lock() {
+            Ok(lock) => lock,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let was = verbose();
+        set_verbose(on);
+        f();
+        set_verbose(was);
+    }
 }
 
 #[cfg(test)]
 mod debug_print_tests {
+
+    use super::testing::with_verbose;
+    use log::capture::capture;
+
     #[allow(unused_assignments)]
     #[test]
     fn drun_test() {
         let mut a = 0i32;
 
-        drun!({ println!("drun"); a = 5i32; });
-
-        debug_assert_eq!(a, 5);
+        let messages = capture(|| with_verbose(true, || drun!({ klog_debug!("drun"); a = 5i32; })));
+        assert_eq!(messages, vec!["DEBUG drun"]);
+        assert_eq!(a, 5);
 
         a = 10i32;
 
+        let messages = capture(|| with_verbose(false, || drun!({ klog_debug!("drun"); a = 5i32; })));
+        assert!(messages.is_empty());
         assert_eq!(a, 10);
     }
 }
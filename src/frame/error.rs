@@ -0,0 +1,51 @@
+//! Which frame an error happened on, as context in an error chain
+
+use std::error::Error;
+use std::fmt;
+
+use krserr::{HasKind, Kind};
+use super::frame_types::types;
+
+/// The frame being processed when something went wrong, it has the
+/// kind of what went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameError {
+    stream_id: u32,
+    frame_type: u8,
+    kind: Kind,
+}
+
+impl FrameError {
+    pub fn new(stream_id: u32, frame_type: u8, kind: Kind) -> Self {
+        FrameError { stream_id: stream_id, frame_type: frame_type, kind: kind }
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub fn frame_type(&self) -> u8 {
+        self.frame_type
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match types::name(self.frame_type) {
+            Some(name) => write!(f, "stream {} {}", self.stream_id, name),
+            None => write!(f, "stream {} frame type {:#x}", self.stream_id, self.frame_type),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn description(&self) -> &str {
+        "Error: FrameError"
+    }
+}
+
+impl HasKind for FrameError {
+    fn kind(&self) -> Kind {
+        self.kind
+    }
+}
@@ -4,29 +4,81 @@
 //! Internet Engineering Task Force (IETF)
 //! Request for Comments: 7540
 
+use std::cell::Cell;
 use std::mem;
 use std::fmt;
 use buf::Buf;
-use super::Http2Frame;
+use super::{FrameHeader, Http2Frame};
 
 use self::flags::*;
 
 // This mod is just used to organize all the flags used by the frames
 pub mod flags {
     pub const END_STREAM : u8 = 0x1;
+    pub const ACK : u8 = 0x1; // SETTINGS and PING only
     pub const END_HEADERS : u8 = 0x4;
     pub const PADDED : u8 = 0x8;
     pub const PRIORITY : u8 = 0x20;
 }
 
+// The type codes for each frame type defined in the spec
+pub mod types {
+    pub const DATA : u8 = 0x0;
+    pub const HEADERS : u8 = 0x1;
+    pub const PRIORITY : u8 = 0x2;
+    pub const RST_STREAM : u8 = 0x3;
+    pub const SETTINGS : u8 = 0x4;
+    pub const PUSH_PROMISE : u8 = 0x5;
+    pub const PING : u8 = 0x6;
+    pub const GOAWAY : u8 = 0x7;
+    pub const WINDOW_UPDATE : u8 = 0x8;
+    pub const CONTINUATION : u8 = 0x9;
+
+    /// the name the spec gives a frame type
+    pub fn name(frame_type: u8) -> Option<&'static str> {
+        match frame_type {
+            DATA => Some("DATA"),
+            HEADERS => Some("HEADERS"),
+            PRIORITY => Some("PRIORITY"),
+            RST_STREAM => Some("RST_STREAM"),
+            SETTINGS => Some("SETTINGS"),
+            PUSH_PROMISE => Some("PUSH_PROMISE"),
+            PING => Some("PING"),
+            GOAWAY => Some("GOAWAY"),
+            WINDOW_UPDATE => Some("WINDOW_UPDATE"),
+            CONTINUATION => Some("CONTINUATION"),
+            _ => None,
+        }
+    }
+}
+
 /// Type used to read initial data from peer.
 /// Used to determine type of frame for further specialization
 pub struct GenericFrame<'buf> {
     buf: &'buf mut [u8],
+    // the length, once it has been read from the buffer
+    parsed_len: Cell<Option<u32>>,
 }
 
-impl_buf!( u8 : buf => GenericFrame; );
-impl<'obj, 'buf> Http2Frame<'obj, 'buf> for GenericFrame<'buf> where 'buf: 'obj {}
+impl_buf!( u8 : buf => GenericFrame { parsed_len: Cell::new(None) }; );
+
+impl<'obj, 'buf> Http2Frame<'obj, 'buf> for GenericFrame<'buf> where 'buf: 'obj {
+    // the length is asked for over and over while a frame is checked,
+    // it is only read from the buffer the first time
+    fn get_length(&'obj self) -> u32 {
+        if let Some(len) = self.parsed_len.get() {
+            return len;
+        }
+        let len = FrameHeader::length(self.buf);
+        self.parsed_len.set(Some(len));
+        len
+    }
+
+    fn set_length(&'obj mut self, len: u32) {
+        FrameHeader::set_length(self.buf, len);
+        self.parsed_len.set(Some(len));
+    }
+}
 
 macro_rules! impl_debug_print {
     ( $($typename:ident),+ ) => {
@@ -68,32 +120,38 @@ macro_rules! create_frame_type {
             $code
     }
 }
-// ==============================================================
-// These functions are used to read numbers from the input stream
-// ==============================================================
-
-// helper function to get 32bit numbers from the big endian input stream
-unsafe fn getu32_from_be(buf: &[u8]) -> u32 {
-    use std::ptr;
-    debug_assert_eq!(buf.len(), 4);
-    let mut num : u32 = mem::uninitialized();
-    ptr::copy_nonoverlapping(buf.as_ptr(), &mut num as *mut u32 as *mut u8, 4);
-    u32::from_be(num)
-}
 
-// helper function to get 16bit numbers from the big endian input stream
-unsafe fn getu16_from_be(buf: &[u8]) -> u16 {
-    use std::ptr;
-    debug_assert_eq!(buf.len(), 2);
-    let mut num : u16 = mem::uninitialized();
-    ptr::copy_nonoverlapping(buf.as_ptr(), &mut num as *mut u16 as *mut u8, 2);
-    u16::from_be(num)
+// the part of a padded payload between the first `start` bytes (the pad
+// length included) and the padding, None when the pad length (which comes
+// from the peer) does not leave room for it
+fn strip_padding(buf: &[u8], start: usize) -> Option<(u8, &[u8])> {
+    let pad = *buf.first()?;
+    let end = buf.len().checked_sub(pad as usize)?;
+    if end < start {
+        return None;
+    }
+    Some((pad, &buf[start..end]))
 }
 
 // ================================================
 // the major header types are defined as follows
 // ================================================
 
+frame_layout! {
+    // the priority fields, of HEADERS (after the pad length, if any) and of
+    // PRIORITY
+    pub(crate) struct PriorityFields {
+        exclusive / set_exclusive : bit @ 0,
+        dependency / set_dependency : u31 @ 0,
+        weight / set_weight : u8 @ 4,
+    }
+}
+
+// (exclusive, stream dependency, weight)
+fn priority_data(buf: &[u8]) -> (bool, u32, u8) {
+    (PriorityFields::exclusive(buf), PriorityFields::dependency(buf), PriorityFields::weight(buf))
+}
+
 /// ===============================
 /// HEADERS
 /// ===============================
@@ -153,48 +211,51 @@ create_frame_type!{
     // Each of these functions first determines the memory layout then
     // and then pulls the correct info
 
-    pub fn get_header_data(&'obj self) -> HeaderData<'obj> {
+    // None when the payload is too short for the fields the flags say are
+    // there, or the padding is longer than what is left of it
+    pub fn get_header_data(&'obj self) -> Option<HeaderData<'obj>> {
         let buf = &self.payload();
 
         use self::PadPrioState::*;
         match self.pad_prio_flags() {
 
             Neither      =>
-                HeaderData {
+                Some(HeaderData {
                     padding: None,
                     priority_data: None,
                     header_block_fragment: &buf[0..],
-                },
+                }),
 
-            PaddedOnly   =>
-                HeaderData {
-                    padding: Some(buf[0]),
+            PaddedOnly   => {
+                let (padding, fragment) = strip_padding(buf, 1)?;
+
+                Some(HeaderData {
+                    padding: Some(padding),
                     priority_data: None,
-                    header_block_fragment: &buf[1..],
-                },
+                    header_block_fragment: fragment,
+                })
+            },
 
             PriorityOnly => {
-                let stream_dep = unsafe { getu32_from_be(&buf[0..4]) };
-                let exclusive = stream_dep & 0x80000000 != 0;
-                let weight = buf[4];
+                if buf.len() < PriorityFields::LEN {
+                    return None;
+                }
 
-                HeaderData {
+                Some(HeaderData {
                     padding: None,
-                    priority_data: Some((exclusive, stream_dep & 0x7FFFFFFF, weight)),
-                    header_block_fragment: &buf[5..],
-                }
+                    priority_data: Some(priority_data(buf)),
+                    header_block_fragment: &buf[PriorityFields::LEN..],
+                })
             },
 
             Both         => {
-                let stream_dep = unsafe { getu32_from_be(&buf[1..5]) };
-                let exclusive = stream_dep & 0x80000000 != 0;
-                let weight = buf[5];
-
-                HeaderData {
-                    padding: Some(buf[0]),
-                    priority_data: Some((exclusive, stream_dep & 0x7FFFFFFF, weight)),
-                    header_block_fragment: &buf[6..],
-                }
+                let (padding, fragment) = strip_padding(buf, 1 + PriorityFields::LEN)?;
+
+                Some(HeaderData {
+                    padding: Some(padding),
+                    priority_data: Some(priority_data(&buf[1..])),
+                    header_block_fragment: fragment,
+                })
             },
 
         }
@@ -223,13 +284,11 @@ create_frame_type!{
         self.get_flags() & PADDED != 0
     }
 
-    pub fn get_data(&'obj self) -> &[u8] {
+    // None when the padding is longer than the frame
+    pub fn get_data(&'obj self) -> Option<&[u8]> {
         match self.padded() {
-            false => &self.payload()[0..],
-            true  => {
-                let end = self.payload().len() - self.payload()[0] as usize;
-                &self.payload()[1..end]
-            }
+            false => Some(&self.payload()[0..]),
+            true  => strip_padding(self.payload(), 1).map(|(_, data)| data),
         }
     }
 
@@ -251,11 +310,7 @@ create_frame_type! {
     PriorityFrame {
 
     pub fn get_priority_info(&'obj self) -> (bool, u32, u8) {
-        let buf = &self.payload()[..];
-        let stream_dep = unsafe { getu32_from_be(&buf[0..4]) };
-        let exclusive = stream_dep & 0x80000000 != 0;
-        let weight = buf[4];
-        (exclusive, stream_dep & 0x7FFFFFFF, weight)
+        priority_data(self.payload())
     }
 } }
 
@@ -269,12 +324,17 @@ create_frame_type! {
 ///  +---------------------------------------------------------------+
 /// Figure 9: RST_STREAM Frame Payload
 
+frame_layout! {
+    pub(crate) struct RstStreamFields {
+        error_code / set_error_code : u32 @ 0,
+    }
+}
+
 create_frame_type! {
     RstStreamFrame {
 
     pub fn get_error_code(&'obj self) -> u32 {
-        let buf = &self.payload()[..];
-        unsafe { getu32_from_be(&buf[0..4]) }
+        RstStreamFields::error_code(self.payload())
     }
 } }
 
@@ -298,18 +358,28 @@ pub struct Settings<'obj> {
     s_buf: &'obj [u8],
 }
 
+frame_layout! {
+    // one parameter, a payload is a run of them
+    pub(crate) struct SettingFields {
+        identifier / set_identifier : u16 @ 0,
+        value / set_value : u32 @ 2,
+    }
+}
+
 impl<'obj> Iterator for Settings<'obj> {
     type Item = (u16, u32); // id / value
 
     fn next(&mut self) -> Option<Self::Item> {
         let buf : &[u8] = &self.s_buf;
-        if buf.len() == 0 {
+        // a length that is not a multiple of 6 is checked for before this,
+        // what does not make a whole parameter is left out
+        if buf.len() < SettingFields::LEN {
             None
         }
         else {
-            let id = unsafe { getu16_from_be(&buf[0..2]) };
-            let value = unsafe { getu32_from_be(&buf[2..6]) };
-            self.s_buf = &buf[6..];
+            let id = SettingFields::identifier(buf);
+            let value = SettingFields::value(buf);
+            self.s_buf = &buf[SettingFields::LEN..];
             Some((id, value))
         }
     }
@@ -320,9 +390,8 @@ create_frame_type! {
 
     // return an array filled with the setting parameters from the frame
     pub fn get_settings_paramaters(&'obj self) -> Settings {
-        let length = self.get_length();
-        debug_assert!(length % 6 == 0); // should probably make this a hard check and return an error
-        // actually just note here that a lot more error checking should be done
+        // the connection makes it a FRAME_SIZE_ERROR before getting here
+        debug_assert!(self.payload().len() % SettingFields::LEN == 0);
         Settings { s_buf: &self.payload()[..] }
     }
 } }
@@ -343,6 +412,13 @@ create_frame_type! {
 ///  +---------------------------------------------------------------+
 /// Figure 11: PUSH_PROMISE Payload Format
 
+frame_layout! {
+    // after the pad length, if any
+    pub(crate) struct PushPromiseFields {
+        promised_id / set_promised_id : u31 @ 0,
+    }
+}
+
 create_frame_type! {
     PushPromiseFrame {
 
@@ -350,19 +426,17 @@ create_frame_type! {
         self.get_flags() & PADDED != 0
     }
 
-    // return the stream id for the push and a ref to the header block fragment
-    pub fn get_push_data(&'obj self) -> (u32, &[u8]) {
-        let (padding, buf) = match self.padded() {
-            true  => {
-                (self.payload()[0], &self.payload()[1..])
-            },
-            false => {
-                (0, &self.payload()[0..])
-            },
+    // return the stream id for the push and a ref to the header block fragment,
+    // None when the payload is too short for the id or the padding
+    pub fn get_push_data(&'obj self) -> Option<(u32, &[u8])> {
+        let buf = match self.padded() {
+            true  => strip_padding(self.payload(), 1)?.1,
+            false => &self.payload()[0..],
         };
-        let id = unsafe { getu32_from_be(&buf[..4]) };
-        let end = buf.len() - padding as usize;
-        (id & 0x7FFFFFFF, &buf[4..end])
+        if buf.len() < PushPromiseFields::LEN {
+            return None;
+        }
+        Some((PushPromiseFields::promised_id(buf), &buf[PushPromiseFields::LEN..]))
     }
 } }
 
@@ -403,14 +477,20 @@ create_frame_type! {
 ///  +---------------------------------------------------------------+
 /// Figure 13: GOAWAY Payload Format
 
+frame_layout! {
+    // the debug data is what follows
+    pub(crate) struct GoAwayFields {
+        last_stream_id / set_last_stream_id : u31 @ 0,
+        error_code / set_error_code : u32 @ 4,
+    }
+}
+
 create_frame_type! {
     GoAwayFrame {
 
     pub fn get_go_away_info(&'obj self) -> (u32, u32, &'obj [u8]) {
-        let buf = &self.payload();
-        let last_stread_id = unsafe { getu32_from_be(&buf[0..4]) & 0x7FFFFFFF };
-        let error_code = unsafe { getu32_from_be(&buf[4..8]) };
-        (last_stread_id, error_code, &buf[8..])
+        let buf = self.payload();
+        (GoAwayFields::last_stream_id(buf), GoAwayFields::error_code(buf), &buf[GoAwayFields::LEN..])
     }
 } }
 
@@ -424,13 +504,19 @@ create_frame_type! {
 ///  +-+-------------------------------------------------------------+
 /// Figure 14: WINDOW_UPDATE Payload Format
 
+frame_layout! {
+    pub(crate) struct WindowUpdateFields {
+        increment / set_increment : u31 @ 0,
+    }
+}
+
 create_frame_type! {
     WindowUpdateFrame {
 
     pub fn get_window_update(&'obj self) -> u32 {
-        let buf = &self.payload()[..];
-        debug_assert_eq!(buf.len(), 4);
-        unsafe { getu32_from_be(buf) }
+        let buf = self.payload();
+        debug_assert_eq!(buf.len(), WindowUpdateFields::LEN);
+        WindowUpdateFields::increment(buf)
     }
 } }
 
@@ -469,7 +555,7 @@ mod frame_type_tests {
 
         let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
 
-        let h_data = headers.get_header_data();
+        let h_data = headers.get_header_data().unwrap();
 
         assert_eq!(None, h_data.padding);
         assert_eq!(None, h_data.priority_data);
@@ -484,11 +570,11 @@ mod frame_type_tests {
 
         let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
 
-        let h_data = headers.get_header_data();
+        let h_data = headers.get_header_data().unwrap();
 
         assert_eq!(Some(15), h_data.padding);
         assert_eq!(None, h_data.priority_data);
-        assert_eq!(h_data.header_block_fragment[..], bc[10..]);
+        assert_eq!(h_data.header_block_fragment[..], bc[10..bc.len() - 15]);
 
         //================================
         // PriorityOnly
@@ -499,7 +585,7 @@ mod frame_type_tests {
 
         let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
 
-        let h_data = headers.get_header_data();
+        let h_data = headers.get_header_data().unwrap();
 
         assert_eq!(None, h_data.padding);
         assert_eq!(Some((true, 31, 255)), h_data.priority_data);
@@ -514,11 +600,11 @@ mod frame_type_tests {
 
         let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
 
-        let h_data = headers.get_header_data();
+        let h_data = headers.get_header_data().unwrap();
 
         assert_eq!(Some(15), h_data.padding);
         assert_eq!(Some((true, 31, 255)), h_data.priority_data);
-        assert_eq!(h_data.header_block_fragment[..], bc[15..]);
+        assert_eq!(h_data.header_block_fragment[..], bc[15..bc.len() - 15]);
     }
 
     #[test]
@@ -529,7 +615,43 @@ mod frame_type_tests {
 
         let data : DataFrame = GenericFrame::point_to(&mut buf).into();
 
-        assert_eq!(data.get_data()[..], bc[10..12]);
+        assert_eq!(data.get_data().unwrap()[..], bc[10..12]);
+    }
+
+    #[test]
+    fn padding_longer_than_payload() {
+        // pad lengths that reach past the data used to underflow
+        for payload in &[&[0x04, 0xFF, 0xFF, 0x10][..], &[0x03, 0xFF, 0xFF][..], &[][..]] {
+            let mut buf = vec![0x00, 0x00, payload.len() as u8, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01];
+            buf.extend_from_slice(payload);
+            let data : DataFrame = GenericFrame::point_to(&mut buf).into();
+            assert!(data.get_data().is_none(), "{:?}", payload);
+        }
+        // all padding is fine
+        let mut buf = vec![0x00, 0x00, 0x03, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00];
+        let data : DataFrame = GenericFrame::point_to(&mut buf).into();
+        assert_eq!(data.get_data(), Some(&[][..]));
+
+        // HEADERS with PADDED and PRIORITY, too short for the priority fields
+        // or with more padding than block
+        for payload in &[&[0x00, 0x80, 0x00][..], &[0x02, 0x80, 0x00, 0x00, 0x01, 0x10, 0x82][..]] {
+            let mut buf = vec![0x00, 0x00, payload.len() as u8, 0x01, 0x2C, 0x00, 0x00, 0x00, 0x01];
+            buf.extend_from_slice(payload);
+            let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
+            assert!(headers.get_header_data().is_none(), "{:?}", payload);
+        }
+        // PRIORITY only, short of the weight
+        let mut buf = vec![0x00, 0x00, 0x04, 0x01, 0x24, 0x00, 0x00, 0x00, 0x01, 0x80, 0x00, 0x00, 0x01];
+        let headers : HeadersFrame = GenericFrame::point_to(&mut buf).into();
+        assert!(headers.get_header_data().is_none());
+
+        // PUSH_PROMISE with padding over the promised id
+        let mut buf = vec![0x00, 0x00, 0x05, 0x05, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x02];
+        let push : PushPromiseFrame = GenericFrame::point_to(&mut buf).into();
+        assert!(push.get_push_data().is_none());
+        let mut buf = vec![0x00, 0x00, 0x06, 0x05, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00];
+        let push : PushPromiseFrame = GenericFrame::point_to(&mut buf).into();
+        assert_eq!(push.get_push_data(), Some((2, &[][..])));
     }
 
     #[test]
@@ -571,7 +693,7 @@ mod frame_type_tests {
 
         let push_frame : PushPromiseFrame = GenericFrame::point_to(&mut buf).into();
 
-        assert_eq!(push_frame.get_push_data(), (7, &bc[13..]));
+        assert_eq!(push_frame.get_push_data(), Some((7, &bc[13..])));
     }
 
     #[test]
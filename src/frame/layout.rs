@@ -0,0 +1,194 @@
+//! Where the fields of a frame (or of one part of its payload) are, said
+//! once instead of as offsets and shifts in every method that reads them
+//!
+//! frame_layout! takes a list of fields, each with the names of its getter
+//! and setter, its width and the octet it starts at, and gives a type with
+//! those functions for a buffer of the layout, plus LEN, the octets the
+//! fields take up (where whatever follows them starts).
+//!
+//! ```ignore
+//! frame_layout! {
+//!     pub struct FrameHeader {
+//!         length / set_length : u24 @ 0,
+//!         frame_type / set_frame_type : u8 @ 3,
+//!         flags / set_flags : u8 @ 4,
+//!         stream_id / set_stream_id : u31 @ 5,
+//!     }
+//! }
+//! ```
+//!
+//! The widths are u8, u16, u24 (read as a u32), u32, u31 (the low 31 bits
+//! of a u32, the bit above them is reserved) and bit (that reserved bit, as
+//! a bool). Setting a u31 clears the bit above it, so a bit that shares its
+//! octet is set after it. Numbers are in network byte order, and a buffer
+//! too short for a field panics like any out of bounds index.
+
+macro_rules! frame_layout {
+    // the type a field is read as
+    ( @type u8 ) => { u8 };
+    ( @type u16 ) => { u16 };
+    ( @type u24 ) => { u32 };
+    ( @type u31 ) => { u32 };
+    ( @type u32 ) => { u32 };
+    ( @type bit ) => { bool };
+
+    // the octets a field takes up
+    ( @octets u8 ) => { 1 };
+    ( @octets u16 ) => { 2 };
+    ( @octets u24 ) => { 3 };
+    ( @octets u31 ) => { 4 };
+    ( @octets u32 ) => { 4 };
+    ( @octets bit ) => { 1 };
+
+    ( @get u8, $buf:ident, $at:expr ) => {
+        $buf[$at]
+    };
+    ( @get u16, $buf:ident, $at:expr ) => {
+        (($buf[$at] as u16) << 8 | $buf[$at + 1] as u16)
+    };
+    ( @get u24, $buf:ident, $at:expr ) => {
+        (($buf[$at] as u32) << 16 | ($buf[$at + 1] as u32) << 8 | $buf[$at + 2] as u32)
+    };
+    ( @get u31, $buf:ident, $at:expr ) => {
+        (frame_layout!(@get u32, $buf, $at) & 0x7FFFFFFF)
+    };
+    ( @get u32, $buf:ident, $at:expr ) => {
+        (($buf[$at] as u32) << 24 | ($buf[$at + 1] as u32) << 16 | ($buf[$at + 2] as u32) << 8 | $buf[$at + 3] as u32)
+    };
+    ( @get bit, $buf:ident, $at:expr ) => {
+        ($buf[$at] & 0x80 != 0)
+    };
+
+    ( @set u8, $buf:ident, $at:expr, $value:ident ) => {
+        $buf[$at] = $value;
+    };
+    ( @set u16, $buf:ident, $at:expr, $value:ident ) => {
+        $buf[$at] = ($value >> 8) as u8;
+        $buf[$at + 1] = $value as u8;
+    };
+    ( @set u24, $buf:ident, $at:expr, $value:ident ) => {
+        debug_assert_eq!($value >> 24, 0);
+        $buf[$at] = ($value >> 16) as u8;
+        $buf[$at + 1] = ($value >> 8) as u8;
+        $buf[$at + 2] = $value as u8;
+    };
+    ( @set u31, $buf:ident, $at:expr, $value:ident ) => {
+        debug_assert_eq!($value & 0x80000000, 0);
+        let $value = $value & 0x7FFFFFFF;
+        frame_layout!(@set u32, $buf, $at, $value);
+    };
+    ( @set u32, $buf:ident, $at:expr, $value:ident ) => {
+        $buf[$at] = ($value >> 24) as u8;
+        $buf[$at + 1] = ($value >> 16) as u8;
+        $buf[$at + 2] = ($value >> 8) as u8;
+        $buf[$at + 3] = $value as u8;
+    };
+    ( @set bit, $buf:ident, $at:expr, $value:ident ) => {
+        $buf[$at] = if $value { $buf[$at] | 0x80 } else { $buf[$at] & 0x7F };
+    };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $( $get:ident / $set:ident : $width:ident @ $at:expr ),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy)]
+        $vis struct $name;
+
+        #[allow(dead_code)]
+        impl $name {
+            /// the octets the fields take up
+            pub const LEN : usize = {
+                #[allow(unused_mut)]
+                let mut len = 0;
+                $(
+                    let end = $at + frame_layout!(@octets $width);
+                    if end > len {
+                        len = end;
+                    }
+                )*
+                len
+            };
+
+            $(
+                pub fn $get(buf: &[u8]) -> frame_layout!(@type $width) {
+                    frame_layout!(@get $width, buf, $at)
+                }
+
+                pub fn $set(buf: &mut [u8], value: frame_layout!(@type $width)) {
+                    frame_layout!(@set $width, buf, $at, value);
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod layout_tests {
+
+    frame_layout! {
+        struct Every {
+            flag / set_flag : bit @ 0,
+            id / set_id : u31 @ 0,
+            byte / set_byte : u8 @ 4,
+            short / set_short : u16 @ 5,
+            length / set_length : u24 @ 7,
+            word / set_word : u32 @ 10,
+        }
+    }
+
+    frame_layout! {
+        struct Empty {}
+    }
+
+    static BYTES : &'static [u8] = &[0x81, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0xF1, 0xF2, 0xF3, 0xF4];
+
+    #[test]
+    fn length() {
+        assert_eq!(Every::LEN, 14);
+        assert_eq!(Empty::LEN, 0);
+    }
+
+    #[test]
+    fn read_each_width() {
+        assert_eq!(Every::flag(BYTES), true);
+        assert_eq!(Every::id(BYTES), 0x01020304);
+        assert_eq!(Every::byte(BYTES), 0x05);
+        assert_eq!(Every::short(BYTES), 0x0607);
+        assert_eq!(Every::length(BYTES), 0x08090A);
+        assert_eq!(Every::word(BYTES), 0xF1F2F3F4);
+        // a longer buffer reads the same
+        let mut longer = BYTES.to_vec();
+        longer.push(0xFF);
+        assert_eq!(Every::word(&longer), 0xF1F2F3F4);
+    }
+
+    #[test]
+    fn write_each_width() {
+        let mut buf = [0u8; 14];
+        Every::set_id(&mut buf, 0x01020304);
+        Every::set_flag(&mut buf, true);
+        Every::set_byte(&mut buf, 0x05);
+        Every::set_short(&mut buf, 0x0607);
+        Every::set_length(&mut buf, 0x08090A);
+        Every::set_word(&mut buf, 0xF1F2F3F4);
+        assert_eq!(&buf[..], BYTES);
+
+        // the bit and the number under it are set apart
+        Every::set_flag(&mut buf, false);
+        assert_eq!(Every::id(&buf), 0x01020304);
+        assert_eq!(buf[0], 0x01);
+        Every::set_flag(&mut buf, true);
+        Every::set_id(&mut buf, 0x7FFFFFFF);
+        assert_eq!(&buf[..4], &[0x7F, 0xFF, 0xFF, 0xFF]);
+        assert!(!Every::flag(&buf));
+    }
+
+    #[test]
+    #[should_panic]
+    fn short_buffer() {
+        Every::word(&BYTES[..13]);
+    }
+}
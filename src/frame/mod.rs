@@ -35,11 +35,31 @@
 //! The structure and content of the frame payload is dependent entirely on the frame type.
 //!
 
-use std::mem;
-
 use buf::Buf;
 
+#[macro_use]
+mod layout;
+mod error;
 pub mod frame_types;
+mod owned_frame;
+pub mod padding;
+mod view;
+
+pub use self::error::FrameError;
+pub use self::owned_frame::OwnedFrame;
+pub use self::padding::{Padder, PaddingCalc, PaddingError, PaddingPolicy};
+pub use self::view::{FrameHeaderView, PayloadView};
+
+frame_layout! {
+    /// Where the fields of the 9 octet frame header are, LEN is where the
+    /// payload starts
+    pub struct FrameHeader {
+        length / set_length : u24 @ 0,
+        frame_type / set_frame_type : u8 @ 3,
+        flags / set_flags : u8 @ 4,
+        stream_id / set_stream_id : u31 @ 5,
+    }
+}
 
 /// The Basic methods defined for all types of HTTP2 Frames.
 /// The types that define more specific Frames all implement this
@@ -52,72 +72,54 @@ pub trait Http2Frame<'obj, 'buf> : Buf<'obj, 'buf, u8> {
     // immutable functions for Http2Frame
     // =============================
     fn get_length(&'obj self) -> u32 {
-        let buf = self.buf();
-        u32::from_be( unsafe { mem::transmute([ 0u8, buf[0], buf[1], buf[2] ]) } )
+        FrameHeader::length(self.buf())
     }
 
     fn get_type(&'obj self) -> u8 {
-        self.buf()[3]
+        FrameHeader::frame_type(self.buf())
     }
 
     fn get_flags(&'obj self) -> u8 {
-        self.buf()[4]
+        FrameHeader::flags(self.buf())
     }
 
     fn get_stream_id(&'obj self) -> u32 {
-        let buf = self.buf();
-        u32::from_be( unsafe { mem::transmute([ buf[5] & 0x7F, buf[6], buf[7], buf[8] ]) } )
+        FrameHeader::stream_id(self.buf())
     }
 
     fn payload(&'obj self) -> &[u8] {
-        &self.buf()[9..]
+        &self.buf()[FrameHeader::LEN..]
     }
 
     // mutable functions for Http2Frame
     // =============================
     fn set_length(&'obj mut self, len: u32) {
-        let len_u8 : &[u8; 4] = unsafe { mem::transmute(&len.to_be()) };
-        debug_assert_eq!(len_u8[0], 0);
-        let buf = self.mut_buf();
-        buf[0] = len_u8[1];
-        buf[1] = len_u8[2];
-        buf[2] = len_u8[3];
+        FrameHeader::set_length(self.mut_buf(), len);
     }
 
     fn set_type(&'obj mut self, f_type: u8) {
-        self.mut_buf()[3] = f_type;
+        FrameHeader::set_frame_type(self.mut_buf(), f_type);
     }
 
     fn set_flags(&'obj mut self, f_flags: u8) {
-        self.mut_buf()[4] = f_flags;
+        FrameHeader::set_flags(self.mut_buf(), f_flags);
     }
 
     fn set_stream_id(&'obj mut self, s_identifier: u32) {
-        let ident_u8 : &[u8; 4] = unsafe { mem::transmute(&s_identifier.to_be()) };
-        debug_assert_eq!(ident_u8[0] & 0x80, 0);
-        let buf = self.mut_buf();
-        buf[5] = ident_u8[0];
-        buf[6] = ident_u8[1];
-        buf[7] = ident_u8[2];
-        buf[8] = ident_u8[3];
+        FrameHeader::set_stream_id(self.mut_buf(), s_identifier);
     }
 
     fn mut_payload(&'obj mut self) -> &mut [u8] {
-        &mut self.mut_buf()[9..]
+        &mut self.mut_buf()[FrameHeader::LEN..]
     }
-}
 
-/// convenience macro to impl Http2Frame for listed types
-/// automatically give Buf trait for u8 type and member name buf
-/// all types that use this macro must then have that member
-//macro_rules! impl_http2_frame{
-//    ( $($typename:ty),+ ) => {
-//        $(
-//            impl_buf!( u8 : buf => $typename ; );
-//            impl Http2Frame for $typename {}
-//        )*
-//    }
-//}
+    /// take the frame apart into its header and payload, which can
+    /// then be used (and changed) separately
+    fn split_payload(self) -> (FrameHeaderView<'buf>, PayloadView<'buf>) where Self: Sized {
+        let (header, payload) = self.into_buf().split_at_mut(FrameHeader::LEN);
+        (FrameHeaderView::point_to(header), PayloadView::point_to(payload))
+    }
+}
 
 #[cfg(test)]
 mod http2_frame_tests {
@@ -161,6 +163,28 @@ mod http2_frame_tests {
 
         assert_eq!(frame.buf()[..], TST_FRAME[..]);
     }
+
+    #[test]
+    fn split_payload_test() {
+        let mut buf = TST_FRAME.to_vec();
+        buf.extend_from_slice(&[0x01, 0x02]);
+
+        {
+            let frame = GenericFrame::point_to(&mut buf);
+            let (mut header, mut payload) = frame.split_payload();
+
+            // each part changed while the other is held
+            payload.mut_buf()[0] = 0x7F;
+            header.set_flags(0x04);
+            assert_eq!(header.get_length(), 238);
+            assert_eq!(header.get_stream_id(), 1);
+            assert!(header.payload().is_empty());
+            assert_eq!(payload.len(), 3);
+            assert_eq!(payload.view(1..3), Some(&[0x01, 0x02][..]));
+        }
+        assert_eq!(buf[4], 0x04);
+        assert_eq!(buf[9], 0x7F);
+    }
 }
 
 // test buffer from Google Chrome
@@ -0,0 +1,230 @@
+//! Frames that are built locally to be sent to the peer.
+//! Unlike the types in frame_types these own their buffer, so
+//! they can be queued up by the connection until they are written.
+
+use buf::{Buf, Pool, PooledBuf};
+use super::{FrameHeader, Http2Frame};
+use super::frame_types::{GenericFrame, types, flags};
+use super::padding::{PaddingCalc, PaddingError};
+use super::frame_types::{GoAwayFields, RstStreamFields, SettingFields, WindowUpdateFields};
+
+/// A complete frame (header and payload) in a single owned buffer,
+/// from the global Pool
+pub struct OwnedFrame {
+    buf: PooledBuf,
+}
+
+impl OwnedFrame {
+
+    /// the 9 byte header of a frame with a payload of length bytes,
+    /// for when the payload is kept (and written) apart from it
+    pub fn header(f_type: u8, f_flags: u8, s_identifier: u32, length: usize) -> [u8; 9] {
+        let mut header = [0u8; 9];
+        {
+            let mut frame = GenericFrame::point_to(&mut header);
+            frame.set_length(length as u32);
+            frame.set_type(f_type);
+            frame.set_flags(f_flags);
+            frame.set_stream_id(s_identifier);
+        }
+        header
+    }
+
+    /// put a frame back together from its header and payload
+    pub fn from_parts(header: &[u8; 9], payload: &[u8]) -> Self {
+        let mut buf = Pool::global().get(FrameHeader::LEN + payload.len());
+        buf.extend_from_slice(header);
+        buf.extend_from_slice(payload);
+        OwnedFrame { buf }
+    }
+
+    /// allocate a frame and fill in the header fields and payload
+    pub fn new(f_type: u8, f_flags: u8, s_identifier: u32, payload: &[u8]) -> Self {
+        let mut buf = Pool::global().get(FrameHeader::LEN + payload.len());
+        buf.resize(FrameHeader::LEN + payload.len(), 0);
+        {
+            let mut frame = GenericFrame::point_to(&mut buf);
+            frame.set_length(payload.len() as u32);
+            frame.set_type(f_type);
+            frame.set_flags(f_flags);
+            frame.set_stream_id(s_identifier);
+            frame.mut_payload().copy_from_slice(payload);
+        }
+        OwnedFrame { buf }
+    }
+
+    /// a frame of a type that can be padded, with fields (like the promised
+    /// stream of a PUSH_PROMISE) then content as its payload, padded with pad
+    /// bytes if there is a pad, as long as it all fits within calc
+    pub fn padded(f_type: u8, f_flags: u8, s_identifier: u32, fields: &[u8], content: &[u8], pad: Option<u8>,
+                  calc: &PaddingCalc) -> Result<Self, PaddingError> {
+        let len = calc.payload_len(fields.len() + content.len(), pad)?;
+        let mut payload = Vec::with_capacity(len);
+        let f_flags = match pad {
+            Some(pad) => {
+                payload.push(pad);
+                f_flags | flags::PADDED
+            },
+            None => f_flags & !flags::PADDED,
+        };
+        payload.extend_from_slice(fields);
+        payload.extend_from_slice(content);
+        payload.resize(len, 0);
+        Ok(OwnedFrame::new(f_type, f_flags, s_identifier, &payload))
+    }
+
+    pub fn data(s_identifier: u32, data: &[u8], end_stream: bool) -> Self {
+        let f_flags = if end_stream { flags::END_STREAM } else { 0 };
+        OwnedFrame::new(types::DATA, f_flags, s_identifier, data)
+    }
+
+    pub fn data_padded(s_identifier: u32, data: &[u8], end_stream: bool, pad: Option<u8>, calc: &PaddingCalc)
+        -> Result<Self, PaddingError> {
+        let f_flags = if end_stream { flags::END_STREAM } else { 0 };
+        OwnedFrame::padded(types::DATA, f_flags, s_identifier, &[], data, pad, calc)
+    }
+
+    pub fn headers(s_identifier: u32, header_block: &[u8], f_flags: u8) -> Self {
+        OwnedFrame::new(types::HEADERS, f_flags, s_identifier, header_block)
+    }
+
+    pub fn headers_padded(s_identifier: u32, header_block: &[u8], f_flags: u8, pad: Option<u8>, calc: &PaddingCalc)
+        -> Result<Self, PaddingError> {
+        OwnedFrame::padded(types::HEADERS, f_flags, s_identifier, &[], header_block, pad, calc)
+    }
+
+    pub fn push_promise(s_identifier: u32, promised_id: u32, header_block: &[u8], f_flags: u8, pad: Option<u8>,
+                        calc: &PaddingCalc) -> Result<Self, PaddingError> {
+        let promised = [(promised_id >> 24) as u8 & 0x7F, (promised_id >> 16) as u8, (promised_id >> 8) as u8, promised_id as u8];
+        OwnedFrame::padded(types::PUSH_PROMISE, f_flags, s_identifier, &promised, header_block, pad, calc)
+    }
+
+    pub fn rst_stream(s_identifier: u32, error_code: u32) -> Self {
+        let mut payload = [0u8; RstStreamFields::LEN];
+        RstStreamFields::set_error_code(&mut payload, error_code);
+        OwnedFrame::new(types::RST_STREAM, 0, s_identifier, &payload)
+    }
+
+    // each parameter is (identifier, value)
+    pub fn settings(params: &[(u16, u32)]) -> Self {
+        let mut payload = vec![0u8; params.len() * SettingFields::LEN];
+        for (chunk, &(id, value)) in payload.chunks_mut(SettingFields::LEN).zip(params) {
+            SettingFields::set_identifier(chunk, id);
+            SettingFields::set_value(chunk, value);
+        }
+        OwnedFrame::new(types::SETTINGS, 0, 0, &payload)
+    }
+
+    pub fn settings_ack() -> Self {
+        OwnedFrame::new(types::SETTINGS, flags::ACK, 0, &[])
+    }
+
+    pub fn ping(ack: bool, data: &[u8; 8]) -> Self {
+        let f_flags = if ack { flags::ACK } else { 0 };
+        OwnedFrame::new(types::PING, f_flags, 0, data)
+    }
+
+    pub fn go_away(last_stream_id: u32, error_code: u32, debug_data: &[u8]) -> Self {
+        let mut payload = vec![0u8; GoAwayFields::LEN + debug_data.len()];
+        GoAwayFields::set_last_stream_id(&mut payload, last_stream_id & 0x7FFFFFFF);
+        GoAwayFields::set_error_code(&mut payload, error_code);
+        payload[GoAwayFields::LEN..].copy_from_slice(debug_data);
+        OwnedFrame::new(types::GOAWAY, 0, 0, &payload)
+    }
+
+    pub fn window_update(s_identifier: u32, increment: u32) -> Self {
+        let mut payload = [0u8; WindowUpdateFields::LEN];
+        WindowUpdateFields::set_increment(&mut payload, increment & 0x7FFFFFFF);
+        OwnedFrame::new(types::WINDOW_UPDATE, 0, s_identifier, &payload)
+    }
+
+    /// view the frame through the same interface as received frames
+    pub fn as_frame(&mut self) -> GenericFrame {
+        GenericFrame::point_to(&mut self.buf)
+    }
+
+    /// the raw bytes to be written to the peer
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn frame_type(&self) -> u8 {
+        FrameHeader::frame_type(&self.buf)
+    }
+
+    pub fn frame_flags(&self) -> u8 {
+        FrameHeader::flags(&self.buf)
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        FrameHeader::stream_id(&self.buf)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buf[FrameHeader::LEN..]
+    }
+}
+
+#[cfg(test)]
+mod owned_frame_tests {
+
+    use super::OwnedFrame;
+    use frame::Http2Frame;
+    use frame::frame_types::{flags, DataFrame, GoAwayFrame, HeadersFrame, WindowUpdateFrame};
+    use frame::padding::{PaddingCalc, PaddingError};
+
+    #[test]
+    fn build_window_update() {
+        let mut frame = OwnedFrame::window_update(3, 400);
+
+        assert_eq!(frame.as_bytes(), &[0x00, 0x00, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x01, 0x90]);
+
+        let wu: WindowUpdateFrame = frame.as_frame().into();
+        assert_eq!(wu.get_stream_id(), 3);
+        assert_eq!(wu.get_window_update(), 400);
+    }
+
+    #[test]
+    fn build_go_away() {
+        let mut frame = OwnedFrame::go_away(2, 5, b"03");
+
+        let ga: GoAwayFrame = frame.as_frame().into();
+        assert_eq!(ga.get_go_away_info(), (2, 5, &b"03"[..]));
+    }
+
+    #[test]
+    fn build_padded() {
+        let calc = PaddingCalc::new(16384);
+
+        let mut frame = OwnedFrame::data_padded(1, b"abc", true, Some(4), &calc).unwrap();
+        assert_eq!(frame.payload(), &[4, b'a', b'b', b'c', 0, 0, 0, 0]);
+        assert_eq!(frame.frame_flags(), flags::END_STREAM | flags::PADDED);
+        let data: DataFrame = frame.as_frame().into();
+        assert_eq!(data.get_data(), Some(&b"abc"[..]));
+
+        // filling the frame exactly
+        let block = vec![0x82; 16373];
+        let mut frame = OwnedFrame::headers_padded(3, &block, flags::END_HEADERS, Some(10), &calc).unwrap();
+        assert_eq!(frame.payload().len(), 16384);
+        let headers: HeadersFrame = frame.as_frame().into();
+        let header_data = headers.get_header_data().unwrap();
+        assert_eq!(header_data.padding, Some(10));
+        assert!(header_data.header_block_fragment == &block[..]);
+        assert_eq!(OwnedFrame::headers_padded(3, &block, flags::END_HEADERS, Some(11), &calc).err(),
+                   Some(PaddingError::PadTooLarge { pad: 11, room: 10 }));
+        assert_eq!(OwnedFrame::headers_padded(3, &vec![0x82; 16384], flags::END_HEADERS, Some(0), &calc).err(),
+                   Some(PaddingError::PayloadTooLarge { len: 16385, max: 16384 }));
+        // without padding there is room for one more byte
+        assert!(OwnedFrame::headers_padded(3, &vec![0x82; 16384], flags::END_HEADERS, None, &calc).is_ok());
+
+        // the promised stream counts as part of the frame
+        let frame = OwnedFrame::push_promise(1, 2, &[0x82, 0x84], flags::END_HEADERS, Some(2), &calc).unwrap();
+        assert_eq!(frame.payload(), &[2, 0, 0, 0, 2, 0x82, 0x84, 0, 0]);
+        assert_eq!(frame.frame_flags(), flags::END_HEADERS | flags::PADDED);
+        let frame = OwnedFrame::push_promise(1, 2, &[0x82, 0x84], flags::END_HEADERS | flags::PADDED, None, &calc).unwrap();
+        assert_eq!(frame.payload(), &[0, 0, 0, 2, 0x82, 0x84]);
+        assert_eq!(frame.frame_flags(), flags::END_HEADERS);
+        assert!(OwnedFrame::push_promise(1, 2, &vec![0x82; 16380], 0, None, &calc).is_ok());
+        assert!(OwnedFrame::push_promise(1, 2, &vec![0x82; 16381], 0, None, &calc).is_err());
+    }
+}
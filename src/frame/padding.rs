@@ -0,0 +1,221 @@
+//! Padding the frames that can have it (6.1, 6.2 and 6.6)
+//!
+//! A padded DATA, HEADERS or PUSH_PROMISE frame starts with a pad length
+//! byte and ends with that many zeros, all of which count towards the
+//! length of the frame (and of a DATA frame, towards flow control). What
+//! is left for the fields and data of the frame is worked out here in one
+//! place, by a PaddingCalc for the peer's SETTINGS_MAX_FRAME_SIZE, so a
+//! frame that would be too long is an error instead of something the peer
+//! has to reject.
+//!
+//! How much padding is added is up to a PaddingPolicy. What it picks is
+//! cut down to what fits, it never makes a frame too long.
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+/// Why a padded frame can not be built
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingError {
+    /// the fields and data (with the pad length byte, if it is padded)
+    /// are already longer than the max frame size
+    PayloadTooLarge { len: usize, max: usize },
+    /// the frame would fit, but not with that much padding
+    PadTooLarge { pad: u8, room: usize },
+}
+
+impl fmt::Display for PaddingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaddingError::PayloadTooLarge { len, max } => write!(f, "payload of {} bytes is over the max frame size {}", len, max),
+            PaddingError::PadTooLarge { pad, room } => write!(f, "padding of {} bytes with room for {}", pad, room),
+        }
+    }
+}
+
+impl Error for PaddingError {
+    fn description(&self) -> &str {
+        "Error: PaddingError"
+    }
+}
+
+/// The lengths of padded frames, for a max frame size
+///
+/// The content of a frame is everything but the pad length and the
+/// padding: the other fields (the promised stream of a PUSH_PROMISE, the
+/// priority of a HEADERS) and the data or header block fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaddingCalc {
+    max_frame_size: usize,
+}
+
+impl PaddingCalc {
+
+    pub fn new(max_frame_size: u32) -> Self {
+        PaddingCalc { max_frame_size: max_frame_size as usize }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// the length of the payload of a frame with content_len bytes of
+    /// content, padded with pad bytes (and the pad length byte) if any
+    pub fn payload_len(&self, content_len: usize, pad: Option<u8>) -> Result<usize, PaddingError> {
+        let unpadded = content_len + pad.map_or(0, |_| 1);
+        if unpadded > self.max_frame_size {
+            return Err(PaddingError::PayloadTooLarge { len: unpadded, max: self.max_frame_size });
+        }
+        let pad = pad.unwrap_or(0);
+        if unpadded + pad as usize > self.max_frame_size {
+            return Err(PaddingError::PadTooLarge { pad: pad, room: self.max_frame_size - unpadded });
+        }
+        Ok(unpadded + pad as usize)
+    }
+
+    /// the most padding a frame with content_len bytes of content can
+    /// have, None when there is not even room for the pad length
+    pub fn max_pad(&self, content_len: usize) -> Option<u8> {
+        let room = self.max_frame_size.checked_sub(content_len + 1)?;
+        Some(::std::cmp::min(room, 255) as u8)
+    }
+
+    /// pad, cut down to what fits with content_len bytes of content
+    pub fn clamp(&self, content_len: usize, pad: Option<u8>) -> Option<u8> {
+        pad.and_then(|pad| self.max_pad(content_len).map(|max| ::std::cmp::min(pad, max)))
+    }
+
+    /// the most content a frame padded with pad can have
+    pub fn max_content(&self, pad: Option<u8>) -> usize {
+        self.max_frame_size.saturating_sub(pad.map_or(0, |pad| 1 + pad as usize))
+    }
+}
+
+/// How much the frames a connection sends are padded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// not at all, the default
+    None,
+    /// always by this many bytes
+    Fixed(u8),
+    /// by anything from 0 up to this many bytes, picked for every frame
+    Random(u8),
+}
+
+impl Default for PaddingPolicy {
+    fn default() -> Self {
+        PaddingPolicy::None
+    }
+}
+
+/// Picks the padding for each frame as a PaddingPolicy says
+#[derive(Debug, Clone)]
+pub struct Padder {
+    policy: PaddingPolicy,
+    // xorshift, which is random enough to hide the lengths of frames
+    state: u32,
+}
+
+impl Padder {
+
+    pub fn new(policy: PaddingPolicy) -> Self {
+        // the keys of a RandomState are different every time
+        let seed = RandomState::new().build_hasher().finish();
+        Padder::with_seed(policy, seed as u32)
+    }
+
+    /// a Padder that picks the same amounts for the same seed
+    pub fn with_seed(policy: PaddingPolicy, seed: u32) -> Self {
+        // xorshift never gets out of 0
+        Padder { policy: policy, state: if seed == 0 { 0x9e3779b9 } else { seed } }
+    }
+
+    pub fn policy(&self) -> PaddingPolicy {
+        self.policy
+    }
+
+    /// the padding for a frame with content_len bytes of content, as much
+    /// as the policy picks or as fits, whichever is less
+    pub fn pad(&mut self, content_len: usize, calc: &PaddingCalc) -> Option<u8> {
+        let pad = match self.policy {
+            PaddingPolicy::None => return None,
+            PaddingPolicy::Fixed(pad) => pad,
+            PaddingPolicy::Random(max) => (self.next() % (max as u32 + 1)) as u8,
+        };
+        calc.clamp(content_len, Some(pad))
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
+impl Default for Padder {
+    fn default() -> Self {
+        Padder::new(PaddingPolicy::None)
+    }
+}
+
+#[cfg(test)]
+mod padding_tests {
+
+    use super::{Padder, PaddingCalc, PaddingError, PaddingPolicy};
+
+    #[test]
+    fn boundaries() {
+        let calc = PaddingCalc::new(16384);
+
+        // exactly the max frame size, unpadded and padded
+        assert_eq!(calc.payload_len(16384, None), Ok(16384));
+        assert_eq!(calc.payload_len(16385, None), Err(PaddingError::PayloadTooLarge { len: 16385, max: 16384 }));
+        assert_eq!(calc.payload_len(16383, Some(0)), Ok(16384));
+        assert_eq!(calc.payload_len(16373, Some(10)), Ok(16384));
+        // the pad length byte alone takes it over
+        assert_eq!(calc.payload_len(16384, Some(0)), Err(PaddingError::PayloadTooLarge { len: 16385, max: 16384 }));
+        assert_eq!(calc.payload_len(16374, Some(10)), Err(PaddingError::PadTooLarge { pad: 10, room: 9 }));
+
+        assert_eq!(calc.max_pad(16383), Some(0));
+        assert_eq!(calc.max_pad(16384), None);
+        assert_eq!(calc.max_pad(16000), Some(255));
+        assert_eq!(calc.max_pad(16200), Some(183));
+        assert_eq!(calc.max_content(None), 16384);
+        assert_eq!(calc.max_content(Some(0)), 16383);
+        assert_eq!(calc.max_content(Some(255)), 16128);
+
+        assert_eq!(calc.clamp(16200, Some(200)), Some(183));
+        assert_eq!(calc.clamp(16200, Some(100)), Some(100));
+        assert_eq!(calc.clamp(16384, Some(100)), None);
+        assert_eq!(calc.clamp(100, None), None);
+    }
+
+    #[test]
+    fn policies() {
+        let calc = PaddingCalc::new(16384);
+        assert_eq!(Padder::new(PaddingPolicy::None).pad(10, &calc), None);
+        assert_eq!(Padder::new(PaddingPolicy::Fixed(32)).pad(10, &calc), Some(32));
+        assert_eq!(Padder::new(PaddingPolicy::Fixed(32)).pad(16360, &calc), Some(23));
+
+        // anything up to the max, and every time a frame that fits
+        let mut padder = Padder::with_seed(PaddingPolicy::Random(255), 1);
+        let mut seen = [false; 256];
+        for _ in 0..10000 {
+            let pad = padder.pad(10, &calc).unwrap();
+            seen[pad as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+        for content_len in 16100..16384 {
+            let pad = padder.pad(content_len, &calc);
+            assert!(pad.is_some());
+            assert!(calc.payload_len(content_len, pad).is_ok());
+        }
+        assert_eq!(padder.pad(16384, &calc), None);
+
+        let mut padder = Padder::with_seed(PaddingPolicy::Random(8), 7);
+        assert!((0..1000).all(|_| padder.pad(10, &calc).unwrap() <= 8));
+    }
+}
@@ -0,0 +1,33 @@
+//! The two parts of a received frame, taken apart so the payload can
+//! be handed on (to a request body, say) without copying it out while
+//! the header is still looked at
+
+use buf::Buf;
+use super::Http2Frame;
+
+/// The 9 octet header of a split frame, read (and changed) with the
+/// usual Http2Frame methods, its payload is always empty
+pub struct FrameHeaderView<'buf> {
+    buf: &'buf mut [u8],
+}
+
+impl_buf!( u8 : buf => FrameHeaderView; );
+impl<'obj, 'buf> Http2Frame<'obj, 'buf> for FrameHeaderView<'buf> where 'buf: 'obj {}
+
+/// The payload of a split frame, everything after the header
+pub struct PayloadView<'buf> {
+    buf: &'buf mut [u8],
+}
+
+impl_buf!( u8 : buf => PayloadView; );
+
+impl<'buf> PayloadView<'buf> {
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
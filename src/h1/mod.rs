@@ -0,0 +1,121 @@
+//! Just enough HTTP/1.1 to read the request a client sends when it
+//! wants to upgrade a cleartext connection to HTTP/2 (h2c)
+//!
+//! RFC 7230 3. Message Format
+//!
+//! ```text
+//! HTTP-message   = start-line
+//!                  *( header-field CRLF )
+//!                  CRLF
+//!                  [ message-body ]
+//! ```
+
+use std::str;
+
+/// the longest request head that will be accepted
+pub const MAX_HEAD_SIZE : usize = 8192;
+
+/// The request line and header fields of an HTTP/1.1 request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    // names are kept as they were sent (they are case insensitive)
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+
+    /// the value of the first header field with the name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    /// does a comma separated header field contain token
+    /// (compared case insensitively, as for Connection and Upgrade)
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|&(_, ref v)| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+/// find the end of the request head (the empty line)
+/// returning the size of the head including it
+pub fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// parse a complete request head as found with head_len
+pub fn parse_request(head: &[u8]) -> Result<RequestHead, &'static str> {
+    let head = str::from_utf8(head).map_err(|_| "h1: request is not valid utf8")?;
+    let mut lines = head.split("\r\n");
+
+    // request-line = method SP request-target SP HTTP-version CRLF
+    let request_line = lines.next().ok_or("h1: missing request line")?;
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+        _ => return Err("h1: malformed request line"),
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err("h1: unsupported version");
+    }
+
+    // header-field = field-name ":" OWS field-value OWS
+    let mut headers = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let colon = line.find(':').ok_or("h1: header field without a colon")?;
+        let (name, value) = line.split_at(colon);
+        if name.is_empty() || name.ends_with(' ') || name.ends_with('\t') {
+            return Err("h1: malformed header field name");
+        }
+        headers.push((name.to_string(), value[1..].trim().to_string()));
+    }
+
+    Ok(RequestHead {
+        method: method.to_string(),
+        target: target.to_string(),
+        version: version.to_string(),
+        headers: headers,
+    })
+}
+
+#[cfg(test)]
+mod h1_tests {
+
+    use super::*;
+
+    static UPGRADE : &'static [u8] = b"GET /index.html HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Connection: Upgrade, HTTP2-Settings\r\n\
+        Upgrade: h2c\r\n\
+        HTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\
+        \r\n";
+
+    #[test]
+    fn parse_upgrade_request() {
+        assert_eq!(head_len(UPGRADE), Some(UPGRADE.len()));
+        assert_eq!(head_len(&UPGRADE[..UPGRADE.len() - 1]), None);
+
+        let req = parse_request(UPGRADE).unwrap();
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.target, "/index.html");
+        assert_eq!(req.header("host"), Some("example.com"));
+        assert!(req.has_token("connection", "http2-settings"));
+        assert!(req.has_token("upgrade", "h2c"));
+        assert!(!req.has_token("upgrade", "websocket"));
+    }
+
+    #[test]
+    fn parse_bad_requests() {
+        assert!(parse_request(b"GET /\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/2.0\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost example.com\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost : example.com\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,1231 @@
+//! Handler
+//!
+//! What an application implements to answer requests. The handler is
+//! called once the headers of a request are in, along with a writer for
+//! the response, and reads the body from the request as it arrives.
+//!
+//! Reading a body that is not all there yet reads and processes frames
+//! from the peer until it is, requests on other streams that come in
+//! meanwhile are handled after the handler returns.
+//!
+//! Extended CONNECT requests (RFC 8441) go to a TunnelHandler instead,
+//! when the server's config has one.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use connection::Connection;
+use connection::budget::MemoryBudget;
+use connection::error::ErrorCode;
+use connection::event::Event;
+use connection::limits::HeaderBlockLimits;
+use connection::reader::{FrameReader, ReadFrame};
+use connection::trace::{self, Tracer};
+use log::Context;
+use request::{Body, BodyQueue, Method, Pump, Request, RequestError, StreamError};
+use response::{Response, ResponseWriter};
+use server::{Config, ShutdownHandle};
+use util::Clock;
+
+mod tunnel;
+
+pub use self::tunnel::{Tunnel, TunnelHandler};
+
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: Request, resp: ResponseWriter);
+}
+
+impl<F> Handler for F where F: Fn(Request, ResponseWriter) + Send + Sync {
+    fn handle(&self, req: Request, resp: ResponseWriter) {
+        self(req, resp)
+    }
+}
+
+// the connection being served and the socket it runs over
+struct Serving<S> {
+    conn: Rc<RefCell<Connection>>,
+    stream: S,
+    reader: FrameReader,
+    // what traces the frames read, if they are traced
+    tracer: Option<Tracer>,
+    // where the DATA for bodies that are still arriving goes
+    bodies: HashMap<u32, Rc<RefCell<BodyQueue>>>,
+    // requests waiting to be handled
+    requests: VecDeque<(u32, Request)>,
+    // handed to the bodies so they can pump
+    this: Option<Weak<RefCell<Pump>>>,
+    // how long the connection can go without a frame while no stream is open
+    idle_timeout: Option<Duration>,
+    last_frame: Instant,
+    // when the client's SETTINGS (the end of its preface) has to be in by
+    preface_deadline: Option<Instant>,
+    // the server shutting down, and how long the open streams get to finish
+    shutdown: Option<ShutdownHandle>,
+    grace: Duration,
+    // the end of that time, once the GOAWAY is sent
+    draining: Option<Instant>,
+    // what all of those times are read from
+    clock: Arc<Clock>,
+}
+
+impl<S: Read + Write> Serving<S> {
+
+    // sort out what happened on the connection, new requests are
+    // queued and DATA goes to the body it belongs to
+    fn take_events(&mut self) {
+        loop {
+            let event = match self.conn.borrow_mut().poll_event() {
+                Some(event) => event,
+                None => return,
+            };
+            match event {
+                Event::Headers { stream_id, headers, end_stream } => {
+                    match Request::from_header_list(headers) {
+                        // RFC 8441 4 a :protocol is only understood once it was advertised
+                        Ok(ref req) if req.protocol().is_some() && !self.conn.borrow().local_settings().enable_connect_protocol => {
+                            klog_debug!(Context::peer(self.conn.borrow().peer_addr()).stream(stream_id) => "extended CONNECT is not enabled");
+                            self.conn.borrow_mut().reset_stream(stream_id, ErrorCode::ProtocolError);
+                        },
+                        Ok(mut req) => {
+                            if !end_stream {
+                                let queue = Rc::new(RefCell::new(BodyQueue::new()));
+                                self.bodies.insert(stream_id, queue.clone());
+                                req.set_body(Body::streaming(stream_id, queue, self.this.clone().unwrap()));
+                            }
+                            self.requests.push_back((stream_id, req));
+                        },
+                        // a path that can not be decoded is well formed, just a bad request
+                        Err(e @ RequestError::InvalidPath(_)) => {
+                            let context = Context::peer(self.conn.borrow().peer_addr()).stream(stream_id);
+                            klog_debug!(context => "{}", e);
+                            if let Err(e) = ResponseWriter::shared(self.conn.clone(), stream_id).send(Response::new(400)) {
+                                klog_debug!(context => "{}", e);
+                            }
+                        },
+                        Err(e) => {
+                            klog_debug!(Context::peer(self.conn.borrow().peer_addr()).stream(stream_id) => "{}", e);
+                            self.conn.borrow_mut().reset_stream(stream_id, ErrorCode::ProtocolError);
+                        },
+                    }
+                },
+                Event::Data { stream_id, data, end_stream } => {
+                    match self.bodies.get(&stream_id) {
+                        Some(queue) => {
+                            queue.borrow_mut().push(data);
+                            if end_stream {
+                                queue.borrow_mut().finish();
+                            }
+                        },
+                        // nobody is reading it any more
+                        None => self.conn.borrow_mut().release_window(stream_id, data.len()),
+                    }
+                    if end_stream {
+                        self.bodies.remove(&stream_id);
+                    }
+                },
+                Event::Trailers { stream_id, .. } => {
+                    if let Some(queue) = self.bodies.remove(&stream_id) {
+                        queue.borrow_mut().finish();
+                    }
+                },
+                Event::StreamReset { stream_id, error, by_peer } => {
+                    if let Some(queue) = self.bodies.remove(&stream_id) {
+                        let e = if by_peer { StreamError::ResetByPeer(error) } else { StreamError::Reset(error) };
+                        queue.borrow_mut().fail(e);
+                    }
+                },
+                // handlers have no use for them, they are only noted
+                Event::ExtensionFrame { frame_type, flags, stream_id, payload } => {
+                    klog_debug!(Context::peer(self.conn.borrow().peer_addr()).stream(stream_id) =>
+                                "extension frame type 0x{:02X} flags 0x{:02X} of {} bytes", frame_type, flags, payload.len());
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // stop reading a body, releasing what was not read (and letting
+    // the peer send the rest, however the handler metered it)
+    fn discard_body(&mut self, stream_id: u32) {
+        if let Some(queue) = self.bodies.remove(&stream_id) {
+            let unread = queue.borrow().len();
+            let mut conn = self.conn.borrow_mut();
+            let _ = conn.set_auto_release(stream_id, true);
+            let _ = conn.pause_recv_window(stream_id, false);
+            conn.release_window(stream_id, unread);
+        }
+    }
+}
+
+impl<S: Read + Write> Pump for Serving<S> {
+
+    // write what is queued, then read and dispatch the next frame
+    fn pump(&mut self) -> io::Result<bool> {
+        let mut conn = self.conn.borrow_mut();
+
+        // once the server is shutting down no new streams are taken,
+        // the open ones get until the end of the grace period to finish
+        // (the GOAWAY is written ahead of the DATA they have queued)
+        if self.draining.is_none() && self.shutdown.as_ref().map_or(false, |s| s.is_shutdown()) {
+            conn.go_away(ErrorCode::NoError);
+            self.draining = Some(self.clock.now() + self.grace);
+        }
+        conn.write_outbound(&mut self.stream)?;
+        if let Some(draining) = self.draining {
+            if conn.is_idle() || self.clock.now() >= draining {
+                return Ok(false);
+            }
+        }
+
+        // a stream that is open may be waiting on the handler, or the
+        // handler on it, so only an idle connection can time out
+        let idle = match self.idle_timeout {
+            Some(timeout) if conn.is_idle() => Some(self.last_frame + timeout),
+            _ => None,
+        };
+        // with a shutdown to watch for, every read that times out comes back here
+        let tick = self.shutdown.as_ref().map(|_| self.clock.now());
+        let deadline = vec![self.preface_deadline, conn.header_block_deadline(), conn.settings_ack_deadline(), conn.stall_deadline(), idle, tick]
+            .into_iter().filter_map(|d| d).min();
+        self.reader.set_deadline(deadline);
+
+        // a frame that only gets an error or dropped is not read in
+        let res = match self.reader.read_frame_or_skip(&mut self.stream, |header| conn.skips_payload(header)) {
+            Ok(Some(ReadFrame::Frame(frame))) => {
+                if let Some(ref tracer) = self.tracer {
+                    trace::trace_received(tracer, &frame);
+                }
+                conn.receive_frame(frame)
+            },
+            Ok(Some(ReadFrame::Skipped(header))) => {
+                if let Some(ref tracer) = self.tracer {
+                    trace::trace_skipped(tracer, &header);
+                }
+                conn.receive_skipped(&header)
+            },
+            Ok(None) => return Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut && deadline.is_some() => {
+                let now = self.clock.now();
+                if self.preface_deadline.map_or(false, |d| now >= d) {
+                    // the client never finished its preface, just close
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "no connection preface"));
+                }
+                match conn.check_timeouts() {
+                    Err(e) => Err(e),
+                    Ok(()) if idle.map_or(false, |d| now >= d) => {
+                        klog_debug!(Context::peer(conn.peer_addr()) => "idle timeout");
+                        conn.go_away(ErrorCode::NoError);
+                        conn.write_outbound(&mut self.stream)?;
+                        return Ok(false);
+                    },
+                    // nothing is due yet
+                    Ok(()) => return Ok(true),
+                }
+            },
+            Err(e) => return Err(e),
+        };
+        self.last_frame = self.clock.now();
+        self.preface_deadline = None;
+
+        // only a connection error is left, stream errors were handled
+        if let Err(e) = res {
+            klog_debug!(Context::peer(conn.peer_addr()) => "{}", e);
+            conn.report_error(&e);
+            conn.write_outbound(&mut self.stream)?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        drop(conn);
+        self.take_events();
+        Ok(true)
+    }
+
+    fn release(&mut self, stream_id: u32, n: usize) {
+        self.conn.borrow_mut().release_window(stream_id, n);
+    }
+
+    fn set_recv_window(&mut self, stream_id: u32, size: u32) {
+        // the body was done with, or the stream reset, in the meantime
+        let _ = self.conn.borrow_mut().set_recv_window(stream_id, size);
+    }
+
+    fn pause_recv_window(&mut self, stream_id: u32, paused: bool) {
+        let _ = self.conn.borrow_mut().pause_recv_window(stream_id, paused);
+    }
+
+    fn set_auto_release(&mut self, stream_id: u32, auto_release: bool) {
+        let _ = self.conn.borrow_mut().set_auto_release(stream_id, auto_release);
+    }
+
+    fn grant(&mut self, stream_id: u32, n: u32) {
+        let _ = self.conn.borrow_mut().grant_recv_window(stream_id, n);
+    }
+}
+
+impl Connection {
+
+    /// run a connection until the peer closes it, answering
+    /// every request on it with handler
+    ///
+    /// Bodies that are not read by the time the handler returns are
+    /// dropped as they come in.
+    pub fn serve<S, H>(stream: S, allow_upgrade: bool, handler: Arc<H>) -> io::Result<()>
+        where S: Read + Write + 'static, H: Handler + ?Sized {
+
+        Connection::serve_with(stream, None, allow_upgrade, &Config::default(), None, handler)
+    }
+
+    /// serve as with serve, for a peer at peer_addr (if it is known)
+    /// and set up as the server's config says
+    ///
+    /// When the shutdown handle is triggered the client is sent a GOAWAY,
+    /// and the connection is closed once the streams that are open finish
+    /// (or the config's shutdown_grace is up).
+    ///
+    /// The timeouts only work if reads from the stream time out on their
+    /// own once in a while (a socket with Config::read_timeout). A
+    /// connection that is idle for too long is sent a GOAWAY and closed,
+    /// one that does not send its whole preface (up to its SETTINGS) in
+    /// time is just closed, and a header block that takes too long ends
+    /// the connection with ENHANCE_YOUR_CALM.
+    pub fn serve_with<S, H>(mut stream: S, peer_addr: Option<SocketAddr>, allow_upgrade: bool, config: &Config,
+                            shutdown: Option<&ShutdownHandle>, handler: Arc<H>) -> io::Result<()>
+        where S: Read + Write + 'static, H: Handler + ?Sized {
+
+        let clock = config.clock().clone();
+        let preface_deadline = config.handshake_timeout().map(|timeout| clock.now() + timeout);
+        let (mut conn, mut reader) = Connection::handshake_within(&mut stream, allow_upgrade, config.settings().clone(), preface_deadline,
+                                                                  clock.clone())?;
+        let limits = HeaderBlockLimits { timeout: config.header_block_timeout(), .. *conn.header_block_limits() };
+        conn.set_header_block_limits(limits);
+        conn.set_stream_limits(*config.stream_limits());
+        conn.set_window_updates(config.window_updates());
+        conn.set_settings_timeout(config.settings_timeout());
+        conn.set_deliver_extension_frames(config.extension_frames());
+        conn.set_padding(config.padding());
+        conn.set_stall_warning(config.stall_warning());
+        // the reader's buffers count against the connection's budget
+        let budget = MemoryBudget::new(config.memory_budget());
+        reader.set_budget(Some(budget.clone()));
+        conn.set_memory_budget(budget);
+        conn.set_peer_addr(peer_addr);
+        conn.set_access_log(config.access_log().cloned());
+        conn.set_observer(config.observer().cloned());
+        let tracer = config.trace().map(|tracer| tracer.for_peer(peer_addr).clock(clock.clone()));
+        conn.set_tracer(tracer.clone());
+        let serving = Rc::new(RefCell::new(Serving {
+            conn: Rc::new(RefCell::new(conn)),
+            stream: stream,
+            reader: reader,
+            tracer: tracer,
+            bodies: HashMap::new(),
+            requests: VecDeque::new(),
+            this: None,
+            idle_timeout: config.idle_timeout(),
+            last_frame: clock.now(),
+            preface_deadline: preface_deadline,
+            shutdown: shutdown.cloned(),
+            grace: config.shutdown_grace(),
+            draining: None,
+            clock: clock,
+        }));
+        let pump: Rc<RefCell<Pump>> = serving.clone();
+        serving.borrow_mut().this = Some(Rc::downgrade(&pump));
+        let conn = serving.borrow().conn.clone();
+
+        // the request of an h2c upgrade
+        serving.borrow_mut().take_events();
+
+        loop {
+            let next = serving.borrow_mut().requests.pop_front();
+            match next {
+                Some((stream_id, req)) => {
+                    match (req.protocol().is_some(), config.tunnel_handler()) {
+                        (true, Some(tunnels)) => {
+                            let pump = serving.borrow().this.clone().unwrap();
+                            handle_tunnel(&**tunnels, &conn, pump, stream_id, req);
+                        },
+                        (true, None) => { let _ = ResponseWriter::shared(conn.clone(), stream_id).send(Response::new(501)); },
+                        (false, _) => handle_request(&*handler, &conn, stream_id, req),
+                    }
+                    serving.borrow_mut().discard_body(stream_id);
+                },
+                None => if !serving.borrow_mut().pump()? {
+                    break;
+                },
+            }
+        }
+
+        let mut serving = serving.borrow_mut();
+        let serving = &mut *serving;
+        let res = serving.conn.borrow_mut().write_outbound(&mut serving.stream);
+        res
+    }
+}
+
+// call the handler, making sure the stream gets an answer even if the
+// handler did not send a whole response or panicked: a 500 when nothing
+// was sent yet, otherwise RST_STREAM with INTERNAL_ERROR
+fn handle_request<H: Handler + ?Sized>(handler: &H, conn: &Rc<RefCell<Connection>>, stream_id: u32, req: Request) {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut resp = ResponseWriter::shared(conn.clone(), stream_id);
+        resp.set_head(*req.method() == Method::Head);
+        resp.set_accepts_gzip(req.accepts_encoding("gzip"));
+        handler.handle(req, resp);
+    }));
+    finish_stream(conn, stream_id, res.is_err());
+}
+
+// call the tunnel handler with the request's body as what the tunnel
+// reads, the stream is finished like a request's is
+fn handle_tunnel(handler: &TunnelHandler, conn: &Rc<RefCell<Connection>>, pump: Weak<RefCell<Pump>>, stream_id: u32, mut req: Request) {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let body = mem::replace(req.body(), Body::empty());
+        let tunnel = Tunnel::new(conn.clone(), stream_id, body, pump);
+        handler.handle(req, tunnel);
+    }));
+    finish_stream(conn, stream_id, res.is_err());
+}
+
+fn finish_stream(conn: &Rc<RefCell<Connection>>, stream_id: u32, panicked: bool) {
+    if panicked {
+        klog_error!(Context::peer(conn.borrow().peer_addr()).stream(stream_id) => "handler panicked");
+    }
+
+    let mut conn = conn.borrow_mut();
+    let headers_sent = match conn.stream(stream_id) {
+        Some(stream) if stream.can_send() && !stream.is_end_queued() => stream.headers_sent(),
+        _ => return,
+    };
+    match headers_sent {
+        false => { let _ = ResponseWriter::new(&mut conn, stream_id).send(Response::new(500)); },
+        true => conn.reset_stream(stream_id, ErrorCode::InternalError),
+    }
+}
+
+#[cfg(test)]
+mod handler_tests {
+
+    use std::collections::VecDeque;
+    use std::io::{self, Cursor, Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Handler, Tunnel};
+    use connection::Connection;
+    use connection::observer::{CloseInfo, ConnectionObserver, FrameSummary};
+    use connection::trace::Direction;
+    use connection::error::{ErrorCode, H2Error};
+    use connection::handshake::PREFACE;
+    use connection::settings::ENABLE_CONNECT_PROTOCOL;
+    use connection::mock::SharedStream;
+    use connection::reader::FrameReader;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags, RstStreamFrame, SettingsFrame};
+    use header::{Decoder, Encoder, HeaderList};
+    use request::{BodyError, Request, StreamError};
+    use response::{Response, ResponseWriter};
+    use server::{Config, ConfigBuilder};
+    use connection::trace::{TraceSink, Tracer};
+    use log::capture::capture;
+    use util::{Clock, MockClock};
+
+    fn request_block(encoder: &mut Encoder, method: &'static str, path: &'static str) -> Vec<u8> {
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":method", method).into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", path).into());
+        encoder.encode_header_list(&list)
+    }
+
+    fn echo(mut req: Request, mut resp: ResponseWriter) {
+        let body = req.body().collect(1024).unwrap();
+        let body = format!("{} {}", req.path(), String::from_utf8_lossy(&body));
+        resp.send(Response::new(200).body(body)).unwrap();
+    }
+
+    // run the connection over the frames, returning everything written
+    fn serve_output<H: Handler>(frames: Vec<OwnedFrame>, handler: Arc<H>) -> Vec<u8> {
+        serve_output_with(frames, &Config::default(), handler)
+    }
+
+    fn serve_output_with<H: Handler>(frames: Vec<OwnedFrame>, config: &Config, handler: Arc<H>) -> Vec<u8> {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        for frame in frames {
+            input.extend_from_slice(frame.as_bytes());
+        }
+        let stream = SharedStream::new(input);
+        Connection::serve_with(stream.clone(), None, false, config, None, handler).unwrap();
+        stream.output()
+    }
+
+    // the total of the WINDOW_UPDATEs for a stream in the output
+    fn released(output: &[u8], stream_id: u32) -> usize {
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut total = 0;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::WINDOW_UPDATE && frame.get_stream_id() == stream_id {
+                let p = frame.payload();
+                total += (p[0] as usize) << 24 | (p[1] as usize) << 16 | (p[2] as usize) << 8 | p[3] as usize;
+            }
+        }
+        total
+    }
+
+    // run the connection over the frames, returning the
+    // (stream id, status, body) of every response in order
+    fn serve<H: Handler>(frames: Vec<OwnedFrame>, handler: Arc<H>) -> Vec<(u32, String, Vec<u8>)> {
+        let output = serve_output(frames, handler);
+
+        let mut decoder = Decoder::new(4096, 20);
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut responses: Vec<(u32, String, Vec<u8>)> = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match frame.get_type() {
+                types::HEADERS => {
+                    let headers = decoder.get_header_list(frame.payload()).unwrap();
+                    let status = headers.get_value_by_name(":status").unwrap().to_string();
+                    responses.push((frame.get_stream_id(), status, Vec::new()));
+                },
+                types::DATA => {
+                    let resp = responses.iter_mut().find(|r| r.0 == frame.get_stream_id()).unwrap();
+                    resp.2.extend_from_slice(frame.payload());
+                },
+                types::RST_STREAM => responses.push((frame.get_stream_id(), "RST_STREAM".to_string(), Vec::new())),
+                _ => {},
+            }
+        }
+        responses
+    }
+
+    #[test]
+    fn concurrent_streams() {
+        let mut encoder = Encoder::new(4096, 20);
+        let frames = vec![
+            OwnedFrame::headers(1, &request_block(&mut encoder, "POST", "/one"), flags::END_HEADERS),
+            OwnedFrame::headers(3, &request_block(&mut encoder, "POST", "/three"), flags::END_HEADERS),
+            OwnedFrame::data(3, b"body 3", true),
+            OwnedFrame::data(1, b"body", false),
+            OwnedFrame::data(1, b" 1", true),
+            OwnedFrame::headers(5, &request_block(&mut encoder, "GET", "/five"), flags::END_HEADERS | flags::END_STREAM),
+        ];
+
+        // stream 3 comes in while the handler for 1 waits on its body
+        assert_eq!(serve(frames, Arc::new(echo)), vec![
+            (1, "200".to_string(), b"/one body 1".to_vec()),
+            (3, "200".to_string(), b"/three body 3".to_vec()),
+            (5, "200".to_string(), b"/five ".to_vec()),
+        ]);
+    }
+
+    fn upload(len: usize) -> Vec<OwnedFrame> {
+        let mut encoder = Encoder::new(4096, 20);
+        let mut frames = vec![OwnedFrame::headers(1, &request_block(&mut encoder, "POST", "/upload"), flags::END_HEADERS)];
+        let chunk = vec![0x55; 16384];
+        let mut sent = 0;
+        while sent < len {
+            let n = ::std::cmp::min(chunk.len(), len - sent);
+            sent += n;
+            frames.push(OwnedFrame::data(1, &chunk[..n], sent == len));
+        }
+        frames
+    }
+
+    #[test]
+    fn large_upload() {
+        // the peer sends 200KB against a 64KB window, which only works
+        // because the window is released as the handler reads the body
+        let len = 200 * 1024;
+        let handler = |mut req: Request, mut resp: ResponseWriter| {
+            let body = req.body().collect(1024 * 1024).unwrap();
+            assert!(body.iter().all(|&b| b == 0x55));
+            resp.send(Response::new(200).body(body.len().to_string())).unwrap();
+        };
+        let output = serve_output(upload(len), Arc::new(handler));
+        assert!(65535 + released(&output, 1) >= len);
+        // what is left once the body is in is not worth a WINDOW_UPDATE
+        assert!(len - released(&output, 0) < 65535 / 2);
+
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut body = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::DATA {
+                body.extend_from_slice(frame.payload());
+            }
+        }
+        assert_eq!(body, len.to_string().into_bytes());
+    }
+
+    #[test]
+    fn metered_upload() {
+        // paused for the first half, then granted a frame at a time
+        let handler = |mut req: Request, mut resp: ResponseWriter| {
+            req.body().pause();
+            let mut read = 0;
+            while read < 32768 {
+                read += req.body().read_chunk().unwrap().unwrap().len();
+            }
+            req.body().set_auto_release(false);
+            req.body().resume();
+            while let Some(chunk) = req.body().read_chunk() {
+                read += chunk.unwrap().len();
+                req.body().grant(16384);
+            }
+            resp.send(Response::new(200).body(read.to_string())).unwrap();
+        };
+        let output = serve_output(upload(64 * 1024), Arc::new(handler));
+        // the first half is given back on resume, then 16KB for the third
+        // frame (the stream is closed by the time the last is read)
+        assert_eq!(released(&output, 1), 32768 + 16384);
+        assert!(released(&output, 0) >= 32768);
+    }
+
+    #[test]
+    fn reset_reason() {
+        // what reading the body and then answering gave the handler
+        fn reset_with(code: ErrorCode) -> (BodyError, H2Error) {
+            let mut encoder = Encoder::new(4096, 20);
+            let frames = vec![
+                OwnedFrame::headers(1, &request_block(&mut encoder, "POST", "/"), flags::END_HEADERS),
+                OwnedFrame::rst_stream(1, code as u32),
+            ];
+            let seen = Arc::new(Mutex::new(None));
+            let handler = {
+                let seen = seen.clone();
+                move |mut req: Request, mut resp: ResponseWriter| {
+                    let read = req.body().collect(1024).unwrap_err();
+                    let written = resp.send(Response::new(200)).unwrap_err();
+                    *seen.lock().unwrap() = Some((read, written));
+                }
+            };
+            serve_output(frames, Arc::new(handler));
+            let seen = seen.lock().unwrap().take();
+            seen.unwrap()
+        }
+
+        let (read, written) = reset_with(ErrorCode::Cancel);
+        assert_eq!(read, BodyError::Stream(StreamError::ResetByPeer(ErrorCode::Cancel)));
+        assert_eq!(written, H2Error::Stream(1, ErrorCode::Cancel));
+
+        // only this one says the request can be sent again
+        let (read, written) = reset_with(ErrorCode::RefusedStream);
+        assert_eq!(read, BodyError::Stream(StreamError::ResetByPeer(ErrorCode::RefusedStream)));
+        assert_eq!(written, H2Error::Stream(1, ErrorCode::RefusedStream));
+        assert!(StreamError::ResetByPeer(ErrorCode::RefusedStream).is_retry_safe());
+        assert!(!StreamError::ResetByPeer(ErrorCode::Cancel).is_retry_safe());
+        assert!(!StreamError::Reset(ErrorCode::RefusedStream).is_retry_safe());
+    }
+
+    #[test]
+    fn enlarged_window() {
+        let len = 200 * 1024;
+        let handler = |mut req: Request, mut resp: ResponseWriter| {
+            req.body().set_recv_window(1 << 20);
+            let body = req.body().collect(1024 * 1024).unwrap();
+            resp.send(Response::new(200).body(body.len().to_string())).unwrap();
+        };
+        // the whole upload fits, so only the growth is advertised
+        let output = serve_output(upload(len), Arc::new(handler));
+        assert_eq!(released(&output, 1), (1 << 20) - 65535);
+    }
+
+    #[test]
+    fn upload_too_large() {
+        let handler = |mut req: Request, mut resp: ResponseWriter| {
+            let e = req.body().collect(64 * 1024).unwrap_err();
+            resp.send(Response::new(e.status())).unwrap();
+        };
+        // the rest of the body is dropped (and released) after the handler returns
+        let output = serve_output(upload(200 * 1024), Arc::new(handler.clone()));
+        assert!(200 * 1024 - released(&output, 0) < 65535 / 2);
+        assert_eq!(serve(upload(200 * 1024), Arc::new(handler)), vec![(1, "413".to_string(), Vec::new())]);
+    }
+
+    #[test]
+    fn head_request() {
+        let mut encoder = Encoder::new(4096, 20);
+        let frames = vec![
+            OwnedFrame::headers(1, &request_block(&mut encoder, "HEAD", "/page"), flags::END_HEADERS | flags::END_STREAM),
+        ];
+        let output = serve_output(frames, Arc::new(echo));
+
+        // the headers of what GET would get, and no DATA
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match frame.get_type() {
+                types::HEADERS => {
+                    let headers = Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap();
+                    assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+                    assert_eq!(headers.get_value_by_name("content-length"), Some("6"));
+                    responses.push(frame.get_flags());
+                },
+                types::DATA => panic!("DATA sent for HEAD"),
+                _ => {},
+            }
+        }
+        assert_eq!(responses, vec![flags::END_HEADERS | flags::END_STREAM]);
+    }
+
+    // a POST with "expect: 100-continue" and its body
+    fn expect_continue() -> Vec<OwnedFrame> {
+        let mut list = HeaderList::with_capacity(4);
+        list.add_entry((":method", "POST").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/upload").into());
+        list.add_entry(("expect", "100-continue").into());
+        let block = Encoder::new(4096, 20).encode_header_list(&list);
+        vec![OwnedFrame::headers(1, &block, flags::END_HEADERS), OwnedFrame::data(1, b"body", true)]
+    }
+
+    // the (type, flags, status) of every frame on stream 1 in the output
+    fn stream_frames(output: Vec<u8>) -> Vec<(u8, u8, Option<String>)> {
+        let mut decoder = Decoder::new(4096, 20);
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_stream_id() != 1 || frame.get_type() == types::WINDOW_UPDATE {
+                continue;
+            }
+            let status = match frame.get_type() {
+                types::HEADERS => decoder.get_header_list(frame.payload()).unwrap()
+                    .get_value_by_name(":status").map(|s| s.to_string()),
+                _ => None,
+            };
+            frames.push((frame.get_type(), frame.get_flags(), status));
+        }
+        frames
+    }
+
+    #[test]
+    fn continue_accepted() {
+        let handler = |mut req: Request, mut resp: ResponseWriter| {
+            assert!(req.expects_continue());
+            resp.send_continue().unwrap();
+            echo(req, resp);
+        };
+        assert_eq!(stream_frames(serve_output(expect_continue(), Arc::new(handler))), vec![
+            (types::HEADERS, flags::END_HEADERS, Some("100".to_string())),
+            (types::HEADERS, flags::END_HEADERS, Some("200".to_string())),
+            (types::DATA, flags::END_STREAM, None),
+        ]);
+    }
+
+    #[test]
+    fn continue_rejected() {
+        // answered without the 100, the body is dropped
+        let handler = |req: Request, mut resp: ResponseWriter| {
+            assert!(req.expects_continue());
+            resp.send(Response::new(417)).unwrap();
+        };
+        assert_eq!(stream_frames(serve_output(expect_continue(), Arc::new(handler))), vec![
+            (types::HEADERS, flags::END_HEADERS | flags::END_STREAM, Some("417".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn handler_failures() {
+        let mut encoder = Encoder::new(4096, 20);
+        let frames = vec![
+            OwnedFrame::headers(1, &request_block(&mut encoder, "GET", "/panic"), flags::END_HEADERS | flags::END_STREAM),
+            OwnedFrame::headers(3, &request_block(&mut encoder, "GET", "/nothing"), flags::END_HEADERS | flags::END_STREAM),
+            OwnedFrame::headers(5, &request_block(&mut encoder, "GET", "/partial"), flags::END_HEADERS | flags::END_STREAM),
+            OwnedFrame::headers(7, &request_block(&mut encoder, "GET", "/fine"), flags::END_HEADERS | flags::END_STREAM),
+        ];
+
+        let handler = |req: Request, mut resp: ResponseWriter| {
+            match req.path() {
+                "/panic" => panic!("handler panicked on purpose"),
+                "/nothing" => {},
+                "/partial" => {
+                    let mut headers = HeaderList::with_capacity(1);
+                    headers.add_entry((":status", "200").into());
+                    resp.context().send_headers(&headers, false).unwrap();
+                },
+                _ => echo(req, resp),
+            }
+        };
+
+        assert_eq!(serve(frames, Arc::new(handler)), vec![
+            (1, "500".to_string(), Vec::new()),
+            (3, "500".to_string(), Vec::new()),
+            (5, "200".to_string(), Vec::new()),
+            (5, "RST_STREAM".to_string(), Vec::new()),
+            (7, "200".to_string(), b"/fine ".to_vec()),
+        ]);
+    }
+
+    // the input with the peer going quiet for a while between each part,
+    // reads in the gaps move the clock on and time out like they would
+    // on a socket
+    struct Gaps {
+        parts: VecDeque<Vec<u8>>,
+        gap: Duration,
+        next: Instant,
+        clock: Arc<MockClock>,
+        out: SharedStream,
+    }
+
+    impl Gaps {
+        fn new(parts: Vec<Vec<u8>>, gap: Duration, clock: Arc<MockClock>, out: SharedStream) -> Self {
+            Gaps { parts: parts.into_iter().collect(), gap: gap, next: clock.now(), clock: clock, out: out }
+        }
+    }
+
+    impl Read for Gaps {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.clock.now() < self.next || self.parts.is_empty() {
+                self.clock.advance(Duration::from_millis(1));
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "read timed out"));
+            }
+            let part = self.parts.pop_front().unwrap();
+            buf[..part.len()].copy_from_slice(&part);
+            self.next = self.clock.now() + self.gap;
+            Ok(part.len())
+        }
+    }
+
+    impl Write for Gaps {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.out.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn timeouts(handshake: u64, idle: u64, clock: &Arc<MockClock>) -> ConfigBuilder {
+        Config::builder()
+            .handshake_timeout(Some(Duration::from_millis(handshake)))
+            .idle_timeout(Some(Duration::from_millis(idle)))
+            .clock(clock.clone())
+    }
+
+    // the (type, stream id) of every frame in the output
+    fn frame_types(output: Vec<u8>) -> Vec<(u8, u32)> {
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            frames.push((frame.get_type(), frame.get_stream_id()));
+        }
+        frames
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        let block = request_block(&mut Encoder::new(4096, 20), "GET", "/");
+        input.extend_from_slice(OwnedFrame::headers(1, &block, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+
+        // answered, then nothing more from the client
+        let clock = Arc::new(MockClock::new());
+        let started = clock.now();
+        let stream = SharedStream::stalling(input, clock.clone());
+        Connection::serve_with(stream.clone(), None, false, &timeouts(1000, 30, &clock).build().unwrap(), None, Arc::new(echo)).unwrap();
+        assert!(clock.now() - started >= Duration::from_millis(30));
+        let output = stream.output();
+        let frames = frame_types(output.clone());
+        assert_eq!(frames.last(), Some(&(types::GOAWAY, 0)));
+        assert!(frames.contains(&(types::HEADERS, 1)));
+        // NO_ERROR, after stream 1
+        assert_eq!(&output[output.len() - 8..], &[0, 0, 0, 1, 0, 0, 0, 0]);
+
+        // only part of the preface, the connection is closed without a GOAWAY
+        let stream = SharedStream::stalling(PREFACE[..10].to_vec(), clock.clone());
+        let err = Connection::serve_with(stream.clone(), None, false, &timeouts(30, 1000, &clock).build().unwrap(), None, Arc::new(echo)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(stream.output().is_empty());
+
+        // the preface is not done until the client's SETTINGS
+        let stream = SharedStream::stalling(PREFACE.to_vec(), clock.clone());
+        let err = Connection::serve_with(stream.clone(), None, false, &timeouts(30, 1000, &clock).build().unwrap(), None, Arc::new(echo)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(frame_types(stream.output()), vec![(types::SETTINGS, 0)]);
+    }
+
+    #[test]
+    fn slow_header_block() {
+        let block = request_block(&mut Encoder::new(4096, 20), "GET", "/");
+        let mut start = PREFACE.to_vec();
+        start.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        start.extend_from_slice(OwnedFrame::headers(1, &block[..1], 0).as_bytes());
+        let parts = vec![
+            start,
+            OwnedFrame::new(types::CONTINUATION, 0, 1, &block[1..2]).as_bytes().to_vec(),
+            OwnedFrame::new(types::CONTINUATION, flags::END_HEADERS, 1, &block[2..]).as_bytes().to_vec(),
+        ];
+
+        // a CONTINUATION every 20ms, but the whole block has to be in within 30ms
+        let clock = Arc::new(MockClock::new());
+        let config = timeouts(1000, 1000, &clock).header_block_timeout(Duration::from_millis(30)).build().unwrap();
+        let out = SharedStream::new(Vec::new());
+        let stream = Gaps::new(parts, Duration::from_millis(20), clock, out.clone());
+        let err = Connection::serve_with(stream, None, false, &config, None, Arc::new(echo)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert_eq!(frame_types(out.output()).last(), Some(&(types::GOAWAY, 0)));
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(out.output());
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::GOAWAY {
+                // ENHANCE_YOUR_CALM
+                assert_eq!(&frame.payload()[4..8], &[0, 0, 0, 0xb]);
+            }
+        }
+    }
+
+    #[test]
+    fn no_idle_timeout_while_streaming() {
+        let mut encoder = Encoder::new(4096, 20);
+        let mut start = PREFACE.to_vec();
+        start.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        start.extend_from_slice(OwnedFrame::headers(1, &request_block(&mut encoder, "POST", "/slow"), flags::END_HEADERS).as_bytes());
+        let parts = vec![
+            start,
+            OwnedFrame::data(1, b"part", false).as_bytes().to_vec(),
+            OwnedFrame::data(1, b" two", true).as_bytes().to_vec(),
+        ];
+
+        // the body is slower than the idle timeout, but the stream is open
+        let clock = Arc::new(MockClock::new());
+        let out = SharedStream::new(Vec::new());
+        let stream = Gaps::new(parts, Duration::from_millis(40), clock.clone(), out.clone());
+        Connection::serve_with(stream, None, false, &timeouts(1000, 20, &clock).build().unwrap(), None, Arc::new(echo)).unwrap();
+
+        let frames: Vec<_> = frame_types(out.output()).into_iter()
+            .filter(|&(t, _)| t != types::SETTINGS && t != types::WINDOW_UPDATE).collect();
+        assert_eq!(frames, vec![(types::HEADERS, 1), (types::DATA, 1), (types::GOAWAY, 0)]);
+    }
+
+    #[test]
+    fn traced() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, &request_block(&mut Encoder::new(4096, 20), "GET", "/"),
+                                                    flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        let stream = SharedStream::new(input);
+        let config = Config::builder().trace(Tracer::new(TraceSink::Log).redact_headers(true)).build().unwrap();
+        let messages = capture(|| {
+            Connection::serve_with(stream.clone(), None, false, &config, None, Arc::new(echo)).unwrap();
+        });
+
+        // what went which way, in order
+        let frames: Vec<String> = messages.iter()
+            .filter_map(|m| m.find(" << recv ").or(m.find(" >> sent ")).map(|i| &m[i + 1..]))
+            .map(|m| m.split(" length").next().unwrap().to_string())
+            .collect();
+        assert_eq!(frames, vec![
+            ">> sent SETTINGS stream=0 flags=0x00",
+            "<< recv SETTINGS stream=0 flags=0x00",
+            ">> sent SETTINGS stream=0 flags=0x01",
+            "<< recv HEADERS stream=1 flags=0x05",
+            ">> sent HEADERS stream=1 flags=0x04",
+            ">> sent DATA stream=1 flags=0x01",
+        ]);
+        assert!(messages.iter().any(|m| m.contains("HEADERS stream=1 flags=0x05 length=3 [header block redacted]")));
+    }
+
+    #[test]
+    fn oversized_frames_skipped() {
+        let mut encoder = Encoder::new(4096, 20);
+        let frames = vec![
+            OwnedFrame::headers(1, &request_block(&mut encoder, "POST", "/one"), flags::END_HEADERS),
+            // more than the default SETTINGS_MAX_FRAME_SIZE
+            OwnedFrame::data(1, &[7; 20000], true),
+            // and an extension frame, which is dropped anyway
+            OwnedFrame::new(0xBB, 0, 0, &[7; 10000]),
+            OwnedFrame::headers(3, &request_block(&mut encoder, "GET", "/three"), flags::END_HEADERS | flags::END_STREAM),
+        ];
+        let config = Config::builder().trace(Tracer::new(TraceSink::Log)).build().unwrap();
+        let handler = |_req: Request, mut resp: ResponseWriter| {
+            resp.send(Response::new(200)).unwrap();
+        };
+        let mut output = Vec::new();
+        let messages = capture(|| {
+            output = serve_output_with(frames, &config, Arc::new(handler));
+        });
+
+        // neither one was read in
+        assert!(messages.iter().any(|m| m.contains("<< recv DATA stream=1 flags=0x01 length=20000 [payload skipped]")));
+        assert!(messages.iter().any(|m| m.contains("<< recv UNKNOWN(0xbb) stream=0 flags=0x00 length=10000 [payload skipped]")));
+
+        // and the connection carried on past them
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut reset = None;
+        let mut answered = false;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match (frame.get_type(), frame.get_stream_id()) {
+                (types::RST_STREAM, 1) => {
+                    let rst: RstStreamFrame = frame.into();
+                    reset = Some(rst.get_error_code());
+                },
+                (types::HEADERS, 3) => answered = true,
+                (types::GOAWAY, _) => panic!("the connection was ended"),
+                _ => {},
+            }
+        }
+        assert_eq!(reset, Some(ErrorCode::FrameSizeError as u32));
+        assert!(answered);
+    }
+
+    // a websocket over stream 1, then the frames the client sends on it
+    fn tunnel_frames(data: Vec<OwnedFrame>) -> Vec<OwnedFrame> {
+        let mut list = HeaderList::with_capacity(5);
+        list.add_entry((":method", "CONNECT").into());
+        list.add_entry((":protocol", "websocket").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/chat").into());
+        list.add_entry((":authority", "localhost").into());
+        let mut frames = vec![OwnedFrame::headers(1, &Encoder::new(4096, 20).encode_header_list(&list), flags::END_HEADERS)];
+        frames.extend(data);
+        frames
+    }
+
+    // sends back everything that comes through the tunnel
+    fn echo_tunnel(req: Request, mut tunnel: Tunnel) {
+        assert_eq!(req.protocol(), Some("websocket"));
+        tunnel.accept(Response::new(200).header("sec-websocket-protocol", "chat")).unwrap();
+        while let Some(chunk) = tunnel.read_chunk() {
+            tunnel.write_all(&chunk.unwrap()).unwrap();
+        }
+    }
+
+    fn not_called(_: Request, _: ResponseWriter) {
+        panic!("the tunnel went to the handler");
+    }
+
+    #[test]
+    fn tunnel_echo() {
+        let config = Config::builder().enable_connect_protocol(echo_tunnel).build().unwrap();
+        let frames = tunnel_frames(vec![OwnedFrame::data(1, b"hello ", false), OwnedFrame::data(1, b"world", true)]);
+        let output = serve_output_with(frames, &config, Arc::new(not_called));
+
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut stream = Vec::new();
+        while let Some(mut frame) = reader.read_frame(&mut output).unwrap() {
+            match (frame.get_type(), frame.get_stream_id()) {
+                (types::SETTINGS, 0) if frame.get_flags() & flags::ACK == 0 => {
+                    let settings: SettingsFrame = frame.into();
+                    assert!(settings.get_settings_paramaters().any(|p| p == (ENABLE_CONNECT_PROTOCOL, 1)));
+                },
+                (types::HEADERS, 1) => {
+                    let headers = Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap();
+                    assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+                    assert_eq!(headers.get_value_by_name("sec-websocket-protocol"), Some("chat"));
+                    // no content-length, the stream stays open for the tunnel
+                    assert_eq!(headers.get_value_by_name("content-length"), None);
+                    stream.push((types::HEADERS, frame.get_flags(), Vec::new()));
+                },
+                (types::DATA, 1) => stream.push((types::DATA, frame.get_flags(), frame.payload().to_vec())),
+                _ => {},
+            }
+        }
+        assert_eq!(stream, vec![
+            (types::HEADERS, flags::END_HEADERS, Vec::new()),
+            (types::DATA, 0, b"hello ".to_vec()),
+            (types::DATA, 0, b"world".to_vec()),
+            (types::DATA, flags::END_STREAM, Vec::new()),
+        ]);
+    }
+
+    #[test]
+    fn tunnel_flow_control() {
+        // more each way than the 64KB windows, the echo of the fifth chunk
+        // waits on the client's WINDOW_UPDATEs, which come after it
+        let chunks: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 16384]).collect();
+        let mut data: Vec<OwnedFrame> = chunks.iter().enumerate().map(|(i, c)| OwnedFrame::data(1, c, i == 7)).collect();
+        data.insert(5, OwnedFrame::window_update(0, 1 << 20));
+        data.insert(6, OwnedFrame::window_update(1, 1 << 20));
+        let config = Config::builder().enable_connect_protocol(echo_tunnel).build().unwrap();
+        let output = serve_output_with(tunnel_frames(data), &config, Arc::new(not_called));
+
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut echoed = Vec::new();
+        let mut ended = false;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::DATA {
+                echoed.extend_from_slice(frame.payload());
+                ended = frame.get_flags() & flags::END_STREAM != 0;
+            }
+        }
+        assert_eq!(echoed, chunks.concat());
+        assert!(ended);
+        // and what was read was given back to the client as it went
+        assert!(65535 + released(&output.into_inner(), 1) >= 8 * 16384);
+    }
+
+    #[test]
+    fn tunnel_not_enabled() {
+        // without SETTINGS_ENABLE_CONNECT_PROTOCOL the :protocol is malformed
+        let output = serve_output(tunnel_frames(vec![OwnedFrame::data(1, b"hello", true)]), Arc::new(not_called));
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut resets = Vec::new();
+        while let Some(mut frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::RST_STREAM {
+                let frame_stream = frame.get_stream_id();
+                let rst: RstStreamFrame = frame.into();
+                resets.push((frame_stream, rst.get_error_code()));
+            }
+        }
+        assert_eq!(resets, vec![(1, ErrorCode::ProtocolError as u32)]);
+
+        // a plain CONNECT still goes to the handler
+        let mut list = HeaderList::with_capacity(2);
+        list.add_entry((":method", "CONNECT").into());
+        list.add_entry((":authority", "example.com:443").into());
+        let frames = vec![OwnedFrame::headers(1, &Encoder::new(4096, 20).encode_header_list(&list), flags::END_HEADERS | flags::END_STREAM)];
+        let handler = |req: Request, mut resp: ResponseWriter| {
+            assert_eq!(req.protocol(), None);
+            resp.send(Response::new(405)).unwrap();
+        };
+        let config = Config::builder().enable_connect_protocol(echo_tunnel).build().unwrap();
+        let output = serve_output_with(frames, &config, Arc::new(handler));
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut statuses = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::HEADERS {
+                statuses.push(Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap().get_value_by_name(":status").unwrap().to_string());
+            }
+        }
+        assert_eq!(statuses, vec!["405".to_string()]);
+    }
+
+    // writes down every callback, frames too if asked to
+    struct Recorder {
+        calls: Mutex<Vec<String>>,
+        frames: bool,
+    }
+
+    impl Recorder {
+        fn new(frames: bool) -> Arc<Recorder> {
+            Arc::new(Recorder { calls: Mutex::new(Vec::new()), frames: frames })
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn push(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    impl ConnectionObserver for Recorder {
+        fn on_stream_open(&self, stream_id: u32, headers: &HeaderList) {
+            self.push(format!("open {} {}", stream_id, headers.get_value_by_name(":path").unwrap()));
+        }
+
+        fn on_stream_close(&self, stream_id: u32, info: CloseInfo) {
+            self.push(format!("close {} {:?} {:?}", stream_id, info.reset, info.status));
+        }
+
+        fn on_goaway(&self, dir: Direction, last_stream_id: u32, error: ErrorCode) {
+            self.push(format!("goaway {:?} {} {:?}", dir, last_stream_id, error));
+        }
+
+        fn wants_frames(&self) -> bool {
+            self.frames
+        }
+
+        fn on_frame(&self, dir: Direction, frame: FrameSummary) {
+            self.push(format!("frame {:?} {} {}", dir, frame.frame_type, frame.stream_id));
+        }
+    }
+
+    // a request that is answered, one the client resets while its body
+    // is being read, then the client's GOAWAY
+    fn two_requests() -> Vec<OwnedFrame> {
+        let mut encoder = Encoder::new(4096, 20);
+        vec![
+            OwnedFrame::headers(1, &request_block(&mut encoder, "GET", "/one"), flags::END_HEADERS | flags::END_STREAM),
+            OwnedFrame::headers(3, &request_block(&mut encoder, "POST", "/two"), flags::END_HEADERS),
+            OwnedFrame::rst_stream(3, ErrorCode::Cancel as u32),
+            OwnedFrame::go_away(3, ErrorCode::NoError as u32, b""),
+        ]
+    }
+
+    fn answer_if_read(mut req: Request, mut resp: ResponseWriter) {
+        if req.body().collect(1024).is_ok() {
+            let _ = resp.send(Response::new(200));
+        }
+    }
+
+    #[test]
+    fn observer_callbacks() {
+        let recorder = Recorder::new(false);
+        let config = Config::builder().observer(recorder.clone()).build().unwrap();
+        serve_output_with(two_requests(), &config, Arc::new(answer_if_read));
+        assert_eq!(recorder.calls(), vec![
+            "open 1 /one".to_string(),
+            "close 1 None Some(200)".to_string(),
+            "open 3 /two".to_string(),
+            "close 3 Some((Received, Cancel)) None".to_string(),
+            "goaway Received 3 NoError".to_string(),
+        ]);
+
+        // frames only for an observer that wants them
+        let recorder = Recorder::new(true);
+        let config = Config::builder().observer(recorder.clone()).build().unwrap();
+        serve_output_with(two_requests(), &config, Arc::new(answer_if_read));
+        let calls = recorder.calls();
+        let frames: Vec<_> = calls.iter().filter(|c| c.starts_with("frame ")).collect();
+        assert!(frames.contains(&&format!("frame Received {} 0", types::SETTINGS)));
+        assert!(frames.contains(&&format!("frame Received {} 3", types::RST_STREAM)));
+        assert!(frames.contains(&&format!("frame Sent {} 1", types::HEADERS)));
+        let received_headers = calls.iter().position(|c| *c == format!("frame Received {} 1", types::HEADERS)).unwrap();
+        assert!(received_headers < calls.iter().position(|c| c == "open 1 /one").unwrap());
+    }
+
+    struct Panics;
+
+    impl ConnectionObserver for Panics {
+        fn on_stream_open(&self, _stream_id: u32, _headers: &HeaderList) {
+            panic!("observer panic");
+        }
+
+        fn on_stream_close(&self, _stream_id: u32, _info: CloseInfo) {
+            unreachable!("called after a panic");
+        }
+    }
+
+    #[test]
+    fn observer_panic() {
+        let config = Config::builder().observer(Arc::new(Panics)).build().unwrap();
+        let mut output = Vec::new();
+        let logged = capture(|| {
+            output = serve_output_with(two_requests(), &config, Arc::new(answer_if_read));
+        });
+        assert!(logged.iter().any(|m| m.contains("observer panicked")));
+
+        // the connection was served like there was no observer
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut statuses = Vec::new();
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            if frame.get_type() == types::HEADERS {
+                let headers = Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap();
+                statuses.push((frame.get_stream_id(), headers.get_value_by_name(":status").unwrap().to_string()));
+            }
+        }
+        assert_eq!(statuses, vec![(1, "200".to_string())]);
+    }
+}
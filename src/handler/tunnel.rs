@@ -0,0 +1,179 @@
+//! Tunnels opened with extended CONNECT (RFC 8441)
+//!
+//! With SETTINGS_ENABLE_CONNECT_PROTOCOL advertised a client can send a
+//! CONNECT with a :protocol (like "websocket") to get a stream that
+//! carries bytes both ways, WebSockets over HTTP/2 being what it is for.
+//! Such a request goes to the TunnelHandler instead of the Handler, along
+//! with a Tunnel to answer it and then read and write the stream with.
+//!
+//! Both ways are flow controlled: reading gives back the window like a
+//! request body does, and a write waits for the peer's window once a
+//! frame's worth is already waiting to be sent.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::{Rc, Weak};
+use std::thread;
+
+use connection::Connection;
+use connection::error::H2Error;
+use request::{Body, Pump, Request, StreamError};
+use response::{Response, ResponseContext, ResponseWriter};
+
+/// What answers extended CONNECT requests
+pub trait TunnelHandler: Send + Sync {
+    fn handle(&self, req: Request, tunnel: Tunnel);
+}
+
+impl<F> TunnelHandler for F where F: Fn(Request, Tunnel) + Send + Sync {
+    fn handle(&self, req: Request, tunnel: Tunnel) {
+        self(req, tunnel)
+    }
+}
+
+/// The stream of an extended CONNECT, as bytes both ways
+///
+/// The tunnel is opened with accept (a 2xx response without a body), or
+/// turned down with refuse. Once it is open what the peer sends is read
+/// with Read and what is written goes out as DATA. Closing it, or dropping
+/// it, ends the stream from this side. A tunnel dropped before it is
+/// answered gets a 500, like a request the handler did not answer.
+pub struct Tunnel {
+    conn: Rc<RefCell<Connection>>,
+    stream_id: u32,
+    body: Body,
+    pump: Weak<RefCell<Pump>>,
+    // the rest of a chunk that did not fit in a read
+    chunk: Vec<u8>,
+    read: usize,
+    accepted: bool,
+    closed: bool,
+}
+
+impl Tunnel {
+
+    pub(crate) fn new(conn: Rc<RefCell<Connection>>, stream_id: u32, body: Body, pump: Weak<RefCell<Pump>>) -> Self {
+        Tunnel {
+            conn: conn,
+            stream_id: stream_id,
+            body: body,
+            pump: pump,
+            chunk: Vec::new(),
+            read: 0,
+            accepted: false,
+            closed: false,
+        }
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    /// open the tunnel with the headers of resp (its status should be
+    /// 2xx, a body is not sent)
+    pub fn accept(&mut self, resp: Response) -> Result<(), H2Error> {
+        debug_assert!(resp.status().as_u16() / 100 == 2);
+        let (mut headers, _) = resp.into_parts();
+        let mut ctx = ResponseContext::shared(self.conn.clone(), self.stream_id);
+        ctx.add_default_headers(&mut headers);
+        ctx.send_headers(&headers, false)?;
+        self.accepted = true;
+        Ok(())
+    }
+
+    /// turn the tunnel down with resp, which ends the stream
+    pub fn refuse(mut self, resp: Response) -> Result<(), H2Error> {
+        self.closed = true;
+        ResponseWriter::shared(self.conn.clone(), self.stream_id).send(resp)
+    }
+
+    /// the next chunk the peer sent (the data of one DATA frame), waiting
+    /// for it to arrive if needed, or None once the peer closed its side
+    pub fn read_chunk(&mut self) -> Option<Result<Vec<u8>, StreamError>> {
+        if self.read < self.chunk.len() {
+            let rest = self.chunk.split_off(self.read);
+            self.chunk.clear();
+            self.read = 0;
+            return Some(Ok(rest));
+        }
+        self.body.read_chunk()
+    }
+
+    /// end the stream from this side, the peer can still send
+    pub fn close(&mut self) -> Result<(), H2Error> {
+        if self.closed || !self.accepted {
+            return Ok(());
+        }
+        self.closed = true;
+        self.conn.borrow_mut().send_data(self.stream_id, &[], true)
+    }
+
+    // how much is waiting on the peer's window, and the most that is
+    // let wait before a write waits with it
+    fn queued(&self) -> (usize, usize) {
+        let conn = self.conn.borrow();
+        let queued = conn.stream(self.stream_id).map_or(0, |s| s.pending_len());
+        (queued, conn.remote_settings().max_frame_size as usize)
+    }
+}
+
+fn io_error(e: H2Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl Read for Tunnel {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.chunk.len() {
+            self.chunk = match self.body.read_chunk() {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::ConnectionReset, e)),
+                None => return Ok(0),
+            };
+            self.read = 0;
+        }
+        let n = (&self.chunk[self.read..]).read(buf)?;
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl Write for Tunnel {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if !self.accepted || self.closed {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "tunnel is not open"));
+        }
+        self.conn.borrow_mut().send_data(self.stream_id, data, false).map_err(io_error)?;
+        // the peer is slower than the writes, wait on it
+        loop {
+            let (queued, max) = self.queued();
+            if queued <= max {
+                return Ok(data.len());
+            }
+            let pump = match self.pump.upgrade() {
+                Some(pump) => pump,
+                None => return Err(io::Error::new(io::ErrorKind::BrokenPipe, StreamError::ConnectionClosed)),
+            };
+            let more = pump.borrow_mut().pump()?;
+            if !more {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, StreamError::ConnectionClosed));
+            }
+        }
+    }
+
+    // what is written is queued on the connection right away, and the
+    // connection writes it out whenever it gets to
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Tunnel {
+    // a handler that panicked has its stream reset instead
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            let _ = self.close();
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Conditional requests (RFC 7232) and If-Range (RFC 7233 3.2)
+//!
+//! A representation is described by its entity tag and last modification
+//! time. not_modified says whether a GET can be answered with 304, and
+//! range_applies whether a Range header should be honored, which is what
+//! lets a download that was cut off be picked up where it stopped without
+//! mixing the bytes of two versions of a file.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use request::Request;
+use util::parse_http_date;
+
+// the opaque part of a tag, without the W/ of a weak one
+fn opaque(tag: &str) -> &str {
+    let tag = tag.trim();
+    if tag.starts_with("W/") { &tag[2..] } else { tag }
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.trim().starts_with("W/")
+}
+
+/// RFC 7232 2.3.2 the weak comparison, which ignores the W/ of either tag
+pub fn weak_eq(a: &str, b: &str) -> bool {
+    opaque(a) == opaque(b)
+}
+
+/// RFC 7232 2.3.2 the strong comparison, where neither tag can be weak
+pub fn strong_eq(a: &str, b: &str) -> bool {
+    !is_weak(a) && !is_weak(b) && opaque(a) == opaque(b)
+}
+
+// whole seconds since the epoch, what a date header can hold
+fn secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+/// RFC 7232 3.2 and 3.3 the client's copy is still current and a GET can
+/// be answered with 304, if-modified-since only counts without if-none-match
+pub fn not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = req.header("if-none-match") {
+        return tags.trim() == "*" || tags.split(',').any(|t| weak_eq(t, etag));
+    }
+    let since = req.header("if-modified-since").and_then(parse_http_date);
+    match (since.and_then(secs), modified.and_then(secs)) {
+        (Some(since), Some(modified)) => since >= modified,
+        _ => false,
+    }
+}
+
+/// RFC 7233 3.2 should the Range of req be honored for the representation
+/// with etag and modified, true unless an if-range names another version
+/// (as of now)
+///
+/// An entity tag only matches with the strong comparison. A date only
+/// matches the exact modification time, and only if that is a strong
+/// validator, a second or more in the past (RFC 7232 2.2.2) so that a
+/// file changed twice in the same second is not mistaken for one version.
+pub fn range_applies(req: &Request, etag: &str, modified: Option<SystemTime>, now: SystemTime) -> bool {
+    let validator = match req.header("if-range") {
+        Some(validator) => validator.trim(),
+        None => return true,
+    };
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        return strong_eq(validator, etag);
+    }
+    let date = parse_http_date(validator).and_then(secs);
+    let strong = modified.map_or(false, |m| m + Duration::from_secs(1) <= now);
+    strong && date.is_some() && date == modified.and_then(secs)
+}
+
+#[cfg(test)]
+mod conditional_tests {
+
+    use std::str;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{not_modified, range_applies, strong_eq, weak_eq};
+    use header::HeaderList;
+    use request::Request;
+    use util::http_date;
+
+    fn request(headers: &[(&'static str, &str)]) -> Request {
+        let mut list = HeaderList::with_capacity(3 + headers.len());
+        list.add_entry((":method", "GET").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/file").into());
+        for &(name, value) in headers {
+            list.add_entry((name, value.to_string()).into());
+        }
+        Request::from_header_list(list).unwrap()
+    }
+
+    fn date(time: SystemTime) -> String {
+        str::from_utf8(&http_date(time)).unwrap().to_string()
+    }
+
+    #[test]
+    fn comparisons() {
+        // RFC 7232 2.3.2's table
+        assert!(!strong_eq("W/\"1\"", "W/\"1\"") && weak_eq("W/\"1\"", "W/\"1\""));
+        assert!(!strong_eq("W/\"1\"", "W/\"2\"") && !weak_eq("W/\"1\"", "W/\"2\""));
+        assert!(!strong_eq("W/\"1\"", "\"1\"") && weak_eq("W/\"1\"", "\"1\""));
+        assert!(strong_eq("\"1\"", " \"1\"") && weak_eq("\"1\"", "\"1\""));
+    }
+
+    #[test]
+    fn if_none_match_first() {
+        let modified = Some(UNIX_EPOCH + Duration::from_secs(1000));
+        let later = date(UNIX_EPOCH + Duration::from_secs(2000));
+        assert!(not_modified(&request(&[("if-none-match", "\"a\", W/\"b\"")]), "\"b\"", modified));
+        assert!(not_modified(&request(&[("if-modified-since", &later)]), "\"b\"", modified));
+        // a tag that does not match wins over a date that does
+        assert!(!not_modified(&request(&[("if-none-match", "\"a\""), ("if-modified-since", &later)]), "\"b\"", modified));
+        assert!(!not_modified(&request(&[]), "\"b\"", modified));
+    }
+
+    #[test]
+    fn if_range() {
+        let now = UNIX_EPOCH + Duration::from_secs(1500000000);
+        let modified = now - Duration::from_secs(3600);
+        assert!(range_applies(&request(&[]), "\"v1\"", Some(modified), now));
+        assert!(range_applies(&request(&[("if-range", "\"v1\"")]), "\"v1\"", Some(modified), now));
+        assert!(!range_applies(&request(&[("if-range", "\"v0\"")]), "\"v1\"", Some(modified), now));
+        // weak tags never match
+        assert!(!range_applies(&request(&[("if-range", "W/\"v1\"")]), "\"v1\"", Some(modified), now));
+        assert!(!range_applies(&request(&[("if-range", "\"v1\"")]), "W/\"v1\"", Some(modified), now));
+
+        assert!(range_applies(&request(&[("if-range", &date(modified))]), "\"v1\"", Some(modified), now));
+        assert!(!range_applies(&request(&[("if-range", &date(modified - Duration::from_secs(5)))]), "\"v1\"", Some(modified), now));
+        assert!(!range_applies(&request(&[("if-range", "yesterday")]), "\"v1\"", Some(modified), now));
+        assert!(!range_applies(&request(&[("if-range", &date(modified))]), "\"v1\"", None, now));
+        // a time this close to now could be one of two versions
+        assert!(!range_applies(&request(&[("if-range", &date(now))]), "\"v1\"", Some(now), now));
+    }
+}
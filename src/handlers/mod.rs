@@ -0,0 +1,8 @@
+//! Handlers for common jobs
+
+pub mod conditional;
+mod router;
+mod static_files;
+
+pub use self::router::Router;
+pub use self::static_files::StaticFiles;
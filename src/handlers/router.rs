@@ -0,0 +1,238 @@
+//! Picking a handler by the request's method and path
+
+use handler::Handler;
+use request::{Method, Request};
+use response::{Response, ResponseWriter};
+
+// what a route's path matches
+enum Pattern {
+    // the whole path
+    Exact(String),
+    // anything after the prefix, which ends with "/"
+    Prefix(String),
+}
+
+impl Pattern {
+
+    fn new(path: &str) -> Self {
+        match path.ends_with("/*") {
+            true => Pattern::Prefix(path[..path.len() - 1].to_string()),
+            false => Pattern::Exact(path.to_string()),
+        }
+    }
+
+    // how well the path matches, None if it does not,
+    // an exact match beats any prefix and longer prefixes beat shorter
+    fn score(&self, path: &str) -> Option<usize> {
+        match *self {
+            Pattern::Exact(ref p) if p == path => Some(::std::usize::MAX),
+            Pattern::Prefix(ref p) if path.starts_with(p.as_str()) => Some(p.len()),
+            _ => None,
+        }
+    }
+
+    fn capture<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match *self {
+            Pattern::Prefix(ref p) => Some(&path[p.len()..]),
+            Pattern::Exact(_) => None,
+        }
+    }
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<Handler>,
+}
+
+/// Sends each request to the handler registered for its path and method
+///
+/// ```ignore
+/// Router::new()
+///     .get("/", index)
+///     .get("/static/*", files)
+///     .post("/upload", upload)
+/// ```
+///
+/// A path ending in "/*" matches everything under it, and what the "*"
+/// matched is available to the handler with Request::wildcard. Exact
+/// paths are preferred over wildcards, and longer wildcards over shorter.
+///
+/// Paths that match no route get the not found handler (a plain 404 by
+/// default), and paths that only match routes for other methods get 405.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<Handler>,
+}
+
+impl Router {
+
+    pub fn new() -> Self {
+        Router { routes: Vec::new(), not_found: Box::new(not_found) }
+    }
+
+    /// add a route for requests with the method to path
+    pub fn route<H: Handler + 'static>(mut self, method: &str, path: &str, handler: H) -> Self {
+        self.routes.push(Route { method: Method::from(method), pattern: Pattern::new(path), handler: Box::new(handler) });
+        self
+    }
+
+    pub fn get<H: Handler + 'static>(self, path: &str, handler: H) -> Self {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H: Handler + 'static>(self, path: &str, handler: H) -> Self {
+        self.route("POST", path, handler)
+    }
+
+    pub fn put<H: Handler + 'static>(self, path: &str, handler: H) -> Self {
+        self.route("PUT", path, handler)
+    }
+
+    pub fn delete<H: Handler + 'static>(self, path: &str, handler: H) -> Self {
+        self.route("DELETE", path, handler)
+    }
+
+    /// replace the handler for paths no route matches
+    pub fn not_found<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.not_found = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, mut req: Request, mut resp: ResponseWriter) {
+        let (best, allowed) = {
+            let path = req.path();
+
+            let matching: Vec<(&Route, usize)> = self.routes.iter()
+                .filter_map(|r| r.pattern.score(path).map(|score| (r, score)))
+                .collect();
+            let top = match matching.iter().map(|&(_, score)| score).max() {
+                Some(top) => top,
+                None => return self.not_found.handle(req, resp),
+            };
+
+            // the best match for the method (the first added on a tie)
+            let best = matching.iter()
+                .filter(|&&(r, score)| score == top && r.method == *req.method())
+                .map(|&(r, _)| (r, r.pattern.capture(path).map(|c| c.to_string())))
+                .next();
+            let mut allowed: Vec<&str> = Vec::new();
+            for &(r, score) in &matching {
+                if score == top && !allowed.contains(&r.method.as_str()) {
+                    allowed.push(r.method.as_str());
+                }
+            }
+            (best, allowed.join(", "))
+        };
+
+        match best {
+            Some((route, wildcard)) => {
+                req.set_wildcard(wildcard);
+                route.handler.handle(req, resp);
+            },
+            None => {
+                let response = Response::new(405).header("allow", allowed);
+                if let Err(e) = resp.send(response) {
+                    klog_debug!("{}", e);
+                }
+            },
+        }
+    }
+}
+
+fn not_found(_req: Request, mut resp: ResponseWriter) {
+    if let Err(e) = resp.send(Response::new(404)) {
+        klog_debug!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod router_tests {
+
+    use std::sync::Arc;
+
+    use super::Router;
+    use connection::Connection;
+    use frame::frame_types::types;
+    use handler::Handler;
+    use header::{Decoder, HeaderList};
+    use request::Request;
+    use response::{Response, ResponseWriter};
+    use test_util::open_stream;
+
+    // answers with its name and the wildcard
+    struct Reply(&'static str);
+
+    impl Handler for Reply {
+        fn handle(&self, req: Request, mut resp: ResponseWriter) {
+            let body = format!("{} {}", self.0, req.wildcard().unwrap_or("-"));
+            resp.send(Response::new(200).body(body)).unwrap();
+        }
+    }
+
+    fn routes() -> Router {
+        Router::new()
+            .get("/", Reply("index"))
+            .get("/static/*", Reply("static"))
+            .get("/static/css/*", Reply("css"))
+            .get("/static/app.js", Reply("app"))
+            .post("/form", Reply("form"))
+            .put("/form", Reply("form"))
+    }
+
+    // send a request through the handler returning the status,
+    // the allow header and the body
+    fn request<H: Handler>(handler: &Arc<H>, method: &'static str, path: &'static str) -> (String, Option<String>, String) {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+
+        open_stream(&mut conn, 1);
+
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":method", method).into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", path).into());
+        handler.handle(Request::from_header_list(list).unwrap(), ResponseWriter::new(&mut conn, 1));
+
+        let headers = conn.next_outbound().unwrap();
+        let headers = Decoder::new(4096, 20).get_header_list(headers.payload()).unwrap();
+        let mut body = String::new();
+        while let Some(frame) = conn.next_outbound() {
+            if frame.frame_type() == types::DATA {
+                body.push_str(::std::str::from_utf8(frame.payload()).unwrap());
+            }
+        }
+        (headers.get_value_by_name(":status").unwrap().to_string(),
+         headers.get_value_by_name("allow").map(|a| a.to_string()),
+         body)
+    }
+
+    fn ok(body: &str) -> (String, Option<String>, String) {
+        ("200".to_string(), None, body.to_string())
+    }
+
+    #[test]
+    fn overlapping_routes() {
+        let router = Arc::new(routes());
+        assert_eq!(request(&router, "GET", "/"), ok("index -"));
+        assert_eq!(request(&router, "GET", "/?q=1"), ok("index -"));
+        assert_eq!(request(&router, "GET", "/static/app.js"), ok("app -"));
+        assert_eq!(request(&router, "GET", "/static/app.css"), ok("static app.css"));
+        assert_eq!(request(&router, "GET", "/static/css/site.css?v=2"), ok("css site.css"));
+        assert_eq!(request(&router, "GET", "/static/img/a/b.png"), ok("static img/a/b.png"));
+    }
+
+    #[test]
+    fn not_found_and_wrong_method() {
+        let router = Arc::new(routes());
+        assert_eq!(request(&router, "GET", "/missing"), ("404".to_string(), None, String::new()));
+        assert_eq!(request(&router, "GET", "/static"), ("404".to_string(), None, String::new()));
+        assert_eq!(request(&router, "GET", "/form"), ("405".to_string(), Some("POST, PUT".to_string()), String::new()));
+        assert_eq!(request(&router, "POST", "/form"), ok("form -"));
+
+        let custom = Arc::new(routes().not_found(Reply("missing")));
+        assert_eq!(request(&custom, "GET", "/missing"), ok("missing -"));
+    }
+}
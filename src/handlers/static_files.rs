@@ -0,0 +1,751 @@
+//! Serving the files under a directory
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use buf::{MappedSlice, Mmap};
+use connection::error::H2Error;
+use handler::Handler;
+use handlers::conditional::{not_modified, range_applies};
+use header::HeaderList;
+use request::Request;
+use response::{Response, ResponseWriter};
+use util::{http_date, Clock, SystemClock};
+
+// what is served for a request for a directory
+const INDEX : &'static str = "index.html";
+
+// the content-codings a file can have a precompressed copy in and the
+// extension added to its name for it, the one to prefer first
+static SIDECARS : &'static [(&'static str, &'static str)] = &[("br", "br"), ("gzip", "gz")];
+
+// content-type by file extension, anything else is application/octet-stream
+static CONTENT_TYPES : &'static [(&'static str, &'static str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "application/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("ico", "image/x-icon"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Serves the files under a root directory
+///
+/// ```ignore
+/// Router::new().get("/static/*", StaticFiles::new(PathBuf::from("public")))
+/// ```
+///
+/// The part of the path a Router wildcard matched is looked up under the
+/// root, or the whole path when there is no wildcard. Paths are checked
+/// after they are percent-decoded, so those that would leave the root with
+/// ".." get 403 even when the dots are encoded, and files that do not exist
+/// get 404. A request for a directory gets its index.html.
+///
+/// Files are sent as they are read, a frame at a time as the flow control
+/// windows open up, so even large files are never held in memory. HEAD
+/// requests only look up the file's metadata.
+///
+/// Responses have an etag made from the file's size and modification time
+/// (to the nanosecond, which makes it a strong one), and a last-modified.
+/// Requests with an if-none-match that has the etag (or an
+/// if-modified-since no older than the file) get 304 without the file
+/// being read.
+///
+/// A request for a single range of bytes gets 206 with just those bytes
+/// (or 416 when the range is past the end of the file), requests for more
+/// than one range get the whole file. With an if-range the range is only
+/// served if it names the file as it is now, otherwise the whole file is
+/// sent, so a download can be resumed without mixing two versions.
+///
+/// With precompressed, a file can have copies next to it that were
+/// compressed ahead of time, app.js.br and app.js.gz for app.js. A client
+/// that accepts one of their encodings is sent that copy (brotli if it
+/// takes both) with a content-encoding, and the content-type of the file
+/// itself. Each copy has an etag of its own. Copies older than the file are
+/// taken to be stale and left alone, and once a file has any copies its
+/// responses get "vary: accept-encoding".
+///
+/// With mmap_above, files (or ranges of them) at least that large are
+/// mapped into memory and sent as slices of the mapping instead of being
+/// read into buffers, which saves copying every byte on the way out. That
+/// is only for unix and for files that are replaced rather than changed
+/// in place (see buf::Mmap), a file that can not be mapped is read as
+/// usual.
+pub struct StaticFiles {
+    root: PathBuf,
+    mmap_threshold: Option<u64>,
+    precompressed: bool,
+    // what if-range dates are checked against
+    clock: Arc<Clock>,
+}
+
+impl StaticFiles {
+
+    pub fn new(root: PathBuf) -> Self {
+        StaticFiles { root: root, mmap_threshold: None, precompressed: false, clock: SystemClock::shared() }
+    }
+
+    /// send the .br or .gz copy of a file the client accepts, if it has one
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// map files of at least threshold bytes into memory to send them
+    pub fn mmap_above(mut self, threshold: u64) -> Self {
+        self.mmap_threshold = Some(threshold);
+        self
+    }
+
+    /// where the time comes from, for whether a file changed too
+    /// recently for its modification time to tell versions apart
+    pub fn clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // the file a (decoded) request path refers to,
+    // or the status to answer with
+    fn resolve(&self, path: &str) -> Result<PathBuf, u16> {
+        let mut file = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {},
+                ".." => return Err(403),
+                s if s.contains('\\') || s.contains('\0') => return Err(403),
+                s => file.push(s),
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, req: Request, mut resp: ResponseWriter) {
+        let res = {
+            let path = match req.wildcard() {
+                Some(wildcard) => wildcard,
+                None => req.path(),
+            };
+            self.resolve(path).and_then(|path| stat(path).map_err(|e| status_for(&e)))
+        };
+
+        let res = match res {
+            Ok((path, metadata)) => {
+                let file = if self.precompressed {
+                    Variant::pick(&req, path, metadata)
+                } else {
+                    Variant::plain(path, metadata)
+                };
+                send_file(&req, &mut resp, &file, self.mmap_threshold, self.clock.system_now())
+            },
+            Err(status) => resp.send(Response::new(status)),
+        };
+        if let Err(e) = res {
+            klog_debug!("{}", e);
+        }
+    }
+}
+
+// what is sent for a file, the file itself or a precompressed copy of it
+struct Variant {
+    // the file that was asked for
+    path: PathBuf,
+    // the one that is sent
+    sent: PathBuf,
+    metadata: fs::Metadata,
+    // the content-coding of a copy
+    encoding: Option<&'static str>,
+    // the file has copies, so what is sent depends on accept-encoding
+    varies: bool,
+}
+
+impl Variant {
+
+    fn plain(path: PathBuf, metadata: fs::Metadata) -> Self {
+        Variant { sent: path.clone(), path: path, metadata: metadata, encoding: None, varies: false }
+    }
+
+    // the first copy of path the request accepts, or path itself
+    fn pick(req: &Request, path: PathBuf, metadata: fs::Metadata) -> Self {
+        let mut variant = Variant::plain(path, metadata);
+        let modified = variant.metadata.modified().ok();
+        for &(encoding, extension) in SIDECARS {
+            let mut name = variant.path.clone().into_os_string();
+            name.push(".");
+            name.push(extension);
+            let copy = PathBuf::from(name);
+            let copy_metadata = match fs::metadata(&copy) {
+                Ok(m) if m.is_file() => m,
+                _ => continue,
+            };
+            // compressed before the file last changed
+            if modified.map_or(false, |modified| copy_metadata.modified().ok().map_or(true, |m| m < modified)) {
+                continue;
+            }
+            variant.varies = true;
+            if variant.encoding.is_none() && req.accepts_encoding(encoding) {
+                variant.sent = copy;
+                variant.metadata = copy_metadata;
+                variant.encoding = Some(encoding);
+            }
+        }
+        variant
+    }
+}
+
+fn send_file(req: &Request, resp: &mut ResponseWriter, file: &Variant,
+             mmap_threshold: Option<u64>, now: SystemTime) -> Result<(), H2Error> {
+    let metadata = &file.metadata;
+    let modified = metadata.modified().ok();
+    let tag = etag(metadata.len(), modified, file.encoding);
+    let last_modified = modified.map(|m| str::from_utf8(&http_date(m)).unwrap().to_string());
+
+    if not_modified(req, &tag, modified) {
+        let mut response = Response::new(304).header("etag", tag);
+        if let Some(last_modified) = last_modified {
+            response = response.header("last-modified", last_modified);
+        }
+        if file.varies {
+            response = response.header("vary", "accept-encoding");
+        }
+        return resp.send(response);
+    }
+
+    let len = metadata.len();
+    let range = if range_applies(req, &tag, modified, now) {
+        byte_range(req.header("range"), len)
+    } else {
+        ByteRange::Full
+    };
+    let mut headers = HeaderList::with_capacity(8);
+    let (status, start, body_len) = match range {
+        ByteRange::Full => {
+            headers.add_entry(("accept-ranges", "bytes").into());
+            (200, 0, len)
+        },
+        ByteRange::Partial(start, end) => {
+            headers.add_entry(("content-range", format!("bytes {}-{}/{}", start, end, len)).into());
+            (206, start, end - start + 1)
+        },
+        ByteRange::Unsatisfiable => {
+            let response = Response::new(416).header("content-range", format!("bytes */{}", len));
+            return resp.send(response);
+        },
+    };
+    headers.add_entry(("content-type", content_type(&file.path)).into());
+    if let Some(encoding) = file.encoding {
+        headers.add_entry(("content-encoding", encoding).into());
+    }
+    if file.varies {
+        headers.add_entry(("vary", "accept-encoding").into());
+    }
+    headers.add_entry(("etag", tag).into());
+    if let Some(last_modified) = last_modified {
+        headers.add_entry(("last-modified", last_modified).into());
+    }
+    // the body is dropped anyway
+    if resp.is_head() {
+        return resp.stream_from(status, headers, io::empty(), Some(body_len));
+    }
+
+    let mut file = match File::open(&file.sent) {
+        Ok(file) => file,
+        Err(e) => return resp.send(Response::new(status_for(&e))),
+    };
+    if let Some(body) = mapped(&file, start, body_len, mmap_threshold) {
+        return resp.stream_mapped(status, headers, body);
+    }
+    match file.seek(SeekFrom::Start(start)) {
+        Ok(_) => resp.stream_from(status, headers, file.take(body_len), Some(body_len)),
+        Err(e) => resp.send(Response::new(status_for(&e))),
+    }
+}
+
+// len bytes of file from start mapped into memory, if they are at least
+// threshold of them and the mapping works
+fn mapped(file: &File, start: u64, len: u64, threshold: Option<u64>) -> Option<MappedSlice> {
+    match threshold {
+        Some(threshold) if len >= threshold && len > 0 && len <= usize::MAX as u64 => {},
+        _ => return None,
+    }
+    match Mmap::map(file, start, len as usize) {
+        Ok(map) => Some(MappedSlice::new(Arc::new(map))),
+        Err(e) => {
+            klog_debug!("could not map the file, reading it instead: {}", e);
+            None
+        },
+    }
+}
+
+// "size-seconds.nanoseconds" of the modification time, in hex, with
+// the content-coding after it for a precompressed copy
+fn etag(len: u64, modified: Option<SystemTime>, encoding: Option<&str>) -> String {
+    let since = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok());
+    let (secs, nanos) = since.map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()));
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}.{:x}-{}\"", len, secs, nanos, encoding),
+        None => format!("\"{:x}-{:x}.{:x}\"", len, secs, nanos),
+    }
+}
+
+// what part of a file a request asks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteRange {
+    Full,
+    // first and last byte
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// RFC 7233 2.1 a single byte range of a file of len bytes, a range header
+// that is not understood (or has more than one range) is ignored
+fn byte_range(range: Option<&str>, len: u64) -> ByteRange {
+    let spec = match range {
+        Some(range) if range.starts_with("bytes=") && !range.contains(',') => range[6..].trim(),
+        _ => return ByteRange::Full,
+    };
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return ByteRange::Full,
+    };
+    let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+    // bytes=-500, the last 500 bytes
+    if first.is_empty() {
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+
+    // bytes=100-199 or bytes=100-
+    let first = match first.parse::<u64>() {
+        Ok(first) => first,
+        Err(_) => return ByteRange::Full,
+    };
+    let last = match last {
+        "" => None,
+        last => match last.parse::<u64>() {
+            Ok(last) if last >= first => Some(last),
+            _ => return ByteRange::Full,
+        },
+    };
+    if first >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(first, cmp::min(last.unwrap_or(len - 1), len - 1))
+}
+
+// the metadata of a file, or of the index of a directory
+fn stat(path: PathBuf) -> io::Result<(PathBuf, fs::Metadata)> {
+    let metadata = fs::metadata(&path)?;
+    if metadata.is_dir() {
+        return stat(path.join(INDEX));
+    }
+    Ok((path, metadata))
+}
+
+fn status_for(e: &io::Error) -> u16 {
+    match e.kind() {
+        io::ErrorKind::NotFound => 404,
+        io::ErrorKind::PermissionDenied => 403,
+        _ => 500,
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+    CONTENT_TYPES.iter()
+        .find(|&&(e, _)| e == extension)
+        .map_or("application/octet-stream", |&(_, t)| t)
+}
+
+#[cfg(test)]
+mod static_files_tests {
+
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+    use std::process;
+    use std::str;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{mapped, StaticFiles};
+    use connection::Connection;
+    use connection::handshake::PREFACE;
+    use connection::mock::SharedStream;
+    use connection::reader::FrameReader;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags};
+    use header::{Decoder, Encoder, HeaderList};
+    use util::{http_date, Clock, SystemClock};
+
+    // a directory of files to serve, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("kurisu-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn file(&self, name: &str, contents: &[u8]) {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // GET path from the files, followed by window_updates WINDOW_UPDATE
+    // frames of 64KB each for the stream and the connection, returning the
+    // response headers, the body and the largest DATA frame
+    fn get(files: StaticFiles, path: &'static str, window_updates: usize) -> (HeaderList, Vec<u8>, usize) {
+        request(files, "GET", path, &[], window_updates)
+    }
+
+    // the same with another method and more headers
+    fn request(files: StaticFiles, method: &'static str, path: &'static str, headers: &[(&'static str, &str)], window_updates: usize)
+        -> (HeaderList, Vec<u8>, usize) {
+        let mut list = HeaderList::with_capacity(3 + headers.len());
+        list.add_entry((":method", method).into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", path).into());
+        for &(name, value) in headers {
+            list.add_entry((name, value.to_string()).into());
+        }
+        let block = Encoder::new(4096, 20).encode_header_list(&list);
+
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, &block, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        for _ in 0..window_updates {
+            input.extend_from_slice(OwnedFrame::window_update(0, 0x10000).as_bytes());
+            input.extend_from_slice(OwnedFrame::window_update(1, 0x10000).as_bytes());
+        }
+        let stream = SharedStream::new(input);
+        Connection::serve(stream.clone(), false, Arc::new(files)).unwrap();
+
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(stream.output());
+        let mut headers = None;
+        let mut body = Vec::new();
+        let mut largest = 0;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match frame.get_type() {
+                types::HEADERS => headers = Some(Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap()),
+                types::DATA => {
+                    largest = ::std::cmp::max(largest, frame.payload().len());
+                    body.extend_from_slice(frame.payload());
+                },
+                _ => {},
+            }
+        }
+        (headers.unwrap(), body, largest)
+    }
+
+    fn status(headers: &HeaderList) -> &str {
+        headers.get_value_by_name(":status").unwrap()
+    }
+
+    #[test]
+    fn large_file_round_trip() {
+        let dir = TempDir::new("large");
+        let contents: Vec<u8> = (0..1024 * 1024).map(|i: u32| (i * 7 % 251) as u8).collect();
+        dir.file("big.bin", &contents);
+
+        // the default windows only allow 64KB before WINDOW_UPDATEs
+        let (headers, body, largest) = get(StaticFiles::new(dir.0.clone()), "/big.bin", 16);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(headers.get_value_by_name("content-length"), Some("1048576"));
+        assert!(headers.get_value_by_name("last-modified").is_some());
+        assert!(largest <= 16384);
+        assert!(body == contents);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn mapped_files() {
+        let dir = TempDir::new("mapped");
+        let contents: Vec<u8> = (0..10 * 1024 * 1024).map(|i: u32| (i * 7 % 251) as u8).collect();
+        dir.file("big.bin", &contents);
+        dir.file("tiny.txt", b"tiny");
+        let files = || StaticFiles::new(dir.0.clone()).mmap_above(64 * 1024);
+
+        let big = File::open(dir.0.join("big.bin")).unwrap();
+        assert!(mapped(&big, 0, contents.len() as u64, Some(64 * 1024)).is_some());
+        let (headers, body, largest) = get(files(), "/big.bin", 160);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(headers.get_value_by_name("content-length"), Some("10485760"));
+        assert!(largest <= 16384);
+        assert!(body == contents);
+
+        // a range that does not start on a page
+        let (headers, body, _) = request(files(), "GET", "/big.bin", &[("range", "bytes=5000-")], 160);
+        assert_eq!(status(&headers), "206");
+        assert!(body[..] == contents[5000..]);
+
+        // small files are read
+        let tiny = File::open(dir.0.join("tiny.txt")).unwrap();
+        assert!(mapped(&tiny, 0, 4, Some(64 * 1024)).is_none());
+        assert!(mapped(&big, 0, contents.len() as u64, None).is_none());
+        let (headers, body, _) = get(files(), "/tiny.txt", 0);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(body, b"tiny");
+    }
+
+    #[test]
+    fn traversal_blocked() {
+        let dir = TempDir::new("traversal");
+        dir.file("public/index.html", b"<p>hi</p>");
+        dir.file("secret.txt", b"secret");
+        let public = || StaticFiles::new(dir.0.join("public"));
+
+        assert_eq!(status(&get(public(), "/../secret.txt", 0).0), "403");
+        assert_eq!(status(&get(public(), "/%2e%2e/secret.txt", 0).0), "403");
+        assert_eq!(status(&get(public(), "/a/%2E%2E/%2e%2e/secret.txt", 0).0), "403");
+        assert_eq!(status(&get(public(), "/%2", 0).0), "400");
+        assert_eq!(status(&get(public(), "/missing.txt", 0).0), "404");
+
+        // directories get their index
+        let (headers, body, _) = get(public(), "/?page=1", 0);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(headers.get_value_by_name("content-type"), Some("text/html; charset=utf-8"));
+        assert_eq!(body, b"<p>hi</p>");
+    }
+
+    #[test]
+    fn head_only_stats() {
+        let dir = TempDir::new("head");
+        dir.file("page.html", &[b'x'; 100000]);
+
+        let (headers, body, _) = request(StaticFiles::new(dir.0.clone()), "HEAD", "/page.html", &[], 0);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(headers.get_value_by_name("content-length"), Some("100000"));
+        assert_eq!(headers.get_value_by_name("content-type"), Some("text/html; charset=utf-8"));
+        assert!(body.is_empty());
+
+        let (headers, _, _) = request(StaticFiles::new(dir.0.clone()), "HEAD", "/missing.html", &[], 0);
+        assert_eq!(status(&headers), "404");
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let dir = TempDir::new("ranges");
+        let contents: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        dir.file("data.bin", &contents);
+        let range = |value: &str| request(StaticFiles::new(dir.0.clone()), "GET", "/data.bin", &[("range", value)], 0);
+
+        let (headers, body, _) = range("bytes=100-199");
+        assert_eq!(status(&headers), "206");
+        assert_eq!(headers.get_value_by_name("content-range"), Some("bytes 100-199/1000"));
+        assert_eq!(headers.get_value_by_name("content-length"), Some("100"));
+        assert!(body == &contents[100..200]);
+
+        let (headers, body, _) = range("bytes=-300");
+        assert_eq!(headers.get_value_by_name("content-range"), Some("bytes 700-999/1000"));
+        assert!(body == &contents[700..]);
+
+        let (headers, body, _) = range("bytes=990-");
+        assert_eq!(headers.get_value_by_name("content-range"), Some("bytes 990-999/1000"));
+        assert!(body == &contents[990..]);
+
+        // the end is clamped to the file, the start can not be past it
+        let (headers, body, _) = range("bytes=900-5000");
+        assert_eq!(headers.get_value_by_name("content-range"), Some("bytes 900-999/1000"));
+        assert_eq!(body.len(), 100);
+        let (headers, body, _) = range("bytes=1000-");
+        assert_eq!(status(&headers), "416");
+        assert_eq!(headers.get_value_by_name("content-range"), Some("bytes */1000"));
+        assert!(body.is_empty());
+
+        // more than one range, or nonsense, gets the whole file
+        for value in &["bytes=0-1,5-6", "bytes=5-1", "items=0-1"] {
+            let (headers, body, _) = range(value);
+            assert_eq!(status(&headers), "200");
+            assert_eq!(headers.get_value_by_name("accept-ranges"), Some("bytes"));
+            assert!(body == contents);
+        }
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let dir = TempDir::new("etags");
+        dir.file("app.js", b"console.log(1)");
+        let files = || StaticFiles::new(dir.0.clone());
+
+        let (headers, body, _) = get(files(), "/app.js", 0);
+        let etag = headers.get_value_by_name("etag").unwrap().to_string();
+        let last_modified = headers.get_value_by_name("last-modified").unwrap().to_string();
+        assert!(etag.starts_with("\""));
+        assert_eq!(body, b"console.log(1)");
+
+        for value in &[&etag[..], "*", &format!("\"other\", W/{}", etag)] {
+            let (headers, body, largest) = request(files(), "GET", "/app.js", &[("if-none-match", value)], 0);
+            assert_eq!(status(&headers), "304");
+            assert_eq!(headers.get_value_by_name("etag"), Some(&etag[..]));
+            assert_eq!(headers.get_value_by_name("last-modified"), Some(&last_modified[..]));
+            assert_eq!(headers.get_value_by_name("content-length"), None);
+            assert!(body.is_empty() && largest == 0);
+        }
+        let (headers, _, _) = request(files(), "GET", "/app.js", &[("if-modified-since", &last_modified)], 0);
+        assert_eq!(status(&headers), "304");
+        let (headers, _, _) = request(files(), "GET", "/app.js", &[("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT")], 0);
+        assert_eq!(status(&headers), "200");
+
+        // a changed file has another etag
+        dir.file("app.js", b"console.log(2, 3)");
+        let (headers, body, _) = request(files(), "GET", "/app.js", &[("if-none-match", &etag)], 0);
+        assert_eq!(status(&headers), "200");
+        assert!(headers.get_value_by_name("etag") != Some(&etag[..]));
+        assert_eq!(body, b"console.log(2, 3)");
+    }
+
+    #[test]
+    fn resumed_download() {
+        let dir = TempDir::new("resume");
+        let contents: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        dir.file("data.bin", &contents);
+        // old enough for its modification time to be a strong validator
+        let modified = SystemClock.system_now() - Duration::from_secs(3600);
+        OpenOptions::new().write(true).open(dir.0.join("data.bin")).unwrap().set_modified(modified).unwrap();
+        let resume = |if_range: &str| request(StaticFiles::new(dir.0.clone()), "GET", "/data.bin",
+                                              &[("range", "bytes=600-"), ("if-range", if_range)], 0);
+
+        let (headers, _, _) = get(StaticFiles::new(dir.0.clone()), "/data.bin", 0);
+        let etag = headers.get_value_by_name("etag").unwrap().to_string();
+        let last_modified = headers.get_value_by_name("last-modified").unwrap().to_string();
+
+        // the file the client has part of, the rest of it
+        for validator in &[&etag, &last_modified] {
+            let (headers, body, _) = resume(validator);
+            assert_eq!(status(&headers), "206");
+            assert_eq!(headers.get_value_by_name("content-range"), Some("bytes 600-999/1000"));
+            assert!(body == &contents[600..]);
+        }
+
+        // another version, all of it
+        let stale = str::from_utf8(&http_date(modified - Duration::from_secs(60))).unwrap().to_string();
+        for validator in &["\"0-0.0\"", &format!("W/{}", etag), &stale] {
+            let (headers, body, _) = resume(validator);
+            assert_eq!(status(&headers), "200");
+            assert_eq!(headers.get_value_by_name("content-range"), None);
+            assert!(body == contents);
+        }
+    }
+
+    #[test]
+    fn precompressed() {
+        let dir = TempDir::new("precompressed");
+        dir.file("app.js", b"console.log('plain')");
+        dir.file("app.js.gz", b"gzipped");
+        dir.file("style.css", b"p {}");
+        dir.file("style.css.gz", b"gz");
+        dir.file("style.css.br", b"br");
+        let files = || StaticFiles::new(dir.0.clone()).precompressed(true);
+        let fetch = |path, headers: &[(&'static str, &str)]| request(files(), "GET", path, headers, 0);
+
+        let (headers, body, _) = fetch("/app.js", &[("accept-encoding", "gzip, deflate")]);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(body, b"gzipped");
+        assert_eq!(headers.get_value_by_name("content-encoding"), Some("gzip"));
+        assert_eq!(headers.get_value_by_name("content-type"), Some("application/javascript; charset=utf-8"));
+        assert_eq!(headers.get_value_by_name("content-length"), Some("7"));
+        assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+        let gzip_etag = headers.get_value_by_name("etag").unwrap().to_string();
+        assert!(gzip_etag.ends_with("-gzip\""));
+
+        // a client that does not take gzip gets the file itself
+        for accept in &["br", "gzip;q=0", "identity"] {
+            let (headers, body, _) = fetch("/app.js", &[("accept-encoding", accept)]);
+            assert_eq!(body, b"console.log('plain')");
+            assert_eq!(headers.get_value_by_name("content-encoding"), None);
+            assert_eq!(headers.get_value_by_name("content-length"), Some("20"));
+            assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+        }
+        let (headers, _, _) = fetch("/app.js", &[]);
+        let plain_etag = headers.get_value_by_name("etag").unwrap().to_string();
+        assert!(plain_etag != gzip_etag);
+
+        // brotli goes first when both are taken
+        let (headers, body, _) = fetch("/style.css", &[("accept-encoding", "gzip, br")]);
+        assert_eq!(body, b"br");
+        assert_eq!(headers.get_value_by_name("content-encoding"), Some("br"));
+        assert_eq!(headers.get_value_by_name("content-type"), Some("text/css; charset=utf-8"));
+
+        // each etag only matches its own variant
+        let (headers, body, _) = fetch("/app.js", &[("accept-encoding", "gzip"), ("if-none-match", &gzip_etag)]);
+        assert_eq!(status(&headers), "304");
+        assert_eq!(headers.get_value_by_name("etag"), Some(&gzip_etag[..]));
+        assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+        assert!(body.is_empty());
+        let (headers, body, _) = fetch("/app.js", &[("if-none-match", &gzip_etag)]);
+        assert_eq!(status(&headers), "200");
+        assert_eq!(body, b"console.log('plain')");
+        let (headers, _, _) = fetch("/app.js", &[("accept-encoding", "gzip"), ("if-none-match", &plain_etag)]);
+        assert_eq!(status(&headers), "200");
+        let (headers, _, _) = fetch("/app.js", &[("if-none-match", &plain_etag)]);
+        assert_eq!(status(&headers), "304");
+
+        // a copy older than the file is stale
+        let old = SystemClock.system_now() - Duration::from_secs(3600);
+        OpenOptions::new().write(true).open(dir.0.join("app.js.gz")).unwrap().set_modified(old).unwrap();
+        let (headers, body, _) = fetch("/app.js", &[("accept-encoding", "gzip")]);
+        assert_eq!(body, b"console.log('plain')");
+        assert_eq!(headers.get_value_by_name("vary"), None);
+
+        // and without precompressed the copies are just files
+        let (headers, body, _) = request(StaticFiles::new(dir.0.clone()), "GET", "/style.css", &[("accept-encoding", "br")], 0);
+        assert_eq!(body, b"p {}");
+        assert_eq!(headers.get_value_by_name("content-encoding"), None);
+        assert_eq!(headers.get_value_by_name("vary"), None);
+    }
+
+    #[test]
+    fn content_types() {
+        let dir = TempDir::new("types");
+        dir.file("data.xyz", b"?");
+        dir.file("no extension", b"?");
+        dir.file("style.CSS", b"p {}");
+
+        let files = || StaticFiles::new(dir.0.clone());
+        let content_type = |path| get(files(), path, 0).0.get_value_by_name("content-type").unwrap().to_string();
+        assert_eq!(content_type("/data.xyz"), "application/octet-stream");
+        assert_eq!(content_type("/no%20extension"), "application/octet-stream");
+        assert_eq!(content_type("/style.CSS"), "text/css; charset=utf-8");
+    }
+}
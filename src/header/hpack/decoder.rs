@@ -5,6 +5,8 @@ use super::huffman::Huffman;
 use std::iter::Peekable;
 
 use borrow_iter::BorrowTake;
+use krserr::Kresult;
+use super::error::HpackError;
 
 use header::*;
 
@@ -22,6 +24,26 @@ impl Decoder {
             huffman: Huffman::new() }
     }
 
+    /// the dynamic table as (name, value), newest (index 62) first
+    #[cfg(any(test, feature = "testing"))]
+    pub fn table_snapshot(&self) -> Vec<(String, String)> {
+        self.table.snapshot()
+    }
+
+    /// start from a dynamic table holding entries, in the order
+    /// table_snapshot gives them, as if partway through a connection
+    #[cfg(any(test, feature = "testing"))]
+    pub fn preload_table(&mut self, entries: &[(&str, &str)]) {
+        self.table.preload(entries);
+    }
+
+    /// the size of the dynamic table the way the spec counts it
+    /// (RFC 7541 4.1)
+    #[cfg(any(test, feature = "testing"))]
+    pub fn table_size(&self) -> usize {
+        self.table.size()
+    }
+
     /// function that takes the hpack block part of the header
     /// and creates a header list from it.
     ///
@@ -30,7 +52,11 @@ impl Decoder {
     ///
     /// Needs the dynamic table to be managed by the connection
     /// because it is a stateful list used for the entire connection
-    pub fn get_header_list(&mut self, hpack_block: &[u8]) -> Result<HeaderList, &'static str> {
+    pub fn get_header_list(&mut self, hpack_block: &[u8]) -> Kresult<HeaderList> {
+        self.decode_block(hpack_block).map_err(|reason| HpackError::new(reason).into())
+    }
+
+    fn decode_block(&mut self, hpack_block: &[u8]) -> Result<HeaderList, &'static str> {
 
         let mut bts = hpack_block.iter().peekable();
 
@@ -65,18 +91,28 @@ impl Decoder {
     // be carful using this funciton as it is stateful, call it in the correct order
     fn consume_literal<'a, I: Iterator<Item=&'a u8>>(&self, bts: &mut Peekable<I>) -> Result<String, &'static str> {
         // get value length and huffman status
-        let is_huffman = *bts.peek().unwrap() & 0x80 == 0x80;
+        let is_huffman = match bts.peek() {
+            Some(b) => *b & 0x80 == 0x80,
+            None => return Err("hpack literal: missing length"),
+        };
         let length = try!(integers::decode_integer(bts, 7)) as usize;
 
+        // the length comes from the peer, it can go past the end of the block
+        let raw: Vec<u8> = bts.borrow_take(length).map(|x|*x).collect();
+        if raw.len() != length {
+            return Err("hpack literal: length past the end of the block");
+        }
+
         let value;
         if is_huffman {
-            value = self.huffman.decode(bts.borrow_take(length));
+            value = try!(self.huffman.decode(&raw));
         }
         else {
-            value = bts.borrow_take(length).map(|x|*x).collect();
+            value = raw;
         }
 
-        unsafe { Ok(String::from_utf8_unchecked(value)) }
+        // what the peer sent ends up in a &str, so it has to be checked
+        String::from_utf8(value).map_err(|_| "hpack literal: not valid UTF-8")
     }
 
     /// ===============================
@@ -221,10 +257,9 @@ impl Decoder {
     /// represented as a string literal (see Section 5.2).
 
     fn literal_header_unindexed<'a, I: Iterator<Item=&'a u8>>(&self, bts: &mut Peekable<I>) -> Result<HeaderEntry, &'static str> {
-        // this function is more useful for intermediaries which
-        // this library does not care about at the moment
-        // so it will be treated the same as never indexed
-        self.literal_header_never_indexed(bts)
+        // the peer only chose not to index it, a list that is encoded
+        // again is free to (unlike one never indexed)
+        self.literal_header_not_added(bts, false)
     }
 
     ///
@@ -271,6 +306,12 @@ impl Decoder {
     /// The encoding of the representation is identical to the literal header field without indexing (see Section 6.2.2).
 
     fn literal_header_never_indexed<'a, I: Iterator<Item=&'a u8>>(&self, bts: &mut Peekable<I>) -> Result<HeaderEntry, &'static str> {
+        self.literal_header_not_added(bts, true)
+    }
+
+    // 6.2.2 and 6.2.3 are encoded the same, only a never indexed entry
+    // is sensitive (so it stays never indexed if it is sent on)
+    fn literal_header_not_added<'a, I: Iterator<Item=&'a u8>>(&self, bts: &mut Peekable<I>, sensitive: bool) -> Result<HeaderEntry, &'static str> {
 
         let index = try!(integers::decode_integer(bts, 4));
 
@@ -278,12 +319,18 @@ impl Decoder {
         if index == 0 { // must get name and value from literal
             let name = try!(self.consume_literal(bts));
             let value = try!(self.consume_literal(bts));
-            header_entry = HeaderEntry::new(name, value);
+            header_entry = match sensitive {
+                true => HeaderEntry::sensitive(name, value),
+                false => HeaderEntry::new(name, value),
+            };
         }
         else { // have name via index
             let name_rc = try!(self.table.get_name_rc(index as usize));
             let value = try!(self.consume_literal(bts));
-            header_entry = HeaderEntry::new(name_rc, value);
+            header_entry = match sensitive {
+                true => HeaderEntry::sensitive(name_rc, value),
+                false => HeaderEntry::new(name_rc, value),
+            };
         }
 
         Ok(header_entry)
@@ -337,6 +384,93 @@ mod decoder_tests {
         assert_eq!(list.get_value_by_name("accept-charset"), Some("1"));
     }
 
+    #[test]
+    fn literal_past_the_end() {
+        let mut decoder = Decoder::new(100, 10);
+
+        // "accept-charset" with a value of 5 octets when 1 is left, this
+        // used to decode as "1"
+        assert!(decoder.get_header_list(&[0x0F, 0x00, 0x05, 0x31]).is_err());
+
+        // and a name with no value length at all used to panic
+        assert!(decoder.get_header_list(&[0x0F, 0x00]).is_err());
+
+        // a huffman value that is too short for its length
+        assert!(decoder.get_header_list(&[0x0F, 0x00, 0x83, 0x1F]).is_err());
+        assert_eq!(decoder.get_header_list(&[0x0F, 0x00, 0x81, 0x1F]).unwrap().get_value_by_name("accept-charset"), Some("a"));
+    }
+
+    #[test]
+    fn literal_not_utf8() {
+        let mut decoder = Decoder::new(100, 10);
+
+        // a value, and a name, that are not UTF-8
+        assert!(decoder.get_header_list(&[0x0F, 0x00, 0x02, 0xC3, 0x28]).is_err());
+        assert!(decoder.get_header_list(&[0x00, 0x01, 0xFF, 0x01, 0x61]).is_err());
+        assert_eq!(decoder.get_header_list(&[0x0F, 0x00, 0x02, 0xC3, 0xA9]).unwrap().get_value_by_name("accept-charset"), Some("\u{e9}"));
+    }
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn indexing_mid_connection() {
+        // room for three entries of 34
+        let mut decoder = Decoder::new(110, 10);
+        decoder.preload_table(&[("x-b", "2"), ("x-a", "1")]);
+        assert_eq!(decoder.table_size(), 72);
+
+        // 62 and 63 are the preloaded entries
+        let list = decoder.get_header_list(&[0xBE, 0xBF]).unwrap();
+        assert_eq!(list.get_value_by_name("x-b"), Some("2"));
+        assert_eq!(list.get_value_by_name("x-a"), Some("1"));
+
+        // a new entry named after 63 and one literal, which evicts the oldest
+        let list = decoder.get_header_list(&[0x7F, 0x00, 0x01, b'3', 0x40, 0x01, b'y', 0x01, b'4']).unwrap();
+        assert_eq!(list.get_value_by_name("x-a"), Some("3"));
+        assert_eq!(list.get_value_by_name("y"), Some("4"));
+        assert_eq!(decoder.table_snapshot(), pairs(&[("y", "4"), ("x-a", "3"), ("x-b", "2")]));
+        assert_eq!(decoder.table_size(), 34 + 36 + 36);
+
+        // literals that are not indexed leave the table alone
+        decoder.get_header_list(&[0x00, 0x01, b'z', 0x01, b'5', 0x10, 0x01, b'z', 0x01, b'6']).unwrap();
+        assert_eq!(decoder.table_size(), 106);
+    }
+
+    #[test]
+    fn never_indexed_is_sensitive() {
+        let mut decoder = Decoder::new(4096, 10);
+
+        // 0000 without indexing and 0001 never indexed, each with an
+        // indexed name (cookie, 32) and a new one
+        let list = decoder.get_header_list(&[
+            0x0F, 0x11, 0x01, b'a',
+            0x00, 0x01, b'x', 0x01, b'b',
+            0x1F, 0x11, 0x01, b'c',
+            0x10, 0x01, b'y', 0x01, b'd',
+        ]).unwrap();
+        let entries: Vec<_> = list.iter().map(|e| (e.name(), e.value(), e.is_sensitive())).collect();
+        assert_eq!(entries, vec![("cookie", "a", false), ("x", "b", false), ("cookie", "c", true), ("y", "d", true)]);
+        assert_eq!(decoder.table_size(), 0);
+    }
+
+    #[test]
+    fn size_update_evicts() {
+        let mut decoder = Decoder::new(4096, 10);
+        decoder.preload_table(&[("c", "3"), ("b", "2"), ("a", "1")]);
+
+        // down to 70 drops only the oldest
+        decoder.get_header_list(&[0x3F, 0x27]).unwrap();
+        assert_eq!(decoder.table_snapshot(), pairs(&[("c", "3"), ("b", "2")]));
+        assert_eq!(decoder.table_size(), 68);
+
+        // and to 0 empties it, after which 62 is out of range
+        assert!(decoder.get_header_list(&[0x20, 0xBE]).is_err());
+        assert_eq!(decoder.table_snapshot(), pairs(&[]));
+        assert_eq!(decoder.table_size(), 0);
+    }
+
     #[test]
     fn comp_decoder_test() {
         let mut decoder = Decoder::new(4096, 10);
@@ -358,5 +492,9 @@ mod decoder_tests {
         assert_eq!(list.get_value_by_name("accept"), Some("text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,image/apng,*/*;q=0.8"));
         assert_eq!(list.get_value_by_name("accept-encoding"), Some("gzip, deflate, br"));
         assert_eq!(list.get_value_by_name("accept-language"), Some("en-US,en;q=0.8"));
+
+        // the block indexed these, newest first
+        let names: Vec<String> = decoder.table_snapshot().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["accept-language", "accept-encoding", "accept", "user-agent", "upgrade-insecure-requests", ":authority"]);
     }
 }
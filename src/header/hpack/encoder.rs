@@ -0,0 +1,652 @@
+use std::cmp;
+use std::collections::HashSet;
+
+use super::table::Table;
+use super::huffman::Huffman;
+use super::integers;
+use super::error::HpackError;
+
+use krserr::Kresult;
+
+use header::*;
+
+/// the fields that are always sent never indexed (7.1.3), credentials
+/// and cookies that could otherwise be guessed at one byte at a time by
+/// watching how well they compress
+pub static DEFAULT_SENSITIVE : &'static [&'static str] = &["authorization", "cookie", "proxy-authorization", "set-cookie"];
+
+/// How the string literals of a header block are sent (5.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralEncoding {
+    /// Huffman coded when that is shorter than the raw octets, the default
+    Shortest,
+    /// always the raw octets, to read a block off the wire
+    Raw,
+    /// always Huffman coded, even when it comes out longer
+    Huffman,
+}
+
+pub struct Encoder {
+    table: Table,
+    // 6.5.2 the peer's SETTINGS_MAX_HEADER_LIST_SIZE, None is unlimited
+    max_list_size: Option<usize>,
+    // the names of the fields that are sent never indexed, whatever entry they are in
+    sensitive: HashSet<String>,
+    // the entries to keep in the dynamic table once they are in it
+    primed: HashSet<(String, String)>,
+    literals: LiteralEncoding,
+    huffman: Huffman,
+    // 4.2 the smallest the table was made since the last block, which
+    // the next block starts by telling the peer (along with the size now)
+    size_update: Option<usize>,
+}
+
+impl Encoder {
+
+    // same as the Decoder, the max_size is the hpack spec size
+    // and the number of entries is just an assumption
+    pub fn new(max_size: usize, num_entries: usize) -> Self {
+        Encoder {
+            table: Table::new(max_size, num_entries),
+            max_list_size: None,
+            sensitive: DEFAULT_SENSITIVE.iter().map(|name| name.to_string()).collect(),
+            primed: HashSet::new(),
+            literals: LiteralEncoding::Shortest,
+            huffman: Huffman::new(),
+            size_update: None,
+        }
+    }
+
+    /// make the dynamic table max_size, as the peer allows with
+    /// SETTINGS_HEADER_TABLE_SIZE
+    ///
+    /// The entries that no longer fit are evicted now, and the next block
+    /// starts with the dynamic table size update that has the peer do the
+    /// same. When the size changed more than once in between, it gets the
+    /// smallest it was first, then the size it is now (RFC 7541 4.2).
+    pub fn set_max_table_size(&mut self, max_size: usize) {
+        if max_size == self.table.max_size() && self.size_update.is_none() {
+            return;
+        }
+        let smallest = self.size_update.map_or(max_size, |size| cmp::min(size, max_size));
+        self.size_update = Some(smallest);
+        self.table.max_size_update(max_size);
+    }
+
+    /// how string literals are sent from the next block on
+    pub fn set_literal_encoding(&mut self, literals: LiteralEncoding) {
+        self.literals = literals;
+    }
+
+    /// the entries that go out with nearly every header block (the server,
+    /// the usual content-type or cache-control)
+    ///
+    /// Nothing is sent for them until a block has them, the peer has to
+    /// see them go into its table. Then they stay: another entry is only
+    /// added to the table if that evicts none of these, otherwise it is
+    /// sent without indexing. So a few one-off values (or a long one) do
+    /// not push out what every response needs.
+    pub fn prime(&mut self, entries: &[(&str, &str)]) {
+        for &(name, value) in entries {
+            self.primed.insert((name.to_ascii_lowercase(), value.to_string()));
+        }
+    }
+
+    /// stop keeping the entries called name, for when the value every
+    /// block had is replaced (what is in the table can be evicted again)
+    pub fn unprime(&mut self, name: &str) {
+        let name = name.to_ascii_lowercase();
+        self.primed.retain(|&(ref n, _)| *n != name);
+    }
+
+    pub(crate) fn is_primed(&self, name: &str, value: &str) -> bool {
+        // the set is small, and usually empty
+        self.primed.iter().any(|&(ref n, ref v)| n == name && v == value)
+    }
+
+    /// send every field called name never indexed from now on, as the
+    /// ones in DEFAULT_SENSITIVE are
+    pub fn mark_sensitive(&mut self, name: &str) {
+        self.sensitive.insert(name.to_ascii_lowercase());
+    }
+
+    /// is a field called name always sent never indexed
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        self.sensitive.contains(name)
+    }
+
+    /// the dynamic table as (name, value), newest (index 62) first
+    #[cfg(any(test, feature = "testing"))]
+    pub fn table_snapshot(&self) -> Vec<(String, String)> {
+        self.table.snapshot()
+    }
+
+    /// start from a dynamic table holding entries, in the order
+    /// table_snapshot gives them, as if partway through a connection
+    #[cfg(any(test, feature = "testing"))]
+    pub fn preload_table(&mut self, entries: &[(&str, &str)]) {
+        self.table.preload(entries);
+    }
+
+    /// the size of the dynamic table the way the spec counts it
+    /// (RFC 7541 4.1)
+    #[cfg(any(test, feature = "testing"))]
+    pub fn table_size(&self) -> usize {
+        self.table.size()
+    }
+
+    /// the largest header list the peer accepts, as it advertised
+    /// with SETTINGS_MAX_HEADER_LIST_SIZE
+    pub fn set_max_header_list_size(&mut self, max: Option<u32>) {
+        self.max_list_size = max.map(|max| max as usize);
+    }
+
+    /// is header_list small enough for the peer to accept
+    pub fn fits(&self, header_list: &HeaderList) -> bool {
+        self.max_list_size.map_or(true, |max| header_list.size() <= max)
+    }
+
+    /// encode_header_list, unless the list is bigger than the peer accepts
+    /// (which it would take as a reason to end the connection)
+    pub fn try_encode_header_list(&mut self, header_list: &HeaderList) -> Kresult<Vec<u8>> {
+        if !self.fits(header_list) {
+            return Err(HpackError::new("hpack: header list is larger than the peer accepts").into());
+        }
+        Ok(self.encode_header_list(header_list))
+    }
+
+    /// encode a header list into a complete hpack block
+    /// (ready to be split over HEADERS and CONTINUATION frames)
+    ///
+    /// Entries that are in the static or dynamic table already are
+    /// indexed. The rest are sent as literals with incremental indexing
+    /// (the name indexed if possible), which adds them to the dynamic
+    /// table the same way the peer's decoder does, so the next block that
+    /// has them only needs the index. An entry too big for the table, or
+    /// one that would push out a primed entry, is sent without indexing
+    /// instead. Sensitive entries (and every entry with a name marked
+    /// sensitive) are always literals marked as never indexed, so no one
+    /// along the way indexes them either. That goes for a sensitive one
+    /// that is in a table already too.
+    pub fn encode_header_list(&mut self, header_list: &HeaderList) -> Vec<u8> {
+        let mut block = Vec::new();
+
+        // 6.3 Dynamic Table Size Update, before any field
+        if let Some(smallest) = self.size_update.take() {
+            put_integer(&mut block, smallest as u32, 5, 0x20);
+            if smallest != self.table.max_size() {
+                put_integer(&mut block, self.table.max_size() as u32, 5, 0x20);
+            }
+        }
+
+        for entry in header_list.iter() {
+            let (name, value) = (entry.name(), entry.value());
+            if entry.is_sensitive() || self.is_sensitive(name) {
+                // 6.2.3 Literal Header Field Never Indexed
+                self.put_name(&mut block, name, 4, 0x10);
+                self.put_literal(&mut block, value);
+                continue;
+            }
+            if let Some(index) = self.table.find_index(name, value) {
+                // 6.1 Indexed Header Field
+                put_integer(&mut block, index as u32, 7, 0x80);
+                continue;
+            }
+            let keep = |n: &str, v: &str| self.is_primed(n, v);
+            if !self.table.fits(name, value) || (!keep(name, value) && self.table.would_evict(name, value, keep)) {
+                // 6.2.2 Literal Header Field without Indexing
+                self.put_name(&mut block, name, 4, 0x00);
+                self.put_literal(&mut block, value);
+                continue;
+            }
+            // 6.2.1 Literal Header Field with Incremental Indexing
+            match self.table.find_name_index(name) {
+                Some(index) => {
+                    put_integer(&mut block, index as u32, 6, 0x40);
+                    // the index was just found, so it is in range
+                    self.table.add_entry_id(index, value.to_string()).unwrap();
+                },
+                None => {
+                    block.push(0x40);
+                    self.put_literal(&mut block, name);
+                    self.table.add_entry_literal(name.to_string(), value.to_string());
+                },
+            }
+            self.put_literal(&mut block, value);
+        }
+
+        block
+    }
+
+    // the first octet of a literal representation (its pattern and the
+    // index of the name in a prefix of prefix_size bits), then the name
+    // itself if it is not in either table
+    fn put_name(&self, block: &mut Vec<u8>, name: &str, prefix_size: u8, pattern: u8) {
+        match self.table.find_name_index(name) {
+            Some(index) => put_integer(block, index as u32, prefix_size, pattern),
+            None => {
+                block.push(pattern);
+                self.put_literal(block, name);
+            },
+        }
+    }
+
+    // 5.2 String Literal Representation, Huffman coded (H = 1) or not as
+    // set, by default whichever is shorter (the raw octets if neither)
+    fn put_literal(&self, block: &mut Vec<u8>, s: &str) {
+        let huffman_len = self.huffman.encoded_len(s.as_bytes());
+        let huffman = match self.literals {
+            LiteralEncoding::Shortest => huffman_len < s.len(),
+            LiteralEncoding::Raw => false,
+            LiteralEncoding::Huffman => true,
+        };
+        if huffman {
+            put_integer(block, huffman_len as u32, 7, 0x80);
+            let start = block.len();
+            block.resize(start + huffman_len, 0);
+            self.huffman.encode(s.as_bytes(), &mut block[start..]);
+        }
+        else {
+            put_integer(block, s.len() as u32, 7, 0x00);
+            block.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+// write an integer with the given prefix size where
+// pattern holds the bits that come before the prefix
+fn put_integer(block: &mut Vec<u8>, n: u32, prefix_size: u8, pattern: u8) {
+    let mut buf = [0u8; 6];
+    integers::encode_integer(n, &mut buf.iter_mut(), prefix_size);
+
+    // first octet plus the continuation octets if the prefix is full
+    let mask = ((1u16 << prefix_size) - 1) as u8;
+    let mut len = 1;
+    if buf[0] == mask {
+        while buf[len] & 0x80 != 0 {
+            len += 1;
+        }
+        len += 1;
+    }
+
+    buf[0] |= pattern;
+    block.extend_from_slice(&buf[..len]);
+}
+
+#[cfg(test)]
+mod encoder_tests {
+
+    use super::{Encoder, LiteralEncoding};
+    use header::{Decoder, HeaderEntry, HeaderList, HpackError};
+
+    #[test]
+    fn encode_static_matches() {
+        let mut encoder = Encoder::new(4096, 10);
+        // to check the octets of the strings
+        encoder.set_literal_encoding(LiteralEncoding::Raw);
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":status", "200").into());
+        list.add_entry((":status", "201").into());
+        list.add_entry(("content-type", "text/html").into());
+
+        let block = encoder.encode_header_list(&list);
+
+        assert_eq!(&block[..1], &[0x88]);
+        // the name indexed, and the entry added to the dynamic table
+        assert_eq!(&block[1..6], &[0x48, 0x03, b'2', b'0', b'1']);
+        assert_eq!(block[6], 0x40 | 31); // content-type
+        assert_eq!(encoder.table_snapshot(), vec![("content-type".to_string(), "text/html".to_string()), (":status".to_string(), "201".to_string())]);
+
+        // from now on they are only an index
+        assert_eq!(encoder.encode_header_list(&list), vec![0x88, 0x80 | 63, 0x80 | 62]);
+    }
+
+    #[test]
+    fn encode_static_statuses() {
+        let mut encoder = Encoder::new(4096, 10);
+        for (i, status) in ["200", "204", "206", "304", "400", "404", "500"].iter().enumerate() {
+            let mut list = HeaderList::with_capacity(1);
+            list.add_entry((":status", *status).into());
+            assert_eq!(encoder.encode_header_list(&list), vec![0x80 | (8 + i as u8)]);
+        }
+
+        // the rest have the name indexed
+        let mut list = HeaderList::with_capacity(1);
+        list.add_entry((":status", "431").into());
+        assert_eq!(encoder.encode_header_list(&list), vec![0x48, 0x03, b'4', b'3', b'1']);
+        assert_eq!(encoder.encode_header_list(&list), vec![0x80 | 62]);
+    }
+
+    #[test]
+    fn encode_static_names() {
+        let mut encoder = Encoder::new(4096, 10);
+        let mut list = HeaderList::with_capacity(1);
+        list.add_entry(("access-control-allow-origin", "*").into());
+
+        // index 20
+        let block = encoder.encode_header_list(&list);
+        assert_eq!(block, vec![0x40 | 20, 0x01, b'*']);
+    }
+
+    #[test]
+    fn encode_never_indexed() {
+        let mut encoder = Encoder::new(4096, 10);
+        encoder.set_literal_encoding(LiteralEncoding::Raw);
+        let mut list = HeaderList::with_capacity(2);
+        list.add_entry(HeaderEntry::sensitive("set-cookie", "id=1"));
+        list.add_entry(HeaderEntry::sensitive("x-token", "t"));
+
+        let block = encoder.encode_header_list(&list);
+        // set-cookie is index 55, over the 4 bit prefix
+        assert_eq!(&block[..7], &[0x1F, 55 - 15, 0x04, b'i', b'd', b'=', b'1']);
+        assert_eq!(&block[7..], &[0x10, 0x07, b'x', b'-', b't', b'o', b'k', b'e', b'n', 0x01, b't']);
+
+        let decoded = Decoder::new(4096, 10).get_header_list(&block).unwrap();
+        assert!(decoded.iter().all(|e| e.is_sensitive()));
+        assert_eq!(decoded.get_value_by_name("set-cookie"), Some("id=1"));
+        assert_eq!(encoder.table_size(), 0);
+    }
+
+    #[test]
+    fn sensitive_names() {
+        let mut encoder = Encoder::new(4096, 10);
+        let mut decoder = Decoder::new(4096, 10);
+        encoder.mark_sensitive("X-Api-Key");
+        assert!(encoder.is_sensitive("x-api-key"));
+
+        let mut list = HeaderList::with_capacity(5);
+        list.add_entry(("authorization", "Bearer abc").into());
+        list.add_entry(("cookie", "id=1").into());
+        list.add_entry(("set-cookie", "id=2").into());
+        list.add_entry(("x-api-key", "secret").into());
+        list.add_entry(("x-other", "not secret").into());
+
+        // the same every time, and never in either table
+        for _ in 0..2 {
+            let block = encoder.encode_header_list(&list);
+            let decoded = decoder.get_header_list(&block).unwrap();
+            let mut firsts = Vec::new();
+            let mut at = 0;
+            while at < block.len() {
+                firsts.push(block[at]);
+                at += field_len(&block[at..]);
+            }
+            // 6.2.3 the first four are literals never indexed, 0001xxxx
+            assert_eq!(firsts.len(), 5);
+            assert!(firsts[..4].iter().all(|&b| b & 0xF0 == 0x10), "{:x?}", firsts);
+            assert!(decoded.iter().take(4).all(|e| e.is_sensitive()));
+            assert_eq!(encoder.table_snapshot(), vec![("x-other".to_string(), "not secret".to_string())]);
+            assert_eq!(decoder.table_snapshot(), encoder.table_snapshot());
+        }
+        assert_eq!(encoder.table_size(), 7 + 10 + 32);
+    }
+
+    // the length of the field representation at the start of block,
+    // for literals with short strings (Huffman coded or not)
+    fn field_len(block: &[u8]) -> usize {
+        if block[0] & 0x80 != 0 {
+            return 1;
+        }
+        let prefix = if block[0] & 0x40 != 0 { 0x3F } else { 0x0F };
+        let mut at = if block[0] & prefix == prefix { 2 } else { 1 };
+        if block[0] & prefix == 0 {
+            at += 1 + (block[at] & 0x7F) as usize;
+        }
+        at + 1 + (block[at] & 0x7F) as usize
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut encoder = Encoder::new(4096, 10);
+        let mut decoder = Decoder::new(4096, 10);
+
+        let mut list = HeaderList::with_capacity(4);
+        list.add_entry((":method", "GET").into());
+        list.add_entry((":path", "/style.css").into());
+        list.add_entry(("x-custom-header", "some value that is long enough to need more than one octet for the length of the string literal, so over 127").into());
+        list.add_entry(("accept", "*/*").into());
+
+        let block = encoder.encode_header_list(&list);
+        let decoded = decoder.get_header_list(&block).unwrap();
+
+        let original: Vec<_> = list.iter().map(|e| (e.name(), e.value())).collect();
+        let result: Vec<_> = decoded.iter().map(|e| (e.name(), e.value())).collect();
+        assert_eq!(original, result);
+    }
+
+    #[test]
+    fn peer_list_size() {
+        let mut encoder = Encoder::new(4096, 10);
+        let mut list = HeaderList::with_capacity(2);
+        list.add_entry((":status", "200").into());
+        list.add_entry(("x-big", "0123456789").into());
+        // 7 + 3 + 32 and 5 + 10 + 32
+        assert_eq!(list.size(), 89);
+
+        encoder.set_max_header_list_size(Some(88));
+        assert!(!encoder.fits(&list));
+        let err = encoder.try_encode_header_list(&list).unwrap_err();
+        assert_eq!(err.find::<HpackError>().unwrap().reason(), "hpack: header list is larger than the peer accepts");
+
+        encoder.set_max_header_list_size(Some(89));
+        assert_eq!(encoder.try_encode_header_list(&list).unwrap(), Encoder::new(4096, 10).encode_header_list(&list));
+    }
+
+    #[test]
+    fn table_entries_indexed() {
+        // entries already in the dynamic table are indexed, and it is left as it was
+        let mut encoder = Encoder::new(4096, 10);
+        encoder.preload_table(&[("x-custom", "1"), (":status", "201")]);
+        let mut list = HeaderList::with_capacity(2);
+        list.add_entry((":status", "201").into());
+        list.add_entry(("x-custom", "1").into());
+        let block = encoder.encode_header_list(&list);
+
+        assert_eq!(block, vec![0x80 | 63, 0x80 | 62]);
+        assert_eq!(encoder.table_snapshot(), vec![("x-custom".to_string(), "1".to_string()), (":status".to_string(), "201".to_string())]);
+        assert_eq!(encoder.table_size(), 83);
+    }
+
+    fn response(date: &str, length: &str) -> HeaderList {
+        let mut list = HeaderList::with_capacity(7);
+        list.add_entry((":status", "200").into());
+        list.add_entry(("server", "kurisu").into());
+        list.add_entry(("date", date.to_string()).into());
+        list.add_entry(("content-type", "text/html; charset=utf-8").into());
+        list.add_entry(("cache-control", "max-age=3600").into());
+        list.add_entry(("x-frame-options", "DENY").into());
+        list.add_entry(("content-length", length.to_string()).into());
+        list
+    }
+
+    #[test]
+    fn repeated_responses() {
+        let mut encoder = Encoder::new(4096, 10);
+        encoder.set_literal_encoding(LiteralEncoding::Raw);
+        let mut decoder = Decoder::new(4096, 10);
+        let lists = [
+            response("Sun, 06 Nov 1994 08:49:37 GMT", "2326"),
+            response("Sun, 06 Nov 1994 08:49:37 GMT", "512"),
+            response("Sun, 06 Nov 1994 08:49:38 GMT", "2326"),
+        ];
+
+        let mut blocks = Vec::new();
+        for list in &lists {
+            let block = encoder.encode_header_list(list);
+            let decoded = decoder.get_header_list(&block).unwrap();
+            let original: Vec<_> = list.iter().map(|e| (e.name(), e.value())).collect();
+            let result: Vec<_> = decoded.iter().map(|e| (e.name(), e.value())).collect();
+            assert_eq!(original, result);
+            blocks.push(block);
+        }
+        // both tables are the same after every block
+        assert_eq!(encoder.table_snapshot(), decoder.table_snapshot());
+
+        // everything is new at first, but the status
+        assert_eq!(blocks[0][0], 0x88);
+        assert!(blocks[0][1..].len() > 80);
+        // the third has one new date, the content-length from the first (which
+        // moved back as the second added its own) and the rest as they were
+        let date = b"Sun, 06 Nov 1994 08:49:38 GMT";
+        let mut expected = vec![0x88, 0x80 | 68, 0x40 | 33, date.len() as u8];
+        expected.extend_from_slice(date);
+        expected.extend_from_slice(&[0x80 | 67, 0x80 | 66, 0x80 | 65, 0x80 | 64]);
+        assert_eq!(blocks[2], expected);
+        let indexed = blocks[2].iter().filter(|&&b| b & 0x80 != 0).count();
+        assert_eq!(indexed, 6);
+    }
+
+    #[test]
+    fn primed_entries() {
+        let primed = [("server", "kurisu"), ("content-type", "text/html; charset=utf-8"), ("cache-control", "max-age=3600")];
+        let dates = ["Sun, 06 Nov 1994 08:49:37 GMT", "Sun, 06 Nov 1994 08:49:38 GMT", "Sun, 06 Nov 1994 08:49:39 GMT",
+                     "Sun, 06 Nov 1994 08:49:40 GMT", "Sun, 06 Nov 1994 08:49:41 GMT"];
+
+        // a table with room for the primed entries and not much more
+        let mut encoder = Encoder::new(256, 10);
+        encoder.prime(&primed);
+        let mut decoder = Decoder::new(256, 10);
+        // nothing goes in the table before a block has it
+        assert_eq!(encoder.table_size(), 0);
+
+        let mut blocks = Vec::new();
+        for (n, date) in dates.iter().enumerate() {
+            let list = response(date, &(1000 + n).to_string());
+            let block = encoder.encode_header_list(&list);
+            let decoded = decoder.get_header_list(&block).unwrap();
+            let original: Vec<_> = list.iter().map(|e| (e.name(), e.value())).collect();
+            let result: Vec<_> = decoded.iter().map(|e| (e.name(), e.value())).collect();
+            assert_eq!(original, result);
+            assert_eq!(encoder.table_snapshot(), decoder.table_snapshot());
+            blocks.push(block);
+        }
+        assert!(blocks[1].len() < blocks[0].len());
+        // the one-off values never push the primed entries out
+        let table = encoder.table_snapshot();
+        for &(name, value) in &primed {
+            assert!(table.contains(&(name.to_string(), value.to_string())));
+        }
+        assert!(blocks[1..].iter().all(|block| block.len() == blocks[1].len()));
+
+        // which they do without priming
+        let mut encoder = Encoder::new(256, 10);
+        for (n, date) in dates.iter().enumerate() {
+            encoder.encode_header_list(&response(date, &(1000 + n).to_string()));
+        }
+        assert!(!encoder.table_snapshot().contains(&("server".to_string(), "kurisu".to_string())));
+
+        // or once they are unprimed
+        let mut encoder = Encoder::new(256, 10);
+        encoder.prime(&primed);
+        encoder.unprime("Server");
+        assert!(!encoder.is_primed("server", "kurisu"));
+        assert!(encoder.is_primed("cache-control", "max-age=3600"));
+        for (n, date) in dates.iter().enumerate() {
+            encoder.encode_header_list(&response(date, &(1000 + n).to_string()));
+        }
+        assert!(!encoder.table_snapshot().contains(&("server".to_string(), "kurisu".to_string())));
+    }
+
+    #[test]
+    fn table_size_updates() {
+        let mut encoder = Encoder::new(4096, 10);
+        let mut decoder = Decoder::new(4096, 10);
+        let list = response("Sun, 06 Nov 1994 08:49:37 GMT", "2326");
+        decoder.get_header_list(&encoder.encode_header_list(&list)).unwrap();
+        assert!(encoder.table_size() > 100);
+
+        // the same size again is no change at all
+        encoder.set_max_table_size(4096);
+        assert_eq!(encoder.encode_header_list(&list)[0], 0x88);
+
+        // what no longer fits goes at once, and the peer hears about it
+        // first thing in the next block, only the once
+        encoder.set_max_table_size(100);
+        assert!(encoder.table_size() <= 100);
+        let block = encoder.encode_header_list(&list);
+        assert_eq!(&block[..3], &[0x3F, 100 - 31, 0x88]);
+        decoder.get_header_list(&block).unwrap();
+        assert_eq!(encoder.table_snapshot(), decoder.table_snapshot());
+        let block = encoder.encode_header_list(&list);
+        assert_eq!(block[0], 0x88);
+        decoder.get_header_list(&block).unwrap();
+
+        // down twice and back up, the smallest first and then the size now
+        encoder.set_max_table_size(60);
+        encoder.set_max_table_size(0);
+        encoder.set_max_table_size(2000);
+        assert_eq!(encoder.table_size(), 0);
+        let block = encoder.encode_header_list(&list);
+        assert_eq!(&block[..4], &[0x20, 0x3F, 0xB1, 0x0F]);
+        let decoded = decoder.get_header_list(&block).unwrap();
+        assert_eq!(decoded.get_value_by_name("content-length"), Some("2326"));
+        assert_eq!(encoder.table_snapshot(), decoder.table_snapshot());
+        assert!(encoder.table_size() > 100);
+
+        // down and back up to where it was is still both
+        encoder.set_max_table_size(10);
+        encoder.set_max_table_size(2000);
+        assert_eq!(&encoder.encode_header_list(&list)[..4], &[0x2A, 0x3F, 0xB1, 0x0F]);
+    }
+
+    #[test]
+    fn too_big_to_index() {
+        let mut encoder = Encoder::new(64, 10);
+        encoder.set_literal_encoding(LiteralEncoding::Raw);
+        encoder.preload_table(&[("x-a", "1")]);
+        let mut list = HeaderList::with_capacity(1);
+        list.add_entry(("x-big", "a value that is longer than the table").into());
+
+        // sent without indexing, which leaves what is in the table alone
+        let block = encoder.encode_header_list(&list);
+        assert_eq!(&block[..2], &[0x00, 0x05]);
+        assert_eq!(encoder.table_snapshot(), vec![("x-a".to_string(), "1".to_string())]);
+        let decoded = Decoder::new(64, 10).get_header_list(&block).unwrap();
+        assert_eq!(decoded.get_value_by_name("x-big"), Some("a value that is longer than the table"));
+    }
+
+    fn literal(name: &'static str, value: &str) -> HeaderList {
+        let mut list = HeaderList::with_capacity(1);
+        list.add_entry((name, value.to_string()).into());
+        list
+    }
+
+    #[test]
+    fn shortest_literals() {
+        // base64 with a lot of + (11 bits Huffman coded)
+        let blob = "+++A+++B+++/+++=";
+        let text = "text/html; charset=utf-8";
+        let mut decoder = Decoder::new(4096, 10);
+
+        let mut encoder = Encoder::new(4096, 10);
+        let block = encoder.encode_header_list(&literal("x-blob", blob));
+        // the name comes out shorter with H set, the blob as it is
+        assert_eq!(block[0], 0x40);
+        assert_eq!(block[1], 0x80 | 5);
+        assert_eq!(&block[7..9], &[blob.len() as u8, b'+']);
+        assert_eq!(&block[8..], blob.as_bytes());
+        assert_eq!(decoder.get_header_list(&block).unwrap().get_value_by_name("x-blob"), Some(blob));
+
+        let block = encoder.encode_header_list(&literal("content-type", text));
+        assert_eq!(block[0], 0x40 | 31);
+        assert_eq!(block[1] & 0x80, 0x80);
+        assert!(((block[1] & 0x7F) as usize) < text.len());
+        assert_eq!(block.len(), 2 + (block[1] & 0x7F) as usize);
+        assert_eq!(decoder.get_header_list(&block).unwrap().get_value_by_name("content-type"), Some(text));
+
+        // or always one or the other
+        let mut raw = Encoder::new(4096, 10);
+        raw.set_literal_encoding(LiteralEncoding::Raw);
+        let block = raw.encode_header_list(&literal("content-type", text));
+        assert_eq!(&block[1..], &[&[text.len() as u8][..], text.as_bytes()].concat()[..]);
+
+        let mut huffman = Encoder::new(4096, 10);
+        huffman.set_literal_encoding(LiteralEncoding::Huffman);
+        let block = huffman.encode_header_list(&literal("x-blob", blob));
+        let value_at = 2 + (block[1] & 0x7F) as usize;
+        assert_eq!(block[value_at] & 0x80, 0x80);
+        assert!((block[value_at] & 0x7F) as usize > blob.len());
+        let decoded = Decoder::new(4096, 10).get_header_list(&block).unwrap();
+        assert_eq!(decoded.get_value_by_name("x-blob"), Some(blob));
+    }
+}
@@ -0,0 +1,12 @@
+//! The error for a header block that could not be decoded
+
+use krserr::Kind;
+
+make_error!(HpackError; "{}"; reason: &'static str; Kind::Hpack);
+
+impl HpackError {
+    /// what was wrong with the block
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+}
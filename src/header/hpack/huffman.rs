@@ -7,6 +7,9 @@ use bititor::BitItor;
 // huffman layout array of (huffman code, length of code)
 type HuffmanTable = [(u32, u8)];
 
+// the longest code in the table
+const MAX_CODE_LEN : u8 = 30;
+
 /// Decodes Huffman encoded strings
 /// Optimized specialized for http2 Huffman encoded strings
 pub struct Huffman {
@@ -20,17 +23,18 @@ lazy_static! {
 
         let mut hash_map = HashMap::with_capacity(len);
 
-        for i in 0..len {
+        // EOS (the last entry) is not a char, and i as u8 would make it 0
+        for i in 0..len - 1 {
             hash_map.insert(HUFFMAN_TABLE[i], i as u8);
         }
 
         drun!({ // checking the memory efficiency of the huffman encoder/decoder
             use std::mem;
-            println!("huffman static HUFFMAN_TABLE len: {}", len);
-            println!("huffman HUFFMAN_TABLE hasmap:\nmain size bytes {} :: Table size bytes {} :: Capacity {}",
-                     mem::size_of::<Huffman>(),
-                     mem::size_of_val(&hash_map.entry((0x1ff8, 13))) * hash_map.capacity(),
-                     hash_map.capacity());
+            klog_debug!("huffman static HUFFMAN_TABLE len: {}", len);
+            klog_debug!("huffman HUFFMAN_TABLE hasmap: main size bytes {} :: Table size bytes {} :: Capacity {}",
+                        mem::size_of::<Huffman>(),
+                        mem::size_of_val(&hash_map.entry((0x1ff8, 13))) * hash_map.capacity(),
+                        hash_map.capacity());
         });
 
         hash_map
@@ -45,7 +49,9 @@ impl Huffman {
         }
     }
 
-    pub fn decode<'a, 'b, B: IntoIterator<Item=&'b u8>>(&self, buf: B) -> Vec<u8>
+    /// decode buf, which fails for a code longer than any in the table (EOS
+    /// included) or padding that is longer than 7 bits or not all 1s
+    pub fn decode<'a, 'b, B: IntoIterator<Item=&'b u8>>(&self, buf: B) -> Result<Vec<u8>, &'static str>
         where <B as ::std::iter::IntoIterator>::IntoIter: 'a {
         // create vec with enough space for most of the decoded buf
         // some reallocation will probably happen with current implementation
@@ -78,6 +84,9 @@ impl Huffman {
                 code |= 0x1;
             }
             size += 1;
+            if size > MAX_CODE_LEN {
+                return Err("huffman: invalid code");
+            }
 
             // check if the curently read bits are a valid huffman code
             match self.decode_table.get(&(code, size)) {
@@ -90,15 +99,18 @@ impl Huffman {
             }
         }
 
-        drun!( {
+        // what is left is padding, the start of EOS
+        if size > 7 || code != (1 << size) - 1 {
+            return Err("huffman: invalid padding");
+        }
+
+        drun!({
             let len = decoded.len();
             let cap = decoded.capacity();
+            klog_debug!("decoded len: {} AND decoded capacity {} (ratio {})", len, cap, len as f32 / cap as f32);
+        });
 
-            println!("decoded len: {} AND decoded capacity {}", len, cap);
-            println!("len capacity ratio: {}", len as f32 / cap as f32);
-        } );
-
-        decoded
+        Ok(decoded)
     }
 
     // write the encoded result to dest and return the length of result
@@ -110,18 +122,18 @@ impl Huffman {
             let mut code_i = 0;
 
             let encodeing = self.encode_table[*i as usize];
-            let mut code = encodeing.0;
             let mut code_len = encodeing.1 as i8;
 
-            let shift = 32 - code_len - offset;
-
-            code = code << shift;
+            // a code is up to 30 bits and offset up to 7, which only fits
+            // in a u32 for the shorter codes
+            debug_assert!(offset >= 0 && offset < 8);
+            let shift = 64 - code_len - offset;
+            let code = (encodeing.0 as u64) << shift;
 
-            let be_code = u32::to_be(code);
+            let be_code = u64::to_be(code);
 
-            let code_buf: &[u8] = unsafe { slice::from_raw_parts(&be_code as *const u32 as *const u8, 4) };
+            let code_buf: &[u8] = unsafe { slice::from_raw_parts(&be_code as *const u64 as *const u8, 8) };
 
-            debug_assert!(offset >= 0);
             // this deal with what happens when a dest byte is
             // only partial filled by the previous huff code
             // because the codes need to be tightly packed
@@ -176,9 +188,17 @@ impl Huffman {
                 bits = ( bits << 1) | 1;
             }
             dest[dest_i] |= bits;
+            dest_i += 1;
         }
 
-        dest_i + 1
+        // a code that ended on a byte boundary already moved dest_i past it
+        dest_i
+    }
+
+    /// the length that encode gives src, padding included
+    pub fn encoded_len(&self, src: &[u8]) -> usize {
+        let bits: usize = src.iter().map(|&c| self.encode_table[c as usize].1 as usize).sum();
+        (bits + 7) / 8
     }
 }
 
@@ -447,13 +467,15 @@ static HUFFMAN_TABLE: &'static HuffmanTable = &[
 mod huffman_tests {
     use super::Huffman;
     use std::str;
+    use debug::testing::with_verbose;
+    use log::capture::capture;
 
     #[test]
     fn decode_test1() {
         let encoded = [0x08, 0x9D, 0x5C, 0x0B, 0x81, 0x70, 0xDC, 0x78, 0x0F, 0x03];
 
         let huff = Huffman::new();
-        let decoded = huff.decode(&encoded);
+        let decoded = huff.decode(&encoded).unwrap();
 
         println!("decoded value: {}", str::from_utf8(&decoded).unwrap());
 
@@ -465,7 +487,7 @@ mod huffman_tests {
         let encoded = [0xA0, 0xE4, 0x1D, 0x13, 0x9D, 0x09, 0xB8, 0xF0, 0x1E, 0x07];
 
         let huff = Huffman::new();
-        let decoded = huff.decode(&encoded);
+        let decoded = huff.decode(&encoded).unwrap();
 
         println!("decoded value: {}", str::from_utf8(&decoded).unwrap());
 
@@ -511,6 +533,60 @@ mod huffman_tests {
         }
     }
 
+    #[test]
+    fn encode_long_codes() {
+        // '\n' has a 30 bit code, after the 5 bits of 'a' it used to be
+        // shifted by a negative amount
+        let s = b"a\n\x01a\x16";
+        let mut v = vec![0; 20];
+        encode(s, &mut v);
+
+        assert_eq!(Huffman::new().decode(&v).unwrap(), s);
+
+        for c in 0..256 {
+            for lead in &["", "a", "ab", "abc", "0", "00", "000"] {
+                let mut s = lead.as_bytes().to_vec();
+                s.push(c as u8);
+                let mut v = vec![0; 20];
+                encode(&s, &mut v);
+                assert_eq!(Huffman::new().decode(&v).unwrap(), s);
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_len() {
+        let huff = Huffman::new();
+        assert_eq!(huff.encoded_len(b""), 0);
+        // 'a' is 5 bits, padded out to a byte
+        assert_eq!(huff.encoded_len(b"a"), 1);
+        // the 30 bit code of '\n'
+        assert_eq!(huff.encoded_len(b"\n"), 4);
+        for s in &[&b"www.example.com"[..], b"no-cache", b"custom-value", b"a\n\x01a\x16", b"+/=+/=+/="] {
+            let mut v = vec![0; 64];
+            assert_eq!(huff.encoded_len(s), huff.encode(s, &mut v));
+        }
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let huff = Huffman::new();
+
+        // a run of 1s longer than any code, its length used to wrap
+        assert_eq!(huff.decode(&[0xFF; 40]), Err("huffman: invalid code"));
+
+        // EOS used to decode as a 0
+        assert_eq!(huff.decode(&[0xFF, 0xFF, 0xFF, 0xFF]), Err("huffman: invalid code"));
+
+        // 'a' (00011) then 11 bits of padding
+        assert_eq!(huff.decode(&[0x1F, 0xFF]), Err("huffman: invalid padding"));
+
+        // padding that is not all 1s
+        assert_eq!(huff.decode(&[0x18]), Err("huffman: invalid padding"));
+
+        assert_eq!(huff.decode(&[0x1F]).unwrap(), b"a");
+    }
+
     fn encode(src: &[u8], dest: &mut Vec<u8>) {
         let huff = Huffman::new();
         let size = huff.encode(src, dest);
@@ -522,4 +598,16 @@ mod huffman_tests {
         }
         println!("");
     }
+
+    #[test]
+    fn verbose_decode() {
+        let encoded = [0x08, 0x9D, 0x5C, 0x0B, 0x81, 0x70, 0xDC, 0x78, 0x0F, 0x03];
+        let huff = Huffman::new();
+
+        let messages = capture(|| with_verbose(true, || { huff.decode(&encoded).unwrap(); }));
+        assert!(messages.iter().any(|m| m.starts_with("DEBUG decoded len: 14 AND decoded capacity 15")), "{:?}", messages);
+
+        let messages = capture(|| with_verbose(false, || { huff.decode(&encoded).unwrap(); }));
+        assert!(!messages.iter().any(|m| m.contains("decoded len")));
+    }
 }
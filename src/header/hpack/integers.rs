@@ -70,7 +70,15 @@ pub fn decode_integer<'a, 'b, I: Iterator<Item=&'b u8>>(bts: &'a mut I, prefix_s
     let octet_limit = 5;
 
     for (i, b) in bts.enumerate() {
-        value += ((b & 127) as u32) * (1 << m);
+        // the last octets can still carry more than fits in a u32
+        let part = match 1u32.checked_shl(m).and_then(|f| ((b & 127) as u32).checked_mul(f)) {
+            Some(part) => part,
+            None => return Err("hpack integer: overflow"),
+        };
+        value = match value.checked_add(part) {
+            Some(value) => value,
+            None => return Err("hpack integer: overflow"),
+        };
         m += 7;
 
         if b & 128 != 128 {
@@ -146,6 +154,25 @@ mod tests {
         assert_eq!(num, 1337);
     }
 
+    #[test]
+    fn decode_overflow() {
+        // 127 << 28 does not fit
+        let tst_num = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(decode_integer(&mut tst_num.iter(), 8), Err("hpack integer: overflow"));
+
+        // neither does adding up to u32::MAX and then some
+        let tst_num = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert_eq!(decode_integer(&mut tst_num.iter(), 8), Err("hpack integer: overflow"));
+
+        // the largest value that fits still decodes
+        let tst_num = vec![0xFF, 0x80, 0xFE, 0xFF, 0xFF, 0x0F];
+        assert_eq!(decode_integer(&mut tst_num.iter(), 8), Ok(0xFFFFFFFF));
+
+        // a sixth continuation octet used to shift by 35
+        let tst_num = vec![0x1F, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert_eq!(decode_integer(&mut tst_num.iter(), 5), Err("hpack integer: overflow"));
+    }
+
     // this test relise on decodeing to work
     #[test]
     fn encode_test() {
@@ -5,3 +5,5 @@ mod huffman;
 mod integers;
 mod table;
 pub mod decoder;
+pub mod encoder;
+pub mod error;
@@ -81,16 +81,86 @@ impl Table {
         Ok(entry.0.clone())
     }
 
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    // would an entry with name and value fit in the table at all,
+    // adding one that does not just empties it
+    pub fn fits(&self, name: &str, value: &str) -> bool {
+        name.len() + value.len() + 32 <= self.max_size
+    }
+
+    // would adding an entry with name and value evict any entry
+    // (oldest first) that evicted says is one to keep
+    pub fn would_evict<F>(&self, name: &str, value: &str, mut evicted: F) -> bool
+        where F: FnMut(&str, &str) -> bool {
+        let mut size = self.current_size + name.len() + value.len() + 32;
+        for entry in self.dyn_table.iter().rev() {
+            if size <= self.max_size {
+                return false;
+            }
+            if evicted(&entry.0, &entry.1) {
+                return true;
+            }
+            size -= Self::size_of_entry(entry);
+        }
+        false
+    }
+
     pub fn max_size_update(&mut self, new_max_size: usize) {
         self.max_size = new_max_size;
         // run evict without intention of adding a new entry
         self.evict(0);
     }
 
+    // the HPACK index of the entry with name and value, searching the
+    // static table first and then the dynamic table, where the newest
+    // entry comes first (so an older copy of it is never the one found)
+    pub fn find_index(&self, name: &str, value: &str) -> Option<usize> {
+        self.static_table.find(name, value).or_else(|| {
+            self.dyn_table.iter()
+                .position(|e| &*e.0 == name && &*e.1 == value)
+                .map(|i| i + 62)
+        })
+    }
+
+    // the HPACK index of an entry with name, whatever its value,
+    // searched for the same way as find_index
+    pub fn find_name_index(&self, name: &str) -> Option<usize> {
+        self.static_table.find_name(name).or_else(|| {
+            self.dyn_table.iter()
+                .position(|e| &*e.0 == name)
+                .map(|i| i + 62)
+        })
+    }
+
     pub fn num_dyn_entries(&self) -> usize {
         self.dyn_table.len()
     }
 
+    // the spec size of everything in the dynamic table
+    #[cfg(any(test, feature = "testing"))]
+    pub fn size(&self) -> usize {
+        self.current_size
+    }
+
+    // every dynamic entry as (name, value), newest first so the
+    // first is the one at index 62
+    #[cfg(any(test, feature = "testing"))]
+    pub fn snapshot(&self) -> Vec<(String, String)> {
+        self.dyn_table.iter().map(|e| (e.0.to_string(), e.1.to_string())).collect()
+    }
+
+    // fill the dynamic table as if entries (in the order snapshot
+    // gives them) had been added, evicting as adding them would
+    #[cfg(any(test, feature = "testing"))]
+    pub fn preload(&mut self, entries: &[(&str, &str)]) {
+        for &(name, value) in entries.iter().rev() {
+            self.add_entry_literal(name.to_string(), value.to_string());
+        }
+    }
+
     //=========================================
     // private utility fn
     //=========================================
@@ -148,7 +218,6 @@ impl Table {
     }
 }
 
-#[allow(unused_variables)]
 #[cfg(test)]
 mod dyn_table_tests {
 
@@ -166,36 +235,116 @@ mod dyn_table_tests {
         assert_eq!(table.get_header_entry(63).unwrap(), ("name1", "value1").into());
     }
 
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
     #[test]
-    #[should_panic]
     fn test_evictions() {
-        let mut table = Table::new(37, 10); // test add
+        let mut table = Table::new(37, 10);
 
         table.add_entry_literal("nm".to_string(), "val".to_string());
-        assert_eq!(table.get_header_entry(62).unwrap(), ("nm", "val").into());
+        assert_eq!(table.snapshot(), pairs(&[("nm", "val")]));
+        assert_eq!(table.size(), 37);
+
+        // evicts the first entry, but the name it shares is still good
+        table.add_entry_id(62, "ttt".to_string()).unwrap();
+        assert_eq!(table.snapshot(), pairs(&[("nm", "ttt")]));
+        assert_eq!(table.size(), 37);
+
+        // evicts everything and is still too big to add
+        table.add_entry_id(62, "XXXX".to_string()).unwrap();
+        assert_eq!(table.snapshot(), pairs(&[]));
+        assert_eq!(table.size(), 0);
+        assert_eq!(table.get_header_entry(62).err(), Some("hpack: index is out of range"));
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        // room for three entries of 34
+        let mut table = Table::new(110, 10);
+        table.preload(&[("c", "3"), ("b", "2"), ("a", "1")]);
+        assert_eq!(table.size(), 102);
+
+        // the oldest goes first, and only as many as it takes
+        table.add_entry_literal("d".to_string(), "4".to_string());
+        assert_eq!(table.snapshot(), pairs(&[("d", "4"), ("c", "3"), ("b", "2")]));
+        table.add_entry_literal("e".to_string(), "5555555555".to_string());
+        assert_eq!(table.snapshot(), pairs(&[("e", "5555555555"), ("d", "4")]));
+        assert_eq!(table.size(), 43 + 34);
+        assert_eq!(table.get_header_entry(63).unwrap(), ("d", "4").into());
+    }
+
+    #[test]
+    fn test_find_index() {
+        let mut table = Table::new(4096, 10);
+
+        // the static table, where the first entry with a name is the one found
+        assert_eq!(table.find_index(":method", "POST"), Some(3));
+        assert_eq!(table.find_index(":status", "404"), Some(13));
+        assert_eq!(table.find_name_index(":status"), Some(8));
+        assert_eq!(table.find_index("x-custom", "1"), None);
+        assert_eq!(table.find_name_index("x-custom"), None);
+
+        table.add_entry_literal("x-custom".to_string(), "1".to_string());
+        table.add_entry_id(1, "example.com".to_string()).unwrap();
+        assert_eq!(table.find_index(":authority", "example.com"), Some(62));
+        assert_eq!(table.find_index("x-custom", "1"), Some(63));
+        assert_eq!(table.find_name_index("x-custom"), Some(63));
+        // a name in the static table is found there, even with a newer dynamic entry
+        assert_eq!(table.find_name_index(":authority"), Some(1));
 
-        table.add_entry_id(62, "ttt".to_string()).unwrap(); // will evict the first entry but Rc should still be valid
-        assert_eq!(table.num_dyn_entries(), 1);
-        assert_eq!(table.get_header_entry(62).unwrap(), ("nm", "ttt").into());
+        // newer entries shadow older ones with the same name, or the same name and value
+        table.add_entry_literal("x-custom".to_string(), "2".to_string());
+        table.add_entry_literal("x-custom".to_string(), "1".to_string());
+        assert_eq!(table.find_index("x-custom", "1"), Some(62));
+        assert_eq!(table.find_index("x-custom", "2"), Some(63));
+        assert_eq!(table.find_name_index("x-custom"), Some(62));
+        // and a static entry shadows any copy of it
+        table.add_entry_id(3, "POST".to_string()).unwrap();
+        assert_eq!(table.find_index(":method", "POST"), Some(3));
+        assert_eq!(table.get_header_entry(table.find_index("x-custom", "2").unwrap()).unwrap(), ("x-custom", "2").into());
+
+        // gone once evicted
+        table.max_size_update(0);
+        assert_eq!(table.find_index("x-custom", "1"), None);
+        assert_eq!(table.find_name_index("x-custom"), None);
+    }
+
+    #[test]
+    fn test_would_evict() {
+        // room for three entries of 34
+        let mut table = Table::new(110, 10);
+        table.preload(&[("c", "3"), ("b", "2"), ("a", "1")]);
+        let mut seen = Vec::new();
+        assert!(!table.would_evict("d", "4", |n, _| { seen.push(n.to_string()); false }));
+        assert_eq!(seen, vec!["a"]);
+        assert!(table.would_evict("d", "4", |n, _| n == "a"));
+        assert!(!table.would_evict("d", "4", |n, _| n == "b"));
+        assert!(table.would_evict("e", "5555555555", |n, _| n == "b"));
+        assert_eq!(table.size(), 102);
 
-        table.add_entry_id(62, "XXXX".to_string()).unwrap(); // will evict and not be enough room to add
-        assert_eq!(table.num_dyn_entries(), 0);
-        let entry = table.get_header_entry(62).unwrap(); // panic here
+        table.max_size_update(200);
+        assert!(!table.would_evict("d", "4", |_, _| true));
     }
 
     #[test]
-    #[should_panic]
     fn test_max_size_set() {
         let mut table = Table::new(200, 10);
 
         table.add_entry_literal("n".to_string(), "v".to_string());
         table.add_entry_id(62, "z".to_string()).unwrap();
+        assert_eq!(table.snapshot(), pairs(&[("n", "z"), ("n", "v")]));
+        assert_eq!(table.size(), 68);
 
-        assert_eq!(table.get_header_entry(62).unwrap(), ("n", "z").into());
-        assert_eq!(table.get_header_entry(63).unwrap(), ("n", "v").into());
+        // only what does not fit any more is evicted
+        table.max_size_update(40);
+        assert_eq!(table.snapshot(), pairs(&[("n", "z")]));
+        assert_eq!(table.size(), 34);
 
-        table.max_size_update(10); // should evict
-        assert_eq!(table.num_dyn_entries(), 0);
-        let entry = table.get_header_entry(62).unwrap(); // panic here
+        table.max_size_update(10);
+        assert_eq!(table.snapshot(), pairs(&[]));
+        assert_eq!(table.size(), 0);
+        assert_eq!(table.get_header_entry(62).err(), Some("hpack: index is out of range"));
     }
 }
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::ops::Index;
 
 use header::*;
+use header::intern::canonical;
 
 // this is basically identical to a HeaderEntry
 // but this provides a lower level interface
@@ -10,7 +12,7 @@ pub struct TableEntry (pub EntryInner, pub EntryInner);
 impl TableEntry {
     pub fn new<A, B>(name: A, value: B) -> Self
         where A: Into<EntryInner>, B: Into<EntryInner> {
-        TableEntry ( name.into(), value.into() )
+        TableEntry ( canonical(name.into()), value.into() )
     }
 }
 
@@ -35,13 +37,21 @@ lazy_static! {
     static ref S_TABLE: StaticInner = {
         let mut vec = Vec::with_capacity(STATIC_TABLE.len());
         for i in STATIC_TABLE {
-            vec.push(TableEntry (i.0.into(), i.1.into()));
+            vec.push(TableEntry::new(i.0, i.1));
         }
-        drun! {{
-            println!("Initializing static table");
-        }}
+        klog_trace!("Initializing static table");
         StaticInner ( vec )
     };
+
+    // every name in the table and the indexes (from 0) of its entries,
+    // so an entry is found without comparing against all of them
+    static ref S_NAMES: HashMap<&'static str, Vec<usize>> = {
+        let mut names = HashMap::with_capacity(STATIC_TABLE.len());
+        for (i, entry) in STATIC_TABLE.iter().enumerate() {
+            names.entry(entry.0).or_insert_with(Vec::new).push(i);
+        }
+        names
+    };
 }
 
 // rather than just the "actual" static table,
@@ -54,6 +64,18 @@ impl StaticTable {
     pub fn new() -> Self {
         StaticTable ( &S_TABLE.0 )
     }
+
+    /// the index (from 1) of the entry with name and value
+    pub fn find(&self, name: &str, value: &str) -> Option<usize> {
+        S_NAMES.get(name)?.iter()
+            .find(|&&i| STATIC_TABLE[i].1 == value)
+            .map(|i| i + 1)
+    }
+
+    /// the index (from 1) of the first entry with name
+    pub fn find_name(&self, name: &str) -> Option<usize> {
+        S_NAMES.get(name).map(|indexes| indexes[0] + 1)
+    }
 }
 
 impl Index<usize> for StaticTable {
@@ -132,10 +154,23 @@ static STATIC_TABLE: &'static [(&'static str, &'static str)] = &[
 #[cfg(test)]
 mod static_table_tests {
 
-    use super::STATIC_TABLE;
+    use super::{StaticTable, STATIC_TABLE};
 
     #[test]
     fn valid_static_table() {
         assert_eq!(STATIC_TABLE.len(), 61);
     }
+
+    #[test]
+    fn find_every_entry() {
+        let table = StaticTable::new();
+        for (i, &(name, value)) in STATIC_TABLE.iter().enumerate() {
+            assert_eq!(table.find(name, value), Some(i + 1));
+            assert_eq!(table[table.find_name(name).unwrap() - 1].0.as_ref(), name);
+        }
+        assert_eq!(table.find_name(":status"), Some(8));
+        assert_eq!(table.find(":status", "418"), None);
+        assert_eq!(table.find("x-custom", ""), None);
+        assert_eq!(table.find_name("x-custom"), None);
+    }
 }
@@ -0,0 +1,177 @@
+//! The header field names that come up all the time, kept as one static
+//! string each
+//!
+//! Every entry made with one of these names (in any HeaderEntry, and in
+//! the HPACK tables) holds the static form instead of its own copy, so a
+//! request full of common fields allocates nothing for their names and
+//! two of them can be compared by pointer.
+//!
+//! Only a name that is exactly the common one (so lowercase) is swapped
+//! for it there, an uppercase name from the peer stays as it was sent for
+//! the request to be found malformed (RFC 7540 8.1.2). intern is for names
+//! from anywhere else, it lowercases them too.
+
+use std::rc::Rc;
+
+use super::list::EntryInner;
+
+// the names of the HPACK static table and a few more, in byte order
+static COMMON_NAMES : &'static [&'static str] = &[
+    ":authority",
+    ":method",
+    ":path",
+    ":protocol",
+    ":scheme",
+    ":status",
+    "accept",
+    "accept-charset",
+    "accept-encoding",
+    "accept-language",
+    "accept-ranges",
+    "access-control-allow-credentials",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-allow-origin",
+    "access-control-expose-headers",
+    "access-control-max-age",
+    "access-control-request-headers",
+    "access-control-request-method",
+    "age",
+    "allow",
+    "authorization",
+    "cache-control",
+    "content-disposition",
+    "content-encoding",
+    "content-language",
+    "content-length",
+    "content-location",
+    "content-range",
+    "content-type",
+    "cookie",
+    "date",
+    "etag",
+    "expect",
+    "expires",
+    "forwarded",
+    "from",
+    "host",
+    "if-match",
+    "if-modified-since",
+    "if-none-match",
+    "if-range",
+    "if-unmodified-since",
+    "last-modified",
+    "link",
+    "location",
+    "max-forwards",
+    "origin",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "range",
+    "referer",
+    "refresh",
+    "retry-after",
+    "sec-websocket-extensions",
+    "sec-websocket-protocol",
+    "sec-websocket-version",
+    "server",
+    "set-cookie",
+    "strict-transport-security",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade-insecure-requests",
+    "user-agent",
+    "vary",
+    "via",
+    "www-authenticate",
+    "x-content-type-options",
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-request-id",
+];
+
+/// the static form of a common header field name, whatever the case of name
+pub fn common_name(name: &str) -> Option<&'static str> {
+    COMMON_NAMES.binary_search_by(|common| {
+        common.bytes().cmp(name.bytes().map(|b| b.to_ascii_lowercase()))
+    }).ok().map(|i| COMMON_NAMES[i])
+}
+
+/// name in lowercase, as the static form when it is a common one and
+/// in an Rc of its own otherwise
+pub fn intern(name: &str) -> EntryInner {
+    match common_name(name) {
+        Some(common) => EntryInner::R(common),
+        None => EntryInner::C(Rc::new(name.to_ascii_lowercase())),
+    }
+}
+
+// the static form of name if it is exactly a common one, what every
+// entry is made with
+pub(crate) fn canonical(name: EntryInner) -> EntryInner {
+    match common_name(&name) {
+        Some(common) if common == &*name => EntryInner::R(common),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod intern_tests {
+
+    use super::{common_name, intern, COMMON_NAMES};
+    use header::{Decoder, HeaderEntry};
+
+    fn same(a: &str, b: &str) -> bool {
+        a.as_ptr() == b.as_ptr() && a.len() == b.len()
+    }
+
+    #[test]
+    fn sorted() {
+        assert!(COMMON_NAMES.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn static_table_names() {
+        // every name of the static table decodes to the interned one
+        let mut decoder = Decoder::new(4096, 20);
+        for index in 1..62u8 {
+            let list = decoder.get_header_list(&[0x80 | index]).unwrap();
+            let entry = list.iter().next().unwrap();
+            assert!(same(entry.name(), common_name(entry.name()).unwrap()), "{}", entry.name());
+        }
+
+        // and so do literal names, whether or not they are indexed
+        let list = decoder.get_header_list(&[0x40, 4, b'v', b'a', b'r', b'y', 1, b'*',
+                                             0x10, 4, b'e', b't', b'a', b'g', 1, b'x']).unwrap();
+        for entry in list.iter() {
+            assert!(same(entry.name(), common_name(entry.name()).unwrap()));
+        }
+    }
+
+    #[test]
+    fn shared_names() {
+        let owned = HeaderEntry::new("content-type".to_string(), "text/plain");
+        assert!(same(owned.name(), &intern("Content-Type")));
+        assert!(same(owned.name(), &intern("CONTENT-TYPE")));
+        assert_eq!(&*intern("Content-Type"), "content-type");
+
+        // an uppercase name is kept as it is
+        let upper = HeaderEntry::new("Content-Type".to_string(), "text/plain");
+        assert_eq!(upper.name(), "Content-Type");
+    }
+
+    #[test]
+    fn unusual_names() {
+        assert_eq!(common_name("x-custom"), None);
+        assert_eq!(&*intern("X-Custom-Thing"), "x-custom-thing");
+        let entry = HeaderEntry::new(intern("X-Custom-Thing"), "1");
+        assert_eq!(entry.name(), "x-custom-thing");
+        assert!(entry.name_is("x-custom-thing"));
+        // names around the ends of the list
+        assert_eq!(common_name(":Authority"), Some(":authority"));
+        assert_eq!(common_name("X-Request-ID"), Some("x-request-id"));
+        assert_eq!(common_name(""), None);
+        assert_eq!(common_name("zzz"), None);
+    }
+}
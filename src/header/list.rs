@@ -9,6 +9,8 @@ use std::rc::Rc;
 use std::slice::Iter;
 use std::ops::Deref;
 
+use super::intern::canonical;
+
 // internal type to manage entries from the shared
 // static table and the connection private dynamic table
 #[derive(Debug)]
@@ -64,16 +66,25 @@ impl From<String> for EntryInner {
 }
 
 /// Header list entry with owed or borrowed string
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HeaderEntry {
     name: EntryInner,
     value: EntryInner,
+    // HPACK 7.1.3 the value is sent never indexed
+    sensitive: bool,
 }
 
 impl HeaderEntry {
     pub fn new<A, B>(name: A, value: B) -> Self
         where A: Into<EntryInner>, B: Into<EntryInner> {
-        HeaderEntry { name: name.into(), value: value.into() }
+        HeaderEntry { name: canonical(name.into()), value: value.into(), sensitive: false }
+    }
+
+    /// an entry that must never be put in a compression table
+    /// (like a cookie, which would be open to guessing attacks)
+    pub fn sensitive<A, B>(name: A, value: B) -> Self
+        where A: Into<EntryInner>, B: Into<EntryInner> {
+        HeaderEntry { name: canonical(name.into()), value: value.into(), sensitive: true }
     }
 }
 // turn a tuple into a HeaderEntry from a &str
@@ -83,7 +94,7 @@ impl<A, B> From<(A, B)> for HeaderEntry
     where A: Into<EntryInner>, B: Into<EntryInner> {
 
     fn from(obj: (A, B)) -> HeaderEntry {
-        HeaderEntry { name: obj.0.into(), value: obj.1.into() }
+        HeaderEntry::new(obj.0, obj.1)
     }
 }
 
@@ -102,11 +113,21 @@ impl HeaderEntry {
     pub fn value(&self) -> &str {
         self.value.as_ref()
     }
+    /// is the name exactly name, which is only a pointer comparison when
+    /// both are the same interned name
+    pub fn name_is(&self, name: &str) -> bool {
+        let own = self.name();
+        (own.as_ptr() == name.as_ptr() && own.len() == name.len()) || own == name
+    }
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
 }
 
 /// Header list to abstract the underlying memory management.
 /// Once something is added to the HeaderList,
 /// IN CAN NOT be modified
+#[derive(Debug)]
 pub struct HeaderList (Vec<HeaderEntry>);
 
 impl HeaderList {
@@ -122,13 +143,25 @@ impl HeaderList {
     // from a request
     pub fn get_value_by_name(&self, _name: &str) -> Option<&str> {
         for entry in &self.0 {
-            if entry.name() == _name {
+            if entry.name_is(_name) {
                 return Some(entry.value.as_ref());
             }
         }
         None
     }
 
+    // 6.5.2 the size as SETTINGS_MAX_HEADER_LIST_SIZE counts it,
+    // the length of every name and value plus 32 for each entry
+    pub fn size(&self) -> usize {
+        self.0.iter().map(|e| e.name().len() + e.value().len() + 32).sum()
+    }
+
+    // take out every entry with the name, they still can't be changed
+    // but they can be replaced
+    pub fn remove_by_name(&mut self, name: &str) {
+        self.0.retain(|entry| !entry.name_is(name));
+    }
+
     // this function is useful when turning the HeaderList over into
     // an hpack representation for the response
     // NO ORDER guaranties
@@ -152,6 +185,7 @@ mod header_list_tests {
         list.add_entry(("host4", "local").into());
 
         assert_eq!(list.get_value_by_name("host3").unwrap(), "local");
+        assert_eq!(list.size(), 4 * (5 + 5 + 32));
 
         for entry in list.iter() {
             println!("{:?}", entry);
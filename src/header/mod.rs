@@ -4,6 +4,10 @@
 
 mod list;
 mod hpack;
+mod intern;
 
 pub use self::list::{HeaderEntry, HeaderList, EntryInner};
+pub use self::intern::{common_name, intern};
 pub use self::hpack::decoder::{Decoder};
+pub use self::hpack::encoder::{Encoder, LiteralEncoding};
+pub use self::hpack::error::HpackError;
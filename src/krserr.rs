@@ -3,9 +3,9 @@
 /// dynamic information about errors in a chain and
 /// write to a log.
 ///
-/// TODO
-/// carry type info in error for dealing with specific errors cases
-/// in different ways
+/// Every link carries a Kind, so a caller can tell what went wrong
+/// (and e.g. pick the GOAWAY code for it) without knowing the type of
+/// each error along the way.
 ///
 /// NOTE
 /// - This creates a linked list of errors. Uses dynamic dispatch for
@@ -14,63 +14,157 @@
 /// that can be propagated up though functions until a point where you want to
 /// deal with the specific error case
 
+use std::io;
+
+use connection::error::{ErrorCode, H2Error};
+use server::ConfigError;
+use tls::TlsError;
+
+/// What sort of thing went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Io,
+    Tls,
+    Hpack,
+    /// the peer broke the protocol, the code is what to tell it
+    Protocol(ErrorCode),
+    Config,
+    Internal,
+}
+
+/// The Kind an error gets when it is put in a chain
+pub trait HasKind {
+    fn kind(&self) -> Kind;
+}
+
+impl HasKind for io::Error {
+    fn kind(&self) -> Kind {
+        Kind::Io
+    }
+}
+
+impl HasKind for H2Error {
+    fn kind(&self) -> Kind {
+        Kind::Protocol(self.code())
+    }
+}
+
+impl HasKind for TlsError {
+    fn kind(&self) -> Kind {
+        Kind::Tls
+    }
+}
+
+impl HasKind for ConfigError {
+    fn kind(&self) -> Kind {
+        Kind::Config
+    }
+}
+
 /// A link in the chain of errors (Forms a linked list)
 #[derive(Debug)]
 pub struct ErrLink {
     error: Box<::std::error::Error>,
+    kind: Kind,
     link: Option<Box<ErrLink>>,
 }
 
 impl ErrLink {
-    fn attach_links<E>(self, err: E) -> ErrLink where E: ::std::error::Error + 'static {
+    /// start a chain with an error that is given a kind of its own
+    pub fn with_kind<E>(err: E, kind: Kind) -> ErrLink where E: ::std::error::Error + 'static {
         ErrLink {
+            error: err.into(),
+            kind: kind,
+            link: None,
+        }
+    }
+
+    /// the error at this link
+    pub fn error(&self) -> &(::std::error::Error + 'static) {
+        self.error.as_ref()
+    }
+
+    /// the kind of the outermost error
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// the links of the chain, starting with this one (the outermost)
+    pub fn iter(&self) -> Iter {
+        Iter { link: Some(self) }
+    }
+
+    /// the outermost error in the chain of the given kind
+    pub fn find_kind(&self, kind: Kind) -> Option<&(::std::error::Error + 'static)> {
+        self.iter().find(|l| l.kind == kind).map(|l| l.error())
+    }
+
+    /// the outermost error in the chain of type E
+    pub fn find<E>(&self) -> Option<&E> where E: ::std::error::Error + 'static {
+        self.iter().filter_map(|l| l.error.downcast_ref::<E>()).next()
+    }
+
+    /// the error code of the outermost Protocol error in the chain
+    pub fn protocol_code(&self) -> Option<ErrorCode> {
+        self.iter().filter_map(|l| match l.kind {
+            Kind::Protocol(code) => Some(code),
+            _ => None,
+        }).next()
+    }
+
+    fn attach_links<E>(self, err: E) -> ErrLink where E: ::std::error::Error + HasKind + 'static {
+        ErrLink {
+            kind: err.kind(),
             error: err.into(),
             link: Some(self.into()),
         }
     }
 }
 
+/// The outermost error, then what caused it on the lines after:
+///
+/// ```text
+/// connection failed
+///   caused by: stream 5 HEADERS
+///   caused by: hpack: invalid index
+/// ```
 impl ::std::fmt::Display for ErrLink {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        let mut err = Ok(());
-        for e in LinkIter::iter_over(self) {
-            err = writeln!(f, "{}", e);
+        write!(f, "{}", self.error)?;
+        for link in self.iter().skip(1) {
+            write!(f, "\n  caused by: {}", link.error)?;
         }
-        err
+        Ok(())
     }
 }
 
-impl<E> From<E> for ErrLink where E: ::std::error::Error + 'static {
-    fn from(e: E) -> Self {
-        ErrLink {
-            error: e.into(),
-            link: None,
-        }
+impl ::std::error::Error for ErrLink {
+    fn description(&self) -> &str {
+        "Error: ErrLink"
     }
-}
 
-struct LinkIter<'a> {
-    error: Option<&'a ::std::error::Error>,
-    link: Option<&'a ErrLink>,
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        self.link.as_ref().map(|l| l.as_ref() as &::std::error::Error)
+    }
 }
 
-impl<'a> LinkIter<'a> {
-    fn iter_over(elink: &'a ErrLink) -> Self {
-        LinkIter {
-            error: Some(elink.error.as_ref()),
-            link: elink.link.as_ref().map(|v| v.as_ref()),
-        }
+impl<E> From<E> for ErrLink where E: ::std::error::Error + HasKind + 'static {
+    fn from(e: E) -> Self {
+        let kind = e.kind();
+        ErrLink::with_kind(e, kind)
     }
 }
 
-impl<'a> Iterator for LinkIter<'a> {
-    type Item = &'a ::std::error::Error;
+/// Iterator over the links of a chain, outermost first
+pub struct Iter<'a> {
+    link: Option<&'a ErrLink>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a ErrLink;
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = self.error;
-        match self.link {
-            Some(val)   => *self = LinkIter::iter_over(val),
-            None        => self.error = None,
-        };
+        let ret = self.link;
+        self.link = ret.and_then(|l| l.link.as_ref().map(|v| v.as_ref()));
         ret
     }
 }
@@ -83,12 +177,12 @@ pub type Kresult<T> = ::std::result::Result<T, ErrLink>;
 /// anything that impls the Error trait
 pub trait ErrorChain<T> {
     fn chain_err<F, E>(self, f: F) -> Kresult<T>
-        where F: FnOnce() -> E, E: ::std::error::Error + 'static;
+        where F: FnOnce() -> E, E: ::std::error::Error + HasKind + 'static;
 }
 
 impl<T, E> ErrorChain<T> for ::std::result::Result<T, E> where E: Into<ErrLink> {
     fn chain_err<F, E2>(self, f: F) -> Kresult<T>
-        where F: FnOnce() -> E2, E2: ::std::error::Error + 'static {
+        where F: FnOnce() -> E2, E2: ::std::error::Error + HasKind + 'static {
             self.map_err(|e| {
                 e.into().attach_links(f())
             })
@@ -98,8 +192,13 @@ impl<T, E> ErrorChain<T> for ::std::result::Result<T, E> where E: Into<ErrLink>
 /// This macro is for simplifying the creation of errors that can carry a message to write
 /// to a buffer (eg. Log) that may contain dynamic error information and be as efficient as
 /// possible
+///
+/// The errors are Kind::Internal in a chain unless a kind is given after the fields
 macro_rules! make_error {
     ( $name:ident $(< $($a:tt),* ; $($T:tt $(: $L:tt)*),* >)* ; $msg:expr ; $( $param:ident : $val:ty),* ) => {
+        make_error!($name $(< $($a),* ; $($T $(: $L)*),* >)* ; $msg ; $( $param : $val),* ; $crate::krserr::Kind::Internal);
+    };
+    ( $name:ident $(< $($a:tt),* ; $($T:tt $(: $L:tt)*),* >)* ; $msg:expr ; $( $param:ident : $val:ty),* ; $kind:expr ) => {
         #[derive(Debug)]
         pub struct $name$(< $($a,)* $($T : $($L +)* ::std::fmt::Debug + ::std::fmt::Display,)* >)*{$( $param : $val,)*}
         impl$(< $($a,)* $($T : ::std::fmt::Debug + ::std::fmt::Display,)* >)* $name$(< $($a,)* $($T,)* >)* {
@@ -117,5 +216,98 @@ macro_rules! make_error {
                 concat!(concat!("Error: ", stringify!($name)))
             }
         }
+        impl$(< $($a,)* $($T : ::std::fmt::Debug + ::std::fmt::Display,)* >)* $crate::krserr::HasKind for $name$(< $($a,)* $($T,)* >)* {
+            fn kind(&self) -> $crate::krserr::Kind {
+                $kind
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod krserr_tests {
+
+    use std::io;
+    use std::error::Error;
+
+    use super::{ErrLink, ErrorChain, Kind, Kresult};
+    use connection::error::{ErrorCode, H2Error};
+
+    make_error!(BadIndex; "invalid index {}"; index: usize; Kind::Hpack);
+    make_error!(StreamFailed; "stream {} failed"; id: u32);
+
+    fn decode() -> Kresult<()> {
+        Err(BadIndex::new(70).into())
+    }
+
+    fn stream() -> Kresult<()> {
+        decode().chain_err(|| H2Error::connection(ErrorCode::CompressionError, "bad header block"))
+    }
+
+    fn connection() -> Kresult<()> {
+        stream().chain_err(|| StreamFailed::new(5))
+    }
+
+    #[test]
+    fn kinds_along_the_chain() {
+        let one = decode().unwrap_err();
+        assert_eq!(one.kind(), Kind::Hpack);
+        assert_eq!(one.protocol_code(), None);
+
+        let two = stream().unwrap_err();
+        assert_eq!(two.kind(), Kind::Protocol(ErrorCode::CompressionError));
+        assert_eq!(two.find_kind(Kind::Hpack).unwrap().to_string(), "invalid index 70");
+        assert_eq!(two.protocol_code(), Some(ErrorCode::CompressionError));
+
+        let three = connection().unwrap_err();
+        assert_eq!(three.kind(), Kind::Internal);
+        assert_eq!(three.find_kind(Kind::Internal).unwrap().to_string(), "stream 5 failed");
+        assert_eq!(three.find_kind(Kind::Hpack).unwrap().to_string(), "invalid index 70");
+        assert!(three.find_kind(Kind::Protocol(ErrorCode::CompressionError)).unwrap().is::<H2Error>());
+        assert!(three.find_kind(Kind::Protocol(ErrorCode::ProtocolError)).is_none());
+        assert!(three.find_kind(Kind::Io).is_none());
+        assert_eq!(three.protocol_code(), Some(ErrorCode::CompressionError));
+    }
+
+    #[test]
+    fn display_one_deep() {
+        assert_eq!(decode().unwrap_err().to_string(), "invalid index 70");
+    }
+
+    #[test]
+    fn display_two_deep() {
+        assert_eq!(stream().unwrap_err().to_string(),
+                   "connection error COMPRESSION_ERROR: bad header block\n  caused by: invalid index 70");
+    }
+
+    #[test]
+    fn display_three_deep() {
+        assert_eq!(connection().unwrap_err().to_string(),
+                   "stream 5 failed\n  caused by: connection error COMPRESSION_ERROR: bad header block\n  caused by: invalid index 70");
+    }
+
+    #[test]
+    fn sources() {
+        let err = connection().unwrap_err();
+        let messages: Vec<String> = err.iter().map(|l| l.error().to_string()).collect();
+        assert_eq!(messages, vec!["stream 5 failed", "connection error COMPRESSION_ERROR: bad header block", "invalid index 70"]);
+
+        // source() is the rest of the chain
+        let mut depth = 0;
+        let mut source = err.source();
+        while let Some(e) = source {
+            depth += 1;
+            source = e.source();
+        }
+        assert_eq!(depth, 2);
+        assert!(err.source().unwrap().to_string().starts_with("connection error"));
+    }
+
+    #[test]
+    fn with_kind() {
+        let err = ErrLink::with_kind(io::Error::new(io::ErrorKind::Other, "no certificate"), Kind::Tls);
+        assert_eq!(err.kind(), Kind::Tls);
+        let err: ErrLink = io::Error::new(io::ErrorKind::Other, "closed").into();
+        assert_eq!(err.kind(), Kind::Io);
     }
 }
@@ -0,0 +1,76 @@
+//! An HTTP/2 server
+//!
+//! A Server listens for connections and answers every request on them
+//! with a Handler:
+//!
+//! ```ignore
+//! let config = Config::builder().addr("127.0.0.1:8080").build()?;
+//! Server::new(config, Arc::new(|req: Request, mut resp: ResponseWriter| {
+//!     resp.send(Response::new(200).body("hello")).unwrap();
+//! }))?.run()
+//! ```
+//!
+//! The pieces it is made of are here too, down to the frames and HPACK,
+//! for running a Connection over some other stream or for a client.
+
+#[cfg(feature = "krs_ssl")]
+extern crate krs_ssl;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[cfg(unix)]
+extern crate libc;
+
+#[macro_use]
+pub mod krserr;
+
+#[macro_use]
+pub mod log;
+
+#[macro_use]
+pub mod debug;
+
+mod bytes;
+
+mod borrow_iter;
+
+#[macro_use]
+pub mod buf;
+
+pub mod header;
+
+pub mod frame;
+
+mod bititor;
+
+pub mod connection;
+
+mod h1;
+
+pub mod handler;
+
+pub mod handlers;
+
+pub mod middleware;
+
+pub mod request;
+
+pub mod response;
+
+pub mod server;
+
+pub mod tls;
+
+pub mod test_util;
+
+pub mod util;
+
+pub use connection::Connection;
+pub use frame::{Http2Frame, OwnedFrame};
+pub use frame::frame_types::{flags, types};
+pub use handler::Handler;
+pub use header::{Decoder, Encoder, HeaderList};
+pub use request::Request;
+pub use response::{Response, ResponseWriter};
+pub use server::{Config, Server};
@@ -0,0 +1,293 @@
+//! Where the crate's diagnostics go
+//!
+//! Messages are written with the klog_error!, klog_warn!, klog_info!,
+//! klog_debug! and klog_trace! macros, which take format! arguments and
+//! optionally the connection and stream they are about:
+//!
+//! ```ignore
+//! klog_warn!("could not accept: {}", e);
+//! klog_debug!(Context::peer(addr).stream(5) => "handler panicked");
+//! ```
+//!
+//! They go to a single global Logger, stderr unless another is set with
+//! set_logger. A message above max_level is never formatted, and one
+//! above STATIC_MAX_LEVEL is compiled out.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use util::{http_date, Clock, SystemClock};
+
+/// How important a message is, from most to least
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_usize(n: usize) -> Level {
+        match n {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// the most detailed level that is compiled in at all
+#[cfg(debug_assertions)]
+pub const STATIC_MAX_LEVEL : Level = Level::Trace;
+#[cfg(not(debug_assertions))]
+pub const STATIC_MAX_LEVEL : Level = Level::Debug;
+
+#[cfg(debug_assertions)]
+const DEFAULT_MAX_LEVEL : Level = Level::Debug;
+#[cfg(not(debug_assertions))]
+const DEFAULT_MAX_LEVEL : Level = Level::Warn;
+
+/// The connection (and stream) a message is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Context {
+    pub peer: Option<SocketAddr>,
+    pub stream: Option<u32>,
+}
+
+impl Context {
+    pub fn none() -> Self {
+        Context { peer: None, stream: None }
+    }
+
+    pub fn peer(peer: Option<SocketAddr>) -> Self {
+        Context { peer: peer, stream: None }
+    }
+
+    pub fn stream(self, stream_id: u32) -> Self {
+        Context { stream: Some(stream_id), ..self }
+    }
+}
+
+/// "127.0.0.1:4000 stream 5: " or as much of it as is known
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.peer, self.stream) {
+            (Some(peer), Some(id)) => write!(f, "{} stream {}: ", peer, id),
+            (Some(peer), None) => write!(f, "{}: ", peer),
+            (None, Some(id)) => write!(f, "stream {}: ", id),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A message to log
+pub struct Record<'a> {
+    pub level: Level,
+    pub context: Context,
+    pub time: SystemTime,
+    pub args: fmt::Arguments<'a>,
+}
+
+/// The level, context and message (without the time)
+impl<'a> fmt::Display for Record<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}{}", self.level, self.context, self.args)
+    }
+}
+
+/// Somewhere for messages to go, it is shared by every thread
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record);
+}
+
+/// Writes each message as a line on stderr, after the time
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn log(&self, record: &Record) {
+        let date = http_date(record.time);
+        eprintln!("[{}] {}", str::from_utf8(&date).unwrap(), record);
+    }
+}
+
+lazy_static! {
+    static ref LOGGER: RwLock<Box<Logger>> = RwLock::new(Box::new(StderrLogger));
+}
+
+static MAX_LEVEL : AtomicUsize = AtomicUsize::new(DEFAULT_MAX_LEVEL as usize);
+
+/// send every message from now on to logger
+pub fn set_logger(logger: Box<Logger>) {
+    match LOGGER.write() {
+        Ok(mut current) => *current = logger,
+        Err(poisoned) => *poisoned.into_inner() = logger,
+    }
+}
+
+/// the most detailed level that is logged
+pub fn max_level() -> Level {
+    Level::from_usize(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// log messages up to level (as long as it is compiled in)
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// is a message at level logged
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level <= STATIC_MAX_LEVEL && level <= max_level()
+}
+
+// what the macros call once a message is known to be enabled
+#[doc(hidden)]
+pub fn log(level: Level, context: Context, args: fmt::Arguments) {
+    let record = Record { level: level, context: context, time: SystemClock.system_now(), args: args };
+    match LOGGER.read() {
+        Ok(logger) => logger.log(&record),
+        Err(poisoned) => poisoned.into_inner().log(&record),
+    }
+}
+
+/// log a message at a level, with a Context before a => if it has one
+#[macro_export]
+macro_rules! klog {
+    ( $level:expr, $ctx:expr => $($arg:tt)+ ) => {
+        {
+            let level = $level;
+            if $crate::log::enabled(level) {
+                $crate::log::log(level, $ctx, format_args!($($arg)+));
+            }
+        }
+    };
+    ( $level:expr, $($arg:tt)+ ) => {
+        klog!($level, $crate::log::Context::none() => $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! klog_error {
+    ( $($arg:tt)+ ) => { klog!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! klog_warn {
+    ( $($arg:tt)+ ) => { klog!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! klog_info {
+    ( $($arg:tt)+ ) => { klog!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! klog_debug {
+    ( $($arg:tt)+ ) => { klog!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! klog_trace {
+    ( $($arg:tt)+ ) => { klog!($crate::log::Level::Trace, $($arg)+) };
+}
+
+/// Running code with the messages it logs on this thread captured
+#[cfg(test)]
+pub mod capture {
+
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    use super::{set_logger, set_max_level, Level, Logger, Record};
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<String>>> = RefCell::new(None);
+    }
+
+    // keeps what is logged on threads that are capturing, and drops
+    // everything else
+    struct CaptureLogger;
+
+    impl Logger for CaptureLogger {
+        fn log(&self, record: &Record) {
+            CAPTURED.with(|captured| {
+                if let Some(ref mut messages) = *captured.borrow_mut() {
+                    messages.push(record.to_string());
+                }
+            });
+        }
+    }
+
+    static INSTALL : Once = Once::new();
+
+    /// the messages f logged, each as the Record displays
+    pub fn capture<F: FnOnce()>(f: F) -> Vec<String> {
+        INSTALL.call_once(|| {
+            set_logger(Box::new(CaptureLogger));
+            set_max_level(Level::Trace);
+        });
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+        f();
+        CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod log_tests {
+
+    use std::net::SocketAddr;
+
+    use super::{Context, Level};
+    use super::capture::capture;
+
+    #[test]
+    fn levels_and_context() {
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let messages = capture(|| {
+            klog_error!("plain {}", 1);
+            klog_warn!(Context::peer(Some(peer)) => "with a peer");
+            klog_debug!(Context::peer(Some(peer)).stream(5) => "with a stream {}", "too");
+            klog_trace!(Context::none().stream(7) => "only a stream");
+        });
+        assert_eq!(messages, vec![
+            "ERROR plain 1",
+            "WARN 127.0.0.1:4000: with a peer",
+            "DEBUG 127.0.0.1:4000 stream 5: with a stream too",
+            "TRACE stream 7: only a stream",
+        ]);
+        assert!(Level::Error < Level::Trace);
+    }
+
+    #[test]
+    fn other_threads_not_captured() {
+        let messages = capture(|| {
+            ::std::thread::spawn(|| klog_error!("elsewhere")).join().unwrap();
+            klog_info!("here");
+        });
+        assert_eq!(messages, vec!["INFO here"]);
+    }
+}
@@ -1,156 +1,63 @@
-extern crate krs_ssl;
+//! Serves every request with a page saying what was asked for
+//!
+//!     http2 h2c    cleartext on 127.0.0.1:8080 (with h2c upgrades)
+//!     http2        TLS with the certificate in test/
 
 #[macro_use]
-extern crate lazy_static;
+extern crate http2;
 
-#[macro_use]
-mod krserr;
-
-#[macro_use]
-mod debug;
-
-mod bytes;
-
-mod borrow_iter;
-
-#[macro_use]
-mod buf;
-use buf::Buf;
-
-mod header;
-use header::*;
-//mod hpack;
-//use hpack::decoder::Decoder;
-
-use krs_ssl::*;
+use std::env;
+use std::io;
+use std::sync::Arc;
 
-use std::net::{TcpListener};
-use std::thread;
-use std::io::{Read, Write};
-use std::str;
-//use std::slice;
-//use std::sync::{Once, ONCE_INIT};
-//use std::cell::Cell;
-use std::fmt::Debug;
+use http2::{Config, Request, Response, ResponseWriter, Server};
+use http2::server::CommonLogFormat;
+use http2::util::HexDump;
 
-//use std::mem;
+// the largest request body echo will look at
+const MAX_ECHO_BODY : usize = 0x100000;
 
-mod frame;
-use frame::frame_types::{GenericFrame, HeadersFrame};
-use frame::Http2Frame;
-
-mod bititor;
-
-mod request;
-
-
-// bad function that is not acctualy safe to call
-fn print_hex(buf: &[u8]) {
-    //let b: &[u8] = unsafe { slice::from_raw_parts(buf.as_ptr(), n) };
-    for i in &buf[..] {
-        print!("{:02X}:", i)
+// say what was asked for
+fn echo(mut req: Request, mut resp: ResponseWriter) {
+    for i in req.headers() {
+        klog_debug!("{:?}", i);
     }
-    println!("\n");
-}
-
-fn handle_client<T: Read + Write + Debug>(mut stream: T) {
-
-    let mut buf: Vec<u8> = Vec::with_capacity(512);
-    // this is here because the read to end function does not work with network stream (never ends),
-    // and don't want to emmty initialize the vector cause that is a waste.
-    unsafe { buf.set_len(512); }
-
-    //let mut buf2 = [0u8; 512];
-    //let mut buf2: [u8; 512] = unsafe { mem::uninitialized() };
-
-    //println!("{:?}", buf.as_mut_slice());
-
-    //let err = stream.read(buf.as_mut_slice());
-    let err = stream.read(&mut buf);
-
-    match err {
-        Ok(n) => {
-            println!("ok: {}", n);
-            let req: &str = unsafe { str::from_utf8_unchecked(&buf[..n]) };
-
-            print_hex(&buf[..n]);
-
-            println!("{}", req);
+    match req.body().collect(MAX_ECHO_BODY) {
+        Ok(ref body) if !body.is_empty() => klog_debug!("body:\n{:.256}", HexDump(body)),
+        Ok(_) => {},
+        Err(e) => {
+            klog_warn!("{}", e);
+            let _ = resp.send(Response::new(e.status()));
+            return;
         },
-        Err(e) => println!("err: {}", e),
     }
 
-    loop {
-        let err = stream.read(&mut buf);
-
-        println!("NEXT");
-
-        match err {
-            Ok(n) => {
-                println!("{:?}", stream);
-                if n == 0 { break; }
-                print_hex(&buf[..n]);
-                //let frame : GenericFrame = buf[..n].into();
-                let frame = GenericFrame::point_to(&mut buf[..n]);
-
-                if frame.get_type() == 0x1 {
-                    println!("{:?}", frame);
-                    let hf: HeadersFrame = frame.into();
-                    let mut dec = Decoder::new(4096, 20);
-                    let res = dec.get_header_list(hf.get_header_data().header_block_fragment);
-
-                    match res {
-                        Ok(hl) => {
-                            for i in hl.iter() {
-                                println!("{:?}", i);
-                            }
-                        },
-                        Err(e) => println!("{}", e),
-                    }
-                }
-
-            },
-            Err(e) => {println!("err: {}", e); break;},
-        }
+    let page = format!("{} {}\n", req.method(), req.path());
+    let response = Response::new(200).header("content-type", "text/plain").body(page);
+    if let Err(e) = resp.send(response) {
+        klog_warn!("{}", e);
     }
-
-    println!("done");
-    //stream.shutdown(std::net::Shutdown::Both);
 }
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-
-    let ctx = krs_ssl::make_ctx("test/server.crt", "test/server.key");
-
-    // accept connections and process them, spawning a new thread for each one
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                if let Ok(ssl_stream) = OsslStream::accept(&ctx, stream) {
-                    thread::spawn(move|| {
-
-                        handle_client(ssl_stream);
-                        // let mut buf = [0;4096];
-                        
-                        // let n = ssl_stream.read(&mut buf).unwrap();
-
-                        // let s = str::from_utf8(&buf[..n]).unwrap();
-
-                        // println!("{}", s);
-
-                    });
-                }
-                else {
-                    println!("could not accept");
-                }
-            }
-            Err(_) => { /* connection failed */ }
-        }
+    // "h2c" as the first argument serves cleartext instead of TLS,
+    // every request is logged to stdout
+    let config = Config::builder()
+        .addr("127.0.0.1:8080")
+        .access_log(CommonLogFormat::new(io::stdout()));
+    let config = match env::args().nth(1) {
+        Some(ref mode) if mode == "h2c" => config,
+        _ => config.certs("test/server.crt", "test/server.key"),
+    };
+    let config = match config.build() {
+        Ok(config) => config,
+        Err(e) => return klog_error!("{}", e),
+    };
+
+    let server = Server::new(config, Arc::new(echo)).unwrap();
+    if let Err(e) = server.run() {
+        klog_error!("{}", e);
     }
-
-    // close the socket server
-    //drop(listener);
 }
 
 #[cfg(test)]
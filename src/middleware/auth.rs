@@ -0,0 +1,255 @@
+//! Authentication
+//!
+//! Auth checks the authorization header of every request with a
+//! Verifier, one for a scheme like Bearer or Basic. A request that gets
+//! through has an AuthContext in its extensions for the handlers after
+//! it, the rest are answered 401 with a www-authenticate challenge right
+//! here.
+//!
+//! ```ignore
+//! Stack::new(router)
+//!     .with(Auth::new(BearerTokens::new(vec![token]), "api"))
+//! ```
+
+use handler::Handler;
+use request::Request;
+use response::{Response, ResponseWriter, StatusCode};
+use util::base64;
+
+use super::Middleware;
+
+/// Who made a request, as an Auth found out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthContext {
+    /// the scheme the credentials were in, like "Bearer"
+    pub scheme: &'static str,
+    /// the user name, for schemes that have one
+    pub user: Option<String>,
+}
+
+/// Checks the credentials of one authentication scheme
+pub trait Verifier: Send + Sync {
+    /// the scheme, as it starts the authorization header (compared
+    /// without regard to case)
+    fn scheme(&self) -> &'static str;
+
+    /// check the credentials that follow the scheme in the header
+    fn verify(&self, credentials: &str) -> Option<AuthContext>;
+
+    /// the www-authenticate challenge for a request that failed
+    fn challenge(&self, realm: &str) -> String {
+        format!("{} realm=\"{}\"", self.scheme(), realm)
+    }
+}
+
+/// Bearer tokens (RFC 6750) from a fixed list
+pub struct BearerTokens {
+    tokens: Vec<String>,
+}
+
+impl BearerTokens {
+
+    pub fn new(tokens: Vec<String>) -> Self {
+        BearerTokens { tokens: tokens }
+    }
+}
+
+impl Verifier for BearerTokens {
+
+    fn scheme(&self) -> &'static str {
+        "Bearer"
+    }
+
+    fn verify(&self, credentials: &str) -> Option<AuthContext> {
+        // every token is compared all the way, so how long the check
+        // takes does not tell how close a guess was
+        let found = self.tokens.iter()
+            .fold(false, |found, token| constant_time_eq(token.as_bytes(), credentials.as_bytes()) | found);
+        if found {
+            Some(AuthContext { scheme: "Bearer", user: None })
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// User names and passwords (RFC 7617) checked with a callback
+pub struct BasicCredentials<F> {
+    check: F,
+}
+
+impl<F> BasicCredentials<F> where F: Fn(&str, &str) -> bool + Send + Sync {
+
+    /// check is given the user name and the password
+    pub fn new(check: F) -> Self {
+        BasicCredentials { check: check }
+    }
+}
+
+impl<F> Verifier for BasicCredentials<F> where F: Fn(&str, &str) -> bool + Send + Sync {
+
+    fn scheme(&self) -> &'static str {
+        "Basic"
+    }
+
+    fn verify(&self, credentials: &str) -> Option<AuthContext> {
+        let decoded = base64::decode(credentials).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        // the user name cannot have a colon in it, the password can
+        let colon = decoded.find(':')?;
+        let (user, password) = (&decoded[..colon], &decoded[colon + 1..]);
+        if (self.check)(user, password) {
+            Some(AuthContext { scheme: "Basic", user: Some(user.to_string()) })
+        }
+        else {
+            None
+        }
+    }
+
+    fn challenge(&self, realm: &str) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm)
+    }
+}
+
+/// Lets through only requests with credentials the verifier accepts
+pub struct Auth {
+    verifier: Box<Verifier>,
+    realm: String,
+}
+
+impl Auth {
+
+    /// realm names what the credentials are for in the challenge
+    pub fn new<V: Verifier + 'static, S: Into<String>>(verifier: V, realm: S) -> Self {
+        Auth { verifier: Box::new(verifier), realm: realm.into() }
+    }
+
+    // who made the request, if the header has credentials that check out
+    fn authenticate(&self, header: &str) -> Option<AuthContext> {
+        let header = header.trim();
+        let space = header.find(' ')?;
+        let (scheme, credentials) = header.split_at(space);
+        if !scheme.eq_ignore_ascii_case(self.verifier.scheme()) {
+            return None;
+        }
+        self.verifier.verify(credentials.trim_start())
+    }
+}
+
+impl Middleware for Auth {
+    fn call(&self, mut req: Request, mut resp: ResponseWriter, next: &Handler) {
+        match req.header("authorization").and_then(|header| self.authenticate(header)) {
+            Some(context) => {
+                req.extensions_mut().insert(context);
+                next.handle(req, resp);
+            },
+            None => {
+                let challenge = self.verifier.challenge(&self.realm);
+                let _ = resp.send(Response::new(StatusCode::UNAUTHORIZED).header("www-authenticate", challenge));
+            },
+        }
+    }
+}
+
+// whether a and b are the same, in a time that only depends on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod auth_tests {
+
+    use super::{constant_time_eq, Auth, AuthContext, BasicCredentials, BearerTokens};
+    use connection::Connection;
+    use handler::Handler;
+    use header::{Decoder, HeaderList};
+    use middleware::Stack;
+    use request::Request;
+    use response::{Response, ResponseWriter};
+    use test_util::open_stream;
+
+    // answers with who the request is from
+    fn whoami(req: Request, mut resp: ResponseWriter) {
+        let context = req.extensions().get::<AuthContext>().cloned().unwrap();
+        let body = format!("{} {}", context.scheme, context.user.unwrap_or("-".to_string()));
+        resp.send(Response::new(200).body(body)).unwrap();
+    }
+
+    // the status, www-authenticate and body of the answer to a request
+    // with the authorization, if any
+    fn request(stack: &Stack, authorization: Option<&'static str>) -> (String, Option<String>, String) {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        let mut list = HeaderList::with_capacity(4);
+        list.add_entry((":method", "GET").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/").into());
+        if let Some(authorization) = authorization {
+            list.add_entry(("authorization", authorization).into());
+        }
+        stack.handle(Request::from_header_list(list).unwrap(), ResponseWriter::new(&mut conn, 1));
+
+        let headers = Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap();
+        let mut body = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            body.extend_from_slice(frame.payload());
+        }
+        (headers.get_value_by_name(":status").unwrap().to_string(),
+         headers.get_value_by_name("www-authenticate").map(|v| v.to_string()),
+         String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn bearer() {
+        let tokens = BearerTokens::new(vec!["s3cret".to_string(), "other".to_string()]);
+        let stack = Stack::new(whoami).with(Auth::new(tokens, "api"));
+
+        assert_eq!(request(&stack, Some("Bearer s3cret")), ("200".to_string(), None, "Bearer -".to_string()));
+        assert_eq!(request(&stack, Some("bearer other")).2, "Bearer -");
+
+        let challenge = Some("Bearer realm=\"api\"".to_string());
+        for &authorization in &[None, Some("Bearer wrong"), Some("Bearer s3cre"), Some("Bearer"), Some("Basic s3cret")] {
+            assert_eq!(request(&stack, authorization), ("401".to_string(), challenge.clone(), String::new()), "{:?}", authorization);
+        }
+    }
+
+    #[test]
+    fn basic() {
+        let users = BasicCredentials::new(|user: &str, password: &str| user == "Aladdin" && password == "open sesame");
+        let stack = Stack::new(whoami).with(Auth::new(users, "files"));
+
+        // RFC 7617 2
+        assert_eq!(request(&stack, Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")),
+                   ("200".to_string(), None, "Basic Aladdin".to_string()));
+        // the padding is optional
+        assert_eq!(request(&stack, Some("BASIC QWxhZGRpbjpvcGVuIHNlc2FtZQ")).0, "200");
+
+        let challenge = Some("Basic realm=\"files\", charset=\"UTF-8\"".to_string());
+        let failures = [
+            None,
+            // Aladdin:open sesamE
+            Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtRQ=="),
+            // no colon
+            Some("Basic QWxhZGRpbg=="),
+            Some("Basic !!!!"),
+            Some("Bearer QWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+        ];
+        for &authorization in &failures {
+            assert_eq!(request(&stack, authorization), ("401".to_string(), challenge.clone(), String::new()), "{:?}", authorization);
+        }
+    }
+
+    #[test]
+    fn compare() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"toke"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
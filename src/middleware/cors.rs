@@ -0,0 +1,219 @@
+//! Cross-origin resource sharing
+
+use handler::Handler;
+use request::{Method, Request};
+use response::{Response, ResponseWriter};
+
+/// Which origins may make cross-origin requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowOrigin {
+    /// any origin, answered with "*"
+    Any,
+    /// only these (like "https://example.com"), answered with the
+    /// request's origin and "vary: origin"
+    List(Vec<String>),
+}
+
+/// What Cors allows
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allow_origin: AllowOrigin,
+    /// access-control-allow-methods for preflights
+    pub allow_methods: Vec<String>,
+    /// access-control-allow-headers for preflights
+    pub allow_headers: Vec<String>,
+    /// how long (in seconds) a preflight can be cached
+    pub max_age: Option<u64>,
+    /// answer requests from other origins with 403 instead of
+    /// passing them on without any CORS headers
+    pub reject: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allow_origin: AllowOrigin::Any,
+            allow_methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            allow_headers: Vec::new(),
+            max_age: None,
+            reject: false,
+        }
+    }
+}
+
+/// Adds CORS to the handler it wraps
+///
+/// ```ignore
+/// Cors::new(CorsConfig::default(), router)
+/// ```
+///
+/// Preflights (an OPTIONS request with access-control-request-method) are
+/// answered with 204 right here. Other requests from an allowed origin go
+/// on to the inner handler, and its response gets
+/// access-control-allow-origin. Requests without an origin are not
+/// cross-origin and pass through untouched.
+pub struct Cors<H> {
+    config: CorsConfig,
+    inner: H,
+}
+
+impl<H: Handler> Cors<H> {
+
+    pub fn new(config: CorsConfig, inner: H) -> Self {
+        Cors { config: config, inner: inner }
+    }
+
+    // the access-control-allow-origin to answer the origin with, if it is allowed
+    fn allow(&self, origin: &str) -> Option<String> {
+        match self.config.allow_origin {
+            AllowOrigin::Any => Some("*".to_string()),
+            AllowOrigin::List(ref list) if list.iter().any(|o| o == origin) => Some(origin.to_string()),
+            AllowOrigin::List(_) => None,
+        }
+    }
+
+    fn preflight(&self, allowed: String) -> Response {
+        let echoed = allowed != "*";
+        let mut response = Response::new(204)
+            .header("access-control-allow-origin", allowed)
+            .header("access-control-allow-methods", self.config.allow_methods.join(", "));
+        if !self.config.allow_headers.is_empty() {
+            response = response.header("access-control-allow-headers", self.config.allow_headers.join(", "));
+        }
+        if let Some(max_age) = self.config.max_age {
+            response = response.header("access-control-max-age", max_age.to_string());
+        }
+        if echoed {
+            response = response.header("vary", "origin");
+        }
+        response
+    }
+}
+
+impl<H: Handler> Handler for Cors<H> {
+    fn handle(&self, req: Request, mut resp: ResponseWriter) {
+        let allowed = match req.header("origin") {
+            Some(origin) => self.allow(origin),
+            None => return self.inner.handle(req, resp),
+        };
+        let preflight = *req.method() == Method::Options && req.header("access-control-request-method").is_some();
+
+        let response = match allowed {
+            Some(allowed) if preflight => self.preflight(allowed),
+            Some(allowed) => {
+                if allowed != "*" {
+                    resp.add_header("vary", "origin");
+                }
+                resp.add_header("access-control-allow-origin", allowed);
+                return self.inner.handle(req, resp);
+            },
+            None if self.config.reject => Response::new(403),
+            None => return self.inner.handle(req, resp),
+        };
+        if let Err(e) = resp.send(response) {
+            klog_debug!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod cors_tests {
+
+    use super::{AllowOrigin, Cors, CorsConfig};
+    use connection::Connection;
+    use handler::Handler;
+    use header::{Decoder, HeaderList};
+    use request::Request;
+    use response::{Response, ResponseWriter};
+    use test_util::open_stream;
+
+    fn inner(req: Request, mut resp: ResponseWriter) {
+        let body = format!("{} {}", req.method(), req.path());
+        resp.send(Response::new(200).body(body)).unwrap();
+    }
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            allow_origin: AllowOrigin::List(vec!["https://good.example".to_string()]),
+            allow_headers: vec!["content-type".to_string(), "x-token".to_string()],
+            max_age: Some(600),
+            .. CorsConfig::default()
+        }
+    }
+
+    // the response headers for the request with the extra headers
+    fn request<H: Handler>(handler: &H, method: &'static str, headers: &[(&'static str, &'static str)]) -> HeaderList {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        let mut list = HeaderList::with_capacity(3 + headers.len());
+        list.add_entry((":method", method).into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/api").into());
+        for header in headers {
+            list.add_entry((*header).into());
+        }
+        handler.handle(Request::from_header_list(list).unwrap(), ResponseWriter::new(&mut conn, 1));
+
+        Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap()
+    }
+
+    fn preflight_from(origin: &'static str) -> Vec<(&'static str, &'static str)> {
+        vec![("origin", origin), ("access-control-request-method", "POST")]
+    }
+
+    #[test]
+    fn preflight_allowed() {
+        let cors = Cors::new(config(), inner);
+        let headers = request(&cors, "OPTIONS", &preflight_from("https://good.example"));
+        assert_eq!(headers.get_value_by_name(":status"), Some("204"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-origin"), Some("https://good.example"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-methods"), Some("GET, HEAD, POST"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-headers"), Some("content-type, x-token"));
+        assert_eq!(headers.get_value_by_name("access-control-max-age"), Some("600"));
+        assert_eq!(headers.get_value_by_name("vary"), Some("origin"));
+
+        let any = Cors::new(CorsConfig::default(), inner);
+        let headers = request(&any, "OPTIONS", &preflight_from("https://anyone.example"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-origin"), Some("*"));
+        assert_eq!(headers.get_value_by_name("vary"), None);
+    }
+
+    #[test]
+    fn preflight_disallowed() {
+        // passed on to the inner handler without any CORS headers
+        let cors = Cors::new(config(), inner);
+        let headers = request(&cors, "OPTIONS", &preflight_from("https://evil.example"));
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert!(headers.iter().all(|h| !h.name().starts_with("access-control-")));
+
+        let cors = Cors::new(CorsConfig { reject: true, .. config() }, inner);
+        let headers = request(&cors, "OPTIONS", &preflight_from("https://evil.example"));
+        assert_eq!(headers.get_value_by_name(":status"), Some("403"));
+        assert!(headers.iter().all(|h| !h.name().starts_with("access-control-")));
+        let headers = request(&cors, "GET", &[("origin", "https://evil.example")]);
+        assert_eq!(headers.get_value_by_name(":status"), Some("403"));
+    }
+
+    #[test]
+    fn simple_requests() {
+        let cors = Cors::new(config(), inner);
+        let headers = request(&cors, "GET", &[("origin", "https://good.example")]);
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-origin"), Some("https://good.example"));
+        assert_eq!(headers.get_value_by_name("vary"), Some("origin"));
+        // preflight only headers are left for preflights
+        assert_eq!(headers.get_value_by_name("access-control-allow-methods"), None);
+
+        // not cross-origin, so nothing to add
+        let headers = request(&cors, "GET", &[]);
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-origin"), None);
+
+        // a plain OPTIONS is not a preflight
+        let headers = request(&cors, "OPTIONS", &[("origin", "https://good.example")]);
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert_eq!(headers.get_value_by_name("access-control-allow-origin"), Some("https://good.example"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Middleware
+//!
+//! Layers around a handler, doing something for every request on the way
+//! in (or answering it themselves) and seeing how it was answered on the
+//! way out. A Stack puts them together:
+//!
+//! ```ignore
+//! Stack::new(router)
+//!     .with(log)
+//!     .with(auth)
+//! ```
+//!
+//! where log sees every request first and auth only the ones log passes on.
+
+use handler::Handler;
+use request::Request;
+use response::ResponseWriter;
+
+mod auth;
+mod cors;
+
+pub use self::auth::{Auth, AuthContext, BasicCredentials, BearerTokens, Verifier};
+pub use self::cors::{AllowOrigin, Cors, CorsConfig};
+
+pub trait Middleware: Send + Sync {
+    /// pass the request on with next.handle(req, resp), or answer it
+    /// here without calling next
+    ///
+    /// The request can be changed before it goes on, and
+    /// resp.status_sent() tells how it was answered after.
+    fn call(&self, req: Request, resp: ResponseWriter, next: &Handler);
+}
+
+impl<F> Middleware for F where F: Fn(Request, ResponseWriter, &Handler) + Send + Sync {
+    fn call(&self, req: Request, resp: ResponseWriter, next: &Handler) {
+        self(req, resp, next)
+    }
+}
+
+/// A handler behind layers of middleware, in the order they are added
+pub struct Stack {
+    layers: Vec<Box<Middleware>>,
+    handler: Box<Handler>,
+}
+
+impl Stack {
+
+    pub fn new<H: Handler + 'static>(handler: H) -> Self {
+        Stack { layers: Vec::new(), handler: Box::new(handler) }
+    }
+
+    /// add a layer inside the ones added before
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Stack {
+    fn handle(&self, req: Request, resp: ResponseWriter) {
+        Next { layers: &self.layers, handler: &*self.handler }.handle(req, resp)
+    }
+}
+
+// the rest of the stack from some layer in
+struct Next<'a> {
+    layers: &'a [Box<Middleware>],
+    handler: &'a Handler,
+}
+
+impl<'a> Handler for Next<'a> {
+    fn handle(&self, req: Request, resp: ResponseWriter) {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.call(req, resp, &Next { layers: rest, handler: self.handler }),
+            None => self.handler.handle(req, resp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod middleware_tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use super::{Middleware, Stack};
+    use connection::Connection;
+    use frame::OwnedFrame;
+    use frame::frame_types::flags;
+    use handler::Handler;
+    use header::{Decoder, HeaderList};
+    use request::Request;
+    use response::{Response, ResponseWriter, StatusCode};
+    use test_util::{open_stream, GET_BLOCK};
+
+    // notes when it is called, what its request had for x-user, and the
+    // status sent, then adds itself to x-user
+    struct Record {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Record {
+        fn call(&self, mut req: Request, resp: ResponseWriter, next: &Handler) {
+            let user = req.header("x-user").unwrap_or("-").to_string();
+            self.log.lock().unwrap().push(format!("{} in {}", self.name, user));
+            req.set_header("x-user", format!("{}{}", user, self.name));
+
+            let status = resp.status_sent();
+            next.handle(req, resp);
+            let status = status.get().map_or("none".to_string(), |s| s.to_string());
+            self.log.lock().unwrap().push(format!("{} out {}", self.name, status));
+        }
+    }
+
+    fn echo_user(req: Request, mut resp: ResponseWriter) {
+        let user = req.header("x-user").unwrap_or("").to_string();
+        resp.send(Response::new(200).body(user)).unwrap();
+    }
+
+    // only lets requests with an authorization through
+    fn auth(req: Request, mut resp: ResponseWriter, next: &Handler) {
+        match req.header("authorization") {
+            Some(_) => next.handle(req, resp),
+            None => resp.send(Response::new(StatusCode::UNAUTHORIZED)).unwrap(),
+        }
+    }
+
+    // the status and body of the answer to a request with the headers
+    fn request<H: Handler>(handler: &H, headers: &[(&'static str, &'static str)]) -> (String, Vec<u8>) {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        let mut list = HeaderList::with_capacity(3 + headers.len());
+        list.add_entry((":method", "GET").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/").into());
+        for header in headers {
+            list.add_entry((*header).into());
+        }
+        handler.handle(Request::from_header_list(list).unwrap(), ResponseWriter::new(&mut conn, 1));
+
+        let headers = Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap();
+        let mut body = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            body.extend_from_slice(frame.payload());
+        }
+        (headers.get_value_by_name(":status").unwrap().to_string(), body)
+    }
+
+    fn recorder(name: &'static str, log: &Arc<Mutex<Vec<String>>>) -> Record {
+        Record { name: name, log: log.clone() }
+    }
+
+    #[test]
+    fn call_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let stack = Stack::new(echo_user)
+            .with(recorder("a", &log))
+            .with(recorder("b", &log))
+            .with(recorder("c", &log));
+
+        let (status, body) = request(&stack, &[("x-user", "u:"), ("x-user", "dropped")]);
+        assert_eq!(status, "200");
+        assert_eq!(body, b"u:abc");
+        assert_eq!(*log.lock().unwrap(), vec![
+            "a in u:", "b in u:a", "c in u:ab", "c out 200", "b out 200", "a out 200",
+        ]);
+
+        let (_, body) = request(&Stack::new(echo_user), &[("x-user", "plain")]);
+        assert_eq!(body, b"plain");
+    }
+
+    #[test]
+    fn short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let stack = Stack::new(echo_user)
+            .with(recorder("outer", &log))
+            .with(auth)
+            .with(recorder("inner", &log));
+
+        let (status, body) = request(&stack, &[]);
+        assert_eq!(status, "401");
+        assert!(body.is_empty());
+        // the inner layer and the handler never saw it, but the outer saw the 401
+        assert_eq!(*log.lock().unwrap(), vec!["outer in -", "outer out 401"]);
+
+        log.lock().unwrap().clear();
+        let (status, body) = request(&stack, &[("authorization", "Bearer t")]);
+        assert_eq!(status, "200");
+        assert_eq!(body, b"-outerinner");
+        assert_eq!(log.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn status_not_sent() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let silent = |_req: Request, mut resp: ResponseWriter| {
+            resp.send_continue().unwrap();
+        };
+        let stack = Stack::new(silent).with(recorder("a", &log));
+
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let mut frame = OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS);
+        conn.dispatch_frame(frame.as_frame()).unwrap();
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":method", "POST").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/").into());
+        stack.handle(Request::from_header_list(list).unwrap(), ResponseWriter::new(&mut conn, 1));
+
+        // an interim response is not the status
+        assert_eq!(*log.lock().unwrap(), vec!["a in -", "a out none"]);
+    }
+}
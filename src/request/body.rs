@@ -0,0 +1,350 @@
+//! The body of a request, read as the DATA for it comes in
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::rc::{Rc, Weak};
+
+use connection::error::ErrorCode;
+
+/// Why the rest of a body can not be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamError {
+    /// the connection reset the stream (because of an error, or to stay
+    /// within its memory budget)
+    Reset(ErrorCode),
+    /// the peer reset the stream with RST_STREAM and the code, like
+    /// CANCEL for a request the user gave up on
+    ResetByPeer(ErrorCode),
+    /// the connection closed before the body was complete
+    ConnectionClosed,
+}
+
+impl StreamError {
+    /// 8.1.4 was the request certainly not processed, so that it can be
+    /// sent again as it is (REFUSED_STREAM from the peer says so)
+    ///
+    /// A client sending requests over its own connection gets the same
+    /// from the server, anything else might have been acted on.
+    pub fn is_retry_safe(&self) -> bool {
+        *self == StreamError::ResetByPeer(ErrorCode::RefusedStream)
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamError::Reset(code) => write!(f, "stream reset with {:?}", code),
+            StreamError::ResetByPeer(code) => write!(f, "stream reset by the peer with {:?}", code),
+            StreamError::ConnectionClosed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl Error for StreamError {
+    fn description(&self) -> &str {
+        "Error: StreamError"
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyError {
+    /// the body is bigger than the limit given to Body::collect
+    TooLarge(usize),
+    Stream(StreamError),
+}
+
+impl BodyError {
+    /// the status to answer with
+    pub fn status(&self) -> u16 {
+        match *self {
+            BodyError::TooLarge(_) => 413,
+            BodyError::Stream(_) => 400,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::TooLarge(max) => write!(f, "body larger than {} bytes", max),
+            BodyError::Stream(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for BodyError {
+    fn description(&self) -> &str {
+        "Error: BodyError"
+    }
+}
+
+impl From<StreamError> for BodyError {
+    fn from(e: StreamError) -> Self {
+        BodyError::Stream(e)
+    }
+}
+
+/// What a body waiting on more DATA uses to get it
+///
+/// pump reads and processes the next frame from the peer (which might
+/// or might not be for this body), returning false when there will be
+/// no more. release gives back the flow control window for data that
+/// was read from the body, and set_recv_window resizes the window. The
+/// rest are how a handler meters the window itself (see Body::pause).
+pub(crate) trait Pump {
+    fn pump(&mut self) -> io::Result<bool>;
+    fn release(&mut self, stream_id: u32, n: usize);
+    fn set_recv_window(&mut self, stream_id: u32, size: u32);
+    fn pause_recv_window(&mut self, stream_id: u32, paused: bool);
+    fn set_auto_release(&mut self, stream_id: u32, auto_release: bool);
+    fn grant(&mut self, stream_id: u32, n: u32);
+}
+
+/// The DATA received for a stream that has not been read yet
+///
+/// This is bounded by the stream's flow control window, since the window
+/// is only released as the data is read.
+#[derive(Debug)]
+pub(crate) struct BodyQueue {
+    chunks: VecDeque<Vec<u8>>,
+    end: Option<Result<(), StreamError>>,
+}
+
+impl BodyQueue {
+
+    pub fn new() -> Self {
+        BodyQueue { chunks: VecDeque::new(), end: None }
+    }
+
+    pub fn push(&mut self, data: Vec<u8>) {
+        if !data.is_empty() {
+            self.chunks.push_back(data);
+        }
+    }
+
+    /// the peer ended the stream
+    pub fn finish(&mut self) {
+        self.end = Some(Ok(()));
+    }
+
+    /// the body will not be completed
+    pub fn fail(&mut self, e: StreamError) {
+        self.end = Some(Err(e));
+    }
+
+    /// how many bytes are waiting to be read
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.len()).sum()
+    }
+}
+
+/// The body of a request
+///
+/// The handler is called as soon as the request's headers are in, and the
+/// body is read as it arrives with read_chunk, or all at once with collect.
+/// The peer can only send as much as the stream's window allows before
+/// what it sent is read, so a handler reading slowly slows down the peer.
+///
+/// A handler can also hold the peer back on purpose. pause stops the
+/// window being given back until resume (say while a backend catches up),
+/// and with set_auto_release(false) reading gives nothing back at all,
+/// the peer only gets the window handed out with grant.
+pub struct Body {
+    stream_id: u32,
+    queue: Rc<RefCell<BodyQueue>>,
+    pump: Option<Weak<RefCell<Pump>>>,
+}
+
+impl Body {
+
+    /// a body that is already complete
+    pub fn empty() -> Self {
+        Body::from(Vec::new())
+    }
+
+    /// a body fed by the connection through queue, read_chunk
+    /// uses pump when it needs to wait for more
+    pub(crate) fn streaming(stream_id: u32, queue: Rc<RefCell<BodyQueue>>, pump: Weak<RefCell<Pump>>) -> Self {
+        Body { stream_id: stream_id, queue: queue, pump: Some(pump) }
+    }
+
+    /// the next chunk of the body (the data of one DATA frame), waiting
+    /// for it to arrive if needed, or None once the whole body was read
+    pub fn read_chunk(&mut self) -> Option<Result<Vec<u8>, StreamError>> {
+        loop {
+            let chunk = {
+                let mut queue = self.queue.borrow_mut();
+                match queue.chunks.pop_front() {
+                    Some(chunk) => Some(chunk),
+                    None => match queue.end {
+                        Some(Ok(())) => return None,
+                        Some(Err(e)) => return Some(Err(e)),
+                        None => None,
+                    },
+                }
+            };
+
+            let pump = match self.pump.as_ref().and_then(|p| p.upgrade()) {
+                Some(pump) => pump,
+                None => return chunk.map(Ok).or(Some(Err(StreamError::ConnectionClosed))),
+            };
+            let mut pump = pump.borrow_mut();
+            if let Some(chunk) = chunk {
+                pump.release(self.stream_id, chunk.len());
+                return Some(Ok(chunk));
+            }
+            match pump.pump() {
+                Ok(true) => {},
+                _ => {
+                    self.queue.borrow_mut().fail(StreamError::ConnectionClosed);
+                },
+            }
+        }
+    }
+
+    /// let the peer send up to size bytes of the body before it is read,
+    /// for a large upload that would stall on the default window
+    ///
+    /// This does nothing for a body that is already complete.
+    pub fn set_recv_window(&mut self, size: u32) {
+        if let Some(pump) = self.pump.as_ref().and_then(|p| p.upgrade()) {
+            pump.borrow_mut().set_recv_window(self.stream_id, size);
+        }
+    }
+
+    /// stop giving the peer window for the body, so it stalls once it
+    /// sent what it already had window for
+    pub fn pause(&mut self) {
+        self.with_pump(|pump, stream_id| pump.pause_recv_window(stream_id, true));
+    }
+
+    /// give the peer the window held back since pause
+    pub fn resume(&mut self) {
+        self.with_pump(|pump, stream_id| pump.pause_recv_window(stream_id, false));
+    }
+
+    /// should reading the body give the peer window to send more (it
+    /// does unless this is turned off, then only grant does)
+    pub fn set_auto_release(&mut self, auto_release: bool) {
+        self.with_pump(|pump, stream_id| pump.set_auto_release(stream_id, auto_release));
+    }
+
+    /// let the peer send n more bytes of the body
+    pub fn grant(&mut self, n: u32) {
+        self.with_pump(|pump, stream_id| pump.grant(stream_id, n));
+    }
+
+    // nothing is done for a body that is already complete
+    fn with_pump<F: FnOnce(&mut Pump, u32)>(&mut self, f: F) {
+        if let Some(pump) = self.pump.as_ref().and_then(|p| p.upgrade()) {
+            f(&mut *pump.borrow_mut(), self.stream_id);
+        }
+    }
+
+    /// read the whole body, as long as it is not bigger than max
+    pub fn collect(&mut self, max: usize) -> Result<Vec<u8>, BodyError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.read_chunk() {
+            let chunk = chunk?;
+            if body.len() + chunk.len() > max {
+                return Err(BodyError::TooLarge(max));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Self {
+        let mut queue = BodyQueue::new();
+        queue.push(data);
+        queue.finish();
+        Body { stream_id: 0, queue: Rc::new(RefCell::new(queue)), pump: None }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Body {{ stream_id: {}, queue: {:?} }}", self.stream_id, self.queue.borrow())
+    }
+}
+
+#[cfg(test)]
+mod body_tests {
+
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use super::{Body, BodyError, BodyQueue, Pump, StreamError};
+    use connection::error::ErrorCode;
+
+    // feeds the queue one chunk per pump, keeping track of what was released
+    struct FakePump {
+        queue: Rc<RefCell<BodyQueue>>,
+        chunks: Vec<Vec<u8>>,
+        released: usize,
+    }
+
+    impl Pump for FakePump {
+        fn pump(&mut self) -> io::Result<bool> {
+            match self.chunks.pop() {
+                Some(chunk) => self.queue.borrow_mut().push(chunk),
+                None => self.queue.borrow_mut().finish(),
+            }
+            Ok(true)
+        }
+        fn release(&mut self, stream_id: u32, n: usize) {
+            assert_eq!(stream_id, 1);
+            self.released += n;
+        }
+        fn set_recv_window(&mut self, _stream_id: u32, _size: u32) {}
+        fn pause_recv_window(&mut self, _stream_id: u32, _paused: bool) {}
+        fn set_auto_release(&mut self, _stream_id: u32, _auto_release: bool) {}
+        fn grant(&mut self, _stream_id: u32, _n: u32) {}
+    }
+
+    fn streaming(mut chunks: Vec<Vec<u8>>) -> (Body, Rc<RefCell<FakePump>>) {
+        chunks.reverse();
+        let queue = Rc::new(RefCell::new(BodyQueue::new()));
+        let pump = Rc::new(RefCell::new(FakePump { queue: queue.clone(), chunks: chunks, released: 0 }));
+        let weak = Rc::downgrade(&pump);
+        (Body::streaming(1, queue, weak), pump)
+    }
+
+    #[test]
+    fn read_chunks() {
+        let (mut body, pump) = streaming(vec![b"abc".to_vec(), b"de".to_vec()]);
+        assert_eq!(body.read_chunk(), Some(Ok(b"abc".to_vec())));
+        assert_eq!(pump.borrow().released, 3);
+        assert_eq!(body.read_chunk(), Some(Ok(b"de".to_vec())));
+        assert_eq!(body.read_chunk(), None);
+        assert_eq!(pump.borrow().released, 5);
+
+        let mut body = Body::from(b"whole".to_vec());
+        assert_eq!(body.collect(5), Ok(b"whole".to_vec()));
+        assert_eq!(Body::empty().read_chunk(), None);
+    }
+
+    #[test]
+    fn collect_limit() {
+        let (mut body, _pump) = streaming(vec![vec![0; 100], vec![0; 100]]);
+        let e = body.collect(150).unwrap_err();
+        assert_eq!(e, BodyError::TooLarge(150));
+        assert_eq!(e.status(), 413);
+
+        let (mut body, pump) = streaming(vec![vec![0; 100]]);
+        body.read_chunk();
+        pump.borrow().queue.borrow_mut().fail(StreamError::Reset(ErrorCode::Cancel));
+        assert_eq!(body.collect(1000), Err(BodyError::Stream(StreamError::Reset(ErrorCode::Cancel))));
+
+        // the connection going away ends the body with an error
+        let (mut body, pump) = streaming(vec![vec![0; 100]]);
+        drop(pump);
+        assert_eq!(body.read_chunk(), Some(Err(StreamError::ConnectionClosed)));
+    }
+}
@@ -0,0 +1,77 @@
+//! Values of any type that go along with a request
+//!
+//! A middleware that works something out about a request (who made it,
+//! say) puts it here for the handlers after it, which look it up by its
+//! type. There is at most one value of each type.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<Any>>,
+}
+
+impl Extensions {
+
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    /// add value, giving back the one of the same type it replaces
+    pub fn insert<T: Any>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>()).and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Any>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>()).and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions({} values)", self.map.len())
+    }
+}
+
+#[cfg(test)]
+mod extensions_tests {
+
+    use super::Extensions;
+
+    #[derive(Debug, PartialEq)]
+    struct User(&'static str);
+
+    #[test]
+    fn by_type() {
+        let mut ext = Extensions::new();
+        assert_eq!(ext.insert(User("a")), None);
+        assert_eq!(ext.insert(5u32), None);
+        assert_eq!(ext.get::<User>(), Some(&User("a")));
+        assert_eq!(ext.get::<u64>(), None);
+
+        // one of each type
+        assert_eq!(ext.insert(User("b")), Some(User("a")));
+        *ext.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(ext.remove::<u32>(), Some(6));
+        assert_eq!(ext.len(), 1);
+    }
+}
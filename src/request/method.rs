@@ -0,0 +1,99 @@
+//! The request method (RFC 7231 4)
+
+use std::fmt;
+
+/// The :method of a request
+///
+/// Methods are case-sensitive, so "get" is not Get but Other("get").
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Connect,
+    Trace,
+    Other(String),
+}
+
+impl Method {
+
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+            Method::Other(ref method) => method,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Method {
+    fn from(method: &'a str) -> Self {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "PATCH" => Method::Patch,
+            "CONNECT" => Method::Connect,
+            "TRACE" => Method::Trace,
+            other => Method::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for Method {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Method {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod method_tests {
+
+    use super::Method;
+
+    #[test]
+    fn parse_methods() {
+        let known = [
+            ("GET", Method::Get), ("HEAD", Method::Head), ("POST", Method::Post),
+            ("PUT", Method::Put), ("DELETE", Method::Delete), ("OPTIONS", Method::Options),
+            ("PATCH", Method::Patch), ("CONNECT", Method::Connect), ("TRACE", Method::Trace),
+        ];
+        for &(name, ref method) in known.iter() {
+            assert_eq!(Method::from(name), *method);
+            assert_eq!(method.as_str(), name);
+        }
+
+        // case-sensitive, anything else is kept as it is
+        assert_eq!(Method::from("get"), Method::Other("get".to_string()));
+        assert_eq!(Method::from("PROPFIND"), Method::Other("PROPFIND".to_string()));
+        assert_eq!(Method::from("PROPFIND").to_string(), "PROPFIND");
+        assert!(Method::Get == "GET");
+        assert!(Method::from("Get") != "GET");
+    }
+}
@@ -1,27 +1,550 @@
 //! Request
 //!
+//! A request as the application sees it, built from the header list
+//! decoded off of a stream once it is checked to be well formed
 
-//use frame::headers::HeaderEntry;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
 
-struct Request {
-    headers: Vec<String>,
+use header::{common_name, HeaderEntry, HeaderList};
+
+mod body;
+mod extensions;
+mod method;
+mod percent;
+
+pub use self::body::{Body, BodyError, StreamError};
+pub(crate) use self::body::{BodyQueue, Pump};
+pub use self::extensions::Extensions;
+pub use self::method::Method;
+pub use self::percent::{form_decode, percent_decode};
+
+// 8.1.2.2 header fields that only mean something for a single HTTP/1.1
+// connection and are not allowed in HTTP/2 (TE is checked on its own)
+static CONNECTION_SPECIFIC : &'static [&'static str] = &[
+    "connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade",
+];
+
+/// Why a header list is not a well formed request (8.1.2.6 malformed
+/// requests are a stream error of type PROTOCOL_ERROR)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// a required pseudo-header field is missing
+    MissingPseudoHeader(&'static str),
+    /// a pseudo-header field that is not defined for requests
+    UnknownPseudoHeader(String),
+    /// a pseudo-header field appears more than once
+    DuplicatePseudoHeader(String),
+    /// a pseudo-header field after a regular header field
+    PseudoHeaderAfterRegular(String),
+    /// header field names must be lowercase
+    UppercaseName(String),
+    /// a connection-specific header field (or TE other than "trailers")
+    ConnectionSpecific(String),
+    /// :path is there but empty
+    EmptyPath,
+    /// :path has a bad percent-encoding, which is a bad request (400)
+    /// rather than a malformed one
+    InvalidPath(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RequestError::*;
+        match *self {
+            MissingPseudoHeader(name) => write!(f, "missing {}", name),
+            UnknownPseudoHeader(ref name) => write!(f, "unknown pseudo-header field {}", name),
+            DuplicatePseudoHeader(ref name) => write!(f, "{} appears more than once", name),
+            PseudoHeaderAfterRegular(ref name) => write!(f, "{} after a regular header field", name),
+            UppercaseName(ref name) => write!(f, "header field name {} is not lowercase", name),
+            ConnectionSpecific(ref name) => write!(f, "connection-specific header field {}", name),
+            EmptyPath => write!(f, "empty :path"),
+            InvalidPath(ref path) => write!(f, "bad percent-encoding in :path {}", path),
+        }
+    }
+}
+
+impl Error for RequestError {
+    fn description(&self) -> &str {
+        "Error: RequestError"
+    }
+}
+
+pub struct Request {
+    method: Method,
+    // empty for CONNECT
+    scheme: String,
+    // as it was sent, and decoded without the query
+    raw_path: String,
+    path: String,
+    authority: Option<String>,
+    // RFC 8441 what an extended CONNECT opens a tunnel for
+    protocol: Option<String>,
+    // the whole list, pseudo-header fields first
+    headers: HeaderList,
+    body: Body,
+    // what the "*" of the route matched
+    wildcard: Option<String>,
+    extensions: Extensions,
 }
 
 impl Request {
-    pub fn new() -> Self {
-        let mut v = Vec::with_capacity(1);
-        v.push("test".to_string());
-        Request { headers: v }
+
+    /// 8.1.2 check that a decoded header list is a well formed request
+    pub fn from_header_list(headers: HeaderList) -> Result<Request, RequestError> {
+        let mut method = None;
+        let mut scheme = None;
+        let mut path = None;
+        let mut authority = None;
+        let mut protocol = None;
+        let mut seen_regular = false;
+
+        for entry in headers.iter() {
+            let name = entry.name();
+            if name.bytes().any(|b| b.is_ascii_uppercase()) {
+                return Err(RequestError::UppercaseName(name.to_string()));
+            }
+
+            // 8.1.2.1 Pseudo-Header Fields
+            if name.starts_with(':') {
+                if seen_regular {
+                    return Err(RequestError::PseudoHeaderAfterRegular(name.to_string()));
+                }
+                let field = match name {
+                    ":method" => &mut method,
+                    ":scheme" => &mut scheme,
+                    ":path" => &mut path,
+                    ":authority" => &mut authority,
+                    ":protocol" => &mut protocol,
+                    _ => return Err(RequestError::UnknownPseudoHeader(name.to_string())),
+                };
+                if field.is_some() {
+                    return Err(RequestError::DuplicatePseudoHeader(name.to_string()));
+                }
+                *field = Some(entry.value().to_string());
+                continue;
+            }
+            seen_regular = true;
+
+            // 8.1.2.2 Connection-Specific Header Fields
+            if CONNECTION_SPECIFIC.contains(&name) || (name == "te" && entry.value() != "trailers") {
+                return Err(RequestError::ConnectionSpecific(name.to_string()));
+            }
+        }
+
+        let method = method.map(|m| Method::from(m.as_str())).ok_or(RequestError::MissingPseudoHeader(":method"))?;
+
+        // RFC 8441 4 :protocol only goes with CONNECT, which then
+        // has the rest of the pseudo-header fields like any request
+        if protocol.is_some() && method != Method::Connect {
+            return Err(RequestError::UnknownPseudoHeader(":protocol".to_string()));
+        }
+        if protocol.is_some() && authority.is_none() {
+            return Err(RequestError::MissingPseudoHeader(":authority"));
+        }
+
+        // 8.3 CONNECT only has :method and :authority
+        if method == Method::Connect && protocol.is_none() {
+            return match (authority, scheme.is_some() || path.is_some()) {
+                (Some(authority), false) => Ok(Request {
+                    method: method,
+                    scheme: String::new(),
+                    raw_path: String::new(),
+                    path: String::new(),
+                    authority: Some(authority),
+                    protocol: None,
+                    headers: headers,
+                    body: Body::empty(),
+                    wildcard: None,
+                    extensions: Extensions::new(),
+                }),
+                (None, _) => Err(RequestError::MissingPseudoHeader(":authority")),
+                (_, true) => Err(RequestError::UnknownPseudoHeader(if path.is_some() { ":path" } else { ":scheme" }.to_string())),
+            };
+        }
+
+        let scheme = scheme.ok_or(RequestError::MissingPseudoHeader(":scheme"))?;
+        let path = path.ok_or(RequestError::MissingPseudoHeader(":path"))?;
+        if path.is_empty() {
+            return Err(RequestError::EmptyPath);
+        }
+        let decoded = match percent_decode(path.split('?').next().unwrap()) {
+            Ok(decoded) => decoded.into_owned(),
+            Err(_) => return Err(RequestError::InvalidPath(path)),
+        };
+
+        Ok(Request {
+            method: method,
+            scheme: scheme,
+            raw_path: path,
+            path: decoded,
+            authority: authority,
+            protocol: protocol,
+            headers: headers,
+            body: Body::empty(),
+            wildcard: None,
+            extensions: Extensions::new(),
+        })
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// the path with percent-encoding decoded and without the query
+    /// (empty for CONNECT)
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// the path and query as they were sent
+    pub fn raw_path(&self) -> &str {
+        &self.raw_path
+    }
+
+    /// the query string (still encoded), without the "?"
+    pub fn query(&self) -> Option<&str> {
+        self.raw_path.find('?').map(|i| &self.raw_path[i + 1..])
+    }
+
+    /// the decoded key value pairs of the query in order, a repeated key
+    /// comes up each time it is there and a key without "=" has an empty
+    /// value
+    pub fn query_pairs<'a>(&'a self) -> Box<Iterator<Item=(Cow<'a, str>, Cow<'a, str>)> + 'a> {
+        let pairs = self.query().unwrap_or("").split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let key = parts.next().unwrap();
+                let value = parts.next().unwrap_or("");
+                (form_decode(key), form_decode(value))
+            });
+        Box::new(pairs)
+    }
+
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_ref().map(|a| a.as_str())
+    }
+
+    /// the protocol of an extended CONNECT (like "websocket"), the
+    /// request is for a tunnel rather than a response
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_ref().map(|p| p.as_str())
+    }
+
+    /// the value of the first header field with the name (case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let entry = match common_name(name) {
+            // the names of a request are lowercase, and interned
+            // when they are common ones
+            Some(name) => self.headers().find(|h| h.name_is(name)),
+            None => self.headers().find(|h| h.name().eq_ignore_ascii_case(name)),
+        };
+        entry.map(|h| h.value())
+    }
+
+    /// the client sent "expect: 100-continue" and might wait for
+    /// ResponseWriter::send_continue before it sends the body
+    /// (RFC 7231 5.1.1)
+    pub fn expects_continue(&self) -> bool {
+        self.header("expect").map_or(false, |e| e.eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// the accept-encoding header allows the content-coding (RFC 7231
+    /// 5.3.4), naming it or "*" with a q-value over 0
+    pub fn accepts_encoding(&self, coding: &str) -> bool {
+        let mut any = false;
+        let values = self.headers().filter(|h| h.name() == "accept-encoding").map(|h| h.value());
+        for item in values.flat_map(|v| v.split(',')) {
+            let mut params = item.split(';');
+            let name = params.next().unwrap().trim();
+            let q = params.map(|p| p.trim())
+                .find(|p| p.starts_with("q=") || p.starts_with("Q="))
+                .map_or(1.0, |p| p[2..].trim().parse::<f32>().unwrap_or(0.0));
+            if name.eq_ignore_ascii_case(coding) {
+                return q > 0.0;
+            }
+            if name == "*" {
+                any = q > 0.0;
+            }
+        }
+        any
+    }
+
+    /// the name value pairs of the cookie header fields in order
+    ///
+    /// HTTP/2 lets a client split the cookie into one field per crumb
+    /// (8.1.2.5), which comes out the same as one field with "; " between
+    /// them. Pairs without a name or "=" are skipped.
+    pub fn cookies<'a>(&'a self) -> Box<Iterator<Item=(&'a str, &'a str)> + 'a> {
+        let pairs = self.headers().filter(|h| h.name() == "cookie")
+            .flat_map(|h| h.value().split(';'))
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let name = parts.next().unwrap().trim();
+                match parts.next() {
+                    Some(value) if !name.is_empty() => Some((name, value.trim())),
+                    _ => None,
+                }
+            });
+        Box::new(pairs)
+    }
+
+    /// the value of the first cookie with the name
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies().find(|&(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// the request body, which might still be arriving
+    pub fn body(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    pub fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    /// the part of the path that matched the "*" of a Router
+    /// route like "/static/*" (without the query)
+    pub fn wildcard(&self) -> Option<&str> {
+        self.wildcard.as_ref().map(|w| w.as_str())
+    }
+
+    pub fn set_wildcard(&mut self, wildcard: Option<String>) {
+        self.wildcard = wildcard;
+    }
+
+    /// what the middleware in front of the handler added to the request
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// replace the regular header fields with the name with one with
+    /// the value (for a middleware passing the request on), the name
+    /// must be lowercase
+    pub fn set_header<V: Into<String>>(&mut self, name: &str, value: V) {
+        debug_assert!(!name.starts_with(':'));
+        self.headers.remove_by_name(name);
+        self.headers.add_entry((name.to_string(), value.into()).into());
+    }
+
+    /// take out the regular header fields with the name
+    pub fn remove_header(&mut self, name: &str) {
+        debug_assert!(!name.starts_with(':'));
+        self.headers.remove_by_name(name);
+    }
+
+    /// the regular header fields, without the pseudo-header fields
+    pub fn headers<'a>(&'a self) -> Box<Iterator<Item=&'a HeaderEntry> + 'a> {
+        Box::new(self.headers.iter().skip_while(|h| h.name().starts_with(':')))
     }
 }
 
 #[cfg(test)]
 mod request_tests {
-    use super::Request;
+
+    use super::{Request, RequestError};
+    use header::{Decoder, HeaderList};
+
+    // a request for https://localhost:8080/ from Chrome
+    static CHROME_BLOCK : &'static [u8] = &[
+        0x82, 0x41, 0x8A, 0xA0, 0xE4, 0x1D, 0x13, 0x9D, 0x09, 0xB8, 0xF0, 0x1E, 0x07, 0x87, 0x84, 0x40,
+        0x92, 0xB6, 0xB9, 0xAC, 0x1C, 0x85, 0x58, 0xD5, 0x20, 0xA4, 0xB6, 0xC2, 0xAD, 0x61, 0x7B, 0x5A,
+        0x54, 0x25, 0x1F, 0x01, 0x31, 0x7A, 0xD1, 0xD0, 0x7F, 0x66, 0xA2, 0x81, 0xB0, 0xDA, 0xE0, 0x53,
+        0xFA, 0xFC, 0x08, 0x7E, 0xD4, 0xCE, 0x6A, 0xAD, 0xF2, 0xA7, 0x97, 0x9C, 0x89, 0xC6, 0xBF, 0xB5,
+        0x21, 0xAE, 0xBA, 0x0B, 0xC8, 0xB1, 0xE6, 0x32, 0x58, 0x6D, 0x97, 0x57, 0x65, 0xC5, 0x3F, 0xAC,
+        0xD8, 0xF7, 0xE8, 0xCF, 0xF4, 0xA5, 0x06, 0xEA, 0x55, 0x31, 0x14, 0x9D, 0x4F, 0xFD, 0xA9, 0x7A,
+        0x7B, 0x0F, 0x49, 0x58, 0x6D, 0xF5, 0xC0, 0xBB, 0x20, 0x74, 0x2B, 0x84, 0x0D, 0x29, 0xB8, 0x72,
+        0x8E, 0xC3, 0x30, 0xDB, 0x2E, 0xAE, 0xCB, 0x9F, 0x53, 0xC0, 0x49, 0x7C, 0xA5, 0x89, 0xD3, 0x4D,
+        0x1F, 0x43, 0xAE, 0xBA, 0x0C, 0x41, 0xA4, 0xC7, 0xA9, 0x8F, 0x33, 0xA6, 0x9A, 0x3F, 0xDF, 0x9A,
+        0x68, 0xFA, 0x1D, 0x75, 0xD0, 0x62, 0x0D, 0x26, 0x3D, 0x4C, 0x79, 0xA6, 0x8F, 0xBE, 0xD0, 0x01,
+        0x77, 0xFE, 0x8D, 0x48, 0xE6, 0x2B, 0x1E, 0x0B, 0x1D, 0x7F, 0x46, 0xA4, 0x73, 0x15, 0x81, 0xD7,
+        0x54, 0xDF, 0x5F, 0x2C, 0x7C, 0xFD, 0xF6, 0x80, 0x0B, 0xBD, 0x50, 0x8D, 0x9B, 0xD9, 0xAB, 0xFA,
+        0x52, 0x42, 0xCB, 0x40, 0xD2, 0x5F, 0xA5, 0x23, 0xB3, 0x51, 0x8B, 0x2D, 0x4B, 0x70, 0xDD, 0xF4,
+        0x5A, 0xBE, 0xFB, 0x40, 0x05, 0xDE,
+    ];
+
+    fn list(entries: &[(&'static str, &'static str)]) -> HeaderList {
+        let mut list = HeaderList::with_capacity(entries.len());
+        for entry in entries {
+            list.add_entry((*entry).into());
+        }
+        list
+    }
+
+    fn request_error(entries: &[(&'static str, &'static str)]) -> RequestError {
+        Request::from_header_list(list(entries)).err().unwrap()
+    }
+
+    #[test]
+    fn request_from_chrome() {
+        let headers = Decoder::new(4096, 10).get_header_list(CHROME_BLOCK).unwrap();
+        let req = Request::from_header_list(headers).unwrap();
+
+        assert_eq!(req.method(), "GET");
+        assert_eq!(req.scheme(), "https");
+        assert_eq!(req.authority(), Some("localhost:8080"));
+        assert_eq!(req.path(), "/");
+        assert_eq!(req.header("Accept-Encoding"), Some("gzip, deflate, br"));
+        assert_eq!(req.header(":method"), None);
+        assert!(req.headers().all(|h| !h.name().starts_with(':')));
+        assert_eq!(req.headers().count(), 5);
+    }
+
+    #[test]
+    fn request_connect() {
+        let req = Request::from_header_list(list(&[(":method", "CONNECT"), (":authority", "example.com:443")])).unwrap();
+        assert_eq!(req.authority(), Some("example.com:443"));
+        assert_eq!(req.path(), "");
+
+        assert_eq!(request_error(&[(":method", "CONNECT")]), RequestError::MissingPseudoHeader(":authority"));
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":authority", "a"), (":path", "/")]), RequestError::UnknownPseudoHeader(":path".to_string()));
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":authority", "a"), (":scheme", "https")]), RequestError::UnknownPseudoHeader(":scheme".to_string()));
+        assert_eq!(req.protocol(), None);
+    }
 
     #[test]
-    fn request_create() {
-        let req = Request::new();
-        assert_eq!("test", req.headers[0]);
+    fn request_extended_connect() {
+        let req = Request::from_header_list(list(&[(":method", "CONNECT"), (":protocol", "websocket"), (":scheme", "https"),
+                                                   (":path", "/chat?room=1"), (":authority", "example.com")])).unwrap();
+        assert_eq!(req.protocol(), Some("websocket"));
+        assert_eq!(req.path(), "/chat");
+        assert_eq!(req.query(), Some("room=1"));
+        assert_eq!(req.authority(), Some("example.com"));
+
+        // the pseudo-header fields a plain CONNECT goes without are needed
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":protocol", "websocket"), (":authority", "a"), (":path", "/")]),
+                   RequestError::MissingPseudoHeader(":scheme"));
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":protocol", "websocket"), (":authority", "a"), (":scheme", "https")]),
+                   RequestError::MissingPseudoHeader(":path"));
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":protocol", "websocket"), (":scheme", "https"), (":path", "/")]),
+                   RequestError::MissingPseudoHeader(":authority"));
+        assert_eq!(request_error(&[(":method", "CONNECT"), (":protocol", "websocket"), (":protocol", "websocket"), (":authority", "a")]),
+                   RequestError::DuplicatePseudoHeader(":protocol".to_string()));
+        // and :protocol only goes with CONNECT
+        assert_eq!(request_error(&[(":method", "GET"), (":protocol", "websocket"), (":scheme", "https"), (":path", "/"), (":authority", "a")]),
+                   RequestError::UnknownPseudoHeader(":protocol".to_string()));
+    }
+
+    #[test]
+    fn malformed_requests() {
+        assert_eq!(request_error(&[(":scheme", "https"), (":path", "/")]), RequestError::MissingPseudoHeader(":method"));
+        assert_eq!(request_error(&[(":method", "GET"), (":path", "/")]), RequestError::MissingPseudoHeader(":scheme"));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https")]), RequestError::MissingPseudoHeader(":path"));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "")]), RequestError::EmptyPath);
+
+        assert_eq!(request_error(&[(":method", "GET"), (":method", "POST"), (":scheme", "https"), (":path", "/")]),
+                   RequestError::DuplicatePseudoHeader(":method".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":status", "200"), (":scheme", "https"), (":path", "/")]),
+                   RequestError::UnknownPseudoHeader(":status".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), ("accept", "*/*"), (":scheme", "https"), (":path", "/")]),
+                   RequestError::PseudoHeaderAfterRegular(":scheme".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/"), ("Accept", "*/*")]),
+                   RequestError::UppercaseName("Accept".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/"), ("connection", "keep-alive")]),
+                   RequestError::ConnectionSpecific("connection".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/"), ("te", "gzip")]),
+                   RequestError::ConnectionSpecific("te".to_string()));
+
+        assert!(Request::from_header_list(list(&[(":method", "GET"), (":scheme", "https"), (":path", "/"), ("te", "trailers")])).is_ok());
+    }
+
+    fn get(path: &'static str) -> Request {
+        Request::from_header_list(list(&[(":method", "GET"), (":scheme", "https"), (":path", path)])).unwrap()
+    }
+
+    fn pairs(req: &Request) -> Vec<(String, String)> {
+        req.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+    }
+
+    #[test]
+    fn path_and_query() {
+        let req = get("/search%20here?q=a%20b&lang=en");
+        assert_eq!(req.raw_path(), "/search%20here?q=a%20b&lang=en");
+        assert_eq!(req.path(), "/search here");
+        assert_eq!(req.query(), Some("q=a%20b&lang=en"));
+        assert_eq!(pairs(&req), vec![("q".to_string(), "a b".to_string()), ("lang".to_string(), "en".to_string())]);
+
+        // + is a space, keys repeat, keys can go without a value
+        let req = get("/?tag=a+b&tag=c&flag&=x&&k=1=2");
+        assert_eq!(pairs(&req), vec![
+            ("tag".to_string(), "a b".to_string()),
+            ("tag".to_string(), "c".to_string()),
+            ("flag".to_string(), String::new()),
+            (String::new(), "x".to_string()),
+            ("k".to_string(), "1=2".to_string()),
+        ]);
+
+        let req = get("/plain");
+        assert_eq!(req.query(), None);
+        assert_eq!(pairs(&req).len(), 0);
+        assert_eq!(get("/?").query(), Some(""));
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let accepts = |value: &'static str| {
+            let req = Request::from_header_list(list(&[
+                (":method", "GET"), (":scheme", "https"), (":path", "/"), ("accept-encoding", value),
+            ])).unwrap();
+            req.accepts_encoding("gzip")
+        };
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, GZIP;q=0.5, br"));
+        assert!(accepts("*"));
+        assert!(!accepts("deflate, br"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("gzip; q=0.000, *"));
+        assert!(!accepts("*;q=0"));
+        assert!(!accepts("gzip;q=nope"));
+        assert!(!get("/").accepts_encoding("gzip"));
+    }
+
+    #[test]
+    fn cookies() {
+        let with = |entries: &[(&'static str, &'static str)]| {
+            let mut all = vec![(":method", "GET"), (":scheme", "https"), (":path", "/")];
+            all.extend_from_slice(entries);
+            Request::from_header_list(list(&all)).unwrap()
+        };
+        let crumbled = with(&[("cookie", "a=1"), ("accept", "*/*"), ("cookie", " b = two "), ("cookie", "c=")]);
+        let combined = with(&[("cookie", "a=1; b = two ;c=")]);
+        let expected = vec![("a", "1"), ("b", "two"), ("c", "")];
+        assert_eq!(crumbled.cookies().collect::<Vec<_>>(), expected);
+        assert_eq!(combined.cookies().collect::<Vec<_>>(), expected);
+        assert_eq!(combined.cookie("b"), Some("two"));
+        assert_eq!(combined.cookie("d"), None);
+
+        // malformed pairs don't spoil the rest
+        let req = with(&[("cookie", "junk; =nameless;; id=x=y"), ("cookie", "")]);
+        assert_eq!(req.cookies().collect::<Vec<_>>(), vec![("id", "x=y")]);
+        assert_eq!(get("/").cookies().count(), 0);
+    }
+
+    #[test]
+    fn path_decoding() {
+        assert_eq!(get("/caf%C3%A9/%E2%82%AC.txt").path(), "/café/€.txt");
+        // a + is only a space in the query
+        assert_eq!(get("/a+b").path(), "/a+b");
+        // dot segments come out as they are for whoever normalizes the path
+        assert_eq!(get("/static/%2e%2e/%2E%2E/etc/passwd").path(), "/static/../../etc/passwd");
+
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/%zz")]),
+                   RequestError::InvalidPath("/%zz".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/100%?q")]),
+                   RequestError::InvalidPath("/100%?q".to_string()));
+        assert_eq!(request_error(&[(":method", "GET"), (":scheme", "https"), (":path", "/%FF")]),
+                   RequestError::InvalidPath("/%FF".to_string()));
+        // the query is not held to that
+        assert_eq!(get("/?q=100%").query_pairs().next().unwrap().1, "100%");
     }
 }
@@ -0,0 +1,105 @@
+//! Percent-encoding (RFC 3986 2.1) in paths and query strings
+
+use std::borrow::Cow;
+
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'...b'9' => Some(b - b'0'),
+        b'a'...b'f' => Some(b - b'a' + 10),
+        b'A'...b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+// the byte of the escape at the start of s, if it is one
+fn escaped(s: &[u8]) -> Option<u8> {
+    match s {
+        &[b'%', hi, lo, ..] => match (hex(hi), hex(lo)) {
+            (Some(hi), Some(lo)) => Some(hi << 4 | lo),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// decode the %XX escapes in a path, an escape that is cut short or
+/// not hex, or bytes that do not decode to UTF-8, are an error
+pub fn percent_decode(s: &str) -> Result<Cow<str>, ()> {
+    if !s.contains('%') {
+        return Ok(Cow::Borrowed(s));
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                decoded.push(escaped(&bytes[i..]).ok_or(())?);
+                i += 3;
+            },
+            b => {
+                decoded.push(b);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).map(Cow::Owned).map_err(|_| ())
+}
+
+/// decode a key or value of a query string, where "+" is a space
+///
+/// This is forgiving like browsers are: a "%" that does not start an
+/// escape is kept as it is and bytes that are not UTF-8 are replaced.
+pub fn form_decode(s: &str) -> Cow<str> {
+    if !s.contains('%') && !s.contains('+') {
+        return Cow::Borrowed(s);
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], escaped(&bytes[i..])) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            },
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            },
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+#[cfg(test)]
+mod percent_tests {
+
+    use super::{form_decode, percent_decode};
+
+    #[test]
+    fn decode_path() {
+        assert_eq!(percent_decode("/plain/path").unwrap(), "/plain/path");
+        assert_eq!(percent_decode("/a%20b").unwrap(), "/a b");
+        assert_eq!(percent_decode("/%2e%2E/x").unwrap(), "/../x");
+        assert_eq!(percent_decode("/caf%C3%A9/%E2%82%AC").unwrap(), "/café/€");
+        assert_eq!(percent_decode("/100%"), Err(()));
+        assert_eq!(percent_decode("/%4"), Err(()));
+        assert_eq!(percent_decode("/%zz"), Err(()));
+        assert_eq!(percent_decode("/%C3"), Err(()));
+    }
+
+    #[test]
+    fn decode_form() {
+        assert_eq!(form_decode("a+b%20c"), "a b c");
+        assert_eq!(form_decode("100%"), "100%");
+        assert_eq!(form_decode("%E2%82%AC"), "€");
+        assert_eq!(form_decode("%FF"), "\u{FFFD}");
+    }
+}
@@ -0,0 +1,323 @@
+//! Writing a response body as it is made
+
+use std::cmp;
+use std::io::{self, Write};
+use std::mem;
+
+use connection::error::{ErrorCode, H2Error};
+use connection::transfer::TransferCount;
+use header::HeaderList;
+
+use super::ResponseContext;
+use super::gzip::Gzip;
+
+// how much of the body is collected before it is gzipped
+const GZIP_CHUNK : usize = 16384;
+
+/// Writes the body of a response started with ResponseWriter::start
+///
+/// Writes are collected until there is a full frame's worth (at the peer's
+/// max frame size as it is when the frame fills), flush sends what there
+/// is right away (for trickling out events as they happen).
+/// What the flow control windows do not allow yet is queued on the stream.
+/// A gzipped body is compressed in chunks, flush compresses what there is.
+///
+/// The body has to be ended with finish or finish_with_trailers, a writer
+/// dropped without that resets the stream with CANCEL so the peer does not
+/// take what it got for the whole body.
+pub struct BodyWriter<'w, 'conn: 'w> {
+    ctx: &'w mut ResponseContext<'conn>,
+    buf: Vec<u8>,
+    frame_size: usize,
+    finished: bool,
+    // for HEAD, the headers to send on finish and the length of the
+    // body that was written (and dropped)
+    head: Option<HeaderList>,
+    written: usize,
+    // the body is gzipped, written data waits in pending to be compressed
+    // and is counted as the body of the response as it is written
+    gzip: Option<Gzip>,
+    pending: Vec<u8>,
+    count: Option<TransferCount>,
+    // everything written, before it is compressed
+    bytes_written: u64,
+}
+
+impl<'w, 'conn> BodyWriter<'w, 'conn> {
+
+    pub fn new(ctx: &'w mut ResponseContext<'conn>, gzip: Option<Gzip>) -> Self {
+        let frame_size = ctx.max_frame_size();
+        let count = match gzip {
+            Some(_) => Some(ctx.transfer()),
+            None => None,
+        };
+        if let Some(ref count) = count {
+            count.set_compressed();
+        }
+        BodyWriter {
+            ctx: ctx,
+            buf: Vec::with_capacity(frame_size),
+            frame_size: frame_size,
+            finished: false,
+            head: None,
+            written: 0,
+            gzip: gzip,
+            pending: Vec::new(),
+            count: count,
+            bytes_written: 0,
+        }
+    }
+
+    /// a writer that only counts the body, sending
+    /// headers with its length on finish
+    pub fn head(ctx: &'w mut ResponseContext<'conn>, headers: HeaderList, gzip: Option<Gzip>) -> Self {
+        BodyWriter {
+            ctx: ctx,
+            buf: Vec::new(),
+            frame_size: 0,
+            finished: false,
+            head: Some(headers),
+            written: 0,
+            gzip: gzip,
+            pending: Vec::new(),
+            count: None,
+            bytes_written: 0,
+        }
+    }
+
+    /// how much of the body has been written so far, before it is
+    /// compressed (and whether or not it was sent yet)
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// send the rest of the body, ending the stream
+    pub fn finish(mut self) -> Result<(), H2Error> {
+        self.finished = true;
+        self.finish_gzip()?;
+        if let Some(headers) = self.head.take() {
+            return self.ctx.send_head(headers, self.written);
+        }
+        let buf = mem::replace(&mut self.buf, Vec::new());
+        self.ctx.send_data(&buf, true)
+    }
+
+    /// send the rest of the body, ending the stream with trailers
+    pub fn finish_with_trailers(mut self, trailers: HeaderList) -> Result<(), H2Error> {
+        if self.head.is_some() {
+            return self.finish();
+        }
+        self.finished = true;
+        self.finish_gzip()?;
+        self.send_buffered()?;
+        self.ctx.send_trailers(trailers)
+    }
+
+    // add to what is sent, sending each full frame
+    fn put(&mut self, mut data: &[u8]) -> Result<(), H2Error> {
+        if self.head.is_some() {
+            self.written += data.len();
+            return Ok(());
+        }
+        while !data.is_empty() {
+            let n = cmp::min(data.len(), self.frame_size.saturating_sub(self.buf.len()));
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() >= self.frame_size {
+                self.send_buffered()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compress_pending(&mut self) -> Result<(), H2Error> {
+        let compressed = match self.gzip {
+            Some(ref mut gzip) => gzip.compress(&self.pending),
+            None => return Ok(()),
+        };
+        self.pending.clear();
+        self.put(&compressed)
+    }
+
+    fn finish_gzip(&mut self) -> Result<(), H2Error> {
+        self.compress_pending()?;
+        match self.gzip.take() {
+            Some(gzip) => self.put(&gzip.finish()),
+            None => Ok(()),
+        }
+    }
+
+    fn send_buffered(&mut self) -> Result<(), H2Error> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let res = self.ctx.send_data(&self.buf, false);
+        self.buf.clear();
+        // the peer may have changed it since
+        self.frame_size = self.ctx.max_frame_size();
+        res
+    }
+}
+
+fn io_error(e: H2Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl<'w, 'conn> Write for BodyWriter<'w, 'conn> {
+
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.bytes_written += data.len() as u64;
+        if let Some(ref count) = self.count {
+            count.add_body(data.len());
+        }
+        if self.gzip.is_none() {
+            self.put(data).map_err(io_error)?;
+            return Ok(data.len());
+        }
+        self.pending.extend_from_slice(data);
+        if self.pending.len() >= GZIP_CHUNK {
+            self.compress_pending().map_err(io_error)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.gzip.is_some() {
+            self.compress_pending().map_err(io_error)?;
+            let flushed = self.gzip.as_mut().unwrap().flush();
+            self.put(&flushed).map_err(io_error)?;
+        }
+        self.send_buffered().map_err(io_error)
+    }
+}
+
+impl<'w, 'conn> Drop for BodyWriter<'w, 'conn> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.ctx.reset(ErrorCode::Cancel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod body_writer_tests {
+
+    use std::io::Write;
+
+    use connection::Connection;
+    use frame::OwnedFrame;
+    use frame::frame_types::{types, flags, RstStreamFrame};
+    use header::{Decoder, Encoder, HeaderList};
+    use response::ResponseWriter;
+    use test_util::GET_BLOCK;
+
+    fn open_stream_with(block: &[u8]) -> Connection {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        let mut frame = OwnedFrame::headers(1, block, flags::END_HEADERS | flags::END_STREAM);
+        conn.dispatch_frame(frame.as_frame()).unwrap();
+        conn
+    }
+
+    fn frames(conn: &mut Connection) -> Vec<OwnedFrame> {
+        let mut frames = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn content_type() -> HeaderList {
+        let mut headers = HeaderList::with_capacity(1);
+        headers.add_entry(("content-type", "text/event-stream").into());
+        headers
+    }
+
+    #[test]
+    fn flushed_events() {
+        let mut conn = open_stream_with(GET_BLOCK);
+        {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            let mut body = resp.start(200, content_type()).unwrap();
+            for event in &["data: 1\n\n", "data: 2\n\n", "data: 3\n\n"] {
+                body.write_all(event.as_bytes()).unwrap();
+                body.flush().unwrap();
+            }
+            body.finish().unwrap();
+        }
+
+        let frames = frames(&mut conn);
+        let headers = Decoder::new(4096, 20).get_header_list(frames[0].payload()).unwrap();
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert_eq!(headers.get_value_by_name("content-type"), Some("text/event-stream"));
+        let data: Vec<(&[u8], u8)> = frames[1..].iter().map(|f| (f.payload(), f.frame_flags())).collect();
+        assert_eq!(data, vec![
+            (&b"data: 1\n\n"[..], 0),
+            (&b"data: 2\n\n"[..], 0),
+            (&b"data: 3\n\n"[..], 0),
+            (&b""[..], flags::END_STREAM),
+        ]);
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn buffered_to_frame_size() {
+        let mut conn = open_stream_with(GET_BLOCK);
+        {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            let mut body = resp.start(200, HeaderList::with_capacity(0)).unwrap();
+            for _ in 0..5 {
+                body.write_all(&[7; 10000]).unwrap();
+            }
+            body.finish().unwrap();
+        }
+
+        let sizes: Vec<(usize, u8)> = frames(&mut conn)[1..].iter().map(|f| (f.payload().len(), f.frame_flags())).collect();
+        assert_eq!(sizes, vec![(16384, 0), (16384, 0), (16384, 0), (848, flags::END_STREAM)]);
+    }
+
+    #[test]
+    fn head_counts_body() {
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":method", "HEAD").into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", "/events").into());
+        let mut conn = open_stream_with(&Encoder::new(4096, 20).encode_header_list(&list));
+        {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            resp.set_head(true);
+            let mut body = resp.start(200, content_type()).unwrap();
+            for _ in 0..5 {
+                body.write_all(&[7; 10000]).unwrap();
+                body.flush().unwrap();
+            }
+            body.finish().unwrap();
+        }
+
+        let frames = frames(&mut conn);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].frame_type(), types::HEADERS);
+        assert_eq!(frames[0].frame_flags(), flags::END_HEADERS | flags::END_STREAM);
+        let headers = Decoder::new(4096, 20).get_header_list(frames[0].payload()).unwrap();
+        assert_eq!(headers.get_value_by_name("content-type"), Some("text/event-stream"));
+        assert_eq!(headers.get_value_by_name("content-length"), Some("50000"));
+        assert!(conn.stream(1).is_none());
+    }
+
+    #[test]
+    fn dropped_writer_cancels() {
+        let mut conn = open_stream_with(GET_BLOCK);
+        {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            let mut body = resp.start(200, HeaderList::with_capacity(0)).unwrap();
+            body.write_all(b"half of it").unwrap();
+        }
+
+        let mut frames = frames(&mut conn);
+        let order: Vec<u8> = frames.iter().map(|f| f.frame_type()).collect();
+        assert_eq!(order, vec![types::HEADERS, types::RST_STREAM]);
+        let rst: RstStreamFrame = frames[1].as_frame().into();
+        assert_eq!(rst.get_error_code(), 0x8);
+        assert!(conn.stream(1).is_none());
+    }
+}
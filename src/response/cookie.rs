@@ -0,0 +1,126 @@
+//! Set-Cookie
+//!
+//! A cookie for the client to store, with the attributes of RFC 6265 4.1
+//! (and SameSite), built up like
+//!
+//! ```ignore
+//! SetCookie::new("id", "abc").path("/").max_age(3600).http_only()
+//! ```
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie sent with ResponseWriter::add_cookie, written out
+/// as the value of a set-cookie header field by Display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    max_age: Option<u64>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+
+    /// the name and value must already be valid cookie octets,
+    /// they are not quoted or encoded
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        SetCookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// seconds until the cookie expires, 0 removes it right away
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// keep the cookie away from scripts
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// only send the cookie back over https
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cookie_tests {
+
+    use super::{SameSite, SetCookie};
+
+    #[test]
+    fn serialize() {
+        assert_eq!(SetCookie::new("id", "abc").to_string(), "id=abc");
+        assert_eq!(SetCookie::new("id", "abc").path("/").max_age(3600).http_only().secure()
+                   .same_site(SameSite::Lax).to_string(),
+                   "id=abc; Path=/; Max-Age=3600; HttpOnly; Secure; SameSite=Lax");
+        // attributes come out in the same order however they were set
+        assert_eq!(SetCookie::new("theme", "dark").secure().same_site(SameSite::Strict).path("/app").to_string(),
+                   "theme=dark; Path=/app; Secure; SameSite=Strict");
+        assert_eq!(SetCookie::new("id", "").max_age(0).same_site(SameSite::None).to_string(),
+                   "id=; Max-Age=0; SameSite=None");
+    }
+}
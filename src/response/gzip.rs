@@ -0,0 +1,402 @@
+//! A small gzip (RFC 1952) encoder for response bodies
+//!
+//! Each piece of the body is compressed into a DEFLATE (RFC 1951) block
+//! with the fixed Huffman codes, using LZ77 matches found within that
+//! piece. It is far from the best compression there is, but it is simple
+//! and still does well on the text that is worth compressing.
+
+use std::cmp;
+use std::io::{self, Read};
+use std::mem;
+
+use connection::transfer::TransferCount;
+
+// ID1 ID2, CM = 8 (deflate), no flags, no MTIME, no XFL, OS = unknown
+static HEADER : [u8; 10] = [0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+
+// the farthest back and the shortest and longest a match can be
+const WINDOW : usize = 32768;
+const MIN_MATCH : usize = 3;
+const MAX_MATCH : usize = 258;
+
+const HASH_BITS : u32 = 12;
+const NO_POS : u32 = ::std::u32::MAX;
+
+// 3.2.5 the lengths and distances each code starts at, and how many extra bits follow it
+static LENGTH_BASE : [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+static LENGTH_EXTRA : [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE : [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+    1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+static DIST_EXTRA : [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for n in 0..256 {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            table[n] = c;
+        }
+        table
+    };
+}
+
+/// A gzip member being written
+///
+/// What compress, flush and finish return is the next part of the gzip
+/// data (the header is in the first part), the parts joined together are
+/// the whole thing.
+pub struct Gzip {
+    out: Vec<u8>,
+    // bits not making up a whole byte of out yet
+    bits: u32,
+    nbits: u32,
+    crc: u32,
+    size: u32,
+    // the last position of each hash of 3 bytes
+    head: Vec<u32>,
+}
+
+impl Gzip {
+
+    pub fn new() -> Self {
+        Gzip {
+            out: HEADER.to_vec(),
+            bits: 0,
+            nbits: 0,
+            crc: 0xFFFFFFFF,
+            size: 0,
+            head: vec![NO_POS; 1 << HASH_BITS],
+        }
+    }
+
+    /// compress the next part of the data
+    pub fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        if data.is_empty() {
+            return self.take();
+        }
+        for &b in data {
+            self.crc = CRC_TABLE[((self.crc ^ b as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        self.size = self.size.wrapping_add(data.len() as u32);
+
+        // BFINAL = 0, BTYPE = 01 (fixed Huffman codes)
+        self.put_bits(0b010, 3);
+        for pos in self.head.iter_mut() {
+            *pos = NO_POS;
+        }
+
+        let mut i = 0;
+        while i < data.len() {
+            let (len, dist) = self.longest_match(data, i);
+            if len < MIN_MATCH {
+                self.put_literal(data[i] as u16);
+                i += 1;
+                continue;
+            }
+            self.put_length(len);
+            self.put_distance(dist);
+            for j in i + 1..i + len {
+                if j + MIN_MATCH <= data.len() {
+                    self.head[hash(&data[j..])] = j as u32;
+                }
+            }
+            i += len;
+        }
+        self.put_literal(256);
+        self.take()
+    }
+
+    /// make everything compressed so far decodable, for sending
+    /// what there is of a body right away
+    pub fn flush(&mut self) -> Vec<u8> {
+        // an empty stored block, which ends on a byte boundary
+        self.put_bits(0, 3);
+        self.align();
+        self.out.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        self.take()
+    }
+
+    /// end the data, returning the rest of it
+    pub fn finish(mut self) -> Vec<u8> {
+        // an empty final block with the fixed codes
+        self.put_bits(0b011, 3);
+        self.put_literal(256);
+        self.align();
+        let crc = !self.crc;
+        let size = self.size;
+        self.put_u32(crc);
+        self.put_u32(size);
+        self.take()
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        mem::replace(&mut self.out, Vec::new())
+    }
+
+    // the longest match for the data at i before it (and its distance),
+    // only the last position with the same hash is tried
+    fn longest_match(&mut self, data: &[u8], i: usize) -> (usize, usize) {
+        if i + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let h = hash(&data[i..]);
+        let candidate = mem::replace(&mut self.head[h], i as u32);
+        if candidate == NO_POS || i - candidate as usize > WINDOW {
+            return (0, 0);
+        }
+        let candidate = candidate as usize;
+        let max = cmp::min(MAX_MATCH, data.len() - i);
+        let mut len = 0;
+        while len < max && data[candidate + len] == data[i + len] {
+            len += 1;
+        }
+        (len, i - candidate)
+    }
+
+    // add n bits of value, bits are packed starting at the least significant
+    fn put_bits(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting at their most significant bit
+    fn put_code(&mut self, code: u32, len: u32) {
+        let mut reversed = 0;
+        for i in 0..len {
+            reversed |= (code >> i & 1) << (len - 1 - i);
+        }
+        self.put_bits(reversed, len);
+    }
+
+    // 3.2.6 the fixed literal/length codes
+    fn put_literal(&mut self, lit: u16) {
+        let lit = lit as u32;
+        match lit {
+            0...143 => self.put_code(0x30 + lit, 8),
+            144...255 => self.put_code(0x190 + lit - 144, 9),
+            256...279 => self.put_code(lit - 256, 7),
+            _ => self.put_code(0xC0 + lit - 280, 8),
+        }
+    }
+
+    fn put_length(&mut self, len: usize) {
+        let i = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+        self.put_literal(257 + i as u16);
+        self.put_bits((len - LENGTH_BASE[i] as usize) as u32, LENGTH_EXTRA[i] as u32);
+    }
+
+    fn put_distance(&mut self, dist: usize) {
+        let i = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+        self.put_code(i as u32, 5);
+        self.put_bits((dist - DIST_BASE[i] as usize) as u32, DIST_EXTRA[i] as u32);
+    }
+
+    fn align(&mut self) {
+        if self.nbits > 0 {
+            let n = 8 - self.nbits;
+            self.put_bits(0, n);
+        }
+    }
+
+    fn put_u32(&mut self, n: u32) {
+        self.out.extend_from_slice(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let n = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (n.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// how much of a body reader is read and compressed at a time
+const READ_SIZE : usize = 16384;
+
+/// Reads what another reader reads, gzipped
+pub struct GzipReader<R> {
+    inner: R,
+    gzip: Option<Gzip>,
+    // compressed and not read yet
+    out: Vec<u8>,
+    pos: usize,
+    // where what is read from inner is counted, if it is
+    count: Option<TransferCount>,
+}
+
+impl<R: Read> GzipReader<R> {
+    pub fn new(inner: R) -> Self {
+        GzipReader { inner: inner, gzip: Some(Gzip::new()), out: Vec::new(), pos: 0, count: None }
+    }
+
+    /// count everything read from inner as the body of a response
+    pub fn counted(inner: R, count: TransferCount) -> Self {
+        GzipReader { count: Some(count), .. GzipReader::new(inner) }
+    }
+}
+
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // a part can compress down to nothing, so keep going until there is something
+        while self.pos == self.out.len() {
+            let mut gzip = match self.gzip.take() {
+                Some(gzip) => gzip,
+                None => return Ok(0),
+            };
+            let mut data = [0u8; READ_SIZE];
+            let n = self.inner.read(&mut data)?;
+            if let Some(ref count) = self.count {
+                count.add_body(n);
+            }
+            self.out = match n {
+                0 => gzip.finish(),
+                n => {
+                    let out = gzip.compress(&data[..n]);
+                    self.gzip = Some(gzip);
+                    out
+                },
+            };
+            self.pos = 0;
+        }
+        let n = cmp::min(buf.len(), self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Just enough of gunzip to check what Gzip makes
+/// (only stored and fixed Huffman blocks)
+#[cfg(test)]
+pub fn gunzip(data: &[u8]) -> Vec<u8> {
+    struct Bits<'a> { data: &'a [u8], pos: usize }
+
+    impl<'a> Bits<'a> {
+        fn bit(&mut self) -> u32 {
+            let bit = self.data[self.pos / 8] >> (self.pos % 8) & 1;
+            self.pos += 1;
+            bit as u32
+        }
+        fn bits(&mut self, n: u8) -> u32 {
+            (0..n).fold(0, |v, i| v | self.bit() << i)
+        }
+        fn code(&mut self, n: u8) -> u32 {
+            (0..n).fold(0, |v, _| v << 1 | self.bit())
+        }
+        fn literal(&mut self) -> u32 {
+            let code = self.code(7);
+            if code < 24 {
+                return 256 + code;
+            }
+            let code = code << 1 | self.bit();
+            match code {
+                0x30...0xBF => code - 0x30,
+                0xC0...0xC7 => 280 + code - 0xC0,
+                _ => 144 + (code << 1 | self.bit()) - 0x190,
+            }
+        }
+    }
+
+    assert_eq!(&data[..10], &HEADER[..]);
+    let mut bits = Bits { data: &data[10..], pos: 0 };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = bits.bit();
+        match bits.bits(2) {
+            0 => {
+                bits.pos = (bits.pos + 7) / 8 * 8;
+                let at = bits.pos / 8;
+                let len = bits.data[at] as usize | (bits.data[at + 1] as usize) << 8;
+                out.extend_from_slice(&bits.data[at + 4..at + 4 + len]);
+                bits.pos += (4 + len) * 8;
+            },
+            1 => loop {
+                match bits.literal() {
+                    lit @ 0...255 => out.push(lit as u8),
+                    256 => break,
+                    code => {
+                        let i = (code - 257) as usize;
+                        let len = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i]) as usize;
+                        let d = bits.code(5) as usize;
+                        let dist = DIST_BASE[d] as usize + bits.bits(DIST_EXTRA[d]) as usize;
+                        for _ in 0..len {
+                            let b = out[out.len() - dist];
+                            out.push(b);
+                        }
+                    },
+                }
+            },
+            t => panic!("unexpected block type {}", t),
+        }
+        if last == 1 {
+            break;
+        }
+    }
+
+    let at = (bits.pos + 7) / 8 + 10;
+    let le = |i: usize| data[i] as u32 | (data[i + 1] as u32) << 8 | (data[i + 2] as u32) << 16 | (data[i + 3] as u32) << 24;
+    let mut check = Gzip::new();
+    check.compress(&out);
+    assert_eq!(le(at), !check.crc);
+    assert_eq!(le(at + 4), out.len() as u32);
+    assert_eq!(data.len(), at + 8);
+    out
+}
+
+#[cfg(test)]
+mod gzip_tests {
+
+    use std::io::{Cursor, Read};
+
+    use super::{gunzip, Gzip, GzipReader};
+
+    fn repeat(data: &[u8], n: usize) -> Vec<u8> {
+        (0..n).flat_map(|_| data.iter().cloned()).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = repeat(b"<p>hello hello hello, this is repeated a lot a lot a lot</p>", 500);
+        let mut gzip = Gzip::new();
+        let mut data = gzip.compress(&text);
+        data.extend(gzip.finish());
+        assert!(data.len() < text.len() / 10);
+        assert_eq!(gunzip(&data), text);
+
+        // every byte value, and nothing at all
+        let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut gzip = Gzip::new();
+        let mut data = gzip.compress(&bytes);
+        data.extend(gzip.finish());
+        assert_eq!(gunzip(&data), bytes);
+        assert_eq!(gunzip(&Gzip::new().finish()), b"");
+    }
+
+    #[test]
+    fn parts_and_flushes() {
+        let mut gzip = Gzip::new();
+        let mut data = Vec::new();
+        for part in &[&b"first part, "[..], b"", b"second part, ", b"first part again"] {
+            data.extend(gzip.compress(part));
+            data.extend(gzip.flush());
+        }
+        data.extend(gzip.finish());
+        assert_eq!(gunzip(&data), b"first part, second part, first part again".to_vec());
+
+        // a reader gives the same as compressing it all
+        let text = repeat(b"abcabcabcabc", 4000);
+        let mut data = Vec::new();
+        GzipReader::new(Cursor::new(text.clone())).read_to_end(&mut data).unwrap();
+        assert_eq!(gunzip(&data), text);
+    }
+}
@@ -0,0 +1,390 @@
+//! Response
+//!
+//! Everything a handler needs to answer the request on one stream
+
+use std::cell::RefCell;
+use std::io::Read;
+use std::rc::Rc;
+
+use buf::MappedSlice;
+use connection::Connection;
+use connection::error::{ErrorCode, H2Error, PushError};
+use connection::transfer::TransferCount;
+use header::{EntryInner, HeaderEntry, HeaderList};
+
+mod body_writer;
+mod cookie;
+mod gzip;
+mod status;
+mod writer;
+
+pub use self::body_writer::BodyWriter;
+pub use self::cookie::{SameSite, SetCookie};
+pub use self::status::StatusCode;
+pub use self::writer::{ResponseWriter, SentStatus};
+
+/// A complete response, built up with
+///
+/// ```ignore
+/// Response::new(200).header("content-type", "text/html").body(page)
+/// ```
+///
+/// and sent with ResponseWriter::send. The status is a StatusCode
+/// or a u16, which must be three digits.
+pub struct Response {
+    status: StatusCode,
+    headers: Vec<HeaderEntry>,
+    body: Vec<u8>,
+}
+
+impl Response {
+
+    pub fn new<S: Into<StatusCode>>(status: S) -> Self {
+        Response { status: status.into(), headers: Vec::new(), body: Vec::new() }
+    }
+
+    /// add a header field, the name must be lowercase
+    pub fn header<A, B>(mut self, name: A, value: B) -> Self
+        where A: Into<EntryInner>, B: Into<EntryInner> {
+        self.headers.push(HeaderEntry::new(name, value));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// the header list to send (:status first) and the body
+    pub fn into_parts(self) -> (HeaderList, Vec<u8>) {
+        let mut headers = HeaderList::with_capacity(self.headers.len() + 2);
+        headers.add_entry((":status", self.status.to_string()).into());
+        for entry in self.headers {
+            headers.add_entry(entry);
+        }
+        (headers, self.body)
+    }
+}
+
+/// A response that is being sent on a stream of the connection
+pub struct ResponseContext<'conn> {
+    conn: ConnRef<'conn>,
+    stream_id: u32,
+}
+
+// the connection is shared when the request body is read while the
+// response is being sent, since reading it processes more frames
+enum ConnRef<'conn> {
+    Borrowed(&'conn mut Connection),
+    Shared(Rc<RefCell<Connection>>),
+}
+
+impl<'conn> ConnRef<'conn> {
+
+    fn with<T, F: FnOnce(&mut Connection) -> T>(&mut self, f: F) -> T {
+        match *self {
+            ConnRef::Borrowed(ref mut conn) => f(conn),
+            ConnRef::Shared(ref conn) => f(&mut conn.borrow_mut()),
+        }
+    }
+
+    fn reborrow(&mut self) -> ConnRef {
+        match *self {
+            ConnRef::Borrowed(ref mut conn) => ConnRef::Borrowed(conn),
+            ConnRef::Shared(ref conn) => ConnRef::Shared(conn.clone()),
+        }
+    }
+}
+
+/// A stream reserved with ResponseContext::push, the response
+/// for the promised request is sent on it with ResponseContext::pushed
+#[derive(Debug, PartialEq, Eq)]
+pub struct PushedStream {
+    stream_id: u32,
+}
+
+impl PushedStream {
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+}
+
+impl<'conn> ResponseContext<'conn> {
+
+    pub fn new(conn: &'conn mut Connection, stream_id: u32) -> Self {
+        ResponseContext { conn: ConnRef::Borrowed(conn), stream_id: stream_id }
+    }
+
+    /// the context for a stream of a connection that is also
+    /// used elsewhere (only borrowed while sending)
+    pub fn shared(conn: Rc<RefCell<Connection>>, stream_id: u32) -> Self {
+        ResponseContext { conn: ConnRef::Shared(conn), stream_id: stream_id }
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub fn send_headers(&mut self, headers: &HeaderList, end_stream: bool) -> Result<(), H2Error> {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.send_headers(stream_id, headers, end_stream))
+    }
+
+    pub fn send_data(&mut self, data: &[u8], end_stream: bool) -> Result<(), H2Error> {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.send_data(stream_id, data, end_stream))
+    }
+
+    /// send a whole response at once, content-length
+    /// is filled in from the body if it is not set
+    pub fn send_response(&mut self, mut headers: HeaderList, body: &[u8]) -> Result<(), H2Error> {
+        set_content_length(&mut headers, body.len());
+        self.send_headers(&headers, body.is_empty())?;
+        match body.is_empty() {
+            true => Ok(()),
+            false => self.send_data(body, true),
+        }
+    }
+
+    /// send only the headers of a response with a body of length,
+    /// ending the stream (the answer to a HEAD request)
+    pub fn send_head(&mut self, mut headers: HeaderList, length: usize) -> Result<(), H2Error> {
+        set_content_length(&mut headers, length);
+        self.send_headers(&headers, true)
+    }
+
+    /// abandon the response with RST_STREAM
+    pub fn reset(&mut self, error: ErrorCode) {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.reset_stream(stream_id, error))
+    }
+
+    /// add the date and server headers that are not set
+    /// yet, for the start of a response
+    pub fn add_default_headers(&mut self, headers: &mut HeaderList) {
+        let date = headers.get_value_by_name("date").is_none();
+        let server = headers.get_value_by_name("server").is_none();
+        self.conn.with(|conn| {
+            if date {
+                headers.add_entry(("date", conn.date().to_string()).into());
+            }
+            match conn.server() {
+                Some(name) if server => headers.add_entry(("server", name.to_string()).into()),
+                _ => {},
+            }
+        })
+    }
+
+    /// the count of what the response on the stream sends (one that
+    /// stays at nothing if the stream is already gone)
+    pub fn transfer(&mut self) -> TransferCount {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.transfer(stream_id)).unwrap_or_default()
+    }
+
+    /// the largest DATA frame the peer accepts
+    pub fn max_frame_size(&mut self) -> usize {
+        self.conn.with(|conn| conn.remote_settings().max_frame_size as usize)
+    }
+
+    /// send everything body reads as the rest of the response
+    pub fn send_body(&mut self, body: Box<Read>) -> Result<(), H2Error> {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.send_body(stream_id, body))
+    }
+
+    /// send a mapped file as the rest of the response, a slice of the
+    /// mapping at a time
+    pub fn send_mapped(&mut self, body: MappedSlice) -> Result<(), H2Error> {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.send_mapped(stream_id, body))
+    }
+
+    /// end the response with trailers, after all of the data
+    pub fn send_trailers(&mut self, trailers: HeaderList) -> Result<(), H2Error> {
+        let stream_id = self.stream_id;
+        self.conn.with(|conn| conn.send_trailers(stream_id, trailers))
+    }
+
+    /// can responses be pushed to the client, for skipping the work of
+    /// getting pushed resources ready when they can not be
+    pub fn push_enabled(&mut self) -> bool {
+        self.conn.with(|conn| conn.push_enabled())
+    }
+
+    /// promise a response for request_headers (which need at least :method,
+    /// :scheme, :authority and :path) before it is asked for
+    ///
+    /// This should be done before sending the response that refers to the
+    /// pushed resource, so the peer knows not to request it itself.
+    pub fn push(&mut self, request_headers: HeaderList) -> Result<PushedStream, PushError> {
+        if !self.push_enabled() {
+            return Err(PushError::Disabled);
+        }
+        let stream_id = self.stream_id;
+        let stream_id = self.conn.with(|conn| conn.push_promise(stream_id, &request_headers))?;
+        Ok(PushedStream { stream_id: stream_id })
+    }
+
+    /// the context for sending the response on a pushed stream
+    pub fn pushed(&mut self, pushed: &PushedStream) -> ResponseContext {
+        ResponseContext { conn: self.conn.reborrow(), stream_id: pushed.stream_id }
+    }
+}
+
+// 204 and 304 responses do not have a body to give the length of
+// (RFC 7230 3.3.2)
+fn set_content_length(headers: &mut HeaderList, length: usize) {
+    match headers.get_value_by_name(":status") {
+        Some("204") | Some("304") => return,
+        _ => {},
+    }
+    if headers.get_value_by_name("content-length").is_none() {
+        headers.add_entry(("content-length", length.to_string()).into());
+    }
+}
+
+#[cfg(test)]
+mod response_tests {
+
+    use super::ResponseContext;
+    use connection::Connection;
+    use connection::error::PushError;
+    use connection::settings::ENABLE_PUSH;
+    use frame::OwnedFrame;
+    use frame::frame_types::{types, flags, PushPromiseFrame};
+    use header::{Decoder, HeaderList};
+    use test_util::open_stream;
+
+    fn list(entries: &[(&'static str, &'static str)]) -> HeaderList {
+        let mut list = HeaderList::with_capacity(entries.len());
+        for entry in entries {
+            list.add_entry((*entry).into());
+        }
+        list
+    }
+
+    fn style_request() -> HeaderList {
+        list(&[(":method", "GET"), (":scheme", "https"), (":authority", "localhost"), (":path", "/style.css")])
+    }
+
+    #[test]
+    fn push_stylesheet() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        {
+            let mut resp = ResponseContext::new(&mut conn, 1);
+            let pushed = resp.push(style_request()).unwrap();
+            assert_eq!(pushed.stream_id(), 2);
+
+            resp.send_headers(&list(&[(":status", "200"), ("content-type", "text/html")]), false).unwrap();
+            resp.send_data(b"<link rel=stylesheet href=/style.css>", true).unwrap();
+
+            let mut push_resp = resp.pushed(&pushed);
+            push_resp.send_headers(&list(&[(":status", "200"), ("content-type", "text/css")]), false).unwrap();
+            push_resp.send_data(b"body {}", true).unwrap();
+        }
+
+        let mut frames = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            frames.push(frame);
+        }
+        let order: Vec<_> = frames.iter().map(|f| (f.frame_type(), f.stream_id())).collect();
+        assert_eq!(order, vec![
+            (types::PUSH_PROMISE, 1),
+            (types::HEADERS, 1),
+            (types::DATA, 1),
+            (types::HEADERS, 2),
+            (types::DATA, 2),
+        ]);
+
+        // the promise refers to the even pushed stream and carries the request
+        let promise: PushPromiseFrame = frames[0].as_frame().into();
+        let (promised_id, block) = promise.get_push_data().unwrap();
+        assert_eq!(promised_id, 2);
+        let request = Decoder::new(4096, 10).get_header_list(block).unwrap();
+        assert_eq!(request.get_value_by_name(":path"), Some("/style.css"));
+
+        // both streams are done
+        assert!(conn.stream(1).is_none());
+        assert!(conn.stream(2).is_none());
+    }
+
+    #[test]
+    fn push_ids_are_even() {
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+        open_stream(&mut conn, 3);
+
+        let ids: Vec<u32> = [1, 3, 3].iter()
+            .map(|id| ResponseContext::new(&mut conn, *id).push(style_request()).unwrap().stream_id())
+            .collect();
+        assert_eq!(ids, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn push_refused() {
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+
+        {
+            let mut resp = ResponseContext::new(&mut conn, 1);
+            let post = list(&[(":method", "POST"), (":scheme", "https"), (":authority", "localhost"), (":path", "/form")]);
+            assert_eq!(resp.push(post), Err(PushError::NotCacheable));
+        }
+
+        let mut settings = OwnedFrame::settings(&[(ENABLE_PUSH, 0)]);
+        conn.dispatch_frame(settings.as_frame()).unwrap();
+        while conn.next_outbound().is_some() {}
+
+        let mut resp = ResponseContext::new(&mut conn, 1);
+        assert!(!resp.push_enabled());
+        assert_eq!(resp.push(style_request()), Err(PushError::Disabled));
+        assert!(conn.next_outbound().is_none());
+    }
+
+    #[test]
+    fn push_setting_changes() {
+        fn set_push(conn: &mut Connection, on: u32) {
+            let mut settings = OwnedFrame::settings(&[(ENABLE_PUSH, on)]);
+            conn.dispatch_frame(settings.as_frame()).unwrap();
+            while conn.next_outbound().is_some() {}
+        }
+
+        let mut conn = Connection::new();
+        open_stream(&mut conn, 1);
+        open_stream(&mut conn, 3);
+
+        set_push(&mut conn, 0);
+        assert_eq!(ResponseContext::new(&mut conn, 1).push(style_request()), Err(PushError::Disabled));
+
+        // turned back on, the next push goes through
+        set_push(&mut conn, 1);
+        let mut resp = ResponseContext::new(&mut conn, 3);
+        assert!(resp.push_enabled());
+        assert_eq!(resp.push(style_request()).unwrap().stream_id(), 2);
+    }
+
+    #[test]
+    fn content_length_filled_in() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        ResponseContext::new(&mut conn, 1).send_response(list(&[(":status", "200")]), b"hello").unwrap();
+
+        let headers = conn.next_outbound().unwrap();
+        assert_eq!(headers.frame_type(), types::HEADERS);
+        let decoded = Decoder::new(4096, 20).get_header_list(headers.payload()).unwrap();
+        assert_eq!(decoded.get_value_by_name("content-length"), Some("5"));
+
+        let data = conn.next_outbound().unwrap();
+        assert_eq!((data.frame_type(), data.frame_flags(), data.payload()), (types::DATA, flags::END_STREAM, &b"hello"[..]));
+    }
+}
@@ -0,0 +1,150 @@
+//! The response status code (RFC 7231 6)
+
+use std::fmt;
+
+/// The :status of a response
+///
+/// Any three digit code can be sent, so this is only ever 100 to 999.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+// 6.1 Overview of Status Codes (and 7540 for 421)
+static REASONS : &'static [(u16, &'static str)] = &[
+    (100, "Continue"),
+    (101, "Switching Protocols"),
+    (200, "OK"),
+    (201, "Created"),
+    (202, "Accepted"),
+    (203, "Non-Authoritative Information"),
+    (204, "No Content"),
+    (205, "Reset Content"),
+    (206, "Partial Content"),
+    (300, "Multiple Choices"),
+    (301, "Moved Permanently"),
+    (302, "Found"),
+    (303, "See Other"),
+    (304, "Not Modified"),
+    (305, "Use Proxy"),
+    (307, "Temporary Redirect"),
+    (308, "Permanent Redirect"),
+    (400, "Bad Request"),
+    (401, "Unauthorized"),
+    (402, "Payment Required"),
+    (403, "Forbidden"),
+    (404, "Not Found"),
+    (405, "Method Not Allowed"),
+    (406, "Not Acceptable"),
+    (407, "Proxy Authentication Required"),
+    (408, "Request Timeout"),
+    (409, "Conflict"),
+    (410, "Gone"),
+    (411, "Length Required"),
+    (412, "Precondition Failed"),
+    (413, "Payload Too Large"),
+    (414, "URI Too Long"),
+    (415, "Unsupported Media Type"),
+    (416, "Range Not Satisfiable"),
+    (417, "Expectation Failed"),
+    (421, "Misdirected Request"),
+    (426, "Upgrade Required"),
+    (428, "Precondition Required"),
+    (429, "Too Many Requests"),
+    (431, "Request Header Fields Too Large"),
+    (500, "Internal Server Error"),
+    (501, "Not Implemented"),
+    (502, "Bad Gateway"),
+    (503, "Service Unavailable"),
+    (504, "Gateway Timeout"),
+    (505, "HTTP Version Not Supported"),
+];
+
+impl StatusCode {
+
+    pub const CONTINUE : StatusCode = StatusCode(100);
+    pub const OK : StatusCode = StatusCode(200);
+    pub const CREATED : StatusCode = StatusCode(201);
+    pub const NO_CONTENT : StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT : StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY : StatusCode = StatusCode(301);
+    pub const FOUND : StatusCode = StatusCode(302);
+    pub const SEE_OTHER : StatusCode = StatusCode(303);
+    pub const NOT_MODIFIED : StatusCode = StatusCode(304);
+    pub const TEMPORARY_REDIRECT : StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT : StatusCode = StatusCode(308);
+    pub const BAD_REQUEST : StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED : StatusCode = StatusCode(401);
+    pub const FORBIDDEN : StatusCode = StatusCode(403);
+    pub const NOT_FOUND : StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED : StatusCode = StatusCode(405);
+    pub const PRECONDITION_FAILED : StatusCode = StatusCode(412);
+    pub const PAYLOAD_TOO_LARGE : StatusCode = StatusCode(413);
+    pub const RANGE_NOT_SATISFIABLE : StatusCode = StatusCode(416);
+    pub const EXPECTATION_FAILED : StatusCode = StatusCode(417);
+    pub const TOO_MANY_REQUESTS : StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE : StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR : StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED : StatusCode = StatusCode(501);
+    pub const SERVICE_UNAVAILABLE : StatusCode = StatusCode(503);
+
+    /// the status for code, if it is three digits
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        match code {
+            100...999 => Some(StatusCode(code)),
+            _ => None,
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// the reason phrase HTTP/1.1 would send with it, if it is a known code
+    pub fn canonical_reason(&self) -> Option<&'static str> {
+        REASONS.iter()
+            .find(|&&(code, _)| code == self.0)
+            .map(|&(_, reason)| reason)
+    }
+}
+
+/// A code outside 100 to 999 panics, it is a bug in the handler
+/// (and is answered with a 500 when the handler panics on it).
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> Self {
+        match StatusCode::from_u16(code) {
+            Some(status) => status,
+            None => panic!("{} is not a three digit status code", code),
+        }
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod status_tests {
+
+    use super::StatusCode;
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(StatusCode::from(200), StatusCode::OK);
+        assert_eq!(StatusCode::OK.canonical_reason(), Some("OK"));
+        assert_eq!(StatusCode::from(431).canonical_reason(), Some("Request Header Fields Too Large"));
+        assert_eq!(StatusCode::from(599).canonical_reason(), None);
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404");
+
+        assert_eq!(StatusCode::from_u16(100), Some(StatusCode::CONTINUE));
+        assert_eq!(StatusCode::from_u16(999).map(|s| s.as_u16()), Some(999));
+        assert_eq!(StatusCode::from_u16(99), None);
+        assert_eq!(StatusCode::from_u16(1000), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn status_out_of_range() {
+        StatusCode::from(1000);
+    }
+}
@@ -0,0 +1,753 @@
+//! Sending a Response on the stream of the request it answers
+
+use std::cell::{Cell, RefCell};
+use std::io::{Cursor, Read};
+use std::rc::Rc;
+
+use buf::MappedSlice;
+use connection::Connection;
+use connection::error::H2Error;
+use connection::transfer::{Transfer, TransferCount};
+use header::{HeaderEntry, HeaderList};
+
+use super::{BodyWriter, Response, ResponseContext, SetCookie, StatusCode};
+use super::gzip::{Gzip, GzipReader};
+
+// content-types that are compressed already, or not worth it
+static INCOMPRESSIBLE : &'static [&'static str] = &[
+    "image/png", "image/jpeg", "image/gif", "image/webp", "image/x-icon",
+    "video/", "audio/", "font/woff", "application/zip", "application/gzip",
+    "application/x-gzip", "application/pdf", "application/wasm",
+];
+
+/// The status of a response once its headers are sent, from
+/// ResponseWriter::status_sent
+///
+/// A middleware gets this before handing the writer on, and looks at it
+/// after the inner handler returns.
+#[derive(Debug, Clone)]
+pub struct SentStatus(Rc<Cell<Option<StatusCode>>>);
+
+impl SentStatus {
+    /// None until the final (not 1xx) headers went out
+    pub fn get(&self) -> Option<StatusCode> {
+        self.0.get()
+    }
+}
+
+/// Writes the response for one stream
+///
+/// The header list is HPACK encoded with the connection's encoder and
+/// sent in a HEADERS frame, then the body goes out in DATA frames as
+/// the peer's max frame size and the flow control windows allow.
+///
+/// Responses get date and server headers unless they have their own (the
+/// server is set with Connection::set_server). Fields added with add_header
+/// (by a middleware say) go out with the next headers sent, as do cookies
+/// added with add_cookie, as set-cookie fields that are never indexed by
+/// HPACK so they can't be guessed through compression.
+///
+/// The answer to a HEAD request is written just like the one to a GET,
+/// the writer sends the headers (with the content-length of the body) and
+/// drops the body.
+///
+/// With compress(true) bodies are gzipped when the client accepts that
+/// and the content-type is not compressed already. The content-length is
+/// then the compressed length, or left out when the body is streamed.
+///
+/// What the response has sent so far is counted, both the body as it was
+/// written and what went out for it (see transfer).
+pub struct ResponseWriter<'conn> {
+    ctx: ResponseContext<'conn>,
+    head: bool,
+    accepts_gzip: bool,
+    compress: bool,
+    // sent along with the next headers
+    extra: Vec<HeaderEntry>,
+    status: SentStatus,
+    transfer: TransferCount,
+}
+
+impl<'conn> ResponseWriter<'conn> {
+
+    pub fn new(conn: &'conn mut Connection, stream_id: u32) -> Self {
+        ResponseWriter::with_context(ResponseContext::new(conn, stream_id))
+    }
+
+    /// the writer for a stream of a connection that is also used
+    /// elsewhere, as when the request body is still being read
+    pub fn shared(conn: Rc<RefCell<Connection>>, stream_id: u32) -> Self {
+        ResponseWriter::with_context(ResponseContext::shared(conn, stream_id))
+    }
+
+    fn with_context(mut ctx: ResponseContext<'conn>) -> Self {
+        let transfer = ctx.transfer();
+        ResponseWriter { ctx: ctx, head: false, accepts_gzip: false, compress: false, extra: Vec::new(), status: SentStatus(Rc::new(Cell::new(None))), transfer: transfer }
+    }
+
+    /// only send the headers of responses, for a HEAD request
+    pub fn set_head(&mut self, head: bool) {
+        self.head = head;
+    }
+
+    /// whether the response body will be dropped, a handler can
+    /// use this to skip the work of making it
+    pub fn is_head(&self) -> bool {
+        self.head
+    }
+
+    /// the request's accept-encoding allows gzip
+    pub fn set_accepts_gzip(&mut self, accepts: bool) {
+        self.accepts_gzip = accepts;
+    }
+
+    /// gzip the body of the response if the client accepts it
+    pub fn compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.ctx.stream_id()
+    }
+
+    /// send a header field with the response, whatever it is
+    /// (the name must be lowercase)
+    pub fn add_header<V: Into<String>>(&mut self, name: &'static str, value: V) {
+        self.extra.push((name, value.into()).into());
+    }
+
+    /// send a set-cookie header field with the response
+    pub fn add_cookie(&mut self, cookie: SetCookie) {
+        self.extra.push(HeaderEntry::sensitive("set-cookie", cookie.to_string()));
+    }
+
+    /// what will have the status of the response once it is sent, which
+    /// still works after the writer is handed on
+    pub fn status_sent(&self) -> SentStatus {
+        self.status.clone()
+    }
+
+    /// what the response has sent so far (what is held up by flow
+    /// control counts once it is queued), also once the stream is done
+    pub fn transfer(&self) -> Transfer {
+        self.transfer.get()
+    }
+
+    /// the count transfer reads, which still works after the
+    /// writer is handed on
+    pub fn transfer_count(&self) -> TransferCount {
+        self.transfer.clone()
+    }
+
+    // the headers every response gets, plus the ones waiting to go out
+    fn add_headers(&mut self, headers: &mut HeaderList) {
+        let status = headers.get_value_by_name(":status").and_then(|s| s.parse().ok()).and_then(StatusCode::from_u16);
+        self.status.0.set(status);
+        self.ctx.add_default_headers(headers);
+        for entry in self.extra.drain(..) {
+            headers.add_entry(entry);
+        }
+    }
+
+    // should the body of a response with this status and these headers
+    // be gzipped, if so content-length is taken out and content-encoding
+    // added
+    //
+    // Whenever it could be, the response gets vary, whether or not this
+    // client accepts gzip, so a cache does not give the copy it has to a
+    // client that takes the other one. A response that has no body (or an
+    // empty one) is never compressed, gzip would give it one.
+    fn use_gzip(&self, status: StatusCode, headers: &mut Vec<HeaderEntry>, empty: bool) -> bool {
+        if !self.compress {
+            return false;
+        }
+        let mut worth_it = true;
+        let mut empty = empty;
+        for entry in headers.iter() {
+            match entry.name() {
+                "content-encoding" => worth_it = false,
+                "content-type" => worth_it &= !INCOMPRESSIBLE.iter().any(|t| entry.value().starts_with(t)),
+                "content-length" => empty |= entry.value() == "0",
+                _ => {},
+            }
+        }
+        if !worth_it {
+            return false;
+        }
+        if !headers.iter().any(|e| e.name() == "vary" && e.value().contains("accept-encoding")) {
+            headers.push(("vary", "accept-encoding").into());
+        }
+        let has_body = match status.as_u16() {
+            100...199 | 204 | 304 => false,
+            _ => !empty,
+        };
+        if !self.accepts_gzip || !has_body {
+            return false;
+        }
+        headers.retain(|e| e.name() != "content-length");
+        headers.push(("content-encoding", "gzip").into());
+        self.transfer.set_compressed();
+        true
+    }
+
+    /// tell a client waiting on "expect: 100-continue" to send the body
+    /// with an interim 100 response, the final response follows as usual
+    pub fn send_continue(&mut self) -> Result<(), H2Error> {
+        let mut headers = HeaderList::with_capacity(1);
+        headers.add_entry((":status", "100").into());
+        self.ctx.send_headers(&headers, false)
+    }
+
+    /// send the whole response, END_STREAM is set on the last DATA
+    /// frame (or on the HEADERS frame when there is no body)
+    pub fn send(&mut self, mut response: Response) -> Result<(), H2Error> {
+        let empty = response.body.is_empty();
+        if self.use_gzip(response.status(), &mut response.headers, empty) {
+            if !self.head {
+                self.transfer.add_body(response.body.len());
+            }
+            let mut gzip = Gzip::new();
+            let mut body = gzip.compress(&response.body);
+            body.extend(gzip.finish());
+            response.body = body;
+        }
+        let (mut headers, body) = response.into_parts();
+        self.add_headers(&mut headers);
+        match self.head {
+            true => self.ctx.send_head(headers, body.len()),
+            false => self.ctx.send_response(headers, &body),
+        }
+    }
+
+    /// send the status and headers (without :status) and
+    /// write the body as it is made with the returned writer
+    ///
+    /// For HEAD the headers are held back until the writer is finished,
+    /// so content-length can be set from what was written.
+    pub fn start<'w, S: Into<StatusCode>>(&'w mut self, status: S, headers: HeaderList) -> Result<BodyWriter<'w, 'conn>, H2Error> {
+        let status = status.into();
+        let mut entries: Vec<HeaderEntry> = headers.iter().cloned().collect();
+        let gzip = match self.use_gzip(status, &mut entries, false) {
+            true => Some(Gzip::new()),
+            false => None,
+        };
+        let mut list = HeaderList::with_capacity(entries.len() + 1);
+        list.add_entry((":status", status.to_string()).into());
+        for entry in entries {
+            list.add_entry(entry);
+        }
+        self.add_headers(&mut list);
+        if self.head {
+            return Ok(BodyWriter::head(&mut self.ctx, list, gzip));
+        }
+        self.ctx.send_headers(&list, false)?;
+        Ok(BodyWriter::new(&mut self.ctx, gzip))
+    }
+
+    /// send the response with everything body reads as its body instead
+    /// of the body it was built with (which should be left empty)
+    ///
+    /// The body is read a frame at a time as the flow control windows
+    /// allow, and should have its length set in content-length if it is
+    /// known.
+    pub fn send_reader<R: Read + 'static>(&mut self, mut response: Response, body: R) -> Result<(), H2Error> {
+        let gzip = self.use_gzip(response.status(), &mut response.headers, false);
+        let (mut headers, _) = response.into_parts();
+        self.add_headers(&mut headers);
+        if self.head {
+            return self.ctx.send_headers(&headers, true);
+        }
+        self.ctx.send_headers(&headers, false)?;
+        match gzip {
+            true => self.ctx.send_body(Box::new(GzipReader::counted(body, self.transfer.clone()))),
+            false => self.ctx.send_body(Box::new(body)),
+        }
+    }
+
+    /// send the status and headers (without :status) with everything
+    /// reader reads as the body
+    ///
+    /// The reader is read a frame at a time into pooled buffers as the
+    /// peer's max frame size and the flow control windows allow, and is
+    /// dropped if the stream is reset. With len the response has that
+    /// content-length (any in headers is replaced) and the body has to be
+    /// exactly that long, without it there is no content-length. A read
+    /// that fails part way through, or a reader that comes up short of
+    /// len, resets the stream with INTERNAL_ERROR, as the headers are
+    /// already out by then.
+    pub fn stream_from<S, R>(&mut self, status: S, headers: HeaderList, reader: R, len: Option<u64>) -> Result<(), H2Error>
+        where S: Into<StatusCode>, R: Read + 'static {
+        let mut response = Response::new(status);
+        response.headers.extend(headers.iter().filter(|e| e.name() != "content-length").cloned());
+        if let Some(len) = len {
+            response.headers.push(("content-length", len.to_string()).into());
+        }
+        self.send_reader(response, reader)
+    }
+
+    /// send the status and headers (without :status) with a mapped file
+    /// as the body, with its length as the content-length
+    ///
+    /// The DATA frames are slices of the mapping, written from it without
+    /// a copy. A body that is gzipped is read from the mapping instead.
+    pub fn stream_mapped<S: Into<StatusCode>>(&mut self, status: S, headers: HeaderList, body: MappedSlice) -> Result<(), H2Error> {
+        let mut response = Response::new(status);
+        response.headers.extend(headers.iter().filter(|e| e.name() != "content-length").cloned());
+        response.headers.push(("content-length", body.len().to_string()).into());
+        let gzip = self.use_gzip(response.status(), &mut response.headers, body.len() == 0);
+        let (mut headers, _) = response.into_parts();
+        self.add_headers(&mut headers);
+        if self.head {
+            return self.ctx.send_headers(&headers, true);
+        }
+        self.ctx.send_headers(&headers, false)?;
+        match gzip {
+            true => self.ctx.send_body(Box::new(GzipReader::counted(Cursor::new(body), self.transfer.clone()))),
+            false => self.ctx.send_mapped(body),
+        }
+    }
+
+    /// end the response with trailers after all of the data
+    /// (for HEAD the stream already ended with the headers)
+    pub fn send_trailers(&mut self, trailers: HeaderList) -> Result<(), H2Error> {
+        if self.head {
+            return Ok(());
+        }
+        self.ctx.send_trailers(trailers)
+    }
+
+    /// the lower level context for the stream (for pushing responses)
+    pub fn context(&mut self) -> &mut ResponseContext<'conn> {
+        &mut self.ctx
+    }
+}
+
+#[cfg(test)]
+mod writer_tests {
+
+    use std::io::{self, Cursor, Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    use super::ResponseWriter;
+    use connection::Connection;
+    use connection::error::ErrorCode;
+    use connection::event::Event;
+    use connection::handshake::PREFACE;
+    use connection::mock::MockStream;
+    use connection::reader::FrameReader;
+    use connection::transfer::Transfer;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags};
+    use header::{Decoder, HeaderEntry, HeaderList};
+    use request::Request;
+    use response::{Response, SetCookie};
+    use response::gzip::gunzip;
+    use server::LogRecord;
+    use test_util::{open_stream, GET_BLOCK};
+
+    #[test]
+    fn serve_request() {
+        let mut input = PREFACE.to_vec();
+        input.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        input.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        let mut stream = MockStream::new(input);
+
+        let page = vec![b'x'; 40000];
+        Connection::run(&mut stream, false, |conn, event| {
+            if let Event::Headers { stream_id, headers, .. } = event {
+                let req = Request::from_header_list(headers).unwrap();
+                assert_eq!(req.path(), "/");
+                let response = Response::new(200).header("content-type", "text/html").body(page.clone());
+                ResponseWriter::new(conn, stream_id).send(response).unwrap();
+            }
+        }).unwrap();
+
+        let mut decoder = Decoder::new(4096, 20);
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(stream.output);
+        let mut body = Vec::new();
+        let mut status = None;
+        let mut end_stream = false;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match frame.get_type() {
+                types::HEADERS => {
+                    let headers = decoder.get_header_list(frame.payload()).unwrap();
+                    status = headers.get_value_by_name(":status").map(|s| s.to_string());
+                    assert_eq!(headers.get_value_by_name("content-type"), Some("text/html"));
+                    assert_eq!(headers.get_value_by_name("content-length"), Some("40000"));
+                },
+                types::DATA => {
+                    assert!(frame.get_length() <= 16384);
+                    body.extend_from_slice(frame.payload());
+                    end_stream = frame.get_flags() & flags::END_STREAM != 0;
+                },
+                _ => {},
+            }
+        }
+
+        assert_eq!(status, Some("200".to_string()));
+        assert_eq!(body, page);
+        assert!(end_stream);
+    }
+
+    // answer a GET with respond, with the writer set to compress and the
+    // client accepting gzip or not, returning the headers and the body
+    fn compressed<F>(accepts_gzip: bool, respond: F) -> (HeaderList, Vec<u8>)
+        where F: FnOnce(&mut ResponseWriter) {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+        {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            resp.set_accepts_gzip(accepts_gzip);
+            resp.compress(true);
+            respond(&mut resp);
+        }
+        let headers = Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap();
+        let mut body = Vec::new();
+        while let Some(frame) = conn.next_outbound() {
+            body.extend_from_slice(frame.payload());
+        }
+        (headers, body)
+    }
+
+    #[test]
+    fn gzip_responses() {
+        let page: Vec<u8> = (0..200).flat_map(|i| format!("<li>item {}</li>\n", i).into_bytes()).collect();
+
+        let html = page.clone();
+        let (headers, body) = compressed(true, move |resp| {
+            resp.send(Response::new(200).header("content-type", "text/html").body(html)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), Some("gzip"));
+        assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+        assert_eq!(headers.get_value_by_name("content-length"), Some(&*body.len().to_string()));
+        assert!(body.len() < page.len() / 2);
+        assert_eq!(gunzip(&body), page);
+
+        // not accepted (as with gzip;q=0), or not worth it
+        let html = page.clone();
+        let (headers, body) = compressed(false, move |resp| {
+            resp.send(Response::new(200).header("content-type", "text/html").body(html)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), None);
+        // it could have been, a cache has to know that
+        assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+        assert_eq!(body, page);
+        let png = page.clone();
+        let (headers, body) = compressed(true, move |resp| {
+            resp.send(Response::new(200).header("content-type", "image/png").body(png)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), None);
+        assert_eq!(body, page);
+
+        // streamed bodies have no content-length
+        let text = page.clone();
+        let (headers, body) = compressed(true, move |resp| {
+            let mut writer = resp.start(200, HeaderList::with_capacity(0)).unwrap();
+            writer.write_all(&text[..1000]).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(&text[1000..]).unwrap();
+            writer.finish().unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), Some("gzip"));
+        assert_eq!(headers.get_value_by_name("content-length"), None);
+        assert_eq!(gunzip(&body), page);
+
+        let text = page.clone();
+        let (headers, body) = compressed(true, move |resp| {
+            let response = Response::new(200).header("content-length", text.len().to_string());
+            resp.send_reader(response, Cursor::new(text)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-length"), None);
+        assert_eq!(gunzip(&body), page);
+    }
+
+    #[test]
+    fn gzip_without_body() {
+        // a 304 and a 204 stay without one
+        for &status in &[304, 204] {
+            let (headers, body) = compressed(true, move |resp| {
+                resp.send(Response::new(status).header("content-type", "text/html")).unwrap();
+            });
+            assert_eq!(headers.get_value_by_name("content-encoding"), None);
+            assert_eq!(headers.get_value_by_name("content-length"), None);
+            assert_eq!(headers.get_value_by_name("vary"), Some("accept-encoding"));
+            assert!(body.is_empty());
+        }
+
+        // as does an empty 200, however it is sent
+        let (headers, body) = compressed(true, |resp| {
+            resp.send(Response::new(200).header("content-type", "text/html")).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), None);
+        assert_eq!(headers.get_value_by_name("content-length"), Some("0"));
+        assert!(body.is_empty());
+        let (headers, body) = compressed(true, |resp| {
+            resp.send_reader(Response::new(200).header("content-length", "0"), Cursor::new(Vec::new())).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-encoding"), None);
+        assert!(body.is_empty());
+    }
+
+    // a reader that fails after giving len bytes
+    struct FailsAfter {
+        len: usize,
+    }
+
+    impl Read for FailsAfter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.len == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk went away"));
+            }
+            let n = ::std::cmp::min(self.len, buf.len());
+            for b in &mut buf[..n] {
+                *b = b'x';
+            }
+            self.len -= n;
+            Ok(n)
+        }
+    }
+
+    // answer a GET with respond, giving the peer all the window it wants,
+    // returning the headers, the body and the frames after the headers
+    // as (type, flags, the error code of a RST_STREAM)
+    fn streamed<F>(respond: F) -> (HeaderList, Vec<u8>, Vec<(u8, u8, Option<u32>)>)
+        where F: FnOnce(&mut ResponseWriter) {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+        respond(&mut ResponseWriter::new(&mut conn, 1));
+
+        let headers = Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap();
+        let mut body = Vec::new();
+        let mut frames = Vec::new();
+        loop {
+            while let Some(frame) = conn.next_outbound() {
+                if frame.frame_type() == types::DATA {
+                    assert!(frame.payload().len() <= 16384);
+                    body.extend_from_slice(frame.payload());
+                }
+                let code = match frame.frame_type() {
+                    types::RST_STREAM => Some(frame.payload().iter().fold(0, |code, b| code << 8 | *b as u32)),
+                    _ => None,
+                };
+                frames.push((frame.frame_type(), frame.frame_flags(), code));
+            }
+            if conn.stream(1).is_none() {
+                break;
+            }
+            for id in &[0, 1] {
+                let mut update = OwnedFrame::window_update(*id, 1 << 20);
+                conn.dispatch_frame(update.as_frame()).unwrap();
+            }
+        }
+        (headers, body, frames)
+    }
+
+    #[test]
+    fn stream_from_reader() {
+        let page: Vec<u8> = (0..3 << 20).map(|i| (i * 7 % 251) as u8).collect();
+
+        let body = page.clone();
+        let (headers, sent, frames) = streamed(move |resp| {
+            let mut headers = HeaderList::with_capacity(2);
+            headers.add_entry(("content-type", "application/octet-stream").into());
+            // replaced by the length given
+            headers.add_entry(("content-length", "7").into());
+            resp.stream_from(200, headers, Cursor::new(body), Some(3 << 20)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name(":status"), Some("200"));
+        assert_eq!(headers.get_value_by_name("content-length"), Some("3145728"));
+        assert!(sent == page);
+        assert_eq!(frames.last(), Some(&(types::DATA, flags::END_STREAM, None)));
+
+        // no length, no content-length
+        let body = page[..1000].to_vec();
+        let (headers, sent, _) = streamed(move |resp| {
+            resp.stream_from(200, HeaderList::with_capacity(0), Cursor::new(body), None).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-length"), None);
+        assert_eq!(sent, &page[..1000]);
+    }
+
+    #[test]
+    fn stream_from_failed_read() {
+        let (headers, sent, frames) = streamed(|resp| {
+            resp.stream_from(200, HeaderList::with_capacity(0), FailsAfter { len: 50000 }, Some(100000)).unwrap();
+        });
+        assert_eq!(headers.get_value_by_name("content-length"), Some("100000"));
+        assert_eq!(sent.len(), 50000);
+        // the body is not passed off as complete
+        assert!(frames.iter().all(|&(_, f_flags, _)| f_flags & flags::END_STREAM == 0));
+        assert_eq!(frames.last(), Some(&(types::RST_STREAM, 0, Some(ErrorCode::InternalError as u32))));
+
+        // coming up short of the length is no better
+        let (_, _, frames) = streamed(|resp| {
+            resp.stream_from(200, HeaderList::with_capacity(0), Cursor::new(vec![0; 10]), Some(20)).unwrap();
+        });
+        assert_eq!(frames, vec![(types::DATA, 0, None), (types::RST_STREAM, 0, Some(ErrorCode::InternalError as u32))]);
+    }
+
+    #[test]
+    fn default_headers() {
+        let send = |response: Response, server: Option<&str>| {
+            let mut conn = Connection::new();
+            conn.set_server(server.map(|s| s.to_string()));
+            conn.next_outbound(); // preface
+            open_stream(&mut conn, 1);
+            ResponseWriter::new(&mut conn, 1).send(response).unwrap();
+            Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap()
+        };
+
+        let headers = send(Response::new(200), Some("kurisu"));
+        let date = headers.get_value_by_name("date").unwrap();
+        assert_eq!(date.len(), 29);
+        assert!(date.ends_with(" GMT"));
+        assert_eq!(headers.get_value_by_name("server"), Some("kurisu"));
+
+        // what the handler set is kept, and not sent twice
+        let response = Response::new(200)
+            .header("date", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .header("server", "custom");
+        let headers = send(response, Some("kurisu"));
+        let dates: Vec<&str> = headers.iter().filter(|h| h.name() == "date").map(|h| h.value()).collect();
+        assert_eq!(dates, vec!["Sun, 06 Nov 1994 08:49:37 GMT"]);
+        assert_eq!(headers.get_value_by_name("server"), Some("custom"));
+
+        let headers = send(Response::new(200), None);
+        assert!(headers.get_value_by_name("date").is_some());
+        assert_eq!(headers.get_value_by_name("server"), None);
+    }
+
+    #[test]
+    fn set_cookies() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        {
+            let mut writer = ResponseWriter::new(&mut conn, 1);
+            writer.add_cookie(SetCookie::new("id", "abc").path("/").http_only());
+            writer.add_cookie(SetCookie::new("theme", "dark"));
+            writer.send(Response::new(200)).unwrap();
+        }
+
+        let headers = Decoder::new(4096, 20).get_header_list(conn.next_outbound().unwrap().payload()).unwrap();
+        let cookies: Vec<&HeaderEntry> = headers.iter().filter(|h| h.name() == "set-cookie").collect();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].value(), "id=abc; Path=/; HttpOnly");
+        assert_eq!(cookies[1].value(), "theme=dark");
+        assert!(cookies.iter().all(|c| c.is_sensitive()));
+    }
+
+    #[test]
+    fn empty_body_ends_with_headers() {
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        open_stream(&mut conn, 1);
+
+        ResponseWriter::new(&mut conn, 1).send(Response::new(204)).unwrap();
+
+        let headers = conn.next_outbound().unwrap();
+        assert_eq!(headers.frame_type(), types::HEADERS);
+        assert_eq!(headers.frame_flags(), flags::END_HEADERS | flags::END_STREAM);
+        let list = Decoder::new(4096, 20).get_header_list(headers.payload()).unwrap();
+        assert_eq!(list.get_value_by_name(":status"), Some("204"));
+        assert!(conn.next_outbound().is_none());
+        assert!(conn.stream(1).is_none());
+    }
+
+    // answer a GET with respond as compressed does, giving the peer all
+    // the window it wants, returning what the writer and the access log
+    // counted and what the frames sent add up to: the header frames, the
+    // DATA and the body (gunzipped when it was gzipped)
+    fn counted<F>(accepts_gzip: bool, respond: F) -> (Transfer, LogRecord, Transfer)
+        where F: FnOnce(&mut ResponseWriter) {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let captured = records.clone();
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        conn.set_access_log(Some(Arc::new(move |rec: &LogRecord| captured.lock().unwrap().push(rec.clone()))));
+        open_stream(&mut conn, 1);
+        let count = {
+            let mut resp = ResponseWriter::new(&mut conn, 1);
+            resp.set_accepts_gzip(accepts_gzip);
+            resp.compress(true);
+            respond(&mut resp);
+            resp.transfer_count()
+        };
+
+        let mut sent = Transfer::default();
+        let mut body = Vec::new();
+        let mut gzipped = false;
+        loop {
+            while let Some(frame) = conn.next_outbound() {
+                match frame.frame_type() {
+                    types::HEADERS => {
+                        sent.header_bytes += frame.as_bytes().len() as u64;
+                        let headers = Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap();
+                        gzipped |= headers.get_value_by_name("content-encoding") == Some("gzip");
+                    },
+                    types::DATA => {
+                        sent.data_bytes += frame.payload().len() as u64;
+                        body.extend_from_slice(frame.payload());
+                    },
+                    _ => {},
+                }
+            }
+            if conn.stream(1).is_none() {
+                break;
+            }
+            for id in &[0, 1] {
+                let mut update = OwnedFrame::window_update(*id, 1 << 20);
+                conn.dispatch_frame(update.as_frame()).unwrap();
+            }
+        }
+        sent.body_bytes = match gzipped {
+            true => gunzip(&body).len() as u64,
+            false => body.len() as u64,
+        };
+        let record = records.lock().unwrap().pop().unwrap();
+        (count.get(), record, sent)
+    }
+
+    #[test]
+    fn transfer_counts() {
+        let page: Vec<u8> = (0..5000).flat_map(|i| format!("<li>item {}</li>\n", i).into_bytes()).collect();
+
+        let html = page.clone();
+        let plain = counted(false, move |resp| {
+            resp.send(Response::new(200).header("content-type", "text/html").body(html)).unwrap();
+        });
+        let html = page.clone();
+        let gzipped = counted(true, move |resp| {
+            resp.send(Response::new(200).header("content-type", "text/html").body(html)).unwrap();
+        });
+        let text = page.clone();
+        let written = counted(true, move |resp| {
+            let mut writer = resp.start(200, HeaderList::with_capacity(0)).unwrap();
+            for chunk in text.chunks(7000) {
+                writer.write_all(chunk).unwrap();
+            }
+            assert_eq!(writer.bytes_written(), text.len() as u64);
+            writer.finish().unwrap();
+        });
+        let text = page.clone();
+        let read = counted(true, move |resp| {
+            resp.send_reader(Response::new(200), Cursor::new(text)).unwrap();
+        });
+
+        assert_eq!(plain.0.data_bytes, page.len() as u64);
+        assert!(gzipped.0.data_bytes < page.len() as u64 / 2);
+        assert!(written.0.data_bytes < page.len() as u64 / 2);
+        for &(transfer, ref record, sent) in &[plain, gzipped, written, read] {
+            // the counts are what the frames add up to
+            assert_eq!(transfer, sent);
+            assert_eq!(transfer.body_bytes, page.len() as u64);
+            assert_eq!(transfer.wire_bytes(), sent.header_bytes + sent.data_bytes);
+            assert_eq!((record.response_header_bytes, record.response_body_bytes, record.response_bytes),
+                       (sent.header_bytes, sent.body_bytes, sent.data_bytes));
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! A record of every request, made once its stream is done with
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::str;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use util::http_date;
+
+/// One request and how it went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// who sent it, if known
+    pub peer: Option<SocketAddr>,
+    pub stream_id: u32,
+    pub method: String,
+    /// as it was sent, with the query
+    pub path: String,
+    /// None if no response was started
+    pub status: Option<u16>,
+    /// the DATA each way, not counting padding
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// the HEADERS and CONTINUATION frames of the response (trailers too)
+    pub response_header_bytes: u64,
+    /// the response body as the handler wrote it, which is more than
+    /// response_bytes when it was compressed
+    pub response_body_bytes: u64,
+    /// when the request came in, and how long until the stream was done
+    pub time: SystemTime,
+    pub duration: Duration,
+    /// the stream was reset, or the connection closed before it was done
+    pub aborted: bool,
+}
+
+/// Where the records go, shared by every connection of the server
+pub trait AccessLog: Send + Sync {
+    fn log(&self, rec: &LogRecord);
+}
+
+impl<F> AccessLog for F where F: Fn(&LogRecord) + Send + Sync {
+    fn log(&self, rec: &LogRecord) {
+        self(rec)
+    }
+}
+
+/// Writes records as lines of the Common Log Format
+///
+/// ```text
+/// 127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET /index.html HTTP/2.0" 200 2326
+/// ```
+pub struct CommonLogFormat<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> CommonLogFormat<W> {
+
+    pub fn new(out: W) -> Self {
+        CommonLogFormat { out: Mutex::new(out) }
+    }
+
+    /// the line for the record, without the newline
+    pub fn format(rec: &LogRecord) -> String {
+        // "Sun, 06 Nov 1994 08:49:37 GMT" has all the parts of "06/Nov/1994:08:49:37"
+        let date = http_date(rec.time);
+        let date = str::from_utf8(&date).unwrap();
+        let host = rec.peer.map_or("-".to_string(), |p| p.ip().to_string());
+        let status = rec.status.map_or("-".to_string(), |s| s.to_string());
+        let bytes = match rec.response_bytes {
+            0 => "-".to_string(),
+            n => n.to_string(),
+        };
+        format!("{} - - [{}/{}/{}:{} +0000] \"{} {} HTTP/2.0\" {} {}",
+                host, &date[5..7], &date[8..11], &date[12..16], &date[17..25],
+                rec.method, rec.path, status, bytes)
+    }
+}
+
+impl<W: Write + Send> AccessLog for CommonLogFormat<W> {
+    fn log(&self, rec: &LogRecord) {
+        let mut out = match self.out.lock() {
+            Ok(out) => out,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let _ = writeln!(out, "{}", CommonLogFormat::<W>::format(rec));
+    }
+}
+
+#[cfg(test)]
+mod access_log_tests {
+
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use super::{AccessLog, CommonLogFormat, LogRecord};
+    use connection::Connection;
+    use connection::error::ErrorCode;
+    use frame::OwnedFrame;
+    use frame::frame_types::flags;
+    use header::{Encoder, HeaderList};
+    use response::{Response, ResponseWriter};
+    use util::{Clock, MockClock};
+
+    fn record() -> LogRecord {
+        LogRecord {
+            peer: Some("127.0.0.1:50000".parse().unwrap()),
+            stream_id: 1,
+            method: "GET".to_string(),
+            path: "/index.html?q=1".to_string(),
+            status: Some(200),
+            request_bytes: 0,
+            response_bytes: 2326,
+            response_header_bytes: 120,
+            response_body_bytes: 2326,
+            time: UNIX_EPOCH + Duration::from_secs(784111777),
+            duration: Duration::from_millis(5),
+            aborted: false,
+        }
+    }
+
+    #[test]
+    fn common_log_format() {
+        let log = CommonLogFormat::new(Vec::new());
+        log.log(&record());
+        log.log(&LogRecord { peer: None, status: None, response_bytes: 0, .. record() });
+
+        let out = log.out.into_inner().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?q=1 HTTP/2.0\" 200 2326\n\
+                    - - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?q=1 HTTP/2.0\" - -\n");
+    }
+
+    // a connection logging to the returned records, with a clock
+    // that moves 10ms every time it is read
+    fn logged() -> (Connection, Arc<Mutex<Vec<LogRecord>>>) {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let captured = records.clone();
+        let mut conn = Connection::new();
+        conn.next_outbound(); // preface
+        // acknowledged, so nothing else reads the clock
+        conn.dispatch_frame(OwnedFrame::settings_ack().as_frame()).unwrap();
+        conn.set_access_log(Some(Arc::new(move |rec: &LogRecord| captured.lock().unwrap().push(rec.clone()))));
+        conn.set_peer_addr(Some("10.0.0.1:4000".parse::<SocketAddr>().unwrap()));
+        conn.set_clock(Arc::new(Ticking(MockClock::new())));
+        (conn, records)
+    }
+
+    struct Ticking(MockClock);
+
+    impl Clock for Ticking {
+        fn now(&self) -> Instant {
+            self.0.advance(Duration::from_millis(10));
+            self.0.now()
+        }
+
+        fn system_now(&self) -> SystemTime {
+            self.0.system_now()
+        }
+    }
+
+    fn request(conn: &mut Connection, stream_id: u32, method: &'static str, path: &'static str, end_stream: bool) {
+        let mut list = HeaderList::with_capacity(3);
+        list.add_entry((":method", method).into());
+        list.add_entry((":scheme", "https").into());
+        list.add_entry((":path", path).into());
+        let block = Encoder::new(4096, 20).encode_header_list(&list);
+        let f_flags = if end_stream { flags::END_HEADERS | flags::END_STREAM } else { flags::END_HEADERS };
+        let mut frame = OwnedFrame::headers(stream_id, &block, f_flags);
+        conn.dispatch_frame(frame.as_frame()).unwrap();
+    }
+
+    #[test]
+    fn records_from_connection() {
+        let (mut conn, records) = logged();
+
+        request(&mut conn, 1, "POST", "/upload?x=1", false);
+        let mut data = OwnedFrame::data(1, b"twelve bytes", true);
+        conn.dispatch_frame(data.as_frame()).unwrap();
+        assert!(records.lock().unwrap().is_empty());
+        ResponseWriter::new(&mut conn, 1).send(Response::new(201).body("created")).unwrap();
+
+        {
+            let records = records.lock().unwrap();
+            assert_eq!(records.len(), 1);
+            let rec = &records[0];
+            assert_eq!(rec.peer, Some("10.0.0.1:4000".parse().unwrap()));
+            assert_eq!(rec.stream_id, 1);
+            assert_eq!(rec.method, "POST");
+            assert_eq!(rec.path, "/upload?x=1");
+            assert_eq!(rec.status, Some(201));
+            assert_eq!(rec.request_bytes, 12);
+            assert_eq!(rec.response_bytes, 7);
+            assert_eq!(rec.duration, Duration::from_millis(10));
+            assert!(!rec.aborted);
+        }
+
+        // reset by the peer before the response
+        request(&mut conn, 3, "GET", "/slow", true);
+        let mut rst = OwnedFrame::rst_stream(3, ErrorCode::Cancel as u32);
+        conn.dispatch_frame(rst.as_frame()).unwrap();
+        // the connection closing with a stream still open
+        request(&mut conn, 5, "GET", "/never", true);
+        drop(conn);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!((records[1].stream_id, records[1].status, records[1].aborted), (3, None, true));
+        assert_eq!((records[2].stream_id, records[2].path.as_str(), records[2].aborted), (5, "/never", true));
+    }
+}
@@ -0,0 +1,554 @@
+//! How the server is set up
+//!
+//! Everything is set with a builder, which checks the values make sense
+//! together before there is a Config to start a server with:
+//!
+//! ```ignore
+//! Config::builder()
+//!     .addr("0.0.0.0:443")
+//!     .certs("server.crt", "server.key")
+//!     .max_concurrent_streams(256)
+//!     .build()
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use connection::budget::DEFAULT_MEMORY_BUDGET;
+use connection::limits::{StreamLimits, DEFAULT_HEADER_BLOCK_TIMEOUT};
+use connection::observer::ConnectionObserver;
+use connection::settings::{Settings, DEFAULT_SETTINGS_TIMEOUT, MAX_FRAME_SIZE_LIMIT, MAX_WINDOW_SIZE, MIN_FRAME_SIZE_LIMIT};
+use connection::trace::Tracer;
+use connection::window::{WindowUpdates, DEFAULT_STALL_WARNING};
+use frame::PaddingPolicy;
+use handler::TunnelHandler;
+use util::{Clock, SystemClock};
+
+use super::{AccessLog, Saturated};
+
+// the longest a connection goes without checking for a shutdown
+const SHUTDOWN_CHECK_MS : u64 = 500;
+
+/// What can be wrong with the values given to a ConfigBuilder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// SETTINGS_MAX_FRAME_SIZE has to be between 2^14 and 2^24-1
+    MaxFrameSize(u32),
+    /// SETTINGS_INITIAL_WINDOW_SIZE can be at most 2^31-1
+    InitialWindowSize(u32),
+    NoWorkers,
+    /// connections can not wait on a full queue that holds nothing
+    NoQueue,
+    /// certificates were given but TLS support was not built in
+    NoTls,
+    /// a connection limit of 0 would refuse everyone
+    NoConnections,
+    /// a window update threshold over 100% is never reached
+    UpdateThreshold(u8),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::MaxFrameSize(size) => write!(f, "max frame size {} is not between 2^14 and 2^24-1", size),
+            ConfigError::InitialWindowSize(size) => write!(f, "initial window size {} is above 2^31-1", size),
+            ConfigError::NoWorkers => write!(f, "the server needs at least one worker"),
+            ConfigError::NoQueue => write!(f, "blocking when saturated needs room for at least one queued connection"),
+            ConfigError::NoTls => write!(f, "built without TLS support, run with h2c"),
+            ConfigError::NoConnections => write!(f, "the connection limits need to let at least one in"),
+            ConfigError::UpdateThreshold(percent) => write!(f, "window update threshold {}% is above 100%", percent),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        "Error: ConfigError"
+    }
+}
+
+/// How the server listens and how its connections are served
+#[derive(Clone)]
+pub struct Config {
+    addr: String,
+    certs: Option<(String, String)>,
+    settings: Settings,
+    access_log: Option<Arc<AccessLog>>,
+    workers: usize,
+    max_queued: usize,
+    saturated: Saturated,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    handshake_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    header_block_timeout: Duration,
+    settings_timeout: Duration,
+    stream_limits: StreamLimits,
+    memory_budget: usize,
+    window_updates: WindowUpdates,
+    stall_warning: Option<Duration>,
+    write_timeout: Option<Duration>,
+    shutdown_grace: Duration,
+    trace: Option<Tracer>,
+    extension_frames: bool,
+    padding: PaddingPolicy,
+    tunnel_handler: Option<Arc<TunnelHandler>>,
+    observer: Option<Arc<ConnectionObserver>>,
+    clock: Arc<Clock>,
+}
+
+impl Config {
+
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder { config: Config::default() }
+    }
+
+    /// where the server listens
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// the certificate and key files, None to serve cleartext (h2c)
+    pub fn certs(&self) -> Option<(&str, &str)> {
+        self.certs.as_ref().map(|&(ref cert, ref key)| (cert.as_str(), key.as_str()))
+    }
+
+    /// the SETTINGS every connection advertises
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// where a record of every request goes once its stream is done
+    pub fn access_log(&self) -> Option<&Arc<AccessLog>> {
+        self.access_log.as_ref()
+    }
+
+    /// how many connections are served at once
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// how many accepted connections can wait for a worker
+    pub fn max_queued(&self) -> usize {
+        self.max_queued
+    }
+
+    /// what to do with a connection when that many are waiting,
+    /// a refused connection is closed right away
+    pub fn saturated(&self) -> Saturated {
+        self.saturated
+    }
+
+    /// the most connections that are open at once, past that a new
+    /// one is closed as soon as it is accepted
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// the most connections one IP address can have open at once
+    pub fn max_connections_per_ip(&self) -> Option<usize> {
+        self.max_connections_per_ip
+    }
+
+    /// how long a client has from being accepted until its connection
+    /// preface is in, up to its SETTINGS (this includes the TLS handshake)
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        self.handshake_timeout
+    }
+
+    /// how long a connection with no open streams is kept without
+    /// hearing from the client, before it is sent a GOAWAY and closed
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// how long a header block split over CONTINUATION frames can take
+    pub fn header_block_timeout(&self) -> Duration {
+        self.header_block_timeout
+    }
+
+    /// how long a client has to acknowledge the server's SETTINGS
+    pub fn settings_timeout(&self) -> Duration {
+        self.settings_timeout
+    }
+
+    /// how many streams a client can open and reset on a connection
+    pub fn stream_limits(&self) -> &StreamLimits {
+        &self.stream_limits
+    }
+
+    /// how many bytes a connection can buffer, in all
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    /// when the window released as bodies are read is given back
+    pub fn window_updates(&self) -> WindowUpdates {
+        self.window_updates
+    }
+
+    /// how long a stream can have data to send and no flow control
+    /// window to send it in before a warning is logged
+    pub fn stall_warning(&self) -> Option<Duration> {
+        self.stall_warning
+    }
+
+    /// how long a write to the socket can take before the
+    /// connection is given up on
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// how long the streams that are open when the server is shut down
+    /// get to finish before their connections are closed anyway
+    pub fn shutdown_grace(&self) -> Duration {
+        self.shutdown_grace
+    }
+
+    /// what traces the frames of every connection, if they are traced
+    pub fn trace(&self) -> Option<&Tracer> {
+        self.trace.as_ref()
+    }
+
+    /// are frames of unknown types logged (at the Debug level)
+    /// instead of being dropped without a trace
+    pub fn extension_frames(&self) -> bool {
+        self.extension_frames
+    }
+
+    /// how much the HEADERS and PUSH_PROMISE frames sent are padded
+    pub fn padding(&self) -> PaddingPolicy {
+        self.padding
+    }
+
+    /// what answers extended CONNECT requests, if they are enabled
+    pub fn tunnel_handler(&self) -> Option<&Arc<TunnelHandler>> {
+        self.tunnel_handler.as_ref()
+    }
+
+    /// what is told about the streams of every connection, if anything
+    pub fn observer(&self) -> Option<&Arc<ConnectionObserver>> {
+        self.observer.as_ref()
+    }
+
+    /// what every connection reads the time from
+    pub fn clock(&self) -> &Arc<Clock> {
+        &self.clock
+    }
+
+    /// the read timeout for the socket, often enough for every
+    /// timeout (and a shutdown) to be noticed on time
+    pub fn read_timeout(&self) -> Option<Duration> {
+        let shutdown_check = Duration::from_millis(SHUTDOWN_CHECK_MS);
+        vec![self.handshake_timeout, self.idle_timeout, Some(self.header_block_timeout), Some(self.settings_timeout), self.stall_warning,
+             Some(shutdown_check)]
+            .into_iter().filter_map(|t| t).min()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: "127.0.0.1:8080".to_string(),
+            certs: None,
+            settings: Settings::local_default(),
+            access_log: None,
+            workers: 16,
+            max_queued: 64,
+            saturated: Saturated::Block,
+            max_connections: None,
+            max_connections_per_ip: None,
+            handshake_timeout: Some(Duration::from_secs(10)),
+            idle_timeout: Some(Duration::from_secs(120)),
+            header_block_timeout: Duration::from_secs(DEFAULT_HEADER_BLOCK_TIMEOUT),
+            settings_timeout: Duration::from_secs(DEFAULT_SETTINGS_TIMEOUT),
+            stream_limits: StreamLimits::default(),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            window_updates: WindowUpdates::default(),
+            stall_warning: Some(Duration::from_secs(DEFAULT_STALL_WARNING)),
+            write_timeout: Some(Duration::from_secs(30)),
+            shutdown_grace: Duration::from_secs(30),
+            trace: None,
+            extension_frames: false,
+            padding: PaddingPolicy::None,
+            tunnel_handler: None,
+            observer: None,
+            clock: SystemClock::shared(),
+        }
+    }
+}
+
+/// Builds a Config, starting from the defaults
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+
+    pub fn addr<A: Into<String>>(mut self, addr: A) -> Self {
+        self.config.addr = addr.into();
+        self
+    }
+
+    /// serve TLS with the certificate and private key in these (PEM) files
+    pub fn certs<C: Into<String>, K: Into<String>>(mut self, cert_file: C, key_file: K) -> Self {
+        self.config.certs = Some((cert_file.into(), key_file.into()));
+        self
+    }
+
+    pub fn max_concurrent_streams(mut self, max: u32) -> Self {
+        self.config.settings.max_concurrent_streams = Some(max);
+        self
+    }
+
+    pub fn initial_window_size(mut self, size: u32) -> Self {
+        self.config.settings.initial_window_size = size;
+        self
+    }
+
+    pub fn max_frame_size(mut self, size: u32) -> Self {
+        self.config.settings.max_frame_size = size;
+        self
+    }
+
+    /// also what the HPACK decoder's limits on a header block are based on
+    pub fn max_header_list_size(mut self, size: u32) -> Self {
+        self.config.settings.max_header_list_size = Some(size);
+        self
+    }
+
+    /// the most the HPACK decoder keeps in its dynamic table
+    pub fn header_table_size(mut self, size: u32) -> Self {
+        self.config.settings.header_table_size = size;
+        self
+    }
+
+    pub fn access_log<L: AccessLog + 'static>(mut self, access_log: L) -> Self {
+        self.config.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    pub fn max_queued(mut self, max_queued: usize) -> Self {
+        self.config.max_queued = max_queued;
+        self
+    }
+
+    pub fn saturated(mut self, saturated: Saturated) -> Self {
+        self.config.saturated = saturated;
+        self
+    }
+
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        self.config.max_connections = max;
+        self
+    }
+
+    pub fn max_connections_per_ip(mut self, max: Option<usize>) -> Self {
+        self.config.max_connections_per_ip = max;
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    pub fn header_block_timeout(mut self, timeout: Duration) -> Self {
+        self.config.header_block_timeout = timeout;
+        self
+    }
+
+    pub fn settings_timeout(mut self, timeout: Duration) -> Self {
+        self.config.settings_timeout = timeout;
+        self
+    }
+
+    pub fn stream_limits(mut self, limits: StreamLimits) -> Self {
+        self.config.stream_limits = limits;
+        self
+    }
+
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.config.memory_budget = bytes;
+        self
+    }
+
+    pub fn window_updates(mut self, window_updates: WindowUpdates) -> Self {
+        self.config.window_updates = window_updates;
+        self
+    }
+
+    pub fn stall_warning(mut self, after: Option<Duration>) -> Self {
+        self.config.stall_warning = after;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.config.shutdown_grace = grace;
+        self
+    }
+
+    /// trace every frame of every connection (after the handshake)
+    pub fn trace(mut self, tracer: Tracer) -> Self {
+        self.config.trace = Some(tracer);
+        self
+    }
+
+    pub fn extension_frames(mut self, deliver: bool) -> Self {
+        self.config.extension_frames = deliver;
+        self
+    }
+
+    pub fn padding(mut self, policy: PaddingPolicy) -> Self {
+        self.config.padding = policy;
+        self
+    }
+
+    /// advertise SETTINGS_ENABLE_CONNECT_PROTOCOL, with handler answering
+    /// the extended CONNECT requests (RFC 8441) clients then send
+    pub fn enable_connect_protocol<T: TunnelHandler + 'static>(mut self, handler: T) -> Self {
+        self.config.settings.enable_connect_protocol = true;
+        self.config.tunnel_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// tell observer what happens on every connection
+    pub fn observer(mut self, observer: Arc<ConnectionObserver>) -> Self {
+        self.config.observer = Some(observer);
+        self
+    }
+
+    /// read the time from clock instead of the system, for every
+    /// timeout, date and log line of the connections
+    pub fn clock(mut self, clock: Arc<Clock>) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    /// the config, if the values work together
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let config = self.config;
+        let settings = &config.settings;
+        if settings.max_frame_size < MIN_FRAME_SIZE_LIMIT || settings.max_frame_size > MAX_FRAME_SIZE_LIMIT {
+            return Err(ConfigError::MaxFrameSize(settings.max_frame_size));
+        }
+        if settings.initial_window_size > MAX_WINDOW_SIZE {
+            return Err(ConfigError::InitialWindowSize(settings.initial_window_size));
+        }
+        if config.workers == 0 {
+            return Err(ConfigError::NoWorkers);
+        }
+        if config.max_queued == 0 && config.saturated == Saturated::Block {
+            return Err(ConfigError::NoQueue);
+        }
+        if config.max_connections == Some(0) || config.max_connections_per_ip == Some(0) {
+            return Err(ConfigError::NoConnections);
+        }
+        if let WindowUpdates::Threshold(percent) = config.window_updates {
+            if percent > 100 {
+                return Err(ConfigError::UpdateThreshold(percent));
+            }
+        }
+        if config.certs.is_some() && !cfg!(feature = "krs_ssl") {
+            return Err(ConfigError::NoTls);
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Config, ConfigError};
+    use connection::settings::Settings;
+    use connection::window::WindowUpdates;
+    use server::{Saturated, ThreadPool};
+
+    #[test]
+    fn defaults() {
+        let config = Config::builder().build().unwrap();
+        let rfc = Settings::default();
+        let settings = config.settings();
+        assert_eq!(settings.header_table_size, rfc.header_table_size);
+        assert_eq!(settings.initial_window_size, rfc.initial_window_size);
+        assert_eq!(settings.max_frame_size, rfc.max_frame_size);
+        assert_eq!(settings.enable_push, rfc.enable_push);
+        // the places a limit is set where the RFC has none
+        assert_eq!(settings.max_concurrent_streams, Some(100));
+        assert_eq!(settings.max_header_list_size, Some(16384));
+        assert!(config.certs().is_none());
+        assert!(config.access_log().is_none() && config.observer().is_none());
+        assert!(!settings.enable_connect_protocol && config.tunnel_handler().is_none());
+        assert_eq!(config.read_timeout(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn builder() {
+        let config = Config::builder()
+            .addr("0.0.0.0:8443")
+            .max_concurrent_streams(256)
+            .initial_window_size(1 << 20)
+            .max_frame_size(1 << 20)
+            .max_header_list_size(8192)
+            .workers(4)
+            .idle_timeout(None)
+            .build().unwrap();
+        assert_eq!(config.addr(), "0.0.0.0:8443");
+        assert_eq!(config.settings().max_concurrent_streams, Some(256));
+        assert_eq!(config.settings().initial_window_size, 1 << 20);
+        assert_eq!(config.settings().max_frame_size, 1 << 20);
+        assert_eq!(config.settings().max_header_list_size, Some(8192));
+        assert_eq!(config.workers(), 4);
+        assert_eq!(config.idle_timeout(), None);
+    }
+
+    #[test]
+    fn no_queue() {
+        // refusing a connection no worker is free for
+        let config = Config::builder().workers(2).max_queued(0).saturated(Saturated::Refuse).build().unwrap();
+        let pool = ThreadPool::new(config.workers(), config.max_queued(), config.saturated());
+        let (tx, rx) = mpsc::channel();
+        let mut job = move || tx.send(()).unwrap();
+        for _ in 0..1000 {
+            match pool.execute(job) {
+                Ok(()) => break,
+                Err(refused) => job = refused,
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn rejected() {
+        assert_eq!(Config::builder().max_frame_size(16383).build().err(), Some(ConfigError::MaxFrameSize(16383)));
+        assert_eq!(Config::builder().max_frame_size(1 << 24).build().err(), Some(ConfigError::MaxFrameSize(1 << 24)));
+        assert!(Config::builder().max_frame_size((1 << 24) - 1).build().is_ok());
+        assert_eq!(Config::builder().initial_window_size(1 << 31).build().err(), Some(ConfigError::InitialWindowSize(1 << 31)));
+        assert_eq!(Config::builder().workers(0).build().err(), Some(ConfigError::NoWorkers));
+        assert_eq!(Config::builder().max_queued(0).build().err(), Some(ConfigError::NoQueue));
+        assert_eq!(Config::builder().max_connections_per_ip(Some(0)).build().err(), Some(ConfigError::NoConnections));
+        assert_eq!(Config::builder().window_updates(WindowUpdates::Threshold(101)).build().err(), Some(ConfigError::UpdateThreshold(101)));
+    }
+}
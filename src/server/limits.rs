@@ -0,0 +1,218 @@
+//! How many connections are let in, in all and from each client
+//!
+//! The listener asks for a slot right after each accept, a connection
+//! over either limit is closed before anything else is done with it. The
+//! slot is kept by the job serving the connection and given back when it
+//! is dropped, whether the connection finished, failed or was never run.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Why a connection was not let in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+    /// the server has max_connections open
+    TooManyConnections,
+    /// the client has max_connections_per_ip open
+    TooManyFromPeer(IpAddr),
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Refused::TooManyConnections => write!(f, "too many connections"),
+            Refused::TooManyFromPeer(ip) => write!(f, "too many connections from {}", ip),
+        }
+    }
+}
+
+impl Error for Refused {
+    fn description(&self) -> &str {
+        "Error: Refused"
+    }
+}
+
+/// How the limits have been used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitStats {
+    /// connections holding a slot
+    pub open: usize,
+    /// clients with at least one of them
+    pub peers: usize,
+    /// connections closed for the server being at max_connections
+    pub refused_total: usize,
+    /// connections closed for their client being at max_connections_per_ip
+    pub refused_per_ip: usize,
+}
+
+struct Shared {
+    // open connections from each client, a client with none is removed
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    open: AtomicUsize,
+    max_connections: Option<usize>,
+    max_per_ip: Option<usize>,
+    refused_total: AtomicUsize,
+    refused_per_ip: AtomicUsize,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<HashMap<IpAddr, usize>> {
+        match self.per_ip.lock() {
+            Ok(per_ip) => per_ip,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// The open connection counts, clones share them
+#[derive(Clone)]
+pub struct ConnectionLimits {
+    shared: Arc<Shared>,
+}
+
+impl ConnectionLimits {
+
+    /// None is no limit
+    pub fn new(max_connections: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        ConnectionLimits {
+            shared: Arc::new(Shared {
+                per_ip: Mutex::new(HashMap::new()),
+                open: AtomicUsize::new(0),
+                max_connections: max_connections,
+                max_per_ip: max_per_ip,
+                refused_total: AtomicUsize::new(0),
+                refused_per_ip: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// a slot for a connection from ip, if it is under both limits
+    pub fn admit(&self, ip: IpAddr) -> Result<ConnectionSlot, Refused> {
+        let mut per_ip = self.shared.lock();
+        // the count only changes with the map locked
+        let open = self.shared.open.load(Ordering::Relaxed);
+        if self.shared.max_connections.map_or(false, |max| open >= max) {
+            self.shared.refused_total.fetch_add(1, Ordering::Relaxed);
+            return Err(Refused::TooManyConnections);
+        }
+        let from_ip = per_ip.entry(ip).or_insert(0);
+        if self.shared.max_per_ip.map_or(false, |max| *from_ip >= max) {
+            self.shared.refused_per_ip.fetch_add(1, Ordering::Relaxed);
+            return Err(Refused::TooManyFromPeer(ip));
+        }
+        *from_ip += 1;
+        self.shared.open.store(open + 1, Ordering::Relaxed);
+        Ok(ConnectionSlot { ip: ip, limits: self.clone() })
+    }
+
+    pub fn stats(&self) -> LimitStats {
+        let per_ip = self.shared.lock();
+        LimitStats {
+            open: self.shared.open.load(Ordering::Relaxed),
+            peers: per_ip.len(),
+            refused_total: self.shared.refused_total.load(Ordering::Relaxed),
+            refused_per_ip: self.shared.refused_per_ip.load(Ordering::Relaxed),
+        }
+    }
+
+    /// how many connections from ip hold a slot
+    pub fn open_from(&self, ip: IpAddr) -> usize {
+        self.shared.lock().get(&ip).cloned().unwrap_or(0)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut per_ip = self.shared.lock();
+        let gone = match per_ip.get_mut(&ip) {
+            Some(from_ip) => {
+                *from_ip -= 1;
+                *from_ip == 0
+            },
+            None => false,
+        };
+        if gone {
+            per_ip.remove(&ip);
+        }
+        self.shared.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection's place under the limits, given back when it is dropped
+pub struct ConnectionSlot {
+    ip: IpAddr,
+    limits: ConnectionLimits,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.limits.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod limits_tests {
+
+    use std::net::IpAddr;
+    use std::thread;
+
+    use super::{ConnectionLimits, LimitStats, Refused};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn per_ip() {
+        let limits = ConnectionLimits::new(None, Some(2));
+        let a = ip("10.0.0.1");
+        let first = limits.admit(a).unwrap();
+        let second = limits.admit(a).unwrap();
+        assert_eq!(limits.admit(a).err(), Some(Refused::TooManyFromPeer(a)));
+        // someone else is not held up by it
+        let other = limits.admit(ip("::1")).unwrap();
+        assert_eq!(limits.stats(), LimitStats { open: 3, peers: 2, refused_total: 0, refused_per_ip: 1 });
+
+        drop(first);
+        assert_eq!(limits.open_from(a), 1);
+        let third = limits.admit(a).unwrap();
+        drop(second);
+        drop(third);
+        drop(other);
+        // nothing is kept for clients that have gone
+        assert_eq!(limits.stats(), LimitStats { open: 0, peers: 0, refused_total: 0, refused_per_ip: 1 });
+    }
+
+    #[test]
+    fn total() {
+        let limits = ConnectionLimits::new(Some(3), Some(2));
+        let slots: Vec<_> = ["10.0.0.1", "10.0.0.2", "10.0.0.3"].iter().map(|s| limits.admit(ip(s)).unwrap()).collect();
+        assert_eq!(limits.admit(ip("10.0.0.4")).err(), Some(Refused::TooManyConnections));
+        // a refused connection never takes a slot
+        assert_eq!(limits.open_from(ip("10.0.0.4")), 0);
+        drop(slots);
+        assert!(limits.admit(ip("10.0.0.4")).is_ok());
+        assert_eq!(limits.stats().refused_total, 1);
+    }
+
+    #[test]
+    fn from_many_threads() {
+        let limits = ConnectionLimits::new(Some(1000), Some(100));
+        let threads: Vec<_> = (0..8).map(|t| {
+            let limits = limits.clone();
+            thread::spawn(move || {
+                let peer = ip(&format!("10.0.0.{}", t % 4));
+                for _ in 0..1000 {
+                    let slot = limits.admit(peer);
+                    drop(slot);
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(limits.stats(), LimitStats { open: 0, peers: 0, refused_total: 0, refused_per_ip: 0 });
+    }
+}
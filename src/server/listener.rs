@@ -0,0 +1,304 @@
+//! Accepting connections and serving them on the thread pool
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use connection::Connection;
+use connection::error::ErrorCode;
+use connection::handshake::PREFACE;
+use frame::OwnedFrame;
+use handler::Handler;
+use log::Context;
+use tls::{Acceptor, AlpnInfo, TlsAcceptor};
+
+use super::{Config, ConnectionLimits, ShutdownHandle, ThreadPool};
+
+// how long the listener waits between looking for new connections
+// (and for a shutdown)
+const ACCEPT_POLL_MS : u64 = 10;
+
+/// Listens for connections and serves each of them with the handler
+///
+/// ```ignore
+/// let server = Server::new(config, handler)?;
+/// let shutdown = server.shutdown_handle();
+/// server.run()?;
+/// ```
+///
+/// Connections are TLS when the config has certificates, otherwise they
+/// are cleartext and can also start with an h2c upgrade. Connections over
+/// the config's connection limits are closed as soon as they are accepted.
+pub struct Server<H: ?Sized> {
+    listener: TcpListener,
+    acceptor: Acceptor,
+    allow_upgrade: bool,
+    config: Arc<Config>,
+    shutdown: ShutdownHandle,
+    limits: ConnectionLimits,
+    handler: Arc<H>,
+}
+
+impl<H: Handler + ?Sized + 'static> Server<H> {
+
+    /// listen where the config says
+    pub fn new(config: Config, handler: Arc<H>) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(config.addr())?,
+            acceptor: Acceptor::new(config.certs()),
+            allow_upgrade: config.certs().is_none(),
+            limits: ConnectionLimits::new(config.max_connections(), config.max_connections_per_ip()),
+            config: Arc::new(config),
+            shutdown: ShutdownHandle::new(),
+            handler: handler,
+        })
+    }
+
+    /// where the server is listening, for when it was bound to port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// a handle that stops the server from any thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// the open connection counts, for keeping an eye on them while
+    /// the server runs
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        self.limits.clone()
+    }
+
+    /// accept connections until the shutdown handle is triggered, then
+    /// stop listening and wait for the connections being served to finish
+    pub fn run(self) -> io::Result<()> {
+        let Server { listener, acceptor, allow_upgrade, config, shutdown, limits, handler } = self;
+        // polled so a shutdown does not wait on the next client
+        listener.set_nonblocking(true)?;
+        let pool = ThreadPool::new(config.workers(), config.max_queued(), config.saturated());
+
+        while !shutdown.is_shutdown() {
+            let (stream, peer_addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(ACCEPT_POLL_MS));
+                    continue;
+                },
+                // the connection failed before it was accepted
+                Err(_) => continue,
+            };
+            let slot = match limits.admit(peer_addr.ip()) {
+                Ok(slot) => slot,
+                Err(e) => {
+                    klog_info!(Context::peer(Some(peer_addr)) => "refused: {}", e);
+                    if allow_upgrade {
+                        calm_down(stream);
+                    }
+                    continue;
+                },
+            };
+            // set before the TLS handshake so a silent client can not hold it up
+            if let Err(e) = set_timeouts(&stream, &config) {
+                klog_warn!(Context::peer(Some(peer_addr)) => "could not set timeouts: {}", e);
+                continue;
+            }
+            let stream = match acceptor.accept(stream) {
+                // another protocol was negotiated, h2 is all that is served
+                Ok(ref stream) if !stream.is_h2() => {
+                    klog_info!(Context::peer(Some(peer_addr)) => "client did not select h2");
+                    continue;
+                },
+                Ok(stream) => stream,
+                Err(e) => {
+                    klog_warn!(Context::peer(Some(peer_addr)) => "could not accept: {}", e);
+                    continue;
+                },
+            };
+
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            let handler = handler.clone();
+            let job = move || {
+                // the slot is given back when the job is done (or dropped)
+                let _slot = slot;
+                if let Err(e) = Connection::serve_with(stream, Some(peer_addr), allow_upgrade, &config, Some(&shutdown), handler) {
+                    klog_warn!(Context::peer(Some(peer_addr)) => "{}", e);
+                }
+            };
+            // dropping the job closes the connection
+            if pool.execute(job).is_err() {
+                klog_warn!("too busy, closing connection");
+            }
+        }
+
+        drop(listener);
+        drop(pool);
+        Ok(())
+    }
+}
+
+// close a refused cleartext connection, with a GOAWAY saying why if
+// its preface is already in (without waiting for it)
+fn calm_down(mut stream: TcpStream) {
+    let mut preface = [0; 24];
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    match stream.peek(&mut preface) {
+        Ok(n) if n == PREFACE.len() && preface[..] == *PREFACE => {},
+        _ => return,
+    }
+    let mut out = OwnedFrame::settings(&[]).as_bytes().to_vec();
+    out.extend_from_slice(OwnedFrame::go_away(0, ErrorCode::EnhanceYourCalm as u32, b"too many connections").as_bytes());
+    // it is being closed either way
+    let _ = stream.write_all(&out);
+}
+
+// the listener is non-blocking, which the accepted socket may have
+// picked up, and reads time out often enough for the connection to
+// check its own timeouts
+fn set_timeouts(stream: &TcpStream, config: &Config) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(config.read_timeout())?;
+    stream.set_write_timeout(config.write_timeout())
+}
+
+#[cfg(test)]
+mod listener_tests {
+
+    use std::io::{Cursor, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Server;
+    use connection::handshake::PREFACE;
+    use connection::reader::FrameReader;
+    use frame::{Http2Frame, OwnedFrame};
+    use frame::frame_types::{types, flags};
+    use header::Decoder;
+    use request::Request;
+    use response::{Response, ResponseWriter};
+    use server::Config;
+    use test_util::GET_BLOCK;
+    use util::{Clock, SystemClock};
+
+    #[test]
+    fn graceful_shutdown() {
+        let (started_tx, started) = mpsc::channel();
+        let started_tx = Mutex::new(started_tx);
+        let slow = move |_req: Request, mut resp: ResponseWriter| {
+            started_tx.lock().unwrap().send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            resp.send(Response::new(200).body("slow")).unwrap();
+        };
+        let (server_tx, server) = mpsc::channel();
+        let running = thread::spawn(move || {
+            let config = Config::builder().addr("127.0.0.1:0").build().unwrap();
+            let server = Server::new(config, Arc::new(slow)).unwrap();
+            server_tx.send((server.local_addr().unwrap(), server.shutdown_handle())).unwrap();
+            server.run()
+        });
+        let (addr, shutdown) = server.recv().unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut request = PREFACE.to_vec();
+        request.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        request.extend_from_slice(OwnedFrame::headers(1, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+        client.write_all(&request).unwrap();
+
+        // shut down in the middle of the response
+        started.recv_timeout(Duration::from_secs(10)).unwrap();
+        shutdown.shutdown();
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).unwrap();
+        running.join().unwrap().unwrap();
+
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        let mut status = None;
+        let mut body = Vec::new();
+        let mut go_away = false;
+        while let Some(frame) = reader.read_frame(&mut output).unwrap() {
+            match frame.get_type() {
+                types::HEADERS => status = Decoder::new(4096, 20).get_header_list(frame.payload()).unwrap()
+                    .get_value_by_name(":status").map(|s| s.to_string()),
+                types::DATA => body.extend_from_slice(frame.payload()),
+                // the last stream id is the one that was in flight
+                types::GOAWAY => go_away = frame.payload()[..8] == [0, 0, 0, 1, 0, 0, 0, 0],
+                _ => {},
+            }
+        }
+        assert_eq!(status, Some("200".to_string()));
+        assert_eq!(body, b"slow");
+        assert!(go_away);
+
+        // nothing is listening any more
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    // a client that has sent its preface and seen the server's SETTINGS,
+    // so it is being served
+    fn served_client(addr: SocketAddr) -> TcpStream {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut preface = PREFACE.to_vec();
+        preface.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+        client.write_all(&preface).unwrap();
+        let mut header = [0; 9];
+        client.read_exact(&mut header).unwrap();
+        assert_eq!(header[3], types::SETTINGS);
+        client
+    }
+
+    #[test]
+    fn per_ip_limit() {
+        let (server_tx, server) = mpsc::channel();
+        let running = thread::spawn(move || {
+            let config = Config::builder().addr("127.0.0.1:0").max_connections_per_ip(Some(2)).build().unwrap();
+            let handler = |_req: Request, mut resp: ResponseWriter| { resp.send(Response::new(200)).unwrap(); };
+            let server = Server::new(config, Arc::new(handler)).unwrap();
+            server_tx.send((server.local_addr().unwrap(), server.shutdown_handle(), server.connection_limits())).unwrap();
+            server.run()
+        });
+        let (addr, shutdown, limits) = server.recv().unwrap();
+
+        let first = served_client(addr);
+        let _second = served_client(addr);
+        assert_eq!(limits.stats().open, 2);
+
+        // the third is closed right away, with a GOAWAY if its preface
+        // was in by the time it was accepted
+        let mut third = TcpStream::connect(addr).unwrap();
+        third.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let _ = third.write_all(PREFACE);
+        let mut output = Vec::new();
+        let _ = third.read_to_end(&mut output);
+        let mut reader = FrameReader::new();
+        let mut output = Cursor::new(output);
+        while let Ok(Some(frame)) = reader.read_frame(&mut output) {
+            if frame.get_type() == types::GOAWAY {
+                assert_eq!(frame.payload()[4..8], [0, 0, 0, 0xb]);
+            }
+        }
+        assert_eq!(limits.stats().refused_per_ip, 1);
+
+        // once one is closed there is room again
+        drop(first);
+        let start = SystemClock.now();
+        while limits.stats().open == 2 && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let _fourth = served_client(addr);
+        assert_eq!(limits.stats().refused_per_ip, 1);
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        assert_eq!(limits.stats().open, 0);
+    }
+}
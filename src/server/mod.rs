@@ -0,0 +1,18 @@
+//! Server
+//!
+//! What is set up once for the server and shared by every connection it
+//! accepts.
+
+mod access_log;
+mod config;
+mod limits;
+mod listener;
+mod pool;
+mod shutdown;
+
+pub use self::access_log::{AccessLog, CommonLogFormat, LogRecord};
+pub use self::config::{Config, ConfigBuilder, ConfigError};
+pub use self::limits::{ConnectionLimits, ConnectionSlot, LimitStats, Refused};
+pub use self::listener::Server;
+pub use self::pool::{Saturated, ThreadPool};
+pub use self::shutdown::ShutdownHandle;
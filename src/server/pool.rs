@@ -0,0 +1,239 @@
+//! A fixed number of threads running the jobs they are given
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// What execute does when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Saturated {
+    /// wait for a worker to take a job off the queue
+    Block,
+    /// give the job back right away
+    Refuse,
+}
+
+// a boxed FnOnce that can be called
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Job for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+struct Queue {
+    jobs: VecDeque<Box<Job>>,
+    // no more jobs are coming, the workers stop once the queue is empty
+    closed: bool,
+    // the workers waiting for a job, a job for one of them is not
+    // counted against max_queued
+    idle: usize,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // signalled when a job is queued (or the pool closes)
+    queued: Condvar,
+    // signalled when a job is taken off the queue
+    taken: Condvar,
+    max_queued: usize,
+    saturated: Saturated,
+}
+
+impl Shared {
+    // the queue, whoever panicked while holding it did not leave it half changed
+    fn lock(&self) -> MutexGuard<Queue> {
+        match self.queue.lock() {
+            Ok(queue) => queue,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// Runs jobs (like serving a connection) on a fixed number of threads
+///
+/// Jobs wait in a queue of at most max_queued until a worker is free (one
+/// that is free takes it straight away, even with a max_queued of 0), what
+/// happens to a job when the queue is full is up to the Saturated policy.
+/// A job that panics only ends itself, the worker goes on to the next one.
+///
+/// Dropping the pool waits for every queued job to finish.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+
+    pub fn new(workers: usize, max_queued: usize, saturated: Saturated) -> Self {
+        assert!(workers > 0, "a thread pool needs a worker");
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue { jobs: VecDeque::new(), closed: false, idle: 0 }),
+            queued: Condvar::new(),
+            taken: Condvar::new(),
+            max_queued: max_queued,
+            saturated: saturated,
+        });
+        let workers = (0..workers).map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || work(&shared))
+        }).collect();
+        ThreadPool { shared: shared, workers: workers }
+    }
+
+    /// queue the job for the next free worker, the job is given back
+    /// if the queue is full and the policy is to refuse
+    pub fn execute<F>(&self, job: F) -> Result<(), F> where F: FnOnce() + Send + 'static {
+        let mut queue = self.shared.lock();
+        while queue.jobs.len() >= self.shared.max_queued + queue.idle {
+            match self.shared.saturated {
+                Saturated::Refuse => return Err(job),
+                Saturated::Block => queue = self.shared.taken.wait(queue).unwrap_or_else(|e| e.into_inner()),
+            }
+        }
+        queue.jobs.push_back(Box::new(job));
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// how many jobs are waiting for a worker
+    pub fn queued(&self) -> usize {
+        self.shared.lock().jobs.len()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.queued.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// what each worker thread does until the pool is dropped
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut queue = shared.lock();
+            loop {
+                if let Some(job) = queue.jobs.pop_front() {
+                    break job;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue.idle += 1;
+                queue = shared.queued.wait(queue).unwrap_or_else(|e| e.into_inner());
+                queue.idle -= 1;
+            }
+        };
+        shared.taken.notify_one();
+
+        if panic::catch_unwind(AssertUnwindSafe(|| job.run())).is_err() {
+            klog_error!("a job panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod pool_tests {
+
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::{Saturated, ThreadPool};
+
+    #[test]
+    fn more_jobs_than_workers() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        {
+            let pool = ThreadPool::new(3, 100, Saturated::Block);
+            for _ in 0..50 {
+                let done = done.clone();
+                let threads = threads.clone();
+                pool.execute(move || {
+                    threads.lock().unwrap().push(::std::thread::current().id());
+                    done.fetch_add(1, Ordering::SeqCst);
+                }).ok().unwrap();
+            }
+        }
+        assert_eq!(done.load(Ordering::SeqCst), 50);
+        let mut threads = threads.lock().unwrap().clone();
+        threads.sort_by_key(|id| format!("{:?}", id));
+        threads.dedup();
+        assert!(threads.len() <= 3);
+    }
+
+    #[test]
+    fn panics_do_not_shrink_the_pool() {
+        let pool = ThreadPool::new(1, 10, Saturated::Block);
+        let (tx, rx) = mpsc::channel();
+        pool.execute(|| panic!("bad connection")).ok().unwrap();
+        for i in 0..3 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).ok().unwrap();
+        }
+        let got: Vec<i32> = rx.iter().take(3).collect();
+        assert_eq!(got, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn saturated() {
+        // the only worker is held up so jobs pile up in the queue
+        let pool = ThreadPool::new(1, 2, Saturated::Refuse);
+        let barrier = Arc::new(Barrier::new(2));
+        let (started_tx, started) = mpsc::channel();
+        let held = barrier.clone();
+        pool.execute(move || { started_tx.send(()).unwrap(); held.wait(); }).ok().unwrap();
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        pool.execute(|| {}).ok().unwrap();
+        pool.execute(|| {}).ok().unwrap();
+        assert_eq!(pool.queued(), 2);
+        assert!(pool.execute(|| {}).is_err());
+
+        barrier.wait();
+        drop(pool);
+
+        // blocking waits for room instead
+        let pool = ThreadPool::new(1, 1, Saturated::Block);
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let ran = ran.clone();
+            pool.execute(move || { ::std::thread::sleep(Duration::from_millis(1)); ran.fetch_add(1, Ordering::SeqCst); }).ok().unwrap();
+            assert!(pool.queued() <= 1);
+        }
+        drop(pool);
+        assert_eq!(ran.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn no_queue() {
+        // a job only goes to a worker that is free
+        let pool = ThreadPool::new(1, 0, Saturated::Refuse);
+        let (tx, ran) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let held = barrier.clone();
+        let mut job = move || { tx.send(()).unwrap(); held.wait(); };
+        // once the worker is up and waiting
+        for _ in 0..1000 {
+            match pool.execute(job) {
+                Ok(()) => break,
+                Err(refused) => job = refused,
+            }
+            ::std::thread::sleep(Duration::from_millis(1));
+        }
+        ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(pool.execute(|| {}).is_err());
+        barrier.wait();
+    }
+}
@@ -0,0 +1,29 @@
+//! Stopping the server
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Tells a running Server, and every connection it is serving, to stop
+///
+/// Clones of a handle all stop the same server.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+
+    pub fn new() -> Self {
+        ShutdownHandle::default()
+    }
+
+    /// stop accepting connections, the ones being served are sent a
+    /// GOAWAY and closed once their open streams are done
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,294 @@
+//! Things for testing a Connection without a socket
+//!
+//! duplex gives two ends of an in-memory stream, what is written to one
+//! is read from the other. A test can serve a Connection on one end in
+//! its own thread and play the client with the other:
+//!
+//! ```ignore
+//! let (client, server) = duplex(4096);
+//! let serving = thread::spawn(move || Connection::serve(server, false, handler));
+//! client.write_all(PREFACE)?;
+//! ```
+//!
+//! Dropping an end closes it, the other end reads Ok(0) once it has read
+//! everything that was written before, and writes to it fail.
+//!
+//! For a test that dispatches frames itself, GET_BLOCK is a request for /
+//! and open_stream has the peer open a stream with it.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use connection::Connection;
+use frame::OwnedFrame;
+use frame::frame_types::flags;
+use util::{Clock, SystemClock};
+
+/// the header block of a GET for / (:method GET, :path /, :scheme https)
+/// with only indexed fields, so it decodes the same in any connection
+pub static GET_BLOCK : &'static [u8] = &[0x82, 0x84, 0x87];
+
+/// have the peer open stream_id with a GET_BLOCK request (and no body)
+pub fn open_stream(conn: &mut Connection, stream_id: u32) {
+    let mut frame = OwnedFrame::headers(stream_id, GET_BLOCK, flags::END_HEADERS | flags::END_STREAM);
+    conn.dispatch_frame(frame.as_frame()).unwrap();
+}
+
+// the bytes going one way
+#[derive(Debug)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // the end that writes (or the one that reads) was dropped
+    writer_closed: bool,
+    reader_closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    pipe: Mutex<Pipe>,
+    // signalled when bytes are written (or the writer is dropped)
+    readable: Condvar,
+    // signalled when bytes are read (or the reader is dropped)
+    writable: Condvar,
+}
+
+impl Shared {
+
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Shared {
+            pipe: Mutex::new(Pipe {
+                buf: VecDeque::with_capacity(capacity),
+                capacity: capacity,
+                writer_closed: false,
+                reader_closed: false,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<Pipe> {
+        match self.pipe.lock() {
+            Ok(pipe) => pipe,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// One end of an in-memory stream, see duplex
+///
+/// Reads and writes block like a socket's unless set_nonblocking is used,
+/// then they say WouldBlock instead. A read timeout also ends a blocked
+/// read with WouldBlock, as it does for a socket.
+pub struct DuplexStream {
+    read: Arc<Shared>,
+    write: Arc<Shared>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+/// the two ends of a stream that can have up to capacity bytes
+/// written each way before they are read
+pub fn duplex(capacity: usize) -> (DuplexStream, DuplexStream) {
+    assert!(capacity > 0, "a duplex stream needs room for a byte");
+    let one = Shared::new(capacity);
+    let two = Shared::new(capacity);
+    let a = DuplexStream { read: one.clone(), write: two.clone(), nonblocking: false, read_timeout: None };
+    let b = DuplexStream { read: two, write: one, nonblocking: false, read_timeout: None };
+    (a, b)
+}
+
+impl DuplexStream {
+
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// how long a blocking read waits before it says WouldBlock,
+    /// None waits for as long as it takes
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// how many bytes were written to this end that have not been read yet
+    pub fn available(&self) -> usize {
+        self.read.lock().buf.len()
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // the pipe waits in real time, whatever clock the server is on
+        let deadline = self.read_timeout.map(|timeout| SystemClock.now() + timeout);
+        let mut pipe = self.read.lock();
+        while pipe.buf.is_empty() {
+            if pipe.writer_closed {
+                return Ok(0);
+            }
+            if self.nonblocking {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "nothing to read"));
+            }
+            pipe = match deadline {
+                Some(deadline) => {
+                    let now = SystemClock.now();
+                    if now >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::WouldBlock, "read timed out"));
+                    }
+                    self.read.readable.wait_timeout(pipe, deadline - now).unwrap_or_else(|e| e.into_inner()).0
+                },
+                None => self.read.readable.wait(pipe).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+
+        let n = pipe.buf.len().min(buf.len());
+        for (to, from) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *to = from;
+        }
+        self.read.writable.notify_all();
+        Ok(n)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.write.lock();
+        loop {
+            if pipe.reader_closed {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the other end was dropped"));
+            }
+            if pipe.buf.len() < pipe.capacity {
+                break;
+            }
+            if self.nonblocking {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "no room to write"));
+            }
+            pipe = self.write.writable.wait(pipe).unwrap_or_else(|e| e.into_inner());
+        }
+
+        let n = (pipe.capacity - pipe.buf.len()).min(buf.len());
+        pipe.buf.extend(&buf[..n]);
+        self.write.readable.notify_all();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.read.lock().reader_closed = true;
+        self.read.writable.notify_all();
+        self.write.lock().writer_closed = true;
+        self.write.readable.notify_all();
+    }
+}
+
+impl fmt::Debug for DuplexStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DuplexStream")
+            .field("available", &self.available())
+            .field("unread", &self.write.lock().buf.len())
+            .field("nonblocking", &self.nonblocking)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod duplex_tests {
+
+    use std::io::{ErrorKind, Read, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::duplex;
+    use util::{Clock, SystemClock};
+
+    #[test]
+    fn both_ways() {
+        let (mut a, mut b) = duplex(16);
+        a.write_all(b"ping").unwrap();
+        b.write_all(b"pong!").unwrap();
+        assert_eq!(b.available(), 4);
+
+        let mut buf = [0; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+        // a short read leaves the rest for the next
+        assert_eq!(a.read(&mut buf[..3]).unwrap(), 3);
+        assert_eq!(a.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"g!");
+    }
+
+    #[test]
+    fn blocking_across_threads() {
+        // much more than fits, so the writer waits on the reader
+        let (mut a, mut b) = duplex(7);
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let writer = thread::spawn(move || {
+            a.write_all(&sent).unwrap();
+        });
+
+        let mut got = Vec::new();
+        b.read_to_end(&mut got).unwrap();
+        writer.join().unwrap();
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn nonblocking() {
+        let (mut a, mut b) = duplex(4);
+        a.set_nonblocking(true);
+        b.set_nonblocking(true);
+
+        let mut buf = [0; 8];
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(a.write(b"abcdef").unwrap(), 4);
+        assert_eq!(a.write(b"ef").unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+        assert_eq!(a.write(b"ef").unwrap(), 2);
+    }
+
+    #[test]
+    fn read_timeout() {
+        let (_a, mut b) = duplex(4);
+        b.set_read_timeout(Some(Duration::from_millis(20)));
+        let start = SystemClock.now();
+        assert_eq!(b.read(&mut [0; 4]).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn half_close() {
+        let (mut a, mut b) = duplex(16);
+        a.write_all(b"last words").unwrap();
+        drop(a);
+
+        // what was written before is still read, then the end of the stream
+        let mut got = Vec::new();
+        b.read_to_end(&mut got).unwrap();
+        assert_eq!(got, b"last words");
+        assert_eq!(b.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(b.write(b"anyone?").unwrap_err().kind(), ErrorKind::BrokenPipe);
+
+        // a blocked reader is woken by the drop
+        let (a, mut b) = duplex(16);
+        let reader = thread::spawn(move || b.read(&mut [0; 4]).unwrap());
+        thread::sleep(Duration::from_millis(10));
+        drop(a);
+        assert_eq!(reader.join().unwrap(), 0);
+    }
+}
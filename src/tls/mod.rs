@@ -0,0 +1,252 @@
+//! TLS
+//!
+//! The listener only needs something that turns an accepted TcpStream into
+//! a stream it can read and write, so the TLS library is hidden behind the
+//! TlsAcceptor trait. PlainAcceptor does no TLS at all, which is what
+//! cleartext connections and the tests use.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+#[cfg(feature = "krs_ssl")]
+use krs_ssl::OsslStream;
+
+/// the ALPN protocol id for HTTP/2 over TLS
+pub const ALPN_H2 : &'static [u8] = b"h2";
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    Handshake(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsError::Io(ref e) => write!(f, "tls io error: {}", e),
+            TlsError::Handshake(ref msg) => write!(f, "tls handshake failed: {}", msg),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn description(&self) -> &str {
+        "Error: TlsError"
+    }
+}
+
+impl From<io::Error> for TlsError {
+    fn from(e: io::Error) -> TlsError {
+        TlsError::Io(e)
+    }
+}
+
+/// What was agreed on with Application-Layer Protocol Negotiation
+pub trait AlpnInfo {
+    /// the protocol selected during the handshake, None
+    /// if ALPN was not used (or is not known)
+    fn negotiated_protocol(&self) -> Option<&[u8]>;
+
+    /// 3.3 Starting HTTP/2 for "https" URIs
+    ///
+    /// HTTP/2 is only spoken when "h2" was selected. When the protocol is not
+    /// known the client is assumed to speak HTTP/2, the preface check will
+    /// catch it if it does not.
+    fn is_h2(&self) -> bool {
+        match self.negotiated_protocol() {
+            Some(protocol) => protocol == ALPN_H2,
+            None => true,
+        }
+    }
+}
+
+pub trait TlsAcceptor {
+    type Stream: Read + Write + Send + AlpnInfo;
+
+    /// do the server side of the handshake on a newly accepted connection
+    fn accept(&self, stream: TcpStream) -> Result<Self::Stream, TlsError>;
+}
+
+/// Accepts connections as they are, without TLS
+pub struct PlainAcceptor;
+
+impl TlsAcceptor for PlainAcceptor {
+    type Stream = TcpStream;
+
+    fn accept(&self, stream: TcpStream) -> Result<TcpStream, TlsError> {
+        Ok(stream)
+    }
+}
+
+impl AlpnInfo for TcpStream {
+    fn negotiated_protocol(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// TLS with the certificate and key files, or cleartext without them,
+/// decided when the server starts
+pub enum Acceptor {
+    Plain(PlainAcceptor),
+    #[cfg(feature = "krs_ssl")]
+    Tls(KrsAcceptor),
+}
+
+impl Acceptor {
+    /// certs are only used when TLS support is built in
+    pub fn new(certs: Option<(&str, &str)>) -> Self {
+        match certs {
+            #[cfg(feature = "krs_ssl")]
+            Some((cert_file, key_file)) => Acceptor::Tls(KrsAcceptor::new(cert_file, key_file)),
+            _ => Acceptor::Plain(PlainAcceptor),
+        }
+    }
+}
+
+/// A connection accepted by an Acceptor
+pub enum Accepted {
+    Plain(TcpStream),
+    #[cfg(feature = "krs_ssl")]
+    Tls(OsslStream),
+}
+
+impl TlsAcceptor for Acceptor {
+    type Stream = Accepted;
+
+    fn accept(&self, stream: TcpStream) -> Result<Accepted, TlsError> {
+        match *self {
+            Acceptor::Plain(ref plain) => plain.accept(stream).map(Accepted::Plain),
+            #[cfg(feature = "krs_ssl")]
+            Acceptor::Tls(ref tls) => tls.accept(stream).map(Accepted::Tls),
+        }
+    }
+}
+
+impl Read for Accepted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Accepted::Plain(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "krs_ssl")]
+            Accepted::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Accepted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Accepted::Plain(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "krs_ssl")]
+            Accepted::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Accepted::Plain(ref mut stream) => stream.flush(),
+            #[cfg(feature = "krs_ssl")]
+            Accepted::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl AlpnInfo for Accepted {
+    fn negotiated_protocol(&self) -> Option<&[u8]> {
+        match *self {
+            Accepted::Plain(ref stream) => stream.negotiated_protocol(),
+            #[cfg(feature = "krs_ssl")]
+            Accepted::Tls(ref stream) => stream.negotiated_protocol(),
+        }
+    }
+}
+
+#[cfg(feature = "krs_ssl")]
+pub use self::krs::KrsAcceptor;
+
+#[cfg(feature = "krs_ssl")]
+mod krs {
+    use std::net::TcpStream;
+
+    use krs_ssl::{self, OsslStream};
+
+    use super::{AlpnInfo, TlsAcceptor, TlsError};
+
+    /// TLS through krs_ssl (OpenSSL)
+    pub struct KrsAcceptor {
+        accept: Box<Fn(TcpStream) -> Option<OsslStream>>,
+    }
+
+    impl KrsAcceptor {
+        pub fn new(cert_file: &str, key_file: &str) -> Self {
+            let ctx = krs_ssl::make_ctx(cert_file, key_file);
+            KrsAcceptor { accept: Box::new(move |stream| OsslStream::accept(&ctx, stream).ok()) }
+        }
+    }
+
+    impl TlsAcceptor for KrsAcceptor {
+        type Stream = OsslStream;
+
+        fn accept(&self, stream: TcpStream) -> Result<OsslStream, TlsError> {
+            (self.accept)(stream).ok_or_else(|| TlsError::Handshake("could not accept".to_string()))
+        }
+    }
+
+    // krs_ssl does not report the ALPN result
+    impl AlpnInfo for OsslStream {
+        fn negotiated_protocol(&self) -> Option<&[u8]> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{AlpnInfo, PlainAcceptor, TlsAcceptor};
+
+    struct Negotiated(Option<&'static [u8]>);
+
+    impl AlpnInfo for Negotiated {
+        fn negotiated_protocol(&self) -> Option<&[u8]> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn alpn_selects_h2() {
+        assert!(Negotiated(Some(b"h2")).is_h2());
+        assert!(Negotiated(None).is_h2());
+        assert!(!Negotiated(Some(b"http/1.1")).is_h2());
+    }
+
+    #[test]
+    fn plain_acceptor() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"ping").unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            buf
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = PlainAcceptor.accept(stream).unwrap();
+        assert!(stream.negotiated_protocol().is_none());
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        stream.write_all(b"pong").unwrap();
+
+        assert_eq!(&client.join().unwrap(), b"pong");
+    }
+}
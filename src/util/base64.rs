@@ -0,0 +1,241 @@
+//! Base64 (RFC 4648)
+//!
+//! Basic authentication sends its credentials in the standard alphabet
+//! (section 4), the HTTP2-Settings of an h2c upgrade is in the URL and
+//! filename safe one (section 5) without padding.
+//!
+//! Encoding always pads. Decoding takes the input with its padding or
+//! without any, but nothing else: a character outside the alphabet
+//! (whitespace included), padding that does not make the length a
+//! multiple of 4, a length no encoding has, or bits left over at the end
+//! that are not zero are all errors.
+
+use std::error::Error;
+use std::fmt;
+
+static STANDARD : &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+static URL : &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Why the input does not decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64Error {
+    /// the byte at the offset is not in the alphabet
+    InvalidCharacter(usize),
+    /// the length (without padding) is one no encoding has
+    InvalidLength,
+    /// there is padding, but not the right amount
+    InvalidPadding,
+    /// the last character has bits set that are not part of the data
+    TrailingBits,
+}
+
+impl fmt::Display for Base64Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Base64Error::InvalidCharacter(at) => write!(f, "base64: invalid character at {}", at),
+            Base64Error::InvalidLength => write!(f, "base64: invalid length"),
+            Base64Error::InvalidPadding => write!(f, "base64: invalid padding"),
+            Base64Error::TrailingBits => write!(f, "base64: trailing bits"),
+        }
+    }
+}
+
+impl Error for Base64Error {
+    fn description(&self) -> &str {
+        "Error: Base64Error"
+    }
+}
+
+/// encode in the standard alphabet
+pub fn encode(input: &[u8]) -> String {
+    encode_with(input, STANDARD)
+}
+
+/// encode in the URL and filename safe alphabet
+pub fn encode_url(input: &[u8]) -> String {
+    encode_with(input, URL)
+}
+
+/// decode the standard alphabet, with or without padding
+pub fn decode(input: &str) -> Result<Vec<u8>, Base64Error> {
+    decode_with(input, standard_value)
+}
+
+/// decode the URL and filename safe alphabet, with or without padding
+pub fn decode_url(input: &str) -> Result<Vec<u8>, Base64Error> {
+    decode_with(input, url_value)
+}
+
+fn encode_with(input: &[u8], alphabet: &[u8; 64]) -> String {
+    let mut out = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let mut bits = 0u32;
+        for (i, &b) in chunk.iter().enumerate() {
+            bits |= (b as u32) << (16 - 8 * i);
+        }
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(alphabet[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            }
+            else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn standard_value(c: u8) -> Option<u32> {
+    match c {
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => alphanumeric_value(c),
+    }
+}
+
+fn url_value(c: u8) -> Option<u32> {
+    match c {
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => alphanumeric_value(c),
+    }
+}
+
+// the first 62 characters, which both alphabets share
+fn alphanumeric_value(c: u8) -> Option<u32> {
+    match c {
+        b'A'...b'Z' => Some((c - b'A') as u32),
+        b'a'...b'z' => Some((c - b'a') as u32 + 26),
+        b'0'...b'9' => Some((c - b'0') as u32 + 52),
+        _ => None,
+    }
+}
+
+fn decode_with(input: &str, value: fn(u8) -> Option<u32>) -> Result<Vec<u8>, Base64Error> {
+    let padded = input.as_bytes();
+    let input = input.trim_end_matches('=').as_bytes();
+
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for (n, chunk) in input.chunks(4).enumerate() {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            bits = bits << 6 | value(c).ok_or(Base64Error::InvalidCharacter(n * 4 + i))?;
+        }
+        // one character is not even a whole byte
+        if chunk.len() == 1 {
+            return Err(Base64Error::InvalidLength);
+        }
+        bits <<= 6 * (4 - chunk.len()) as u32;
+        out.push((bits >> 16) as u8);
+        if chunk.len() > 2 {
+            out.push((bits >> 8) as u8);
+        }
+        if chunk.len() > 3 {
+            out.push(bits as u8);
+        }
+        // a short last chunk only has room for the bytes it holds
+        else if bits & (0xFFFFFF >> (8 * (chunk.len() - 1))) != 0 {
+            return Err(Base64Error::TrailingBits);
+        }
+    }
+
+    let padding = padded.len() - input.len();
+    if padding > 0 && (padding > 2 || padded.len() % 4 != 0) {
+        return Err(Base64Error::InvalidPadding);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod base64_tests {
+
+    use super::{decode, decode_url, encode, encode_url, Base64Error};
+
+    // RFC 4648 10
+    static VECTORS : &'static [(&'static str, &'static str)] = &[
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    // xorshift, to make up the same data every run
+    struct Rng(u32);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+    }
+
+    #[test]
+    fn test_vectors() {
+        for &(plain, encoded) in VECTORS {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            assert_eq!(encode_url(plain.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+            assert_eq!(decode_url(encoded).unwrap(), plain.as_bytes());
+            // and without the padding
+            let unpadded = encoded.trim_end_matches('=');
+            assert_eq!(decode(unpadded).unwrap(), plain.as_bytes());
+            assert_eq!(decode_url(unpadded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(decode("QWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap(), b"Aladdin:open sesame");
+    }
+
+    #[test]
+    fn alphabets() {
+        assert_eq!(encode(&[0xFB, 0xFF]), "+/8=");
+        assert_eq!(encode_url(&[0xFB, 0xFF]), "-_8=");
+        assert_eq!(decode("+/8=").unwrap(), &[0xFB, 0xFF]);
+        assert_eq!(decode_url("_-8").unwrap(), &[0xFF, 0xEF]);
+        assert_eq!(decode("_-8"), Err(Base64Error::InvalidCharacter(0)));
+        assert_eq!(decode_url("Zm9v+"), Err(Base64Error::InvalidCharacter(4)));
+
+        // SETTINGS_MAX_CONCURRENT_STREAMS 100, SETTINGS_INITIAL_WINDOW_SIZE 2^30, SETTINGS_ENABLE_PUSH 0
+        assert_eq!(decode_url("AAMAAABkAARAAAAAAAIAAAAA").unwrap(),
+                   &[0, 3, 0, 0, 0, 100, 0, 4, 0x40, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejected() {
+        // whitespace is not skipped
+        assert_eq!(decode("Zm9v YmFy"), Err(Base64Error::InvalidCharacter(4)));
+        assert_eq!(decode("Zm9v\r\nYmFy"), Err(Base64Error::InvalidCharacter(4)));
+        assert_eq!(decode(" Zg=="), Err(Base64Error::InvalidCharacter(0)));
+        assert_eq!(decode("Zg\n"), Err(Base64Error::InvalidCharacter(2)));
+
+        assert_eq!(decode("Z"), Err(Base64Error::InvalidLength));
+        assert_eq!(decode("Zm9vY"), Err(Base64Error::InvalidLength));
+        assert_eq!(decode("Z==="), Err(Base64Error::InvalidLength));
+        assert_eq!(decode("Zg="), Err(Base64Error::InvalidPadding));
+        assert_eq!(decode("Zm9v===="), Err(Base64Error::InvalidPadding));
+        assert_eq!(decode("Zm8=="), Err(Base64Error::InvalidPadding));
+        // padding in the middle is just a character that does not belong
+        assert_eq!(decode("Zg==Zg=="), Err(Base64Error::InvalidCharacter(2)));
+
+        assert_eq!(decode("Zh=="), Err(Base64Error::TrailingBits));
+        assert_eq!(decode_url("Zm9"), Err(Base64Error::TrailingBits));
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng(0x9e3779b9);
+        for _ in 0..500 {
+            let data: Vec<u8> = (0..rng.below(100)).map(|_| rng.below(256) as u8).collect();
+            let encoded = encode(&data);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode(&encoded).unwrap(), data);
+            let encoded = encode_url(&data);
+            assert!(!encoded.contains('+') && !encoded.contains('/'));
+            assert_eq!(decode_url(&encoded).unwrap(), data);
+            assert_eq!(decode_url(encoded.trim_end_matches('=')).unwrap(), data);
+        }
+    }
+}
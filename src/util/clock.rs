@@ -0,0 +1,134 @@
+//! Where the time comes from
+//!
+//! Everything that needs to know what time it is (the timeouts and
+//! deadlines of a connection, how long a request took, the date header
+//! and the times in the logs) asks a Clock, the one in the Config of the
+//! server. SystemClock is the real time, and the only place the time is
+//! read from the system. A test can serve with a MockClock instead and
+//! move the time along itself, so a timeout does not have to be waited
+//! out for real.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock: Send + Sync {
+    /// the time to measure durations and deadlines with
+    fn now(&self) -> Instant;
+
+    /// the wall clock time, for dates and the logs
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The time as the system tells it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+
+    /// a SystemClock to share, what everything uses unless
+    /// it is given another clock
+    pub fn shared() -> Arc<Clock> {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when it is told to
+///
+/// It starts at the time it is made, and both of its times move together.
+#[derive(Debug)]
+pub struct MockClock {
+    times: Mutex<(Instant, SystemTime)>,
+}
+
+impl MockClock {
+
+    pub fn new() -> Self {
+        MockClock { times: Mutex::new((SystemClock.now(), SystemClock.system_now())) }
+    }
+
+    /// move the time on by how long
+    pub fn advance(&self, how_long: Duration) {
+        let mut times = self.times.lock().unwrap_or_else(|e| e.into_inner());
+        times.0 += how_long;
+        times.1 += how_long;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+
+    fn now(&self) -> Instant {
+        self.times.lock().unwrap_or_else(|e| e.into_inner()).0
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.times.lock().unwrap_or_else(|e| e.into_inner()).1
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::{Clock, MockClock};
+
+    #[test]
+    fn mock_clock() {
+        let clock = MockClock::new();
+        let (start, system_start) = (clock.now(), clock.system_now());
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+        assert_eq!(clock.system_now().duration_since(system_start).unwrap(), Duration::from_millis(1500));
+    }
+
+    // the files under dir (and the directories in it) that ask
+    // the system for the time themselves
+    fn reading_the_time(dir: &Path, found: &mut Vec<String>) {
+        // put together so this file does not find itself
+        let calls = [concat!("Instant", "::now()"), concat!("SystemTime", "::now()")];
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                reading_the_time(&path, found);
+            }
+            else if path.extension().map_or(false, |ext| ext == "rs") && !path.ends_with("util/clock.rs") {
+                let source = fs::read_to_string(&path).unwrap();
+                for (n, line) in source.lines().enumerate() {
+                    if calls.iter().any(|call| line.contains(call)) {
+                        found.push(format!("{}:{}: {}", path.display(), n + 1, line.trim()));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_system_clock() {
+        // file!() is relative to the manifest, unless it is a whole path
+        let here = Path::new(env!("CARGO_MANIFEST_DIR")).join(file!());
+        let src = here.parent().and_then(|util| util.parent()).unwrap();
+        let mut found = Vec::new();
+        reading_the_time(src, &mut found);
+        assert!(found.is_empty(), "the time is read outside of the SystemClock:\n{}", found.join("\n"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Small things that do not belong anywhere else
+
+use std::cmp;
+use std::fmt;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod base64;
+pub mod clock;
+
+pub use self::clock::{Clock, MockClock, SystemClock};
+
+static DAYS : [&'static [u8; 3]; 7] = [b"Thu", b"Fri", b"Sat", b"Sun", b"Mon", b"Tue", b"Wed"];
+static MONTHS : [&'static [u8; 3]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+/// RFC 7231 7.1.1.1 the IMF-fixdate format, like "Sun, 06 Nov 1994 08:49:37 GMT"
+/// (times before 1970 are given as the epoch)
+pub fn http_date(time: SystemTime) -> [u8; 29] {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = secs / 86400;
+    let secs = secs % 86400;
+
+    // civil from days, shifted so the year starts in March
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let mut date = *b"Thu, 01 Jan 1970 00:00:00 GMT";
+    date[..3].copy_from_slice(DAYS[(days % 7) as usize]);
+    put_digits(&mut date[5..7], day as u64);
+    date[8..11].copy_from_slice(MONTHS[(month - 1) as usize]);
+    put_digits(&mut date[12..16], year as u64);
+    put_digits(&mut date[17..19], secs / 3600);
+    put_digits(&mut date[20..22], secs % 3600 / 60);
+    put_digits(&mut date[23..25], secs % 60);
+    date
+}
+
+/// the time of an IMF-fixdate (the only format that has to be sent,
+/// the obsolete ones are not understood)
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let bytes = date.as_bytes();
+    if bytes.len() != 29 || &bytes[3..5] != b", " || &bytes[25..] != b" GMT"
+        || bytes[7] != b' ' || bytes[11] != b' ' || bytes[16] != b' ' || bytes[19] != b':' || bytes[22] != b':' {
+        return None;
+    }
+    let number = |range: ::std::ops::Range<usize>| -> Option<u64> {
+        date.get(range).and_then(|n| n.parse().ok())
+    };
+    let day = number(5..7)?;
+    let month = MONTHS.iter().position(|m| &m[..] == &bytes[8..11])? as u64 + 1;
+    let year = number(12..16)?;
+    let (hour, minute, second) = (number(17..19)?, number(20..22)?, number(23..25)?);
+    if year < 1970 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // days from civil, the inverse of what http_date does
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// write n in decimal filling all of buf, with leading zeros
+fn put_digits(buf: &mut [u8], mut n: u64) {
+    for b in buf.iter_mut().rev() {
+        *b = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+}
+
+/// The http_date of the time it is asked for, only formatted again once
+/// the second changes
+pub struct DateCache {
+    secs: u64,
+    date: [u8; 29],
+}
+
+impl DateCache {
+
+    pub fn new() -> Self {
+        DateCache { secs: 0, date: http_date(UNIX_EPOCH) }
+    }
+
+    /// the date of now (the time by some Clock)
+    pub fn at(&mut self, now: SystemTime) -> &str {
+        let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        if secs != self.secs {
+            self.secs = secs;
+            self.date = http_date(now);
+        }
+        str::from_utf8(&self.date).unwrap()
+    }
+}
+
+// how many bytes go on each line of a hex dump
+const HEXDUMP_WIDTH : usize = 16;
+
+/// write buf the way `hexdump -C` does, an offset, the bytes in hex and
+/// the printable ones as ASCII, 16 to a line:
+///
+/// ```text
+/// 00000000  68 65 6c 6c 6f 20 77 6f  72 6c 64 0a              |hello world.|
+/// ```
+///
+/// There is no newline after the last line, and nothing at all for an
+/// empty buf.
+pub fn hexdump<W: fmt::Write>(buf: &[u8], out: &mut W) -> fmt::Result {
+    hexdump_max(buf, buf.len(), out)
+}
+
+/// hexdump at most max_bytes of buf, with a line at the end saying how
+/// many bytes were left out
+pub fn hexdump_max<W: fmt::Write>(buf: &[u8], max_bytes: usize, out: &mut W) -> fmt::Result {
+    let shown = &buf[..cmp::min(buf.len(), max_bytes)];
+    for (i, line) in shown.chunks(HEXDUMP_WIDTH).enumerate() {
+        if i > 0 {
+            out.write_char('\n')?;
+        }
+        write!(out, "{:08x}  ", i * HEXDUMP_WIDTH)?;
+        for j in 0..HEXDUMP_WIDTH {
+            match line.get(j) {
+                Some(b) => write!(out, "{:02x} ", b)?,
+                None => out.write_str("   ")?,
+            }
+            if j == HEXDUMP_WIDTH / 2 - 1 || j == HEXDUMP_WIDTH - 1 {
+                out.write_char(' ')?;
+            }
+        }
+        out.write_char('|')?;
+        for &b in line {
+            out.write_char(if b >= 0x20 && b < 0x7f { b as char } else { '.' })?;
+        }
+        out.write_char('|')?;
+    }
+    if shown.len() < buf.len() {
+        if !shown.is_empty() {
+            out.write_char('\n')?;
+        }
+        write!(out, "... {} more bytes", buf.len() - shown.len())?;
+    }
+    Ok(())
+}
+
+/// Displays the bytes as a hexdump, a precision is the most bytes
+/// that are shown (so "{:.64}" shows at most 64)
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Display for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        hexdump_max(self.0, f.precision().unwrap_or(self.0.len()), f)
+    }
+}
+
+#[cfg(test)]
+mod util_tests {
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{http_date, parse_http_date, hexdump, DateCache, HexDump};
+
+    fn date(secs: u64) -> String {
+        String::from_utf8(http_date(UNIX_EPOCH + Duration::from_secs(secs)).to_vec()).unwrap()
+    }
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(date(1234567890), "Fri, 13 Feb 2009 23:31:30 GMT");
+        assert_eq!(date(4102444799), "Thu, 31 Dec 2099 23:59:59 GMT");
+        assert_eq!(http_date(UNIX_EPOCH - Duration::from_secs(1)), http_date(UNIX_EPOCH));
+
+        for &secs in &[0, 784111777, 951782400, 1234567890, 4102444799] {
+            assert_eq!(parse_http_date(&date(secs)), Some(UNIX_EPOCH + Duration::from_secs(secs)));
+        }
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse_http_date("Sun, 06 Xyz 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+
+        let mut cache = DateCache::new();
+        assert_eq!(cache.at(UNIX_EPOCH + Duration::from_secs(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(cache.at(UNIX_EPOCH + Duration::from_millis(784111777999)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(cache.at(UNIX_EPOCH + Duration::from_secs(784111778)), "Sun, 06 Nov 1994 08:49:38 GMT");
+    }
+
+    #[test]
+    fn hexdump_empty() {
+        let mut out = String::new();
+        hexdump(&[], &mut out).unwrap();
+        assert_eq!(out, "");
+        assert_eq!(format!("{:.0}", HexDump(b"abc")), "... 3 more bytes");
+    }
+
+    #[test]
+    fn hexdump_short() {
+        assert_eq!(HexDump(b"hello world\n\x00ab").to_string(),
+                   "00000000  68 65 6c 6c 6f 20 77 6f  72 6c 64 0a 00 61 62     |hello world..ab|");
+    }
+
+    #[test]
+    fn hexdump_lines() {
+        let buf: Vec<u8> = (0..64).map(|i| (i * 7) as u8 ^ 0x30).collect();
+        assert_eq!(HexDump(&buf).to_string(), concat!(
+            "00000000  30 37 3e 25 2c 13 1a 01  08 0f 76 7d 64 6b 52 59  |07>%,.....v}dkRY|\n",
+            "00000010  40 47 4e b5 bc a3 aa 91  98 9f 86 8d f4 fb e2 e9  |@GN.............|\n",
+            "00000020  d0 d7 de c5 cc 33 3a 21  28 2f 16 1d 04 0b 72 79  |.....3:!(/....ry|\n",
+            "00000030  60 67 6e 55 5c 43 4a b1  b8 bf a6 ad 94 9b 82 89  |`gnU\\CJ.........|"));
+
+        // cut off partway through a line
+        assert_eq!(format!("{:.20}", HexDump(&buf)), concat!(
+            "00000000  30 37 3e 25 2c 13 1a 01  08 0f 76 7d 64 6b 52 59  |07>%,.....v}dkRY|\n",
+            "00000010  40 47 4e b5                                       |@GN.|\n",
+            "... 44 more bytes"));
+    }
+}
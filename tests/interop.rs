@@ -0,0 +1,401 @@
+//! What other clients send, replayed against the server
+//!
+//! Each transcript in tests/corpus is the client side of a connection,
+//! the raw frames after TLS as hex, one frame to a line (a frame can go
+//! on over more lines, whitespace does not count). Lines starting with #
+//! are comments, and a line like
+//!
+//! ```text
+//! = 3 GET /style.css
+//! ```
+//!
+//! is a request the transcript makes, which stream it is on and the
+//! method and path it should be decoded to. A capture (from the trace of
+//! a connection or a tool like Wireshark) goes in as its hex and the
+//! requests it makes.
+//!
+//! The transcripts here were put together by hand after what Chrome,
+//! Firefox, curl and nghttp are known to send, down to the order of their
+//! SETTINGS and how they use the dynamic table, since there is no client
+//! to record in the build. Real captures can be dropped in next to them.
+//!
+//! Every transcript is replayed against a server that answers each
+//! request with its method and path. It has to get through without an
+//! error (no GOAWAY with one and no RST_STREAM) and answer every request,
+//! otherwise the test fails with the trace of the frames both ways.
+//!
+//! What a client sees of the server shutting down in the middle of a
+//! download is checked here as well, and of it lowering the max frame
+//! size in the middle of one.
+
+extern crate http2;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use http2::{flags, types, Config, Connection, Decoder, Encoder, HeaderList, Http2Frame, OwnedFrame};
+use http2::{Request, Response, ResponseWriter};
+use http2::connection::handshake::PREFACE;
+use http2::connection::reader::FrameReader;
+use http2::connection::settings::{MAX_FRAME_SIZE, MIN_FRAME_SIZE_LIMIT};
+use http2::connection::trace::{TraceSink, Tracer};
+use http2::server::ShutdownHandle;
+use http2::test_util::{duplex, DuplexStream};
+use http2::util::{Clock, MockClock};
+
+// how long the server gets to answer before the replay gives up on it,
+// counted in reads of the client that time out
+const ANSWER_TIMEOUT_MS : u64 = 5000;
+const READ_TIMEOUT_MS : u64 = 100;
+
+// the client end of a duplex, each read of it that times out moves the
+// clock on by the read timeout
+//
+// A FrameReader on that clock gives up after the same number of empty
+// reads however fast the machine running the test is.
+struct Ticking<'a> {
+    client: &'a mut DuplexStream,
+    clock: &'a MockClock,
+}
+
+impl<'a> Read for Ticking<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.client.read(buf);
+        if let Err(ref e) = res {
+            if e.kind() == io::ErrorKind::WouldBlock {
+                self.clock.advance(Duration::from_millis(READ_TIMEOUT_MS));
+            }
+        }
+        res
+    }
+}
+
+// a reader for the answers of the server, with its deadline on clock
+fn answer_reader(clock: &Arc<MockClock>) -> FrameReader {
+    let mut reader = FrameReader::new();
+    reader.set_clock(clock.clone());
+    reader.set_deadline(Some(clock.now() + Duration::from_millis(ANSWER_TIMEOUT_MS)));
+    reader
+}
+
+struct Transcript {
+    name: String,
+    bytes: Vec<u8>,
+    // (stream, method, path)
+    requests: Vec<(u32, String, String)>,
+}
+
+impl Transcript {
+
+    fn load(path: &Path) -> Result<Transcript, String> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", name, e))?;
+        let mut transcript = Transcript { name: name, bytes: Vec::new(), requests: Vec::new() };
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('=') {
+                let mut parts = line[1..].split_whitespace();
+                let request = match (parts.next().and_then(|s| s.parse().ok()), parts.next(), parts.next()) {
+                    (Some(stream), Some(method), Some(path)) => (stream, method.to_string(), path.to_string()),
+                    _ => return Err(format!("{}:{}: not a request", transcript.name, n + 1)),
+                };
+                transcript.requests.push(request);
+                continue;
+            }
+            let hex: Vec<u8> = line.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+            if hex.len() % 2 != 0 {
+                return Err(format!("{}:{}: odd number of hex digits", transcript.name, n + 1));
+            }
+            for pair in hex.chunks(2) {
+                let byte = str::from_utf8(pair).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+                match byte {
+                    Some(byte) => transcript.bytes.push(byte),
+                    None => return Err(format!("{}:{}: not hex", transcript.name, n + 1)),
+                }
+            }
+        }
+        Ok(transcript)
+    }
+}
+
+// the trace of a replay, kept to show when it fails
+#[derive(Clone)]
+struct TraceBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for TraceBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn echo(req: Request, mut resp: ResponseWriter) {
+    let page = format!("{} {}", req.method(), req.path());
+    resp.send(Response::new(200).header("content-type", "text/plain").body(page)).unwrap();
+}
+
+// (stream, status, body) of every answer, or what went wrong
+fn replay(transcript: &Transcript, tracer: &Tracer) -> Result<Vec<(u32, String, String)>, String> {
+    let config = Config::builder().trace(tracer.clone()).build().unwrap();
+    let (mut client, server) = duplex(1 << 20);
+    client.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)));
+    let serving = thread::spawn(move || Connection::serve_with(server, None, false, &config, None, Arc::new(echo)));
+
+    client.write_all(&transcript.bytes).map_err(|e| format!("writing the transcript: {}", e))?;
+
+    // the answers, as they come
+    let mut answers: Vec<(u32, String, Vec<u8>)> = Vec::new();
+    let mut decoder = Decoder::new(4096, 20);
+    let clock = Arc::new(MockClock::new());
+    let mut reader = answer_reader(&clock);
+    let mut done = 0;
+    while done < transcript.requests.len() {
+        let frame = match reader.read_frame(&mut Ticking { client: &mut client, clock: &clock }) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Err("the server closed the connection".to_string()),
+            Err(e) => return Err(format!("waiting for the server: {}", e)),
+        };
+        let stream = frame.get_stream_id();
+        match frame.get_type() {
+            types::GOAWAY => {
+                let code = &frame.payload()[4..8];
+                if code != [0, 0, 0, 0] {
+                    return Err(format!("GOAWAY with error {:?}", code));
+                }
+            },
+            types::RST_STREAM => return Err(format!("stream {} was reset", stream)),
+            types::HEADERS => {
+                let headers = decoder.get_header_list(frame.payload())
+                    .map_err(|e| format!("the response headers of {} did not decode: {:?}", stream, e))?;
+                let status = headers.get_value_by_name(":status").unwrap_or("").to_string();
+                answers.push((stream, status, Vec::new()));
+            },
+            types::DATA => {
+                match answers.iter_mut().find(|a| a.0 == stream) {
+                    Some(answer) => answer.2.extend_from_slice(frame.payload()),
+                    None => return Err(format!("DATA on {} before its headers", stream)),
+                }
+            },
+            _ => {},
+        }
+        let ends = frame.get_type() == types::HEADERS || frame.get_type() == types::DATA;
+        if ends && frame.get_flags() & flags::END_STREAM != 0 {
+            done += 1;
+        }
+    }
+
+    drop(client);
+    match serving.join() {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => return Err(format!("the connection ended with {}", e)),
+        Err(_) => return Err("the connection panicked".to_string()),
+    }
+    Ok(answers.into_iter().map(|(stream, status, body)| (stream, status, String::from_utf8_lossy(&body).into_owned())).collect())
+}
+
+// what is wrong with the answers to transcript, if anything
+fn check(transcript: &Transcript, answers: &[(u32, String, String)]) -> Result<(), String> {
+    for &(stream, ref method, ref path) in &transcript.requests {
+        match answers.iter().find(|a| a.0 == stream) {
+            Some(&(_, ref status, ref body)) if status == "200" && *body == format!("{} {}", method, path) => {},
+            Some(&(_, ref status, ref body)) => return Err(format!("stream {} was answered {} {:?}", stream, status, body)),
+            None => return Err(format!("stream {} was not answered", stream)),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn client_transcripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let mut paths: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "h2"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no transcripts in {}", dir.display());
+
+    let mut failed = Vec::new();
+    for path in &paths {
+        let transcript = match Transcript::load(path) {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                failed.push(e);
+                continue;
+            },
+        };
+        let trace = TraceBuf(Arc::new(Mutex::new(Vec::new())));
+        let tracer = Tracer::new(TraceSink::writer(trace.clone())).max_dump(256);
+        let result = replay(&transcript, &tracer).and_then(|answers| check(&transcript, &answers));
+        if let Err(e) = result {
+            let trace = String::from_utf8_lossy(&trace.0.lock().unwrap()).into_owned();
+            failed.push(format!("{}: {}\n{}", transcript.name, e, trace));
+        }
+    }
+    assert!(failed.is_empty(), "{} of {} transcripts failed\n\n{}", failed.len(), paths.len(), failed.join("\n"));
+}
+
+// the body of the download, long enough that most of it is still to
+// come when the server shuts down
+const DOWNLOAD_LEN : usize = 1 << 20;
+
+fn download(_req: Request, mut resp: ResponseWriter) {
+    let body: Vec<u8> = (0..DOWNLOAD_LEN).map(|i| (i % 251) as u8).collect();
+    resp.send(Response::new(200).header("content-type", "application/octet-stream").body(body)).unwrap();
+}
+
+#[test]
+fn shutdown_mid_download() {
+    let shutdown = ShutdownHandle::new();
+    let (mut client, server) = duplex(1 << 20);
+    client.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)));
+    let handle = shutdown.clone();
+    let serving = thread::spawn(move || {
+        Connection::serve_with(server, None, false, &Config::default(), Some(&handle), Arc::new(download))
+    });
+
+    let mut request = HeaderList::with_capacity(4);
+    request.add_entry((":method", "GET").into());
+    request.add_entry((":scheme", "https").into());
+    request.add_entry((":authority", "localhost").into());
+    request.add_entry((":path", "/download").into());
+    let block = Encoder::new(4096, 10).encode_header_list(&request);
+    let mut preface = PREFACE.to_vec();
+    preface.extend_from_slice(OwnedFrame::settings(&[]).as_bytes());
+    preface.extend_from_slice(OwnedFrame::headers(1, &block, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+    client.write_all(&preface).unwrap();
+
+    let clock = Arc::new(MockClock::new());
+    let mut reader = answer_reader(&clock);
+    let mut body = Vec::new();
+    // how much of the body was in before the GOAWAY
+    let mut before_go_away = None;
+    let mut shut_down = false;
+    loop {
+        let frame = reader.read_frame(&mut Ticking { client: &mut client, clock: &clock }).unwrap().expect("the server closed the connection");
+        match frame.get_type() {
+            types::SETTINGS if frame.get_flags() & flags::ACK == 0 => {
+                client.write_all(OwnedFrame::settings_ack().as_bytes()).unwrap();
+            },
+            types::GOAWAY => {
+                assert_eq!(&frame.payload()[..8], &[0, 0, 0, 1, 0, 0, 0, 0], "GOAWAY with last stream 1 and no error");
+                before_go_away = Some(body.len());
+            },
+            types::RST_STREAM => panic!("stream {} was reset", frame.get_stream_id()),
+            types::DATA => {
+                body.extend_from_slice(frame.payload());
+                // the first window is in, shut down and let the rest come
+                if !shut_down && body.len() == 65535 {
+                    shutdown.shutdown();
+                    client.write_all(OwnedFrame::window_update(0, DOWNLOAD_LEN as u32).as_bytes()).unwrap();
+                    client.write_all(OwnedFrame::window_update(1, DOWNLOAD_LEN as u32).as_bytes()).unwrap();
+                    shut_down = true;
+                }
+                if frame.get_flags() & flags::END_STREAM != 0 {
+                    break;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // the GOAWAY came as soon as the server noticed, not after the download
+    let before_go_away = before_go_away.expect("no GOAWAY before the download finished");
+    assert_eq!(before_go_away, 65535);
+    assert_eq!(body.len(), DOWNLOAD_LEN);
+    assert!(body.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+
+    // and the connection closes now that the stream is done
+    serving.join().unwrap().unwrap();
+}
+
+#[test]
+fn max_frame_size_mid_download() {
+    let (mut client, server) = duplex(1 << 20);
+    client.set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT_MS)));
+    let serving = thread::spawn(move || {
+        Connection::serve_with(server, None, false, &Config::default(), None, Arc::new(download))
+    });
+
+    let mut request = HeaderList::with_capacity(4);
+    request.add_entry((":method", "GET").into());
+    request.add_entry((":scheme", "https").into());
+    request.add_entry((":authority", "localhost").into());
+    request.add_entry((":path", "/download").into());
+    let block = Encoder::new(4096, 10).encode_header_list(&request);
+    // frames of up to 64K at first, and the windows for a few of them
+    let mut preface = PREFACE.to_vec();
+    preface.extend_from_slice(OwnedFrame::settings(&[(MAX_FRAME_SIZE, 1 << 16)]).as_bytes());
+    preface.extend_from_slice(OwnedFrame::headers(1, &block, flags::END_HEADERS | flags::END_STREAM).as_bytes());
+    preface.extend_from_slice(OwnedFrame::window_update(0, 1 << 18).as_bytes());
+    preface.extend_from_slice(OwnedFrame::window_update(1, 1 << 18).as_bytes());
+    client.write_all(&preface).unwrap();
+
+    let clock = Arc::new(MockClock::new());
+    let mut reader = answer_reader(&clock);
+    let mut body = Vec::new();
+    let mut acks = 0;
+    let mut lowered = false;
+    // the largest DATA before the ACK of the lower size, and after it
+    let mut largest = (0, 0);
+    let mut after = 0;
+    loop {
+        let frame = reader.read_frame(&mut Ticking { client: &mut client, clock: &clock }).unwrap().expect("the server closed the connection");
+        match frame.get_type() {
+            types::SETTINGS if frame.get_flags() & flags::ACK == 0 => {
+                client.write_all(OwnedFrame::settings_ack().as_bytes()).unwrap();
+            },
+            types::SETTINGS => acks += 1,
+            types::GOAWAY => panic!("GOAWAY {:?}", frame.payload()),
+            types::RST_STREAM => panic!("stream {} was reset", frame.get_stream_id()),
+            types::DATA => {
+                let len = frame.payload().len();
+                body.extend_from_slice(frame.payload());
+                if acks < 2 {
+                    largest.0 = ::std::cmp::max(largest.0, len);
+                }
+                else {
+                    largest.1 = ::std::cmp::max(largest.1, len);
+                    after += 1;
+                }
+                // back down to the smallest, then the window for the rest
+                if !lowered && len > MIN_FRAME_SIZE_LIMIT as usize {
+                    client.write_all(OwnedFrame::settings(&[(MAX_FRAME_SIZE, MIN_FRAME_SIZE_LIMIT)]).as_bytes()).unwrap();
+                    client.write_all(OwnedFrame::window_update(0, DOWNLOAD_LEN as u32).as_bytes()).unwrap();
+                    client.write_all(OwnedFrame::window_update(1, DOWNLOAD_LEN as u32).as_bytes()).unwrap();
+                    lowered = true;
+                }
+                if frame.get_flags() & flags::END_STREAM != 0 {
+                    break;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // the frames built before the ACK were as big as they could be then,
+    // every one after it fits the new size
+    assert_eq!(acks, 2);
+    assert_eq!(largest.0, 1 << 16);
+    assert!(after > 0);
+    assert!(largest.1 <= MIN_FRAME_SIZE_LIMIT as usize, "a frame of {} after the ACK", largest.1);
+    assert_eq!(body.len(), DOWNLOAD_LEN);
+    assert!(body.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+
+    // the server is done once the client goes
+    drop(client);
+    serving.join().unwrap().unwrap();
+}
@@ -0,0 +1,76 @@
+//! A request from the preface to the response, with nothing but the
+//! public API
+//!
+//! The connection is served on its own thread at one end of a duplex
+//! stream, the test is the client at the other.
+
+extern crate http2;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+
+use http2::{flags, types, Connection, Decoder, Encoder, HeaderList, Http2Frame, OwnedFrame};
+use http2::{Request, Response, ResponseWriter};
+use http2::connection::handshake::PREFACE;
+use http2::connection::reader::FrameReader;
+use http2::test_util::{duplex, DuplexStream};
+
+fn hello(mut req: Request, mut resp: ResponseWriter) {
+    let body = req.body().collect(1024).unwrap();
+    let page = format!("{} {} {}", req.method(), req.path(), String::from_utf8_lossy(&body));
+    resp.send(Response::new(200).header("content-type", "text/plain").body(page)).unwrap();
+}
+
+// the type, flags and payload of the next frame from the server
+fn next_frame(reader: &mut FrameReader, client: &mut DuplexStream) -> Option<(u8, u8, Vec<u8>)> {
+    reader.read_frame(client).unwrap().map(|frame| (frame.get_type(), frame.get_flags(), frame.payload().to_vec()))
+}
+
+#[test]
+fn request_and_response() {
+    let (mut client, server) = duplex(4096);
+    let serving = thread::spawn(move || Connection::serve(server, false, Arc::new(hello)));
+    let mut reader = FrameReader::new();
+
+    // the server's SETTINGS comes first, whatever the client has sent
+    client.write_all(PREFACE).unwrap();
+    let (ty, f_flags, _) = next_frame(&mut reader, &mut client).unwrap();
+    assert_eq!((ty, f_flags & flags::ACK), (types::SETTINGS, 0));
+
+    // and the client's is acknowledged
+    client.write_all(OwnedFrame::settings(&[]).as_bytes()).unwrap();
+    let (ty, f_flags, _) = next_frame(&mut reader, &mut client).unwrap();
+    assert_eq!((ty, f_flags & flags::ACK), (types::SETTINGS, flags::ACK));
+
+    let mut list = HeaderList::with_capacity(3);
+    list.add_entry((":method", "POST").into());
+    list.add_entry((":scheme", "https").into());
+    list.add_entry((":path", "/hello").into());
+    let block = Encoder::new(4096, 20).encode_header_list(&list);
+    client.write_all(OwnedFrame::headers(1, &block, flags::END_HEADERS).as_bytes()).unwrap();
+    client.write_all(OwnedFrame::data(1, b"world", true).as_bytes()).unwrap();
+
+    let mut status = None;
+    let mut body = Vec::new();
+    loop {
+        let (ty, f_flags, payload) = next_frame(&mut reader, &mut client).unwrap();
+        match ty {
+            types::HEADERS => {
+                let headers = Decoder::new(4096, 20).get_header_list(&payload).unwrap();
+                status = headers.get_value_by_name(":status").map(|s| s.to_string());
+            },
+            types::DATA => body.extend_from_slice(&payload),
+            _ => {},
+        }
+        if f_flags & flags::END_STREAM != 0 {
+            break;
+        }
+    }
+    assert_eq!(status, Some("200".to_string()));
+    assert_eq!(body, b"POST /hello world");
+
+    // closing the client's end is the end of the connection
+    drop(client);
+    serving.join().unwrap().unwrap();
+}